@@ -0,0 +1,288 @@
+pub(crate) mod pcapng;
+
+use crate::capture::pcapng::PcapngWriter;
+use crate::types::{EndpointId, SessionId};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use shared::error::Result;
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// CaptureRequest starts capturing the decrypted RTP and RTCP of a session, or of one of its
+/// endpoints, until it is stopped or reaches its limits
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CaptureRequest {
+    /// endpoint captured, every endpoint of the session when unset
+    pub endpoint_id: Option<EndpointId>,
+    /// size in bytes the capture stops at, the server limit when unset or above it
+    pub max_bytes: Option<u64>,
+    /// duration in seconds the capture stops after, the server limit when unset or above it
+    pub max_duration: Option<u64>,
+}
+
+/// CaptureSummary describes a capture once it is stopped
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureSummary {
+    pub session_id: SessionId,
+    pub endpoint_id: Option<EndpointId>,
+    pub file: PathBuf,
+    /// unix time in milliseconds
+    pub started_at: u64,
+    /// unix time in milliseconds
+    pub stopped_at: u64,
+    pub packets: u64,
+    pub bytes: u64,
+    /// the capture stopped before the request because of its size or duration limit
+    pub is_limit_reached: bool,
+}
+
+/// PacketCapture writes the RTP and RTCP packets of a session, as they are after decryption
+/// and before encryption, into a pcapng file
+pub(crate) struct PacketCapture {
+    session_id: SessionId,
+    endpoint_id: Option<EndpointId>,
+    file: PathBuf,
+    writer: Option<PcapngWriter<BufWriter<File>>>,
+    started_at: SystemTime,
+    started_instant: Instant,
+    stopped_at: Option<SystemTime>,
+    max_bytes: u64,
+    max_duration: Duration,
+    packets: u64,
+    bytes: u64,
+    is_limit_reached: bool,
+}
+
+impl PacketCapture {
+    /// new creates the capture file in the capture directory, the limits of the request being
+    /// bounded by the server ones
+    pub(crate) fn new(
+        session_id: SessionId,
+        capture_dir: &Path,
+        request: CaptureRequest,
+        max_bytes: u64,
+        max_duration: Duration,
+        now: Instant,
+    ) -> Result<Self> {
+        let started_at = SystemTime::now();
+        let started_at_millis = unix_time_micros(started_at) / 1000;
+        let file_name = match request.endpoint_id {
+            Some(endpoint_id) => {
+                format!(
+                    "{}-{}-{}.pcapng",
+                    session_id, endpoint_id, started_at_millis
+                )
+            }
+            None => format!("{}-{}.pcapng", session_id, started_at_millis),
+        };
+        std::fs::create_dir_all(capture_dir)?;
+        let file = capture_dir.join(file_name);
+        let writer = PcapngWriter::new(BufWriter::new(File::create(&file)?))?;
+        debug!("{}: start capture in {:?}", session_id, file);
+
+        Ok(Self {
+            session_id,
+            endpoint_id: request.endpoint_id,
+            file,
+            writer: Some(writer),
+            started_at,
+            started_instant: now,
+            stopped_at: None,
+            max_bytes: request
+                .max_bytes
+                .map_or(max_bytes, |bytes| bytes.min(max_bytes)),
+            max_duration: request
+                .max_duration
+                .map(Duration::from_secs)
+                .map_or(max_duration, |duration| duration.min(max_duration)),
+            packets: 0,
+            bytes: 0,
+            is_limit_reached: false,
+        })
+    }
+
+    pub(crate) fn get_file(&self) -> &Path {
+        &self.file
+    }
+
+    /// on_packet captures a packet of an endpoint, sent to it when outbound or received from it
+    /// otherwise, until a limit is reached
+    pub(crate) fn on_packet(
+        &mut self,
+        now: Instant,
+        endpoint_id: EndpointId,
+        local_addr: SocketAddr,
+        peer_addr: SocketAddr,
+        is_outbound: bool,
+        payload: &[u8],
+    ) {
+        if self
+            .endpoint_id
+            .is_some_and(|captured_endpoint_id| captured_endpoint_id != endpoint_id)
+        {
+            return;
+        }
+        let elapsed = now.saturating_duration_since(self.started_instant);
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+        if elapsed >= self.max_duration || self.bytes >= self.max_bytes {
+            info!(
+                "{}: capture {:?} reached its limit",
+                self.session_id, self.file
+            );
+            self.is_limit_reached = true;
+            self.close();
+            return;
+        }
+
+        let (source, destination) = if is_outbound {
+            (local_addr, peer_addr)
+        } else {
+            (peer_addr, local_addr)
+        };
+        let timestamp = unix_time_micros(self.started_at) + elapsed.as_micros() as u64;
+        match writer.write_packet(timestamp, source, destination, is_outbound, payload) {
+            Ok(bytes) => {
+                self.packets += 1;
+                self.bytes += bytes as u64;
+            }
+            Err(err) => {
+                warn!(
+                    "{}: can't write capture {:?}: {}",
+                    self.session_id, self.file, err
+                );
+                self.close();
+            }
+        }
+    }
+
+    /// stop completes the capture file, unless a limit stopped it before
+    pub(crate) fn stop(mut self) -> CaptureSummary {
+        self.close();
+        debug!("{}: stop capture in {:?}", self.session_id, self.file);
+
+        CaptureSummary {
+            session_id: self.session_id,
+            endpoint_id: self.endpoint_id,
+            file: self.file,
+            started_at: unix_time_micros(self.started_at) / 1000,
+            stopped_at: self
+                .stopped_at
+                .map_or(0, |stopped_at| unix_time_micros(stopped_at) / 1000),
+            packets: self.packets,
+            bytes: self.bytes,
+            is_limit_reached: self.is_limit_reached,
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            if let Err(err) = writer.flush() {
+                warn!(
+                    "{}: can't flush capture {:?}: {}",
+                    self.session_id, self.file, err
+                );
+            }
+            self.stopped_at = Some(SystemTime::now());
+        }
+    }
+}
+
+fn unix_time_micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_micros() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("beep-sfu-capture-{}-{}", name, std::process::id()))
+    }
+
+    fn capture(name: &str, request: CaptureRequest, now: Instant) -> PacketCapture {
+        PacketCapture::new(
+            1,
+            &capture_dir(name),
+            request,
+            1_000,
+            Duration::from_secs(10),
+            now,
+        )
+        .unwrap()
+    }
+
+    fn on_packet(capture: &mut PacketCapture, now: Instant, endpoint_id: EndpointId) {
+        capture.on_packet(
+            now,
+            endpoint_id,
+            "10.0.0.1:5000".parse().unwrap(),
+            "10.0.0.2:6000".parse().unwrap(),
+            false,
+            &[0; 100],
+        );
+    }
+
+    #[test]
+    fn captures_the_packets_of_the_requested_endpoint() {
+        let now = Instant::now();
+        let request = CaptureRequest {
+            endpoint_id: Some(2),
+            ..Default::default()
+        };
+        let mut capture = capture("endpoint", request, now);
+        on_packet(&mut capture, now, 2);
+        on_packet(&mut capture, now, 3);
+
+        let summary = capture.stop();
+        assert_eq!(summary.packets, 1);
+        assert!(!summary.is_limit_reached);
+        let file_len = std::fs::metadata(&summary.file).unwrap().len();
+        assert!(file_len > summary.bytes);
+        std::fs::remove_dir_all(capture_dir("endpoint")).unwrap();
+    }
+
+    #[test]
+    fn stops_at_the_size_limit() {
+        let now = Instant::now();
+        let request = CaptureRequest {
+            max_bytes: Some(300),
+            ..Default::default()
+        };
+        let mut capture = capture("size", request, now);
+        for _ in 0..10 {
+            on_packet(&mut capture, now, 2);
+        }
+
+        // a packet takes 172 bytes, the one crossing the limit is still written
+        let summary = capture.stop();
+        assert_eq!(summary.packets, 2);
+        assert_eq!(summary.bytes, 344);
+        assert!(summary.is_limit_reached);
+        std::fs::remove_dir_all(capture_dir("size")).unwrap();
+    }
+
+    #[test]
+    fn stops_at_the_duration_limit_of_the_server() {
+        let now = Instant::now();
+        let request = CaptureRequest {
+            max_duration: Some(60),
+            ..Default::default()
+        };
+        let mut capture = capture("duration", request, now);
+        on_packet(&mut capture, now + Duration::from_secs(9), 2);
+        on_packet(&mut capture, now + Duration::from_secs(10), 2);
+        on_packet(&mut capture, now + Duration::from_secs(11), 2);
+
+        let summary = capture.stop();
+        assert_eq!(summary.packets, 1);
+        assert!(summary.is_limit_reached);
+        std::fs::remove_dir_all(capture_dir("duration")).unwrap();
+    }
+}
@@ -0,0 +1,355 @@
+use shared::error::Result;
+use std::io::Write;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+const BLOCK_TYPE_SECTION_HEADER: u32 = 0x0A0D0D0A;
+const BLOCK_TYPE_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const BLOCK_TYPE_ENHANCED_PACKET: u32 = 0x00000006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+/// raw IPv4 or IPv6 packets, without link layer header
+const LINKTYPE_RAW: u16 = 101;
+const OPTION_END_OF_OPT: u16 = 0;
+const OPTION_SHB_USER_APPL: u16 = 4;
+const OPTION_EPB_FLAGS: u16 = 2;
+const EPB_FLAGS_INBOUND: u32 = 0x01;
+const EPB_FLAGS_OUTBOUND: u32 = 0x02;
+const USER_APPL: &str = "beep-sfu";
+const IP_PROTOCOL_UDP: u8 = 17;
+const IP_TTL: u8 = 64;
+
+/// PcapngWriter writes packets into a pcapng file, RFC draft-ietf-opsawg-pcapng, wrapped in
+/// synthetic IP and UDP headers so that packet analyzers dissect them as if they were captured
+/// on the wire
+pub(crate) struct PcapngWriter<W: Write> {
+    writer: W,
+    ipv4_identification: u16,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    pub(crate) fn new(writer: W) -> Result<Self> {
+        let mut pcapng_writer = Self {
+            writer,
+            ipv4_identification: 0,
+        };
+
+        let mut section_header = vec![];
+        section_header.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        section_header.extend_from_slice(&1u16.to_le_bytes()); // major version
+        section_header.extend_from_slice(&0u16.to_le_bytes()); // minor version
+        section_header.extend_from_slice(&(-1i64).to_le_bytes()); // section length unknown
+        push_option(
+            &mut section_header,
+            OPTION_SHB_USER_APPL,
+            USER_APPL.as_bytes(),
+        );
+        push_option(&mut section_header, OPTION_END_OF_OPT, &[]);
+        pcapng_writer.write_block(BLOCK_TYPE_SECTION_HEADER, &section_header)?;
+
+        // timestamps are in microseconds, the default resolution
+        let mut interface_description = vec![];
+        interface_description.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        interface_description.extend_from_slice(&0u16.to_le_bytes()); // reserved
+        interface_description.extend_from_slice(&0u32.to_le_bytes()); // no snap length
+        pcapng_writer.write_block(BLOCK_TYPE_INTERFACE_DESCRIPTION, &interface_description)?;
+
+        Ok(pcapng_writer)
+    }
+
+    /// write_packet writes a UDP payload sent from source to destination, timestamp being in
+    /// microseconds since the unix epoch, and returns the number of bytes written
+    pub(crate) fn write_packet(
+        &mut self,
+        timestamp: u64,
+        source: SocketAddr,
+        destination: SocketAddr,
+        is_outbound: bool,
+        payload: &[u8],
+    ) -> Result<usize> {
+        let packet = self.build_ip_packet(source, destination, payload);
+
+        let mut enhanced_packet = vec![];
+        enhanced_packet.extend_from_slice(&0u32.to_le_bytes()); // interface id
+        enhanced_packet.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        enhanced_packet.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        enhanced_packet.extend_from_slice(&(packet.len() as u32).to_le_bytes()); // captured
+        enhanced_packet.extend_from_slice(&(packet.len() as u32).to_le_bytes()); // original
+        enhanced_packet.extend_from_slice(&packet);
+        pad(&mut enhanced_packet);
+        let flags = if is_outbound {
+            EPB_FLAGS_OUTBOUND
+        } else {
+            EPB_FLAGS_INBOUND
+        };
+        push_option(&mut enhanced_packet, OPTION_EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut enhanced_packet, OPTION_END_OF_OPT, &[]);
+        self.write_block(BLOCK_TYPE_ENHANCED_PACKET, &enhanced_packet)
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> Result<usize> {
+        let total_length = (body.len() + 12) as u32;
+        self.writer.write_all(&block_type.to_le_bytes())?;
+        self.writer.write_all(&total_length.to_le_bytes())?;
+        self.writer.write_all(body)?;
+        self.writer.write_all(&total_length.to_le_bytes())?;
+        Ok(total_length as usize)
+    }
+
+    /// build_ip_packet wraps the payload in UDP over IPv4 when both addresses are IPv4, or over
+    /// IPv6 otherwise, IPv4 addresses being mapped then
+    fn build_ip_packet(
+        &mut self,
+        source: SocketAddr,
+        destination: SocketAddr,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let udp_length = (8 + payload.len()) as u16;
+        let mut udp = Vec::with_capacity(udp_length as usize);
+        udp.extend_from_slice(&source.port().to_be_bytes());
+        udp.extend_from_slice(&destination.port().to_be_bytes());
+        udp.extend_from_slice(&udp_length.to_be_bytes());
+        udp.extend_from_slice(&0u16.to_be_bytes()); // checksum, computed below
+        udp.extend_from_slice(payload);
+
+        match (source.ip(), destination.ip()) {
+            (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+                let mut pseudo_header = vec![];
+                pseudo_header.extend_from_slice(&source_ip.octets());
+                pseudo_header.extend_from_slice(&destination_ip.octets());
+                pseudo_header.extend_from_slice(&[0, IP_PROTOCOL_UDP]);
+                pseudo_header.extend_from_slice(&udp_length.to_be_bytes());
+                set_udp_checksum(&mut udp, &pseudo_header);
+
+                self.ipv4_identification = self.ipv4_identification.wrapping_add(1);
+                let mut packet = Vec::with_capacity(20 + udp.len());
+                packet.push(0x45); // version 4, 5 words header
+                packet.push(0); // DSCP and ECN
+                packet.extend_from_slice(&((20 + udp.len()) as u16).to_be_bytes());
+                packet.extend_from_slice(&self.ipv4_identification.to_be_bytes());
+                packet.extend_from_slice(&0x4000u16.to_be_bytes()); // don't fragment
+                packet.push(IP_TTL);
+                packet.push(IP_PROTOCOL_UDP);
+                packet.extend_from_slice(&0u16.to_be_bytes()); // checksum, computed below
+                packet.extend_from_slice(&source_ip.octets());
+                packet.extend_from_slice(&destination_ip.octets());
+                let checksum = internet_checksum(&packet);
+                packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+                packet.extend_from_slice(&udp);
+                packet
+            }
+            (source_ip, destination_ip) => {
+                let source_ip = to_ipv6(source_ip);
+                let destination_ip = to_ipv6(destination_ip);
+                let mut pseudo_header = vec![];
+                pseudo_header.extend_from_slice(&source_ip.octets());
+                pseudo_header.extend_from_slice(&destination_ip.octets());
+                pseudo_header.extend_from_slice(&(udp_length as u32).to_be_bytes());
+                pseudo_header.extend_from_slice(&[0, 0, 0, IP_PROTOCOL_UDP]);
+                set_udp_checksum(&mut udp, &pseudo_header);
+
+                let mut packet = Vec::with_capacity(40 + udp.len());
+                packet.extend_from_slice(&0x60000000u32.to_be_bytes()); // version 6
+                packet.extend_from_slice(&udp_length.to_be_bytes());
+                packet.push(IP_PROTOCOL_UDP);
+                packet.push(IP_TTL);
+                packet.extend_from_slice(&source_ip.octets());
+                packet.extend_from_slice(&destination_ip.octets());
+                packet.extend_from_slice(&udp);
+                packet
+            }
+        }
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// push_option appends a pcapng option, its value padded to 32 bits
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+fn pad(body: &mut Vec<u8>) {
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+fn set_udp_checksum(udp: &mut [u8], pseudo_header: &[u8]) {
+    let checksum = match internet_checksum(&[pseudo_header, udp].concat()) {
+        // RFC 768: a computed checksum of zero is sent as all ones
+        0 => 0xFFFF,
+        checksum => checksum,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// internet_checksum computes the one's complement sum of RFC 1071
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum = data.chunks(2).fold(0u32, |sum, chunk| {
+        sum + u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32
+    });
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    /// blocks splits a pcapng file into its block types and bodies, checking the lengths which
+    /// surround every block
+    fn blocks(file: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut blocks = vec![];
+        let mut rest = file;
+        while !rest.is_empty() {
+            let block_type = u32::from_le_bytes(rest[0..4].try_into().unwrap());
+            let total_length = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            assert_eq!(total_length % 4, 0);
+            assert_eq!(
+                &rest[total_length - 4..total_length],
+                &(total_length as u32).to_le_bytes()
+            );
+            blocks.push((block_type, &rest[8..total_length - 4]));
+            rest = &rest[total_length..];
+        }
+        blocks
+    }
+
+    fn header_blocks_length() -> usize {
+        let mut file = vec![];
+        PcapngWriter::new(&mut file).unwrap();
+        file.len()
+    }
+
+    #[test]
+    fn writes_the_section_header_and_interface_description() {
+        let mut file = vec![];
+        PcapngWriter::new(&mut file).unwrap();
+
+        let blocks = blocks(&file);
+        assert_eq!(blocks.len(), 2);
+        let (block_type, section_header) = blocks[0];
+        assert_eq!(block_type, BLOCK_TYPE_SECTION_HEADER);
+        assert_eq!(&section_header[0..4], &BYTE_ORDER_MAGIC.to_le_bytes());
+        assert_eq!(&section_header[4..8], &[1, 0, 0, 0]);
+        assert_eq!(&section_header[8..16], &[0xFF; 8]);
+        assert_eq!(&section_header[16..18], &OPTION_SHB_USER_APPL.to_le_bytes());
+        assert_eq!(&section_header[18..20], &8u16.to_le_bytes());
+        assert_eq!(&section_header[20..28], b"beep-sfu");
+        assert_eq!(&section_header[28..], &[0, 0, 0, 0]);
+
+        let (block_type, interface_description) = blocks[1];
+        assert_eq!(block_type, BLOCK_TYPE_INTERFACE_DESCRIPTION);
+        assert_eq!(&interface_description[0..2], &LINKTYPE_RAW.to_le_bytes());
+        assert_eq!(interface_description.len(), 8);
+    }
+
+    #[test]
+    fn writes_udp_over_ipv4_packets() {
+        let mut file = vec![];
+        let mut writer = PcapngWriter::new(&mut file).unwrap();
+        let source: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let destination: SocketAddr = "10.0.0.2:6000".parse().unwrap();
+        let timestamp = 0x0001_0002_0003_0004;
+        let bytes = writer
+            .write_packet(timestamp, source, destination, true, b"hello")
+            .unwrap();
+        assert_eq!(bytes, file.len() - header_blocks_length());
+
+        let blocks = blocks(&file);
+        let (block_type, enhanced_packet) = blocks[2];
+        assert_eq!(block_type, BLOCK_TYPE_ENHANCED_PACKET);
+        assert_eq!(&enhanced_packet[0..4], &0u32.to_le_bytes());
+        assert_eq!(&enhanced_packet[4..8], &0x0001_0002u32.to_le_bytes());
+        assert_eq!(&enhanced_packet[8..12], &0x0003_0004u32.to_le_bytes());
+        let captured_length = u32::from_le_bytes(enhanced_packet[12..16].try_into().unwrap());
+        assert_eq!(captured_length, 20 + 8 + 5);
+        assert_eq!(&enhanced_packet[16..20], &captured_length.to_le_bytes());
+
+        // the packet is padded to 32 bits before the options
+        let packet = &enhanced_packet[20..20 + captured_length as usize];
+        assert_eq!(&enhanced_packet[53..56], &[0, 0, 0]);
+        let options = &enhanced_packet[56..];
+        assert_eq!(&options[0..2], &OPTION_EPB_FLAGS.to_le_bytes());
+        assert_eq!(&options[4..8], &EPB_FLAGS_OUTBOUND.to_le_bytes());
+        assert_eq!(&options[8..], &[0, 0, 0, 0]);
+
+        assert_eq!(packet[0], 0x45);
+        assert_eq!(&packet[2..4], &33u16.to_be_bytes());
+        assert_eq!(packet[9], IP_PROTOCOL_UDP);
+        assert_eq!(&packet[12..16], &[10, 0, 0, 1]);
+        assert_eq!(&packet[16..20], &[10, 0, 0, 2]);
+        // a header with a valid checksum sums to zero
+        assert_eq!(internet_checksum(&packet[..20]), 0);
+
+        let udp = &packet[20..];
+        assert_eq!(&udp[0..2], &5000u16.to_be_bytes());
+        assert_eq!(&udp[2..4], &6000u16.to_be_bytes());
+        assert_eq!(&udp[4..6], &13u16.to_be_bytes());
+        assert_eq!(&udp[8..], b"hello");
+        let pseudo_header = [&packet[12..20], &[0, IP_PROTOCOL_UDP], &udp[4..6]].concat();
+        assert_eq!(internet_checksum(&[&pseudo_header, udp].concat()), 0);
+    }
+
+    #[test]
+    fn writes_udp_over_ipv6_packets_when_an_address_is_ipv6() {
+        let mut file = vec![];
+        let mut writer = PcapngWriter::new(&mut file).unwrap();
+        let source: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let destination: SocketAddr = "[2001:db8::1]:6000".parse().unwrap();
+        writer
+            .write_packet(0, source, destination, false, b"hi")
+            .unwrap();
+
+        let blocks = blocks(&file);
+        let (_, enhanced_packet) = blocks[2];
+        let captured_length = u32::from_le_bytes(enhanced_packet[12..16].try_into().unwrap());
+        assert_eq!(captured_length, 40 + 8 + 2);
+        let packet = &enhanced_packet[20..20 + captured_length as usize];
+        assert_eq!(packet[0] >> 4, 6);
+        assert_eq!(&packet[4..6], &10u16.to_be_bytes());
+        assert_eq!(packet[6], IP_PROTOCOL_UDP);
+        assert_eq!(
+            &packet[8..24],
+            &Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped().octets()
+        );
+        assert_eq!(
+            &packet[24..40],
+            &"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets()
+        );
+        let options = &enhanced_packet[20 + 52..];
+        assert_eq!(&options[4..8], &EPB_FLAGS_INBOUND.to_le_bytes());
+
+        let udp = &packet[40..];
+        let pseudo_header = [
+            &packet[8..40],
+            &10u32.to_be_bytes()[..],
+            &[0, 0, 0, IP_PROTOCOL_UDP],
+        ]
+        .concat();
+        assert_eq!(internet_checksum(&[&pseudo_header, udp].concat()), 0);
+    }
+
+    #[test]
+    fn computes_the_internet_checksum() {
+        // RFC 1071 section 3 example, with an odd length padded with zero
+        assert_eq!(
+            internet_checksum(&[0x00, 0x01, 0xF2, 0x03, 0xF4, 0xF5, 0xF6, 0xF7]),
+            !0xDDF2
+        );
+        assert_eq!(internet_checksum(&[0x01]), !0x0100);
+    }
+}
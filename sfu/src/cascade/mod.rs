@@ -0,0 +1,683 @@
+pub(crate) mod wire;
+
+use crate::chat::ChatEvent;
+use crate::description::rtp_transceiver::{RTCRtpTransceiver, SSRC};
+use crate::endpoint::IncomingStream;
+use crate::types::{EndpointId, Mid, SessionId};
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// WorkerPlacement places the endpoints of a session on the media workers, identified by their
+/// port. A session spans up to session_workers workers, starting from its home worker, which
+/// hosts its virtual endpoints, recording, egress and HLS
+#[derive(Debug, Clone, Default)]
+pub struct WorkerPlacement {
+    ports: Vec<u16>,
+    session_workers: usize,
+}
+
+impl WorkerPlacement {
+    /// new places sessions on the workers of the ports, each session on a single worker unless
+    /// session_workers is greater than 1
+    pub fn new(mut ports: Vec<u16>, session_workers: usize) -> Self {
+        ports.sort_unstable();
+        ports.dedup();
+        let session_workers = session_workers.clamp(1, ports.len().max(1));
+        Self {
+            ports,
+            session_workers,
+        }
+    }
+
+    /// is_cascaded tells whether sessions may span several workers
+    pub fn is_cascaded(&self) -> bool {
+        self.session_workers > 1
+    }
+
+    /// session_ports returns the ports of the workers the session spans, its home worker first
+    pub fn session_ports(&self, session_id: SessionId) -> Vec<u16> {
+        if self.ports.is_empty() {
+            return vec![];
+        }
+        let home = (session_id % self.ports.len() as u64) as usize;
+        (0..self.session_workers)
+            .map(|i| self.ports[(home + i) % self.ports.len()])
+            .collect()
+    }
+
+    /// session_port returns the port of the home worker of the session
+    pub fn session_port(&self, session_id: SessionId) -> Option<u16> {
+        self.session_ports(session_id).first().copied()
+    }
+
+    /// endpoint_port returns the port of the worker the endpoint connects to, among the ones the
+    /// session spans
+    pub fn endpoint_port(&self, session_id: SessionId, endpoint_id: EndpointId) -> Option<u16> {
+        let ports = self.session_ports(session_id);
+        if ports.is_empty() {
+            return None;
+        }
+        ports
+            .get((endpoint_id % ports.len() as u64) as usize)
+            .copied()
+    }
+}
+
+/// RelayPeer is where the tracks of a session are relayed to, or from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RelayPeer {
+    /// another media worker of this node, by its port
+    Worker(u16),
+    /// another node, by the id of the link to it
+    Node(u64),
+}
+
+impl fmt::Display for RelayPeer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelayPeer::Worker(port) => write!(f, "worker {}", port),
+            RelayPeer::Node(link_id) => write!(f, "node of link {}", link_id),
+        }
+    }
+}
+
+/// RelayMessage carries the tracks of a session between the workers hosting its endpoints,
+/// over the channels the workers receive their packets from, or between nodes over their links
+pub struct RelayMessage {
+    pub(crate) session_id: SessionId,
+    /// peer sending the message
+    pub(crate) origin: RelayPeer,
+    pub(crate) event: RelayEvent,
+}
+
+impl RelayMessage {
+    /// is_leave tells whether the message ends the relay of the session with the peer, after
+    /// which a link to another node can be closed
+    pub fn is_leave(&self) -> bool {
+        matches!(self.event, RelayEvent::Leave)
+    }
+}
+
+pub(crate) enum RelayEvent {
+    /// the sending peer hosts endpoints of the session, and asks for the tracks published on
+    /// the receiving one
+    Join,
+    /// the sending peer no longer hosts the session
+    Leave,
+    /// tracks published by an endpoint reachable through the sending peer, including the ones
+    /// published before
+    Publish {
+        endpoint_id: EndpointId,
+        transceivers: Vec<RTCRtpTransceiver>,
+    },
+    Unpublish {
+        endpoint_id: EndpointId,
+    },
+    /// decrypted packet published by an endpoint reachable through the sending peer, without
+    /// the header extensions only meaningful to its bundle
+    Rtp {
+        endpoint_id: EndpointId,
+        incoming_stream: IncomingStream,
+        audio_level: Option<u8>,
+        packet: rtp::packet::Packet,
+    },
+    /// compound RTCP sent by an endpoint reachable through the sending peer, such as its sender
+    /// reports
+    Rtcp {
+        endpoint_id: EndpointId,
+        payload: Bytes,
+    },
+    /// keyframe requests for streams published by an endpoint reachable through the receiving
+    /// peer
+    KeyframeRequest {
+        endpoint_id: EndpointId,
+        ssrcs: Vec<SSRC>,
+    },
+    /// chat message sent by an endpoint reachable through the sending peer
+    Chat {
+        event: ChatEvent,
+    },
+}
+
+/// SessionCascade relays the tracks published on this worker to the other workers hosting the
+/// session, and keeps track of the endpoints they relay in turn. The home worker of the session
+/// also holds its links to other nodes, and relays between them and the workers of this node:
+/// the tracks of the workers go to the nodes, and the tracks of a node go to every other peer
+pub(crate) struct SessionCascade {
+    session_id: SessionId,
+    local_port: u16,
+    /// ports of the workers the session spans, its home first
+    ports: Vec<u16>,
+    /// links to other nodes hosting the session too
+    links: HashSet<u64>,
+    /// workers and nodes hosting endpoints of the session too
+    peers: HashSet<RelayPeer>,
+    /// endpoints relayed by peers, with the peer relaying them
+    relayed_endpoints: HashMap<EndpointId, RelayPeer>,
+    /// mids relayed to the peers for each endpoint
+    published_mids: HashMap<EndpointId, HashSet<Mid>>,
+    relayed_packets: Vec<(EndpointId, IncomingStream, Option<u8>, rtp::packet::Packet)>,
+    relayed_rtcp: Vec<(EndpointId, Bytes)>,
+    /// chat messages relayed by peers, with the endpoint which sent them
+    relayed_chat: Vec<(EndpointId, ChatEvent)>,
+    messages: Vec<(RelayPeer, RelayMessage)>,
+}
+
+impl SessionCascade {
+    /// new joins the other workers the session spans, the ones already hosting it answering
+    /// with their tracks
+    pub(crate) fn new(session_id: SessionId, local_port: u16, ports: Vec<u16>) -> Self {
+        let mut cascade = Self {
+            session_id,
+            local_port,
+            ports,
+            links: HashSet::new(),
+            peers: HashSet::new(),
+            relayed_endpoints: HashMap::new(),
+            published_mids: HashMap::new(),
+            relayed_packets: vec![],
+            relayed_rtcp: vec![],
+            relayed_chat: vec![],
+            messages: vec![],
+        };
+        let other_ports: Vec<u16> = cascade
+            .ports
+            .iter()
+            .copied()
+            .filter(|&port| port != local_port)
+            .collect();
+        for port in other_ports {
+            cascade.send(RelayPeer::Worker(port), RelayEvent::Join);
+        }
+        cascade
+    }
+
+    /// is_home tells whether this worker is the home worker of the session
+    pub(crate) fn is_home(&self) -> bool {
+        self.ports.first() == Some(&self.local_port)
+    }
+
+    pub(crate) fn has_peers(&self) -> bool {
+        !self.peers.is_empty()
+    }
+
+    pub(crate) fn has_link(&self, link_id: u64) -> bool {
+        self.links.contains(&link_id)
+    }
+
+    pub(crate) fn is_relayed(&self, endpoint_id: &EndpointId) -> bool {
+        self.relayed_endpoints.contains_key(endpoint_id)
+    }
+
+    /// add_link joins the node at the other end of the link, which answers with its tracks
+    pub(crate) fn add_link(&mut self, link_id: u64) {
+        if self.links.insert(link_id) {
+            self.send(RelayPeer::Node(link_id), RelayEvent::Join);
+        }
+    }
+
+    /// links returns the links to other nodes, in the order they were assigned
+    pub(crate) fn links(&self) -> Vec<u64> {
+        let mut links: Vec<u64> = self.links.iter().copied().collect();
+        links.sort_unstable();
+        links
+    }
+
+    /// add_peer returns whether the peer just joined, in which case it is joined back for it to
+    /// relay its tracks too
+    pub(crate) fn add_peer(&mut self, peer: RelayPeer) -> bool {
+        let is_known = match peer {
+            RelayPeer::Worker(port) => port != self.local_port && self.ports.contains(&port),
+            RelayPeer::Node(link_id) => self.links.contains(&link_id),
+        };
+        if !is_known || !self.peers.insert(peer) {
+            return false;
+        }
+        self.send(peer, RelayEvent::Join);
+        true
+    }
+
+    /// remove_peer returns the endpoints relayed by the peer, which left
+    pub(crate) fn remove_peer(&mut self, peer: RelayPeer) -> Vec<EndpointId> {
+        self.peers.remove(&peer);
+        if let RelayPeer::Node(link_id) = peer {
+            self.links.remove(&link_id);
+        }
+        let mut endpoint_ids: Vec<EndpointId> = self
+            .relayed_endpoints
+            .iter()
+            .filter(|(_, &origin)| origin == peer)
+            .map(|(&endpoint_id, _)| endpoint_id)
+            .collect();
+        endpoint_ids.sort_unstable();
+        endpoint_ids
+    }
+
+    /// relayed_origin returns the peer relaying the endpoint
+    pub(crate) fn relayed_origin(&self, endpoint_id: &EndpointId) -> Option<RelayPeer> {
+        self.relayed_endpoints.get(endpoint_id).copied()
+    }
+
+    pub(crate) fn add_relayed_endpoint(&mut self, endpoint_id: EndpointId, origin: RelayPeer) {
+        self.relayed_endpoints.insert(endpoint_id, origin);
+    }
+
+    /// remove_endpoint forgets a relayed endpoint, and unpublishes it from the peers it was
+    /// relayed to
+    pub(crate) fn remove_endpoint(&mut self, endpoint_id: &EndpointId) {
+        let origin = self.relayed_endpoints.remove(endpoint_id);
+        if self.published_mids.remove(endpoint_id).is_some() {
+            let endpoint_id = *endpoint_id;
+            self.broadcast(origin, || RelayEvent::Unpublish { endpoint_id });
+        }
+    }
+
+    /// publish relays the tracks published by an endpoint to the peers, once one of them
+    /// wasn't relayed yet
+    pub(crate) fn publish(
+        &mut self,
+        endpoint_id: EndpointId,
+        transceivers: Vec<RTCRtpTransceiver>,
+    ) {
+        let origin = self.relayed_origin(&endpoint_id);
+        if transceivers.is_empty() || self.targets(origin).is_empty() {
+            return;
+        }
+        let published_mids = self.published_mids.entry(endpoint_id).or_default();
+        let mut is_new = false;
+        for transceiver in transceivers.iter() {
+            is_new |= published_mids.insert(transceiver.mid.clone());
+        }
+        if is_new {
+            self.broadcast(origin, || RelayEvent::Publish {
+                endpoint_id,
+                transceivers: transceivers.clone(),
+            });
+        }
+    }
+
+    /// publish_to relays the tracks published by an endpoint to a peer which just joined
+    pub(crate) fn publish_to(
+        &mut self,
+        peer: RelayPeer,
+        endpoint_id: EndpointId,
+        transceivers: Vec<RTCRtpTransceiver>,
+    ) {
+        let origin = self.relayed_origin(&endpoint_id);
+        if transceivers.is_empty() || !self.targets(origin).contains(&peer) {
+            return;
+        }
+        self.published_mids.entry(endpoint_id).or_default().extend(
+            transceivers
+                .iter()
+                .map(|transceiver| transceiver.mid.clone()),
+        );
+        self.send(
+            peer,
+            RelayEvent::Publish {
+                endpoint_id,
+                transceivers,
+            },
+        );
+    }
+
+    /// relay_rtp relays a packet published by an endpoint to the peers
+    pub(crate) fn relay_rtp(
+        &mut self,
+        endpoint_id: EndpointId,
+        incoming_stream: &IncomingStream,
+        audio_level: Option<u8>,
+        packet: &rtp::packet::Packet,
+    ) {
+        let origin = self.relayed_origin(&endpoint_id);
+        self.broadcast(origin, || RelayEvent::Rtp {
+            endpoint_id,
+            incoming_stream: incoming_stream.clone(),
+            audio_level,
+            packet: packet.clone(),
+        });
+    }
+
+    /// relay_rtcp relays the RTCP sent by an endpoint to the peers
+    pub(crate) fn relay_rtcp(&mut self, endpoint_id: EndpointId, payload: Bytes) {
+        let origin = self.relayed_origin(&endpoint_id);
+        self.broadcast(origin, || RelayEvent::Rtcp {
+            endpoint_id,
+            payload: payload.clone(),
+        });
+    }
+
+    /// request_keyframes sends keyframe requests to the peer relaying the endpoint, and returns
+    /// whether it is a relayed one
+    pub(crate) fn request_keyframes(&mut self, endpoint_id: EndpointId, ssrcs: Vec<SSRC>) -> bool {
+        let Some(origin) = self.relayed_origin(&endpoint_id) else {
+            return false;
+        };
+        self.send(origin, RelayEvent::KeyframeRequest { endpoint_id, ssrcs });
+        true
+    }
+
+    /// push_relayed_packet keeps a packet relayed by a peer until the timeout loop forwards it
+    pub(crate) fn push_relayed_packet(
+        &mut self,
+        endpoint_id: EndpointId,
+        incoming_stream: IncomingStream,
+        audio_level: Option<u8>,
+        packet: rtp::packet::Packet,
+    ) {
+        self.relayed_packets
+            .push((endpoint_id, incoming_stream, audio_level, packet));
+    }
+
+    pub(crate) fn take_relayed_packets(
+        &mut self,
+    ) -> Vec<(EndpointId, IncomingStream, Option<u8>, rtp::packet::Packet)> {
+        std::mem::take(&mut self.relayed_packets)
+    }
+
+    pub(crate) fn push_relayed_rtcp(&mut self, endpoint_id: EndpointId, payload: Bytes) {
+        self.relayed_rtcp.push((endpoint_id, payload));
+    }
+
+    pub(crate) fn take_relayed_rtcp(&mut self) -> Vec<(EndpointId, Bytes)> {
+        std::mem::take(&mut self.relayed_rtcp)
+    }
+
+    /// relay_chat relays a chat message sent by an endpoint to the peers, given the peer which
+    /// relayed it, if any. Endpoints which publish nothing aren't known to the peers, so direct
+    /// messages are relayed like any other for the peer hosting the recipient to deliver them
+    pub(crate) fn relay_chat(&mut self, origin: Option<RelayPeer>, event: &ChatEvent) {
+        self.broadcast(origin, || RelayEvent::Chat {
+            event: event.clone(),
+        });
+    }
+
+    pub(crate) fn push_relayed_chat(&mut self, endpoint_id: EndpointId, event: ChatEvent) {
+        self.relayed_chat.push((endpoint_id, event));
+    }
+
+    pub(crate) fn take_relayed_chat(&mut self) -> Vec<(EndpointId, ChatEvent)> {
+        std::mem::take(&mut self.relayed_chat)
+    }
+
+    /// leave tells the peers this worker no longer hosts the session
+    pub(crate) fn leave(&mut self) {
+        let mut peers: Vec<RelayPeer> = self.peers.drain().collect();
+        // links whose node didn't join yet are left too
+        for link_id in self.links.drain() {
+            if !peers.contains(&RelayPeer::Node(link_id)) {
+                peers.push(RelayPeer::Node(link_id));
+            }
+        }
+        peers.sort_unstable();
+        for peer in peers {
+            self.send(peer, RelayEvent::Leave);
+        }
+    }
+
+    /// leave_link tells the node at the other end of the link this node no longer relays the
+    /// session with it, and returns the endpoints it relayed
+    pub(crate) fn leave_link(&mut self, link_id: u64) -> Vec<EndpointId> {
+        let peer = RelayPeer::Node(link_id);
+        if !self.links.contains(&link_id) {
+            return vec![];
+        }
+        self.send(peer, RelayEvent::Leave);
+        self.remove_peer(peer)
+    }
+
+    /// take_messages returns the messages to send, with the peer to send them to
+    pub(crate) fn take_messages(&mut self) -> Vec<(RelayPeer, RelayMessage)> {
+        std::mem::take(&mut self.messages)
+    }
+
+    /// targets returns the peers to relay the tracks of an endpoint to, given the peer relaying
+    /// it, if any. The workers of this node relay their own tracks to each other, so only the
+    /// nodes get the tracks relayed by a worker
+    fn targets(&self, origin: Option<RelayPeer>) -> Vec<RelayPeer> {
+        let mut peers: Vec<RelayPeer> = self
+            .peers
+            .iter()
+            .copied()
+            .filter(|&peer| match origin {
+                None => true,
+                Some(RelayPeer::Worker(_)) => matches!(peer, RelayPeer::Node(_)),
+                Some(origin) => peer != origin,
+            })
+            .collect();
+        peers.sort_unstable();
+        peers
+    }
+
+    fn send(&mut self, peer: RelayPeer, event: RelayEvent) {
+        self.messages.push((
+            peer,
+            RelayMessage {
+                session_id: self.session_id,
+                origin: RelayPeer::Worker(self.local_port),
+                event,
+            },
+        ));
+    }
+
+    fn broadcast(&mut self, origin: Option<RelayPeer>, event: impl Fn() -> RelayEvent) {
+        for peer in self.targets(origin) {
+            self.send(peer, event());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::description::rtp_codec::{RTCRtpParameters, RTPCodecType};
+    use crate::description::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+
+    #[test]
+    fn places_a_session_on_a_single_worker() {
+        let placement = WorkerPlacement::new(vec![3480, 3478, 3479, 3478], 1);
+        assert!(!placement.is_cascaded());
+        assert_eq!(placement.session_ports(4), vec![3479]);
+        assert_eq!(placement.session_port(4), Some(3479));
+        // every endpoint connects to the worker of its session
+        for endpoint_id in 0..4 {
+            assert_eq!(placement.endpoint_port(4, endpoint_id), Some(3479));
+        }
+    }
+
+    #[test]
+    fn spreads_a_cascaded_session_over_its_workers() {
+        let placement = WorkerPlacement::new(vec![3481, 3478, 3480, 3479], 2);
+        assert!(placement.is_cascaded());
+        assert_eq!(placement.session_ports(5), vec![3479, 3480]);
+        // the workers of a session wrap around the ports, its home first
+        assert_eq!(placement.session_ports(3), vec![3481, 3478]);
+        assert_eq!(placement.session_port(3), Some(3481));
+        assert_eq!(placement.endpoint_port(5, 4), Some(3479));
+        assert_eq!(placement.endpoint_port(5, 7), Some(3480));
+    }
+
+    #[test]
+    fn spans_no_more_workers_than_there_are() {
+        let placement = WorkerPlacement::new(vec![3478, 3479], 5);
+        assert_eq!(placement.session_ports(1), vec![3479, 3478]);
+
+        let placement = WorkerPlacement::new(vec![], 2);
+        assert!(placement.session_ports(1).is_empty());
+        assert_eq!(placement.session_port(1), None);
+        assert_eq!(placement.endpoint_port(1, 1), None);
+    }
+
+    fn round_trip(event: RelayEvent) -> RelayMessage {
+        let message = RelayMessage {
+            session_id: 42,
+            origin: RelayPeer::Worker(3478),
+            event,
+        };
+        let buf = message.marshal().unwrap();
+        let received = RelayMessage::unmarshal(7, buf.clone()).unwrap();
+        assert_eq!(received.session_id, 42);
+        assert_eq!(received.origin, RelayPeer::Node(7));
+        assert_eq!(received.marshal().unwrap(), buf);
+        received
+    }
+
+    #[test]
+    fn relays_the_membership_of_a_session() {
+        assert!(matches!(
+            round_trip(RelayEvent::Join).event,
+            RelayEvent::Join
+        ));
+        assert!(round_trip(RelayEvent::Leave).is_leave());
+        assert!(matches!(
+            round_trip(RelayEvent::Unpublish { endpoint_id: 3 }).event,
+            RelayEvent::Unpublish { endpoint_id: 3 }
+        ));
+
+        let transceiver = RTCRtpTransceiver {
+            mid: "0".to_string(),
+            sender: None,
+            direction: RTCRtpTransceiverDirection::Recvonly,
+            current_direction: RTCRtpTransceiverDirection::Recvonly,
+            rtp_params: RTCRtpParameters::default(),
+            kind: RTPCodecType::Audio,
+            rids: vec![],
+        };
+        match round_trip(RelayEvent::Publish {
+            endpoint_id: 3,
+            transceivers: vec![transceiver],
+        })
+        .event
+        {
+            RelayEvent::Publish {
+                endpoint_id,
+                transceivers,
+            } => {
+                assert_eq!(endpoint_id, 3);
+                assert_eq!(transceivers.len(), 1);
+                assert_eq!(transceivers[0].mid, "0");
+                assert_eq!(transceivers[0].kind, RTPCodecType::Audio);
+            }
+            _ => panic!("expected a publish"),
+        }
+    }
+
+    #[test]
+    fn relays_rtp_with_its_stream() {
+        let packet = rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: 96,
+                sequence_number: 7,
+                timestamp: 3000,
+                ssrc: 1234,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(&[1, 2, 3]),
+        };
+        for (rid, audio_level) in [(Some("h".to_string()), None), (None, Some(30))] {
+            match round_trip(RelayEvent::Rtp {
+                endpoint_id: 3,
+                incoming_stream: IncomingStream {
+                    mid: "1".to_string(),
+                    rid: rid.clone(),
+                    is_repair: true,
+                },
+                audio_level,
+                packet: packet.clone(),
+            })
+            .event
+            {
+                RelayEvent::Rtp {
+                    endpoint_id,
+                    incoming_stream,
+                    audio_level: received_audio_level,
+                    packet: received_packet,
+                } => {
+                    assert_eq!(endpoint_id, 3);
+                    assert_eq!(incoming_stream.mid, "1");
+                    assert_eq!(incoming_stream.rid, rid);
+                    assert!(incoming_stream.is_repair);
+                    assert_eq!(received_audio_level, audio_level);
+                    assert_eq!(received_packet, packet);
+                }
+                _ => panic!("expected rtp"),
+            }
+        }
+    }
+
+    #[test]
+    fn relays_rtcp_keyframe_requests_and_chat() {
+        match round_trip(RelayEvent::Rtcp {
+            endpoint_id: 3,
+            payload: Bytes::from_static(&[0x80, 0xC8, 0x00, 0x00]),
+        })
+        .event
+        {
+            RelayEvent::Rtcp {
+                endpoint_id,
+                payload,
+            } => assert_eq!((endpoint_id, &payload[..]), (3, &[0x80, 0xC8, 0, 0][..])),
+            _ => panic!("expected rtcp"),
+        }
+        match round_trip(RelayEvent::KeyframeRequest {
+            endpoint_id: 3,
+            ssrcs: vec![1234, 5678],
+        })
+        .event
+        {
+            RelayEvent::KeyframeRequest { endpoint_id, ssrcs } => {
+                assert_eq!((endpoint_id, ssrcs), (3, vec![1234, 5678]))
+            }
+            _ => panic!("expected a keyframe request"),
+        }
+        let chat = ChatEvent::RaiseHand {
+            from: 3,
+            raised: true,
+        };
+        match round_trip(RelayEvent::Chat {
+            event: chat.clone(),
+        })
+        .event
+        {
+            RelayEvent::Chat { event } => assert_eq!(event, chat),
+            _ => panic!("expected chat"),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_relay_messages() {
+        let buf = RelayMessage {
+            session_id: 42,
+            origin: RelayPeer::Worker(3478),
+            event: RelayEvent::Unpublish { endpoint_id: 3 },
+        }
+        .marshal()
+        .unwrap();
+        for len in [0, 8, buf.len() - 1] {
+            assert!(RelayMessage::unmarshal(7, buf.slice(..len)).is_err());
+        }
+
+        let mut unknown = buf.to_vec();
+        unknown[0] = 0xFF;
+        assert!(RelayMessage::unmarshal(7, Bytes::from(unknown)).is_err());
+
+        // a mid is relayed with a single byte length
+        let message = RelayMessage {
+            session_id: 42,
+            origin: RelayPeer::Worker(3478),
+            event: RelayEvent::Rtp {
+                endpoint_id: 3,
+                incoming_stream: IncomingStream {
+                    mid: "m".repeat(256),
+                    rid: None,
+                    is_repair: false,
+                },
+                audio_level: None,
+                packet: rtp::packet::Packet::default(),
+            },
+        };
+        assert!(message.marshal().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::cascade::{RelayEvent, RelayMessage, RelayPeer};
+use crate::description::rtp_transceiver::RTCRtpTransceiver;
+use crate::endpoint::IncomingStream;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use shared::error::{Error, Result};
+use shared::marshal::{Marshal, Unmarshal};
+
+const KIND_JOIN: u8 = 0;
+const KIND_LEAVE: u8 = 1;
+const KIND_PUBLISH: u8 = 2;
+const KIND_UNPUBLISH: u8 = 3;
+const KIND_RTP: u8 = 4;
+const KIND_RTCP: u8 = 5;
+const KIND_KEYFRAME_REQUEST: u8 = 6;
+const KIND_CHAT: u8 = 7;
+
+/// audio levels range from 0 to 127 dBov
+const NO_AUDIO_LEVEL: u8 = 0xFF;
+
+impl RelayMessage {
+    /// marshal encodes the message for a link to another node, as its kind, the session id and
+    /// the fields of the event. The packets are sent decrypted, the link being trusted
+    pub fn marshal(&self) -> Result<Bytes> {
+        let mut buf = BytesMut::new();
+        match &self.event {
+            RelayEvent::Join => put_header(&mut buf, KIND_JOIN, self.session_id),
+            RelayEvent::Leave => put_header(&mut buf, KIND_LEAVE, self.session_id),
+            RelayEvent::Publish {
+                endpoint_id,
+                transceivers,
+            } => {
+                put_header(&mut buf, KIND_PUBLISH, self.session_id);
+                buf.put_u64(*endpoint_id);
+                let transceivers = serde_json::to_vec(transceivers)
+                    .map_err(|err| Error::Other(err.to_string()))?;
+                buf.put_slice(&transceivers);
+            }
+            RelayEvent::Unpublish { endpoint_id } => {
+                put_header(&mut buf, KIND_UNPUBLISH, self.session_id);
+                buf.put_u64(*endpoint_id);
+            }
+            RelayEvent::Rtp {
+                endpoint_id,
+                incoming_stream,
+                audio_level,
+                packet,
+            } => {
+                put_header(&mut buf, KIND_RTP, self.session_id);
+                buf.put_u64(*endpoint_id);
+                put_string(&mut buf, &incoming_stream.mid)?;
+                put_string(&mut buf, incoming_stream.rid.as_deref().unwrap_or_default())?;
+                buf.put_u8(incoming_stream.is_repair as u8);
+                buf.put_u8(audio_level.unwrap_or(NO_AUDIO_LEVEL));
+                buf.put_slice(&packet.marshal()?);
+            }
+            RelayEvent::Rtcp {
+                endpoint_id,
+                payload,
+            } => {
+                put_header(&mut buf, KIND_RTCP, self.session_id);
+                buf.put_u64(*endpoint_id);
+                buf.put_slice(payload);
+            }
+            RelayEvent::KeyframeRequest { endpoint_id, ssrcs } => {
+                put_header(&mut buf, KIND_KEYFRAME_REQUEST, self.session_id);
+                buf.put_u64(*endpoint_id);
+                for ssrc in ssrcs {
+                    buf.put_u32(*ssrc);
+                }
+            }
+            RelayEvent::Chat { event } => {
+                put_header(&mut buf, KIND_CHAT, self.session_id);
+                let event =
+                    serde_json::to_vec(event).map_err(|err| Error::Other(err.to_string()))?;
+                buf.put_slice(&event);
+            }
+        }
+        Ok(buf.freeze())
+    }
+
+    /// unmarshal decodes a message received on the link to another node
+    pub fn unmarshal(link_id: u64, mut buf: Bytes) -> Result<Self> {
+        if buf.remaining() < 9 {
+            return Err(Error::Other("relay message too short".to_string()));
+        }
+        let kind = buf.get_u8();
+        let session_id = buf.get_u64();
+        let event = match kind {
+            KIND_JOIN => RelayEvent::Join,
+            KIND_LEAVE => RelayEvent::Leave,
+            KIND_PUBLISH => {
+                let endpoint_id = get_u64(&mut buf)?;
+                let transceivers: Vec<RTCRtpTransceiver> =
+                    serde_json::from_slice(&buf).map_err(|err| Error::Other(err.to_string()))?;
+                RelayEvent::Publish {
+                    endpoint_id,
+                    transceivers,
+                }
+            }
+            KIND_UNPUBLISH => RelayEvent::Unpublish {
+                endpoint_id: get_u64(&mut buf)?,
+            },
+            KIND_RTP => {
+                let endpoint_id = get_u64(&mut buf)?;
+                let mid = get_string(&mut buf)?;
+                let rid = get_string(&mut buf)?;
+                if buf.remaining() < 2 {
+                    return Err(Error::Other("relayed rtp too short".to_string()));
+                }
+                let is_repair = buf.get_u8() != 0;
+                let audio_level = Some(buf.get_u8()).filter(|&level| level != NO_AUDIO_LEVEL);
+                let packet = rtp::packet::Packet::unmarshal(&mut buf)?;
+                RelayEvent::Rtp {
+                    endpoint_id,
+                    incoming_stream: IncomingStream {
+                        mid,
+                        rid: Some(rid).filter(|rid| !rid.is_empty()),
+                        is_repair,
+                    },
+                    audio_level,
+                    packet,
+                }
+            }
+            KIND_RTCP => RelayEvent::Rtcp {
+                endpoint_id: get_u64(&mut buf)?,
+                payload: buf,
+            },
+            KIND_KEYFRAME_REQUEST => {
+                let endpoint_id = get_u64(&mut buf)?;
+                let mut ssrcs = vec![];
+                while buf.remaining() >= 4 {
+                    ssrcs.push(buf.get_u32());
+                }
+                RelayEvent::KeyframeRequest { endpoint_id, ssrcs }
+            }
+            KIND_CHAT => RelayEvent::Chat {
+                event: serde_json::from_slice(&buf).map_err(|err| Error::Other(err.to_string()))?,
+            },
+            _ => return Err(Error::Other(format!("unknown relay message kind {}", kind))),
+        };
+        Ok(Self {
+            session_id,
+            origin: RelayPeer::Node(link_id),
+            event,
+        })
+    }
+}
+
+fn put_header(buf: &mut BytesMut, kind: u8, session_id: u64) {
+    buf.put_u8(kind);
+    buf.put_u64(session_id);
+}
+
+fn put_string(buf: &mut BytesMut, value: &str) -> Result<()> {
+    let len = u8::try_from(value.len())
+        .map_err(|_| Error::Other(format!("{} is too long to relay", value)))?;
+    buf.put_u8(len);
+    buf.put_slice(value.as_bytes());
+    Ok(())
+}
+
+fn get_u64(buf: &mut Bytes) -> Result<u64> {
+    if buf.remaining() < 8 {
+        return Err(Error::Other("relay message too short".to_string()));
+    }
+    Ok(buf.get_u64())
+}
+
+fn get_string(buf: &mut Bytes) -> Result<String> {
+    if !buf.has_remaining() {
+        return Err(Error::Other("relay message too short".to_string()));
+    }
+    let len = buf.get_u8() as usize;
+    if buf.remaining() < len {
+        return Err(Error::Other("relay message too short".to_string()));
+    }
+    String::from_utf8(buf.split_to(len).to_vec()).map_err(|err| Error::Other(err.to_string()))
+}
@@ -0,0 +1,292 @@
+use crate::types::EndpointId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// ChatLimits bounds the chat messages an endpoint sends, in bytes per message and in messages
+/// per second, bursts of up to burst messages being let through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ChatLimits {
+    pub(crate) max_message_size: usize,
+    /// messages per second, unlimited when 0
+    pub(crate) rate: u32,
+    pub(crate) burst: u32,
+}
+
+impl Default for ChatLimits {
+    fn default() -> Self {
+        Self {
+            max_message_size: 4096,
+            rate: 5,
+            burst: 10,
+        }
+    }
+}
+
+/// ChatRequest is a message an endpoint sends over its data channel, for the SFU to relay to the
+/// other endpoints of its session
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ChatRequest {
+    /// chat message to every other endpoint of the session
+    Chat {
+        text: String,
+    },
+    /// chat message to a single endpoint
+    DirectMessage {
+        to: EndpointId,
+        text: String,
+    },
+    Typing {
+        is_typing: bool,
+    },
+    RaiseHand {
+        raised: bool,
+    },
+    Reaction {
+        reaction: String,
+    },
+}
+
+impl ChatRequest {
+    /// into_event names the endpoint the request is from, and timestamps chat messages
+    pub(crate) fn into_event(self, from: EndpointId) -> ChatEvent {
+        match self {
+            ChatRequest::Chat { text } => ChatEvent::Chat {
+                from,
+                text,
+                timestamp: unix_millis(),
+            },
+            ChatRequest::DirectMessage { to, text } => ChatEvent::DirectMessage {
+                from,
+                to,
+                text,
+                timestamp: unix_millis(),
+            },
+            ChatRequest::Typing { is_typing } => ChatEvent::Typing { from, is_typing },
+            ChatRequest::RaiseHand { raised } => ChatEvent::RaiseHand { from, raised },
+            ChatRequest::Reaction { reaction } => ChatEvent::Reaction { from, reaction },
+        }
+    }
+}
+
+/// ChatEvent is a chat message relayed by the SFU to the endpoints of a session over their data
+/// channel, or the reason the message of an endpoint was rejected, sent back to it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ChatEvent {
+    Chat {
+        from: EndpointId,
+        text: String,
+        /// unix time in milliseconds the SFU received the message at
+        timestamp: u64,
+    },
+    DirectMessage {
+        from: EndpointId,
+        to: EndpointId,
+        text: String,
+        timestamp: u64,
+    },
+    Typing {
+        from: EndpointId,
+        is_typing: bool,
+    },
+    RaiseHand {
+        from: EndpointId,
+        raised: bool,
+    },
+    Reaction {
+        from: EndpointId,
+        reaction: String,
+    },
+    ChatError {
+        reason: String,
+    },
+}
+
+impl ChatEvent {
+    /// sender returns the endpoint the message is from, errors being from the SFU
+    pub(crate) fn sender(&self) -> Option<EndpointId> {
+        match self {
+            ChatEvent::Chat { from, .. }
+            | ChatEvent::DirectMessage { from, .. }
+            | ChatEvent::Typing { from, .. }
+            | ChatEvent::RaiseHand { from, .. }
+            | ChatEvent::Reaction { from, .. } => Some(*from),
+            ChatEvent::ChatError { .. } => None,
+        }
+    }
+
+    /// is_for tells whether the event is sent to the endpoint, direct messages and errors going
+    /// to a single endpoint and the others to every endpoint but the sender
+    pub(crate) fn is_for(&self, endpoint_id: EndpointId, sender: EndpointId) -> bool {
+        match self {
+            ChatEvent::DirectMessage { to, .. } => *to == endpoint_id,
+            ChatEvent::ChatError { .. } => sender == endpoint_id,
+            _ => sender != endpoint_id,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RateBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// ChatRoom enforces the chat limits of the endpoints of a session connected to this worker,
+/// the messages of the endpoints of other workers being checked by their own worker
+#[derive(Default, Debug)]
+pub(crate) struct ChatRoom {
+    buckets: HashMap<EndpointId, RateBucket>,
+}
+
+impl ChatRoom {
+    /// admit checks a message of the endpoint against the limits, taking it from the messages
+    /// the endpoint may still send, and returns why it is rejected otherwise
+    pub(crate) fn admit(
+        &mut self,
+        endpoint_id: EndpointId,
+        size: usize,
+        limits: &ChatLimits,
+        now: Instant,
+    ) -> std::result::Result<(), String> {
+        if size > limits.max_message_size {
+            return Err(format!(
+                "message of {} bytes exceeds the limit of {} bytes",
+                size, limits.max_message_size
+            ));
+        }
+        if limits.rate == 0 {
+            return Ok(());
+        }
+
+        let burst = limits.burst.max(1) as f64;
+        let bucket = self.buckets.entry(endpoint_id).or_insert(RateBucket {
+            tokens: burst,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limits.rate as f64).min(burst);
+        bucket.last_refill = now;
+        if bucket.tokens < 1.0 {
+            return Err(format!(
+                "rate limit of {} messages per second exceeded",
+                limits.rate
+            ));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    pub(crate) fn remove_endpoint(&mut self, endpoint_id: &EndpointId) {
+        self.buckets.remove(endpoint_id);
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const LIMITS: ChatLimits = ChatLimits {
+        max_message_size: 4096,
+        rate: 5,
+        burst: 10,
+    };
+
+    #[test]
+    fn rejects_messages_over_the_size_limit() {
+        let mut chat_room = ChatRoom::default();
+        let now = Instant::now();
+        assert!(chat_room.admit(1, 4096, &LIMITS, now).is_ok());
+        assert_eq!(
+            chat_room.admit(1, 4097, &LIMITS, now),
+            Err("message of 4097 bytes exceeds the limit of 4096 bytes".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_messages_over_the_rate_once_the_burst_is_spent() {
+        let mut chat_room = ChatRoom::default();
+        let now = Instant::now();
+        for _ in 0..LIMITS.burst {
+            assert!(chat_room.admit(1, 10, &LIMITS, now).is_ok());
+        }
+        assert_eq!(
+            chat_room.admit(1, 10, &LIMITS, now),
+            Err("rate limit of 5 messages per second exceeded".to_string())
+        );
+        // every endpoint has a bucket of its own
+        assert!(chat_room.admit(2, 10, &LIMITS, now).is_ok());
+    }
+
+    #[test]
+    fn refills_the_bucket_at_the_rate() {
+        let mut chat_room = ChatRoom::default();
+        let mut now = Instant::now();
+        for _ in 0..LIMITS.burst {
+            assert!(chat_room.admit(1, 10, &LIMITS, now).is_ok());
+        }
+        // a message every 200 ms at 5 messages per second
+        now += Duration::from_millis(200);
+        assert!(chat_room.admit(1, 10, &LIMITS, now).is_ok());
+        assert!(chat_room.admit(1, 10, &LIMITS, now).is_err());
+
+        // the bucket holds no more than the burst, however long the endpoint stays silent
+        now += Duration::from_secs(60);
+        for _ in 0..LIMITS.burst {
+            assert!(chat_room.admit(1, 10, &LIMITS, now).is_ok());
+        }
+        assert!(chat_room.admit(1, 10, &LIMITS, now).is_err());
+
+        // a removed endpoint starts over with a full bucket
+        chat_room.remove_endpoint(&1);
+        assert!(chat_room.admit(1, 10, &LIMITS, now).is_ok());
+    }
+
+    #[test]
+    fn admits_any_rate_without_limit() {
+        let mut chat_room = ChatRoom::default();
+        let limits = ChatLimits { rate: 0, ..LIMITS };
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert!(chat_room.admit(1, 10, &limits, now).is_ok());
+        }
+    }
+
+    #[test]
+    fn routes_direct_messages_to_their_recipient() {
+        let request: ChatRequest =
+            serde_json::from_str(r#"{"type":"direct_message","to":3,"text":"hi"}"#).unwrap();
+        let event = request.into_event(1);
+        assert_eq!(event.sender(), Some(1));
+        assert!(event.is_for(3, 1));
+        assert!(!event.is_for(2, 1));
+        assert!(!event.is_for(1, 1));
+    }
+
+    #[test]
+    fn routes_messages_to_every_endpoint_but_their_sender() {
+        let request: ChatRequest = serde_json::from_str(r#"{"type":"chat","text":"hi"}"#).unwrap();
+        let event = request.into_event(1);
+        assert!(event.is_for(2, 1));
+        assert!(event.is_for(3, 1));
+        assert!(!event.is_for(1, 1));
+
+        // errors go back to the sender only
+        let error = ChatEvent::ChatError {
+            reason: "too fast".to_string(),
+        };
+        assert_eq!(error.sender(), None);
+        assert!(error.is_for(1, 1));
+        assert!(!error.is_for(2, 1));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::description::config::MIME_TYPE_H264;
+use crate::description::fmtp;
+use crate::description::rtp_codec::{RTCRtpCodecParameters, RTPCodecType};
+use serde::Deserialize;
+
+const CODEC_NAME_RTX: &str = "rtx";
+const CODEC_NAME_RED: &str = "red";
+const CODEC_NAMES_FEC: [&str; 3] = ["ulpfec", "flexfec", "flexfec-03"];
+
+/// CodecPolicy restricts and orders the codecs offered and answered to the endpoints of a
+/// session, so that every endpoint can decode every track published in it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CodecPolicy {
+    /// audio codecs allowed, by name or mime type, the preferred first, all when empty
+    pub audio_codecs: Vec<String>,
+    /// video codecs allowed, by name or mime type, the preferred first, all when empty
+    pub video_codecs: Vec<String>,
+    /// H.264 profile-level-ids allowed, all when empty
+    pub h264_profile_level_ids: Vec<String>,
+    pub rtx: bool,
+    pub red: bool,
+    pub fec: bool,
+}
+
+impl Default for CodecPolicy {
+    fn default() -> Self {
+        Self {
+            audio_codecs: vec![],
+            video_codecs: vec![],
+            h264_profile_level_ids: vec![],
+            rtx: true,
+            red: true,
+            fec: true,
+        }
+    }
+}
+
+/// codec_name returns the name of the codec, without the media type of its mime type
+fn codec_name(mime_type: &str) -> &str {
+    mime_type
+        .split_once('/')
+        .map_or(mime_type, |(_, name)| name)
+}
+
+impl CodecPolicy {
+    /// build with the allowed audio codecs, the preferred first
+    pub fn with_audio_codecs(mut self, audio_codecs: Vec<String>) -> Self {
+        self.audio_codecs = audio_codecs;
+        self
+    }
+
+    /// build with the allowed video codecs, the preferred first
+    pub fn with_video_codecs(mut self, video_codecs: Vec<String>) -> Self {
+        self.video_codecs = video_codecs;
+        self
+    }
+
+    /// build with the allowed H.264 profile-level-ids
+    pub fn with_h264_profile_level_ids(mut self, h264_profile_level_ids: Vec<String>) -> Self {
+        self.h264_profile_level_ids = h264_profile_level_ids;
+        self
+    }
+
+    /// build with RTX retransmissions allowed or not
+    pub fn with_rtx(mut self, rtx: bool) -> Self {
+        self.rtx = rtx;
+        self
+    }
+
+    /// build with RED redundant encoding allowed or not
+    pub fn with_red(mut self, red: bool) -> Self {
+        self.red = red;
+        self
+    }
+
+    /// build with FEC allowed or not
+    pub fn with_fec(mut self, fec: bool) -> Self {
+        self.fec = fec;
+        self
+    }
+
+    /// apply filters the codecs of the kind down to the allowed ones, in the preferred order,
+    /// RTX being kept only for the allowed codecs it repairs
+    pub(crate) fn apply(
+        &self,
+        typ: RTPCodecType,
+        codecs: &[RTCRtpCodecParameters],
+    ) -> Vec<RTCRtpCodecParameters> {
+        let preferred_codecs = match typ {
+            RTPCodecType::Audio => &self.audio_codecs,
+            RTPCodecType::Video => &self.video_codecs,
+            _ => return codecs.to_vec(),
+        };
+        let preference = |codec: &RTCRtpCodecParameters| {
+            let name = codec_name(&codec.capability.mime_type);
+            preferred_codecs
+                .iter()
+                .position(|preferred_codec| codec_name(preferred_codec).eq_ignore_ascii_case(name))
+        };
+        let is_repair = |codec: &RTCRtpCodecParameters| {
+            let name = codec_name(&codec.capability.mime_type);
+            name.eq_ignore_ascii_case(CODEC_NAME_RTX)
+                || name.eq_ignore_ascii_case(CODEC_NAME_RED)
+                || CODEC_NAMES_FEC
+                    .iter()
+                    .any(|fec_name| name.eq_ignore_ascii_case(fec_name))
+        };
+
+        let mut media_codecs: Vec<&RTCRtpCodecParameters> = codecs
+            .iter()
+            .filter(|codec| !is_repair(codec))
+            .filter(|codec| preferred_codecs.is_empty() || preference(codec).is_some())
+            .filter(|codec| self.is_allowed_h264_profile(codec))
+            .collect();
+        // sort is stable, the codecs of the same name keep the order they are registered in
+        media_codecs.sort_by_key(|codec| preference(codec));
+
+        let repair_codecs = codecs
+            .iter()
+            .filter(|codec| is_repair(codec))
+            .filter(|codec| {
+                let name = codec_name(&codec.capability.mime_type);
+                if name.eq_ignore_ascii_case(CODEC_NAME_RTX) {
+                    self.rtx
+                        && fmtp::parse(&codec.capability.mime_type, &codec.capability.sdp_fmtp_line)
+                            .parameter("apt")
+                            .and_then(|apt| apt.parse::<u8>().ok())
+                            .is_some_and(|apt| {
+                                media_codecs
+                                    .iter()
+                                    .any(|media_codec| media_codec.payload_type == apt)
+                            })
+                } else if name.eq_ignore_ascii_case(CODEC_NAME_RED) {
+                    self.red
+                } else {
+                    self.fec
+                }
+            });
+
+        media_codecs
+            .iter()
+            .copied()
+            .chain(repair_codecs)
+            .cloned()
+            .collect()
+    }
+
+    fn is_allowed_h264_profile(&self, codec: &RTCRtpCodecParameters) -> bool {
+        if self.h264_profile_level_ids.is_empty()
+            || !codec
+                .capability
+                .mime_type
+                .eq_ignore_ascii_case(MIME_TYPE_H264)
+        {
+            return true;
+        }
+        fmtp::parse(&codec.capability.mime_type, &codec.capability.sdp_fmtp_line)
+            .parameter("profile-level-id")
+            .is_some_and(|profile_level_id| {
+                self.h264_profile_level_ids
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(profile_level_id))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::description::config::{
+        MIME_TYPE_OPUS, MIME_TYPE_PCMU, MIME_TYPE_RTX, MIME_TYPE_VP8, MIME_TYPE_VP9,
+    };
+    use crate::description::rtp_codec::RTCRtpCodecCapability;
+
+    fn codec(mime_type: &str, payload_type: u8, sdp_fmtp_line: &str) -> RTCRtpCodecParameters {
+        RTCRtpCodecParameters {
+            capability: RTCRtpCodecCapability {
+                mime_type: mime_type.to_string(),
+                clock_rate: 90000,
+                sdp_fmtp_line: sdp_fmtp_line.to_string(),
+                ..Default::default()
+            },
+            payload_type,
+            ..Default::default()
+        }
+    }
+
+    fn video_codecs() -> Vec<RTCRtpCodecParameters> {
+        vec![
+            codec(MIME_TYPE_VP8, 96, ""),
+            codec(MIME_TYPE_RTX, 97, "apt=96"),
+            codec(MIME_TYPE_VP9, 98, "profile-id=0"),
+            codec(MIME_TYPE_RTX, 99, "apt=98"),
+            codec(
+                MIME_TYPE_H264,
+                102,
+                "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42001f",
+            ),
+            codec(MIME_TYPE_RTX, 103, "apt=102"),
+            codec(
+                MIME_TYPE_H264,
+                104,
+                "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=640032",
+            ),
+            codec(MIME_TYPE_RTX, 105, "apt=104"),
+            codec("video/red", 116, ""),
+            codec("video/ulpfec", 117, ""),
+        ]
+    }
+
+    fn payload_types(codecs: &[RTCRtpCodecParameters]) -> Vec<u8> {
+        codecs.iter().map(|codec| codec.payload_type).collect()
+    }
+
+    #[test]
+    fn keeps_every_codec_by_default() {
+        let policy = CodecPolicy::default();
+        // the repair codecs follow the media codecs
+        assert_eq!(
+            payload_types(&policy.apply(RTPCodecType::Video, &video_codecs())),
+            vec![96, 98, 102, 104, 97, 99, 103, 105, 116, 117]
+        );
+    }
+
+    #[test]
+    fn orders_the_allowed_codecs_by_preference() {
+        let policy = CodecPolicy::default()
+            .with_video_codecs(vec!["H264".to_string(), MIME_TYPE_VP8.to_string()]);
+        // the H.264 codecs keep the order they are registered in, RTX follows the media codecs
+        assert_eq!(
+            payload_types(&policy.apply(RTPCodecType::Video, &video_codecs())),
+            vec![102, 104, 96, 97, 103, 105, 116, 117]
+        );
+
+        let audio_codecs = vec![
+            codec(MIME_TYPE_OPUS, 111, "minptime=10;useinbandfec=1"),
+            codec(MIME_TYPE_PCMU, 0, ""),
+        ];
+        let policy = CodecPolicy::default().with_audio_codecs(vec!["pcmu".to_string()]);
+        assert_eq!(
+            payload_types(&policy.apply(RTPCodecType::Audio, &audio_codecs)),
+            vec![0]
+        );
+        // the video codecs don't apply to audio
+        let policy = CodecPolicy::default().with_video_codecs(vec!["VP8".to_string()]);
+        assert_eq!(
+            policy.apply(RTPCodecType::Audio, &audio_codecs),
+            audio_codecs
+        );
+    }
+
+    #[test]
+    fn keeps_rtx_only_for_the_allowed_codecs() {
+        let policy = CodecPolicy::default().with_video_codecs(vec!["VP9".to_string()]);
+        assert_eq!(
+            payload_types(&policy.apply(RTPCodecType::Video, &video_codecs())),
+            vec![98, 99, 116, 117]
+        );
+
+        // an RTX codec without apt repairs nothing
+        let codecs = vec![codec(MIME_TYPE_VP8, 96, ""), codec(MIME_TYPE_RTX, 97, "")];
+        assert_eq!(
+            payload_types(&CodecPolicy::default().apply(RTPCodecType::Video, &codecs)),
+            vec![96]
+        );
+    }
+
+    #[test]
+    fn drops_the_disabled_repair_codecs() {
+        let policy = CodecPolicy::default().with_rtx(false);
+        assert_eq!(
+            payload_types(&policy.apply(RTPCodecType::Video, &video_codecs())),
+            vec![96, 98, 102, 104, 116, 117]
+        );
+        let policy = CodecPolicy::default().with_red(false).with_fec(false);
+        assert_eq!(
+            payload_types(&policy.apply(RTPCodecType::Video, &video_codecs())),
+            vec![96, 98, 102, 104, 97, 99, 103, 105]
+        );
+        let policy = CodecPolicy::default().with_fec(false);
+        assert_eq!(
+            payload_types(&policy.apply(RTPCodecType::Video, &video_codecs())),
+            vec![96, 98, 102, 104, 97, 99, 103, 105, 116]
+        );
+    }
+
+    #[test]
+    fn keeps_the_allowed_h264_profiles() {
+        let policy = CodecPolicy::default().with_h264_profile_level_ids(vec!["42001F".to_string()]);
+        // the other codecs are left alone, and so is the RTX of the allowed profile
+        assert_eq!(
+            payload_types(&policy.apply(RTPCodecType::Video, &video_codecs())),
+            vec![96, 98, 102, 97, 99, 103, 116, 117]
+        );
+
+        let policy = CodecPolicy::default()
+            .with_video_codecs(vec!["H264".to_string()])
+            .with_h264_profile_level_ids(vec!["4d001f".to_string()]);
+        assert!(policy
+            .apply(RTPCodecType::Video, &video_codecs())
+            .iter()
+            .all(|codec| !codec
+                .capability
+                .mime_type
+                .eq_ignore_ascii_case(MIME_TYPE_H264)));
+    }
+}
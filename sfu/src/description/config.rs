@@ -0,0 +1,1292 @@
+use crate::description::{
+    codecs_from_media_description, fmtp,
+    rtp_codec::{
+        codec_parameters_fuzzy_search, CodecMatch, RTCRtpCodecCapability, RTCRtpCodecParameters,
+        RTCRtpHeaderExtensionCapability, RTCRtpHeaderExtensionParameters, RTCRtpParameters,
+        RTPCodecType,
+    },
+    rtp_extensions_from_media_description,
+    rtp_transceiver::{TYPE_RTCP_FB_CCM, TYPE_RTCP_FB_GOOG_REMB, TYPE_RTCP_FB_TRANSPORT_CC},
+    rtp_transceiver_direction::RTCRtpTransceiverDirection,
+    PayloadType, RTCPFeedback,
+};
+
+//TODO: use crate::stats::stats_collector::StatsCollector;
+//use crate::stats::CodecStats;
+//use crate::stats::StatsReportType::Codec;
+use crate::interceptor::nack::responder::Responder;
+use crate::interceptor::report::receiver_report::ReceiverReport;
+use crate::interceptor::report::sender_report::SenderReport;
+use crate::interceptor::twcc::sender::Sender;
+use crate::interceptor::Registry;
+use sdp::description::session::SessionDescription;
+use shared::error::{Error, Result};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// MIME_TYPE_H264 H264 MIME type.
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_H264: &str = "video/H264";
+/// MIME_TYPE_OPUS Opus MIME type
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_OPUS: &str = "audio/opus";
+/// MIME_TYPE_VP8 VP8 MIME type
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_VP8: &str = "video/VP8";
+/// MIME_TYPE_VP9 VP9 MIME type
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_VP9: &str = "video/VP9";
+/// MIME_TYPE_AV1 AV1 MIME type
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_AV1: &str = "video/AV1";
+/// MIME_TYPE_G722 G722 MIME type
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_G722: &str = "audio/G722";
+/// MIME_TYPE_PCMU PCMU MIME type
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_PCMU: &str = "audio/PCMU";
+/// MIME_TYPE_PCMA PCMA MIME type
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_PCMA: &str = "audio/PCMA";
+/// MIME_TYPE_RTX RTX MIME type
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_RTX: &str = "video/rtx";
+/// MIME_TYPE_TELEPHONE_EVENT telephone-event MIME type
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_TELEPHONE_EVENT: &str = "audio/telephone-event";
+
+/// SDES_REPAIR_RTP_STREAM_ID_URI carries the rid of the stream repaired by an RTX packet.
+pub const SDES_REPAIR_RTP_STREAM_ID_URI: &str =
+    "urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id";
+
+const VALID_EXT_IDS: Range<isize> = 1..15;
+
+/// DEFAULT_NACK_BUFFER_SIZE is the number of packets kept per stream to answer NACKs
+pub const DEFAULT_NACK_BUFFER_SIZE: u16 = 1024;
+
+#[derive(Default, Debug, Clone)]
+pub(crate) struct RTCRtpHeaderExtension {
+    pub(crate) uri: String,
+    pub(crate) is_audio: bool,
+    pub(crate) is_video: bool,
+    pub(crate) allowed_direction: Option<RTCRtpTransceiverDirection>,
+}
+
+impl RTCRtpHeaderExtension {
+    pub fn is_matching_direction(&self, dir: RTCRtpTransceiverDirection) -> bool {
+        if let Some(allowed_direction) = self.allowed_direction {
+            use RTCRtpTransceiverDirection::*;
+            allowed_direction == Inactive && dir == Inactive
+                || allowed_direction.has_send() && dir.has_send()
+                || allowed_direction.has_recv() && dir.has_recv()
+        } else {
+            // None means all directions matches.
+            true
+        }
+    }
+}
+
+/// A MediaConfig defines the codecs supported by a PeerConnection, and the
+/// configuration of those codecs. A MediaConfig must not be rtc-shared between
+/// PeerConnections.
+pub struct MediaConfig {
+    registry: Registry,
+    nack_buffer_size: u16,
+
+    // If we have attempted to negotiate a codec type yet.
+    pub(crate) negotiated_video: bool,
+    pub(crate) negotiated_audio: bool,
+
+    pub(crate) video_codecs: Vec<RTCRtpCodecParameters>,
+    pub(crate) audio_codecs: Vec<RTCRtpCodecParameters>,
+    pub(crate) negotiated_video_codecs: Vec<RTCRtpCodecParameters>,
+    pub(crate) negotiated_audio_codecs: Vec<RTCRtpCodecParameters>,
+
+    header_extensions: Vec<RTCRtpHeaderExtension>,
+    proposed_header_extensions: HashMap<isize, RTCRtpHeaderExtension>,
+    pub(crate) negotiated_header_extensions: HashMap<isize, RTCRtpHeaderExtension>,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        MediaConfig::with_nack_buffer_size(DEFAULT_NACK_BUFFER_SIZE)
+    }
+}
+
+impl MediaConfig {
+    /// with_nack_buffer_size creates a MediaConfig with the default codecs and interceptors,
+    /// keeping nack_buffer_size packets per stream to answer NACKs
+    pub fn with_nack_buffer_size(nack_buffer_size: u16) -> Self {
+        let mut media_config = MediaConfig {
+            registry: Registry::new(),
+            nack_buffer_size,
+
+            negotiated_video: false,
+            negotiated_audio: false,
+            video_codecs: vec![],
+            audio_codecs: vec![],
+            negotiated_video_codecs: vec![],
+            negotiated_audio_codecs: vec![],
+            header_extensions: vec![],
+            proposed_header_extensions: HashMap::new(),
+            negotiated_header_extensions: HashMap::new(),
+        };
+
+        let _ = media_config.register_default_codecs();
+        let _ = media_config.register_default_interceptors();
+
+        media_config
+    }
+
+    /// get Registry
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// register_default_codecs registers the default codecs supported by Pion WebRTC.
+    /// register_default_codecs is not safe for concurrent use.
+    pub fn register_default_codecs(&mut self) -> Result<()> {
+        // Default Audio Codecs
+        for codec in [
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_OPUS.to_owned(),
+                    clock_rate: 48000,
+                    channels: 2,
+                    sdp_fmtp_line: "minptime=10;useinbandfec=1".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 111,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_G722.to_owned(),
+                    clock_rate: 8000,
+                    channels: 0,
+                    sdp_fmtp_line: "".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 9,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_PCMU.to_owned(),
+                    clock_rate: 8000,
+                    channels: 0,
+                    sdp_fmtp_line: "".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 0,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_PCMA.to_owned(),
+                    clock_rate: 8000,
+                    channels: 0,
+                    sdp_fmtp_line: "".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 8,
+                ..Default::default()
+            },
+        ] {
+            self.register_codec(codec, RTPCodecType::Audio)?;
+        }
+
+        let video_rtcp_feedback = vec![
+            /*TODO: RTCPFeedback {
+                typ: "goog-remb".to_owned(),
+                parameter: "".to_owned(),
+            },
+            RTCPFeedback {
+                typ: "ccm".to_owned(),
+                parameter: "fir".to_owned(),
+            },
+            RTCPFeedback {
+                typ: "nack".to_owned(),
+                parameter: "".to_owned(),
+            },
+            RTCPFeedback {
+                typ: "nack".to_owned(),
+                parameter: "pli".to_owned(),
+            },*/
+        ];
+        for codec in vec![
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_VP8.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "".to_owned(),
+                    rtcp_feedbacks: video_rtcp_feedback.clone(),
+                },
+                payload_type: 96,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_VP9.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "profile-id=0".to_owned(),
+                    rtcp_feedbacks: video_rtcp_feedback.clone(),
+                },
+                payload_type: 98,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_VP9.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "profile-id=1".to_owned(),
+                    rtcp_feedbacks: video_rtcp_feedback.clone(),
+                },
+                payload_type: 100,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_H264.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line:
+                        "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42001f"
+                            .to_owned(),
+                    rtcp_feedbacks: video_rtcp_feedback.clone(),
+                },
+                payload_type: 102,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_H264.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line:
+                        "level-asymmetry-allowed=1;packetization-mode=0;profile-level-id=42001f"
+                            .to_owned(),
+                    rtcp_feedbacks: video_rtcp_feedback.clone(),
+                },
+                payload_type: 127,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_H264.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line:
+                        "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f"
+                            .to_owned(),
+                    rtcp_feedbacks: video_rtcp_feedback.clone(),
+                },
+                payload_type: 125,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_H264.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line:
+                        "level-asymmetry-allowed=1;packetization-mode=0;profile-level-id=42e01f"
+                            .to_owned(),
+                    rtcp_feedbacks: video_rtcp_feedback.clone(),
+                },
+                payload_type: 108,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_H264.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line:
+                        "level-asymmetry-allowed=1;packetization-mode=0;profile-level-id=42001f"
+                            .to_owned(),
+                    rtcp_feedbacks: video_rtcp_feedback.clone(),
+                },
+                payload_type: 127,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_H264.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line:
+                        "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=640032"
+                            .to_owned(),
+                    rtcp_feedbacks: video_rtcp_feedback.clone(),
+                },
+                payload_type: 123,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_AV1.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "profile-id=0".to_owned(),
+                    rtcp_feedbacks: video_rtcp_feedback,
+                },
+                payload_type: 41,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_RTX.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "apt=96".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 97,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_RTX.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "apt=98".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 99,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_RTX.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "apt=100".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 101,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_RTX.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "apt=102".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 103,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_RTX.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "apt=127".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 104,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_RTX.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "apt=125".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 107,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_RTX.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "apt=108".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 109,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_RTX.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "apt=123".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 118,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_RTX.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "apt=41".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 42,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: "video/ulpfec".to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 116,
+                ..Default::default()
+            },
+        ] {
+            self.register_codec(codec, RTPCodecType::Video)?;
+        }
+
+        Ok(())
+    }
+
+    /// register_default_interceptors will register some useful interceptors.
+    /// If you want to customize which interceptors are loaded, you should copy the
+    /// code from this method and remove unwanted interceptors.
+    pub fn register_default_interceptors(&mut self) -> Result<()> {
+        self.configure_rtcp_reports();
+        self.configure_simulcast_extension_headers()?;
+        self.configure_audio_level_extension_header()?;
+        // retransmissions answering NACKs are sent without transport wide sequence number
+        self.configure_nack(self.nack_buffer_size);
+        self.configure_fir();
+        self.configure_twcc_sender_only()?;
+        self.configure_remb();
+
+        /*TODO:self.configure_twcc_receiver_only()?;*/
+
+        Ok(())
+    }
+
+    /// register_codec adds codec to the MediaConfig
+    /// These are the list of codecs supported by this PeerConnection.
+    /// register_codec is not safe for concurrent use.
+    pub fn register_codec(
+        &mut self,
+        codec: RTCRtpCodecParameters,
+        typ: RTPCodecType,
+    ) -> Result<()> {
+        /*TODO:codec.stats_id = format!(
+            "RTPCodec-{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        );*/
+        match typ {
+            RTPCodecType::Audio => {
+                MediaConfig::add_codec(&mut self.audio_codecs, codec);
+                Ok(())
+            }
+            RTPCodecType::Video => {
+                MediaConfig::add_codec(&mut self.video_codecs, codec);
+                Ok(())
+            }
+            _ => Err(Error::Other("ErrUnknownType".to_string())),
+        }
+    }
+
+    /// Adds a header extension to the MediaConfig
+    /// To determine the negotiated value use [`get_header_extension_id`] after signaling is complete.
+    ///
+    /// The `allowed_direction` controls for which transceiver directions the extension matches. If
+    /// set to `None` it matches all directions. The `SendRecv` direction would match all transceiver
+    /// directions apart from `Inactive`. Inactive only matches inactive.
+    pub fn register_header_extension(
+        &mut self,
+        extension: RTCRtpHeaderExtensionCapability,
+        typ: RTPCodecType,
+        allowed_direction: Option<RTCRtpTransceiverDirection>,
+    ) -> Result<()> {
+        let ext = {
+            match self
+                .header_extensions
+                .iter_mut()
+                .find(|ext| ext.uri == extension.uri)
+            {
+                Some(ext) => ext,
+                None => {
+                    // We have registered too many extensions
+                    if self.header_extensions.len() > VALID_EXT_IDS.end as usize {
+                        return Err(Error::Other(
+                            "ErrRegisterHeaderExtensionNoFreeID".to_string(),
+                        ));
+                    }
+                    self.header_extensions.push(RTCRtpHeaderExtension {
+                        allowed_direction,
+                        ..Default::default()
+                    });
+
+                    // Unwrap is fine because we just pushed
+                    self.header_extensions.last_mut().unwrap()
+                }
+            }
+        };
+
+        if typ == RTPCodecType::Audio {
+            ext.is_audio = true;
+        } else if typ == RTPCodecType::Video {
+            ext.is_video = true;
+        }
+
+        ext.uri = extension.uri;
+
+        if ext.allowed_direction != allowed_direction {
+            return Err(Error::Other(
+                "ErrRegisterHeaderExtensionInvalidDirection".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// register_rtcp_feedback adds feedback mechanism to already registered codecs.
+    pub fn register_rtcp_feedback(&mut self, rtcp_feedback: RTCPFeedback, typ: RTPCodecType) {
+        match typ {
+            RTPCodecType::Video => {
+                for v in &mut self.video_codecs {
+                    v.capability.rtcp_feedbacks.push(rtcp_feedback.clone());
+                }
+            }
+            RTPCodecType::Audio => {
+                for a in &mut self.audio_codecs {
+                    a.capability.rtcp_feedbacks.push(rtcp_feedback.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// add_codec will append codec if it not exists
+    fn add_codec(codecs: &mut Vec<RTCRtpCodecParameters>, codec: RTCRtpCodecParameters) {
+        for c in codecs.iter() {
+            if c.capability.mime_type == codec.capability.mime_type
+                && c.payload_type == codec.payload_type
+            {
+                return;
+            }
+        }
+        codecs.push(codec);
+    }
+
+    /// get_header_extension_id returns the negotiated ID for a header extension.
+    /// If the Header Extension isn't enabled ok will be false
+    pub(crate) fn get_header_extension_id(
+        &self,
+        extension: RTCRtpHeaderExtensionCapability,
+    ) -> (isize, bool, bool) {
+        if self.negotiated_header_extensions.is_empty() {
+            return (0, false, false);
+        }
+
+        for (id, h) in &self.negotiated_header_extensions {
+            if extension.uri == h.uri {
+                return (*id, h.is_audio, h.is_video);
+            }
+        }
+
+        (0, false, false)
+    }
+
+    /// clone_to copies any user modifiable state of the MediaConfig
+    /// all internal state is reset
+    pub(crate) fn clone_to(&self) -> Self {
+        MediaConfig {
+            video_codecs: self.video_codecs.clone(),
+            audio_codecs: self.audio_codecs.clone(),
+            header_extensions: self.header_extensions.clone(),
+            ..MediaConfig::with_nack_buffer_size(self.nack_buffer_size)
+        }
+    }
+
+    pub(crate) fn get_codec_by_payload(
+        &self,
+        payload_type: PayloadType,
+    ) -> Result<(RTCRtpCodecParameters, RTPCodecType)> {
+        {
+            for codec in &self.negotiated_video_codecs {
+                if codec.payload_type == payload_type {
+                    return Ok((codec.clone(), RTPCodecType::Video));
+                }
+            }
+        }
+        {
+            for codec in &self.negotiated_audio_codecs {
+                if codec.payload_type == payload_type {
+                    return Ok((codec.clone(), RTPCodecType::Audio));
+                }
+            }
+        }
+
+        Err(Error::Other("ErrCodecNotFound".to_string()))
+    }
+
+    /*pub(crate) fn collect_stats(&self, collector: &StatsCollector) {
+        let mut reports = HashMap::new();
+
+        for codec in &self.video_codecs {
+            reports.insert(codec.stats_id.clone(), Codec(CodecStats::from(codec)));
+        }
+
+        for codec in &self.audio_codecs {
+            reports.insert(codec.stats_id.clone(), Codec(CodecStats::from(codec)));
+        }
+
+        collector.merge(reports);
+    }*/
+
+    /// Look up a codec and enable if it exists
+    pub(crate) fn match_remote_codec(
+        &self,
+        remote_codec: &RTCRtpCodecParameters,
+        typ: RTPCodecType,
+        exact_matches: &[RTCRtpCodecParameters],
+        partial_matches: &[RTCRtpCodecParameters],
+    ) -> Result<CodecMatch> {
+        let codecs = if typ == RTPCodecType::Audio {
+            &self.audio_codecs
+        } else {
+            &self.video_codecs
+        };
+
+        let remote_fmtp = fmtp::parse(
+            &remote_codec.capability.mime_type,
+            remote_codec.capability.sdp_fmtp_line.as_str(),
+        );
+        if let Some(apt) = remote_fmtp.parameter("apt") {
+            let payload_type = apt.parse::<u8>()?;
+
+            let mut apt_match = CodecMatch::None;
+            for codec in exact_matches {
+                if codec.payload_type == payload_type {
+                    apt_match = CodecMatch::Exact;
+                    break;
+                }
+            }
+
+            if apt_match == CodecMatch::None {
+                for codec in partial_matches {
+                    if codec.payload_type == payload_type {
+                        apt_match = CodecMatch::Partial;
+                        break;
+                    }
+                }
+            }
+
+            if apt_match == CodecMatch::None {
+                return Ok(CodecMatch::None); // not an error, we just ignore this codec we don't support
+            }
+
+            // if apt's media codec is partial match, then apt codec must be partial match too
+            let (_, mut match_type) = codec_parameters_fuzzy_search(remote_codec, codecs);
+            if match_type == CodecMatch::Exact && apt_match == CodecMatch::Partial {
+                match_type = CodecMatch::Partial;
+            }
+            return Ok(match_type);
+        }
+
+        let (_, match_type) = codec_parameters_fuzzy_search(remote_codec, codecs);
+        Ok(match_type)
+    }
+
+    /// Look up a header extension and enable if it exists
+    pub(crate) fn update_header_extension(
+        &mut self,
+        id: isize,
+        uri: &str,
+        typ: RTPCodecType,
+    ) -> Result<()> {
+        for local_extension in &self.header_extensions {
+            if local_extension.uri != uri {
+                continue;
+            }
+
+            let negotiated_ext = self
+                .negotiated_header_extensions
+                .iter_mut()
+                .find(|(_, ext)| ext.uri == uri);
+
+            if let Some(n_ext) = negotiated_ext {
+                if *n_ext.0 == id {
+                    n_ext.1.is_video |= typ == RTPCodecType::Video;
+                    n_ext.1.is_audio |= typ == RTPCodecType::Audio;
+                } else {
+                    let nid = n_ext.0;
+                    log::warn!("Invalid ext id mapping in update_header_extension. {} was negotiated as {}, but was {} in call", uri, nid, id);
+                }
+            } else {
+                // We either only have a proposal or we have neither proposal nor a negotiated id
+                // Accept whatevers the peer suggests
+                if let Some(prev_ext) = self.negotiated_header_extensions.get(&id) {
+                    let prev_uri = &prev_ext.uri;
+                    log::warn!("Assigning {} to {} would override previous assignment to {}, no action taken", id, uri, prev_uri);
+                } else {
+                    let h = RTCRtpHeaderExtension {
+                        uri: uri.to_owned(),
+                        is_audio: local_extension.is_audio && typ == RTPCodecType::Audio,
+                        is_video: local_extension.is_video && typ == RTPCodecType::Video,
+                        allowed_direction: local_extension.allowed_direction,
+                    };
+                    self.negotiated_header_extensions.insert(id, h);
+                }
+            }
+
+            // Clear any proposals we had for this id
+            self.proposed_header_extensions.remove(&id);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn push_codecs(&mut self, codecs: Vec<RTCRtpCodecParameters>, typ: RTPCodecType) {
+        for codec in codecs {
+            if typ == RTPCodecType::Audio {
+                MediaConfig::add_codec(&mut self.negotiated_audio_codecs, codec);
+            } else if typ == RTPCodecType::Video {
+                MediaConfig::add_codec(&mut self.negotiated_video_codecs, codec);
+            }
+        }
+    }
+
+    /// Update the MediaConfig from a remote description
+    pub(crate) fn update_from_remote_description(
+        &mut self,
+        desc: &SessionDescription,
+    ) -> Result<()> {
+        for media in &desc.media_descriptions {
+            let typ = if !self.negotiated_audio && media.media_name.media.to_lowercase() == "audio"
+            {
+                self.negotiated_audio = true;
+                RTPCodecType::Audio
+            } else if !self.negotiated_video && media.media_name.media.to_lowercase() == "video" {
+                self.negotiated_video = true;
+                RTPCodecType::Video
+            } else {
+                continue;
+            };
+
+            let codecs = codecs_from_media_description(media)?;
+
+            let mut exact_matches = vec![]; //make([]RTPCodecParameters, 0, len(codecs))
+            let mut partial_matches = vec![]; //make([]RTPCodecParameters, 0, len(codecs))
+
+            for codec in codecs {
+                let match_type =
+                    self.match_remote_codec(&codec, typ, &exact_matches, &partial_matches)?;
+
+                if match_type == CodecMatch::Exact {
+                    exact_matches.push(codec);
+                } else if match_type == CodecMatch::Partial {
+                    partial_matches.push(codec);
+                }
+            }
+
+            // use exact matches when they exist, otherwise fall back to partial
+            if !exact_matches.is_empty() {
+                self.push_codecs(exact_matches, typ);
+            } else if !partial_matches.is_empty() {
+                self.push_codecs(partial_matches, typ);
+            } else {
+                // no match, not negotiated
+                continue;
+            }
+
+            let extensions = rtp_extensions_from_media_description(media)?;
+
+            for extension in extensions {
+                self.update_header_extension(extension.id, &extension.uri, typ)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn get_codecs_by_kind(&self, typ: RTPCodecType) -> &[RTCRtpCodecParameters] {
+        if typ == RTPCodecType::Video {
+            if self.negotiated_video {
+                &self.negotiated_video_codecs
+            } else {
+                &self.video_codecs
+            }
+        } else if typ == RTPCodecType::Audio {
+            if self.negotiated_audio {
+                &self.negotiated_audio_codecs
+            } else {
+                &self.audio_codecs
+            }
+        } else {
+            &[]
+        }
+    }
+
+    /// get_header_extensions_by_kind filters the header extensions offered by the remote
+    /// down to the registered ones, keeping the remote ids since they are shared by the bundle.
+    pub(crate) fn get_header_extensions_by_kind(
+        &self,
+        typ: RTPCodecType,
+        direction: RTCRtpTransceiverDirection,
+        offered: &[RTCRtpHeaderExtensionParameters],
+    ) -> Vec<RTCRtpHeaderExtensionParameters> {
+        offered
+            .iter()
+            .filter(|offered_extension| {
+                self.header_extensions.iter().any(|local_extension| {
+                    local_extension.uri == offered_extension.uri
+                        && local_extension.is_matching_direction(direction)
+                        && (local_extension.is_audio && typ == RTPCodecType::Audio
+                            || local_extension.is_video && typ == RTPCodecType::Video)
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn get_rtp_parameters_by_kind(
+        &self,
+        typ: RTPCodecType,
+        direction: RTCRtpTransceiverDirection,
+    ) -> RTCRtpParameters {
+        let mut header_extensions = vec![];
+
+        if self.negotiated_video && typ == RTPCodecType::Video
+            || self.negotiated_audio && typ == RTPCodecType::Audio
+        {
+            for (id, e) in &self.negotiated_header_extensions {
+                if e.is_matching_direction(direction)
+                    && (e.is_audio && typ == RTPCodecType::Audio
+                        || e.is_video && typ == RTPCodecType::Video)
+                {
+                    header_extensions.push(RTCRtpHeaderExtensionParameters {
+                        id: *id,
+                        uri: e.uri.clone(),
+                    });
+                }
+            }
+        } else {
+            for local_extension in &self.header_extensions {
+                let relevant = local_extension.is_matching_direction(direction)
+                    && (local_extension.is_audio && typ == RTPCodecType::Audio
+                        || local_extension.is_video && typ == RTPCodecType::Video);
+
+                if !relevant {
+                    continue;
+                }
+
+                if let Some((id, negotiated_extension)) = self
+                    .negotiated_header_extensions
+                    .iter()
+                    .find(|(_, e)| e.uri == local_extension.uri)
+                {
+                    // We have previously negotiated this extension, make sure to record it as
+                    // active for the current type
+                    //TODO: negotiated_extension.is_audio |= typ == RTPCodecType::Audio;
+                    // negotiated_extension.is_video |= typ == RTPCodecType::Video;
+
+                    header_extensions.push(RTCRtpHeaderExtensionParameters {
+                        id: *id,
+                        uri: negotiated_extension.uri.clone(),
+                    });
+
+                    continue;
+                }
+
+                if let Some((id, negotiated_extension)) = self
+                    .proposed_header_extensions
+                    .iter()
+                    .find(|(_, e)| e.uri == local_extension.uri)
+                {
+                    // We have previously proposed this extension, re-use it
+                    header_extensions.push(RTCRtpHeaderExtensionParameters {
+                        id: *id,
+                        uri: negotiated_extension.uri.clone(),
+                    });
+
+                    continue;
+                }
+
+                // Figure out which (unused id) to propose.
+                let id = VALID_EXT_IDS.clone().find(|id| {
+                    !self
+                        .negotiated_header_extensions
+                        .keys()
+                        .any(|nid| nid == id)
+                        && !self.proposed_header_extensions.keys().any(|pid| pid == id)
+                });
+
+                if let Some(id) = id {
+                    /*TODO: self.proposed_header_extensions.insert(
+                        id,
+                        RTCRtpHeaderExtension {
+                            uri: local_extension.uri.clone(),
+                            is_audio: local_extension.is_audio,
+                            is_video: local_extension.is_video,
+                            allowed_direction: local_extension.allowed_direction,
+                        },
+                    );*/
+
+                    header_extensions.push(RTCRtpHeaderExtensionParameters {
+                        id,
+                        uri: local_extension.uri.clone(),
+                    });
+                } else {
+                    log::warn!("No available RTP extension ID for {}", local_extension.uri);
+                }
+            }
+        }
+
+        RTCRtpParameters {
+            header_extensions,
+            codecs: self.get_codecs_by_kind(typ).to_vec(),
+        }
+    }
+
+    pub(crate) fn get_mut_rtp_parameters_by_kind(
+        &mut self,
+        typ: RTPCodecType,
+        direction: RTCRtpTransceiverDirection,
+    ) -> RTCRtpParameters {
+        let mut header_extensions = vec![];
+
+        if self.negotiated_video && typ == RTPCodecType::Video
+            || self.negotiated_audio && typ == RTPCodecType::Audio
+        {
+            for (id, e) in &self.negotiated_header_extensions {
+                if e.is_matching_direction(direction)
+                    && (e.is_audio && typ == RTPCodecType::Audio
+                        || e.is_video && typ == RTPCodecType::Video)
+                {
+                    header_extensions.push(RTCRtpHeaderExtensionParameters {
+                        id: *id,
+                        uri: e.uri.clone(),
+                    });
+                }
+            }
+        } else {
+            for local_extension in &self.header_extensions {
+                let relevant = local_extension.is_matching_direction(direction)
+                    && (local_extension.is_audio && typ == RTPCodecType::Audio
+                        || local_extension.is_video && typ == RTPCodecType::Video);
+
+                if !relevant {
+                    continue;
+                }
+
+                if let Some((id, negotiated_extension)) = self
+                    .negotiated_header_extensions
+                    .iter_mut()
+                    .find(|(_, e)| e.uri == local_extension.uri)
+                {
+                    // We have previously negotiated this extension, make sure to record it as
+                    // active for the current type
+                    negotiated_extension.is_audio |= typ == RTPCodecType::Audio;
+                    negotiated_extension.is_video |= typ == RTPCodecType::Video;
+
+                    header_extensions.push(RTCRtpHeaderExtensionParameters {
+                        id: *id,
+                        uri: negotiated_extension.uri.clone(),
+                    });
+
+                    continue;
+                }
+
+                if let Some((id, negotiated_extension)) = self
+                    .proposed_header_extensions
+                    .iter()
+                    .find(|(_, e)| e.uri == local_extension.uri)
+                {
+                    // We have previously proposed this extension, re-use it
+                    header_extensions.push(RTCRtpHeaderExtensionParameters {
+                        id: *id,
+                        uri: negotiated_extension.uri.clone(),
+                    });
+
+                    continue;
+                }
+
+                // Figure out which (unused id) to propose.
+                let id = VALID_EXT_IDS.clone().find(|id| {
+                    !self
+                        .negotiated_header_extensions
+                        .keys()
+                        .any(|nid| nid == id)
+                        && !self.proposed_header_extensions.keys().any(|pid| pid == id)
+                });
+
+                if let Some(id) = id {
+                    self.proposed_header_extensions.insert(
+                        id,
+                        RTCRtpHeaderExtension {
+                            uri: local_extension.uri.clone(),
+                            is_audio: local_extension.is_audio,
+                            is_video: local_extension.is_video,
+                            allowed_direction: local_extension.allowed_direction,
+                        },
+                    );
+
+                    header_extensions.push(RTCRtpHeaderExtensionParameters {
+                        id,
+                        uri: local_extension.uri.clone(),
+                    });
+                } else {
+                    log::warn!("No available RTP extension ID for {}", local_extension.uri);
+                }
+            }
+        }
+
+        RTCRtpParameters {
+            header_extensions,
+            codecs: self.get_codecs_by_kind(typ).to_vec(),
+        }
+    }
+
+    pub(crate) fn get_rtp_parameters_by_payload_type(
+        &self,
+        payload_type: PayloadType,
+    ) -> Result<RTCRtpParameters> {
+        let (codec, typ) = self.get_codec_by_payload(payload_type)?;
+
+        let mut header_extensions = vec![];
+        {
+            for (id, e) in &self.negotiated_header_extensions {
+                if e.is_audio && typ == RTPCodecType::Audio
+                    || e.is_video && typ == RTPCodecType::Video
+                {
+                    header_extensions.push(RTCRtpHeaderExtensionParameters {
+                        uri: e.uri.clone(),
+                        id: *id,
+                    });
+                }
+            }
+        }
+
+        Ok(RTCRtpParameters {
+            header_extensions,
+            codecs: vec![codec],
+        })
+    }
+
+    /// configure_rtcp_reports will setup everything necessary for generating Sender and Receiver Reports
+    pub fn configure_rtcp_reports(&mut self) {
+        let sender = Box::new(SenderReport::builder());
+        self.registry.add(sender);
+
+        let receiver = Box::new(ReceiverReport::builder());
+        self.registry.add(receiver);
+    }
+
+    /// configure_nack will setup everything necessary for handling generating/responding to nack messages.
+    /// The last buffer_size packets of every stream sent to an endpoint are kept to answer its NACKs.
+    pub fn configure_nack(&mut self, buffer_size: u16) {
+        self.register_rtcp_feedback(
+            RTCPFeedback {
+                typ: "nack".to_owned(),
+                parameter: "".to_owned(),
+            },
+            RTPCodecType::Video,
+        );
+        self.register_rtcp_feedback(
+            RTCPFeedback {
+                typ: "nack".to_owned(),
+                parameter: "pli".to_owned(),
+            },
+            RTPCodecType::Video,
+        );
+
+        //TODO: let generator = Box::new(Generator::builder());
+        let responder = Box::new(Responder::builder().with_size(buffer_size));
+        self.registry.add(responder);
+    }
+
+    /// configure_fir will setup Full Intra Requests, for the endpoints requesting keyframes with
+    /// FIR rather than PLI
+    pub fn configure_fir(&mut self) {
+        self.register_rtcp_feedback(
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_CCM.to_owned(),
+                parameter: "fir".to_owned(),
+            },
+            RTPCodecType::Video,
+        );
+    }
+
+    /// configure_twcc will setup everything necessary for adding
+    /// a TWCC header extension to outgoing RTP packets and generating TWCC reports.
+    pub fn configure_twcc(&mut self) -> Result<()> {
+        self.register_rtcp_feedback(
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_TRANSPORT_CC.to_owned(),
+                ..Default::default()
+            },
+            RTPCodecType::Video,
+        );
+        self.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: sdp::extmap::TRANSPORT_CC_URI.to_owned(),
+            },
+            RTPCodecType::Video,
+            None,
+        )?;
+
+        self.register_rtcp_feedback(
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_TRANSPORT_CC.to_owned(),
+                ..Default::default()
+            },
+            RTPCodecType::Audio,
+        );
+        self.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: sdp::extmap::TRANSPORT_CC_URI.to_owned(),
+            },
+            RTPCodecType::Audio,
+            None,
+        )?;
+
+        /*TODO:
+        let sender = Box::new(Sender::builder());
+        let receiver = Box::new(Receiver::builder());
+        registry.add(sender);
+        registry.add(receiver); */
+        Ok(())
+    }
+
+    /// configure_twcc_sender will setup everything necessary for adding
+    /// a TWCC header extension to outgoing RTP packets. This will allow the remote peer to generate TWCC reports,
+    /// from which the bandwidth towards it is estimated. The extension is only negotiated on forwarded
+    /// media sections, since no TWCC report is generated for the received ones.
+    pub fn configure_twcc_sender_only(&mut self) -> Result<()> {
+        self.register_rtcp_feedback(
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_TRANSPORT_CC.to_owned(),
+                ..Default::default()
+            },
+            RTPCodecType::Video,
+        );
+        self.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: sdp::extmap::TRANSPORT_CC_URI.to_owned(),
+            },
+            RTPCodecType::Video,
+            Some(RTCRtpTransceiverDirection::Sendonly),
+        )?;
+
+        self.register_rtcp_feedback(
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_TRANSPORT_CC.to_owned(),
+                ..Default::default()
+            },
+            RTPCodecType::Audio,
+        );
+        self.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: sdp::extmap::TRANSPORT_CC_URI.to_owned(),
+            },
+            RTPCodecType::Audio,
+            Some(RTCRtpTransceiverDirection::Sendonly),
+        )?;
+
+        let sender = Box::new(Sender::builder());
+        self.registry.add(sender);
+
+        Ok(())
+    }
+
+    /// configure_remb will setup receiving REMB, used to estimate the bandwidth towards endpoints
+    /// not supporting TWCC.
+    pub fn configure_remb(&mut self) {
+        self.register_rtcp_feedback(
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_GOOG_REMB.to_owned(),
+                ..Default::default()
+            },
+            RTPCodecType::Video,
+        );
+    }
+
+    /// configure_twcc_receiver will setup everything necessary for generating TWCC reports.
+    pub fn configure_twcc_receiver_only(&mut self) -> Result<()> {
+        self.register_rtcp_feedback(
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_TRANSPORT_CC.to_owned(),
+                ..Default::default()
+            },
+            RTPCodecType::Video,
+        );
+        self.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: sdp::extmap::TRANSPORT_CC_URI.to_owned(),
+            },
+            RTPCodecType::Video,
+            None,
+        )?;
+
+        self.register_rtcp_feedback(
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_TRANSPORT_CC.to_owned(),
+                ..Default::default()
+            },
+            RTPCodecType::Audio,
+        );
+        self.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: sdp::extmap::TRANSPORT_CC_URI.to_owned(),
+            },
+            RTPCodecType::Audio,
+            None,
+        )?;
+
+        /*TODO:
+        let receiver = Box::new(Receiver::builder());
+        registry.add(receiver);*/
+
+        Ok(())
+    }
+
+    /// configure_simulcast_extension_headers will setup everything necessary for receiving
+    /// rid based simulcast. These extensions are only negotiated on receiving transceivers,
+    /// since forwarded streams are sent with a single SSRC announced in the SDP.
+    pub fn configure_simulcast_extension_headers(&mut self) -> Result<()> {
+        for (uri, typ) in [
+            (sdp::extmap::SDES_MID_URI, RTPCodecType::Audio),
+            (sdp::extmap::SDES_MID_URI, RTPCodecType::Video),
+            (sdp::extmap::SDES_RTP_STREAM_ID_URI, RTPCodecType::Video),
+            (SDES_REPAIR_RTP_STREAM_ID_URI, RTPCodecType::Video),
+        ] {
+            self.register_header_extension(
+                RTCRtpHeaderExtensionCapability {
+                    uri: uri.to_owned(),
+                },
+                typ,
+                Some(RTCRtpTransceiverDirection::Recvonly),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// configure_audio_level_extension_header will setup the `ssrc-audio-level` header extension
+    /// on received audio, from which the dominant speaker of a session is detected, and on
+    /// forwarded audio for the receivers rendering audio levels
+    pub fn configure_audio_level_extension_header(&mut self) -> Result<()> {
+        self.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: sdp::extmap::AUDIO_LEVEL_URI.to_owned(),
+            },
+            RTPCodecType::Audio,
+            None,
+        )
+    }
+}
@@ -0,0 +1,145 @@
+use crate::description::{
+    rtp_codec::{RTCRtpParameters, RTPCodecType},
+    rtp_transceiver_direction::RTCRtpTransceiverDirection,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// SSRC represents a synchronization source
+/// A synchronization source is a randomly chosen
+/// value meant to be globally unique within a particular
+/// RTP session. Used to identify a single stream of media.
+/// <https://tools.ietf.org/html/rfc3550#section-3>
+#[allow(clippy::upper_case_acronyms)]
+pub type SSRC = u32;
+
+/// PayloadType identifies the format of the RTP payload and determines
+/// its interpretation by the application. Each codec in a RTP Session
+/// will have a different PayloadType
+/// <https://tools.ietf.org/html/rfc3550#section-3>
+pub type PayloadType = u8;
+
+/// TYPE_RTCP_FBT_RANSPORT_CC ..
+pub const TYPE_RTCP_FB_TRANSPORT_CC: &str = "transport-cc";
+
+/// TYPE_RTCP_FB_GOOG_REMB ..
+pub const TYPE_RTCP_FB_GOOG_REMB: &str = "goog-remb";
+
+/// TYPE_RTCP_FB_ACK ..
+pub const TYPE_RTCP_FB_ACK: &str = "ack";
+
+/// TYPE_RTCP_FB_CCM ..
+pub const TYPE_RTCP_FB_CCM: &str = "ccm";
+
+/// TYPE_RTCP_FB_NACK ..
+pub const TYPE_RTCP_FB_NACK: &str = "nack";
+
+/// rtcpfeedback signals the connection to use additional RTCP packet types.
+/// <https://draft.ortc.org/#dom-rtcrtcpfeedback>
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RTCPFeedback {
+    /// Type is the type of feedback.
+    /// see: <https://draft.ortc.org/#dom-rtcrtcpfeedback>
+    /// valid: ack, ccm, nack, goog-remb, transport-cc
+    pub typ: String,
+
+    /// The parameter value depends on the type.
+    /// For example, type="nack" parameter="pli" will send Picture Loss Indicator packets.
+    pub parameter: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct MediaStreamId {
+    pub(crate) stream_id: String,
+    pub(crate) track_id: String,
+}
+
+/// SSRC_GROUP_FID groups the SSRC of a stream with the SSRC of its retransmissions
+pub(crate) const SSRC_GROUP_FID: &str = "FID";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SsrcGroup {
+    pub(crate) name: String,
+    pub(crate) ssrcs: Vec<SSRC>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RTCRtpSender {
+    pub(crate) cname: String,
+    pub(crate) msid: MediaStreamId,
+    pub(crate) ssrcs: Vec<SSRC>,
+    pub(crate) ssrc_groups: Vec<SsrcGroup>,
+}
+
+/// RTPTransceiver represents a combination of an RTPSender and an RTPReceiver that share a common mid.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RTCRtpTransceiver {
+    pub(crate) mid: String,
+
+    pub(crate) sender: Option<RTCRtpSender>,
+
+    pub(crate) direction: RTCRtpTransceiverDirection,
+    pub(crate) current_direction: RTCRtpTransceiverDirection,
+
+    pub(crate) rtp_params: RTCRtpParameters,
+
+    pub(crate) kind: RTPCodecType,
+
+    /// rids of the simulcast layers received on this transceiver, empty without simulcast
+    pub(crate) rids: Vec<String>,
+}
+
+impl RTCRtpTransceiver {
+    /// current_direction returns the RTPTransceiver's current direction as negotiated.
+    pub(crate) fn current_direction(&self) -> RTCRtpTransceiverDirection {
+        self.current_direction
+    }
+
+    pub(crate) fn set_current_direction(&mut self, d: RTCRtpTransceiverDirection) {
+        self.current_direction = d;
+    }
+
+    /// header_extension_id returns the id negotiated for the header extension uri
+    pub(crate) fn header_extension_id(&self, uri: &str) -> Option<u8> {
+        self.rtp_params
+            .header_extensions
+            .iter()
+            .find(|extension| extension.uri == uri)
+            .map(|extension| extension.id as u8)
+    }
+
+    pub(crate) fn is_simulcast(&self) -> bool {
+        !self.rids.is_empty()
+    }
+
+    /// align_header_extension_ids rewrites the ids of a transceiver forwarded to another endpoint,
+    /// which come from the publisher's bundle, so that they are consistent with the ids already
+    /// used in the bundle of the receiving endpoint.
+    pub(crate) fn align_header_extension_ids<'a>(
+        &mut self,
+        bundle: impl Iterator<Item = &'a RTCRtpTransceiver>,
+    ) {
+        let mut used: HashMap<isize, String> = HashMap::new();
+        for transceiver in bundle {
+            for extension in &transceiver.rtp_params.header_extensions {
+                used.entry(extension.id)
+                    .or_insert_with(|| extension.uri.clone());
+            }
+        }
+
+        let mut header_extensions = vec![];
+        for mut extension in self.rtp_params.header_extensions.drain(..) {
+            if let Some((&id, _)) = used.iter().find(|(_, uri)| **uri == extension.uri) {
+                extension.id = id;
+            } else if used.contains_key(&extension.id) {
+                match (1..15).find(|id| !used.contains_key(id)) {
+                    Some(id) => extension.id = id,
+                    None => continue,
+                }
+            }
+            used.insert(extension.id, extension.uri.clone());
+            header_extensions.push(extension);
+        }
+        self.rtp_params.header_extensions = header_extensions;
+    }
+}
@@ -0,0 +1,540 @@
+use crate::description::rtp_codec::{RTCRtpCodecCapability, RTPCodecType};
+use crate::description::rtp_transceiver::RTCRtpTransceiver;
+use crate::description::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+use crate::endpoint::IncomingStream;
+use crate::simulcast::{keyframe, LayerPreference, SimulcastForwarder, SimulcastTrack};
+use crate::types::{EndpointId, Mid, SessionId};
+use bytes::{Bytes, BytesMut};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use shared::error::{Error, Result};
+use shared::marshal::Marshal;
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// first payload type the codecs of a track are remapped to
+const FIRST_DYNAMIC_PAYLOAD_TYPE: u8 = 96;
+const SENDER_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// seconds from the NTP epoch, 1900, to the unix one
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// EgressRequest forwards the decrypted media of a session, or of one of its endpoints, as
+/// plain RTP to an external processor, each track on a port of its own
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct EgressRequest {
+    /// endpoint forwarded, every endpoint of the session when unset
+    #[serde(default)]
+    pub endpoint_id: Option<EndpointId>,
+    pub host: IpAddr,
+    /// even port of the first track, the next tracks taking the next even ports, and the RTCP
+    /// of a track being sent to the port after its own
+    pub port: u16,
+    #[serde(default = "default_true")]
+    pub audio: bool,
+    #[serde(default = "default_true")]
+    pub video: bool,
+    /// send RTCP sender reports, for receivers to synchronize the tracks
+    #[serde(default)]
+    pub sender_reports: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// EgressSummary describes the tracks forwarded by an egress, and the SDP file receivers read
+/// them with
+#[derive(Debug, Clone, Serialize)]
+pub struct EgressSummary {
+    pub session_id: SessionId,
+    pub endpoint_id: Option<EndpointId>,
+    pub sdp_file: PathBuf,
+    /// unix time in milliseconds
+    pub started_at: u64,
+    /// unix time in milliseconds, 0 while running
+    pub stopped_at: u64,
+    pub tracks: Vec<EgressTrackSummary>,
+}
+
+/// EgressTrackSummary describes a track forwarded by an egress
+#[derive(Debug, Clone, Serialize)]
+pub struct EgressTrackSummary {
+    pub endpoint_id: EndpointId,
+    pub mid: Mid,
+    pub kind: String,
+    pub port: u16,
+    pub ssrc: u32,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// PlainRtpEgress forwards the packets of the published tracks of a session as plain RTP, with
+/// an SSRC of their own and payload types remapped from 96, and keeps an SDP file describing
+/// them up to date as tracks are published
+pub(crate) struct PlainRtpEgress {
+    session_id: SessionId,
+    request: EgressRequest,
+    sdp_file: PathBuf,
+    started_at: SystemTime,
+    next_sender_report: Instant,
+    tracks: Vec<EgressTrack>,
+}
+
+impl PlainRtpEgress {
+    /// new creates the SDP file in the egress directory, without any track yet
+    pub(crate) fn new(
+        session_id: SessionId,
+        egress_dir: &Path,
+        request: EgressRequest,
+        now: Instant,
+    ) -> Result<Self> {
+        if request.port == 0 || request.port % 2 != 0 {
+            return Err(Error::Other(format!(
+                "egress port {} is not an even port",
+                request.port
+            )));
+        }
+        if !request.audio && !request.video {
+            return Err(Error::Other("nothing to egress".to_string()));
+        }
+
+        let started_at = SystemTime::now();
+        let started_at_millis = unix_time_millis(started_at);
+        let file_name = match request.endpoint_id {
+            Some(endpoint_id) => {
+                format!("{}-{}-{}.sdp", session_id, endpoint_id, started_at_millis)
+            }
+            None => format!("{}-{}.sdp", session_id, started_at_millis),
+        };
+        std::fs::create_dir_all(egress_dir)?;
+        let egress = Self {
+            session_id,
+            request,
+            sdp_file: egress_dir.join(file_name),
+            started_at,
+            next_sender_report: now + SENDER_REPORT_INTERVAL,
+            tracks: vec![],
+        };
+        egress.write_sdp()?;
+        debug!(
+            "{}: start egress to {} described by {:?}",
+            session_id, egress.request.host, egress.sdp_file
+        );
+
+        Ok(egress)
+    }
+
+    pub(crate) fn get_sdp_file(&self) -> &Path {
+        &self.sdp_file
+    }
+
+    /// add_track forwards a track published by an endpoint, if the request covers it, on the
+    /// next free ports, and returns whether it was added
+    pub(crate) fn add_track(
+        &mut self,
+        endpoint_id: EndpointId,
+        transceiver: &RTCRtpTransceiver,
+    ) -> Result<bool> {
+        if !self.is_forwarded(endpoint_id, transceiver)
+            || self
+                .tracks
+                .iter()
+                .any(|track| track.endpoint_id == endpoint_id && track.mid == transceiver.mid)
+        {
+            return Ok(false);
+        }
+        // the RTCP port of the track has to fit too
+        let port = u16::try_from(self.request.port as usize + 2 * self.tracks.len())
+            .ok()
+            .filter(|&port| port < u16::MAX)
+            .ok_or(Error::Other(format!(
+                "no port left to egress mid {} of endpoint {}",
+                transceiver.mid, endpoint_id
+            )))?;
+
+        let track = EgressTrack::new(endpoint_id, transceiver, port);
+        debug!(
+            "{}: egress mid {} of endpoint {} to port {}",
+            self.session_id, track.mid, endpoint_id, port
+        );
+        self.tracks.push(track);
+        self.write_sdp()?;
+        Ok(true)
+    }
+
+    /// on_rtp returns the packet of an incoming stream to send to the external processor, if
+    /// forwarded, and whether a keyframe has to be requested for it
+    pub(crate) fn on_rtp(
+        &mut self,
+        now: Instant,
+        endpoint_id: EndpointId,
+        transceiver: &RTCRtpTransceiver,
+        simulcast_track: Option<&SimulcastTrack>,
+        incoming_stream: &IncomingStream,
+        rtp_packet: &rtp::packet::Packet,
+    ) -> (Option<(SocketAddr, BytesMut)>, bool) {
+        if incoming_stream.is_repair {
+            return (None, false);
+        }
+        if !self
+            .tracks
+            .iter()
+            .any(|track| track.endpoint_id == endpoint_id && track.mid == incoming_stream.mid)
+        {
+            if let Err(err) = self.add_track(endpoint_id, transceiver) {
+                warn!("{}: can't egress: {}", self.session_id, err);
+            }
+        }
+        let host = self.request.host;
+        let Some(track) = self
+            .tracks
+            .iter_mut()
+            .find(|track| track.endpoint_id == endpoint_id && track.mid == incoming_stream.mid)
+        else {
+            return (None, false);
+        };
+
+        let (packet, is_keyframe_needed) =
+            track.forward(now, simulcast_track, incoming_stream, rtp_packet);
+        let Some(packet) = packet else {
+            return (None, is_keyframe_needed);
+        };
+        match packet.marshal() {
+            Ok(payload) => {
+                track.packets += 1;
+                track.bytes += packet.payload.len() as u64;
+                (
+                    Some((SocketAddr::new(host, track.port), payload)),
+                    is_keyframe_needed,
+                )
+            }
+            Err(err) => {
+                warn!(
+                    "{}: can't marshal egress packet of mid {}: {}",
+                    self.session_id, track.mid, err
+                );
+                (None, is_keyframe_needed)
+            }
+        }
+    }
+
+    /// poll_timeout returns when the next sender reports are due, if they are enabled
+    pub(crate) fn poll_timeout(&self) -> Option<Instant> {
+        if self.request.sender_reports && !self.tracks.is_empty() {
+            Some(self.next_sender_report)
+        } else {
+            None
+        }
+    }
+
+    /// poll_sender_reports returns the sender reports of the tracks which sent packets, once
+    /// they are due, each to the RTCP port of its track
+    pub(crate) fn poll_sender_reports(&mut self, now: Instant) -> Vec<(SocketAddr, BytesMut)> {
+        if !self.request.sender_reports || now < self.next_sender_report {
+            return vec![];
+        }
+        self.next_sender_report = now + SENDER_REPORT_INTERVAL;
+
+        let ntp_time = ntp_time(SystemTime::now());
+        let mut reports = vec![];
+        for track in self.tracks.iter() {
+            let Some(packets) = track.sender_report(now, ntp_time) else {
+                continue;
+            };
+            match rtcp::packet::marshal(&packets) {
+                Ok(payload) => {
+                    reports.push((SocketAddr::new(self.request.host, track.port + 1), payload))
+                }
+                Err(err) => warn!(
+                    "{}: can't marshal sender report of mid {}: {}",
+                    self.session_id, track.mid, err
+                ),
+            }
+        }
+        reports
+    }
+
+    /// stop leaves the SDP file in place, and returns the summary of the egress
+    pub(crate) fn stop(self) -> EgressSummary {
+        debug!("{}: stop egress to {}", self.session_id, self.request.host);
+        let mut summary = self.summary();
+        summary.stopped_at = unix_time_millis(SystemTime::now());
+        summary
+    }
+
+    pub(crate) fn summary(&self) -> EgressSummary {
+        EgressSummary {
+            session_id: self.session_id,
+            endpoint_id: self.request.endpoint_id,
+            sdp_file: self.sdp_file.clone(),
+            started_at: unix_time_millis(self.started_at),
+            stopped_at: 0,
+            tracks: self
+                .tracks
+                .iter()
+                .map(|track| EgressTrackSummary {
+                    endpoint_id: track.endpoint_id,
+                    mid: track.mid.clone(),
+                    kind: track.kind.to_string(),
+                    port: track.port,
+                    ssrc: track.ssrc,
+                    packets: track.packets,
+                    bytes: track.bytes,
+                })
+                .collect(),
+        }
+    }
+
+    fn is_forwarded(&self, endpoint_id: EndpointId, transceiver: &RTCRtpTransceiver) -> bool {
+        let is_kind_forwarded = match transceiver.kind {
+            RTPCodecType::Audio => self.request.audio,
+            RTPCodecType::Video => self.request.video,
+            _ => false,
+        };
+        // the server receives what endpoints publish
+        transceiver.direction == RTCRtpTransceiverDirection::Recvonly
+            && is_kind_forwarded
+            && self
+                .request
+                .endpoint_id
+                .map_or(true, |forwarded_endpoint_id| {
+                    forwarded_endpoint_id == endpoint_id
+                })
+    }
+
+    /// write_sdp describes every track as a media section of its own, the way ffmpeg or
+    /// gstreamer expect to receive plain RTP
+    fn write_sdp(&self) -> Result<()> {
+        let address_type = if self.request.host.is_ipv4() {
+            "IP4"
+        } else {
+            "IP6"
+        };
+        let mut sdp = String::new();
+        let _ = write!(
+            sdp,
+            "v=0\r\no=- {} {} IN {} {}\r\ns=session {}\r\nc=IN {} {}\r\nt=0 0\r\n",
+            self.session_id,
+            self.tracks.len(),
+            address_type,
+            self.request.host,
+            self.session_id,
+            address_type,
+            self.request.host
+        );
+        for track in self.tracks.iter() {
+            let payload_types: Vec<String> = track
+                .codecs
+                .iter()
+                .map(|codec| codec.payload_type.to_string())
+                .collect();
+            let _ = write!(
+                sdp,
+                "m={} {} RTP/AVP {}\r\n",
+                track.kind,
+                track.port,
+                payload_types.join(" ")
+            );
+            for codec in track.codecs.iter() {
+                let encoding_name = codec
+                    .capability
+                    .mime_type
+                    .split_once('/')
+                    .map_or(codec.capability.mime_type.as_str(), |(_, name)| name);
+                let _ = write!(
+                    sdp,
+                    "a=rtpmap:{} {}/{}",
+                    codec.payload_type, encoding_name, codec.capability.clock_rate
+                );
+                if codec.capability.channels > 1 {
+                    let _ = write!(sdp, "/{}", codec.capability.channels);
+                }
+                sdp.push_str("\r\n");
+                if !codec.capability.sdp_fmtp_line.is_empty() {
+                    let _ = write!(
+                        sdp,
+                        "a=fmtp:{} {}\r\n",
+                        codec.payload_type, codec.capability.sdp_fmtp_line
+                    );
+                }
+            }
+            let _ = write!(
+                sdp,
+                "a=rtcp:{}\r\na=ssrc:{} cname:{}\r\na=label:{}-{}\r\na=recvonly\r\n",
+                track.port + 1,
+                track.ssrc,
+                track.endpoint_id,
+                track.endpoint_id,
+                track.mid
+            );
+        }
+
+        std::fs::write(&self.sdp_file, sdp)?;
+        Ok(())
+    }
+}
+
+/// EgressCodec maps the payload type a publisher sends a codec with to the one it is forwarded
+/// with
+struct EgressCodec {
+    incoming_payload_type: u8,
+    payload_type: u8,
+    capability: RTCRtpCodecCapability,
+}
+
+/// EgressTrack forwards a single stream of a track, the highest active layer of a simulcast
+/// track, as retransmissions and redundancy aren't forwarded
+struct EgressTrack {
+    endpoint_id: EndpointId,
+    mid: Mid,
+    kind: RTPCodecType,
+    port: u16,
+    ssrc: u32,
+    codecs: Vec<EgressCodec>,
+    forwarder: Option<SimulcastForwarder>,
+    is_keyframe_requested: bool,
+    /// RTP timestamp and clock rate of the last packet forwarded, and when it was
+    last_forwarded: Option<(u32, u32, Instant)>,
+    packets: u64,
+    bytes: u64,
+}
+
+impl EgressTrack {
+    fn new(endpoint_id: EndpointId, transceiver: &RTCRtpTransceiver, port: u16) -> Self {
+        let codecs = transceiver
+            .rtp_params
+            .codecs
+            .iter()
+            .filter(|codec| {
+                let mime_type = codec.capability.mime_type.to_lowercase();
+                !["/rtx", "/red", "/ulpfec", "/flexfec-03"]
+                    .iter()
+                    .any(|suffix| mime_type.ends_with(suffix))
+            })
+            .zip(FIRST_DYNAMIC_PAYLOAD_TYPE..=127)
+            .map(|(codec, payload_type)| EgressCodec {
+                incoming_payload_type: codec.payload_type,
+                payload_type,
+                capability: codec.capability.clone(),
+            })
+            .collect();
+
+        Self {
+            endpoint_id,
+            mid: transceiver.mid.clone(),
+            kind: transceiver.kind,
+            port,
+            ssrc: rand::random::<u32>(),
+            codecs,
+            forwarder: transceiver.is_simulcast().then(SimulcastForwarder::default),
+            is_keyframe_requested: false,
+            last_forwarded: None,
+            packets: 0,
+            bytes: 0,
+        }
+    }
+
+    /// forward rewrites a packet of the track for the external processor, and tells whether a
+    /// keyframe has to be requested, as a receiver can only decode video from one
+    fn forward(
+        &mut self,
+        now: Instant,
+        simulcast_track: Option<&SimulcastTrack>,
+        incoming_stream: &IncomingStream,
+        rtp_packet: &rtp::packet::Packet,
+    ) -> (Option<rtp::packet::Packet>, bool) {
+        let Some(codec) = self
+            .codecs
+            .iter()
+            .find(|codec| codec.incoming_payload_type == rtp_packet.header.payload_type)
+        else {
+            return (None, false);
+        };
+        let (payload_type, clock_rate) = (codec.payload_type, codec.capability.clock_rate);
+
+        let mut is_keyframe_needed = false;
+        let packet = match self.forwarder.as_mut() {
+            Some(forwarder) => {
+                let Some(rid) = incoming_stream.rid.as_deref() else {
+                    return (None, false);
+                };
+                let target_rid = simulcast_track.and_then(|simulcast_track| {
+                    simulcast_track.select(&LayerPreference::default(), None, now)
+                });
+                forwarder.set_target_rid(target_rid.or(Some(rid)));
+                is_keyframe_needed =
+                    forwarder.is_switch_pending() && forwarder.target_rid() == Some(rid);
+                let is_keyframe =
+                    keyframe::is_keyframe(&codec.capability.mime_type, &rtp_packet.payload);
+                match forwarder.forward(rid, rtp_packet, is_keyframe, self.ssrc, clock_rate, now) {
+                    Some(packet) => packet,
+                    None => return (None, is_keyframe_needed),
+                }
+            }
+            None => {
+                if self.kind == RTPCodecType::Video && !self.is_keyframe_requested {
+                    self.is_keyframe_requested = true;
+                    is_keyframe_needed = true;
+                }
+                let mut packet = rtp_packet.clone();
+                packet.header.ssrc = self.ssrc;
+                packet
+            }
+        };
+
+        let mut packet = packet;
+        packet.header.payload_type = payload_type;
+        // header extensions are negotiated with the publisher only
+        packet.header.extension = false;
+        packet.header.extension_profile = 0;
+        packet.header.extensions.clear();
+        self.last_forwarded = Some((packet.header.timestamp, clock_rate, now));
+        (Some(packet), is_keyframe_needed)
+    }
+
+    /// sender_report maps the wallclock to the RTP timestamp of the track, extrapolated from its
+    /// last packet, with a CNAME for receivers to group the tracks of an endpoint
+    fn sender_report(
+        &self,
+        now: Instant,
+        ntp_time: u64,
+    ) -> Option<Vec<Box<dyn rtcp::packet::Packet>>> {
+        let (timestamp, clock_rate, forwarded_at) = self.last_forwarded?;
+        let elapsed_ticks =
+            (now.duration_since(forwarded_at).as_secs_f64() * clock_rate as f64) as u32;
+
+        Some(vec![
+            Box::new(rtcp::sender_report::SenderReport {
+                ssrc: self.ssrc,
+                ntp_time,
+                rtp_time: timestamp.wrapping_add(elapsed_ticks),
+                packet_count: self.packets as u32,
+                octet_count: self.bytes as u32,
+                ..Default::default()
+            }),
+            Box::new(rtcp::source_description::SourceDescription {
+                chunks: vec![rtcp::source_description::SourceDescriptionChunk {
+                    source: self.ssrc,
+                    items: vec![rtcp::source_description::SourceDescriptionItem {
+                        sdes_type: rtcp::source_description::SdesType::SdesCname,
+                        text: Bytes::from(self.endpoint_id.to_string()),
+                    }],
+                }],
+            }),
+        ])
+    }
+}
+
+fn ntp_time(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((since_epoch.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+fn unix_time_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
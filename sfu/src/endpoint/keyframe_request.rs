@@ -0,0 +1,183 @@
+use crate::description::rtp_transceiver::SSRC;
+use rtcp::payload_feedbacks::full_intra_request::{FirEntry, FullIntraRequest};
+use rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Default, Debug)]
+struct KeyframeRequestState {
+    last_sent: Option<Instant>,
+    is_pending: bool,
+    fir_sequence_number: u8,
+}
+
+/// KeyframeRequests aggregates the keyframe requests for the streams received from a publisher,
+/// so that at most one PLI or FIR is sent per stream and interval. A request made too early is
+/// kept pending until the interval elapses, and merged with the ones made meanwhile.
+#[derive(Default, Debug)]
+pub(crate) struct KeyframeRequests {
+    streams: HashMap<SSRC, KeyframeRequestState>,
+}
+
+impl KeyframeRequests {
+    /// request returns whether a keyframe request for the stream is to be sent now, otherwise
+    /// it is kept pending
+    pub(crate) fn request(&mut self, ssrc: SSRC, now: Instant, interval: Duration) -> bool {
+        let state = self.streams.entry(ssrc).or_default();
+        if state
+            .last_sent
+            .is_some_and(|last_sent| now.duration_since(last_sent) < interval)
+        {
+            state.is_pending = true;
+            false
+        } else {
+            state.last_sent = Some(now);
+            state.is_pending = false;
+            true
+        }
+    }
+
+    /// defer keeps a keyframe request for the stream pending, to be sent once the interval
+    /// since the last one elapses
+    pub(crate) fn defer(&mut self, ssrc: SSRC) {
+        self.streams.entry(ssrc).or_default().is_pending = true;
+    }
+
+    /// poll_pending returns the streams whose pending request is due, and marks it sent
+    pub(crate) fn poll_pending(&mut self, now: Instant, interval: Duration) -> Vec<SSRC> {
+        let mut ssrcs = vec![];
+        for (&ssrc, state) in self.streams.iter_mut() {
+            if state.is_pending
+                && state
+                    .last_sent
+                    .map_or(true, |last_sent| now.duration_since(last_sent) >= interval)
+            {
+                state.last_sent = Some(now);
+                state.is_pending = false;
+                ssrcs.push(ssrc);
+            }
+        }
+        ssrcs
+    }
+
+    /// next_pending returns when the earliest pending request is due
+    pub(crate) fn next_pending(&self, interval: Duration) -> Option<Instant> {
+        self.streams
+            .values()
+            .filter(|state| state.is_pending)
+            .filter_map(|state| state.last_sent.map(|last_sent| last_sent + interval))
+            .min()
+    }
+
+    /// packet builds the keyframe request for the stream, a FIR when the publisher doesn't
+    /// support PLI
+    pub(crate) fn packet(&mut self, ssrc: SSRC, use_fir: bool) -> Box<dyn rtcp::packet::Packet> {
+        if use_fir {
+            let state = self.streams.entry(ssrc).or_default();
+            state.fir_sequence_number = state.fir_sequence_number.wrapping_add(1);
+            Box::new(FullIntraRequest {
+                sender_ssrc: 0,
+                // the media source is only identified by the FIR entries, RFC 5104
+                media_ssrc: 0,
+                fir: vec![FirEntry {
+                    ssrc,
+                    sequence_number: state.fir_sequence_number,
+                }],
+            })
+        } else {
+            Box::new(PictureLossIndication {
+                sender_ssrc: 0,
+                media_ssrc: ssrc,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(500);
+
+    fn fir_sequence_number(keyframe_requests: &mut KeyframeRequests, ssrc: SSRC) -> u8 {
+        let packet = keyframe_requests.packet(ssrc, true);
+        let fir = packet
+            .as_any()
+            .downcast_ref::<FullIntraRequest>()
+            .expect("expected a FIR");
+        assert_eq!(fir.fir.len(), 1);
+        assert_eq!(fir.fir[0].ssrc, ssrc);
+        fir.fir[0].sequence_number
+    }
+
+    #[test]
+    fn sends_a_request_per_stream_and_interval() {
+        let mut keyframe_requests = KeyframeRequests::default();
+        let now = Instant::now();
+        assert!(keyframe_requests.request(1111, now, INTERVAL));
+        assert!(!keyframe_requests.request(1111, now + INTERVAL / 2, INTERVAL));
+        // every stream is throttled on its own
+        assert!(keyframe_requests.request(2222, now + INTERVAL / 2, INTERVAL));
+        assert!(keyframe_requests.request(1111, now + INTERVAL, INTERVAL));
+    }
+
+    #[test]
+    fn sends_the_requests_made_too_early_once_the_interval_elapses() {
+        let mut keyframe_requests = KeyframeRequests::default();
+        let now = Instant::now();
+        assert!(keyframe_requests.request(1111, now, INTERVAL));
+        assert_eq!(keyframe_requests.next_pending(INTERVAL), None);
+
+        // the requests made meanwhile are merged into a single one
+        assert!(!keyframe_requests.request(1111, now + INTERVAL / 4, INTERVAL));
+        assert!(!keyframe_requests.request(1111, now + INTERVAL / 2, INTERVAL));
+        assert_eq!(
+            keyframe_requests.next_pending(INTERVAL),
+            Some(now + INTERVAL)
+        );
+        assert!(keyframe_requests
+            .poll_pending(now + INTERVAL / 2, INTERVAL)
+            .is_empty());
+        assert_eq!(
+            keyframe_requests.poll_pending(now + INTERVAL, INTERVAL),
+            vec![1111]
+        );
+        assert_eq!(keyframe_requests.next_pending(INTERVAL), None);
+        assert!(keyframe_requests
+            .poll_pending(now + INTERVAL * 2, INTERVAL)
+            .is_empty());
+        // the pending request sent restarts the interval
+        assert!(!keyframe_requests.request(1111, now + INTERVAL * 3 / 2, INTERVAL));
+    }
+
+    #[test]
+    fn sends_a_deferred_request_of_a_new_stream_right_away() {
+        let mut keyframe_requests = KeyframeRequests::default();
+        let now = Instant::now();
+        keyframe_requests.defer(1111);
+        assert_eq!(keyframe_requests.poll_pending(now, INTERVAL), vec![1111]);
+        assert!(!keyframe_requests.request(1111, now, INTERVAL));
+    }
+
+    #[test]
+    fn increments_the_fir_sequence_number_of_each_stream() {
+        let mut keyframe_requests = KeyframeRequests::default();
+        assert_eq!(fir_sequence_number(&mut keyframe_requests, 1111), 1);
+        assert_eq!(fir_sequence_number(&mut keyframe_requests, 1111), 2);
+        assert_eq!(fir_sequence_number(&mut keyframe_requests, 2222), 1);
+
+        // a PLI leaves the sequence number alone
+        let packet = keyframe_requests.packet(1111, false);
+        let pli = packet
+            .as_any()
+            .downcast_ref::<PictureLossIndication>()
+            .expect("expected a PLI");
+        assert_eq!(pli.media_ssrc, 1111);
+        assert_eq!(fir_sequence_number(&mut keyframe_requests, 1111), 3);
+
+        for _ in 3..255 {
+            fir_sequence_number(&mut keyframe_requests, 1111);
+        }
+        assert_eq!(fir_sequence_number(&mut keyframe_requests, 1111), 0);
+    }
+}
@@ -0,0 +1,490 @@
+pub(crate) mod candidate;
+pub(crate) mod keyframe_request;
+pub(crate) mod transport;
+
+use crate::description::{
+    config::SDES_REPAIR_RTP_STREAM_ID_URI,
+    rtp_codec::RTPCodecType,
+    rtp_transceiver::{
+        RTCRtpTransceiver, SSRC, SSRC_GROUP_FID, TYPE_RTCP_FB_CCM, TYPE_RTCP_FB_NACK,
+    },
+    rtp_transceiver_direction::RTCRtpTransceiverDirection,
+    RTCSessionDescription,
+};
+use crate::endpoint::keyframe_request::KeyframeRequests;
+use crate::endpoint::transport::Transport;
+use crate::interceptor::{BandwidthEstimate, Interceptor, StreamInfo};
+use crate::session::config::SessionConfig;
+use crate::simulcast::{SimulcastForwarder, SimulcastTrack};
+use crate::types::{EndpointId, FourTuple, Mid};
+use log::info;
+use sdp::extmap::{SDES_MID_URI, SDES_RTP_STREAM_ID_URI};
+use std::collections::{HashMap, HashSet};
+
+/// video forwarded to the endpoint is paused when its estimated bandwidth falls below this,
+/// leaving what remains to audio
+const VIDEO_PAUSE_BITRATE: u64 = 100_000;
+/// and resumed once it is back above this, higher to avoid flapping
+const VIDEO_RESUME_BITRATE: u64 = 150_000;
+/// bandwidth kept for each audio track forwarded to the endpoint
+const AUDIO_TRACK_BITRATE: u64 = 40_000;
+
+/// IncomingStream identifies which transceiver, and which simulcast layer, an SSRC received
+/// from the endpoint belongs to
+#[derive(Debug, Clone)]
+pub(crate) struct IncomingStream {
+    pub(crate) mid: Mid,
+    pub(crate) rid: Option<String>,
+    pub(crate) is_repair: bool,
+}
+
+pub(crate) struct Endpoint {
+    endpoint_id: EndpointId,
+    interceptor: Box<dyn Interceptor>,
+
+    is_renegotiation_needed: bool,
+    /// an offer sent to the endpoint awaits its answer
+    is_offer_pending: bool,
+    remote_description: Option<RTCSessionDescription>,
+    local_description: Option<RTCSessionDescription>,
+
+    transports: HashMap<FourTuple, Transport>,
+
+    mids: Vec<Mid>,
+    transceivers: HashMap<Mid, RTCRtpTransceiver>,
+
+    incoming_streams: HashMap<SSRC, IncomingStream>,
+    simulcast_tracks: HashMap<Mid, SimulcastTrack>,
+    simulcast_forwarders: HashMap<Mid, SimulcastForwarder>,
+    keyframe_requests: KeyframeRequests,
+
+    local_streams: HashSet<SSRC>,
+    bandwidth_estimate: Option<BandwidthEstimate>,
+    is_video_paused: bool,
+
+    pinned_endpoints: HashSet<EndpointId>,
+    paused_video_mids: HashSet<Mid>,
+
+    is_auto_subscribed: bool,
+    unsubscribed_mids: HashSet<Mid>,
+    /// mids of the tracks the endpoint stopped publishing, still negotiated in its SDP
+    stopped_mids: HashSet<Mid>,
+}
+
+impl Endpoint {
+    pub(crate) fn new(endpoint_id: EndpointId, interceptor: Box<dyn Interceptor>) -> Self {
+        Self {
+            endpoint_id,
+            interceptor,
+
+            is_renegotiation_needed: false,
+            is_offer_pending: false,
+            remote_description: None,
+            local_description: None,
+
+            transports: HashMap::new(),
+
+            mids: vec![],
+            transceivers: HashMap::new(),
+
+            incoming_streams: HashMap::new(),
+            simulcast_tracks: HashMap::new(),
+            simulcast_forwarders: HashMap::new(),
+            keyframe_requests: KeyframeRequests::default(),
+
+            local_streams: HashSet::new(),
+            bandwidth_estimate: None,
+            is_video_paused: false,
+
+            pinned_endpoints: HashSet::new(),
+            paused_video_mids: HashSet::new(),
+
+            is_auto_subscribed: true,
+            unsubscribed_mids: HashSet::new(),
+            stopped_mids: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn endpoint_id(&self) -> EndpointId {
+        self.endpoint_id
+    }
+
+    pub(crate) fn add_transport(&mut self, transport: Transport) {
+        self.transports.insert(*transport.four_tuple(), transport);
+    }
+
+    pub(crate) fn remove_transport(&mut self, four_tuple: &FourTuple) -> Option<Transport> {
+        self.transports.remove(four_tuple)
+    }
+
+    pub(crate) fn has_transport(&self, four_tuple: &FourTuple) -> bool {
+        self.transports.contains_key(four_tuple)
+    }
+
+    pub(crate) fn get_transports(&self) -> &HashMap<FourTuple, Transport> {
+        &self.transports
+    }
+
+    pub(crate) fn get_mut_transports(&mut self) -> &mut HashMap<FourTuple, Transport> {
+        &mut self.transports
+    }
+
+    /// is_datachannel_ready tells whether offers can be sent to the endpoint over its data channel
+    pub(crate) fn is_datachannel_ready(&self) -> bool {
+        self.transports
+            .values()
+            .any(|transport| transport.association_handle_and_stream_id().0.is_some())
+    }
+
+    pub(crate) fn get_mut_interceptor(&mut self) -> &mut Box<dyn Interceptor> {
+        &mut self.interceptor
+    }
+
+    pub(crate) fn get_mids(&self) -> &Vec<Mid> {
+        &self.mids
+    }
+
+    pub(crate) fn get_mut_mids(&mut self) -> &mut Vec<Mid> {
+        &mut self.mids
+    }
+
+    pub(crate) fn get_transceivers(&self) -> &HashMap<Mid, RTCRtpTransceiver> {
+        &self.transceivers
+    }
+
+    pub(crate) fn get_mut_transceivers(&mut self) -> &mut HashMap<Mid, RTCRtpTransceiver> {
+        &mut self.transceivers
+    }
+
+    pub(crate) fn get_mut_mids_and_transceivers(
+        &mut self,
+    ) -> (&mut Vec<Mid>, &mut HashMap<Mid, RTCRtpTransceiver>) {
+        (&mut self.mids, &mut self.transceivers)
+    }
+
+    pub(crate) fn remote_description(&self) -> Option<&RTCSessionDescription> {
+        self.remote_description.as_ref()
+    }
+
+    pub(crate) fn local_description(&self) -> Option<&RTCSessionDescription> {
+        self.local_description.as_ref()
+    }
+
+    pub(crate) fn set_remote_description(&mut self, description: RTCSessionDescription) {
+        self.remote_description = Some(description);
+    }
+
+    pub(crate) fn set_local_description(&mut self, description: RTCSessionDescription) {
+        self.local_description = Some(description);
+    }
+
+    pub(crate) fn is_renegotiation_needed(&self) -> bool {
+        self.is_renegotiation_needed
+    }
+
+    pub(crate) fn set_renegotiation_needed(&mut self, is_renegotiation_needed: bool) {
+        self.is_renegotiation_needed = is_renegotiation_needed;
+    }
+
+    pub(crate) fn is_offer_pending(&self) -> bool {
+        self.is_offer_pending
+    }
+
+    pub(crate) fn set_offer_pending(&mut self, is_offer_pending: bool) {
+        self.is_offer_pending = is_offer_pending;
+    }
+
+    /// header_extension_id returns the id the endpoint negotiated for the header extension uri
+    /// on the media it sends, which is the same for all those transceivers of the bundle
+    pub(crate) fn header_extension_id(&self, uri: &str) -> Option<u8> {
+        self.transceivers
+            .values()
+            .filter(|transceiver| transceiver.direction.has_recv())
+            .find_map(|transceiver| transceiver.header_extension_id(uri))
+    }
+
+    /// resolve_incoming_stream maps the SSRC of a received RTP packet to its transceiver, from
+    /// the SSRCs announced in the SDP or from the mid and rid header extensions
+    pub(crate) fn resolve_incoming_stream(
+        &mut self,
+        header: &rtp::header::Header,
+    ) -> Option<IncomingStream> {
+        if let Some(incoming_stream) = self.incoming_streams.get(&header.ssrc) {
+            return Some(incoming_stream.clone());
+        }
+
+        let read_extension = |uri: &str| -> Option<String> {
+            let payload = header.get_extension(self.header_extension_id(uri)?)?;
+            String::from_utf8(payload.to_vec()).ok()
+        };
+
+        let incoming_stream = if let Some(mid) = read_extension(SDES_MID_URI) {
+            let repaired_rid = read_extension(SDES_REPAIR_RTP_STREAM_ID_URI);
+            IncomingStream {
+                mid,
+                is_repair: repaired_rid.is_some(),
+                rid: repaired_rid.or_else(|| read_extension(SDES_RTP_STREAM_ID_URI)),
+            }
+        } else {
+            let (mid, _) = self.transceivers.iter().find(|(_, transceiver)| {
+                transceiver
+                    .sender
+                    .as_ref()
+                    .is_some_and(|sender| sender.ssrcs.contains(&header.ssrc))
+                    && !transceiver.is_simulcast()
+            })?;
+            IncomingStream {
+                mid: mid.clone(),
+                rid: None,
+                is_repair: false,
+            }
+        };
+
+        self.incoming_streams
+            .insert(header.ssrc, incoming_stream.clone());
+        Some(incoming_stream)
+    }
+
+    /// bind_incoming_stream maps the SSRC of a stream relayed by another worker to its
+    /// transceiver, its header extensions being stripped by that worker
+    pub(crate) fn bind_incoming_stream(&mut self, ssrc: SSRC, incoming_stream: &IncomingStream) {
+        self.incoming_streams
+            .entry(ssrc)
+            .or_insert_with(|| incoming_stream.clone());
+    }
+
+    pub(crate) fn get_simulcast_tracks(&self) -> &HashMap<Mid, SimulcastTrack> {
+        &self.simulcast_tracks
+    }
+
+    pub(crate) fn get_mut_simulcast_tracks(&mut self) -> &mut HashMap<Mid, SimulcastTrack> {
+        &mut self.simulcast_tracks
+    }
+
+    pub(crate) fn get_simulcast_forwarders(&self) -> &HashMap<Mid, SimulcastForwarder> {
+        &self.simulcast_forwarders
+    }
+
+    pub(crate) fn get_mut_simulcast_forwarders(&mut self) -> &mut HashMap<Mid, SimulcastForwarder> {
+        &mut self.simulcast_forwarders
+    }
+
+    pub(crate) fn get_keyframe_requests(&self) -> &KeyframeRequests {
+        &self.keyframe_requests
+    }
+
+    pub(crate) fn get_mut_keyframe_requests(&mut self) -> &mut KeyframeRequests {
+        &mut self.keyframe_requests
+    }
+
+    /// incoming_ssrc returns the SSRC received from the endpoint for a transceiver, or for one of
+    /// its simulcast layers, once known
+    pub(crate) fn incoming_ssrc(&self, mid: &str, rid: Option<&str>) -> Option<SSRC> {
+        if let Some((&ssrc, _)) = self.incoming_streams.iter().find(|(_, incoming_stream)| {
+            incoming_stream.mid == mid
+                && incoming_stream.rid.as_deref() == rid
+                && !incoming_stream.is_repair
+        }) {
+            return Some(ssrc);
+        }
+
+        let transceiver = self.transceivers.get(mid)?;
+        if rid.is_some() || transceiver.is_simulcast() {
+            return None;
+        }
+        transceiver.sender.as_ref()?.ssrcs.first().copied()
+    }
+
+    /// get_unattached_video_ssrcs returns the SSRCs of the video streams to forward to the
+    /// endpoint whose negotiation isn't complete yet
+    pub(crate) fn get_unattached_video_ssrcs(&self) -> Vec<SSRC> {
+        self.transceivers
+            .values()
+            .filter(|transceiver| {
+                transceiver.kind == RTPCodecType::Video
+                    && transceiver.direction.has_send()
+                    && !transceiver.current_direction().has_send()
+            })
+            .filter_map(|transceiver| transceiver.sender.as_ref()?.ssrcs.first().copied())
+            .collect()
+    }
+
+    /// is_fir_preferred tells whether keyframes of a stream received from the endpoint have to be
+    /// requested with FIR, because its codec is negotiated with ccm fir but without nack pli
+    pub(crate) fn is_fir_preferred(&self, ssrc: SSRC) -> bool {
+        let Some(transceiver) = self
+            .incoming_streams
+            .get(&ssrc)
+            .and_then(|incoming_stream| self.transceivers.get(&incoming_stream.mid))
+        else {
+            return false;
+        };
+
+        let has_feedback = |typ: &str, parameter: &str| {
+            transceiver.rtp_params.codecs.iter().any(|codec| {
+                codec
+                    .capability
+                    .rtcp_feedbacks
+                    .iter()
+                    .any(|feedback| feedback.typ == typ && feedback.parameter == parameter)
+            })
+        };
+        !has_feedback(TYPE_RTCP_FB_NACK, "pli") && has_feedback(TYPE_RTCP_FB_CCM, "fir")
+    }
+
+    /// bind_local_stream lets the interceptor know about a stream sent to the endpoint, once its
+    /// SSRC is announced in a transceiver of the endpoint
+    pub(crate) fn bind_local_stream(&mut self, ssrc: SSRC, session_config: &SessionConfig) {
+        if self.local_streams.contains(&ssrc) {
+            return;
+        }
+
+        let Some((transceiver, sender)) = self.transceivers.values().find_map(|transceiver| {
+            let sender = transceiver.sender.as_ref()?;
+            (transceiver.direction.has_send() && sender.ssrcs.contains(&ssrc))
+                .then_some((transceiver, sender))
+        }) else {
+            return;
+        };
+        let info = StreamInfo {
+            ssrc,
+            ssrc_rtx: sender
+                .ssrc_groups
+                .iter()
+                .find(|ssrc_group| {
+                    ssrc_group.name == SSRC_GROUP_FID && ssrc_group.ssrcs.first() == Some(&ssrc)
+                })
+                .and_then(|ssrc_group| ssrc_group.ssrcs.get(1).copied()),
+            mid: transceiver.mid.clone(),
+            kind: transceiver.kind,
+            rtp_header_extensions: transceiver.rtp_params.header_extensions.clone(),
+            // the codecs offered to the endpoint, rather than the publisher's ones
+            codecs: session_config.get_codecs_by_kind(transceiver.kind),
+        };
+
+        self.interceptor.bind_local_stream(&info);
+        self.local_streams.insert(ssrc);
+    }
+
+    pub(crate) fn bandwidth_estimate(&self) -> Option<BandwidthEstimate> {
+        self.bandwidth_estimate
+    }
+
+    /// set_bandwidth_estimate updates the bandwidth estimated towards the endpoint, pausing or
+    /// resuming the video forwarded to it
+    pub(crate) fn set_bandwidth_estimate(&mut self, bandwidth_estimate: Option<BandwidthEstimate>) {
+        self.bandwidth_estimate = bandwidth_estimate;
+
+        let Some(bandwidth_estimate) = bandwidth_estimate else {
+            return;
+        };
+        if !self.is_video_paused && bandwidth_estimate.bitrate < VIDEO_PAUSE_BITRATE {
+            info!(
+                "pause video to endpoint {} with estimated bitrate {}",
+                self.endpoint_id, bandwidth_estimate.bitrate
+            );
+            self.is_video_paused = true;
+        } else if self.is_video_paused && bandwidth_estimate.bitrate >= VIDEO_RESUME_BITRATE {
+            info!(
+                "resume video to endpoint {} with estimated bitrate {}",
+                self.endpoint_id, bandwidth_estimate.bitrate
+            );
+            self.is_video_paused = false;
+        }
+    }
+
+    /// estimated_bitrate is the bandwidth estimated towards the endpoint, in bits per second
+    pub(crate) fn estimated_bitrate(&self) -> Option<u64> {
+        self.bandwidth_estimate
+            .map(|bandwidth_estimate| bandwidth_estimate.bitrate)
+    }
+
+    /// estimated_video_bitrate is the share of the estimated bandwidth available to each video
+    /// track forwarded to the endpoint, once forwarded audio tracks are served
+    pub(crate) fn estimated_video_bitrate(&self) -> Option<u64> {
+        let estimated_bitrate = self.estimated_bitrate()?;
+
+        let (mut audio_tracks, mut video_tracks) = (0, 0);
+        for transceiver in self.transceivers.values() {
+            if transceiver.direction != RTCRtpTransceiverDirection::Sendonly {
+                continue;
+            }
+            match transceiver.kind {
+                RTPCodecType::Audio => audio_tracks += 1,
+                RTPCodecType::Video => video_tracks += 1,
+                _ => {}
+            }
+        }
+
+        Some(
+            estimated_bitrate.saturating_sub(audio_tracks * AUDIO_TRACK_BITRATE)
+                / video_tracks.max(1),
+        )
+    }
+
+    /// is_video_paused tells whether the estimated bandwidth is too low to forward any video
+    pub(crate) fn is_video_paused(&self) -> bool {
+        self.is_video_paused
+    }
+
+    pub(crate) fn get_pinned_endpoints(&self) -> &HashSet<EndpointId> {
+        &self.pinned_endpoints
+    }
+
+    pub(crate) fn set_pinned_endpoints(&mut self, pinned_endpoints: HashSet<EndpointId>) {
+        self.pinned_endpoints = pinned_endpoints;
+    }
+
+    /// is_auto_subscribed tells whether the endpoint receives every track published in the
+    /// session, until it subscribes or unsubscribes explicitly
+    pub(crate) fn is_auto_subscribed(&self) -> bool {
+        self.is_auto_subscribed
+    }
+
+    pub(crate) fn set_auto_subscribed(&mut self, is_auto_subscribed: bool) {
+        self.is_auto_subscribed = is_auto_subscribed;
+    }
+
+    /// is_unsubscribed tells whether the endpoint unsubscribed from the forwarded mid, which
+    /// stays inactive whatever the publisher renegotiates
+    pub(crate) fn is_unsubscribed(&self, mid: &Mid) -> bool {
+        self.unsubscribed_mids.contains(mid)
+    }
+
+    pub(crate) fn get_mut_unsubscribed_mids(&mut self) -> &mut HashSet<Mid> {
+        &mut self.unsubscribed_mids
+    }
+
+    /// is_stopped tells whether the endpoint stopped publishing the track of the mid
+    pub(crate) fn is_stopped(&self, mid: &Mid) -> bool {
+        self.stopped_mids.contains(mid)
+    }
+
+    /// set_stopped records whether the endpoint stopped publishing the track of the mid, and
+    /// returns whether it changed
+    pub(crate) fn set_stopped(&mut self, mid: &Mid, is_stopped: bool) -> bool {
+        if is_stopped {
+            self.stopped_mids.insert(mid.clone())
+        } else {
+            self.stopped_mids.remove(mid)
+        }
+    }
+
+    /// is_subscribed tells whether media of the forwarded mid is to be sent to the endpoint
+    pub(crate) fn is_subscribed(&self, mid: &Mid) -> bool {
+        self.transceivers
+            .get(mid)
+            .is_some_and(|transceiver| transceiver.direction.has_send())
+    }
+
+    /// set_video_forwarded records whether the video forwarded with the mid is paused by the
+    /// last N policy, and returns whether it resumes
+    pub(crate) fn set_video_forwarded(&mut self, mid: &Mid, is_forwarded: bool) -> bool {
+        if is_forwarded {
+            self.paused_video_mids.remove(mid)
+        } else {
+            self.paused_video_mids.insert(mid.clone());
+            false
+        }
+    }
+}
@@ -0,0 +1,2037 @@
+use crate::chat::{ChatEvent, ChatRequest};
+use crate::description::rtp_transceiver::SSRC;
+use crate::description::{
+    config::{MIME_TYPE_RTX, SDES_REPAIR_RTP_STREAM_ID_URI},
+    fmtp,
+    rtp_codec::RTPCodecType,
+    rtp_transceiver_direction::RTCRtpTransceiverDirection,
+    sdp_type::RTCSdpType,
+    RTCSessionDescription,
+};
+use crate::endpoint::{candidate::Candidate, Endpoint, IncomingStream};
+use crate::messages::{
+    ApplicationMessage, DTLSMessageEvent, DataChannelControlMessage, DataChannelEvent,
+    MessageEvent, RTPMessageEvent, STUNMessageEvent, SessionEvent, TaggedMessageEvent,
+};
+use crate::server::states::ServerStates;
+use crate::session::Session;
+use crate::simulcast::{keyframe, LayerPreference};
+use crate::speaker;
+use crate::types::{EndpointId, FourTuple, Mid, SessionId};
+use bytes::{Bytes, BytesMut};
+use log::{debug, info, trace, warn};
+use retty::channel::{Context, Handler};
+use retty::transport::{EcnCodepoint, TransportContext};
+use rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+use rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use rtcp::transport_feedbacks::transport_layer_nack::{
+    nack_pairs_from_sequence_numbers, TransportLayerNack,
+};
+use sdp::extmap::{AUDIO_LEVEL_URI, SDES_MID_URI, SDES_RTP_STREAM_ID_URI};
+use shared::error::{Error, Result};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::ops::{Add, Sub};
+use std::rc::Rc;
+use std::time::Duration;
+use std::time::Instant;
+use stun::attributes::{
+    ATTR_ICE_CONTROLLED, ATTR_ICE_CONTROLLING, ATTR_NETWORK_COST, ATTR_PRIORITY, ATTR_USERNAME,
+    ATTR_USE_CANDIDATE,
+};
+use stun::fingerprint::FINGERPRINT;
+use stun::integrity::MessageIntegrity;
+use stun::message::{Setter, TransactionId, BINDING_SUCCESS};
+use stun::textattrs::TextAttribute;
+use stun::xoraddr::XorMappedAddress;
+
+/// GatewayHandler implements Data/Media Selective Forward handling
+pub struct GatewayHandler {
+    server_states: Rc<RefCell<ServerStates>>,
+    transmits: VecDeque<TaggedMessageEvent>,
+    next_timeout: Instant,
+    idle_timeout: Duration,
+}
+
+impl GatewayHandler {
+    pub fn new(server_states: Rc<RefCell<ServerStates>>) -> Self {
+        let idle_timeout = server_states.borrow().server_config().idle_timeout;
+
+        GatewayHandler {
+            server_states,
+            transmits: VecDeque::new(),
+            next_timeout: Instant::now().add(idle_timeout),
+            idle_timeout,
+        }
+    }
+}
+
+impl Handler for GatewayHandler {
+    type Rin = TaggedMessageEvent;
+    type Rout = Self::Rin;
+    type Win = TaggedMessageEvent;
+    type Wout = Self::Win;
+
+    fn name(&self) -> &str {
+        "GatewayHandler"
+    }
+
+    fn transport_inactive(&mut self, _ctx: &Context<Self::Rin, Self::Rout, Self::Win, Self::Wout>) {
+        let server_states = self.server_states.borrow();
+        let sessions = server_states.get_sessions();
+        let mut endpoint_count = 0;
+        for session in sessions.values() {
+            endpoint_count += session.get_endpoints().len();
+        }
+        info!(
+            "Still Active Sessions {}, Endpoints {}/{}, Candidates {} on {}",
+            sessions.len(),
+            endpoint_count,
+            server_states.get_endpoints().len(),
+            server_states.get_candidates().len(),
+            server_states.local_addr()
+        );
+    }
+
+    fn handle_read(
+        &mut self,
+        ctx: &Context<Self::Rin, Self::Rout, Self::Win, Self::Wout>,
+        msg: Self::Rin,
+    ) {
+        let try_read = || -> Result<Vec<TaggedMessageEvent>> {
+            let mut server_states = self.server_states.borrow_mut();
+            match msg.message {
+                MessageEvent::Stun(STUNMessageEvent::Stun(message)) => {
+                    GatewayHandler::handle_stun_message(
+                        &mut server_states,
+                        msg.now,
+                        msg.transport,
+                        message,
+                    )
+                }
+                MessageEvent::Dtls(DTLSMessageEvent::DataChannel(message)) => {
+                    GatewayHandler::handle_dtls_message(
+                        &mut server_states,
+                        msg.now,
+                        msg.transport,
+                        message,
+                    )
+                }
+                MessageEvent::Rtp(RTPMessageEvent::Rtp(message)) => {
+                    GatewayHandler::handle_rtp_message(
+                        &mut server_states,
+                        msg.now,
+                        msg.transport,
+                        message,
+                    )
+                }
+                MessageEvent::Rtp(RTPMessageEvent::Rtcp(message)) => {
+                    GatewayHandler::handle_rtcp_message(
+                        &mut server_states,
+                        msg.now,
+                        msg.transport,
+                        message,
+                    )
+                }
+                _ => {
+                    warn!("drop unsupported message from {}", msg.transport.peer_addr);
+                    Ok(vec![])
+                }
+            }
+        };
+
+        match try_read() {
+            Ok(messages) => {
+                for message in messages {
+                    self.transmits.push_back(message);
+                }
+            }
+            Err(err) => {
+                warn!("try_read got error {}", err);
+                ctx.fire_exception(Box::new(err));
+            }
+        }
+    }
+
+    fn handle_timeout(
+        &mut self,
+        _ctx: &Context<Self::Rin, Self::Rout, Self::Win, Self::Wout>,
+        now: Instant,
+    ) {
+        // terminate timeout here, no more ctx.fire_handle_timeout(now);
+        let mut server_states = self.server_states.borrow_mut();
+        let keyframe_request_interval = server_states.server_config().keyframe_request_interval;
+        for session in server_states.get_mut_sessions().values_mut() {
+            session.poll_activity(now);
+            let endpoint_ids: Vec<EndpointId> = session.get_endpoints().keys().copied().collect();
+            for endpoint_id in endpoint_ids {
+                let is_relayed = session.is_relayed(&endpoint_id);
+                let Some(endpoint) = session.get_mut_endpoint(&endpoint_id) else {
+                    continue;
+                };
+                let ssrcs = endpoint
+                    .get_mut_keyframe_requests()
+                    .poll_pending(now, keyframe_request_interval);
+                if ssrcs.is_empty() {
+                    continue;
+                }
+                if is_relayed {
+                    if let Some(cascade) = session.get_mut_cascade() {
+                        cascade.request_keyframes(endpoint_id, ssrcs);
+                    }
+                } else {
+                    self.transmits
+                        .extend(GatewayHandler::create_keyframe_request_message_events(
+                            endpoint, now, ssrcs, None,
+                        ));
+                }
+            }
+        }
+
+        let local_addr = server_states.local_addr();
+        for session in server_states.get_mut_sessions().values_mut() {
+            for (peer_addr, payload) in session.poll_egress_sender_reports(now) {
+                self.transmits.push_back(TaggedMessageEvent {
+                    now,
+                    transport: TransportContext {
+                        local_addr,
+                        peer_addr,
+                        ecn: None,
+                    },
+                    message: MessageEvent::Rtp(RTPMessageEvent::Raw(payload)),
+                });
+            }
+        }
+
+        // injected media is forwarded like the media of any publisher, and the endpoints whose
+        // transceivers changed with an injection are offered them
+        let mut pending_offers = vec![];
+        for session in server_states.get_mut_sessions().values_mut() {
+            for (endpoint_id, mid, _, rtp_packet) in session.poll_injected_packets(now) {
+                let incoming_stream = IncomingStream {
+                    mid,
+                    rid: None,
+                    is_repair: false,
+                };
+                if let Some(cascade) = session.get_mut_cascade() {
+                    cascade.relay_rtp(endpoint_id, &incoming_stream, None, &rtp_packet);
+                }
+                match GatewayHandler::forward_rtp_message(
+                    session,
+                    now,
+                    local_addr,
+                    None,
+                    endpoint_id,
+                    Some(incoming_stream),
+                    None,
+                    rtp_packet,
+                ) {
+                    Ok(messages) => self.transmits.extend(messages),
+                    Err(err) => warn!(
+                        "can't forward packet injected as endpoint {}: {}",
+                        endpoint_id, err
+                    ),
+                }
+            }
+
+            for endpoint_id in session.take_pending_offers() {
+                let Some(endpoint) = session.get_endpoint(&endpoint_id) else {
+                    continue;
+                };
+                for (four_tuple, transport) in endpoint.get_transports().iter() {
+                    if let (Some(association_handle), Some(stream_id)) =
+                        transport.association_handle_and_stream_id()
+                    {
+                        pending_offers.push((
+                            TransportContext {
+                                local_addr: four_tuple.local_addr,
+                                peer_addr: four_tuple.peer_addr,
+                                ecn: None,
+                            },
+                            association_handle,
+                            stream_id,
+                        ));
+                    }
+                }
+            }
+        }
+        // media relayed by the other workers hosting a session is forwarded to the subscribers
+        // connected to this one
+        for session in server_states.get_mut_sessions().values_mut() {
+            let Some(cascade) = session.get_mut_cascade() else {
+                continue;
+            };
+            let relayed_packets = cascade.take_relayed_packets();
+            let relayed_rtcp = cascade.take_relayed_rtcp();
+            let relayed_chat = cascade.take_relayed_chat();
+            for (endpoint_id, incoming_stream, audio_level, rtp_packet) in relayed_packets {
+                match GatewayHandler::forward_rtp_message(
+                    session,
+                    now,
+                    local_addr,
+                    None,
+                    endpoint_id,
+                    Some(incoming_stream),
+                    audio_level,
+                    rtp_packet,
+                ) {
+                    Ok(messages) => self.transmits.extend(messages),
+                    Err(err) => warn!(
+                        "can't forward packet relayed for endpoint {}: {}",
+                        endpoint_id, err
+                    ),
+                }
+            }
+            for (endpoint_id, payload) in relayed_rtcp {
+                let rtcp_packets = match rtcp::packet::unmarshal(&mut payload.clone()) {
+                    Ok(rtcp_packets) => rtcp_packets,
+                    Err(err) => {
+                        warn!(
+                            "can't unmarshal rtcp relayed for endpoint {}: {}",
+                            endpoint_id, err
+                        );
+                        continue;
+                    }
+                };
+                let peers = GatewayHandler::get_subscriber_transport_contexts(
+                    session,
+                    &endpoint_id,
+                    None,
+                    false,
+                    None,
+                );
+                for transport in peers {
+                    self.transmits.push_back(TaggedMessageEvent {
+                        now,
+                        transport,
+                        message: MessageEvent::Rtp(RTPMessageEvent::Rtcp(rtcp_packets.clone())),
+                    });
+                }
+            }
+            for (endpoint_id, event) in relayed_chat {
+                match GatewayHandler::create_chat_message_events(
+                    session,
+                    now,
+                    endpoint_id,
+                    &event,
+                    None,
+                ) {
+                    Ok(messages) => self.transmits.extend(messages),
+                    Err(err) => warn!(
+                        "can't forward chat message relayed for endpoint {}: {}",
+                        endpoint_id, err
+                    ),
+                }
+            }
+        }
+
+        for (transport_context, association_handle, stream_id) in pending_offers {
+            match GatewayHandler::create_offer_message_event(
+                &mut server_states,
+                now,
+                transport_context,
+                association_handle,
+                stream_id,
+            ) {
+                Ok(message) => self.transmits.extend(message),
+                Err(err) => warn!(
+                    "can't create offer for {}: {}",
+                    transport_context.peer_addr, err
+                ),
+            }
+        }
+
+        if self.next_timeout <= now {
+            let mut four_tuples = vec![];
+            for session in server_states.get_mut_sessions().values_mut() {
+                for endpoint in session.get_mut_endpoints().values_mut() {
+                    for transport in endpoint.get_mut_transports().values_mut() {
+                        if transport.last_activity() <= now.sub(self.idle_timeout) {
+                            four_tuples.push(*transport.four_tuple());
+                        }
+                    }
+                }
+            }
+            for four_tuple in four_tuples {
+                server_states.remove_transport(four_tuple);
+            }
+
+            self.next_timeout = self.next_timeout.add(self.idle_timeout);
+        }
+    }
+
+    fn poll_timeout(
+        &mut self,
+        ctx: &Context<Self::Rin, Self::Rout, Self::Win, Self::Wout>,
+        eto: &mut Instant,
+    ) {
+        if self.next_timeout < *eto {
+            *eto = self.next_timeout;
+        }
+
+        let server_states = self.server_states.borrow();
+        let keyframe_request_interval = server_states.server_config().keyframe_request_interval;
+        for session in server_states.get_sessions().values() {
+            for endpoint in session.get_endpoints().values() {
+                if let Some(next_pending) = endpoint
+                    .get_keyframe_requests()
+                    .next_pending(keyframe_request_interval)
+                {
+                    if next_pending < *eto {
+                        *eto = next_pending;
+                    }
+                }
+            }
+            if let Some(next_frame) = session.poll_injection_timeout() {
+                if next_frame < *eto {
+                    *eto = next_frame;
+                }
+            }
+            if let Some(next_sender_report) = session.poll_egress_timeout() {
+                if next_sender_report < *eto {
+                    *eto = next_sender_report;
+                }
+            }
+        }
+        drop(server_states);
+
+        ctx.fire_poll_timeout(eto);
+    }
+
+    fn poll_write(
+        &mut self,
+        ctx: &Context<Self::Rin, Self::Rout, Self::Win, Self::Wout>,
+    ) -> Option<Self::Wout> {
+        if let Some(msg) = ctx.fire_poll_write() {
+            self.transmits.push_back(msg);
+        }
+        self.transmits.pop_front()
+    }
+}
+
+impl GatewayHandler {
+    fn handle_stun_message(
+        server_states: &mut ServerStates,
+        now: Instant,
+        transport_context: TransportContext,
+        mut request: stun::message::Message,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let candidate = match GatewayHandler::check_stun_message(server_states, &mut request)? {
+            Some(candidate) => candidate,
+            None => {
+                return GatewayHandler::create_server_reflective_address_message_event(
+                    now,
+                    transport_context,
+                    request.transaction_id,
+                );
+            }
+        };
+
+        GatewayHandler::add_endpoint(server_states, &request, &candidate, &transport_context)?;
+
+        let mut response = stun::message::Message::new();
+        response.build(&[
+            Box::new(BINDING_SUCCESS),
+            Box::new(request.transaction_id),
+            Box::new(XorMappedAddress {
+                ip: transport_context.peer_addr.ip(),
+                port: transport_context.peer_addr.port(),
+            }),
+        ])?;
+        let integrity = MessageIntegrity::new_short_term_integrity(
+            candidate.get_local_parameters().password.clone(),
+        );
+        integrity.add_to(&mut response)?;
+        FINGERPRINT.add_to(&mut response)?;
+
+        debug!(
+            "handle_stun_message response type {} with ip {} and port {} sent",
+            response.typ,
+            transport_context.peer_addr.ip(),
+            transport_context.peer_addr.port()
+        );
+
+        Ok(vec![TaggedMessageEvent {
+            now,
+            transport: transport_context,
+            message: MessageEvent::Stun(STUNMessageEvent::Stun(response)),
+        }])
+    }
+
+    fn handle_dtls_message(
+        server_states: &mut ServerStates,
+        now: Instant,
+        transport_context: TransportContext,
+        message: ApplicationMessage,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        match message.data_channel_event {
+            DataChannelEvent::Open => GatewayHandler::handle_datachannel_open(
+                server_states,
+                now,
+                transport_context,
+                message.association_handle,
+                message.stream_id,
+            ),
+            DataChannelEvent::Message(payload) => GatewayHandler::handle_datachannel_message(
+                server_states,
+                now,
+                transport_context,
+                message.association_handle,
+                message.stream_id,
+                payload,
+            ),
+            DataChannelEvent::Close => GatewayHandler::handle_datachannel_close(
+                server_states,
+                now,
+                transport_context,
+                message.association_handle,
+                message.stream_id,
+            ),
+        }
+    }
+
+    fn handle_datachannel_open(
+        server_states: &mut ServerStates,
+        now: Instant,
+        transport_context: TransportContext,
+        association_handle: usize,
+        stream_id: u16,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let four_tuple = (&transport_context).into();
+        let (session_id, endpoint_id) = server_states
+            .find_endpoint(&four_tuple)
+            .ok_or(Error::ErrClientTransportNotSet)?;
+
+        let session = server_states
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        let mut new_transceivers = vec![];
+        let is_auto_subscribed = session
+            .get_endpoint(&endpoint_id)
+            .is_some_and(|endpoint| endpoint.is_auto_subscribed());
+        let endpoints = session.get_endpoints();
+        for (&other_endpoint_id, other_endpoint) in endpoints.iter() {
+            if other_endpoint_id != endpoint_id && is_auto_subscribed {
+                let other_transceivers = other_endpoint.get_transceivers();
+                for (other_mid_value, other_transceiver) in other_transceivers.iter() {
+                    if other_transceiver.direction == RTCRtpTransceiverDirection::Recvonly
+                        && !other_endpoint.is_stopped(other_mid_value)
+                    {
+                        let mut transceiver = other_transceiver.clone();
+                        transceiver.mid = format!("{}-{}", other_endpoint_id, other_mid_value);
+                        transceiver.direction = RTCRtpTransceiverDirection::Sendonly;
+                        new_transceivers.push(transceiver);
+                    }
+                }
+            }
+        }
+
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+
+        let transports = endpoint.get_mut_transports();
+        let transport = transports.get_mut(&four_tuple).ok_or(Error::Other(format!(
+            "can't find transport for endpoint id {} with {:?}",
+            endpoint_id, four_tuple
+        )))?;
+        transport.set_association_handle_and_stream_id(association_handle, stream_id);
+        info!(
+            "{}/{}: data channel is ready for {:?}",
+            session_id,
+            endpoint_id,
+            transport.four_tuple()
+        );
+        // tracks subscribed to before the data channel opened are offered with the new ones
+        if !new_transceivers.is_empty() {
+            endpoint.set_renegotiation_needed(true);
+        }
+
+        let (mids, transceivers) = endpoint.get_mut_mids_and_transceivers();
+        for mut transceiver in new_transceivers {
+            transceiver.align_header_extension_ids(transceivers.values());
+            mids.push(transceiver.mid.clone());
+            transceivers.insert(transceiver.mid.clone(), transceiver);
+        }
+
+        if endpoint.is_renegotiation_needed() {
+            Ok(Vec::from_iter(GatewayHandler::create_offer_message_event(
+                server_states,
+                now,
+                transport_context,
+                association_handle,
+                stream_id,
+            )?))
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    fn handle_datachannel_close(
+        _server_states: &mut ServerStates,
+        _now: Instant,
+        _transport_context: TransportContext,
+        _association_handle: usize,
+        _stream_id: u16,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        //TODO: handle datachannel close event!
+        // clean up resources, like sctp_association, endpoint, etc.
+        Ok(vec![])
+    }
+
+    fn handle_datachannel_message(
+        server_states: &mut ServerStates,
+        now: Instant,
+        transport_context: TransportContext,
+        association_handle: usize,
+        stream_id: u16,
+        payload: BytesMut,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let request_str = String::from_utf8(payload.to_vec())?;
+
+        let four_tuple = (&transport_context).into();
+        let (session_id, endpoint_id) = server_states
+            .find_endpoint(&four_tuple)
+            .ok_or(Error::ErrClientTransportNotSet)?;
+
+        if let Ok(control_message) = serde_json::from_str::<DataChannelControlMessage>(&request_str)
+        {
+            return match control_message {
+                DataChannelControlMessage::Layer(request) => {
+                    server_states.request_layer(session_id, endpoint_id, request)?;
+                    Ok(vec![])
+                }
+                DataChannelControlMessage::Pin(request) => {
+                    server_states.pin_endpoints(session_id, endpoint_id, request)?;
+                    Ok(vec![])
+                }
+                DataChannelControlMessage::Subscribe(request) => {
+                    server_states.subscribe(session_id, endpoint_id, request)?;
+                    Ok(vec![])
+                }
+                DataChannelControlMessage::Unsubscribe(request) => {
+                    server_states.unsubscribe(session_id, endpoint_id, request)?;
+                    Ok(vec![])
+                }
+            };
+        }
+
+        if let Ok(chat_request) = serde_json::from_str::<ChatRequest>(&request_str) {
+            let session = server_states
+                .get_mut_session(&session_id)
+                .ok_or(Error::Other(format!(
+                    "can't find session id {}",
+                    session_id
+                )))?;
+            let event = session.handle_chat(endpoint_id, chat_request, payload.len(), now);
+            if let ChatEvent::ChatError { reason } = &event {
+                debug!(
+                    "{}: chat message of endpoint {} rejected: {}",
+                    session_id, endpoint_id, reason
+                );
+            }
+            return GatewayHandler::create_chat_message_events(
+                session,
+                now,
+                endpoint_id,
+                &event,
+                transport_context.ecn,
+            );
+        }
+
+        let request_sdp = serde_json::from_str::<RTCSessionDescription>(&request_str)
+            .map_err(|err| Error::Other(err.to_string()))?;
+
+        match request_sdp.sdp_type {
+            RTCSdpType::Offer => {
+                let answer = server_states.accept_offer(
+                    session_id,
+                    endpoint_id,
+                    Some(four_tuple),
+                    request_sdp,
+                    true,
+                )?;
+                let answer_str =
+                    serde_json::to_string(&answer).map_err(|err| Error::Other(err.to_string()))?;
+
+                let peers = GatewayHandler::get_other_datachannel_transport_contexts(
+                    server_states,
+                    &transport_context,
+                )?;
+                let mut messages = Vec::with_capacity(peers.len() + 1);
+
+                messages.push(TaggedMessageEvent {
+                    now,
+                    transport: transport_context,
+                    message: MessageEvent::Dtls(DTLSMessageEvent::DataChannel(
+                        ApplicationMessage {
+                            association_handle,
+                            stream_id,
+                            data_channel_event: DataChannelEvent::Message(BytesMut::from(
+                                answer_str.as_str(),
+                            )),
+                        },
+                    )),
+                });
+
+                // the offer given up when the endpoint's own crossed it follows the answer
+                if GatewayHandler::is_renegotiation_needed(server_states, session_id, endpoint_id) {
+                    messages.extend(GatewayHandler::create_offer_message_event(
+                        server_states,
+                        now,
+                        transport_context,
+                        association_handle,
+                        stream_id,
+                    )?);
+                }
+
+                // trigger other endpoints' create_offer()
+                for (
+                    other_transport_context,
+                    association_handle,
+                    stream_id,
+                    is_renegotiation_needed,
+                ) in peers
+                {
+                    if is_renegotiation_needed {
+                        messages.extend(GatewayHandler::create_offer_message_event(
+                            server_states,
+                            now,
+                            other_transport_context,
+                            association_handle,
+                            stream_id,
+                        )?);
+                    }
+                }
+
+                Ok(messages)
+            }
+            RTCSdpType::Answer => {
+                let unattached_ssrcs = server_states
+                    .get_session(&session_id)
+                    .and_then(|session| session.get_endpoint(&endpoint_id))
+                    .map(|endpoint| endpoint.get_unattached_video_ssrcs())
+                    .unwrap_or_default();
+
+                server_states.accept_answer(session_id, endpoint_id, four_tuple, request_sdp)?;
+
+                // new subscribers can't decode anything until the publisher's next keyframe
+                let still_unattached_ssrcs = server_states
+                    .get_session(&session_id)
+                    .and_then(|session| session.get_endpoint(&endpoint_id))
+                    .map(|endpoint| endpoint.get_unattached_video_ssrcs())
+                    .unwrap_or_default();
+                let attached_ssrcs = unattached_ssrcs
+                    .into_iter()
+                    .filter(|ssrc| !still_unattached_ssrcs.contains(ssrc))
+                    .collect();
+                let mut messages = GatewayHandler::request_keyframes(
+                    server_states,
+                    now,
+                    &transport_context,
+                    attached_ssrcs,
+                )?;
+
+                // tracks published while the offer was pending are offered once it's answered
+                if GatewayHandler::is_renegotiation_needed(server_states, session_id, endpoint_id) {
+                    messages.extend(GatewayHandler::create_offer_message_event(
+                        server_states,
+                        now,
+                        transport_context,
+                        association_handle,
+                        stream_id,
+                    )?);
+                }
+                Ok(messages)
+            }
+            _ => Err(Error::Other(format!(
+                "Unsupported SDP type {}",
+                request_sdp.sdp_type
+            ))),
+        }
+    }
+
+    fn handle_rtp_message(
+        server_states: &mut ServerStates,
+        now: Instant,
+        transport_context: TransportContext,
+        mut rtp_packet: rtp::packet::Packet,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        debug!("handle_rtp_message {}", transport_context.peer_addr);
+        let four_tuple = (&transport_context).into();
+        server_states.get_mut_transport(&four_tuple)?.keep_alive();
+
+        let (session_id, endpoint_id) = server_states
+            .find_endpoint(&four_tuple)
+            .ok_or(Error::ErrClientTransportNotSet)?;
+        let session = server_states
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        if let Some(plain_ingest) = session.get_plain_ingest(&endpoint_id) {
+            plain_ingest.rewrite_payload_type(&mut rtp_packet.header);
+        }
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+
+        let incoming_stream = endpoint.resolve_incoming_stream(&rtp_packet.header);
+        let audio_level = endpoint
+            .header_extension_id(AUDIO_LEVEL_URI)
+            .and_then(|id| rtp_packet.header.get_extension(id))
+            .and_then(|payload| speaker::parse_audio_level(&payload));
+
+        // mid and rid are only meaningful to the publisher's bundle, receivers demux by SSRC,
+        // and the audio level is set again with the id negotiated by each receiver
+        for uri in [
+            SDES_MID_URI,
+            SDES_RTP_STREAM_ID_URI,
+            SDES_REPAIR_RTP_STREAM_ID_URI,
+            AUDIO_LEVEL_URI,
+        ] {
+            if let Some(id) = endpoint.header_extension_id(uri) {
+                let _ = rtp_packet.header.del_extension(id);
+            }
+        }
+        if rtp_packet.header.extensions.is_empty() {
+            rtp_packet.header.extension = false;
+        }
+
+        // the other workers hosting the session forward the packet to their own subscribers
+        if let (Some(incoming_stream), Some(cascade)) =
+            (incoming_stream.as_ref(), session.get_mut_cascade())
+        {
+            cascade.relay_rtp(endpoint_id, incoming_stream, audio_level, &rtp_packet);
+        }
+
+        GatewayHandler::forward_rtp_message(
+            session,
+            now,
+            transport_context.local_addr,
+            transport_context.ecn,
+            endpoint_id,
+            incoming_stream,
+            audio_level,
+            rtp_packet,
+        )
+    }
+
+    /// forward_rtp_message forwards a packet published by an endpoint of the session, connected
+    /// to this worker or relayed by another one, to its subscribers, recording, egress and HLS
+    #[allow(clippy::too_many_arguments)]
+    fn forward_rtp_message(
+        session: &mut Session,
+        now: Instant,
+        local_addr: SocketAddr,
+        ecn: Option<EcnCodepoint>,
+        endpoint_id: EndpointId,
+        incoming_stream: Option<IncomingStream>,
+        audio_level: Option<u8>,
+        rtp_packet: rtp::packet::Packet,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let session_id = session.session_id();
+        let keyframe_request_interval = session
+            .session_config()
+            .server_config
+            .keyframe_request_interval;
+        let endpoint = session
+            .get_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+
+        let mut outgoing_messages = vec![];
+        let mut is_video = false;
+        let mut is_video_stream = false;
+        let mut forwarded_mid = None;
+        if let Some(incoming_stream) = incoming_stream {
+            forwarded_mid = Some(format!("{}-{}", endpoint_id, incoming_stream.mid));
+            let (is_simulcast, kind) = endpoint
+                .get_transceivers()
+                .get(&incoming_stream.mid)
+                .map_or((false, RTPCodecType::Unspecified), |transceiver| {
+                    (transceiver.is_simulcast(), transceiver.kind)
+                });
+            is_video = kind == RTPCodecType::Video;
+            if is_video && !is_simulcast && !incoming_stream.is_repair {
+                is_video_stream = true;
+            }
+            if !incoming_stream.is_repair {
+                session.on_published_rtp(endpoint_id, &incoming_stream.mid, kind, audio_level, now);
+            }
+            if GatewayHandler::record_rtp_message(
+                session,
+                now,
+                endpoint_id,
+                &incoming_stream,
+                kind,
+                &rtp_packet,
+            ) {
+                // a recording starts from a keyframe, like a new subscriber
+                outgoing_messages.extend(GatewayHandler::request_publisher_keyframes(
+                    session,
+                    now,
+                    keyframe_request_interval,
+                    vec![(endpoint_id, rtp_packet.header.ssrc)],
+                    ecn,
+                ));
+            }
+            let (egress_message, is_keyframe_needed) = GatewayHandler::egress_rtp_message(
+                session,
+                now,
+                local_addr,
+                endpoint_id,
+                &incoming_stream,
+                &rtp_packet,
+            );
+            outgoing_messages.extend(egress_message);
+            if is_keyframe_needed {
+                // an external processor starts decoding from a keyframe too
+                outgoing_messages.extend(GatewayHandler::request_publisher_keyframes(
+                    session,
+                    now,
+                    keyframe_request_interval,
+                    vec![(endpoint_id, rtp_packet.header.ssrc)],
+                    ecn,
+                ));
+            }
+            if session.hls_rtp(now, endpoint_id, &incoming_stream, &rtp_packet) {
+                // hls segments start with a keyframe, and so does a speaker switch
+                outgoing_messages.extend(GatewayHandler::request_publisher_keyframes(
+                    session,
+                    now,
+                    keyframe_request_interval,
+                    vec![(endpoint_id, rtp_packet.header.ssrc)],
+                    ecn,
+                ));
+            }
+            if is_simulcast {
+                outgoing_messages.extend(GatewayHandler::forward_simulcast_rtp_message(
+                    session,
+                    now,
+                    ecn,
+                    endpoint_id,
+                    incoming_stream,
+                    rtp_packet,
+                )?);
+                return Ok(outgoing_messages);
+            }
+        }
+
+        if let Some(dominant_speaker) = audio_level
+            .and_then(|audio_level| session.on_audio_level(endpoint_id, audio_level, now))
+        {
+            info!(
+                "{}: dominant speaker changed to endpoint {}",
+                session_id, dominant_speaker
+            );
+            session.report_active_speaker(dominant_speaker);
+            outgoing_messages.extend(GatewayHandler::create_session_event_message_events(
+                session,
+                now,
+                &SessionEvent::ActiveSpeakerChanged {
+                    endpoint_id: dominant_speaker,
+                },
+                ecn,
+            )?);
+        }
+
+        if let (true, Some(forwarded_mid)) = (is_video_stream, &forwarded_mid) {
+            // video paused by the last N policy resumes on a keyframe
+            let subscriber_ids: Vec<EndpointId> = session
+                .get_endpoints()
+                .keys()
+                .filter(|&&other_endpoint_id| other_endpoint_id != endpoint_id)
+                .copied()
+                .collect();
+            let mut is_resumed = false;
+            for other_endpoint_id in subscriber_ids {
+                let is_forwarded = session.is_video_forwarded(&endpoint_id, &other_endpoint_id);
+                if let Some(other_endpoint) = session.get_mut_endpoint(&other_endpoint_id) {
+                    is_resumed |= other_endpoint.set_video_forwarded(forwarded_mid, is_forwarded);
+                }
+            }
+            if is_resumed {
+                outgoing_messages.extend(GatewayHandler::request_publisher_keyframes(
+                    session,
+                    now,
+                    keyframe_request_interval,
+                    vec![(endpoint_id, rtp_packet.header.ssrc)],
+                    ecn,
+                ));
+            }
+        }
+
+        let peers = GatewayHandler::get_subscriber_transport_contexts(
+            session,
+            &endpoint_id,
+            forwarded_mid.as_ref(),
+            is_video,
+            ecn,
+        );
+        let audio_level_ids = match (audio_level, &forwarded_mid) {
+            (Some(_), Some(forwarded_mid)) => {
+                GatewayHandler::get_audio_level_extension_ids(session, forwarded_mid)
+            }
+            _ => HashMap::new(),
+        };
+
+        for transport in peers {
+            let mut rtp_packet = rtp_packet.clone();
+            if let (Some(audio_level), Some(&id)) = (
+                audio_level,
+                audio_level_ids.get(&FourTuple::from(&transport)),
+            ) {
+                // the voice activity flag isn't relayed between workers, only the level
+                let _ = rtp_packet
+                    .header
+                    .set_extension(id, Bytes::copy_from_slice(&[audio_level]));
+            }
+            outgoing_messages.push(TaggedMessageEvent {
+                now,
+                transport,
+                message: MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet)),
+            });
+        }
+
+        Ok(outgoing_messages)
+    }
+
+    /// get_audio_level_extension_ids returns the id of the audio level header extension on the
+    /// forwarded mid, by transport of the endpoints which negotiated it
+    fn get_audio_level_extension_ids(
+        session: &Session,
+        forwarded_mid: &Mid,
+    ) -> HashMap<FourTuple, u8> {
+        let mut audio_level_ids = HashMap::new();
+        for endpoint in session.get_endpoints().values() {
+            let Some(id) = endpoint
+                .get_transceivers()
+                .get(forwarded_mid)
+                .and_then(|transceiver| transceiver.header_extension_id(AUDIO_LEVEL_URI))
+            else {
+                continue;
+            };
+            for four_tuple in endpoint.get_transports().keys() {
+                audio_level_ids.insert(*four_tuple, id);
+            }
+        }
+        audio_level_ids
+    }
+
+    /// record_rtp_message hands a packet of an incoming stream to the recorder of the session,
+    /// and returns whether a keyframe has to be requested for the recording
+    fn record_rtp_message(
+        session: &mut Session,
+        now: Instant,
+        endpoint_id: EndpointId,
+        incoming_stream: &IncomingStream,
+        kind: RTPCodecType,
+        rtp_packet: &rtp::packet::Packet,
+    ) -> bool {
+        if !session.is_recording() || incoming_stream.is_repair {
+            return false;
+        }
+        let Some(codec) = session
+            .get_endpoint(&endpoint_id)
+            .and_then(|endpoint| endpoint.get_transceivers().get(&incoming_stream.mid))
+            .and_then(|transceiver| {
+                transceiver
+                    .rtp_params
+                    .codecs
+                    .iter()
+                    .find(|codec| codec.payload_type == rtp_packet.header.payload_type)
+            })
+            .map(|codec| codec.capability.clone())
+        else {
+            return false;
+        };
+        let Some(recorder) = session.get_mut_recorder() else {
+            return false;
+        };
+
+        match recorder.on_rtp(
+            now,
+            endpoint_id,
+            incoming_stream,
+            kind,
+            &codec,
+            rtp_packet.clone(),
+        ) {
+            Ok(is_waiting_keyframe) => is_waiting_keyframe,
+            Err(err) => {
+                warn!(
+                    "can't record mid {} of endpoint {}: {}",
+                    incoming_stream.mid, endpoint_id, err
+                );
+                false
+            }
+        }
+    }
+
+    /// egress_rtp_message returns the plain RTP packet to send to the egress of the session for
+    /// a packet of an incoming stream, and whether a keyframe has to be requested for it
+    fn egress_rtp_message(
+        session: &mut Session,
+        now: Instant,
+        local_addr: SocketAddr,
+        endpoint_id: EndpointId,
+        incoming_stream: &IncomingStream,
+        rtp_packet: &rtp::packet::Packet,
+    ) -> (Option<TaggedMessageEvent>, bool) {
+        let (egress_packet, is_keyframe_needed) =
+            session.egress_rtp(now, endpoint_id, incoming_stream, rtp_packet);
+        // plain RTP bypasses the interceptors and SRTP on its way out
+        let message = egress_packet.map(|(peer_addr, payload)| TaggedMessageEvent {
+            now,
+            transport: TransportContext {
+                local_addr,
+                peer_addr,
+                ecn: None,
+            },
+            message: MessageEvent::Rtp(RTPMessageEvent::Raw(payload)),
+        });
+        (message, is_keyframe_needed)
+    }
+
+    /// forward_simulcast_rtp_message forwards the layer selected for each subscriber, as a single
+    /// stream with the SSRC announced in the subscriber's SDP
+    fn forward_simulcast_rtp_message(
+        session: &mut Session,
+        now: Instant,
+        ecn: Option<EcnCodepoint>,
+        endpoint_id: EndpointId,
+        incoming_stream: IncomingStream,
+        rtp_packet: rtp::packet::Packet,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let Some(rid) = incoming_stream.rid.as_deref() else {
+            trace!(
+                "drop simulcast packet without rid from endpoint {}",
+                endpoint_id
+            );
+            return Ok(vec![]);
+        };
+        if incoming_stream.is_repair {
+            return GatewayHandler::forward_simulcast_retransmission(
+                session,
+                now,
+                ecn,
+                endpoint_id,
+                &incoming_stream,
+                rtp_packet,
+            );
+        }
+
+        let keyframe_request_interval = session
+            .session_config()
+            .server_config
+            .keyframe_request_interval;
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+
+        let (mime_type, clock_rate) = endpoint
+            .get_transceivers()
+            .get(&incoming_stream.mid)
+            .and_then(|transceiver| {
+                transceiver
+                    .rtp_params
+                    .codecs
+                    .iter()
+                    .find(|codec| codec.payload_type == rtp_packet.header.payload_type)
+            })
+            .map(|codec| {
+                (
+                    codec.capability.mime_type.clone(),
+                    codec.capability.clock_rate,
+                )
+            })
+            .ok_or(Error::Other(format!(
+                "unknown payload type {} for mid {}",
+                rtp_packet.header.payload_type, incoming_stream.mid
+            )))?;
+        let is_keyframe = keyframe::is_keyframe(&mime_type, &rtp_packet.payload);
+        let keyframe_size = if is_keyframe {
+            keyframe::keyframe_size(&mime_type, &rtp_packet.payload)
+        } else {
+            None
+        };
+        endpoint
+            .get_mut_simulcast_tracks()
+            .entry(incoming_stream.mid.clone())
+            .or_default()
+            .on_packet(rid, now, rtp_packet.payload.len(), keyframe_size);
+
+        // select the layer of every subscriber first, the track can't be borrowed while
+        // their forwarders are updated
+        let forwarded_mid = format!("{}-{}", endpoint_id, incoming_stream.mid);
+        let default_preference = LayerPreference::default();
+        let mut subscribers = vec![];
+        if let Some(track) = session
+            .get_endpoint(&endpoint_id)
+            .and_then(|endpoint| endpoint.get_simulcast_tracks().get(&incoming_stream.mid))
+        {
+            for (&other_endpoint_id, other_endpoint) in session.get_endpoints().iter() {
+                if other_endpoint_id == endpoint_id {
+                    continue;
+                }
+                let Some(ssrc) = other_endpoint
+                    .get_transceivers()
+                    .get(&forwarded_mid)
+                    .filter(|transceiver| transceiver.direction.has_send())
+                    .and_then(|transceiver| transceiver.sender.as_ref())
+                    .and_then(|sender| sender.ssrcs.first().copied())
+                else {
+                    continue;
+                };
+                let preference = other_endpoint
+                    .get_simulcast_forwarders()
+                    .get(&forwarded_mid)
+                    .map(|forwarder| forwarder.preference())
+                    .unwrap_or(&default_preference);
+                let target_rid = if !session.is_video_forwarded(&endpoint_id, &other_endpoint_id) {
+                    None
+                } else {
+                    track
+                        .select(preference, other_endpoint.estimated_video_bitrate(), now)
+                        .map(|rid| rid.to_string())
+                };
+                subscribers.push((other_endpoint_id, ssrc, target_rid));
+            }
+        }
+
+        let mut outgoing_messages = vec![];
+        let mut keyframe_rids = vec![];
+        for (other_endpoint_id, ssrc, target_rid) in subscribers {
+            let Some(other_endpoint) = session.get_mut_endpoint(&other_endpoint_id) else {
+                continue;
+            };
+            let forwarder = other_endpoint
+                .get_mut_simulcast_forwarders()
+                .entry(forwarded_mid.clone())
+                .or_default();
+            if forwarder.set_target_rid(target_rid.as_deref()) && forwarder.is_switch_pending() {
+                // switching only happens on a keyframe of the target layer
+                if let Some(target_rid) = target_rid {
+                    if !keyframe_rids.contains(&target_rid) {
+                        keyframe_rids.push(target_rid);
+                    }
+                }
+            }
+            let Some(forwarded_packet) =
+                forwarder.forward(rid, &rtp_packet, is_keyframe, ssrc, clock_rate, now)
+            else {
+                continue;
+            };
+
+            for (other_four_tuple, other_transport) in other_endpoint.get_transports().iter() {
+                if other_transport.is_local_srtp_context_ready() {
+                    outgoing_messages.push(TaggedMessageEvent {
+                        now,
+                        transport: TransportContext {
+                            local_addr: other_four_tuple.local_addr,
+                            peer_addr: other_four_tuple.peer_addr,
+                            ecn,
+                        },
+                        message: MessageEvent::Rtp(RTPMessageEvent::Rtp(forwarded_packet.clone())),
+                    });
+                }
+            }
+        }
+
+        let streams = keyframe_rids
+            .iter()
+            .filter_map(|rid| {
+                session
+                    .get_endpoint(&endpoint_id)?
+                    .incoming_ssrc(&incoming_stream.mid, Some(rid))
+                    .map(|ssrc| (endpoint_id, ssrc))
+            })
+            .collect();
+        outgoing_messages.extend(GatewayHandler::request_publisher_keyframes(
+            session,
+            now,
+            keyframe_request_interval,
+            streams,
+            ecn,
+        ));
+
+        Ok(outgoing_messages)
+    }
+
+    /// forward_simulcast_retransmission unwraps a RTX packet of a simulcast layer (RFC 4588) and
+    /// forwards the original packet to the subscribers receiving the layer, as the answer to the
+    /// NACK they sent
+    fn forward_simulcast_retransmission(
+        session: &mut Session,
+        now: Instant,
+        ecn: Option<EcnCodepoint>,
+        endpoint_id: EndpointId,
+        incoming_stream: &IncomingStream,
+        mut rtp_packet: rtp::packet::Packet,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let Some(rid) = incoming_stream.rid.as_deref() else {
+            return Ok(vec![]);
+        };
+        let codecs = session
+            .get_endpoint(&endpoint_id)
+            .and_then(|endpoint| endpoint.get_transceivers().get(&incoming_stream.mid))
+            .map(|transceiver| transceiver.rtp_params.codecs.as_slice())
+            .unwrap_or_default();
+        let Some((payload_type, clock_rate)) = codecs
+            .iter()
+            .find(|codec| {
+                codec.payload_type == rtp_packet.header.payload_type
+                    && codec
+                        .capability
+                        .mime_type
+                        .eq_ignore_ascii_case(MIME_TYPE_RTX)
+            })
+            .and_then(|codec| {
+                fmtp::parse(&codec.capability.mime_type, &codec.capability.sdp_fmtp_line)
+                    .parameter("apt")?
+                    .parse::<u8>()
+                    .ok()
+            })
+            .and_then(|payload_type| {
+                codecs
+                    .iter()
+                    .find(|codec| codec.payload_type == payload_type)
+                    .map(|codec| (payload_type, codec.capability.clock_rate))
+            })
+        else {
+            trace!(
+                "drop repaired simulcast packet with payload type {} from endpoint {}",
+                rtp_packet.header.payload_type,
+                endpoint_id
+            );
+            return Ok(vec![]);
+        };
+        // padding only packets probe the bandwidth, they don't carry a retransmission
+        if rtp_packet.payload.len() < 2 {
+            return Ok(vec![]);
+        }
+        rtp_packet.header.sequence_number =
+            u16::from_be_bytes([rtp_packet.payload[0], rtp_packet.payload[1]]);
+        rtp_packet.header.payload_type = payload_type;
+        rtp_packet.payload = rtp_packet.payload.slice(2..);
+
+        let forwarded_mid = format!("{}-{}", endpoint_id, incoming_stream.mid);
+        let mut outgoing_messages = vec![];
+        for (&other_endpoint_id, other_endpoint) in session.get_mut_endpoints().iter_mut() {
+            if other_endpoint_id == endpoint_id {
+                continue;
+            }
+            let Some(ssrc) = other_endpoint
+                .get_transceivers()
+                .get(&forwarded_mid)
+                .filter(|transceiver| transceiver.direction.has_send())
+                .and_then(|transceiver| transceiver.sender.as_ref())
+                .and_then(|sender| sender.ssrcs.first().copied())
+            else {
+                continue;
+            };
+            // never switches layers, the retransmission isn't a keyframe for the forwarder
+            let Some(forwarded_packet) = other_endpoint
+                .get_mut_simulcast_forwarders()
+                .get_mut(&forwarded_mid)
+                .and_then(|forwarder| {
+                    forwarder.forward(rid, &rtp_packet, false, ssrc, clock_rate, now)
+                })
+            else {
+                continue;
+            };
+
+            for (other_four_tuple, other_transport) in other_endpoint.get_transports().iter() {
+                if other_transport.is_local_srtp_context_ready() {
+                    outgoing_messages.push(TaggedMessageEvent {
+                        now,
+                        transport: TransportContext {
+                            local_addr: other_four_tuple.local_addr,
+                            peer_addr: other_four_tuple.peer_addr,
+                            ecn,
+                        },
+                        message: MessageEvent::Rtp(RTPMessageEvent::Rtp(forwarded_packet.clone())),
+                    });
+                }
+            }
+        }
+        Ok(outgoing_messages)
+    }
+
+    fn handle_rtcp_message(
+        server_states: &mut ServerStates,
+        now: Instant,
+        transport_context: TransportContext,
+        rtcp_packets: Vec<Box<dyn rtcp::packet::Packet>>,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        debug!("handle_rtcp_message {}", transport_context.peer_addr);
+        server_states
+            .get_mut_transport(&(&transport_context).into())?
+            .keep_alive();
+
+        // keyframe requests are sent to the publishers by the SFU, aggregated per stream
+        let (keyframe_requests, rtcp_packets): (Vec<_>, Vec<_>) =
+            rtcp_packets.into_iter().partition(|rtcp_packet| {
+                rtcp_packet.as_any().is::<PictureLossIndication>()
+                    || rtcp_packet.as_any().is::<FullIntraRequest>()
+            });
+        let mut ssrcs = vec![];
+        for keyframe_request in &keyframe_requests {
+            if let Some(pli) = keyframe_request
+                .as_any()
+                .downcast_ref::<PictureLossIndication>()
+            {
+                ssrcs.push(pli.media_ssrc);
+            } else if let Some(fir) = keyframe_request.as_any().downcast_ref::<FullIntraRequest>() {
+                ssrcs.extend(fir.fir.iter().map(|fir_entry| fir_entry.ssrc));
+            }
+        }
+        let mut outgoing_messages =
+            GatewayHandler::request_keyframes(server_states, now, &transport_context, ssrcs)?;
+        let rtcp_packets = GatewayHandler::resolve_simulcast_nacks(
+            server_states,
+            &transport_context,
+            rtcp_packets,
+        );
+        if rtcp_packets.is_empty() {
+            return Ok(outgoing_messages);
+        }
+
+        if let Some((session_id, endpoint_id)) =
+            server_states.find_endpoint(&(&transport_context).into())
+        {
+            if let Some(cascade) = server_states
+                .get_mut_session(&session_id)
+                .and_then(|session| session.get_mut_cascade())
+            {
+                // the subscribers of the other workers hosting the session get the sender
+                // reports of the publisher too
+                match rtcp::packet::marshal(&rtcp_packets) {
+                    Ok(payload) => cascade.relay_rtcp(endpoint_id, payload.freeze()),
+                    Err(err) => warn!("can't marshal rtcp of endpoint {}: {}", endpoint_id, err),
+                }
+            }
+        }
+
+        //TODO: Selective Forwarding RTCP Packets
+        let peers = GatewayHandler::get_other_media_transport_contexts(
+            server_states,
+            &transport_context,
+            None,
+            false,
+        )?;
+
+        for transport in peers {
+            outgoing_messages.push(TaggedMessageEvent {
+                now,
+                transport,
+                message: MessageEvent::Rtp(RTPMessageEvent::Rtcp(rtcp_packets.clone())),
+            });
+        }
+
+        Ok(outgoing_messages)
+    }
+
+    /// resolve_simulcast_nacks rewrites the NACKs of simulcast tracks, sent by the endpoint for
+    /// the SSRC and sequence numbers it receives, into ones the publisher of the forwarded layer
+    /// can answer. NACKs left without any packet to retransmit are dropped.
+    fn resolve_simulcast_nacks(
+        server_states: &ServerStates,
+        transport_context: &TransportContext,
+        rtcp_packets: Vec<Box<dyn rtcp::packet::Packet>>,
+    ) -> Vec<Box<dyn rtcp::packet::Packet>> {
+        let Some((session_id, endpoint_id)) =
+            server_states.find_endpoint(&transport_context.into())
+        else {
+            return rtcp_packets;
+        };
+        let Some(session) = server_states.get_session(&session_id) else {
+            return rtcp_packets;
+        };
+
+        rtcp_packets
+            .into_iter()
+            .filter_map(|rtcp_packet| {
+                let Some(nack) = rtcp_packet.as_any().downcast_ref::<TransportLayerNack>() else {
+                    return Some(rtcp_packet);
+                };
+                let sequence_numbers: Vec<u16> = nack
+                    .nacks
+                    .iter()
+                    .flat_map(|nack_pair| nack_pair.packet_list())
+                    .collect();
+                let Some((publisher_ssrc, published_sequence_numbers)) = session
+                    .resolve_simulcast_nack(&endpoint_id, nack.media_ssrc, &sequence_numbers)
+                else {
+                    return Some(rtcp_packet);
+                };
+                if published_sequence_numbers.is_empty() {
+                    trace!(
+                        "{}/{}: drop nack for packets of ssrc {} forwarded before the layer switch",
+                        session.session_id(),
+                        endpoint_id,
+                        nack.media_ssrc
+                    );
+                    return None;
+                }
+                Some(Box::new(TransportLayerNack {
+                    sender_ssrc: nack.sender_ssrc,
+                    media_ssrc: publisher_ssrc,
+                    nacks: nack_pairs_from_sequence_numbers(&published_sequence_numbers),
+                }) as Box<dyn rtcp::packet::Packet>)
+            })
+            .collect()
+    }
+
+    /// request_keyframes requests keyframes to the publishers of the streams forwarded to the
+    /// endpoint with the SSRCs
+    fn request_keyframes(
+        server_states: &mut ServerStates,
+        now: Instant,
+        transport_context: &TransportContext,
+        ssrcs: Vec<SSRC>,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        if ssrcs.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let four_tuple = transport_context.into();
+        let (session_id, endpoint_id) = server_states
+            .find_endpoint(&four_tuple)
+            .ok_or(Error::ErrClientTransportNotSet)?;
+        let keyframe_request_interval = server_states.server_config().keyframe_request_interval;
+        let session = server_states
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        let mut streams = vec![];
+        for ssrc in ssrcs {
+            match session.resolve_forwarded_stream(&endpoint_id, ssrc) {
+                Some(stream) => streams.push(stream),
+                None => trace!(
+                    "{}/{}: drop keyframe request for unknown ssrc {}",
+                    session_id,
+                    endpoint_id,
+                    ssrc
+                ),
+            }
+        }
+
+        Ok(GatewayHandler::request_publisher_keyframes(
+            session,
+            now,
+            keyframe_request_interval,
+            streams,
+            transport_context.ecn,
+        ))
+    }
+
+    /// request_publisher_keyframes sends keyframe requests for streams received from publishers,
+    /// unless one was sent for the stream within the interval, in which case it is sent once the
+    /// interval elapses
+    fn request_publisher_keyframes(
+        session: &mut Session,
+        now: Instant,
+        keyframe_request_interval: Duration,
+        streams: Vec<(EndpointId, SSRC)>,
+        ecn: Option<EcnCodepoint>,
+    ) -> Vec<TaggedMessageEvent> {
+        let mut ssrcs_by_publisher: HashMap<EndpointId, Vec<SSRC>> = HashMap::new();
+        for (publisher_id, ssrc) in streams {
+            let Some(publisher) = session.get_mut_endpoint(&publisher_id) else {
+                continue;
+            };
+            if publisher
+                .get_mut_keyframe_requests()
+                .request(ssrc, now, keyframe_request_interval)
+            {
+                let ssrcs = ssrcs_by_publisher.entry(publisher_id).or_default();
+                if !ssrcs.contains(&ssrc) {
+                    ssrcs.push(ssrc);
+                }
+            } else {
+                trace!(
+                    "aggregate keyframe request for ssrc {} of endpoint {}",
+                    ssrc,
+                    publisher_id
+                );
+            }
+        }
+
+        let mut outgoing_messages = vec![];
+        for (publisher_id, ssrcs) in ssrcs_by_publisher {
+            // publishers connected to another worker are sent the requests by that worker
+            if session
+                .get_mut_cascade()
+                .is_some_and(|cascade| cascade.request_keyframes(publisher_id, ssrcs.clone()))
+            {
+                continue;
+            }
+            if let Some(publisher) = session.get_mut_endpoint(&publisher_id) {
+                outgoing_messages.extend(GatewayHandler::create_keyframe_request_message_events(
+                    publisher, now, ssrcs, ecn,
+                ));
+            }
+        }
+        outgoing_messages
+    }
+
+    fn create_keyframe_request_message_events(
+        endpoint: &mut Endpoint,
+        now: Instant,
+        ssrcs: Vec<SSRC>,
+        ecn: Option<EcnCodepoint>,
+    ) -> Vec<TaggedMessageEvent> {
+        debug!(
+            "request keyframes of ssrcs {:?} to endpoint {}",
+            ssrcs,
+            endpoint.endpoint_id()
+        );
+        let mut rtcp_packets = Vec::with_capacity(ssrcs.len());
+        for ssrc in ssrcs {
+            let use_fir = endpoint.is_fir_preferred(ssrc);
+            rtcp_packets.push(endpoint.get_mut_keyframe_requests().packet(ssrc, use_fir));
+        }
+
+        let mut outgoing_messages = vec![];
+        for (four_tuple, transport) in endpoint.get_transports().iter() {
+            // plain RTP sources get their keyframe requests unencrypted
+            if transport.is_local_srtp_context_ready() || transport.is_plain() {
+                outgoing_messages.push(TaggedMessageEvent {
+                    now,
+                    transport: TransportContext {
+                        local_addr: four_tuple.local_addr,
+                        peer_addr: four_tuple.peer_addr,
+                        ecn,
+                    },
+                    message: MessageEvent::Rtp(RTPMessageEvent::Rtcp(rtcp_packets.clone())),
+                });
+            }
+        }
+        outgoing_messages
+    }
+
+    /// create_session_event_message_events sends the event to every endpoint of the session whose
+    /// data channel is ready
+    fn create_session_event_message_events(
+        session: &Session,
+        now: Instant,
+        event: &SessionEvent,
+        ecn: Option<EcnCodepoint>,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let event_str =
+            serde_json::to_string(event).map_err(|err| Error::Other(err.to_string()))?;
+
+        let mut outgoing_messages = vec![];
+        for endpoint in session.get_endpoints().values() {
+            outgoing_messages.extend(GatewayHandler::create_datachannel_message_events(
+                endpoint, now, &event_str, ecn,
+            ));
+        }
+        Ok(outgoing_messages)
+    }
+
+    /// create_chat_message_events sends the chat message of the sender to the endpoints of the
+    /// session it is for, among the ones whose data channel is ready
+    fn create_chat_message_events(
+        session: &Session,
+        now: Instant,
+        sender: EndpointId,
+        event: &ChatEvent,
+        ecn: Option<EcnCodepoint>,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let event_str =
+            serde_json::to_string(event).map_err(|err| Error::Other(err.to_string()))?;
+
+        let mut outgoing_messages = vec![];
+        for (&endpoint_id, endpoint) in session.get_endpoints().iter() {
+            if event.is_for(endpoint_id, sender) {
+                outgoing_messages.extend(GatewayHandler::create_datachannel_message_events(
+                    endpoint, now, &event_str, ecn,
+                ));
+            }
+        }
+        Ok(outgoing_messages)
+    }
+
+    fn create_datachannel_message_events(
+        endpoint: &Endpoint,
+        now: Instant,
+        message: &str,
+        ecn: Option<EcnCodepoint>,
+    ) -> Vec<TaggedMessageEvent> {
+        let mut outgoing_messages = vec![];
+        for (four_tuple, transport) in endpoint.get_transports().iter() {
+            if let (Some(association_handle), Some(stream_id)) =
+                transport.association_handle_and_stream_id()
+            {
+                outgoing_messages.push(TaggedMessageEvent {
+                    now,
+                    transport: TransportContext {
+                        local_addr: four_tuple.local_addr,
+                        peer_addr: four_tuple.peer_addr,
+                        ecn,
+                    },
+                    message: MessageEvent::Dtls(DTLSMessageEvent::DataChannel(
+                        ApplicationMessage {
+                            association_handle,
+                            stream_id,
+                            data_channel_event: DataChannelEvent::Message(BytesMut::from(message)),
+                        },
+                    )),
+                });
+            }
+        }
+        outgoing_messages
+    }
+
+    fn check_stun_message(
+        server_states: &ServerStates,
+        request: &mut stun::message::Message,
+    ) -> Result<Option<Rc<Candidate>>> {
+        match TextAttribute::get_from_as(request, ATTR_USERNAME) {
+            Ok(username) => {
+                if !request.contains(ATTR_PRIORITY) {
+                    return Err(Error::Other(
+                        "invalid STUN message without ATTR_PRIORITY".to_string(),
+                    ));
+                }
+
+                if request.contains(ATTR_ICE_CONTROLLING) {
+                    if request.contains(ATTR_ICE_CONTROLLED) {
+                        return Err(Error::Other("invalid STUN message with both ATTR_ICE_CONTROLLING and ATTR_ICE_CONTROLLED".to_string()));
+                    }
+                } else if request.contains(ATTR_ICE_CONTROLLED) {
+                    if request.contains(ATTR_USE_CANDIDATE) {
+                        return Err(Error::Other("invalid STUN message with both ATTR_USE_CANDIDATE and ATTR_ICE_CONTROLLED".to_string()));
+                    }
+                } else {
+                    return Err(Error::Other(
+                        "invalid STUN message without ATTR_ICE_CONTROLLING or ATTR_ICE_CONTROLLED"
+                            .to_string(),
+                    ));
+                }
+
+                if let Some(candidate) = server_states.find_candidate(&username.text) {
+                    let password = candidate.get_local_parameters().password.clone();
+                    let integrity = MessageIntegrity::new_short_term_integrity(password);
+                    integrity.check(request)?;
+                    Ok(Some(candidate.clone()))
+                } else {
+                    Err(Error::Other("username not found".to_string()))
+                }
+            }
+            Err(_) => {
+                if request.contains(ATTR_ICE_CONTROLLED)
+                    || request.contains(ATTR_ICE_CONTROLLING)
+                    || request.contains(ATTR_NETWORK_COST)
+                    || request.contains(ATTR_PRIORITY)
+                    || request.contains(ATTR_USE_CANDIDATE)
+                {
+                    Err(Error::Other("unexpected attribute".to_string()))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    fn get_other_datachannel_transport_contexts(
+        server_states: &mut ServerStates,
+        transport_context: &TransportContext,
+    ) -> Result<Vec<(TransportContext, usize, u16, bool)>> {
+        let four_tuple = transport_context.into();
+        let (session_id, endpoint_id) = server_states
+            .find_endpoint(&four_tuple)
+            .ok_or(Error::ErrClientTransportNotSet)?;
+        let session = server_states
+            .get_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        let mut peers = vec![];
+        let endpoints = session.get_endpoints();
+        for (&other_endpoint_id, other_endpoint) in endpoints.iter() {
+            if other_endpoint_id != endpoint_id {
+                let transports = other_endpoint.get_transports();
+                for (other_four_tuple, other_transport) in transports.iter() {
+                    if let (Some(association_handle), Some(stream_id)) =
+                        other_transport.association_handle_and_stream_id()
+                    {
+                        peers.push((
+                            TransportContext {
+                                local_addr: other_four_tuple.local_addr,
+                                peer_addr: other_four_tuple.peer_addr,
+                                ecn: transport_context.ecn,
+                            },
+                            association_handle,
+                            stream_id,
+                            other_endpoint.is_renegotiation_needed(),
+                        ));
+                    } else {
+                        // data channel is not ready yet for other_endpoint_id's other_four_tuple.
+                        // this transport just joins, but data channel is still setup
+                        trace!(
+                            "{}/{}'s data channel is not ready yet for {:?} since it is still setup",
+                            session_id,
+                            other_endpoint_id,
+                            other_four_tuple,
+                        );
+                    }
+                }
+            }
+        }
+        Ok(peers)
+    }
+
+    /// get_other_media_transport_contexts returns the transports of the other endpoints ready to
+    /// receive media, skipping the endpoints not subscribed to the forwarded mid, and the ones the
+    /// video isn't forwarded to when forwarding video
+    fn get_other_media_transport_contexts(
+        server_states: &mut ServerStates,
+        transport_context: &TransportContext,
+        forwarded_mid: Option<&Mid>,
+        is_video: bool,
+    ) -> Result<Vec<TransportContext>> {
+        let four_tuple = transport_context.into();
+        let (session_id, endpoint_id) = server_states
+            .find_endpoint(&four_tuple)
+            .ok_or(Error::ErrClientTransportNotSet)?;
+        let session = server_states
+            .get_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        Ok(GatewayHandler::get_subscriber_transport_contexts(
+            session,
+            &endpoint_id,
+            forwarded_mid,
+            is_video,
+            transport_context.ecn,
+        ))
+    }
+
+    /// get_subscriber_transport_contexts returns the transports ready to receive media of the
+    /// endpoints other than the publisher, with the same filters as
+    /// get_other_media_transport_contexts
+    fn get_subscriber_transport_contexts(
+        session: &Session,
+        endpoint_id: &EndpointId,
+        forwarded_mid: Option<&Mid>,
+        is_video: bool,
+        ecn: Option<EcnCodepoint>,
+    ) -> Vec<TransportContext> {
+        let mut peers = vec![];
+        let endpoints = session.get_endpoints();
+        for (other_endpoint_id, other_endpoint) in endpoints.iter() {
+            if other_endpoint_id != endpoint_id
+                && forwarded_mid.map_or(true, |mid| other_endpoint.is_subscribed(mid))
+                && (!is_video || session.is_video_forwarded(endpoint_id, other_endpoint_id))
+            {
+                let transports = other_endpoint.get_transports();
+                for (other_four_tuple, other_transport) in transports.iter() {
+                    if other_transport.is_plain() {
+                        // plain RTP sources only publish
+                        continue;
+                    }
+                    if other_transport.is_local_srtp_context_ready() {
+                        peers.push(TransportContext {
+                            local_addr: other_four_tuple.local_addr,
+                            peer_addr: other_four_tuple.peer_addr,
+                            ecn,
+                        });
+                    } else {
+                        // local_srtp_context is not ready yet for other_endpoint_id's other_four_tuple.
+                        // this transport just joins, but local_srtp_context is still setup
+                        trace!(
+                            "{}/{}'s local_srtp_context is not ready yet for {:?} since it is still setup",
+                            session.session_id(),
+                            other_endpoint_id,
+                            other_four_tuple,
+                        );
+                    }
+                }
+            }
+        }
+        peers
+    }
+
+    fn create_server_reflective_address_message_event(
+        now: Instant,
+        transport_context: TransportContext,
+        transaction_id: TransactionId,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let mut response = stun::message::Message::new();
+        response.build(&[
+            Box::new(BINDING_SUCCESS),
+            Box::new(transaction_id),
+            Box::new(XorMappedAddress {
+                ip: transport_context.peer_addr.ip(),
+                port: transport_context.peer_addr.port(),
+            }),
+        ])?;
+
+        debug!(
+            "create_server_reflective_address_message_event response type {} sent",
+            response.typ
+        );
+
+        Ok(vec![TaggedMessageEvent {
+            now,
+            transport: transport_context,
+            message: MessageEvent::Stun(STUNMessageEvent::Stun(response)),
+        }])
+    }
+
+    fn add_endpoint(
+        server_states: &mut ServerStates,
+        request: &stun::message::Message,
+        candidate: &Rc<Candidate>,
+        transport_context: &TransportContext,
+    ) -> Result<bool> {
+        let mut is_new_endpoint = false;
+
+        let session_id = candidate.session_id();
+        let session = server_states
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!("session {} not found", session_id)))?;
+
+        let endpoint_id = candidate.endpoint_id();
+        let endpoint = session.get_endpoint(&endpoint_id);
+        let four_tuple = transport_context.into();
+        let has_transport = if let Some(endpoint) = &endpoint {
+            endpoint.has_transport(&four_tuple)
+        } else {
+            is_new_endpoint = true;
+            false
+        };
+
+        if !request.contains(ATTR_USE_CANDIDATE) || has_transport {
+            return Ok(is_new_endpoint);
+        }
+
+        let is_new_endpoint = session.add_endpoint(candidate, transport_context)?;
+
+        server_states.add_endpoint(four_tuple, session_id, endpoint_id);
+
+        Ok(is_new_endpoint)
+    }
+
+    fn is_renegotiation_needed(
+        server_states: &ServerStates,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    ) -> bool {
+        server_states
+            .get_session(&session_id)
+            .and_then(|session| session.get_endpoint(&endpoint_id))
+            .is_some_and(|endpoint| endpoint.is_renegotiation_needed())
+    }
+
+    /// create_offer_message_event offers the endpoint its transceivers over its data channel,
+    /// unless an offer sent before still awaits its answer, in which case the endpoint is
+    /// offered again once it answers
+    pub(crate) fn create_offer_message_event(
+        server_states: &mut ServerStates,
+        now: Instant,
+        transport_context: TransportContext,
+        association_handle: usize,
+        stream_id: u16,
+    ) -> Result<Option<TaggedMessageEvent>> {
+        let four_tuple = (&transport_context).into();
+        let (session_id, endpoint_id) = server_states
+            .find_endpoint(&four_tuple)
+            .ok_or(Error::ErrClientTransportNotSet)?;
+        let session = server_states
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+        if endpoint.is_offer_pending() {
+            debug!(
+                "{}/{} is offered again once it answers the pending offer",
+                session_id, endpoint_id
+            );
+            endpoint.set_renegotiation_needed(true);
+            return Ok(None);
+        }
+        endpoint.set_renegotiation_needed(false); //clean renegotiation_needed flag
+
+        let remote_description = endpoint
+            .remote_description()
+            .ok_or(Error::Other("remote_description is not set".to_string()))?
+            .clone();
+
+        let local_conn_cred = {
+            let transports = endpoint.get_mut_transports();
+            let transport = transports.get_mut(&four_tuple).ok_or(Error::Other(format!(
+                "can't find transport for endpoint id {} with {:?}",
+                endpoint_id, four_tuple
+            )))?;
+            transport
+                .candidate()
+                .ok_or(Error::Other(format!(
+                    "plain transport of endpoint id {} can't be negotiated",
+                    endpoint_id
+                )))?
+                .local_connection_credentials()
+                .clone()
+        };
+
+        let offer = session.create_offer(
+            endpoint_id,
+            &remote_description,
+            &local_conn_cred.ice_params,
+        )?;
+        session.set_local_description(endpoint_id, &offer)?;
+        if let Some(endpoint) = session.get_mut_endpoint(&endpoint_id) {
+            endpoint.set_offer_pending(true);
+        }
+
+        let offer_str =
+            serde_json::to_string(&offer).map_err(|err| Error::Other(err.to_string()))?;
+
+        Ok(Some(TaggedMessageEvent {
+            now,
+            transport: transport_context,
+            message: MessageEvent::Dtls(DTLSMessageEvent::DataChannel(ApplicationMessage {
+                association_handle,
+                stream_id,
+                data_channel_event: DataChannelEvent::Message(BytesMut::from(offer_str.as_str())),
+            })),
+        }))
+    }
+}
@@ -0,0 +1,9 @@
+pub(crate) mod datachannel;
+pub(crate) mod demuxer;
+pub(crate) mod dtls;
+pub(crate) mod exception;
+pub(crate) mod gateway;
+pub(crate) mod interceptor;
+pub(crate) mod sctp;
+pub(crate) mod srtp;
+pub(crate) mod stun;
@@ -0,0 +1,216 @@
+use crate::messages::{MessageEvent, RTPMessageEvent, TaggedMessageEvent};
+use crate::server::states::ServerStates;
+use bytes::BytesMut;
+use log::{debug, error};
+use retty::channel::{Context, Handler};
+use shared::{
+    error::{Error, Result},
+    marshal::{Marshal, Unmarshal},
+    util::is_rtcp,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// SrtpHandler implements SRTP/RTP/RTCP Protocols handling
+pub struct SrtpHandler {
+    server_states: Rc<RefCell<ServerStates>>,
+}
+
+impl SrtpHandler {
+    pub fn new(server_states: Rc<RefCell<ServerStates>>) -> Self {
+        SrtpHandler { server_states }
+    }
+}
+
+impl Handler for SrtpHandler {
+    type Rin = TaggedMessageEvent;
+    type Rout = Self::Rin;
+    type Win = TaggedMessageEvent;
+    type Wout = Self::Win;
+
+    fn name(&self) -> &str {
+        "SrtpHandler"
+    }
+
+    fn handle_read(
+        &mut self,
+        ctx: &Context<Self::Rin, Self::Rout, Self::Win, Self::Wout>,
+        mut msg: Self::Rin,
+    ) {
+        if let MessageEvent::Rtp(RTPMessageEvent::Raw(message)) = msg.message {
+            debug!("srtp read {:?}", msg.transport.peer_addr);
+            let try_read = || -> Result<MessageEvent> {
+                let four_tuple = (&msg.transport).into();
+                let mut server_states = self.server_states.borrow_mut();
+                let is_rtcp = is_rtcp(&message);
+                if server_states.find_endpoint(&four_tuple).is_none() {
+                    // the first packet of a plain RTP source tells its address
+                    server_states.latch_plain_transport(four_tuple, is_rtcp, &message)?;
+                }
+                let transport = server_states.get_mut_transport(&four_tuple)?;
+                let is_plain = transport.is_plain();
+
+                if is_rtcp {
+                    let mut remote_context = transport.remote_srtp_context();
+                    if let Some(context) = remote_context.as_mut() {
+                        let mut decrypted = context.decrypt_rtcp(&message)?;
+                        server_states.capture_packet(msg.now, &msg.transport, false, &decrypted);
+                        let rtcp_packets = rtcp::packet::unmarshal(&mut decrypted)?;
+                        if rtcp_packets.is_empty() {
+                            return Err(Error::Other("empty rtcp_packets".to_string()));
+                        }
+                        Ok(MessageEvent::Rtp(RTPMessageEvent::Rtcp(rtcp_packets)))
+                    } else if is_plain {
+                        server_states.capture_packet(msg.now, &msg.transport, false, &message);
+                        let rtcp_packets = rtcp::packet::unmarshal(&mut message.freeze())?;
+                        if rtcp_packets.is_empty() {
+                            return Err(Error::Other("empty rtcp_packets".to_string()));
+                        }
+                        Ok(MessageEvent::Rtp(RTPMessageEvent::Rtcp(rtcp_packets)))
+                    } else {
+                        Err(Error::Other(format!(
+                            "remote_srtp_context is not set yet for four_tuple {:?}",
+                            four_tuple
+                        )))
+                    }
+                } else {
+                    let mut remote_context = transport.remote_srtp_context();
+                    if let Some(context) = remote_context.as_mut() {
+                        let mut decrypted = context.decrypt_rtp(&message)?;
+                        server_states.capture_packet(msg.now, &msg.transport, false, &decrypted);
+                        let rtp_packet = rtp::Packet::unmarshal(&mut decrypted)?;
+                        Ok(MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet)))
+                    } else if is_plain {
+                        server_states.capture_packet(msg.now, &msg.transport, false, &message);
+                        let rtp_packet = rtp::Packet::unmarshal(&mut message.freeze())?;
+                        Ok(MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet)))
+                    } else {
+                        Err(Error::Other(format!(
+                            "remote_srtp_context is not set yet for four_tuple {:?}",
+                            four_tuple
+                        )))
+                    }
+                }
+            };
+
+            match try_read() {
+                Ok(message) => {
+                    msg.message = message;
+                    ctx.fire_read(msg);
+                }
+                Err(err) => {
+                    error!("try_read got error {}", err);
+                    ctx.fire_exception(Box::new(err))
+                }
+            };
+        } else {
+            debug!("bypass srtp read {:?}", msg.transport.peer_addr);
+            ctx.fire_read(msg);
+        }
+    }
+
+    fn poll_write(
+        &mut self,
+        ctx: &Context<Self::Rin, Self::Rout, Self::Win, Self::Wout>,
+    ) -> Option<Self::Wout> {
+        if let Some(mut msg) = ctx.fire_poll_write() {
+            if let MessageEvent::Rtp(message) = msg.message {
+                debug!("srtp write {:?}", msg.transport.peer_addr);
+                let try_write = || -> Result<BytesMut> {
+                    let four_tuple = (&msg.transport).into();
+                    let mut server_states = self.server_states.borrow_mut();
+
+                    match message {
+                        RTPMessageEvent::Rtcp(rtcp_packets) => {
+                            if rtcp_packets.is_empty() {
+                                return Err(Error::Other("empty rtcp_packets".to_string()));
+                            };
+
+                            let transport = server_states.get_mut_transport(&four_tuple)?;
+                            let is_plain = transport.is_plain();
+                            let mut local_context = transport.local_srtp_context();
+                            if let Some(context) = local_context.as_mut() {
+                                let packet = rtcp::packet::marshal(&rtcp_packets)?;
+                                let encrypted = context.encrypt_rtcp(&packet)?;
+                                server_states.capture_packet(
+                                    msg.now,
+                                    &msg.transport,
+                                    true,
+                                    &packet,
+                                );
+                                Ok(encrypted)
+                            } else if is_plain {
+                                let packet = rtcp::packet::marshal(&rtcp_packets)?;
+                                server_states.capture_packet(
+                                    msg.now,
+                                    &msg.transport,
+                                    true,
+                                    &packet,
+                                );
+                                Ok(packet)
+                            } else {
+                                Err(Error::Other(format!(
+                                    "local_srtp_context is not set yet for four_tuple {:?}",
+                                    four_tuple
+                                )))
+                            }
+                        }
+                        RTPMessageEvent::Rtp(rtp_message) => {
+                            let transport = server_states.get_mut_transport(&four_tuple)?;
+                            let is_plain = transport.is_plain();
+                            let mut local_context = transport.local_srtp_context();
+                            if let Some(context) = local_context.as_mut() {
+                                let packet = rtp_message.marshal()?;
+                                let encrypted = context.encrypt_rtp(&packet)?;
+                                server_states.capture_packet(
+                                    msg.now,
+                                    &msg.transport,
+                                    true,
+                                    &packet,
+                                );
+                                Ok(encrypted)
+                            } else if is_plain {
+                                let packet = rtp_message.marshal()?;
+                                server_states.capture_packet(
+                                    msg.now,
+                                    &msg.transport,
+                                    true,
+                                    &packet,
+                                );
+                                Ok(packet)
+                            } else {
+                                Err(Error::Other(format!(
+                                    "local_srtp_context is not set yet for four_tuple {:?}",
+                                    four_tuple
+                                )))
+                            }
+                        }
+                        RTPMessageEvent::Raw(raw_packet) => {
+                            // Bypass, plain RTP egress has no transport to encrypt with
+                            debug!("Bypass srtp write {:?}", msg.transport.peer_addr);
+                            Ok(raw_packet)
+                        }
+                    }
+                };
+
+                match try_write() {
+                    Ok(encrypted) => {
+                        msg.message = MessageEvent::Rtp(RTPMessageEvent::Raw(encrypted));
+                        Some(msg)
+                    }
+                    Err(err) => {
+                        error!("try_write with error {}", err);
+                        ctx.fire_exception(Box::new(err));
+                        None
+                    }
+                }
+            } else {
+                // Bypass
+                debug!("Bypass srtp write {:?}", msg.transport.peer_addr);
+                Some(msg)
+            }
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,490 @@
+use bytes::{BufMut, BytesMut};
+
+pub(crate) const VIDEO_TRACK_ID: u32 = 1;
+pub(crate) const AUDIO_TRACK_ID: u32 = 2;
+pub(crate) const VIDEO_TIMESCALE: u32 = 90000;
+pub(crate) const AUDIO_TIMESCALE: u32 = 48000;
+/// Opus is always negotiated as stereo, mono packets being upmixed by decoders
+const AUDIO_CHANNELS: u16 = 2;
+
+const SAMPLE_FLAGS_SYNC: u32 = 0x0200_0000;
+const SAMPLE_FLAGS_NON_SYNC: u32 = 0x0101_0000;
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// AvcConfig is the H.264 configuration the init segment describes, from the parameter sets of
+/// a keyframe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AvcConfig {
+    pub(crate) sps: Vec<u8>,
+    pub(crate) pps: Vec<u8>,
+    pub(crate) width: u16,
+    pub(crate) height: u16,
+}
+
+/// Sample is a frame of a track, timed in the timescale of its track
+#[derive(Debug, Clone)]
+pub(crate) struct Sample {
+    pub(crate) decode_time: u64,
+    pub(crate) duration: u32,
+    /// AVC length prefixed NAL units for video, an Opus packet for audio
+    pub(crate) data: Vec<u8>,
+    pub(crate) is_sync: bool,
+}
+
+/// init_segment returns the ftyp and moov boxes of an H.264 track and an Opus track, whose
+/// samples are all carried by fragments
+pub(crate) fn init_segment(avc_config: &AvcConfig) -> BytesMut {
+    let mut buf = BytesMut::new();
+    write_box(&mut buf, b"ftyp", |buf| {
+        buf.put_slice(b"iso6");
+        buf.put_u32(0);
+        for brand in [b"iso6", b"cmfc", b"isom", b"avc1", b"mp41"] {
+            buf.put_slice(brand);
+        }
+    });
+    write_box(&mut buf, b"moov", |buf| {
+        write_full_box(buf, b"mvhd", 0, 0, |buf| {
+            buf.put_u32(0); // creation_time
+            buf.put_u32(0); // modification_time
+            buf.put_u32(1000);
+            buf.put_u32(0); // duration
+            buf.put_u32(0x0001_0000); // rate
+            buf.put_u16(0x0100); // volume
+            buf.put_bytes(0, 10);
+            put_matrix(buf);
+            buf.put_bytes(0, 24); // pre_defined
+            buf.put_u32(AUDIO_TRACK_ID + 1); // next_track_ID
+        });
+        write_track(buf, VIDEO_TRACK_ID, VIDEO_TIMESCALE, Some(avc_config));
+        write_track(buf, AUDIO_TRACK_ID, AUDIO_TIMESCALE, None);
+        write_box(buf, b"mvex", |buf| {
+            for track_id in [VIDEO_TRACK_ID, AUDIO_TRACK_ID] {
+                write_full_box(buf, b"trex", 0, 0, |buf| {
+                    buf.put_u32(track_id);
+                    buf.put_u32(1); // default_sample_description_index
+                    buf.put_u32(0); // default_sample_duration
+                    buf.put_u32(0); // default_sample_size
+                    buf.put_u32(0); // default_sample_flags
+                });
+            }
+        });
+    });
+    buf
+}
+
+/// fragment returns the moof and mdat boxes of the samples of both tracks, either of them
+/// possibly empty
+pub(crate) fn fragment(
+    sequence_number: u32,
+    video_samples: &[Sample],
+    audio_samples: &[Sample],
+) -> BytesMut {
+    let tracks: Vec<(u32, &[Sample])> = [
+        (VIDEO_TRACK_ID, video_samples),
+        (AUDIO_TRACK_ID, audio_samples),
+    ]
+    .into_iter()
+    .filter(|(_, samples)| !samples.is_empty())
+    .collect();
+
+    let mut buf = BytesMut::new();
+    // the data offsets of the runs are known once the size of the moof box is
+    let mut data_offset_positions = vec![];
+    write_box(&mut buf, b"moof", |buf| {
+        write_full_box(buf, b"mfhd", 0, 0, |buf| buf.put_u32(sequence_number));
+        for (track_id, samples) in tracks.iter() {
+            write_box(buf, b"traf", |buf| {
+                // default-base-is-moof
+                write_full_box(buf, b"tfhd", 0, 0x02_0000, |buf| buf.put_u32(*track_id));
+                write_full_box(buf, b"tfdt", 1, 0, |buf| {
+                    buf.put_u64(samples[0].decode_time)
+                });
+                // data-offset, sample-duration, sample-size and sample-flags present
+                write_full_box(buf, b"trun", 0, 0x00_0701, |buf| {
+                    buf.put_u32(samples.len() as u32);
+                    data_offset_positions.push(buf.len());
+                    buf.put_i32(0);
+                    for sample in samples.iter() {
+                        buf.put_u32(sample.duration);
+                        buf.put_u32(sample.data.len() as u32);
+                        buf.put_u32(if sample.is_sync {
+                            SAMPLE_FLAGS_SYNC
+                        } else {
+                            SAMPLE_FLAGS_NON_SYNC
+                        });
+                    }
+                });
+            });
+        }
+    });
+
+    let mut data_offset = buf.len() + 8;
+    for ((_, samples), position) in tracks.iter().zip(data_offset_positions) {
+        buf[position..position + 4].copy_from_slice(&(data_offset as i32).to_be_bytes());
+        data_offset += samples
+            .iter()
+            .map(|sample| sample.data.len())
+            .sum::<usize>();
+    }
+    write_box(&mut buf, b"mdat", |buf| {
+        for (_, samples) in tracks.iter() {
+            for sample in samples.iter() {
+                buf.put_slice(&sample.data);
+            }
+        }
+    });
+    buf
+}
+
+/// write_track writes the trak box of the video track when given its configuration, or of the
+/// audio track otherwise
+fn write_track(buf: &mut BytesMut, track_id: u32, timescale: u32, avc_config: Option<&AvcConfig>) {
+    write_box(buf, b"trak", |buf| {
+        // track enabled and in movie
+        write_full_box(buf, b"tkhd", 0, 0x03, |buf| {
+            buf.put_u32(0); // creation_time
+            buf.put_u32(0); // modification_time
+            buf.put_u32(track_id);
+            buf.put_u32(0);
+            buf.put_u32(0); // duration
+            buf.put_bytes(0, 8);
+            buf.put_u16(0); // layer
+            buf.put_u16(0); // alternate_group
+            buf.put_u16(if avc_config.is_some() { 0 } else { 0x0100 }); // volume
+            buf.put_u16(0);
+            put_matrix(buf);
+            let (width, height) = avc_config.map_or((0, 0), |config| (config.width, config.height));
+            buf.put_u32((width as u32) << 16);
+            buf.put_u32((height as u32) << 16);
+        });
+        write_box(buf, b"mdia", |buf| {
+            write_full_box(buf, b"mdhd", 0, 0, |buf| {
+                buf.put_u32(0); // creation_time
+                buf.put_u32(0); // modification_time
+                buf.put_u32(timescale);
+                buf.put_u32(0); // duration
+                buf.put_u16(0x55C4); // und
+                buf.put_u16(0);
+            });
+            write_full_box(buf, b"hdlr", 0, 0, |buf| {
+                buf.put_u32(0);
+                buf.put_slice(if avc_config.is_some() {
+                    b"vide"
+                } else {
+                    b"soun"
+                });
+                buf.put_bytes(0, 12);
+                buf.put_slice(if avc_config.is_some() {
+                    b"VideoHandler\0".as_slice()
+                } else {
+                    b"SoundHandler\0".as_slice()
+                });
+            });
+            write_box(buf, b"minf", |buf| {
+                match avc_config {
+                    Some(_) => write_full_box(buf, b"vmhd", 0, 0x01, |buf| buf.put_bytes(0, 8)),
+                    None => write_full_box(buf, b"smhd", 0, 0, |buf| buf.put_u32(0)),
+                }
+                write_box(buf, b"dinf", |buf| {
+                    write_full_box(buf, b"dref", 0, 0, |buf| {
+                        buf.put_u32(1);
+                        // media data in the same file
+                        write_full_box(buf, b"url ", 0, 0x01, |_| {});
+                    });
+                });
+                write_box(buf, b"stbl", |buf| {
+                    write_full_box(buf, b"stsd", 0, 0, |buf| {
+                        buf.put_u32(1);
+                        match avc_config {
+                            Some(avc_config) => write_avc1(buf, avc_config),
+                            None => write_opus(buf),
+                        }
+                    });
+                    write_full_box(buf, b"stts", 0, 0, |buf| buf.put_u32(0));
+                    write_full_box(buf, b"stsc", 0, 0, |buf| buf.put_u32(0));
+                    write_full_box(buf, b"stsz", 0, 0, |buf| {
+                        buf.put_u32(0);
+                        buf.put_u32(0);
+                    });
+                    write_full_box(buf, b"stco", 0, 0, |buf| buf.put_u32(0));
+                });
+            });
+        });
+    });
+}
+
+fn write_avc1(buf: &mut BytesMut, avc_config: &AvcConfig) {
+    write_box(buf, b"avc1", |buf| {
+        buf.put_bytes(0, 6);
+        buf.put_u16(1); // data_reference_index
+        buf.put_bytes(0, 16);
+        buf.put_u16(avc_config.width);
+        buf.put_u16(avc_config.height);
+        buf.put_u32(0x0048_0000); // 72 dpi
+        buf.put_u32(0x0048_0000);
+        buf.put_u32(0);
+        buf.put_u16(1); // frame_count
+        buf.put_bytes(0, 32); // compressorname
+        buf.put_u16(0x0018); // depth
+        buf.put_i16(-1);
+        write_box(buf, b"avcC", |buf| {
+            buf.put_u8(1); // configurationVersion
+            buf.put_u8(avc_config.sps.get(1).copied().unwrap_or_default());
+            buf.put_u8(avc_config.sps.get(2).copied().unwrap_or_default());
+            buf.put_u8(avc_config.sps.get(3).copied().unwrap_or_default());
+            buf.put_u8(0xFF); // four bytes NAL unit lengths
+            buf.put_u8(0xE1); // one sequence parameter set
+            buf.put_u16(avc_config.sps.len() as u16);
+            buf.put_slice(&avc_config.sps);
+            buf.put_u8(1);
+            buf.put_u16(avc_config.pps.len() as u16);
+            buf.put_slice(&avc_config.pps);
+        });
+    });
+}
+
+/// write_opus writes the Opus sample entry, as specified for the ISO base media file format
+fn write_opus(buf: &mut BytesMut) {
+    write_box(buf, b"Opus", |buf| {
+        buf.put_bytes(0, 6);
+        buf.put_u16(1); // data_reference_index
+        buf.put_bytes(0, 8);
+        buf.put_u16(AUDIO_CHANNELS);
+        buf.put_u16(16); // samplesize
+        buf.put_u32(0);
+        buf.put_u32(AUDIO_TIMESCALE << 16);
+        write_box(buf, b"dOps", |buf| {
+            buf.put_u8(0); // Version
+            buf.put_u8(AUDIO_CHANNELS as u8);
+            buf.put_u16(0); // PreSkip
+            buf.put_u32(AUDIO_TIMESCALE); // InputSampleRate
+            buf.put_i16(0); // OutputGain
+            buf.put_u8(0); // ChannelMappingFamily
+        });
+    });
+}
+
+fn put_matrix(buf: &mut BytesMut) {
+    for value in UNITY_MATRIX {
+        buf.put_u32(value);
+    }
+}
+
+fn write_box(buf: &mut BytesMut, box_type: &[u8; 4], write_payload: impl FnOnce(&mut BytesMut)) {
+    let start = buf.len();
+    buf.put_u32(0);
+    buf.put_slice(box_type);
+    write_payload(buf);
+    let size = (buf.len() - start) as u32;
+    buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box(
+    buf: &mut BytesMut,
+    box_type: &[u8; 4],
+    version: u8,
+    flags: u32,
+    write_payload: impl FnOnce(&mut BytesMut),
+) {
+    write_box(buf, box_type, |buf| {
+        buf.put_u32((version as u32) << 24 | (flags & 0x00FF_FFFF));
+        write_payload(buf);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// boxes splits data into its boxes, checking that their sizes add up
+    fn boxes(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut boxes = vec![];
+        while !data.is_empty() {
+            let size = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+            assert!(size >= 8 && size <= data.len());
+            boxes.push((&data[4..8], &data[8..size]));
+            data = &data[size..];
+        }
+        boxes
+    }
+
+    /// find returns the payload of the first box at the path, full boxes and sample entries
+    /// being skipped past their header with the number of bytes following their type
+    fn find<'a>(data: &'a [u8], path: &[(&[u8], usize)]) -> &'a [u8] {
+        path.iter().fold(data, |data, (box_type, header_len)| {
+            let (_, payload) = boxes(data)
+                .into_iter()
+                .find(|(found_type, _)| found_type == box_type)
+                .unwrap_or_else(|| panic!("no {} box", String::from_utf8_lossy(box_type)));
+            &payload[*header_len..]
+        })
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn avc_config() -> AvcConfig {
+        AvcConfig {
+            sps: vec![0x67, 0x42, 0xC0, 0x1E, 0xDA],
+            pps: vec![0x68, 0xCE, 0x3C, 0x80],
+            width: 640,
+            height: 360,
+        }
+    }
+
+    fn sample(decode_time: u64, duration: u32, data: &[u8], is_sync: bool) -> Sample {
+        Sample {
+            decode_time,
+            duration,
+            data: data.to_vec(),
+            is_sync,
+        }
+    }
+
+    #[test]
+    fn writes_the_init_segment() {
+        let init = init_segment(&avc_config());
+        let box_types: Vec<&[u8]> = boxes(&init).into_iter().map(|(t, _)| t).collect();
+        assert_eq!(box_types, vec![b"ftyp", b"moov"]);
+        let moov = find(&init, &[(b"moov", 0)]);
+        let box_types: Vec<&[u8]> = boxes(moov).into_iter().map(|(t, _)| t).collect();
+        assert_eq!(box_types, vec![b"mvhd", b"trak", b"trak", b"mvex"]);
+
+        let traks: Vec<&[u8]> = boxes(moov)
+            .into_iter()
+            .filter(|(box_type, _)| box_type == b"trak")
+            .map(|(_, payload)| payload)
+            .collect();
+        let video_tkhd = find(traks[0], &[(b"tkhd", 4)]);
+        assert_eq!(read_u32(video_tkhd, 8), VIDEO_TRACK_ID);
+        assert_eq!(read_u32(video_tkhd, 72), 640 << 16);
+        assert_eq!(read_u32(video_tkhd, 76), 360 << 16);
+        let video_mdhd = find(traks[0], &[(b"mdia", 0), (b"mdhd", 4)]);
+        assert_eq!(read_u32(video_mdhd, 8), VIDEO_TIMESCALE);
+        let video_hdlr = find(traks[0], &[(b"mdia", 0), (b"hdlr", 4)]);
+        assert_eq!(&video_hdlr[4..8], b"vide");
+        let avcc = find(
+            traks[0],
+            &[
+                (b"mdia", 0),
+                (b"minf", 0),
+                (b"stbl", 0),
+                (b"stsd", 8),
+                (b"avc1", 78),
+                (b"avcC", 0),
+            ],
+        );
+        assert_eq!(
+            avcc,
+            [
+                &[1, 0x42, 0xC0, 0x1E, 0xFF, 0xE1, 0, 5][..],
+                &avc_config().sps,
+                &[1, 0, 4],
+                &avc_config().pps,
+            ]
+            .concat()
+        );
+
+        let audio_tkhd = find(traks[1], &[(b"tkhd", 4)]);
+        assert_eq!(read_u32(audio_tkhd, 8), AUDIO_TRACK_ID);
+        let audio_mdhd = find(traks[1], &[(b"mdia", 0), (b"mdhd", 4)]);
+        assert_eq!(read_u32(audio_mdhd, 8), AUDIO_TIMESCALE);
+        let dops = find(
+            traks[1],
+            &[
+                (b"mdia", 0),
+                (b"minf", 0),
+                (b"stbl", 0),
+                (b"stsd", 8),
+                (b"Opus", 28),
+                (b"dOps", 0),
+            ],
+        );
+        assert_eq!(dops, [0, 2, 0, 0, 0, 0, 0xBB, 0x80, 0, 0, 0]);
+
+        let trexs = boxes(find(moov, &[(b"mvex", 0)]));
+        assert_eq!(trexs.len(), 2);
+        assert_eq!(read_u32(trexs[1].1, 4), AUDIO_TRACK_ID);
+    }
+
+    #[test]
+    fn writes_fragments_pointing_at_their_samples() {
+        let video_samples = [
+            sample(9000, 3000, &[0, 0, 0, 2, 0x65, 0x88], true),
+            sample(12000, 3000, &[0, 0, 0, 1, 0x41], false),
+        ];
+        let audio_samples = [sample(4800, 960, &[0xFC, 0x01, 0x02], true)];
+        let fragment = fragment(7, &video_samples, &audio_samples);
+
+        let box_types: Vec<&[u8]> = boxes(&fragment).into_iter().map(|(t, _)| t).collect();
+        assert_eq!(box_types, vec![b"moof", b"mdat"]);
+        let moof = find(&fragment, &[(b"moof", 0)]);
+        assert_eq!(read_u32(find(moof, &[(b"mfhd", 4)]), 0), 7);
+        let trafs: Vec<&[u8]> = boxes(moof)
+            .into_iter()
+            .filter(|(box_type, _)| box_type == b"traf")
+            .map(|(_, payload)| payload)
+            .collect();
+        assert_eq!(trafs.len(), 2);
+
+        let expected = [
+            (VIDEO_TRACK_ID, &video_samples[..]),
+            (AUDIO_TRACK_ID, &audio_samples[..]),
+        ];
+        for (traf, (track_id, samples)) in trafs.into_iter().zip(expected) {
+            let tfhd = find(traf, &[(b"tfhd", 0)]);
+            assert_eq!(read_u32(tfhd, 0), 0x02_0000);
+            assert_eq!(read_u32(tfhd, 4), track_id);
+            let tfdt = find(traf, &[(b"tfdt", 0)]);
+            assert_eq!(tfdt[0], 1);
+            assert_eq!(
+                u64::from_be_bytes(tfdt[4..12].try_into().unwrap()),
+                samples[0].decode_time
+            );
+
+            let trun = find(traf, &[(b"trun", 4)]);
+            assert_eq!(read_u32(trun, 0), samples.len() as u32);
+            // the data offset is relative to the start of the moof box
+            let mut data_offset = read_u32(trun, 4) as usize;
+            for (i, sample) in samples.iter().enumerate() {
+                let entry = &trun[8 + 12 * i..];
+                assert_eq!(read_u32(entry, 0), sample.duration);
+                assert_eq!(read_u32(entry, 4), sample.data.len() as u32);
+                let flags = if sample.is_sync {
+                    SAMPLE_FLAGS_SYNC
+                } else {
+                    SAMPLE_FLAGS_NON_SYNC
+                };
+                assert_eq!(read_u32(entry, 8), flags);
+                assert_eq!(
+                    &fragment[data_offset..data_offset + sample.data.len()],
+                    &sample.data[..]
+                );
+                data_offset += sample.data.len();
+            }
+        }
+        assert_eq!(
+            find(&fragment, &[(b"mdat", 0)]),
+            [0, 0, 0, 2, 0x65, 0x88, 0, 0, 0, 1, 0x41, 0xFC, 0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn writes_fragments_of_a_single_track() {
+        let audio_samples = [sample(0, 960, &[0xFC], true)];
+        let fragment = fragment(1, &[], &audio_samples);
+        let moof = find(&fragment, &[(b"moof", 0)]);
+        let trafs: Vec<_> = boxes(moof)
+            .into_iter()
+            .filter(|(box_type, _)| box_type == b"traf")
+            .collect();
+        assert_eq!(trafs.len(), 1);
+        assert_eq!(
+            read_u32(find(trafs[0].1, &[(b"tfhd", 4)]), 0),
+            AUDIO_TRACK_ID
+        );
+        let data_offset = read_u32(find(trafs[0].1, &[(b"trun", 8)]), 0) as usize;
+        assert_eq!(fragment[data_offset], 0xFC);
+    }
+}
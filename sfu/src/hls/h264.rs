@@ -0,0 +1,185 @@
+use shared::error::{Error, Result};
+
+pub(crate) const NAL_UNIT_TYPE_SPS: u8 = 7;
+pub(crate) const NAL_UNIT_TYPE_PPS: u8 = 8;
+pub(crate) const NAL_UNIT_TYPE_AUD: u8 = 9;
+
+/// split_annex_b returns the NAL units of an Annex B access unit, without their start codes
+pub(crate) fn split_annex_b(access_unit: &[u8]) -> Vec<&[u8]> {
+    let mut nal_units = vec![];
+    let mut start = None;
+    let mut i = 0;
+    while i + 3 <= access_unit.len() {
+        if access_unit[i] == 0 && access_unit[i + 1] == 0 && access_unit[i + 2] == 1 {
+            if let Some(start) = start {
+                // a four bytes start code leaves a zero behind the previous unit
+                let mut end = i;
+                while end > start && access_unit[end - 1] == 0 {
+                    end -= 1;
+                }
+                nal_units.push(&access_unit[start..end]);
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(start) = start {
+        if start < access_unit.len() {
+            nal_units.push(&access_unit[start..]);
+        }
+    }
+    nal_units.retain(|nal_unit| !nal_unit.is_empty());
+    nal_units
+}
+
+pub(crate) fn nal_unit_type(nal_unit: &[u8]) -> u8 {
+    nal_unit.first().map_or(0, |header| header & 0x1F)
+}
+
+/// sps_dimensions returns the width and height in pixels of the pictures of a sequence parameter
+/// set, after cropping
+pub(crate) fn sps_dimensions(sps: &[u8]) -> Result<(u16, u16)> {
+    // the header byte is followed by the payload, with emulation prevention bytes removed
+    let mut rbsp = Vec::with_capacity(sps.len());
+    let mut zeros = 0;
+    for &byte in sps.iter().skip(1) {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    let mut reader = BitReader::new(&rbsp);
+
+    let profile_idc = reader.read_bits(8)?;
+    reader.read_bits(16)?; // constraint flags and level_idc
+    reader.read_ue()?; // seq_parameter_set_id
+    let mut chroma_format_idc = 1;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = reader.read_ue()?;
+        if chroma_format_idc == 3 {
+            reader.read_bits(1)?; // separate_colour_plane_flag
+        }
+        reader.read_ue()?; // bit_depth_luma_minus8
+        reader.read_ue()?; // bit_depth_chroma_minus8
+        reader.read_bits(1)?; // qpprime_y_zero_transform_bypass_flag
+        if reader.read_bits(1)? == 1 {
+            let scaling_lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..scaling_lists {
+                if reader.read_bits(1)? == 1 {
+                    reader.skip_scaling_list(if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+    reader.read_ue()?; // log2_max_frame_num_minus4
+    match reader.read_ue()? {
+        0 => {
+            reader.read_ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            reader.read_bits(1)?; // delta_pic_order_always_zero_flag
+            reader.read_se()?; // offset_for_non_ref_pic
+            reader.read_se()?; // offset_for_top_to_bottom_field
+            for _ in 0..reader.read_ue()? {
+                reader.read_se()?; // offset_for_ref_frame
+            }
+        }
+        _ => {}
+    }
+    reader.read_ue()?; // max_num_ref_frames
+    reader.read_bits(1)?; // gaps_in_frame_num_value_allowed_flag
+    let width_in_mbs = reader.read_ue()? + 1;
+    let height_in_map_units = reader.read_ue()? + 1;
+    let frame_mbs_only = reader.read_bits(1)?;
+    if frame_mbs_only == 0 {
+        reader.read_bits(1)?; // mb_adaptive_frame_field_flag
+    }
+    reader.read_bits(1)?; // direct_8x8_inference_flag
+    let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+    if reader.read_bits(1)? == 1 {
+        crop_left = reader.read_ue()?;
+        crop_right = reader.read_ue()?;
+        crop_top = reader.read_ue()?;
+        crop_bottom = reader.read_ue()?;
+    }
+
+    let (crop_unit_x, crop_unit_y) = match chroma_format_idc {
+        0 | 3 => (1, 2 - frame_mbs_only),
+        2 => (2, 2 - frame_mbs_only),
+        _ => (2, 2 * (2 - frame_mbs_only)),
+    };
+    let width = (width_in_mbs * 16).saturating_sub(crop_unit_x * (crop_left + crop_right));
+    let height = ((2 - frame_mbs_only) * height_in_map_units * 16)
+        .saturating_sub(crop_unit_y * (crop_top + crop_bottom));
+    Ok((
+        u16::try_from(width).map_err(|_| Error::Other("sps width out of range".to_string()))?,
+        u16::try_from(height).map_err(|_| Error::Other("sps height out of range".to_string()))?,
+    ))
+}
+
+/// BitReader reads the exp-Golomb coded fields of a parameter set
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read_bits(&mut self, count: usize) -> Result<u32> {
+        let mut value = 0u32;
+        for _ in 0..count {
+            let byte = self
+                .data
+                .get(self.position / 8)
+                .ok_or(Error::Other("truncated sps".to_string()))?;
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn read_ue(&mut self) -> Result<u32> {
+        let mut leading_zeros = 0;
+        while self.read_bits(1)? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(Error::Other("invalid exp-golomb code in sps".to_string()));
+            }
+        }
+        Ok((1u32 << leading_zeros) - 1 + self.read_bits(leading_zeros)?)
+    }
+
+    fn read_se(&mut self) -> Result<i32> {
+        let code = self.read_ue()?;
+        Ok(if code % 2 == 1 {
+            code.div_ceil(2) as i32
+        } else {
+            -((code / 2) as i32)
+        })
+    }
+
+    fn skip_scaling_list(&mut self, size: usize) -> Result<()> {
+        let (mut last_scale, mut next_scale) = (8i32, 8i32);
+        for _ in 0..size {
+            if next_scale != 0 {
+                let delta_scale = self.read_se()?;
+                next_scale = (last_scale + delta_scale + 256) % 256;
+            }
+            if next_scale != 0 {
+                last_scale = next_scale;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,764 @@
+pub(crate) mod fmp4;
+pub(crate) mod h264;
+pub(crate) mod playlist;
+
+use crate::description::config::{MIME_TYPE_H264, MIME_TYPE_OPUS};
+use crate::description::rtp_codec::RTPCodecType;
+use crate::description::rtp_transceiver::RTCRtpTransceiver;
+use crate::endpoint::IncomingStream;
+use crate::hls::fmp4::{AvcConfig, Sample, AUDIO_TIMESCALE, VIDEO_TIMESCALE};
+use crate::hls::playlist::{
+    init_file_name, part_file_name, segment_file_name, MediaPlaylist, Part, Segment, PART_TARGET,
+    PLAYLIST_FILE_NAME, TARGET_DURATION,
+};
+use crate::recording::jitter_buffer::{JitterBuffer, OrderedPacket};
+use crate::recording::writer::opus_samples;
+use crate::simulcast::{keyframe, LayerPreference, SimulcastTrack};
+use crate::types::{EndpointId, Mid, SessionId};
+use bytes::BytesMut;
+use log::{debug, trace, warn};
+use rtp::codecs::{h264::H264Packet, opus::OpusPacket};
+use rtp::packetizer::Depacketizer;
+use serde::{Deserialize, Serialize};
+use shared::error::Result;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// shortest segment, the next one starting at the first keyframe after it
+const MIN_SEGMENT_DURATION: f64 = 2.0;
+/// longest segment, cut without a keyframe if none comes, so that it rounds to the target
+const MAX_SEGMENT_DURATION: f64 = TARGET_DURATION as f64 + 0.4;
+/// how long the video may stay silent before the audio alone paces the segments
+const VIDEO_TIMEOUT: Duration = Duration::from_secs(1);
+/// duration of a frame when no next frame tells it
+const DEFAULT_VIDEO_FRAME_DURATION: u32 = VIDEO_TIMESCALE / 30;
+
+/// HlsRequest streams the media of a session over HLS, from the tracks of a single endpoint at
+/// a time
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct HlsRequest {
+    /// endpoint streamed, the dominant speaker of the session when unset
+    #[serde(default)]
+    pub endpoint_id: Option<EndpointId>,
+}
+
+/// HlsSummary describes an HLS stream and where its playlist is served from
+#[derive(Debug, Clone, Serialize)]
+pub struct HlsSummary {
+    pub session_id: SessionId,
+    pub endpoint_id: Option<EndpointId>,
+    /// path of the media playlist on the web server
+    pub playlist: String,
+    pub directory: PathBuf,
+    /// unix time in milliseconds
+    pub started_at: u64,
+    /// unix time in milliseconds, 0 while running
+    pub stopped_at: u64,
+    /// endpoint the video is currently taken from
+    pub video_endpoint_id: Option<EndpointId>,
+    pub segments: u64,
+}
+
+type StreamKey = (EndpointId, Mid, Option<String>);
+
+/// HlsPackager packages the H.264 video and Opus audio of an endpoint into fragmented MP4
+/// segments and partial segments, listed by a low latency HLS playlist in a directory of the
+/// session, which the web server serves to any number of viewers.
+///
+/// When following the dominant speaker, audio switches right away, while video keeps showing
+/// the previous speaker until the new one sends a keyframe. Timestamps stay continuous across
+/// switches, a new init segment behind a discontinuity being written when the video
+/// configuration changes.
+pub(crate) struct HlsPackager {
+    session_id: SessionId,
+    request: HlsRequest,
+    directory: PathBuf,
+    started_at: SystemTime,
+    started_instant: Instant,
+
+    video: PackagedTrack,
+    audio: PackagedTrack,
+    /// video stream switched to once it sends a keyframe
+    pending_video: Option<(StreamKey, FrameAssembler)>,
+    last_video_at: Option<Instant>,
+
+    avc_config: Option<AvcConfig>,
+    init: u32,
+    playlist: MediaPlaylist,
+    /// partial segments of the segment being packaged
+    segment_data: BytesMut,
+    /// 90 kHz times the segment and the partial segment being packaged start at
+    segment_start: u64,
+    part_start: u64,
+    fragment_sequence_number: u32,
+    is_keyframe_needed: bool,
+}
+
+impl HlsPackager {
+    /// new creates the directory of the session in the HLS directory, removing the files of a
+    /// previous stream
+    pub(crate) fn new(
+        session_id: SessionId,
+        hls_dir: &Path,
+        request: HlsRequest,
+        now: Instant,
+    ) -> Result<Self> {
+        let directory = hls_dir.join(session_id.to_string());
+        if directory.exists() {
+            std::fs::remove_dir_all(&directory)?;
+        }
+        std::fs::create_dir_all(&directory)?;
+        debug!("{}: start hls in {:?}", session_id, directory);
+
+        Ok(Self {
+            session_id,
+            request,
+            directory,
+            started_at: SystemTime::now(),
+            started_instant: now,
+
+            video: PackagedTrack::new(VIDEO_TIMESCALE),
+            audio: PackagedTrack::new(AUDIO_TIMESCALE),
+            pending_video: None,
+            last_video_at: None,
+
+            avc_config: None,
+            init: 0,
+            playlist: MediaPlaylist::new(),
+            segment_data: BytesMut::new(),
+            segment_start: 0,
+            part_start: 0,
+            fragment_sequence_number: 1,
+            is_keyframe_needed: false,
+        })
+    }
+
+    pub(crate) fn get_playlist_path(&self) -> String {
+        format!("/hls/{}/{}", self.session_id, PLAYLIST_FILE_NAME)
+    }
+
+    /// on_rtp packages a packet of an incoming stream, if its endpoint is the one streamed, and
+    /// returns whether a keyframe has to be requested for it
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn on_rtp(
+        &mut self,
+        now: Instant,
+        endpoint_id: EndpointId,
+        dominant_speaker: Option<EndpointId>,
+        transceiver: &RTCRtpTransceiver,
+        simulcast_track: Option<&SimulcastTrack>,
+        incoming_stream: &IncomingStream,
+        rtp_packet: &rtp::packet::Packet,
+    ) -> Result<bool> {
+        if incoming_stream.is_repair {
+            return Ok(false);
+        }
+        let streamed_endpoint_id = self
+            .request
+            .endpoint_id
+            .or(dominant_speaker)
+            .or(self.video.source_endpoint_id())
+            .or(self.audio.source_endpoint_id());
+        if streamed_endpoint_id.is_some_and(|streamed| streamed != endpoint_id) {
+            return Ok(false);
+        }
+        let Some(codec) = transceiver
+            .rtp_params
+            .codecs
+            .iter()
+            .find(|codec| codec.payload_type == rtp_packet.header.payload_type)
+        else {
+            return Ok(false);
+        };
+        let mime_type = codec.capability.mime_type.as_str();
+
+        match transceiver.kind {
+            RTPCodecType::Audio if mime_type.eq_ignore_ascii_case(MIME_TYPE_OPUS) => {
+                let key = (endpoint_id, incoming_stream.mid.clone(), None);
+                match self.audio.source_endpoint_id() {
+                    // the first audio track of an endpoint is streamed
+                    Some(source_endpoint_id) if source_endpoint_id == endpoint_id => {
+                        if self.audio.source_key() != Some(&key) {
+                            return Ok(false);
+                        }
+                    }
+                    _ => {
+                        debug!(
+                            "{}: hls audio switches to endpoint {}",
+                            self.session_id, endpoint_id
+                        );
+                        self.audio.set_source(key, FrameAssembler::new(false));
+                    }
+                }
+                let frames = self.audio.push(now, rtp_packet.clone());
+                for frame in frames {
+                    self.on_audio_frame(frame)?;
+                }
+                Ok(false)
+            }
+            RTPCodecType::Video if mime_type.eq_ignore_ascii_case(MIME_TYPE_H264) => {
+                let rid = incoming_stream.rid.clone();
+                if transceiver.is_simulcast() {
+                    // the highest active layer is streamed
+                    let target_rid = simulcast_track.and_then(|simulcast_track| {
+                        simulcast_track.select(&LayerPreference::default(), None, now)
+                    });
+                    if rid.is_none() || target_rid != rid.as_deref() {
+                        return Ok(false);
+                    }
+                }
+                let key = (endpoint_id, incoming_stream.mid.clone(), rid);
+                if self.video.source_key() == Some(&key) {
+                    let frames = self.video.push(now, rtp_packet.clone());
+                    for frame in frames {
+                        self.on_video_frame(frame)?;
+                    }
+                    return Ok(self.video.is_waiting_keyframe() || self.is_keyframe_needed);
+                }
+
+                // another video track of the streamed endpoint, other than a layer switch
+                if let Some((source_endpoint_id, source_mid, _)) = self.video.source_key() {
+                    if *source_endpoint_id == endpoint_id && *source_mid != incoming_stream.mid {
+                        return Ok(false);
+                    }
+                }
+                if self
+                    .pending_video
+                    .as_ref()
+                    .map_or(true, |(pending_key, _)| *pending_key != key)
+                {
+                    self.pending_video = Some((key.clone(), FrameAssembler::new(true)));
+                }
+                let frames = match self.pending_video.as_mut() {
+                    Some((_, assembler)) => assembler.push(now, rtp_packet.clone()),
+                    None => vec![],
+                };
+                // the first frame of a video stream is a keyframe
+                if !frames.is_empty() {
+                    if let Some((key, assembler)) = self.pending_video.take() {
+                        debug!(
+                            "{}: hls video switches to endpoint {} mid {} rid {:?}",
+                            self.session_id, key.0, key.1, key.2
+                        );
+                        self.video.set_source(key, assembler);
+                    }
+                    for frame in frames {
+                        self.on_video_frame(frame)?;
+                    }
+                    return Ok(self.is_keyframe_needed);
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// remove_endpoint lets another endpoint be streamed once the streamed one leaves
+    pub(crate) fn remove_endpoint(&mut self, endpoint_id: &EndpointId) {
+        if self.video.source_endpoint_id() == Some(*endpoint_id) {
+            self.video.source = None;
+        }
+        if self.audio.source_endpoint_id() == Some(*endpoint_id) {
+            self.audio.source = None;
+        }
+        if self
+            .pending_video
+            .as_ref()
+            .is_some_and(|((pending_endpoint_id, _, _), _)| pending_endpoint_id == endpoint_id)
+        {
+            self.pending_video = None;
+        }
+    }
+
+    /// stop packages the frames received so far into a last segment, and ends the playlist
+    pub(crate) fn stop(mut self) -> HlsSummary {
+        if self.avc_config.is_some() {
+            let video_end = self.video.complete_last(DEFAULT_VIDEO_FRAME_DURATION);
+            let audio_duration = self
+                .audio
+                .held
+                .as_ref()
+                .map_or(0, |sample| opus_samples(&sample.data));
+            let audio_end = self.audio.complete_last(audio_duration);
+            let time = [video_end, audio_end.map(audio_to_video_time)]
+                .into_iter()
+                .flatten()
+                .max()
+                .unwrap_or(self.part_start);
+            if let Err(err) = self.complete_segment(time, self.init, false) {
+                warn!(
+                    "{}: can't complete last hls segment: {}",
+                    self.session_id, err
+                );
+            }
+        }
+        self.playlist.end();
+        if let Err(err) = self.write_playlist() {
+            warn!("{}: can't end hls playlist: {}", self.session_id, err);
+        }
+        debug!("{}: stop hls in {:?}", self.session_id, self.directory);
+
+        let mut summary = self.summary();
+        summary.stopped_at = unix_time_millis(SystemTime::now());
+        summary
+    }
+
+    pub(crate) fn summary(&self) -> HlsSummary {
+        HlsSummary {
+            session_id: self.session_id,
+            endpoint_id: self.request.endpoint_id,
+            playlist: self.get_playlist_path(),
+            directory: self.directory.clone(),
+            started_at: unix_time_millis(self.started_at),
+            stopped_at: 0,
+            video_endpoint_id: self.video.source_endpoint_id(),
+            segments: self.playlist.segment_count(),
+        }
+    }
+
+    fn on_video_frame(&mut self, frame: Frame) -> Result<()> {
+        let mut sps = None;
+        let mut pps = None;
+        let mut data = Vec::with_capacity(frame.data.len() + 16);
+        for nal_unit in h264::split_annex_b(&frame.data) {
+            match h264::nal_unit_type(nal_unit) {
+                // parameter sets are carried by the init segment
+                h264::NAL_UNIT_TYPE_SPS => sps = Some(nal_unit),
+                h264::NAL_UNIT_TYPE_PPS => pps = Some(nal_unit),
+                h264::NAL_UNIT_TYPE_AUD => {}
+                _ => {
+                    data.extend_from_slice(&(nal_unit.len() as u32).to_be_bytes());
+                    data.extend_from_slice(nal_unit);
+                }
+            }
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let mut avc_config = None;
+        if let (true, Some(sps), Some(pps)) = (frame.is_keyframe, sps, pps) {
+            match h264::sps_dimensions(sps) {
+                Ok((width, height)) => {
+                    avc_config = Some(AvcConfig {
+                        sps: sps.to_vec(),
+                        pps: pps.to_vec(),
+                        width,
+                        height,
+                    })
+                    .filter(|avc_config| self.avc_config.as_ref() != Some(avc_config))
+                }
+                Err(err) => trace!("{}: can't parse hls sps: {}", self.session_id, err),
+            }
+        }
+        if self.avc_config.is_none() && avc_config.is_none() {
+            self.is_keyframe_needed = true;
+            return Ok(());
+        }
+
+        let decode_time =
+            self.video
+                .decode_time(frame.timestamp, frame.received_at, self.started_instant);
+        let last_duration = self.video.complete_held(decode_time);
+        let time = decode_time;
+        let segment_duration = ticks_to_seconds(time.saturating_sub(self.segment_start));
+        let part_duration = ticks_to_seconds(time.saturating_sub(self.part_start));
+        let next_duration = ticks_to_seconds(last_duration as u64);
+
+        if let Some(avc_config) = avc_config {
+            if self.avc_config.is_some() {
+                // players reset their decoder on a discontinuity
+                self.write_init(self.init + 1, &avc_config)?;
+                self.complete_segment(time, self.init + 1, true)?;
+                self.init += 1;
+            } else {
+                self.write_init(self.init, &avc_config)?;
+                self.segment_start = time;
+                self.part_start = time;
+                self.audio.clear();
+            }
+            debug!(
+                "{}: hls video is {}x{}",
+                self.session_id, avc_config.width, avc_config.height
+            );
+            self.avc_config = Some(avc_config);
+            self.is_keyframe_needed = false;
+        } else if frame.is_keyframe && segment_duration >= MIN_SEGMENT_DURATION {
+            self.complete_segment(time, self.init, false)?;
+            self.is_keyframe_needed = false;
+        } else if segment_duration + next_duration > MAX_SEGMENT_DURATION {
+            self.complete_segment(time, self.init, false)?;
+        } else if part_duration + next_duration > PART_TARGET {
+            self.complete_part(time)?;
+        }
+        if !frame.is_keyframe && segment_duration >= MIN_SEGMENT_DURATION {
+            self.is_keyframe_needed = true;
+        }
+
+        self.video.held = Some(Sample {
+            decode_time,
+            duration: 0,
+            data,
+            is_sync: frame.is_keyframe,
+        });
+        self.last_video_at = Some(frame.received_at);
+        Ok(())
+    }
+
+    fn on_audio_frame(&mut self, frame: Frame) -> Result<()> {
+        // the stream starts with the first video keyframe
+        if self.avc_config.is_none() || frame.data.is_empty() {
+            return Ok(());
+        }
+        let decode_time =
+            self.audio
+                .decode_time(frame.timestamp, frame.received_at, self.started_instant);
+        self.audio.complete_held(decode_time);
+
+        let is_video_silent = self.last_video_at.map_or(true, |last_video_at| {
+            frame.received_at.saturating_duration_since(last_video_at) > VIDEO_TIMEOUT
+        });
+        if is_video_silent {
+            let time = audio_to_video_time(decode_time);
+            let next_duration = opus_samples(&frame.data) as f64 / AUDIO_TIMESCALE as f64;
+            if ticks_to_seconds(time.saturating_sub(self.segment_start)) >= MIN_SEGMENT_DURATION {
+                self.complete_segment(time, self.init, false)?;
+            } else if ticks_to_seconds(time.saturating_sub(self.part_start)) + next_duration
+                > PART_TARGET
+            {
+                self.complete_part(time)?;
+            }
+        }
+
+        self.audio.held = Some(Sample {
+            decode_time,
+            duration: 0,
+            data: frame.data,
+            is_sync: true,
+        });
+        Ok(())
+    }
+
+    /// complete_part writes the samples before a 90 kHz time as a partial segment
+    fn complete_part(&mut self, time: u64) -> Result<()> {
+        let video_samples = std::mem::take(&mut self.video.samples);
+        let audio_count = self
+            .audio
+            .samples
+            .iter()
+            .take_while(|sample| audio_to_video_time(sample.decode_time) < time)
+            .count();
+        let audio_samples: Vec<Sample> = self.audio.samples.drain(..audio_count).collect();
+        if video_samples.is_empty() && audio_samples.is_empty() {
+            return Ok(());
+        }
+
+        let fragment = fmp4::fragment(
+            self.fragment_sequence_number,
+            &video_samples,
+            &audio_samples,
+        );
+        self.fragment_sequence_number = self.fragment_sequence_number.wrapping_add(1);
+        let current = self.playlist.current();
+        self.write_file(
+            &part_file_name(current.sequence_number, current.parts.len()),
+            &fragment,
+        )?;
+        self.segment_data.extend_from_slice(&fragment);
+        self.playlist.push_part(Part {
+            duration: ticks_to_seconds(time.saturating_sub(self.part_start)),
+            is_independent: video_samples.first().is_some_and(|sample| sample.is_sync),
+        });
+        self.part_start = time;
+        self.write_playlist()
+    }
+
+    /// complete_segment writes the samples before a 90 kHz time as the last partial segment of
+    /// the segment being packaged, then the segment itself
+    fn complete_segment(
+        &mut self,
+        time: u64,
+        next_init: u32,
+        is_discontinuity: bool,
+    ) -> Result<()> {
+        self.complete_part(time)?;
+        self.segment_start = time;
+        if !self.playlist.current().parts.is_empty() {
+            let segment_data = std::mem::take(&mut self.segment_data);
+            let sequence_number = self.playlist.current().sequence_number;
+            self.write_file(&segment_file_name(sequence_number), &segment_data)?;
+        }
+        let removed_segments = self.playlist.complete_segment(next_init, is_discontinuity);
+        self.write_playlist()?;
+        self.remove_segments(removed_segments);
+        Ok(())
+    }
+
+    /// remove_segments deletes the files of the segments no longer listed, and of the init
+    /// segments they used
+    fn remove_segments(&self, segments: Vec<Segment>) {
+        let oldest_init = self.playlist.oldest_init();
+        for segment in segments {
+            let mut file_names = vec![segment_file_name(segment.sequence_number)];
+            file_names.extend(
+                (0..segment.parts.len())
+                    .map(|part_index| part_file_name(segment.sequence_number, part_index)),
+            );
+            if segment.init < oldest_init {
+                file_names.push(init_file_name(segment.init));
+            }
+            for file_name in file_names {
+                if let Err(err) = std::fs::remove_file(self.directory.join(&file_name)) {
+                    trace!("{}: can't remove {}: {}", self.session_id, file_name, err);
+                }
+            }
+        }
+    }
+
+    fn write_init(&self, init: u32, avc_config: &AvcConfig) -> Result<()> {
+        self.write_file(&init_file_name(init), &fmp4::init_segment(avc_config))
+    }
+
+    fn write_playlist(&self) -> Result<()> {
+        self.write_file(PLAYLIST_FILE_NAME, self.playlist.render().as_bytes())
+    }
+
+    /// write_file replaces a file at once, so that viewers never read it partially written
+    fn write_file(&self, file_name: &str, data: &[u8]) -> Result<()> {
+        let temporary_file = self.directory.join(format!(".{}.tmp", file_name));
+        std::fs::write(&temporary_file, data)?;
+        std::fs::rename(&temporary_file, self.directory.join(file_name))?;
+        Ok(())
+    }
+}
+
+/// Frame is a frame assembled from the packets of a stream
+struct Frame {
+    data: Vec<u8>,
+    timestamp: u32,
+    received_at: Instant,
+    is_keyframe: bool,
+}
+
+/// PackagedTrack times the frames of the stream a track is taken from, and keeps the samples
+/// of the partial segment being packaged
+struct PackagedTrack {
+    timescale: u32,
+    source: Option<(StreamKey, FrameAssembler)>,
+    /// RTP timestamp and decode time of the last frame of the source, unset until its first
+    /// frame, which is timed from the wallclock
+    last_timestamp: Option<(u32, u64)>,
+    /// last sample, whose duration is known with the next one
+    held: Option<Sample>,
+    samples: Vec<Sample>,
+}
+
+impl PackagedTrack {
+    fn new(timescale: u32) -> Self {
+        Self {
+            timescale,
+            source: None,
+            last_timestamp: None,
+            held: None,
+            samples: vec![],
+        }
+    }
+
+    fn source_key(&self) -> Option<&StreamKey> {
+        self.source.as_ref().map(|(key, _)| key)
+    }
+
+    fn source_endpoint_id(&self) -> Option<EndpointId> {
+        self.source_key().map(|(endpoint_id, _, _)| *endpoint_id)
+    }
+
+    fn is_waiting_keyframe(&self) -> bool {
+        self.source
+            .as_ref()
+            .is_some_and(|(_, assembler)| assembler.is_waiting_keyframe)
+    }
+
+    fn set_source(&mut self, key: StreamKey, assembler: FrameAssembler) {
+        self.source = Some((key, assembler));
+        self.last_timestamp = None;
+    }
+
+    fn push(&mut self, now: Instant, packet: rtp::packet::Packet) -> Vec<Frame> {
+        match self.source.as_mut() {
+            Some((_, assembler)) => assembler.push(now, packet),
+            None => vec![],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.held = None;
+        self.samples.clear();
+    }
+
+    /// decode_time follows the RTP timestamps of the source, and starts from the wallclock
+    /// when the source changes, after the last sample
+    fn decode_time(&mut self, timestamp: u32, received_at: Instant, started_at: Instant) -> u64 {
+        let decode_time = match self.last_timestamp {
+            Some((last_timestamp, last_decode_time)) => {
+                let delta = timestamp.wrapping_sub(last_timestamp) as i32 as i64;
+                (last_decode_time as i64 + delta).max(0) as u64
+            }
+            None => {
+                let elapsed = received_at.saturating_duration_since(started_at);
+                let wallclock_time = (elapsed.as_secs_f64() * self.timescale as f64) as u64;
+                let last_duration = self.samples.last().map_or(1, |sample| sample.duration);
+                let held_end = self
+                    .held
+                    .as_ref()
+                    .map_or(0, |held| held.decode_time + last_duration as u64);
+                wallclock_time.max(held_end)
+            }
+        };
+        let decode_time = self
+            .held
+            .as_ref()
+            .map_or(decode_time, |held| decode_time.max(held.decode_time + 1));
+        self.last_timestamp = Some((timestamp, decode_time));
+        decode_time
+    }
+
+    /// complete_held ends the held sample at a decode time, and returns its duration
+    fn complete_held(&mut self, decode_time: u64) -> u32 {
+        let Some(mut held) = self.held.take() else {
+            return 0;
+        };
+        held.duration = decode_time.saturating_sub(held.decode_time).max(1) as u32;
+        let duration = held.duration;
+        self.samples.push(held);
+        duration
+    }
+
+    /// complete_last ends the held sample with the given duration, and returns its end
+    fn complete_last(&mut self, duration: u32) -> Option<u64> {
+        let end = self.held.as_ref()?.decode_time + duration as u64;
+        self.complete_held(end);
+        Some(end)
+    }
+}
+
+/// FrameAssembler reorders the packets of a stream and assembles them into frames, video
+/// starting from a keyframe and waiting for another one after any gap
+struct FrameAssembler {
+    is_video: bool,
+    jitter_buffer: JitterBuffer,
+    depacketizer: Box<dyn Depacketizer>,
+    frame: Vec<u8>,
+    frame_timestamp: Option<u32>,
+    is_frame_keyframe: bool,
+    is_frame_broken: bool,
+    is_waiting_keyframe: bool,
+}
+
+impl FrameAssembler {
+    fn new(is_video: bool) -> Self {
+        Self {
+            is_video,
+            jitter_buffer: JitterBuffer::default(),
+            depacketizer: new_depacketizer(is_video),
+            frame: vec![],
+            frame_timestamp: None,
+            is_frame_keyframe: false,
+            is_frame_broken: false,
+            is_waiting_keyframe: is_video,
+        }
+    }
+
+    fn push(&mut self, now: Instant, packet: rtp::packet::Packet) -> Vec<Frame> {
+        self.jitter_buffer
+            .push(now, packet)
+            .into_iter()
+            .filter_map(|ordered_packet| self.on_ordered_packet(ordered_packet))
+            .collect()
+    }
+
+    fn on_ordered_packet(&mut self, ordered_packet: OrderedPacket) -> Option<Frame> {
+        let OrderedPacket {
+            packet,
+            received_at,
+            is_after_gap,
+        } = ordered_packet;
+        // padding only packets carry no media
+        if packet.payload.is_empty() {
+            return None;
+        }
+        let timestamp = packet.header.timestamp;
+
+        if !self.is_video {
+            return self
+                .depacketizer
+                .depacketize(&packet.payload)
+                .ok()
+                .map(|data| Frame {
+                    data: data.to_vec(),
+                    timestamp,
+                    received_at,
+                    is_keyframe: true,
+                });
+        }
+
+        if is_after_gap {
+            // the next frames may reference the lost ones, decoding restarts from a keyframe
+            self.is_waiting_keyframe = true;
+            self.is_frame_broken = true;
+            self.depacketizer = new_depacketizer(true);
+        }
+        if self.frame_timestamp != Some(timestamp) {
+            // a frame whose marker bit was never received is incomplete
+            self.frame.clear();
+            self.frame_timestamp = Some(timestamp);
+            self.is_frame_keyframe = keyframe::is_keyframe(MIME_TYPE_H264, &packet.payload);
+            self.is_frame_broken = !self.depacketizer.is_partition_head(&packet.payload);
+        }
+        match self.depacketizer.depacketize(&packet.payload) {
+            Ok(payload) => self.frame.extend_from_slice(&payload),
+            Err(err) => {
+                trace!("can't depacketize hls {}: {}", MIME_TYPE_H264, err);
+                self.is_frame_broken = true;
+            }
+        }
+
+        if !packet.header.marker {
+            return None;
+        }
+        let frame = std::mem::take(&mut self.frame);
+        self.frame_timestamp = None;
+        if self.is_frame_broken || (self.is_waiting_keyframe && !self.is_frame_keyframe) {
+            self.is_waiting_keyframe = true;
+            return None;
+        }
+        self.is_waiting_keyframe = false;
+        Some(Frame {
+            data: frame,
+            timestamp,
+            received_at,
+            is_keyframe: self.is_frame_keyframe,
+        })
+    }
+}
+
+fn new_depacketizer(is_video: bool) -> Box<dyn Depacketizer> {
+    if is_video {
+        // annex B start codes, converted to length prefixes when packaged
+        Box::<H264Packet>::default()
+    } else {
+        Box::new(OpusPacket)
+    }
+}
+
+fn audio_to_video_time(decode_time: u64) -> u64 {
+    decode_time * VIDEO_TIMESCALE as u64 / AUDIO_TIMESCALE as u64
+}
+
+fn ticks_to_seconds(ticks: u64) -> f64 {
+    ticks as f64 / VIDEO_TIMESCALE as f64
+}
+
+fn unix_time_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
@@ -0,0 +1,327 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+pub(crate) const PLAYLIST_FILE_NAME: &str = "index.m3u8";
+/// longest a segment lasts, rounded to the nearest second
+pub(crate) const TARGET_DURATION: u32 = 4;
+/// longest a partial segment lasts, in seconds
+pub(crate) const PART_TARGET: f64 = 0.5;
+/// segments listed in the playlist, older ones being deleted
+const MAX_SEGMENTS: usize = 8;
+/// most recent segments whose partial segments are listed too
+const MAX_SEGMENTS_WITH_PARTS: usize = 3;
+
+pub(crate) fn init_file_name(init: u32) -> String {
+    format!("init{}.mp4", init)
+}
+
+pub(crate) fn segment_file_name(sequence_number: u64) -> String {
+    format!("segment{}.m4s", sequence_number)
+}
+
+pub(crate) fn part_file_name(sequence_number: u64, part_index: usize) -> String {
+    format!("segment{}.{}.m4s", sequence_number, part_index)
+}
+
+/// Part is a partial segment, served as a file of its own
+#[derive(Debug, Clone)]
+pub(crate) struct Part {
+    pub(crate) duration: f64,
+    /// starts with a keyframe
+    pub(crate) is_independent: bool,
+}
+
+/// Segment is a complete segment, made of its partial segments
+#[derive(Debug, Clone)]
+pub(crate) struct Segment {
+    pub(crate) sequence_number: u64,
+    pub(crate) duration: f64,
+    pub(crate) init: u32,
+    pub(crate) is_discontinuity: bool,
+    pub(crate) parts: Vec<Part>,
+}
+
+/// MediaPlaylist is the low latency media playlist of a live stream, listing its most recent
+/// segments and the partial segments of the one being packaged
+pub(crate) struct MediaPlaylist {
+    segments: VecDeque<Segment>,
+    discontinuity_sequence: u64,
+    /// segment being packaged
+    current: Segment,
+    is_ended: bool,
+}
+
+impl MediaPlaylist {
+    pub(crate) fn new() -> Self {
+        Self {
+            segments: VecDeque::new(),
+            discontinuity_sequence: 0,
+            current: Segment {
+                sequence_number: 0,
+                duration: 0.0,
+                init: 0,
+                is_discontinuity: false,
+                parts: vec![],
+            },
+            is_ended: false,
+        }
+    }
+
+    pub(crate) fn current(&self) -> &Segment {
+        &self.current
+    }
+
+    pub(crate) fn segment_count(&self) -> u64 {
+        self.current.sequence_number
+    }
+
+    /// push_part appends a partial segment to the segment being packaged
+    pub(crate) fn push_part(&mut self, part: Part) {
+        self.current.duration += part.duration;
+        self.current.parts.push(part);
+    }
+
+    /// complete_segment lists the segment being packaged, the next one starting with the given
+    /// init segment, and returns the segments no longer listed. A segment without any partial
+    /// segment yet starts with the init segment instead
+    pub(crate) fn complete_segment(&mut self, init: u32, is_discontinuity: bool) -> Vec<Segment> {
+        if self.current.parts.is_empty() {
+            self.current.init = init;
+            self.current.is_discontinuity |= is_discontinuity;
+            return vec![];
+        }
+        let next = Segment {
+            sequence_number: self.current.sequence_number + 1,
+            duration: 0.0,
+            init,
+            is_discontinuity,
+            parts: vec![],
+        };
+        self.segments
+            .push_back(std::mem::replace(&mut self.current, next));
+
+        let mut removed = vec![];
+        while self.segments.len() > MAX_SEGMENTS {
+            if let Some(segment) = self.segments.pop_front() {
+                if segment.is_discontinuity {
+                    self.discontinuity_sequence += 1;
+                }
+                removed.push(segment);
+            }
+        }
+        removed
+    }
+
+    /// oldest_init returns the init segment the oldest listed segment starts with
+    pub(crate) fn oldest_init(&self) -> u32 {
+        self.segments
+            .front()
+            .map_or(self.current.init, |segment| segment.init)
+    }
+
+    /// end lists no more segment after the completed ones
+    pub(crate) fn end(&mut self) {
+        self.is_ended = true;
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let mut playlist = String::new();
+        let _ = write!(
+            playlist,
+            "#EXTM3U\n#EXT-X-VERSION:9\n#EXT-X-TARGETDURATION:{}\n",
+            TARGET_DURATION
+        );
+        let _ = write!(
+            playlist,
+            "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.3}\n\
+             #EXT-X-PART-INF:PART-TARGET={:.3}\n",
+            3.0 * PART_TARGET,
+            PART_TARGET
+        );
+        let first_sequence_number = self
+            .segments
+            .front()
+            .map_or(self.current.sequence_number, |segment| {
+                segment.sequence_number
+            });
+        let _ = write!(
+            playlist,
+            "#EXT-X-MEDIA-SEQUENCE:{}\n#EXT-X-DISCONTINUITY-SEQUENCE:{}\n",
+            first_sequence_number, self.discontinuity_sequence
+        );
+
+        let with_parts_from = self.segments.len().saturating_sub(MAX_SEGMENTS_WITH_PARTS);
+        let mut init = None;
+        for (i, segment) in self.segments.iter().enumerate() {
+            render_segment_header(&mut playlist, segment, &mut init);
+            if i >= with_parts_from {
+                render_parts(&mut playlist, segment);
+            }
+            let _ = write!(
+                playlist,
+                "#EXTINF:{:.5},\n{}\n",
+                segment.duration,
+                segment_file_name(segment.sequence_number)
+            );
+        }
+
+        if self.is_ended {
+            playlist.push_str("#EXT-X-ENDLIST\n");
+        } else {
+            if !self.current.parts.is_empty() {
+                render_segment_header(&mut playlist, &self.current, &mut init);
+                render_parts(&mut playlist, &self.current);
+            }
+            let _ = writeln!(
+                playlist,
+                "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"{}\"",
+                part_file_name(self.current.sequence_number, self.current.parts.len())
+            );
+        }
+        playlist
+    }
+}
+
+/// render_segment_header marks a discontinuity before a segment, and the init segment it
+/// starts with when it changes
+fn render_segment_header(playlist: &mut String, segment: &Segment, init: &mut Option<u32>) {
+    if segment.is_discontinuity && init.is_some() {
+        playlist.push_str("#EXT-X-DISCONTINUITY\n");
+    }
+    if *init != Some(segment.init) {
+        let _ = writeln!(
+            playlist,
+            "#EXT-X-MAP:URI=\"{}\"",
+            init_file_name(segment.init)
+        );
+        *init = Some(segment.init);
+    }
+}
+
+fn render_parts(playlist: &mut String, segment: &Segment) {
+    for (part_index, part) in segment.parts.iter().enumerate() {
+        let _ = write!(
+            playlist,
+            "#EXT-X-PART:DURATION={:.5},URI=\"{}\"",
+            part.duration,
+            part_file_name(segment.sequence_number, part_index)
+        );
+        if part.is_independent {
+            playlist.push_str(",INDEPENDENT=YES");
+        }
+        playlist.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "#EXTM3U\n\
+        #EXT-X-VERSION:9\n\
+        #EXT-X-TARGETDURATION:4\n\
+        #EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=1.500\n\
+        #EXT-X-PART-INF:PART-TARGET=0.500\n";
+
+    fn part(duration: f64, is_independent: bool) -> Part {
+        Part {
+            duration,
+            is_independent,
+        }
+    }
+
+    #[test]
+    fn renders_an_empty_playlist() {
+        let playlist = MediaPlaylist::new();
+        assert_eq!(
+            playlist.render(),
+            format!(
+                "{}#EXT-X-MEDIA-SEQUENCE:0\n\
+                 #EXT-X-DISCONTINUITY-SEQUENCE:0\n\
+                 #EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"segment0.0.m4s\"\n",
+                HEADER
+            )
+        );
+    }
+
+    #[test]
+    fn renders_segments_and_partial_segments() {
+        let mut playlist = MediaPlaylist::new();
+        playlist.push_part(part(0.5, true));
+        playlist.push_part(part(0.5, false));
+        assert!(playlist.complete_segment(0, false).is_empty());
+        playlist.push_part(part(0.4, true));
+        assert!(playlist.complete_segment(1, true).is_empty());
+        playlist.push_part(part(0.25, true));
+
+        assert_eq!(playlist.segment_count(), 2);
+        assert_eq!(
+            playlist.render(),
+            format!(
+                "{}#EXT-X-MEDIA-SEQUENCE:0\n\
+                 #EXT-X-DISCONTINUITY-SEQUENCE:0\n\
+                 #EXT-X-MAP:URI=\"init0.mp4\"\n\
+                 #EXT-X-PART:DURATION=0.50000,URI=\"segment0.0.m4s\",INDEPENDENT=YES\n\
+                 #EXT-X-PART:DURATION=0.50000,URI=\"segment0.1.m4s\"\n\
+                 #EXTINF:1.00000,\n\
+                 segment0.m4s\n\
+                 #EXT-X-PART:DURATION=0.40000,URI=\"segment1.0.m4s\",INDEPENDENT=YES\n\
+                 #EXTINF:0.40000,\n\
+                 segment1.m4s\n\
+                 #EXT-X-DISCONTINUITY\n\
+                 #EXT-X-MAP:URI=\"init1.mp4\"\n\
+                 #EXT-X-PART:DURATION=0.25000,URI=\"segment2.0.m4s\",INDEPENDENT=YES\n\
+                 #EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"segment2.1.m4s\"\n",
+                HEADER
+            )
+        );
+
+        playlist.end();
+        assert!(playlist
+            .render()
+            .ends_with("#EXTINF:0.40000,\nsegment1.m4s\n#EXT-X-ENDLIST\n"));
+    }
+
+    #[test]
+    fn starts_segments_without_parts_with_the_latest_init() {
+        let mut playlist = MediaPlaylist::new();
+        assert!(playlist.complete_segment(3, true).is_empty());
+        assert_eq!(playlist.current().init, 3);
+        assert!(playlist.current().is_discontinuity);
+        assert_eq!(playlist.segment_count(), 0);
+        assert_eq!(playlist.oldest_init(), 3);
+    }
+
+    #[test]
+    fn removes_the_oldest_segments() {
+        let mut playlist = MediaPlaylist::new();
+        let mut removed = vec![];
+        for sequence_number in 0..MAX_SEGMENTS as u32 + 2 {
+            playlist.push_part(part(0.5, true));
+            // the first two segments completed start with a new init
+            let init = sequence_number.min(2);
+            removed.extend(playlist.complete_segment(init, sequence_number < 2));
+        }
+
+        let removed: Vec<u64> = removed
+            .iter()
+            .map(|segment| segment.sequence_number)
+            .collect();
+        assert_eq!(removed, vec![0, 1]);
+        assert_eq!(playlist.oldest_init(), 1);
+        let rendered = playlist.render();
+        assert!(rendered.contains("#EXT-X-MEDIA-SEQUENCE:2\n"));
+        // segment 1 was the first one marked as a discontinuity
+        assert!(rendered.contains("#EXT-X-DISCONTINUITY-SEQUENCE:1\n"));
+        assert!(rendered.starts_with(HEADER));
+        assert!(!rendered.contains("segment1.m4s"));
+        assert_eq!(rendered.matches("#EXTINF").count(), MAX_SEGMENTS);
+        // only the most recent segments list their partial segments
+        assert_eq!(
+            rendered.matches("#EXT-X-PART:").count(),
+            MAX_SEGMENTS_WITH_PARTS
+        );
+        assert!(rendered.contains("URI=\"segment9.0.m4s\""));
+        assert!(!rendered.contains("URI=\"segment6.0.m4s\""));
+    }
+}
@@ -0,0 +1,515 @@
+use crate::description::rtp_codec::{RTCRtpCodecParameters, RTCRtpParameters, RTPCodecType};
+use crate::description::rtp_transceiver::{MediaStreamId, RTCRtpSender, RTCRtpTransceiver, SSRC};
+use crate::description::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+use crate::session::config::SessionConfig;
+use crate::types::{EndpointId, Mid, SessionId};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use shared::error::{Error, Result};
+use srtp::context::Context;
+use srtp::option::{srtcp_replay_protection, srtp_replay_protection};
+use srtp::protection_profile::ProtectionProfile;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::net::SocketAddr;
+
+const SRTP_REPLAY_PROTECTION_WINDOW: usize = 64;
+const SRTP_MASTER_KEY_LEN: usize = 16;
+const CRYPTO_SUITE_AES_CM_128_HMAC_SHA1_80: &str = "AES_CM_128_HMAC_SHA1_80";
+const CRYPTO_SUITE_AEAD_AES_128_GCM: &str = "AEAD_AES_128_GCM";
+
+/// PlainIngestRequest publishes the RTP sent by a non-WebRTC source, such as gstreamer or ffmpeg,
+/// into a session as an endpoint without ICE nor DTLS
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PlainIngestRequest {
+    /// endpoint id of the source, which mustn't be used in the session
+    pub endpoint_id: EndpointId,
+    /// address the source sends from, learnt from its first packet when unset
+    #[serde(default)]
+    pub remote_addr: Option<SocketAddr>,
+    pub tracks: Vec<PlainTrack>,
+    /// SDES keys the source encrypts with, plain RTP when unset
+    #[serde(default)]
+    pub srtp: Option<SrtpParameters>,
+}
+
+/// PlainTrack declares a stream sent by a plain RTP source
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PlainTrack {
+    /// mime type of a codec negotiated in the session, such as audio/opus or video/VP8
+    pub mime_type: String,
+    pub ssrc: SSRC,
+    /// payload type the source sends with, rewritten to the one of the session, which it
+    /// defaults to
+    #[serde(default)]
+    pub payload_type: Option<u8>,
+}
+
+/// SrtpParameters are SDES keys, as in the crypto attribute of RFC 4568
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SrtpParameters {
+    /// AES_CM_128_HMAC_SHA1_80 or AEAD_AES_128_GCM
+    pub crypto_suite: String,
+    /// base64 of the master key followed by the master salt
+    pub key: String,
+}
+
+/// PlainIngestDescription tells a plain RTP source where and how to send its tracks
+#[derive(Debug, Clone, Serialize)]
+pub struct PlainIngestDescription {
+    pub session_id: SessionId,
+    pub endpoint_id: EndpointId,
+    /// address of the worker of the session to send RTP and RTCP to
+    pub local_addr: SocketAddr,
+    /// SDES keys the RTCP sent back to the source is encrypted with
+    pub srtp: Option<SrtpParameters>,
+    /// SDP of the tracks as the source sends them
+    pub sdp: String,
+}
+
+/// PlainIngest is the endpoint of a plain RTP source, whose transport is registered once its
+/// address is known
+pub(crate) struct PlainIngest {
+    endpoint_id: EndpointId,
+    remote_addr: Option<SocketAddr>,
+    tracks: Vec<PlainIngestTrack>,
+    srtp_keys: Option<SrtpKeys>,
+}
+
+struct PlainIngestTrack {
+    mid: Mid,
+    kind: RTPCodecType,
+    codec: RTCRtpCodecParameters,
+    ssrc: SSRC,
+    payload_type: u8,
+}
+
+struct SrtpKeys {
+    profile: ProtectionProfile,
+    crypto_suite: String,
+    /// key of the RTCP sent to the source, generated by the server
+    local_key: Vec<u8>,
+    /// key of the RTP and RTCP sent by the source
+    remote_key: Vec<u8>,
+}
+
+impl PlainIngest {
+    /// new declares the tracks of the request with the codecs the session negotiates for their
+    /// mime types
+    pub(crate) fn new(request: PlainIngestRequest, session_config: &SessionConfig) -> Result<Self> {
+        if request.tracks.is_empty() {
+            return Err(Error::Other(format!(
+                "plain ingest of endpoint id {} has no track",
+                request.endpoint_id
+            )));
+        }
+
+        let mut ssrcs = HashSet::new();
+        let mut tracks = vec![];
+        for (i, track) in request.tracks.iter().enumerate() {
+            if !ssrcs.insert(track.ssrc) {
+                return Err(Error::Other(format!(
+                    "ssrc {} is declared twice",
+                    track.ssrc
+                )));
+            }
+            let kind =
+                RTPCodecType::from(track.mime_type.split_once('/').map_or("", |(kind, _)| kind));
+            let codec = session_config
+                .get_codecs_by_kind(kind)
+                .into_iter()
+                .find(|codec| {
+                    codec
+                        .capability
+                        .mime_type
+                        .eq_ignore_ascii_case(&track.mime_type)
+                })
+                .ok_or(Error::Other(format!(
+                    "{} is not negotiated in the session",
+                    track.mime_type
+                )))?;
+            tracks.push(PlainIngestTrack {
+                mid: i.to_string(),
+                kind,
+                payload_type: track.payload_type.unwrap_or(codec.payload_type),
+                codec,
+                ssrc: track.ssrc,
+            });
+        }
+
+        let srtp_keys = request
+            .srtp
+            .as_ref()
+            .map(|srtp| {
+                let (profile, salt_len) = match srtp.crypto_suite.as_str() {
+                    CRYPTO_SUITE_AES_CM_128_HMAC_SHA1_80 => {
+                        (ProtectionProfile::Aes128CmHmacSha1_80, 14)
+                    }
+                    CRYPTO_SUITE_AEAD_AES_128_GCM => (ProtectionProfile::AeadAes128Gcm, 12),
+                    crypto_suite => {
+                        return Err(Error::Other(format!(
+                            "unsupported crypto suite {}",
+                            crypto_suite
+                        )))
+                    }
+                };
+                let remote_key = BASE64_STANDARD
+                    .decode(&srtp.key)
+                    .map_err(|err| Error::Other(format!("invalid srtp key: {}", err)))?;
+                if remote_key.len() != SRTP_MASTER_KEY_LEN + salt_len {
+                    return Err(Error::Other(format!(
+                        "srtp key of {} must be {} bytes long",
+                        srtp.crypto_suite,
+                        SRTP_MASTER_KEY_LEN + salt_len
+                    )));
+                }
+                let mut local_key = vec![0u8; SRTP_MASTER_KEY_LEN + salt_len];
+                SystemRandom::new()
+                    .fill(&mut local_key)
+                    .map_err(|_| Error::Other("can't generate srtp key".to_string()))?;
+                Ok(SrtpKeys {
+                    profile,
+                    crypto_suite: srtp.crypto_suite.clone(),
+                    local_key,
+                    remote_key,
+                })
+            })
+            .transpose()?;
+
+        Ok(Self {
+            endpoint_id: request.endpoint_id,
+            remote_addr: request.remote_addr,
+            tracks,
+            srtp_keys,
+        })
+    }
+
+    pub(crate) fn endpoint_id(&self) -> EndpointId {
+        self.endpoint_id
+    }
+
+    pub(crate) fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    pub(crate) fn set_remote_addr(&mut self, remote_addr: SocketAddr) {
+        self.remote_addr = Some(remote_addr);
+    }
+
+    /// has_ssrc tells whether a stream with the SSRC is declared by the source
+    pub(crate) fn has_ssrc(&self, ssrc: SSRC) -> bool {
+        self.tracks.iter().any(|track| track.ssrc == ssrc)
+    }
+
+    /// transceivers returns the transceivers the endpoint publishes its tracks on, as received
+    /// by the server
+    pub(crate) fn transceivers(&self) -> Vec<RTCRtpTransceiver> {
+        self.tracks
+            .iter()
+            .map(|track| RTCRtpTransceiver {
+                mid: track.mid.clone(),
+                sender: Some(RTCRtpSender {
+                    cname: format!("{}", self.endpoint_id),
+                    msid: MediaStreamId {
+                        stream_id: format!("{}", self.endpoint_id),
+                        track_id: format!("{}-{}", self.endpoint_id, track.mid),
+                    },
+                    ssrcs: vec![track.ssrc],
+                    ssrc_groups: vec![],
+                }),
+                direction: RTCRtpTransceiverDirection::Recvonly,
+                current_direction: RTCRtpTransceiverDirection::Recvonly,
+                rtp_params: RTCRtpParameters {
+                    header_extensions: vec![],
+                    codecs: vec![track.codec.clone()],
+                },
+                kind: track.kind,
+                rids: vec![],
+            })
+            .collect()
+    }
+
+    /// srtp_contexts returns the local and remote SRTP contexts of the transport, none when the
+    /// source sends plain RTP
+    pub(crate) fn srtp_contexts(&self) -> Result<Option<(Context, Context)>> {
+        let Some(srtp_keys) = self.srtp_keys.as_ref() else {
+            return Ok(None);
+        };
+
+        let local_context = Context::new(
+            &srtp_keys.local_key[..SRTP_MASTER_KEY_LEN],
+            &srtp_keys.local_key[SRTP_MASTER_KEY_LEN..],
+            srtp_keys.profile,
+            None,
+            None,
+        )?;
+        let remote_context = Context::new(
+            &srtp_keys.remote_key[..SRTP_MASTER_KEY_LEN],
+            &srtp_keys.remote_key[SRTP_MASTER_KEY_LEN..],
+            srtp_keys.profile,
+            Some(srtp_replay_protection(SRTP_REPLAY_PROTECTION_WINDOW)),
+            Some(srtcp_replay_protection(SRTP_REPLAY_PROTECTION_WINDOW)),
+        )?;
+
+        Ok(Some((local_context, remote_context)))
+    }
+
+    /// rewrite_payload_type maps the payload type a track is sent with to the one of the session
+    pub(crate) fn rewrite_payload_type(&self, header: &mut rtp::header::Header) {
+        if let Some(track) = self
+            .tracks
+            .iter()
+            .find(|track| track.ssrc == header.ssrc && track.payload_type == header.payload_type)
+        {
+            header.payload_type = track.codec.payload_type;
+        }
+    }
+
+    /// description returns what the source needs to send its tracks to the local address
+    pub(crate) fn description(
+        &self,
+        session_id: SessionId,
+        local_addr: SocketAddr,
+    ) -> PlainIngestDescription {
+        let address_type = if local_addr.is_ipv4() { "IP4" } else { "IP6" };
+        let mut sdp = String::new();
+        let _ = write!(
+            sdp,
+            "v=0\r\no=- {} {} IN {} {}\r\ns=endpoint {}\r\nc=IN {} {}\r\nt=0 0\r\n",
+            session_id,
+            self.endpoint_id,
+            address_type,
+            local_addr.ip(),
+            self.endpoint_id,
+            address_type,
+            local_addr.ip()
+        );
+        let protocol = if self.srtp_keys.is_some() {
+            "RTP/SAVP"
+        } else {
+            "RTP/AVP"
+        };
+        for track in self.tracks.iter() {
+            let _ = write!(
+                sdp,
+                "m={} {} {} {}\r\n",
+                track.kind,
+                local_addr.port(),
+                protocol,
+                track.payload_type
+            );
+            let capability = &track.codec.capability;
+            let encoding_name = capability
+                .mime_type
+                .split_once('/')
+                .map_or(capability.mime_type.as_str(), |(_, name)| name);
+            let _ = write!(
+                sdp,
+                "a=rtpmap:{} {}/{}",
+                track.payload_type, encoding_name, capability.clock_rate
+            );
+            if capability.channels > 1 {
+                let _ = write!(sdp, "/{}", capability.channels);
+            }
+            sdp.push_str("\r\n");
+            if !capability.sdp_fmtp_line.is_empty() {
+                let _ = write!(
+                    sdp,
+                    "a=fmtp:{} {}\r\n",
+                    track.payload_type, capability.sdp_fmtp_line
+                );
+            }
+            if let Some(srtp_keys) = self.srtp_keys.as_ref() {
+                let _ = write!(
+                    sdp,
+                    "a=crypto:1 {} inline:{}\r\n",
+                    srtp_keys.crypto_suite,
+                    BASE64_STANDARD.encode(&srtp_keys.remote_key)
+                );
+            }
+            let _ = write!(
+                sdp,
+                "a=rtcp-mux\r\na=ssrc:{} cname:{}\r\na=mid:{}\r\na=sendonly\r\n",
+                track.ssrc, self.endpoint_id, track.mid
+            );
+        }
+
+        PlainIngestDescription {
+            session_id,
+            endpoint_id: self.endpoint_id,
+            local_addr,
+            srtp: self.srtp_keys.as_ref().map(|srtp_keys| SrtpParameters {
+                crypto_suite: srtp_keys.crypto_suite.clone(),
+                key: BASE64_STANDARD.encode(&srtp_keys.local_key),
+            }),
+            sdp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::config::ServerConfig;
+    use std::sync::Arc;
+
+    fn session_config() -> SessionConfig {
+        SessionConfig::new(
+            Arc::new(ServerConfig::new(vec![])),
+            "127.0.0.1:3478".parse().unwrap(),
+            vec![],
+            vec![],
+        )
+    }
+
+    fn request(tracks: Vec<PlainTrack>) -> PlainIngestRequest {
+        PlainIngestRequest {
+            endpoint_id: 10,
+            remote_addr: None,
+            tracks,
+            srtp: None,
+        }
+    }
+
+    fn track(mime_type: &str, ssrc: SSRC, payload_type: Option<u8>) -> PlainTrack {
+        PlainTrack {
+            mime_type: mime_type.to_string(),
+            ssrc,
+            payload_type,
+        }
+    }
+
+    fn header(ssrc: SSRC, payload_type: u8) -> rtp::header::Header {
+        rtp::header::Header {
+            ssrc,
+            payload_type,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn declares_the_tracks_with_the_codecs_of_the_session() {
+        let ingest = PlainIngest::new(
+            request(vec![
+                track("audio/opus", 1111, Some(100)),
+                track("video/vp8", 2222, None),
+            ]),
+            &session_config(),
+        )
+        .unwrap();
+        assert!(ingest.has_ssrc(1111));
+        assert!(ingest.has_ssrc(2222));
+        assert!(!ingest.has_ssrc(3333));
+
+        let transceivers = ingest.transceivers();
+        assert_eq!(transceivers.len(), 2);
+        for (transceiver, (mid, kind, payload_type, ssrc)) in transceivers.iter().zip([
+            ("0", RTPCodecType::Audio, 111, 1111),
+            ("1", RTPCodecType::Video, 96, 2222),
+        ]) {
+            assert_eq!(transceiver.mid, mid);
+            assert_eq!(transceiver.kind, kind);
+            assert_eq!(transceiver.rtp_params.codecs[0].payload_type, payload_type);
+            assert_eq!(
+                transceiver
+                    .sender
+                    .as_ref()
+                    .map(|sender| sender.ssrcs.clone()),
+                Some(vec![ssrc])
+            );
+        }
+    }
+
+    #[test]
+    fn rewrites_the_payload_types_of_the_source() {
+        let ingest = PlainIngest::new(
+            request(vec![
+                track("audio/opus", 1111, Some(100)),
+                track("video/VP8", 2222, None),
+            ]),
+            &session_config(),
+        )
+        .unwrap();
+
+        let mut opus = header(1111, 100);
+        ingest.rewrite_payload_type(&mut opus);
+        assert_eq!(opus.payload_type, 111);
+        let mut vp8 = header(2222, 96);
+        ingest.rewrite_payload_type(&mut vp8);
+        assert_eq!(vp8.payload_type, 96);
+
+        // payload types the track isn't declared with, and unknown streams, are left alone
+        let mut other_payload_type = header(1111, 101);
+        ingest.rewrite_payload_type(&mut other_payload_type);
+        assert_eq!(other_payload_type.payload_type, 101);
+        let mut unknown = header(3333, 100);
+        ingest.rewrite_payload_type(&mut unknown);
+        assert_eq!(unknown.payload_type, 100);
+    }
+
+    #[test]
+    fn describes_the_tracks_as_the_source_sends_them() {
+        let mut request = request(vec![track("audio/opus", 1111, Some(100))]);
+        request.srtp = Some(SrtpParameters {
+            crypto_suite: CRYPTO_SUITE_AES_CM_128_HMAC_SHA1_80.to_string(),
+            key: BASE64_STANDARD.encode([7u8; 30]),
+        });
+        let ingest = PlainIngest::new(request, &session_config()).unwrap();
+        let local_addr = "192.0.2.1:3478".parse().unwrap();
+        let description = ingest.description(1, local_addr);
+
+        assert!(description
+            .sdp
+            .contains("m=audio 3478 RTP/SAVP 100\r\na=rtpmap:100 opus/48000/2\r\n"));
+        assert!(description
+            .sdp
+            .contains(&format!("inline:{}", BASE64_STANDARD.encode([7u8; 30]))));
+        assert!(description
+            .sdp
+            .contains("a=ssrc:1111 cname:10\r\na=mid:0\r\n"));
+        // the server encrypts its RTCP with a key of its own
+        let srtp = description.srtp.unwrap();
+        assert_eq!(srtp.crypto_suite, CRYPTO_SUITE_AES_CM_128_HMAC_SHA1_80);
+        assert_ne!(srtp.key, BASE64_STANDARD.encode([7u8; 30]));
+        assert!(ingest.srtp_contexts().unwrap().is_some());
+    }
+
+    #[test]
+    fn rejects_invalid_tracks_and_keys() {
+        let session_config = session_config();
+        assert!(PlainIngest::new(request(vec![]), &session_config).is_err());
+        assert!(PlainIngest::new(
+            request(vec![
+                track("audio/opus", 1111, None),
+                track("video/VP8", 1111, None),
+            ]),
+            &session_config
+        )
+        .is_err());
+        assert!(PlainIngest::new(
+            request(vec![track("video/H265", 1111, None)]),
+            &session_config
+        )
+        .is_err());
+
+        for (crypto_suite, key) in [
+            ("NULL_HMAC_SHA1_80", BASE64_STANDARD.encode([7u8; 30])),
+            (
+                CRYPTO_SUITE_AEAD_AES_128_GCM,
+                BASE64_STANDARD.encode([7u8; 30]),
+            ),
+            (
+                CRYPTO_SUITE_AES_CM_128_HMAC_SHA1_80,
+                "not base64".to_string(),
+            ),
+        ] {
+            let mut request = request(vec![track("audio/opus", 1111, None)]);
+            request.srtp = Some(SrtpParameters {
+                crypto_suite: crypto_suite.to_string(),
+                key,
+            });
+            assert!(PlainIngest::new(request, &session_config).is_err());
+        }
+    }
+}
@@ -0,0 +1,527 @@
+pub(crate) mod reader;
+
+use crate::description::config::{MIME_TYPE_OPUS, MIME_TYPE_VP8};
+use crate::description::rtp_codec::{RTCRtpCodecParameters, RTCRtpParameters, RTPCodecType};
+use crate::description::rtp_transceiver::{MediaStreamId, RTCRtpSender, RTCRtpTransceiver, SSRC};
+use crate::description::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+use crate::injector::reader::{read_ahead, Frame, IvfReader, MediaReader, OggReader, ReadEvent};
+use crate::session::config::SessionConfig;
+use crate::types::{EndpointId, Mid};
+use log::{debug, warn};
+use rtp::codecs::{opus::OpusPayloader, vp8::Vp8Payloader};
+use rtp::packetizer::Payloader;
+use serde::Deserialize;
+use shared::error::{Error, Result};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// mtu of the payloads of injected packets, leaving room for SRTP and header extensions
+const RTP_PAYLOAD_MTU: usize = 1200;
+/// frame duration assumed before the second frame of a track is read, in milliseconds
+const DEFAULT_FRAME_DURATION_MS: u64 = 20;
+const AUDIO_MID: &str = "0";
+const VIDEO_MID: &str = "1";
+
+/// InjectRequest plays local media files into a session, as a virtual endpoint publishing their
+/// tracks to the other endpoints
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct InjectRequest {
+    /// endpoint id of the virtual endpoint, which mustn't be used in the session
+    pub endpoint_id: EndpointId,
+    /// Ogg Opus file, relative to the media directory
+    pub audio_file: Option<PathBuf>,
+    /// IVF VP8 file, relative to the media directory
+    pub video_file: Option<PathBuf>,
+    /// play the files again from their start once they end, until stopped
+    #[serde(default, rename = "loop")]
+    pub is_looped: bool,
+}
+
+/// MediaInjector paces the frames of media files in real time, and packetizes them into RTP
+/// packets as a publisher would send them
+pub(crate) struct MediaInjector {
+    endpoint_id: EndpointId,
+    started_at: Instant,
+    tracks: Vec<InjectedTrack>,
+}
+
+impl MediaInjector {
+    /// new opens the files of the request in the media directory, with the codecs the session
+    /// negotiates for Opus and VP8
+    pub(crate) fn new(
+        request: InjectRequest,
+        media_dir: &Path,
+        session_config: &SessionConfig,
+        now: Instant,
+    ) -> Result<Self> {
+        let mut tracks = vec![];
+        if let Some(audio_file) = &request.audio_file {
+            let file = File::open(resolve_media_file(media_dir, audio_file)?)?;
+            let reader = OggReader::new(BufReader::new(file))?;
+            debug!(
+                "inject {:?} with {} channels as endpoint {}",
+                audio_file,
+                reader.channels(),
+                request.endpoint_id
+            );
+            tracks.push(InjectedTrack::new(
+                AUDIO_MID.to_string(),
+                RTPCodecType::Audio,
+                find_codec(session_config, RTPCodecType::Audio, MIME_TYPE_OPUS)?,
+                Box::new(reader),
+                Box::new(OpusPayloader),
+                request.is_looped,
+                format!("{:?} of endpoint {}", audio_file, request.endpoint_id),
+            )?);
+        }
+        if let Some(video_file) = &request.video_file {
+            let file = File::open(resolve_media_file(media_dir, video_file)?)?;
+            let reader = IvfReader::new(BufReader::new(file))?;
+            debug!(
+                "inject {:?} as endpoint {}",
+                video_file, request.endpoint_id
+            );
+            tracks.push(InjectedTrack::new(
+                VIDEO_MID.to_string(),
+                RTPCodecType::Video,
+                find_codec(session_config, RTPCodecType::Video, MIME_TYPE_VP8)?,
+                Box::new(reader),
+                Box::new({
+                    let mut payloader = Vp8Payloader::default();
+                    payloader.enable_picture_id = true;
+                    payloader
+                }),
+                request.is_looped,
+                format!("{:?} of endpoint {}", video_file, request.endpoint_id),
+            )?);
+        }
+        if tracks.is_empty() {
+            return Err(Error::Other(format!(
+                "nothing to inject as endpoint id {}",
+                request.endpoint_id
+            )));
+        }
+
+        Ok(Self {
+            endpoint_id: request.endpoint_id,
+            started_at: now,
+            tracks,
+        })
+    }
+
+    pub(crate) fn endpoint_id(&self) -> EndpointId {
+        self.endpoint_id
+    }
+
+    /// transceivers returns the transceivers the virtual endpoint publishes its tracks on, as
+    /// received by the server
+    pub(crate) fn transceivers(&self) -> Vec<RTCRtpTransceiver> {
+        self.tracks
+            .iter()
+            .map(|track| RTCRtpTransceiver {
+                mid: track.mid.clone(),
+                sender: Some(RTCRtpSender {
+                    cname: format!("{}", self.endpoint_id),
+                    msid: MediaStreamId {
+                        stream_id: format!("{}", self.endpoint_id),
+                        track_id: format!("{}-{}", self.endpoint_id, track.mid),
+                    },
+                    ssrcs: vec![track.ssrc],
+                    ssrc_groups: vec![],
+                }),
+                direction: RTCRtpTransceiverDirection::Recvonly,
+                current_direction: RTCRtpTransceiverDirection::Recvonly,
+                rtp_params: RTCRtpParameters {
+                    header_extensions: vec![],
+                    codecs: vec![track.codec.clone()],
+                },
+                kind: track.kind,
+                rids: vec![],
+            })
+            .collect()
+    }
+
+    /// is_finished tells whether every file reached its end, which never happens when looped
+    pub(crate) fn is_finished(&self) -> bool {
+        self.tracks.iter().all(|track| track.is_finished())
+    }
+
+    /// poll_timeout returns when the next frame is due, a track whose next frame isn't read yet
+    /// being polled again on the next timeout of the worker
+    pub(crate) fn poll_timeout(&self) -> Option<Instant> {
+        self.tracks
+            .iter()
+            .filter_map(|track| track.next_frame_due(self.started_at))
+            .min()
+    }
+
+    /// poll_packets returns the packets of the frames due by now, with the mid and kind of their
+    /// track
+    pub(crate) fn poll_packets(
+        &mut self,
+        now: Instant,
+    ) -> Vec<(Mid, RTPCodecType, rtp::packet::Packet)> {
+        let mut packets = vec![];
+        for track in self.tracks.iter_mut() {
+            track.receive();
+            while track
+                .next_frame_due(self.started_at)
+                .is_some_and(|due| due <= now)
+            {
+                match track.packetize() {
+                    Ok(track_packets) => packets.extend(
+                        track_packets
+                            .into_iter()
+                            .map(|packet| (track.mid.clone(), track.kind, packet)),
+                    ),
+                    Err(err) => warn!(
+                        "can't packetize mid {} of endpoint {}: {}",
+                        track.mid, self.endpoint_id, err
+                    ),
+                }
+                track.advance();
+            }
+        }
+        packets
+    }
+}
+
+/// InjectedTrack sends the frames of a file on an SSRC of its own, with a random initial
+/// sequence number and timestamp, the timestamps going on increasing when the file loops. Its
+/// frames are read ahead by a thread of their own
+struct InjectedTrack {
+    mid: Mid,
+    kind: RTPCodecType,
+    codec: RTCRtpCodecParameters,
+    ssrc: SSRC,
+    frames: Receiver<ReadEvent>,
+    payloader: Box<dyn Payloader>,
+    next_frame: Option<Frame>,
+    /// the file was read to its end
+    is_ended: bool,
+    /// samples the frames are sent ahead of the start of the track, see MediaReader::pre_skip
+    pre_skip: u64,
+    sequence_number: u16,
+    initial_timestamp: u32,
+    /// timestamp the file starts at, moved past its end each time it loops
+    loop_offset: u64,
+    last_timestamp: u64,
+    frame_duration: u64,
+}
+
+impl InjectedTrack {
+    fn new(
+        mid: Mid,
+        kind: RTPCodecType,
+        codec: RTCRtpCodecParameters,
+        mut reader: Box<dyn MediaReader + Send>,
+        payloader: Box<dyn Payloader>,
+        is_looped: bool,
+        name: String,
+    ) -> Result<Self> {
+        // the first frame is read right away, for a file without frames to fail the injection
+        let next_frame = reader.next_frame()?;
+        if next_frame.is_none() {
+            return Err(Error::Other(format!("no frame to inject on mid {}", mid)));
+        }
+        let pre_skip = reader.pre_skip();
+        let frames = read_ahead(reader, is_looped, name)?;
+        let frame_duration = codec.capability.clock_rate as u64 * DEFAULT_FRAME_DURATION_MS / 1000;
+
+        Ok(Self {
+            mid,
+            kind,
+            codec,
+            ssrc: rand::random::<u32>(),
+            frames,
+            payloader,
+            next_frame,
+            is_ended: false,
+            pre_skip,
+            sequence_number: rand::random::<u16>(),
+            initial_timestamp: rand::random::<u32>(),
+            loop_offset: 0,
+            last_timestamp: 0,
+            frame_duration,
+        })
+    }
+
+    fn next_frame_due(&self, started_at: Instant) -> Option<Instant> {
+        let frame = self.next_frame.as_ref()?;
+        let clock_rate = self.codec.capability.clock_rate.max(1) as u128;
+        let nanos = (self.loop_offset + frame.timestamp).saturating_sub(self.pre_skip) as u128
+            * 1_000_000_000
+            / clock_rate;
+        Some(started_at + Duration::from_nanos(nanos as u64))
+    }
+
+    /// packetize splits the next frame into packets, the marker bit being set on the last
+    /// packet of video frames
+    fn packetize(&mut self) -> Result<Vec<rtp::packet::Packet>> {
+        let Some(frame) = self.next_frame.as_ref() else {
+            return Ok(vec![]);
+        };
+        if frame.timestamp > self.last_timestamp {
+            self.frame_duration = frame.timestamp - self.last_timestamp;
+        }
+        self.last_timestamp = frame.timestamp;
+
+        let timestamp = self
+            .initial_timestamp
+            .wrapping_add((self.loop_offset + frame.timestamp) as u32);
+        let payloads = self.payloader.payload(RTP_PAYLOAD_MTU, &frame.data)?;
+        let payload_count = payloads.len();
+        let mut packets = Vec::with_capacity(payload_count);
+        for (i, payload) in payloads.into_iter().enumerate() {
+            packets.push(rtp::packet::Packet {
+                header: rtp::header::Header {
+                    version: 2,
+                    marker: self.kind == RTPCodecType::Video && i + 1 == payload_count,
+                    payload_type: self.codec.payload_type,
+                    sequence_number: self.sequence_number,
+                    timestamp,
+                    ssrc: self.ssrc,
+                    ..Default::default()
+                },
+                payload,
+            });
+            self.sequence_number = self.sequence_number.wrapping_add(1);
+        }
+        Ok(packets)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.is_ended && self.next_frame.is_none()
+    }
+
+    /// advance moves on to the frame after the one just sent
+    fn advance(&mut self) {
+        self.next_frame = None;
+        self.receive();
+    }
+
+    /// receive takes the next frame from the thread reading the file, unless it lags behind,
+    /// moving the timestamps past the end of the file each time it starts over
+    fn receive(&mut self) {
+        while self.next_frame.is_none() && !self.is_ended {
+            match self.frames.try_recv() {
+                Ok(ReadEvent::Frame(frame)) => self.next_frame = Some(frame),
+                Ok(ReadEvent::Rewound) => {
+                    self.loop_offset += self.last_timestamp + self.frame_duration;
+                    self.last_timestamp = 0;
+                }
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => self.is_ended = true,
+            }
+        }
+    }
+}
+
+/// resolve_media_file returns the path of a file in the media directory, refusing paths out of
+/// it
+fn resolve_media_file(media_dir: &Path, file: &Path) -> Result<PathBuf> {
+    if file.as_os_str().is_empty()
+        || !file
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(Error::Other(format!(
+            "media file {:?} is not in the media directory",
+            file
+        )));
+    }
+    Ok(media_dir.join(file))
+}
+
+/// find_codec returns the codec the session negotiates for the mime type
+pub(crate) fn find_codec(
+    session_config: &SessionConfig,
+    kind: RTPCodecType,
+    mime_type: &str,
+) -> Result<RTCRtpCodecParameters> {
+    session_config
+        .get_codecs_by_kind(kind)
+        .into_iter()
+        .find(|codec| codec.capability.mime_type.eq_ignore_ascii_case(mime_type))
+        .ok_or(Error::Other(format!(
+            "{} is not negotiated in the session",
+            mime_type
+        )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use rtp::codecs::vp8::Vp8Payloader;
+
+    /// VecReader reads the frames of the timestamps from memory, as a file would
+    struct VecReader {
+        timestamps: Vec<u64>,
+        position: usize,
+        pre_skip: u64,
+    }
+
+    impl VecReader {
+        fn new(timestamps: Vec<u64>, pre_skip: u64) -> Box<Self> {
+            Box::new(Self {
+                timestamps,
+                position: 0,
+                pre_skip,
+            })
+        }
+    }
+
+    impl MediaReader for VecReader {
+        fn next_frame(&mut self) -> Result<Option<Frame>> {
+            let frame = self.timestamps.get(self.position).map(|&timestamp| Frame {
+                data: Bytes::from_static(&[0x10, 0x02, 0x00]),
+                timestamp,
+            });
+            self.position += 1;
+            Ok(frame)
+        }
+
+        fn rewind(&mut self) -> Result<()> {
+            self.position = 0;
+            Ok(())
+        }
+
+        fn pre_skip(&self) -> u64 {
+            self.pre_skip
+        }
+    }
+
+    fn track(reader: Box<VecReader>, clock_rate: u32, is_looped: bool) -> InjectedTrack {
+        let codec = RTCRtpCodecParameters {
+            capability: crate::description::rtp_codec::RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_VP8.to_string(),
+                clock_rate,
+                ..Default::default()
+            },
+            payload_type: 96,
+            ..Default::default()
+        };
+        InjectedTrack::new(
+            VIDEO_MID.to_string(),
+            RTPCodecType::Video,
+            codec,
+            reader,
+            Box::<Vp8Payloader>::default(),
+            is_looped,
+            "test".to_string(),
+        )
+        .unwrap()
+    }
+
+    /// send_frame sends the next frame of the track, waiting for the thread reading it, and
+    /// returns its RTP timestamp relative to the initial one
+    fn send_frame(track: &mut InjectedTrack) -> Option<u32> {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while track.next_frame.is_none() && !track.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+            track.receive();
+        }
+        track.next_frame.as_ref()?;
+        let packets = track.packetize().unwrap();
+        track.advance();
+        Some(
+            packets[0]
+                .header
+                .timestamp
+                .wrapping_sub(track.initial_timestamp),
+        )
+    }
+
+    #[test]
+    fn moves_the_timestamps_past_the_end_of_a_looped_file() {
+        let mut track = track(VecReader::new(vec![0, 3000, 6000], 0), 90000, true);
+        let timestamps: Vec<Option<u32>> = (0..7).map(|_| send_frame(&mut track)).collect();
+        // the file starts over one frame duration after its last frame
+        assert_eq!(
+            timestamps,
+            [0, 3000, 6000, 9000, 12000, 15000, 18000].map(Some)
+        );
+        assert_eq!(track.loop_offset, 18000);
+    }
+
+    #[test]
+    fn ends_with_the_file_when_not_looped() {
+        let mut track = track(VecReader::new(vec![0, 3000], 0), 90000, false);
+        assert_eq!(send_frame(&mut track), Some(0));
+        assert_eq!(send_frame(&mut track), Some(3000));
+        assert_eq!(send_frame(&mut track), None);
+        assert!(track.is_finished());
+    }
+
+    #[test]
+    fn sends_the_pre_skip_ahead_of_the_start() {
+        let started_at = Instant::now();
+        let mut track = track(VecReader::new(vec![0, 960], 312), 48000, false);
+        assert_eq!(track.next_frame_due(started_at), Some(started_at));
+        send_frame(&mut track);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while track.next_frame.is_none() && Instant::now() < deadline {
+            track.receive();
+        }
+        assert_eq!(
+            track.next_frame_due(started_at),
+            Some(started_at + Duration::from_micros(13500))
+        );
+    }
+
+    fn read_events(reader: Box<VecReader>, is_looped: bool, count: usize) -> Vec<Option<u64>> {
+        read_ahead(reader, is_looped, "test".to_string())
+            .unwrap()
+            .into_iter()
+            .take(count)
+            .map(|event| match event {
+                ReadEvent::Frame(frame) => Some(frame.timestamp),
+                ReadEvent::Rewound => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_ahead_until_the_end_of_the_file() {
+        assert_eq!(
+            read_events(VecReader::new(vec![0, 3000], 0), false, 10),
+            vec![Some(0), Some(3000)]
+        );
+    }
+
+    #[test]
+    fn reads_ahead_looped_files_again() {
+        assert_eq!(
+            read_events(VecReader::new(vec![0, 3000], 0), true, 5),
+            vec![Some(0), Some(3000), None, Some(0), Some(3000)]
+        );
+        // an empty file is started over once
+        assert_eq!(read_events(VecReader::new(vec![], 0), true, 10), vec![None]);
+    }
+
+    #[test]
+    fn refuses_media_files_out_of_the_media_directory() {
+        let media_dir = Path::new("/srv/media");
+        assert_eq!(
+            resolve_media_file(media_dir, Path::new("talks/intro.ivf")).unwrap(),
+            PathBuf::from("/srv/media/talks/intro.ivf")
+        );
+        for file in [
+            "../secret.ivf",
+            "talks/../../secret.ivf",
+            "/etc/passwd",
+            "./intro.ivf",
+            "",
+        ] {
+            assert!(
+                resolve_media_file(media_dir, Path::new(file)).is_err(),
+                "{} is accepted",
+                file
+            );
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use crate::recording::writer::opus_samples;
+use bytes::Bytes;
+use log::warn;
+use shared::error::{Error, Result};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::sync::mpsc::{self, Receiver};
+
+const IVF_SIGNATURE: &[u8; 4] = b"DKIF";
+const IVF_HEADER_SIZE: usize = 32;
+const IVF_FRAME_HEADER_SIZE: usize = 12;
+const IVF_FOURCC_VP8: &[u8; 4] = b"VP80";
+/// larger frames are taken for a corrupted file rather than allocated
+const MAX_IVF_FRAME_SIZE: u32 = 4 * 1024 * 1024;
+const VIDEO_CLOCK_RATE: u128 = 90000;
+const OGG_CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+const OGG_PAGE_HEADER_SIZE: usize = 27;
+const OPUS_HEAD: &[u8; 8] = b"OpusHead";
+const OPUS_TAGS: &[u8; 8] = b"OpusTags";
+/// frames read ahead of their sending, by the thread reading a file
+const READ_AHEAD_FRAMES: usize = 32;
+
+/// Frame is a complete frame read from a media file
+pub(crate) struct Frame {
+    pub(crate) data: Bytes,
+    /// in clock rate units since the first frame of the file
+    pub(crate) timestamp: u64,
+}
+
+/// MediaReader reads the frames of a track from a container
+pub(crate) trait MediaReader {
+    /// next_frame returns the next frame, or none once the end of the file is reached
+    fn next_frame(&mut self) -> Result<Option<Frame>>;
+
+    /// rewind goes back to the first frame of the file
+    fn rewind(&mut self) -> Result<()>;
+
+    /// pre_skip returns the samples at the start of the file a decoder discards, in clock rate
+    /// units, which play before the start of the track
+    fn pre_skip(&self) -> u64 {
+        0
+    }
+}
+
+/// ReadEvent is what the thread reading a file hands to the track sending its frames
+pub(crate) enum ReadEvent {
+    Frame(Frame),
+    /// the file starts over, the next frames being read from its start again
+    Rewound,
+}
+
+/// read_ahead reads the frames of a file on a thread of its own, so that the media worker never
+/// waits on the file, starting the file over at its end when looped. The channel disconnects at
+/// the end of the file, or once a read fails
+pub(crate) fn read_ahead(
+    mut reader: Box<dyn MediaReader + Send>,
+    is_looped: bool,
+    name: String,
+) -> Result<Receiver<ReadEvent>> {
+    let (tx, rx) = mpsc::sync_channel(READ_AHEAD_FRAMES);
+    std::thread::Builder::new()
+        .name("media-reader".to_string())
+        .spawn(move || {
+            // frames read since the file was last started over
+            let mut has_frames = true;
+            loop {
+                let event = match reader.next_frame() {
+                    Ok(Some(frame)) => {
+                        has_frames = true;
+                        ReadEvent::Frame(frame)
+                    }
+                    // an empty file would be started over forever
+                    Ok(None) if is_looped && has_frames => match reader.rewind() {
+                        Ok(()) => {
+                            has_frames = false;
+                            ReadEvent::Rewound
+                        }
+                        Err(err) => {
+                            warn!("can't rewind {}: {}", name, err);
+                            return;
+                        }
+                    },
+                    Ok(None) => return,
+                    Err(err) => {
+                        warn!("can't read {}: {}", name, err);
+                        return;
+                    }
+                };
+                if tx.send(event).is_err() {
+                    // the injection stopped
+                    return;
+                }
+            }
+        })?;
+    Ok(rx)
+}
+
+/// IvfReader reads VP8 frames from an IVF file, their timestamps being converted from the
+/// timebase of the file to the 90 kHz video clock rate
+pub(crate) struct IvfReader<R: Read + Seek> {
+    reader: R,
+    header_size: u64,
+    timebase_denominator: u128,
+    timebase_numerator: u128,
+    first_timestamp: Option<u64>,
+}
+
+impl<R: Read + Seek> IvfReader<R> {
+    pub(crate) fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; IVF_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != IVF_SIGNATURE {
+            return Err(Error::Other("not an IVF file".to_string()));
+        }
+        if &header[8..12] != IVF_FOURCC_VP8 {
+            return Err(Error::Other(format!(
+                "unsupported IVF fourcc {}",
+                String::from_utf8_lossy(&header[8..12])
+            )));
+        }
+        let header_size = u16::from_le_bytes([header[6], header[7]]) as u64;
+        let timebase_denominator =
+            u32::from_le_bytes([header[16], header[17], header[18], header[19]]);
+        let timebase_numerator =
+            u32::from_le_bytes([header[20], header[21], header[22], header[23]]);
+        if timebase_denominator == 0 || timebase_numerator == 0 {
+            return Err(Error::Other("invalid IVF timebase".to_string()));
+        }
+        reader.seek(SeekFrom::Start(header_size))?;
+
+        Ok(Self {
+            reader,
+            header_size,
+            timebase_denominator: timebase_denominator as u128,
+            timebase_numerator: timebase_numerator as u128,
+            first_timestamp: None,
+        })
+    }
+}
+
+impl<R: Read + Seek> MediaReader for IvfReader<R> {
+    fn next_frame(&mut self) -> Result<Option<Frame>> {
+        let mut frame_header = [0u8; IVF_FRAME_HEADER_SIZE];
+        if !read_exact_or_eof(&mut self.reader, &mut frame_header)? {
+            return Ok(None);
+        }
+        let frame_size = u32::from_le_bytes([
+            frame_header[0],
+            frame_header[1],
+            frame_header[2],
+            frame_header[3],
+        ]);
+        let mut timestamp_bytes = [0u8; 8];
+        timestamp_bytes.copy_from_slice(&frame_header[4..12]);
+        let timestamp = u64::from_le_bytes(timestamp_bytes);
+        if frame_size > MAX_IVF_FRAME_SIZE {
+            return Err(Error::Other(format!(
+                "IVF frame of {} bytes is too large",
+                frame_size
+            )));
+        }
+
+        let mut data = vec![0u8; frame_size as usize];
+        if !read_exact_or_eof(&mut self.reader, &mut data)? {
+            // a truncated last frame, as left by an interrupted recording
+            return Ok(None);
+        }
+
+        let first_timestamp = *self.first_timestamp.get_or_insert(timestamp);
+        let timestamp = (timestamp.saturating_sub(first_timestamp) as u128
+            * VIDEO_CLOCK_RATE
+            * self.timebase_numerator
+            / self.timebase_denominator) as u64;
+        Ok(Some(Frame {
+            data: Bytes::from(data),
+            timestamp,
+        }))
+    }
+
+    fn rewind(&mut self) -> Result<()> {
+        self.reader.seek(SeekFrom::Start(self.header_size))?;
+        Ok(())
+    }
+}
+
+/// OggReader reads Opus packets from an Ogg file, RFC 7845, their timestamps being the number
+/// of 48 kHz samples before them
+pub(crate) struct OggReader<R: Read + Seek> {
+    reader: R,
+    /// position of the first page after the OpusHead and OpusTags headers
+    data_start: u64,
+    channels: u8,
+    pre_skip: u16,
+    packets: VecDeque<Vec<u8>>,
+    partial_packet: Vec<u8>,
+    timestamp: u64,
+}
+
+impl<R: Read + Seek> OggReader<R> {
+    pub(crate) fn new(reader: R) -> Result<Self> {
+        let mut ogg_reader = Self {
+            reader,
+            data_start: 0,
+            channels: 0,
+            pre_skip: 0,
+            packets: VecDeque::new(),
+            partial_packet: vec![],
+            timestamp: 0,
+        };
+
+        let head = ogg_reader.next_packet()?.unwrap_or_default();
+        if head.len() < 19 || &head[0..8] != OPUS_HEAD {
+            return Err(Error::Other("not an Ogg Opus file".to_string()));
+        }
+        ogg_reader.channels = head[9];
+        ogg_reader.pre_skip = u16::from_le_bytes([head[10], head[11]]);
+        let tags = ogg_reader.next_packet()?.unwrap_or_default();
+        if tags.len() < 8 || &tags[0..8] != OPUS_TAGS {
+            return Err(Error::Other(
+                "missing OpusTags in Ogg Opus file".to_string(),
+            ));
+        }
+        // audio data starts on a page of its own
+        ogg_reader.data_start = ogg_reader.reader.stream_position()?;
+
+        Ok(ogg_reader)
+    }
+
+    pub(crate) fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(packet) = self.packets.pop_front() {
+                return Ok(Some(packet));
+            }
+            if !self.read_page()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// read_page reads the next page, splitting its segments into packets, a packet being
+    /// continued on the next page when its last lacing value is 255
+    fn read_page(&mut self) -> Result<bool> {
+        let mut page_header = [0u8; OGG_PAGE_HEADER_SIZE];
+        if !read_exact_or_eof(&mut self.reader, &mut page_header)? {
+            return Ok(false);
+        }
+        if &page_header[0..4] != OGG_CAPTURE_PATTERN {
+            return Err(Error::Other("invalid Ogg page".to_string()));
+        }
+        let mut segment_table = vec![0u8; page_header[26] as usize];
+        if !read_exact_or_eof(&mut self.reader, &mut segment_table)? {
+            return Ok(false);
+        }
+        let page_size = segment_table.iter().map(|&lacing| lacing as usize).sum();
+        let mut payload = vec![0u8; page_size];
+        if !read_exact_or_eof(&mut self.reader, &mut payload)? {
+            return Ok(false);
+        }
+
+        let mut offset = 0;
+        for lacing in segment_table {
+            let lacing = lacing as usize;
+            self.partial_packet
+                .extend_from_slice(&payload[offset..offset + lacing]);
+            offset += lacing;
+            if lacing < 255 {
+                self.packets
+                    .push_back(std::mem::take(&mut self.partial_packet));
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read + Seek> MediaReader for OggReader<R> {
+    fn next_frame(&mut self) -> Result<Option<Frame>> {
+        while let Some(packet) = self.next_packet()? {
+            if packet.is_empty() {
+                continue;
+            }
+            let timestamp = self.timestamp;
+            self.timestamp += opus_samples(&packet) as u64;
+            return Ok(Some(Frame {
+                data: Bytes::from(packet),
+                timestamp,
+            }));
+        }
+        Ok(None)
+    }
+
+    fn rewind(&mut self) -> Result<()> {
+        self.reader.seek(SeekFrom::Start(self.data_start))?;
+        self.packets.clear();
+        self.partial_packet.clear();
+        self.timestamp = 0;
+        Ok(())
+    }
+
+    fn pre_skip(&self) -> u64 {
+        // always counted at 48 kHz, the clock rate of Opus over RTP
+        self.pre_skip as u64
+    }
+}
+
+/// read_exact_or_eof fills the buffer, and returns false when the end of the file is reached
+/// before
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::writer::{IvfWriter, MediaWriter, OggWriter};
+    use std::io::Cursor;
+
+    /// 20 ms Opus packet, TOC config 31 with one frame
+    const OPUS_PACKET: [u8; 3] = [0xF8, 0xAA, 0xBB];
+
+    fn ivf_file(frames: &[(&[u8], u64)]) -> Vec<u8> {
+        let mut output = Cursor::new(vec![]);
+        let mut writer = IvfWriter::new(&mut output, *b"VP80", 1000).unwrap();
+        for (frame, timestamp) in frames {
+            writer.write_frame(frame, *timestamp).unwrap();
+        }
+        writer.close().unwrap();
+        output.into_inner()
+    }
+
+    fn ogg_file(packet_count: usize, pre_skip: u16) -> Vec<u8> {
+        let mut output = vec![];
+        let mut writer = OggWriter::new(&mut output, 2).unwrap();
+        for i in 0..packet_count {
+            writer.write_frame(&OPUS_PACKET, i as u64 * 960).unwrap();
+        }
+        writer.close().unwrap();
+        // the reader doesn't check the checksums, the pre-skip follows the page header, its
+        // single lacing value, and the magic, version and channel count of OpusHead
+        output[OGG_PAGE_HEADER_SIZE + 1 + 10..OGG_PAGE_HEADER_SIZE + 1 + 12]
+            .copy_from_slice(&pre_skip.to_le_bytes());
+        output
+    }
+
+    fn read_frames(reader: &mut dyn MediaReader) -> Vec<(Vec<u8>, u64)> {
+        let mut frames = vec![];
+        while let Some(frame) = reader.next_frame().unwrap() {
+            frames.push((frame.data.to_vec(), frame.timestamp));
+        }
+        frames
+    }
+
+    #[test]
+    fn reads_ivf_frames_at_the_video_clock_rate() {
+        let file = ivf_file(&[(&[1, 2, 3], 100), (&[4, 5], 133)]);
+        let mut reader = IvfReader::new(Cursor::new(file)).unwrap();
+        let frames = vec![(vec![1, 2, 3], 0), (vec![4, 5], 2970)];
+        assert_eq!(read_frames(&mut reader), frames);
+
+        reader.rewind().unwrap();
+        assert_eq!(read_frames(&mut reader), frames);
+    }
+
+    #[test]
+    fn rejects_truncated_ivf_headers() {
+        let file = ivf_file(&[]);
+        assert!(IvfReader::new(Cursor::new(file[..IVF_HEADER_SIZE - 1].to_vec())).is_err());
+
+        let mut file = ivf_file(&[]);
+        file[8..12].copy_from_slice(b"VP90");
+        assert!(IvfReader::new(Cursor::new(file)).is_err());
+    }
+
+    #[test]
+    fn ends_ivf_files_at_a_truncated_frame() {
+        let file = ivf_file(&[(&[1, 2, 3], 0), (&[4, 5, 6], 33)]);
+        let mut reader = IvfReader::new(Cursor::new(file[..file.len() - 1].to_vec())).unwrap();
+        assert_eq!(read_frames(&mut reader), vec![(vec![1, 2, 3], 0)]);
+
+        let mut reader = IvfReader::new(Cursor::new(file[..IVF_HEADER_SIZE + 5].to_vec())).unwrap();
+        assert!(read_frames(&mut reader).is_empty());
+    }
+
+    #[test]
+    fn rejects_oversized_ivf_frames() {
+        let mut file = ivf_file(&[(&[1, 2, 3], 0)]);
+        file[IVF_HEADER_SIZE..IVF_HEADER_SIZE + 4]
+            .copy_from_slice(&(MAX_IVF_FRAME_SIZE + 1).to_le_bytes());
+        let mut reader = IvfReader::new(Cursor::new(file)).unwrap();
+        assert!(reader.next_frame().is_err());
+    }
+
+    #[test]
+    fn reads_opus_packets_from_ogg_pages() {
+        let mut reader = OggReader::new(Cursor::new(ogg_file(3, 312))).unwrap();
+        assert_eq!(reader.channels(), 2);
+        assert_eq!(reader.pre_skip(), 312);
+        let frames = vec![
+            (OPUS_PACKET.to_vec(), 0),
+            (OPUS_PACKET.to_vec(), 960),
+            (OPUS_PACKET.to_vec(), 1920),
+        ];
+        assert_eq!(read_frames(&mut reader), frames);
+
+        reader.rewind().unwrap();
+        assert_eq!(read_frames(&mut reader), frames);
+    }
+
+    #[test]
+    fn rejects_truncated_ogg_headers() {
+        let file = ogg_file(1, 0);
+        // OpusHead is cut short
+        assert!(OggReader::new(Cursor::new(file[..OGG_PAGE_HEADER_SIZE + 10].to_vec())).is_err());
+        // OpusTags is missing
+        let opus_head_end = OGG_PAGE_HEADER_SIZE + 1 + 19;
+        assert!(OggReader::new(Cursor::new(file[..opus_head_end].to_vec())).is_err());
+
+        let mut file = ogg_file(1, 0);
+        file[0..4].copy_from_slice(b"RIFF");
+        assert!(OggReader::new(Cursor::new(file)).is_err());
+    }
+
+    #[test]
+    fn ends_ogg_files_at_a_truncated_page() {
+        let file = ogg_file(2, 0);
+        // the end of stream page and the last byte of the second packet are cut
+        let end_of_stream_page_size = OGG_PAGE_HEADER_SIZE + 1;
+        let file = file[..file.len() - end_of_stream_page_size - 1].to_vec();
+        let mut reader = OggReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(read_frames(&mut reader), vec![(OPUS_PACKET.to_vec(), 0)]);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::description::rtp_codec::{
+    RTCRtpCodecParameters, RTCRtpHeaderExtensionParameters, RTPCodecType,
+};
+use crate::messages::TaggedMessageEvent;
+use crate::stats::EndpointStats;
+use crate::types::FourTuple;
+use serde::Serialize;
+use std::time::Instant;
+
+pub(crate) mod nack;
+pub(crate) mod report;
+pub(crate) mod twcc;
+
+pub enum InterceptorEvent {
+    Inbound(TaggedMessageEvent),
+    Outbound(TaggedMessageEvent),
+    Error(Box<dyn std::error::Error>),
+}
+
+/// StreamInfo describes a stream sent to the endpoint, as negotiated in its SDP
+#[derive(Default, Debug, Clone)]
+pub struct StreamInfo {
+    pub ssrc: u32,
+    /// SSRC of the retransmissions of the stream, when announced with a FID group
+    pub ssrc_rtx: Option<u32>,
+    pub mid: String,
+    pub kind: RTPCodecType,
+    pub rtp_header_extensions: Vec<RTCRtpHeaderExtensionParameters>,
+    pub codecs: Vec<RTCRtpCodecParameters>,
+}
+
+/// BandwidthEstimateSource is the kind of feedback a bandwidth estimate comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BandwidthEstimateSource {
+    /// transport wide congestion control feedback
+    Twcc,
+    /// receiver estimated maximum bitrate sent by the endpoint
+    Remb,
+}
+
+/// BandwidthEstimate is the bandwidth available towards the endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BandwidthEstimate {
+    /// bits per second
+    pub bitrate: u64,
+    pub source: BandwidthEstimateSource,
+}
+
+pub trait Interceptor {
+    fn chain(self: Box<Self>, next: Box<dyn Interceptor>) -> Box<dyn Interceptor>;
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>>;
+
+    fn read(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        if let Some(next) = self.next() {
+            next.read(msg)
+        } else {
+            vec![]
+        }
+    }
+    fn write(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        if let Some(next) = self.next() {
+            next.write(msg)
+        } else {
+            vec![]
+        }
+    }
+
+    fn handle_timeout(&mut self, now: Instant, four_tuples: &[FourTuple]) -> Vec<InterceptorEvent> {
+        if let Some(next) = self.next() {
+            next.handle_timeout(now, four_tuples)
+        } else {
+            vec![]
+        }
+    }
+
+    fn poll_timeout(&mut self, eto: &mut Instant) {
+        if let Some(next) = self.next() {
+            next.poll_timeout(eto);
+        }
+    }
+
+    /// bind_local_stream lets the interceptor know about a new stream sent to the endpoint
+    fn bind_local_stream(&mut self, info: &StreamInfo) {
+        if let Some(next) = self.next() {
+            next.bind_local_stream(info);
+        }
+    }
+
+    /// collect_stats adds the counters of the interceptor to the stats of the endpoint
+    fn collect_stats(&mut self, stats: &mut EndpointStats) {
+        if let Some(next) = self.next() {
+            next.collect_stats(stats);
+        }
+    }
+
+    /// bandwidth_estimate returns the bandwidth available towards the endpoint, if estimated
+    fn bandwidth_estimate(&mut self) -> Option<BandwidthEstimate> {
+        if let Some(next) = self.next() {
+            next.bandwidth_estimate()
+        } else {
+            None
+        }
+    }
+}
+
+/// InterceptorBuilder provides an interface for constructing interceptors
+pub trait InterceptorBuilder {
+    fn build(&self, id: &str) -> Box<dyn Interceptor>;
+}
+
+/// Registry is a collector for interceptors.
+#[derive(Default)]
+pub struct Registry {
+    builders: Vec<Box<dyn InterceptorBuilder + Send + Sync>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// add a new InterceptorBuilder to the registry.
+    pub fn add(&mut self, builder: Box<dyn InterceptorBuilder + Send + Sync>) {
+        self.builders.push(builder);
+    }
+
+    /// build a single Interceptor from an InterceptorRegistry
+    pub fn build(&self, id: &str) -> Box<dyn Interceptor> {
+        let mut next = Box::new(NoOp) as Box<dyn Interceptor>;
+        for interceptor in self.builders.iter().rev().map(|b| b.build(id)) {
+            next = interceptor.chain(next);
+        }
+        next
+    }
+}
+
+/// NoOp is an Interceptor that does not modify any packets. It can be embedded in other interceptors, so it's
+/// possible to implement only a subset of the methods.
+struct NoOp;
+
+impl Interceptor for NoOp {
+    fn chain(self: Box<Self>, _next: Box<dyn Interceptor>) -> Box<dyn Interceptor> {
+        self
+    }
+
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>> {
+        None
+    }
+}
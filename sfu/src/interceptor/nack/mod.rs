@@ -0,0 +1,34 @@
+use crate::description::config::DEFAULT_NACK_BUFFER_SIZE;
+use crate::interceptor::{Interceptor, InterceptorBuilder};
+use std::collections::HashMap;
+
+pub(crate) mod responder;
+pub(crate) mod send_buffer;
+
+use responder::Responder;
+
+/// ResponderBuilder can be used to configure the NACK Responder Interceptor.
+#[derive(Default)]
+pub struct ResponderBuilder {
+    size: Option<u16>,
+}
+
+impl ResponderBuilder {
+    /// with_size sets the number of packets kept per stream, rounded up to a power of two.
+    pub fn with_size(mut self, size: u16) -> ResponderBuilder {
+        self.size = Some(size);
+        self
+    }
+}
+
+impl InterceptorBuilder for ResponderBuilder {
+    fn build(&self, _id: &str) -> Box<dyn Interceptor> {
+        Box::new(Responder {
+            size: self.size.unwrap_or(DEFAULT_NACK_BUFFER_SIZE),
+            streams: HashMap::new(),
+            nacks_served_locally: 0,
+            nacks_forwarded: 0,
+            next: None,
+        })
+    }
+}
@@ -0,0 +1,205 @@
+use crate::description::config::MIME_TYPE_RTX;
+use crate::description::fmtp;
+use crate::interceptor::nack::send_buffer::SendBuffer;
+use crate::interceptor::nack::ResponderBuilder;
+use crate::interceptor::{Interceptor, InterceptorEvent, StreamInfo};
+use crate::messages::{MessageEvent, RTPMessageEvent, TaggedMessageEvent};
+use crate::stats::EndpointStats;
+use bytes::{BufMut, BytesMut};
+use log::trace;
+use rtcp::transport_feedbacks::transport_layer_nack::{
+    nack_pairs_from_sequence_numbers, TransportLayerNack,
+};
+use std::collections::HashMap;
+
+/// ResponderStream keeps the packets recently sent on a stream, and how to retransmit them
+pub(crate) struct ResponderStream {
+    send_buffer: SendBuffer,
+    /// RTX SSRC and RTX payload types by payload type, when negotiated
+    rtx: Option<(u32, HashMap<u8, u8>)>,
+    rtx_sequence_number: u16,
+}
+
+impl ResponderStream {
+    /// retransmission returns the packet to send again, wrapped in RTX when negotiated
+    fn retransmission(&mut self, sequence_number: u16) -> Option<rtp::packet::Packet> {
+        let packet = self.send_buffer.get(sequence_number)?;
+        let Some((ssrc_rtx, payload_types_rtx)) = &self.rtx else {
+            return Some(packet.clone());
+        };
+        let Some(&payload_type_rtx) = payload_types_rtx.get(&packet.header.payload_type) else {
+            return Some(packet.clone());
+        };
+
+        // RTX payload starts with the original sequence number, RFC 4588
+        let mut payload = BytesMut::with_capacity(2 + packet.payload.len());
+        payload.put_u16(packet.header.sequence_number);
+        payload.put_slice(&packet.payload);
+
+        let mut header = packet.header.clone();
+        header.ssrc = *ssrc_rtx;
+        header.payload_type = payload_type_rtx;
+        header.sequence_number = self.rtx_sequence_number;
+        self.rtx_sequence_number = self.rtx_sequence_number.wrapping_add(1);
+
+        Some(rtp::packet::Packet {
+            header,
+            payload: payload.freeze(),
+        })
+    }
+}
+
+/// Responder keeps the packets sent to the endpoint to answer its NACKs, only the NACKs for
+/// packets no longer kept are forwarded to the publishers.
+pub(crate) struct Responder {
+    pub(super) size: u16,
+    pub(super) streams: HashMap<u32, ResponderStream>,
+    pub(super) nacks_served_locally: u64,
+    pub(super) nacks_forwarded: u64,
+    pub(super) next: Option<Box<dyn Interceptor>>,
+}
+
+impl Responder {
+    pub(crate) fn builder() -> ResponderBuilder {
+        ResponderBuilder::default()
+    }
+
+    fn on_transport_layer_nack(
+        &mut self,
+        msg: &TaggedMessageEvent,
+        nack: &TransportLayerNack,
+    ) -> Vec<InterceptorEvent> {
+        let mut interceptor_events = vec![];
+
+        let mut missing_sequence_numbers = vec![];
+        for nack_pair in &nack.nacks {
+            for sequence_number in nack_pair.packet_list() {
+                match self
+                    .streams
+                    .get_mut(&nack.media_ssrc)
+                    .and_then(|stream| stream.retransmission(sequence_number))
+                {
+                    Some(packet) => {
+                        self.nacks_served_locally += 1;
+                        interceptor_events.push(InterceptorEvent::Outbound(TaggedMessageEvent {
+                            now: msg.now,
+                            transport: msg.transport,
+                            message: MessageEvent::Rtp(RTPMessageEvent::Rtp(packet)),
+                        }));
+                    }
+                    None => missing_sequence_numbers.push(sequence_number),
+                }
+            }
+        }
+
+        if !missing_sequence_numbers.is_empty() {
+            trace!(
+                "forward nack of {} packets of ssrc {}",
+                missing_sequence_numbers.len(),
+                nack.media_ssrc
+            );
+            self.nacks_forwarded += missing_sequence_numbers.len() as u64;
+            interceptor_events.push(InterceptorEvent::Inbound(TaggedMessageEvent {
+                now: msg.now,
+                transport: msg.transport,
+                message: MessageEvent::Rtp(RTPMessageEvent::Rtcp(vec![Box::new(
+                    TransportLayerNack {
+                        sender_ssrc: nack.sender_ssrc,
+                        media_ssrc: nack.media_ssrc,
+                        nacks: nack_pairs_from_sequence_numbers(&missing_sequence_numbers),
+                    },
+                )])),
+            }));
+        }
+
+        interceptor_events
+    }
+}
+
+impl Interceptor for Responder {
+    fn chain(mut self: Box<Self>, next: Box<dyn Interceptor>) -> Box<dyn Interceptor> {
+        self.next = Some(next);
+        self
+    }
+
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>> {
+        self.next.as_mut()
+    }
+
+    fn read(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        let mut interceptor_events = vec![];
+
+        if let MessageEvent::Rtp(RTPMessageEvent::Rtcp(rtcp_packets)) = &msg.message {
+            for rtcp_packet in rtcp_packets {
+                if let Some(nack) = rtcp_packet.as_any().downcast_ref::<TransportLayerNack>() {
+                    let mut events = self.on_transport_layer_nack(msg, nack);
+                    interceptor_events.append(&mut events);
+                }
+            }
+        }
+
+        if let Some(next) = self.next() {
+            let mut events = next.read(msg);
+            interceptor_events.append(&mut events);
+        }
+        interceptor_events
+    }
+
+    fn write(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        if let MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet)) = &msg.message {
+            if let Some(stream) = self.streams.get_mut(&rtp_packet.header.ssrc) {
+                stream.send_buffer.add(rtp_packet);
+            }
+        }
+
+        if let Some(next) = self.next() {
+            next.write(msg)
+        } else {
+            vec![]
+        }
+    }
+
+    fn bind_local_stream(&mut self, info: &StreamInfo) {
+        let payload_types_rtx: HashMap<u8, u8> = info
+            .codecs
+            .iter()
+            .filter(|codec| {
+                codec
+                    .capability
+                    .mime_type
+                    .eq_ignore_ascii_case(MIME_TYPE_RTX)
+            })
+            .filter_map(|codec| {
+                let fmtp =
+                    fmtp::parse(&codec.capability.mime_type, &codec.capability.sdp_fmtp_line);
+                let payload_type = fmtp.parameter("apt")?.parse::<u8>().ok()?;
+                Some((payload_type, codec.payload_type))
+            })
+            .collect();
+
+        self.streams.insert(
+            info.ssrc,
+            ResponderStream {
+                send_buffer: SendBuffer::new(self.size),
+                rtx: info
+                    .ssrc_rtx
+                    .filter(|_| !payload_types_rtx.is_empty())
+                    .map(|ssrc_rtx| (ssrc_rtx, payload_types_rtx)),
+                rtx_sequence_number: rand::random::<u16>(),
+            },
+        );
+
+        if let Some(next) = self.next() {
+            next.bind_local_stream(info);
+        }
+    }
+
+    fn collect_stats(&mut self, stats: &mut EndpointStats) {
+        stats.nacks_served_locally += self.nacks_served_locally;
+        stats.nacks_forwarded += self.nacks_forwarded;
+
+        if let Some(next) = self.next() {
+            next.collect_stats(stats);
+        }
+    }
+}
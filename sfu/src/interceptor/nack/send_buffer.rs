@@ -0,0 +1,123 @@
+use rtp::packet::Packet;
+
+const UINT16_SIZE_HALF: u16 = 1 << 15;
+
+/// SendBuffer keeps the last packets sent on a stream, indexed by sequence number
+pub(crate) struct SendBuffer {
+    packets: Vec<Option<Packet>>,
+    size: u16,
+    last_added: Option<u16>,
+}
+
+impl SendBuffer {
+    /// new creates a buffer for size packets, rounded up to a power of two
+    pub(crate) fn new(size: u16) -> Self {
+        let size = size.clamp(1, UINT16_SIZE_HALF).next_power_of_two();
+        Self {
+            packets: vec![None; size as usize],
+            size,
+            last_added: None,
+        }
+    }
+
+    pub(crate) fn add(&mut self, packet: &Packet) {
+        let sequence_number = packet.header.sequence_number;
+        let is_newer = self.last_added.map_or(true, |last_added| {
+            let diff = sequence_number.wrapping_sub(last_added);
+            diff != 0 && diff < UINT16_SIZE_HALF
+        });
+        if is_newer {
+            self.last_added = Some(sequence_number);
+        }
+        self.packets[(sequence_number % self.size) as usize] = Some(packet.clone());
+    }
+
+    pub(crate) fn get(&self, sequence_number: u16) -> Option<&Packet> {
+        let last_added = self.last_added?;
+        let diff = last_added.wrapping_sub(sequence_number);
+        if diff >= UINT16_SIZE_HALF || diff >= self.size {
+            return None;
+        }
+
+        self.packets[(sequence_number % self.size) as usize]
+            .as_ref()
+            .filter(|packet| packet.header.sequence_number == sequence_number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence_number: u16) -> Packet {
+        let mut packet = Packet::default();
+        packet.header.sequence_number = sequence_number;
+        packet
+    }
+
+    fn sequence_number(send_buffer: &SendBuffer, sequence_number: u16) -> Option<u16> {
+        send_buffer
+            .get(sequence_number)
+            .map(|packet| packet.header.sequence_number)
+    }
+
+    #[test]
+    fn rounds_size_up_to_a_power_of_two() {
+        assert_eq!(SendBuffer::new(0).size, 1);
+        assert_eq!(SendBuffer::new(100).size, 128);
+        assert_eq!(SendBuffer::new(u16::MAX).size, UINT16_SIZE_HALF);
+    }
+
+    #[test]
+    fn keeps_the_last_packets() {
+        let mut send_buffer = SendBuffer::new(8);
+        assert!(send_buffer.get(0).is_none());
+        for sequence_number in 0..20 {
+            send_buffer.add(&packet(sequence_number));
+        }
+
+        for sequence_number in 12..20 {
+            assert_eq!(
+                self::sequence_number(&send_buffer, sequence_number),
+                Some(sequence_number)
+            );
+        }
+        // overwritten, and not sent yet
+        assert!(send_buffer.get(11).is_none());
+        assert!(send_buffer.get(4).is_none());
+        assert!(send_buffer.get(20).is_none());
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut send_buffer = SendBuffer::new(8);
+        for sequence_number in 65530..=65535 {
+            send_buffer.add(&packet(sequence_number));
+        }
+        for sequence_number in 0..3 {
+            send_buffer.add(&packet(sequence_number));
+        }
+
+        assert_eq!(sequence_number(&send_buffer, 65534), Some(65534));
+        assert_eq!(sequence_number(&send_buffer, 2), Some(2));
+        assert!(send_buffer.get(65530).is_none());
+        assert!(send_buffer.get(3).is_none());
+    }
+
+    #[test]
+    fn keeps_reordered_packets() {
+        let mut send_buffer = SendBuffer::new(8);
+        for sequence_number in [1, 3, 2, 5] {
+            send_buffer.add(&packet(sequence_number));
+        }
+
+        assert_eq!(sequence_number(&send_buffer, 2), Some(2));
+        assert_eq!(sequence_number(&send_buffer, 5), Some(5));
+        // never sent
+        assert!(send_buffer.get(4).is_none());
+        // a late packet doesn't move the newest one back
+        send_buffer.add(&packet(4));
+        assert_eq!(sequence_number(&send_buffer, 5), Some(5));
+        assert_eq!(sequence_number(&send_buffer, 4), Some(4));
+    }
+}
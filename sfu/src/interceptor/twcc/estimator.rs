@@ -0,0 +1,366 @@
+use crate::interceptor::{BandwidthEstimate, BandwidthEstimateSource};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// number of delay samples the trend of the queuing delay is computed over
+const TRENDLINE_WINDOW: usize = 20;
+const TRENDLINE_SMOOTHING: f64 = 0.9;
+const TRENDLINE_GAIN: f64 = 4.0;
+/// trend of the queuing delay, in ms, above which the path is considered overused
+const OVERUSE_THRESHOLD: f64 = 12.5;
+/// window over which the bitrate acknowledged by the feedback is measured
+const ACKED_BITRATE_WINDOW: Duration = Duration::from_millis(500);
+/// multiplicative increase per second while the path is not congested
+const INCREASE_RATE: f64 = 0.08;
+/// the estimate is set to this ratio of the acknowledged bitrate on overuse
+const DECREASE_FACTOR: f64 = 0.85;
+/// the estimate isn't decreased more than once in this interval, the time for the previous
+/// decrease to show up in the feedback
+const DECREASE_INTERVAL: Duration = Duration::from_millis(300);
+const LOSS_LOW: f64 = 0.02;
+const LOSS_HIGH: f64 = 0.1;
+/// REMB is ignored as long as TWCC feedback was received within this timeout
+const TWCC_FEEDBACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// PacketResult is the fate of a sent packet as reported by TWCC feedback
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PacketResult {
+    pub(crate) sent_at: Instant,
+    pub(crate) size: usize,
+    /// arrival time in microseconds on the remote clock, None if the packet was lost
+    pub(crate) arrival: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BandwidthUsage {
+    Normal,
+    Overusing,
+    Underusing,
+}
+
+/// Estimator is a simplified Google Congestion Control: a delay based controller detecting
+/// the growth of the queuing delay from TWCC feedback, combined with a loss based one. REMB
+/// is used as is when the endpoint doesn't send TWCC feedback.
+pub(crate) struct Estimator {
+    bitrate: u64,
+    min_bitrate: u64,
+    max_bitrate: u64,
+    source: Option<BandwidthEstimateSource>,
+
+    last_twcc_feedback: Option<Instant>,
+    last_update: Option<Instant>,
+    last_decrease: Option<Instant>,
+
+    previous_packet: Option<(Instant, i64)>,
+    first_arrival: Option<i64>,
+    accumulated_delay: f64,
+    smoothed_delay: f64,
+    delays: VecDeque<(f64, f64)>,
+    acked_packets: VecDeque<(i64, usize)>,
+}
+
+impl Estimator {
+    pub(crate) fn new(initial_bitrate: u64, min_bitrate: u64, max_bitrate: u64) -> Self {
+        Self {
+            bitrate: initial_bitrate.clamp(min_bitrate, max_bitrate),
+            min_bitrate,
+            max_bitrate,
+            source: None,
+
+            last_twcc_feedback: None,
+            last_update: None,
+            last_decrease: None,
+
+            previous_packet: None,
+            first_arrival: None,
+            accumulated_delay: 0.0,
+            smoothed_delay: 0.0,
+            delays: VecDeque::new(),
+            acked_packets: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn estimate(&self) -> Option<BandwidthEstimate> {
+        self.source.map(|source| BandwidthEstimate {
+            bitrate: self.bitrate,
+            source,
+        })
+    }
+
+    /// on_twcc_feedback updates the estimate with the packets reported by one TWCC feedback,
+    /// in sequence number order
+    pub(crate) fn on_twcc_feedback(&mut self, now: Instant, packet_results: &[PacketResult]) {
+        let (mut received, mut lost) = (0usize, 0usize);
+        for packet_result in packet_results {
+            let Some(arrival) = packet_result.arrival else {
+                lost += 1;
+                continue;
+            };
+            received += 1;
+            self.acked_packets.push_back((arrival, packet_result.size));
+
+            match self.previous_packet {
+                Some((previous_sent_at, previous_arrival)) => {
+                    // reordered packets don't tell anything about the queuing delay
+                    if packet_result.sent_at < previous_sent_at {
+                        continue;
+                    }
+                    let send_delta = packet_result
+                        .sent_at
+                        .duration_since(previous_sent_at)
+                        .as_secs_f64()
+                        * 1000.0;
+                    let arrival_delta = (arrival - previous_arrival) as f64 / 1000.0;
+                    self.update_trendline(arrival, arrival_delta - send_delta);
+                }
+                None => self.first_arrival = Some(arrival),
+            }
+            self.previous_packet = Some((packet_result.sent_at, arrival));
+        }
+        if received == 0 && lost == 0 {
+            return;
+        }
+
+        let acked_bitrate = self.acked_bitrate();
+        let loss = lost as f64 / (received + lost) as f64;
+        let elapsed = self
+            .last_update
+            .map(|last_update| now.duration_since(last_update).min(Duration::from_secs(1)))
+            .unwrap_or_default();
+        let can_decrease = self.last_decrease.map_or(true, |last_decrease| {
+            now.duration_since(last_decrease) >= DECREASE_INTERVAL
+        });
+
+        let mut bitrate = self.bitrate as f64;
+        match self.detect() {
+            BandwidthUsage::Overusing if can_decrease => {
+                bitrate = acked_bitrate.map_or(bitrate, |acked| acked as f64) * DECREASE_FACTOR;
+                self.last_decrease = Some(now);
+            }
+            BandwidthUsage::Normal if loss < LOSS_LOW => {
+                bitrate *= 1.0 + INCREASE_RATE * elapsed.as_secs_f64();
+            }
+            _ => {}
+        }
+        if loss > LOSS_HIGH && can_decrease {
+            bitrate *= 1.0 - 0.5 * loss;
+            self.last_decrease = Some(now);
+        }
+
+        self.bitrate = (bitrate as u64).clamp(self.min_bitrate, self.max_bitrate);
+        self.source = Some(BandwidthEstimateSource::Twcc);
+        self.last_twcc_feedback = Some(now);
+        self.last_update = Some(now);
+    }
+
+    /// on_remb takes the bitrate estimated by the endpoint, unless TWCC feedback is received
+    pub(crate) fn on_remb(&mut self, now: Instant, bitrate: u64) {
+        let has_twcc_feedback = self.last_twcc_feedback.is_some_and(|last_twcc_feedback| {
+            now.duration_since(last_twcc_feedback) < TWCC_FEEDBACK_TIMEOUT
+        });
+        if has_twcc_feedback {
+            return;
+        }
+
+        self.bitrate = bitrate.clamp(self.min_bitrate, self.max_bitrate);
+        self.source = Some(BandwidthEstimateSource::Remb);
+        self.last_update = Some(now);
+    }
+
+    fn update_trendline(&mut self, arrival: i64, delay_delta: f64) {
+        self.accumulated_delay += delay_delta;
+        self.smoothed_delay = TRENDLINE_SMOOTHING * self.smoothed_delay
+            + (1.0 - TRENDLINE_SMOOTHING) * self.accumulated_delay;
+
+        let arrival_ms = (arrival - self.first_arrival.unwrap_or(arrival)) as f64 / 1000.0;
+        self.delays.push_back((arrival_ms, self.smoothed_delay));
+        if self.delays.len() > TRENDLINE_WINDOW {
+            self.delays.pop_front();
+        }
+    }
+
+    /// detect compares the slope of the smoothed queuing delay to the overuse threshold
+    fn detect(&self) -> BandwidthUsage {
+        if self.delays.len() < TRENDLINE_WINDOW {
+            return BandwidthUsage::Normal;
+        }
+
+        let count = self.delays.len() as f64;
+        let mean_x = self.delays.iter().map(|(x, _)| x).sum::<f64>() / count;
+        let mean_y = self.delays.iter().map(|(_, y)| y).sum::<f64>() / count;
+        let (mut numerator, mut denominator) = (0.0, 0.0);
+        for (x, y) in &self.delays {
+            numerator += (x - mean_x) * (y - mean_y);
+            denominator += (x - mean_x) * (x - mean_x);
+        }
+        if denominator == 0.0 {
+            return BandwidthUsage::Normal;
+        }
+
+        let trend = numerator / denominator * count * TRENDLINE_GAIN;
+        if trend > OVERUSE_THRESHOLD {
+            BandwidthUsage::Overusing
+        } else if trend < -OVERUSE_THRESHOLD {
+            BandwidthUsage::Underusing
+        } else {
+            BandwidthUsage::Normal
+        }
+    }
+
+    /// acked_bitrate is the bitrate received by the endpoint over the last window
+    fn acked_bitrate(&mut self) -> Option<u64> {
+        let &(last_arrival, _) = self.acked_packets.back()?;
+        let window = ACKED_BITRATE_WINDOW.as_micros() as i64;
+        while self
+            .acked_packets
+            .front()
+            .is_some_and(|&(arrival, _)| last_arrival - arrival > window)
+        {
+            self.acked_packets.pop_front();
+        }
+
+        let &(first_arrival, _) = self.acked_packets.front()?;
+        let span = last_arrival - first_arrival;
+        if span < window / 5 {
+            return None;
+        }
+        let bytes: usize = self.acked_packets.iter().map(|&(_, size)| size).sum();
+        Some((bytes as i64 * 8 * 1_000_000 / span) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKET_SIZE: usize = 1200;
+
+    /// feed sends `rounds` feedbacks of 10 packets sent 10ms apart, each one arriving
+    /// `queuing(index)` ms later than it would on an empty path, and returns the last estimate
+    fn feed(
+        estimator: &mut Estimator,
+        start: Instant,
+        rounds: usize,
+        queuing: impl Fn(usize) -> f64,
+        lost: impl Fn(usize) -> bool,
+    ) -> u64 {
+        let mut index = 0;
+        for round in 0..rounds {
+            let packet_results: Vec<PacketResult> = (0..10)
+                .map(|_| {
+                    let sent = Duration::from_millis(index as u64 * 10);
+                    let arrival = sent.as_micros() as i64 + (queuing(index) * 1000.0) as i64;
+                    let packet_result = PacketResult {
+                        sent_at: start + sent,
+                        size: PACKET_SIZE,
+                        arrival: (!lost(index)).then_some(arrival),
+                    };
+                    index += 1;
+                    packet_result
+                })
+                .collect();
+            let now = start + Duration::from_millis((round as u64 + 1) * 100);
+            estimator.on_twcc_feedback(now, &packet_results);
+        }
+        estimator.estimate().unwrap().bitrate
+    }
+
+    #[test]
+    fn increases_on_a_stable_path() {
+        let mut estimator = Estimator::new(500_000, 100_000, 10_000_000);
+        assert!(estimator.estimate().is_none());
+
+        let bitrate = feed(&mut estimator, Instant::now(), 30, |_| 5.0, |_| false);
+        assert!(bitrate > 500_000, "{}", bitrate);
+        assert_eq!(
+            estimator.estimate().unwrap().source,
+            BandwidthEstimateSource::Twcc
+        );
+    }
+
+    #[test]
+    fn decreases_on_growing_queuing_delay() {
+        let mut estimator = Estimator::new(5_000_000, 100_000, 10_000_000);
+        // the queue grows by 2ms per packet sent, i.e. the path carries a fraction of the rate
+        let bitrate = feed(
+            &mut estimator,
+            Instant::now(),
+            5,
+            |index| index as f64 * 2.0,
+            |_| false,
+        );
+
+        // decreased below the acknowledged bitrate, 960 kbps
+        assert!(bitrate < 960_000, "{}", bitrate);
+        assert!(bitrate >= 100_000, "{}", bitrate);
+    }
+
+    #[test]
+    fn decreases_on_heavy_loss() {
+        let mut estimator = Estimator::new(1_000_000, 100_000, 10_000_000);
+        let bitrate = feed(
+            &mut estimator,
+            Instant::now(),
+            1,
+            |_| 5.0,
+            |index| index % 3 == 0,
+        );
+        assert!(bitrate < 1_000_000, "{}", bitrate);
+    }
+
+    #[test]
+    fn decreases_at_most_once_per_interval() {
+        let mut estimator = Estimator::new(1_000_000, 10_000, 10_000_000);
+        let start = Instant::now();
+        let lost = |index: usize| index % 2 == 0;
+        let first = feed(&mut estimator, start, 1, |_| 5.0, lost);
+        // feedback for the same time, before the previous decrease can show up
+        estimator.on_twcc_feedback(
+            start + Duration::from_millis(150),
+            &[PacketResult {
+                sent_at: start + Duration::from_millis(100),
+                size: PACKET_SIZE,
+                arrival: None,
+            }],
+        );
+        assert_eq!(estimator.estimate().unwrap().bitrate, first);
+    }
+
+    #[test]
+    fn stays_within_bounds() {
+        let mut estimator = Estimator::new(50_000_000, 100_000, 2_000_000);
+        assert_eq!(estimator.bitrate, 2_000_000);
+
+        let bitrate = feed(&mut estimator, Instant::now(), 100, |_| 5.0, |_| false);
+        assert_eq!(bitrate, 2_000_000);
+        let bitrate = feed(&mut estimator, Instant::now(), 20, |_| 5.0, |_| true);
+        assert_eq!(bitrate, 100_000);
+    }
+
+    #[test]
+    fn ignores_remb_while_twcc_feedback_is_received() {
+        let mut estimator = Estimator::new(1_000_000, 100_000, 10_000_000);
+        let start = Instant::now();
+        estimator.on_remb(start, 300_000);
+        assert_eq!(
+            estimator.estimate(),
+            Some(BandwidthEstimate {
+                bitrate: 300_000,
+                source: BandwidthEstimateSource::Remb
+            })
+        );
+
+        let bitrate = feed(&mut estimator, start, 1, |_| 5.0, |_| false);
+        estimator.on_remb(start + Duration::from_secs(1), 200_000);
+        assert_eq!(estimator.estimate().unwrap().bitrate, bitrate);
+
+        estimator.on_remb(start + Duration::from_secs(3), 200_000);
+        assert_eq!(
+            estimator.estimate(),
+            Some(BandwidthEstimate {
+                bitrate: 200_000,
+                source: BandwidthEstimateSource::Remb
+            })
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use crate::interceptor::{Interceptor, InterceptorBuilder};
+use std::collections::{HashMap, VecDeque};
+
+pub(crate) mod estimator;
+pub(crate) mod sender;
+
+use estimator::Estimator;
+use sender::Sender;
+
+/// SenderBuilder can be used to configure the TWCC Sender Interceptor.
+#[derive(Default)]
+pub struct SenderBuilder {
+    initial_bitrate: Option<u64>,
+    min_bitrate: Option<u64>,
+    max_bitrate: Option<u64>,
+}
+
+impl SenderBuilder {
+    /// with_initial_bitrate sets the bandwidth assumed when the first feedback arrives.
+    pub fn with_initial_bitrate(mut self, initial_bitrate: u64) -> SenderBuilder {
+        self.initial_bitrate = Some(initial_bitrate);
+        self
+    }
+
+    /// with_min_bitrate sets the lowest bandwidth the estimate can go down to.
+    pub fn with_min_bitrate(mut self, min_bitrate: u64) -> SenderBuilder {
+        self.min_bitrate = Some(min_bitrate);
+        self
+    }
+
+    /// with_max_bitrate sets the highest bandwidth the estimate can go up to.
+    pub fn with_max_bitrate(mut self, max_bitrate: u64) -> SenderBuilder {
+        self.max_bitrate = Some(max_bitrate);
+        self
+    }
+}
+
+impl InterceptorBuilder for SenderBuilder {
+    fn build(&self, _id: &str) -> Box<dyn Interceptor> {
+        Box::new(Sender {
+            streams: HashMap::new(),
+            sequence_number: 0,
+            sent_packets: VecDeque::new(),
+            estimator: Estimator::new(
+                self.initial_bitrate.unwrap_or(1_000_000),
+                self.min_bitrate.unwrap_or(30_000),
+                self.max_bitrate.unwrap_or(10_000_000),
+            ),
+            next: None,
+        })
+    }
+}
@@ -0,0 +1,190 @@
+use crate::interceptor::twcc::estimator::{Estimator, PacketResult};
+use crate::interceptor::twcc::SenderBuilder;
+use crate::interceptor::{BandwidthEstimate, Interceptor, InterceptorEvent, StreamInfo};
+use crate::messages::{MessageEvent, RTPMessageEvent, TaggedMessageEvent};
+use bytes::Bytes;
+use log::trace;
+use rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+use rtcp::transport_feedbacks::transport_layer_cc::{
+    PacketStatusChunk, SymbolTypeTcc, TransportLayerCc,
+};
+use sdp::extmap::TRANSPORT_CC_URI;
+use shared::marshal::MarshalSize;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// sent packets are kept until their feedback is received, or for this long
+const SENT_PACKETS_TIMEOUT: Duration = Duration::from_secs(2);
+/// bounded below the sequence number space for lookups to be unambiguous
+const MAX_SENT_PACKETS: usize = 1 << 14;
+/// reference time of TWCC feedback is expressed in multiples of 64ms
+const REFERENCE_TIME_UNIT: i64 = 64_000;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SentPacket {
+    sequence_number: u16,
+    sent_at: Instant,
+    size: usize,
+}
+
+/// Sender adds the transport wide sequence number header extension to the RTP packets sent to
+/// the endpoint, and estimates the bandwidth towards it from the TWCC feedback it sends back,
+/// or from its REMB when it doesn't support TWCC.
+pub(crate) struct Sender {
+    /// transport-cc header extension id by SSRC of the streams negotiating it
+    pub(super) streams: HashMap<u32, u8>,
+    pub(super) sequence_number: u16,
+    pub(super) sent_packets: VecDeque<SentPacket>,
+    pub(super) estimator: Estimator,
+    pub(super) next: Option<Box<dyn Interceptor>>,
+}
+
+impl Sender {
+    pub(crate) fn builder() -> SenderBuilder {
+        SenderBuilder::default()
+    }
+
+    fn get_sent_packet(&self, sequence_number: u16) -> Option<&SentPacket> {
+        let first = self.sent_packets.front()?;
+        let index = sequence_number.wrapping_sub(first.sequence_number) as usize;
+        self.sent_packets.get(index)
+    }
+
+    fn on_transport_layer_cc(&mut self, now: Instant, tcc: &TransportLayerCc) {
+        let mut symbols = Vec::with_capacity(tcc.packet_status_count as usize);
+        for chunk in &tcc.packet_chunks {
+            match chunk {
+                PacketStatusChunk::RunLengthChunk(chunk) => {
+                    for _ in 0..chunk.run_length {
+                        symbols.push(chunk.packet_status_symbol);
+                    }
+                }
+                PacketStatusChunk::StatusVectorChunk(chunk) => {
+                    symbols.extend_from_slice(&chunk.symbol_list);
+                }
+            }
+        }
+        // the last status vector chunk may be padded
+        symbols.truncate(tcc.packet_status_count as usize);
+
+        let mut arrival = tcc.reference_time as i64 * REFERENCE_TIME_UNIT;
+        let mut recv_deltas = tcc.recv_deltas.iter();
+        let mut packet_results = Vec::with_capacity(symbols.len());
+        for (i, symbol) in symbols.into_iter().enumerate() {
+            let received = match symbol {
+                SymbolTypeTcc::PacketReceivedSmallDelta
+                | SymbolTypeTcc::PacketReceivedLargeDelta => {
+                    let Some(recv_delta) = recv_deltas.next() else {
+                        break;
+                    };
+                    arrival += recv_delta.delta;
+                    true
+                }
+                _ => false,
+            };
+
+            let sequence_number = tcc.base_sequence_number.wrapping_add(i as u16);
+            if let Some(sent_packet) = self.get_sent_packet(sequence_number) {
+                packet_results.push(PacketResult {
+                    sent_at: sent_packet.sent_at,
+                    size: sent_packet.size,
+                    arrival: received.then_some(arrival),
+                });
+            }
+        }
+
+        trace!(
+            "twcc feedback for {} packets from {}",
+            packet_results.len(),
+            tcc.base_sequence_number
+        );
+        self.estimator.on_twcc_feedback(now, &packet_results);
+    }
+}
+
+impl Interceptor for Sender {
+    fn chain(mut self: Box<Self>, next: Box<dyn Interceptor>) -> Box<dyn Interceptor> {
+        self.next = Some(next);
+        self
+    }
+
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>> {
+        self.next.as_mut()
+    }
+
+    fn read(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        if let MessageEvent::Rtp(RTPMessageEvent::Rtcp(rtcp_packets)) = &msg.message {
+            for rtcp_packet in rtcp_packets {
+                if let Some(tcc) = rtcp_packet.as_any().downcast_ref::<TransportLayerCc>() {
+                    self.on_transport_layer_cc(msg.now, tcc);
+                } else if let Some(remb) = rtcp_packet
+                    .as_any()
+                    .downcast_ref::<ReceiverEstimatedMaximumBitrate>()
+                {
+                    self.estimator.on_remb(msg.now, remb.bitrate as u64);
+                }
+            }
+        }
+
+        if let Some(next) = self.next() {
+            next.read(msg)
+        } else {
+            vec![]
+        }
+    }
+
+    fn write(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        let mut interceptor_events = vec![];
+
+        if let MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet)) = &mut msg.message {
+            if let Some(&id) = self.streams.get(&rtp_packet.header.ssrc) {
+                let sequence_number = self.sequence_number;
+                match rtp_packet
+                    .header
+                    .set_extension(id, Bytes::copy_from_slice(&sequence_number.to_be_bytes()))
+                {
+                    Ok(()) => {
+                        self.sequence_number = sequence_number.wrapping_add(1);
+                        while self.sent_packets.len() >= MAX_SENT_PACKETS
+                            || self.sent_packets.front().is_some_and(|sent_packet| {
+                                msg.now.duration_since(sent_packet.sent_at) > SENT_PACKETS_TIMEOUT
+                            })
+                        {
+                            self.sent_packets.pop_front();
+                        }
+                        self.sent_packets.push_back(SentPacket {
+                            sequence_number,
+                            sent_at: msg.now,
+                            size: rtp_packet.marshal_size(),
+                        });
+                    }
+                    Err(err) => interceptor_events.push(InterceptorEvent::Error(Box::new(err))),
+                }
+            }
+        }
+
+        if let Some(next) = self.next() {
+            let mut events = next.write(msg);
+            interceptor_events.append(&mut events);
+        }
+        interceptor_events
+    }
+
+    fn bind_local_stream(&mut self, info: &StreamInfo) {
+        if let Some(extension) = info
+            .rtp_header_extensions
+            .iter()
+            .find(|extension| extension.uri == TRANSPORT_CC_URI)
+        {
+            self.streams.insert(info.ssrc, extension.id as u8);
+        }
+
+        if let Some(next) = self.next() {
+            next.bind_local_stream(info);
+        }
+    }
+
+    fn bandwidth_estimate(&mut self) -> Option<BandwidthEstimate> {
+        self.estimator.estimate()
+    }
+}
@@ -0,0 +1,47 @@
+#![warn(rust_2018_idioms)]
+#![allow(dead_code)]
+
+pub(crate) mod capture;
+pub(crate) mod cascade;
+pub(crate) mod chat;
+pub(crate) mod description;
+pub(crate) mod egress;
+pub(crate) mod endpoint;
+pub(crate) mod handler;
+pub(crate) mod hls;
+pub(crate) mod ingest;
+pub(crate) mod injector;
+pub(crate) mod interceptor;
+pub(crate) mod messages;
+pub(crate) mod recording;
+pub(crate) mod rtmp;
+pub(crate) mod server;
+pub(crate) mod session;
+pub(crate) mod simulcast;
+pub(crate) mod speaker;
+pub(crate) mod stats;
+pub(crate) mod types;
+
+pub use capture::{CaptureRequest, CaptureSummary};
+pub use cascade::{RelayMessage, RelayPeer, WorkerPlacement};
+pub use description::{codec_policy::CodecPolicy, config::MediaConfig, RTCSessionDescription};
+pub use egress::{EgressRequest, EgressSummary, EgressTrackSummary};
+pub use handler::{
+    datachannel::DataChannelHandler, demuxer::DemuxerHandler, dtls::DtlsHandler,
+    exception::ExceptionHandler, gateway::GatewayHandler, interceptor::InterceptorHandler,
+    sctp::SctpHandler, srtp::SrtpHandler, stun::StunHandler,
+};
+pub use hls::{HlsRequest, HlsSummary};
+pub use ingest::{PlainIngestDescription, PlainIngestRequest, PlainTrack, SrtpParameters};
+pub use injector::InjectRequest;
+pub use messages::{ConnectionQuality, ServerEvent, SessionEvent};
+pub use recording::{RecordingManifest, TrackManifest};
+pub use rtmp::{
+    connection::{RtmpConnection, RtmpEvent},
+    RtmpMedia,
+};
+pub use server::{certificate::RTCCertificate, config::ServerConfig, states::ServerStates};
+pub use session::subscription::SubscriptionRequest;
+pub use simulcast::{LayerPreference, LayerRequest};
+pub use speaker::PinRequest;
+pub use stats::EndpointStats;
@@ -0,0 +1,193 @@
+use crate::session::subscription::SubscriptionRequest;
+use crate::simulcast::LayerRequest;
+use crate::speaker::PinRequest;
+use crate::types::{EndpointId, SessionId};
+use bytes::BytesMut;
+use retty::transport::TransportContext;
+use sctp::ReliabilityType;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum DataChannelMessageType {
+    None,
+    Control,
+    Binary,
+    Text,
+}
+
+#[derive(Debug)]
+pub(crate) struct DataChannelMessageParams {
+    pub(crate) unordered: bool,
+    pub(crate) reliability_type: ReliabilityType,
+    pub(crate) reliability_parameter: u32,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum DataChannelEvent {
+    Open,
+    Message(BytesMut),
+    Close,
+}
+
+/// DataChannelControlMessage is a request sent by an endpoint over its data channel, next to
+/// the SDP offers and answers of renegotiation
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum DataChannelControlMessage {
+    Layer(LayerRequest),
+    Pin(PinRequest),
+    Subscribe(SubscriptionRequest),
+    Unsubscribe(SubscriptionRequest),
+}
+
+/// SessionEvent is sent by the SFU to the endpoints of a session over their data channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionEvent {
+    ActiveSpeakerChanged { endpoint_id: EndpointId },
+}
+
+/// ConnectionQuality rates the connection of an endpoint from the bandwidth estimated towards it,
+/// poor once too low to forward any video
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionQuality {
+    Good,
+    Poor,
+}
+
+/// ServerEvent reports what happens in the sessions hosted by a worker, for the signalling
+/// server to notify it. Endpoints and their tracks are reported by the worker they are connected
+/// to, and sessions and their active speaker by their home worker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    SessionStarted {
+        session_id: SessionId,
+    },
+    SessionEnded {
+        session_id: SessionId,
+    },
+    EndpointJoined {
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    },
+    EndpointLeft {
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    },
+    TrackPublished {
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        mid: String,
+        kind: String,
+    },
+    TrackUnpublished {
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        mid: String,
+        kind: String,
+    },
+    TrackMuted {
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        mid: String,
+        kind: String,
+    },
+    TrackUnmuted {
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        mid: String,
+        kind: String,
+    },
+    ActiveSpeakerChanged {
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    },
+    ConnectionQualityChanged {
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        quality: ConnectionQuality,
+    },
+}
+
+impl ServerEvent {
+    pub fn session_id(&self) -> SessionId {
+        match self {
+            ServerEvent::SessionStarted { session_id }
+            | ServerEvent::SessionEnded { session_id }
+            | ServerEvent::EndpointJoined { session_id, .. }
+            | ServerEvent::EndpointLeft { session_id, .. }
+            | ServerEvent::TrackPublished { session_id, .. }
+            | ServerEvent::TrackUnpublished { session_id, .. }
+            | ServerEvent::TrackMuted { session_id, .. }
+            | ServerEvent::TrackUnmuted { session_id, .. }
+            | ServerEvent::ActiveSpeakerChanged { session_id, .. }
+            | ServerEvent::ConnectionQualityChanged { session_id, .. } => *session_id,
+        }
+    }
+
+    /// is_lifecycle tells whether the event starts or ends a session, an endpoint or a track,
+    /// rather than reporting a change of its state
+    pub fn is_lifecycle(&self) -> bool {
+        matches!(
+            self,
+            ServerEvent::SessionStarted { .. }
+                | ServerEvent::SessionEnded { .. }
+                | ServerEvent::EndpointJoined { .. }
+                | ServerEvent::EndpointLeft { .. }
+                | ServerEvent::TrackPublished { .. }
+                | ServerEvent::TrackUnpublished { .. }
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct DataChannelMessage {
+    pub(crate) association_handle: usize,
+    pub(crate) stream_id: u16,
+    pub(crate) data_message_type: DataChannelMessageType,
+    pub(crate) params: Option<DataChannelMessageParams>,
+    pub(crate) payload: BytesMut,
+}
+
+#[derive(Debug)]
+pub struct ApplicationMessage {
+    pub(crate) association_handle: usize,
+    pub(crate) stream_id: u16,
+    pub(crate) data_channel_event: DataChannelEvent,
+}
+
+#[derive(Debug)]
+pub enum STUNMessageEvent {
+    Raw(BytesMut),
+    Stun(stun::message::Message),
+}
+
+#[derive(Debug)]
+pub enum DTLSMessageEvent {
+    Raw(BytesMut),
+    Sctp(DataChannelMessage),
+    DataChannel(ApplicationMessage),
+}
+
+#[derive(Debug)]
+pub enum RTPMessageEvent {
+    Raw(BytesMut),
+    Rtp(rtp::packet::Packet),
+    Rtcp(Vec<Box<dyn rtcp::packet::Packet>>),
+}
+
+#[derive(Debug)]
+pub enum MessageEvent {
+    Stun(STUNMessageEvent),
+    Dtls(DTLSMessageEvent),
+    Rtp(RTPMessageEvent),
+}
+
+pub struct TaggedMessageEvent {
+    pub now: Instant,
+    pub transport: TransportContext,
+    pub message: MessageEvent,
+}
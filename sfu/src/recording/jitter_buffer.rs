@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// packets are waited for this long before the missing ones are given up
+const JITTER_BUFFER_LATENCY: Duration = Duration::from_millis(200);
+/// missing packets are given up earlier when this many packets are waiting behind them
+const MAX_BUFFERED_PACKETS: usize = 512;
+
+/// OrderedPacket is a packet released by the jitter buffer in sequence number order
+pub(crate) struct OrderedPacket {
+    pub(crate) packet: rtp::packet::Packet,
+    pub(crate) received_at: Instant,
+    /// packets are missing right before this one
+    pub(crate) is_after_gap: bool,
+}
+
+/// JitterBuffer reorders the packets of a stream by sequence number, waiting a little for the
+/// late ones, and reports the ones given up as gaps
+#[derive(Default)]
+pub(crate) struct JitterBuffer {
+    packets: BTreeMap<u64, (Instant, rtp::packet::Packet)>,
+    highest_sequence_number: Option<u64>,
+    next_sequence_number: Option<u64>,
+    lost_packets: u64,
+}
+
+impl JitterBuffer {
+    pub(crate) fn lost_packets(&self) -> u64 {
+        self.lost_packets
+    }
+
+    /// push buffers a packet received at now, and releases the packets which are next in order
+    /// or waited for long enough
+    pub(crate) fn push(&mut self, now: Instant, packet: rtp::packet::Packet) -> Vec<OrderedPacket> {
+        let sequence_number = self.unwrap_sequence_number(packet.header.sequence_number);
+        let next_sequence_number = *self.next_sequence_number.get_or_insert(sequence_number);
+        // too late, or a duplicate
+        if sequence_number >= next_sequence_number {
+            self.packets.insert(sequence_number, (now, packet));
+        }
+
+        let mut ordered_packets = vec![];
+        while let Some((&sequence_number, &(received_at, _))) = self.packets.first_key_value() {
+            let Some(next_sequence_number) = self.next_sequence_number else {
+                break;
+            };
+            let is_after_gap = sequence_number != next_sequence_number;
+            if is_after_gap
+                && now.duration_since(received_at) < JITTER_BUFFER_LATENCY
+                && self.packets.len() < MAX_BUFFERED_PACKETS
+            {
+                break;
+            }
+            ordered_packets.push(self.release(sequence_number, is_after_gap));
+        }
+        ordered_packets
+    }
+
+    /// flush releases every buffered packet, since no more packet is to be received
+    pub(crate) fn flush(&mut self) -> Vec<OrderedPacket> {
+        let sequence_numbers: Vec<u64> = self.packets.keys().copied().collect();
+        sequence_numbers
+            .into_iter()
+            .map(|sequence_number| {
+                let is_after_gap = self.next_sequence_number != Some(sequence_number);
+                self.release(sequence_number, is_after_gap)
+            })
+            .collect()
+    }
+
+    fn release(&mut self, sequence_number: u64, is_after_gap: bool) -> OrderedPacket {
+        if let Some(next_sequence_number) = self.next_sequence_number {
+            self.lost_packets += sequence_number.saturating_sub(next_sequence_number);
+        }
+        self.next_sequence_number = Some(sequence_number + 1);
+        let (received_at, packet) = self.packets.remove(&sequence_number).unwrap();
+        OrderedPacket {
+            packet,
+            received_at,
+            is_after_gap,
+        }
+    }
+
+    /// unwrap_sequence_number extends the 16 bits sequence number, relative to the highest one
+    /// received so far
+    fn unwrap_sequence_number(&mut self, sequence_number: u16) -> u64 {
+        // starts far from zero so that packets reordered before the first one don't underflow
+        let highest = self
+            .highest_sequence_number
+            .unwrap_or(1 << 32 | sequence_number as u64);
+        let delta = sequence_number.wrapping_sub(highest as u16) as i16;
+        let unwrapped = (highest as i64 + delta as i64) as u64;
+        if unwrapped > highest || self.highest_sequence_number.is_none() {
+            self.highest_sequence_number = Some(unwrapped);
+        }
+        unwrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence_number: u16) -> rtp::packet::Packet {
+        let mut packet = rtp::packet::Packet::default();
+        packet.header.sequence_number = sequence_number;
+        packet
+    }
+
+    fn released(ordered_packets: &[OrderedPacket]) -> Vec<(u16, bool)> {
+        ordered_packets
+            .iter()
+            .map(|ordered_packet| {
+                (
+                    ordered_packet.packet.header.sequence_number,
+                    ordered_packet.is_after_gap,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reorders_packets() {
+        let mut jitter_buffer = JitterBuffer::default();
+        let now = Instant::now();
+        assert_eq!(
+            released(&jitter_buffer.push(now, packet(10))),
+            vec![(10, false)]
+        );
+        assert!(jitter_buffer.push(now, packet(12)).is_empty());
+        assert!(jitter_buffer.push(now, packet(13)).is_empty());
+        assert_eq!(
+            released(&jitter_buffer.push(now, packet(11))),
+            vec![(11, false), (12, false), (13, false)]
+        );
+        // too late, or a duplicate
+        assert!(jitter_buffer.push(now, packet(11)).is_empty());
+        assert!(jitter_buffer.push(now, packet(9)).is_empty());
+        assert_eq!(jitter_buffer.lost_packets(), 0);
+    }
+
+    #[test]
+    fn reorders_packets_across_the_sequence_number_wrap_around() {
+        let mut jitter_buffer = JitterBuffer::default();
+        let now = Instant::now();
+        assert_eq!(
+            released(&jitter_buffer.push(now, packet(65534))),
+            vec![(65534, false)]
+        );
+        assert!(jitter_buffer.push(now, packet(0)).is_empty());
+        assert!(jitter_buffer.push(now, packet(1)).is_empty());
+        assert_eq!(
+            released(&jitter_buffer.push(now, packet(65535))),
+            vec![(65535, false), (0, false), (1, false)]
+        );
+        assert_eq!(
+            released(&jitter_buffer.push(now, packet(2))),
+            vec![(2, false)]
+        );
+    }
+
+    #[test]
+    fn gives_up_missing_packets_after_the_latency() {
+        let mut jitter_buffer = JitterBuffer::default();
+        let now = Instant::now();
+        jitter_buffer.push(now, packet(1));
+        assert!(jitter_buffer.push(now, packet(4)).is_empty());
+        let later = now + JITTER_BUFFER_LATENCY / 2;
+        assert!(jitter_buffer.push(later, packet(5)).is_empty());
+
+        // the packets waiting behind the gap go with the first one waited for long enough
+        let later = now + JITTER_BUFFER_LATENCY;
+        assert_eq!(
+            released(&jitter_buffer.push(later, packet(6))),
+            vec![(4, true), (5, false), (6, false)]
+        );
+        assert_eq!(jitter_buffer.lost_packets(), 2);
+        // the packets given up are too late once they arrive
+        assert!(jitter_buffer.push(later, packet(2)).is_empty());
+    }
+
+    #[test]
+    fn gives_up_missing_packets_when_too_many_wait() {
+        let mut jitter_buffer = JitterBuffer::default();
+        let now = Instant::now();
+        jitter_buffer.push(now, packet(0));
+        for sequence_number in 2..MAX_BUFFERED_PACKETS as u16 + 1 {
+            assert!(jitter_buffer.push(now, packet(sequence_number)).is_empty());
+        }
+        let ordered_packets = jitter_buffer.push(now, packet(MAX_BUFFERED_PACKETS as u16 + 1));
+        assert_eq!(ordered_packets.len(), MAX_BUFFERED_PACKETS);
+        assert_eq!(released(&ordered_packets[..1]), vec![(2, true)]);
+        assert_eq!(jitter_buffer.lost_packets(), 1);
+    }
+
+    #[test]
+    fn flushes_the_buffered_packets() {
+        let mut jitter_buffer = JitterBuffer::default();
+        let now = Instant::now();
+        jitter_buffer.push(now, packet(1));
+        jitter_buffer.push(now, packet(3));
+        jitter_buffer.push(now, packet(4));
+        jitter_buffer.push(now, packet(7));
+        assert_eq!(
+            released(&jitter_buffer.flush()),
+            vec![(3, true), (4, false), (7, true)]
+        );
+        assert_eq!(jitter_buffer.lost_packets(), 3);
+        assert!(jitter_buffer.flush().is_empty());
+    }
+}
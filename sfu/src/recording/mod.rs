@@ -0,0 +1,433 @@
+pub(crate) mod jitter_buffer;
+pub(crate) mod writer;
+
+use crate::description::config::{MIME_TYPE_H264, MIME_TYPE_OPUS, MIME_TYPE_VP8, MIME_TYPE_VP9};
+use crate::description::rtp_codec::{RTCRtpCodecCapability, RTPCodecType};
+use crate::endpoint::IncomingStream;
+use crate::recording::jitter_buffer::{JitterBuffer, OrderedPacket};
+use crate::recording::writer::{AnnexBWriter, IvfWriter, MediaWriter, OggWriter};
+use crate::simulcast::keyframe;
+use crate::types::{EndpointId, Mid, SessionId};
+use log::{debug, trace, warn};
+use rtp::codecs::{h264::H264Packet, opus::OpusPacket, vp8::Vp8Packet, vp9::Vp9Packet};
+use rtp::packetizer::Depacketizer;
+use serde::Serialize;
+use shared::error::{Error, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// RecordingManifest describes the files of a recording, with the timings needed to mux its
+/// tracks together later
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingManifest {
+    pub session_id: SessionId,
+    pub directory: PathBuf,
+    /// unix time in milliseconds
+    pub started_at: u64,
+    /// unix time in milliseconds
+    pub stopped_at: u64,
+    pub tracks: Vec<TrackManifest>,
+}
+
+/// TrackManifest describes the file of a track, one per simulcast layer
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackManifest {
+    pub endpoint_id: EndpointId,
+    pub mid: Mid,
+    pub rid: Option<String>,
+    pub kind: String,
+    pub mime_type: String,
+    pub clock_rate: u32,
+    /// file name, relative to the directory of the recording
+    pub file: String,
+    /// file of the time of every frame in milliseconds, relative to the directory of the
+    /// recording, for the containers which can't carry it
+    pub timestamps_file: Option<String>,
+    /// unix time in milliseconds the first frame was received at
+    pub started_at: Option<u64>,
+    /// unix time in milliseconds the last frame ends at, from the RTP timestamps
+    pub stopped_at: Option<u64>,
+    /// RTP timestamp of the first frame, frames are written relative to it
+    pub first_rtp_timestamp: Option<u32>,
+    pub frames: u64,
+    pub lost_packets: u64,
+}
+
+type TrackKey = (EndpointId, Mid, Option<String>);
+
+/// SessionRecorder writes every track published in a session into its own file, in a directory
+/// of its own, until it is stopped
+pub(crate) struct SessionRecorder {
+    session_id: SessionId,
+    directory: PathBuf,
+    started_at: SystemTime,
+    started_instant: Instant,
+    tracks: HashMap<TrackKey, TrackRecorder>,
+    closed_tracks: Vec<TrackManifest>,
+}
+
+impl SessionRecorder {
+    /// new creates the directory of the recording, named after the session and the time it
+    /// starts at, in the recording directory
+    pub(crate) fn new(session_id: SessionId, recording_dir: &Path, now: Instant) -> Result<Self> {
+        let started_at = SystemTime::now();
+        let directory =
+            recording_dir.join(format!("{}-{}", session_id, unix_time_millis(started_at)));
+        std::fs::create_dir_all(&directory)?;
+        debug!("{}: start recording in {:?}", session_id, directory);
+
+        Ok(Self {
+            session_id,
+            directory,
+            started_at,
+            started_instant: now,
+            tracks: HashMap::new(),
+            closed_tracks: vec![],
+        })
+    }
+
+    pub(crate) fn get_directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// on_rtp records a packet of an incoming stream, and returns whether the track waits for
+    /// a keyframe to start from
+    pub(crate) fn on_rtp(
+        &mut self,
+        now: Instant,
+        endpoint_id: EndpointId,
+        incoming_stream: &IncomingStream,
+        kind: RTPCodecType,
+        codec: &RTCRtpCodecCapability,
+        rtp_packet: rtp::packet::Packet,
+    ) -> Result<bool> {
+        let key = (
+            endpoint_id,
+            incoming_stream.mid.clone(),
+            incoming_stream.rid.clone(),
+        );
+        let track = match self.tracks.get_mut(&key) {
+            Some(track) => track,
+            None => {
+                let Some(track) = TrackRecorder::new(&self.directory, key.clone(), kind, codec)?
+                else {
+                    trace!(
+                        "{}: skip recording {} of endpoint {} mid {}",
+                        self.session_id,
+                        codec.mime_type,
+                        endpoint_id,
+                        incoming_stream.mid
+                    );
+                    return Ok(false);
+                };
+                self.tracks.entry(key).or_insert(track)
+            }
+        };
+
+        for ordered_packet in track.jitter_buffer.push(now, rtp_packet) {
+            track.on_ordered_packet(ordered_packet)?;
+        }
+        Ok(track.is_waiting_keyframe)
+    }
+
+    /// remove_endpoint closes the tracks of an endpoint leaving the session
+    pub(crate) fn remove_endpoint(&mut self, endpoint_id: &EndpointId) {
+        let keys: Vec<TrackKey> = self
+            .tracks
+            .keys()
+            .filter(|(track_endpoint_id, _, _)| track_endpoint_id == endpoint_id)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(track) = self.tracks.remove(&key) {
+                self.close_track(track);
+            }
+        }
+    }
+
+    /// stop closes every track and writes the manifest of the recording next to them
+    pub(crate) fn stop(mut self) -> Result<RecordingManifest> {
+        let tracks: Vec<TrackRecorder> = self.tracks.drain().map(|(_, track)| track).collect();
+        for track in tracks {
+            self.close_track(track);
+        }
+
+        let manifest = RecordingManifest {
+            session_id: self.session_id,
+            directory: self.directory.clone(),
+            started_at: unix_time_millis(self.started_at),
+            stopped_at: unix_time_millis(SystemTime::now()),
+            tracks: self.closed_tracks,
+        };
+        let manifest_json =
+            serde_json::to_vec_pretty(&manifest).map_err(|err| Error::Other(err.to_string()))?;
+        std::fs::write(self.directory.join(MANIFEST_FILE_NAME), manifest_json)?;
+        debug!(
+            "{}: stop recording in {:?}",
+            manifest.session_id, manifest.directory
+        );
+
+        Ok(manifest)
+    }
+
+    fn close_track(&mut self, mut track: TrackRecorder) {
+        if let Err(err) = track.close() {
+            warn!(
+                "{}: can't close recording of {}: {}",
+                self.session_id, track.file, err
+            );
+        }
+        let to_unix_time_millis = |instant: Instant| {
+            unix_time_millis(self.started_at)
+                + instant
+                    .saturating_duration_since(self.started_instant)
+                    .as_millis() as u64
+        };
+        let started_at = track.first_received_at.map(to_unix_time_millis);
+        let stopped_at = started_at.map(|started_at| {
+            started_at + track.elapsed_timestamp() * 1000 / track.clock_rate.max(1) as u64
+        });
+        self.closed_tracks.push(TrackManifest {
+            endpoint_id: track.endpoint_id,
+            mid: track.mid,
+            rid: track.rid,
+            kind: track.kind.to_string(),
+            mime_type: track.mime_type,
+            clock_rate: track.clock_rate,
+            file: track.file,
+            timestamps_file: track.timestamps_file,
+            started_at,
+            stopped_at,
+            first_rtp_timestamp: track.first_timestamp,
+            frames: track.frames,
+            lost_packets: track.jitter_buffer.lost_packets(),
+        });
+    }
+}
+
+/// TrackRecorder reorders the packets of an incoming stream, assembles them into frames and
+/// writes the frames which can be decoded, video waiting for a keyframe after any gap
+struct TrackRecorder {
+    endpoint_id: EndpointId,
+    mid: Mid,
+    rid: Option<String>,
+    kind: RTPCodecType,
+    mime_type: String,
+    clock_rate: u32,
+    file: String,
+    timestamps_file: Option<String>,
+
+    jitter_buffer: JitterBuffer,
+    depacketizer: Box<dyn Depacketizer>,
+    writer: Box<dyn MediaWriter>,
+
+    frame: Vec<u8>,
+    frame_timestamp: Option<u32>,
+    is_frame_keyframe: bool,
+    is_frame_broken: bool,
+    is_waiting_keyframe: bool,
+
+    first_received_at: Option<Instant>,
+    first_timestamp: Option<u32>,
+    last_timestamp: Option<(u32, u64)>,
+    frames: u64,
+}
+
+impl TrackRecorder {
+    /// new creates the file of the track, or returns None when its codec can't be recorded
+    fn new(
+        directory: &Path,
+        (endpoint_id, mid, rid): TrackKey,
+        kind: RTPCodecType,
+        codec: &RTCRtpCodecCapability,
+    ) -> Result<Option<Self>> {
+        let mime_type = codec.mime_type.as_str();
+        let (Some(depacketizer), Some(extension)) =
+            (new_depacketizer(mime_type), file_extension(mime_type))
+        else {
+            return Ok(None);
+        };
+        let file = match &rid {
+            Some(rid) => format!("{}-{}-{}.{}", endpoint_id, mid, rid, extension),
+            None => format!("{}-{}.{}", endpoint_id, mid, extension),
+        };
+        let output = BufWriter::new(File::create(directory.join(&file))?);
+        let mut timestamps_file = None;
+        let writer: Box<dyn MediaWriter> = if mime_type.eq_ignore_ascii_case(MIME_TYPE_OPUS) {
+            Box::new(OggWriter::new(output, codec.channels as u8)?)
+        } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_VP8) {
+            Box::new(IvfWriter::new(output, *b"VP80", codec.clock_rate)?)
+        } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_VP9) {
+            Box::new(IvfWriter::new(output, *b"VP90", codec.clock_rate)?)
+        } else {
+            let timestamps = format!("{}.timestamps.txt", file);
+            let timestamps_output = BufWriter::new(File::create(directory.join(&timestamps))?);
+            timestamps_file = Some(timestamps);
+            Box::new(AnnexBWriter::new(
+                output,
+                timestamps_output,
+                codec.clock_rate,
+            )?)
+        };
+
+        Ok(Some(Self {
+            endpoint_id,
+            mid,
+            rid,
+            kind,
+            mime_type: codec.mime_type.clone(),
+            clock_rate: codec.clock_rate,
+            file,
+            timestamps_file,
+
+            jitter_buffer: JitterBuffer::default(),
+            depacketizer,
+            writer,
+
+            frame: vec![],
+            frame_timestamp: None,
+            is_frame_keyframe: false,
+            is_frame_broken: false,
+            is_waiting_keyframe: kind == RTPCodecType::Video,
+
+            first_received_at: None,
+            first_timestamp: None,
+            last_timestamp: None,
+            frames: 0,
+        }))
+    }
+
+    fn on_ordered_packet(&mut self, ordered_packet: OrderedPacket) -> Result<()> {
+        let OrderedPacket {
+            packet,
+            received_at,
+            is_after_gap,
+        } = ordered_packet;
+        // padding only packets carry no media
+        if packet.payload.is_empty() {
+            return Ok(());
+        }
+        let timestamp = packet.header.timestamp;
+
+        if self.kind != RTPCodecType::Video {
+            let frame = self.depacketizer.depacketize(&packet.payload)?;
+            return self.write_frame(&frame, timestamp, received_at);
+        }
+
+        if is_after_gap {
+            // the next frames may reference the lost ones, decoding restarts from a keyframe
+            self.is_waiting_keyframe = true;
+            self.is_frame_broken = true;
+            if let Some(depacketizer) = new_depacketizer(&self.mime_type) {
+                self.depacketizer = depacketizer;
+            }
+        }
+        if self.frame_timestamp != Some(timestamp) {
+            // a frame whose marker bit was never received is incomplete
+            self.frame.clear();
+            self.frame_timestamp = Some(timestamp);
+            self.is_frame_keyframe = keyframe::is_keyframe(&self.mime_type, &packet.payload);
+            self.is_frame_broken = !self.depacketizer.is_partition_head(&packet.payload);
+        }
+        match self.depacketizer.depacketize(&packet.payload) {
+            Ok(payload) => self.frame.extend_from_slice(&payload),
+            Err(err) => {
+                trace!(
+                    "can't depacketize {} of {}: {}",
+                    self.mime_type,
+                    self.file,
+                    err
+                );
+                self.is_frame_broken = true;
+            }
+        }
+
+        if !packet.header.marker {
+            return Ok(());
+        }
+        let frame = std::mem::take(&mut self.frame);
+        self.frame_timestamp = None;
+        if self.is_frame_broken || (self.is_waiting_keyframe && !self.is_frame_keyframe) {
+            self.is_waiting_keyframe = true;
+            return Ok(());
+        }
+        self.is_waiting_keyframe = false;
+        self.write_frame(&frame, timestamp, received_at)
+    }
+
+    fn write_frame(&mut self, frame: &[u8], timestamp: u32, received_at: Instant) -> Result<()> {
+        if frame.is_empty() {
+            return Ok(());
+        }
+        let elapsed = match self.last_timestamp {
+            Some((last_timestamp, last_elapsed)) => {
+                let delta = timestamp.wrapping_sub(last_timestamp) as i32 as i64;
+                (last_elapsed as i64 + delta).max(0) as u64
+            }
+            None => {
+                self.first_received_at = Some(received_at);
+                self.first_timestamp = Some(timestamp);
+                0
+            }
+        };
+        self.last_timestamp = Some((timestamp, elapsed));
+
+        self.writer.write_frame(frame, elapsed)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn elapsed_timestamp(&self) -> u64 {
+        self.last_timestamp.map_or(0, |(_, elapsed)| elapsed)
+    }
+
+    /// close writes the packets still buffered, then completes the file
+    fn close(&mut self) -> Result<()> {
+        for ordered_packet in self.jitter_buffer.flush() {
+            if let Err(err) = self.on_ordered_packet(ordered_packet) {
+                trace!("can't record buffered packet of {}: {}", self.file, err);
+            }
+        }
+        self.writer.close()
+    }
+}
+
+/// new_depacketizer returns the depacketizer of the codecs which can be recorded
+fn new_depacketizer(mime_type: &str) -> Option<Box<dyn Depacketizer>> {
+    if mime_type.eq_ignore_ascii_case(MIME_TYPE_OPUS) {
+        Some(Box::new(OpusPacket))
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_VP8) {
+        Some(Box::<Vp8Packet>::default())
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_VP9) {
+        Some(Box::<Vp9Packet>::default())
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_H264) {
+        // annex B start codes rather than AVC length prefixes
+        Some(Box::<H264Packet>::default())
+    } else {
+        None
+    }
+}
+
+fn file_extension(mime_type: &str) -> Option<&'static str> {
+    if mime_type.eq_ignore_ascii_case(MIME_TYPE_OPUS) {
+        Some("ogg")
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_VP8)
+        || mime_type.eq_ignore_ascii_case(MIME_TYPE_VP9)
+    {
+        Some("ivf")
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_H264) {
+        Some("h264")
+    } else {
+        None
+    }
+}
+
+fn unix_time_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
@@ -0,0 +1,395 @@
+use shared::error::Result;
+use std::io::{Seek, SeekFrom, Write};
+
+const IVF_HEADER_SIZE: u16 = 32;
+const OGG_PAGE_HEADER_TYPE_BOS: u8 = 0x02;
+const OGG_PAGE_HEADER_TYPE_EOS: u8 = 0x04;
+const OPUS_SAMPLE_RATE: u32 = 48000;
+const OPUS_VENDOR: &str = "beep-sfu";
+
+/// MediaWriter writes the frames of a track into a container
+pub(crate) trait MediaWriter {
+    /// write_frame writes a complete frame, timestamp being in clock rate units since the first
+    /// frame of the track
+    fn write_frame(&mut self, frame: &[u8], timestamp: u64) -> Result<()>;
+
+    /// close completes the container, once the last frame is written
+    fn close(&mut self) -> Result<()>;
+}
+
+/// IvfWriter writes VP8 or VP9 frames into an IVF file, whose header is completed on close
+/// with the frame count and the size of the first VP8 keyframe
+pub(crate) struct IvfWriter<W: Write + Seek> {
+    writer: W,
+    fourcc: [u8; 4],
+    clock_rate: u32,
+    width: u16,
+    height: u16,
+    frame_count: u32,
+}
+
+impl<W: Write + Seek> IvfWriter<W> {
+    pub(crate) fn new(writer: W, fourcc: [u8; 4], clock_rate: u32) -> Result<Self> {
+        let mut ivf_writer = Self {
+            writer,
+            fourcc,
+            clock_rate,
+            width: 0,
+            height: 0,
+            frame_count: 0,
+        };
+        ivf_writer.write_header()?;
+        Ok(ivf_writer)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let mut header = Vec::with_capacity(IVF_HEADER_SIZE as usize);
+        header.extend_from_slice(b"DKIF");
+        header.extend_from_slice(&0u16.to_le_bytes()); // version
+        header.extend_from_slice(&IVF_HEADER_SIZE.to_le_bytes());
+        header.extend_from_slice(&self.fourcc);
+        header.extend_from_slice(&self.width.to_le_bytes());
+        header.extend_from_slice(&self.height.to_le_bytes());
+        header.extend_from_slice(&self.clock_rate.to_le_bytes()); // timebase denominator
+        header.extend_from_slice(&1u32.to_le_bytes()); // timebase numerator
+        header.extend_from_slice(&self.frame_count.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // unused
+        self.writer.write_all(&header)?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> MediaWriter for IvfWriter<W> {
+    fn write_frame(&mut self, frame: &[u8], timestamp: u64) -> Result<()> {
+        // RFC 6386 section 9.1: a VP8 keyframe carries its size after its 3 bytes start code
+        if self.width == 0
+            && &self.fourcc == b"VP80"
+            && frame.len() >= 10
+            && frame[0] & 0x01 == 0
+            && frame[3..6] == [0x9D, 0x01, 0x2A]
+        {
+            self.width = u16::from_le_bytes([frame[6], frame[7]]) & 0x3FFF;
+            self.height = u16::from_le_bytes([frame[8], frame[9]]) & 0x3FFF;
+        }
+
+        self.writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&timestamp.to_le_bytes())?;
+        self.writer.write_all(frame)?;
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// OggWriter writes Opus packets into an Ogg file, RFC 7845, one packet per page
+pub(crate) struct OggWriter<W: Write> {
+    writer: W,
+    serial: u32,
+    page_sequence_number: u32,
+    granule_position: u64,
+    checksum_table: [u32; 256],
+}
+
+impl<W: Write> OggWriter<W> {
+    pub(crate) fn new(writer: W, channels: u8) -> Result<Self> {
+        let mut ogg_writer = Self {
+            writer,
+            serial: rand::random::<u32>(),
+            page_sequence_number: 0,
+            granule_position: 0,
+            checksum_table: ogg_checksum_table(),
+        };
+
+        let mut opus_head = Vec::with_capacity(19);
+        opus_head.extend_from_slice(b"OpusHead");
+        opus_head.push(1); // version
+        opus_head.push(channels.max(1));
+        opus_head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+        opus_head.extend_from_slice(&OPUS_SAMPLE_RATE.to_le_bytes());
+        opus_head.extend_from_slice(&0u16.to_le_bytes()); // output gain
+        opus_head.push(0); // channel mapping family
+        ogg_writer.write_page(&opus_head, 0, OGG_PAGE_HEADER_TYPE_BOS)?;
+
+        let mut opus_tags = Vec::with_capacity(16 + OPUS_VENDOR.len());
+        opus_tags.extend_from_slice(b"OpusTags");
+        opus_tags.extend_from_slice(&(OPUS_VENDOR.len() as u32).to_le_bytes());
+        opus_tags.extend_from_slice(OPUS_VENDOR.as_bytes());
+        opus_tags.extend_from_slice(&0u32.to_le_bytes()); // user comment list length
+        ogg_writer.write_page(&opus_tags, 0, 0)?;
+
+        Ok(ogg_writer)
+    }
+
+    fn write_page(&mut self, payload: &[u8], granule_position: u64, header_type: u8) -> Result<()> {
+        // lacing values: as many 255 as needed, then the remainder, which ends the packet
+        let mut segments = vec![255u8; payload.len() / 255];
+        segments.push((payload.len() % 255) as u8);
+
+        let mut page = Vec::with_capacity(27 + segments.len() + payload.len());
+        page.extend_from_slice(b"OggS");
+        page.push(0); // version
+        page.push(header_type);
+        page.extend_from_slice(&granule_position.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.page_sequence_number.to_le_bytes());
+        page.extend_from_slice(&0u32.to_le_bytes()); // checksum, computed with itself zeroed
+        page.push(segments.len() as u8);
+        page.extend_from_slice(&segments);
+        page.extend_from_slice(payload);
+
+        let checksum = ogg_checksum(&self.checksum_table, &page);
+        page[22..26].copy_from_slice(&checksum.to_le_bytes());
+
+        self.writer.write_all(&page)?;
+        self.page_sequence_number += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> MediaWriter for OggWriter<W> {
+    fn write_frame(&mut self, frame: &[u8], timestamp: u64) -> Result<()> {
+        // the granule position of a page is the number of samples at the end of its packet
+        self.granule_position = timestamp + opus_samples(frame) as u64;
+        self.write_page(frame, self.granule_position, 0)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.write_page(&[], self.granule_position, OGG_PAGE_HEADER_TYPE_EOS)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// ogg_checksum_table computes the table of the CRC-32 of Ogg pages, polynomial 0x04c11db7
+/// without reflection
+fn ogg_checksum_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut r = (i as u32) << 24;
+        for _ in 0..8 {
+            r = if r & 0x80000000 != 0 {
+                (r << 1) ^ 0x04C11DB7
+            } else {
+                r << 1
+            };
+        }
+        *entry = r;
+    }
+    table
+}
+
+/// ogg_checksum computes the CRC-32 of an Ogg page, whose checksum field is zeroed
+fn ogg_checksum(checksum_table: &[u32; 256], page: &[u8]) -> u32 {
+    page.iter().fold(0u32, |checksum, &byte| {
+        (checksum << 8) ^ checksum_table[(((checksum >> 24) as u8) ^ byte) as usize]
+    })
+}
+
+/// opus_samples returns the number of 48 kHz samples of an Opus packet, from its TOC byte,
+/// RFC 6716 section 3.1
+pub(crate) fn opus_samples(packet: &[u8]) -> u32 {
+    let Some(&toc) = packet.first() else {
+        return 0;
+    };
+    let config = toc >> 3;
+    let frame_samples = match config {
+        0..=11 => [480, 960, 1920, 2880][(config % 4) as usize],
+        12..=15 => [480, 960][(config % 2) as usize],
+        _ => [120, 240, 480, 960][(config % 4) as usize],
+    };
+    let frame_count = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map_or(0, |&byte| (byte & 0x3F) as u32),
+    };
+    frame_samples * frame_count
+}
+
+/// AnnexBWriter writes H.264 access units as an Annex B byte stream, which has no timing, and
+/// the time of every access unit to a timestamps file next to it, in milliseconds (mkvmerge's
+/// timestamp format v2)
+pub(crate) struct AnnexBWriter<W: Write> {
+    writer: W,
+    timestamps_writer: W,
+    clock_rate: u32,
+}
+
+impl<W: Write> AnnexBWriter<W> {
+    pub(crate) fn new(writer: W, mut timestamps_writer: W, clock_rate: u32) -> Result<Self> {
+        timestamps_writer.write_all(b"# timestamp format v2\n")?;
+        Ok(Self {
+            writer,
+            timestamps_writer,
+            clock_rate,
+        })
+    }
+}
+
+impl<W: Write> MediaWriter for AnnexBWriter<W> {
+    fn write_frame(&mut self, frame: &[u8], timestamp: u64) -> Result<()> {
+        self.writer.write_all(frame)?;
+        let millis = timestamp as f64 * 1000.0 / self.clock_rate.max(1) as f64;
+        writeln!(self.timestamps_writer, "{:.3}", millis)?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.timestamps_writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// ogg_pages splits an Ogg stream into its pages, as (header type, granule position,
+    /// sequence number, lacing values, payload, page)
+    #[allow(clippy::type_complexity)]
+    fn ogg_pages(mut data: &[u8]) -> Vec<(u8, u64, u32, Vec<u8>, Vec<u8>, Vec<u8>)> {
+        let mut pages = vec![];
+        while !data.is_empty() {
+            assert_eq!(&data[..4], b"OggS");
+            let segment_count = data[26] as usize;
+            let segments = data[27..27 + segment_count].to_vec();
+            let payload_size: usize = segments.iter().map(|&size| size as usize).sum();
+            let page_size = 27 + segment_count + payload_size;
+            let page = data[..page_size].to_vec();
+            pages.push((
+                data[5],
+                u64::from_le_bytes(data[6..14].try_into().unwrap()),
+                u32::from_le_bytes(data[18..22].try_into().unwrap()),
+                segments,
+                data[27 + segment_count..page_size].to_vec(),
+                page,
+            ));
+            data = &data[page_size..];
+        }
+        pages
+    }
+
+    #[test]
+    fn computes_the_ogg_checksum() {
+        // CRC-32/POSIX of "123456789" before its final inversion
+        assert_eq!(
+            ogg_checksum(&ogg_checksum_table(), b"123456789"),
+            !0x765E7680
+        );
+    }
+
+    #[test]
+    fn writes_opus_packets_into_ogg_pages() {
+        let mut output = vec![];
+        let mut writer = OggWriter::new(&mut output, 2).unwrap();
+        // 20 ms packets, TOC config 31 with one frame
+        let small_packet = [0xF8u8; 100];
+        let large_packet = [0xF8u8; 510];
+        writer.write_frame(&small_packet, 0).unwrap();
+        writer.write_frame(&large_packet, 960).unwrap();
+        writer.close().unwrap();
+
+        let pages = ogg_pages(&output);
+        assert_eq!(pages.len(), 5);
+        for (i, (_, _, sequence_number, _, _, page)) in pages.iter().enumerate() {
+            assert_eq!(*sequence_number, i as u32);
+            let mut zeroed = page.clone();
+            zeroed[22..26].fill(0);
+            assert_eq!(
+                page[22..26],
+                ogg_checksum(&ogg_checksum_table(), &zeroed).to_le_bytes()
+            );
+        }
+
+        let (header_type, _, _, _, opus_head, _) = &pages[0];
+        assert_eq!(*header_type, OGG_PAGE_HEADER_TYPE_BOS);
+        assert_eq!(&opus_head[..8], b"OpusHead");
+        assert_eq!(opus_head[9], 2);
+        assert_eq!(&pages[1].4[..8], b"OpusTags");
+
+        assert_eq!(pages[2].1, 960);
+        assert_eq!(pages[2].3, vec![100]);
+        assert_eq!(pages[2].4, small_packet);
+        // a packet of a multiple of 255 bytes ends with an empty lacing value
+        assert_eq!(pages[3].1, 1920);
+        assert_eq!(pages[3].3, vec![255, 255, 0]);
+        assert_eq!(pages[3].4, large_packet);
+
+        let (header_type, granule_position, _, segments, payload, _) = &pages[4];
+        assert_eq!(*header_type, OGG_PAGE_HEADER_TYPE_EOS);
+        assert_eq!(*granule_position, 1920);
+        assert_eq!(*segments, vec![0]);
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    fn counts_opus_samples() {
+        assert_eq!(opus_samples(&[]), 0);
+        // SILK 10 ms, one frame
+        assert_eq!(opus_samples(&[0x00]), 480);
+        // CELT 20 ms, two frames
+        assert_eq!(opus_samples(&[0xF9]), 1920);
+        // CELT 2.5 ms, code 3 with 4 frames
+        assert_eq!(opus_samples(&[0x83, 0x04]), 480);
+    }
+
+    #[test]
+    fn completes_the_ivf_header_on_close() {
+        let mut writer = IvfWriter::new(Cursor::new(vec![]), *b"VP80", 90000).unwrap();
+        // keyframe of 640x360 after its 3 bytes frame tag
+        let keyframe = [
+            0x10, 0x02, 0x00, 0x9D, 0x01, 0x2A, 0x80, 0x02, 0x68, 0x01, 0xAA,
+        ];
+        let delta_frame = [0x31, 0x00, 0x00, 0xBB];
+        writer.write_frame(&keyframe, 0).unwrap();
+        writer.write_frame(&delta_frame, 3000).unwrap();
+        writer.close().unwrap();
+
+        let output = writer.writer.into_inner();
+        assert_eq!(&output[..4], b"DKIF");
+        assert_eq!(u16::from_le_bytes([output[6], output[7]]), IVF_HEADER_SIZE);
+        assert_eq!(&output[8..12], b"VP80");
+        assert_eq!(u16::from_le_bytes([output[12], output[13]]), 640);
+        assert_eq!(u16::from_le_bytes([output[14], output[15]]), 360);
+        assert_eq!(
+            u32::from_le_bytes(output[16..20].try_into().unwrap()),
+            90000
+        );
+        assert_eq!(u32::from_le_bytes(output[20..24].try_into().unwrap()), 1);
+        assert_eq!(u32::from_le_bytes(output[24..28].try_into().unwrap()), 2);
+
+        let frames = &output[IVF_HEADER_SIZE as usize..];
+        assert_eq!(
+            u32::from_le_bytes(frames[..4].try_into().unwrap()),
+            keyframe.len() as u32
+        );
+        assert_eq!(u64::from_le_bytes(frames[4..12].try_into().unwrap()), 0);
+        assert_eq!(&frames[12..12 + keyframe.len()], keyframe);
+        let frames = &frames[12 + keyframe.len()..];
+        assert_eq!(u64::from_le_bytes(frames[4..12].try_into().unwrap()), 3000);
+        assert_eq!(&frames[12..], delta_frame);
+    }
+
+    #[test]
+    fn writes_the_timestamps_of_annex_b_frames() {
+        let mut output = vec![];
+        let mut timestamps = vec![];
+        let mut writer = AnnexBWriter::new(&mut output, &mut timestamps, 90000).unwrap();
+        writer.write_frame(&[0, 0, 0, 1, 0x65], 0).unwrap();
+        writer.write_frame(&[0, 0, 0, 1, 0x41], 3000).unwrap();
+        writer.close().unwrap();
+
+        assert_eq!(output, vec![0, 0, 0, 1, 0x65, 0, 0, 0, 1, 0x41]);
+        assert_eq!(
+            String::from_utf8(timestamps).unwrap(),
+            "# timestamp format v2\n0.000\n33.333\n"
+        );
+    }
+}
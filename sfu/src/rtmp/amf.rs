@@ -0,0 +1,326 @@
+use bytes::{BufMut, BytesMut};
+use shared::error::{Error, Result};
+
+const MARKER_NUMBER: u8 = 0x00;
+const MARKER_BOOLEAN: u8 = 0x01;
+const MARKER_STRING: u8 = 0x02;
+const MARKER_OBJECT: u8 = 0x03;
+const MARKER_NULL: u8 = 0x05;
+const MARKER_UNDEFINED: u8 = 0x06;
+const MARKER_ECMA_ARRAY: u8 = 0x08;
+const MARKER_OBJECT_END: u8 = 0x09;
+const MARKER_STRICT_ARRAY: u8 = 0x0a;
+const MARKER_DATE: u8 = 0x0b;
+const MARKER_LONG_STRING: u8 = 0x0c;
+
+/// Amf0Value is a value of the Action Message Format 0, which RTMP commands are encoded with
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Amf0Value {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Object(Vec<(String, Amf0Value)>),
+    Null,
+    Undefined,
+    EcmaArray(Vec<(String, Amf0Value)>),
+    StrictArray(Vec<Amf0Value>),
+    Date(f64),
+}
+
+impl Amf0Value {
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Amf0Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_number(&self) -> Option<f64> {
+        match self {
+            Amf0Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// property returns the value of a property of an object or an ECMA array
+    pub(crate) fn property(&self, name: &str) -> Option<&Amf0Value> {
+        match self {
+            Amf0Value::Object(properties) | Amf0Value::EcmaArray(properties) => properties
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn encode(&self, buf: &mut BytesMut) {
+        match self {
+            Amf0Value::Number(n) => {
+                buf.put_u8(MARKER_NUMBER);
+                buf.put_f64(*n);
+            }
+            Amf0Value::Boolean(b) => {
+                buf.put_u8(MARKER_BOOLEAN);
+                buf.put_u8(*b as u8);
+            }
+            Amf0Value::String(s) => {
+                if s.len() > u16::MAX as usize {
+                    buf.put_u8(MARKER_LONG_STRING);
+                    buf.put_u32(s.len() as u32);
+                } else {
+                    buf.put_u8(MARKER_STRING);
+                    buf.put_u16(s.len() as u16);
+                }
+                buf.put_slice(s.as_bytes());
+            }
+            Amf0Value::Object(properties) => {
+                buf.put_u8(MARKER_OBJECT);
+                encode_properties(properties, buf);
+            }
+            Amf0Value::Null => buf.put_u8(MARKER_NULL),
+            Amf0Value::Undefined => buf.put_u8(MARKER_UNDEFINED),
+            Amf0Value::EcmaArray(properties) => {
+                buf.put_u8(MARKER_ECMA_ARRAY);
+                buf.put_u32(properties.len() as u32);
+                encode_properties(properties, buf);
+            }
+            Amf0Value::StrictArray(values) => {
+                buf.put_u8(MARKER_STRICT_ARRAY);
+                buf.put_u32(values.len() as u32);
+                for value in values {
+                    value.encode(buf);
+                }
+            }
+            Amf0Value::Date(millis) => {
+                buf.put_u8(MARKER_DATE);
+                buf.put_f64(*millis);
+                buf.put_i16(0);
+            }
+        }
+    }
+
+    /// decode reads the next value of the buffer, and advances it past the value
+    pub(crate) fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let marker = read_bytes(buf, 1)?[0];
+        Ok(match marker {
+            MARKER_NUMBER => Amf0Value::Number(read_f64(buf)?),
+            MARKER_BOOLEAN => Amf0Value::Boolean(read_bytes(buf, 1)?[0] != 0),
+            MARKER_STRING => {
+                let len = read_u16(buf)? as usize;
+                Amf0Value::String(read_string(buf, len)?)
+            }
+            MARKER_LONG_STRING => {
+                let len = read_u32(buf)? as usize;
+                Amf0Value::String(read_string(buf, len)?)
+            }
+            MARKER_OBJECT => Amf0Value::Object(decode_properties(buf)?),
+            MARKER_NULL => Amf0Value::Null,
+            MARKER_UNDEFINED => Amf0Value::Undefined,
+            MARKER_ECMA_ARRAY => {
+                // the count is a hint only, the properties end with an object end marker
+                read_u32(buf)?;
+                Amf0Value::EcmaArray(decode_properties(buf)?)
+            }
+            MARKER_STRICT_ARRAY => {
+                let count = read_u32(buf)?;
+                let mut values = vec![];
+                for _ in 0..count {
+                    values.push(Amf0Value::decode(buf)?);
+                }
+                Amf0Value::StrictArray(values)
+            }
+            MARKER_DATE => {
+                let millis = read_f64(buf)?;
+                read_bytes(buf, 2)?;
+                Amf0Value::Date(millis)
+            }
+            _ => {
+                return Err(Error::Other(format!(
+                    "unsupported amf0 marker {:#04x}",
+                    marker
+                )))
+            }
+        })
+    }
+
+    /// decode_all reads every value of the buffer
+    pub(crate) fn decode_all(mut buf: &[u8]) -> Result<Vec<Self>> {
+        let mut values = vec![];
+        while !buf.is_empty() {
+            values.push(Amf0Value::decode(&mut buf)?);
+        }
+        Ok(values)
+    }
+}
+
+fn encode_properties(properties: &[(String, Amf0Value)], buf: &mut BytesMut) {
+    for (key, value) in properties {
+        buf.put_u16(key.len() as u16);
+        buf.put_slice(key.as_bytes());
+        value.encode(buf);
+    }
+    buf.put_u16(0);
+    buf.put_u8(MARKER_OBJECT_END);
+}
+
+fn decode_properties(buf: &mut &[u8]) -> Result<Vec<(String, Amf0Value)>> {
+    let mut properties = vec![];
+    loop {
+        let len = read_u16(buf)? as usize;
+        if len == 0 && buf.first() == Some(&MARKER_OBJECT_END) {
+            read_bytes(buf, 1)?;
+            return Ok(properties);
+        }
+        let key = read_string(buf, len)?;
+        properties.push((key, Amf0Value::decode(buf)?));
+    }
+}
+
+fn read_bytes<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(Error::Other("truncated amf0 value".to_string()));
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(bytes)
+}
+
+fn read_u16(buf: &mut &[u8]) -> Result<u16> {
+    let bytes = read_bytes(buf, 2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(buf: &mut &[u8]) -> Result<u32> {
+    let bytes = read_bytes(buf, 4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_f64(buf: &mut &[u8]) -> Result<f64> {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(read_bytes(buf, 8)?);
+    Ok(f64::from_be_bytes(bytes))
+}
+
+fn read_string(buf: &mut &[u8], len: usize) -> Result<String> {
+    Ok(String::from_utf8_lossy(read_bytes(buf, len)?).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(value: &Amf0Value) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        value.encode(&mut buf);
+        buf.to_vec()
+    }
+
+    #[test]
+    fn encodes_values() {
+        assert_eq!(
+            encode(&Amf0Value::Number(1.0)),
+            [&[MARKER_NUMBER][..], &1.0f64.to_be_bytes()].concat()
+        );
+        assert_eq!(encode(&Amf0Value::Boolean(true)), [MARKER_BOOLEAN, 1]);
+        assert_eq!(
+            encode(&Amf0Value::String("app".to_string())),
+            [MARKER_STRING, 0, 3, b'a', b'p', b'p']
+        );
+        assert_eq!(
+            encode(&Amf0Value::Object(vec![("a".to_string(), Amf0Value::Null)])),
+            [
+                MARKER_OBJECT,
+                0,
+                1,
+                b'a',
+                MARKER_NULL,
+                0,
+                0,
+                MARKER_OBJECT_END
+            ]
+        );
+        assert_eq!(
+            encode(&Amf0Value::StrictArray(vec![Amf0Value::Undefined])),
+            [MARKER_STRICT_ARRAY, 0, 0, 0, 1, MARKER_UNDEFINED]
+        );
+    }
+
+    #[test]
+    fn encodes_long_strings_with_their_own_marker() {
+        let long_string = "a".repeat(u16::MAX as usize + 1);
+        let encoded = encode(&Amf0Value::String(long_string.clone()));
+        assert_eq!(&encoded[..5], &[MARKER_LONG_STRING, 0, 1, 0, 0]);
+        assert_eq!(
+            Amf0Value::decode(&mut &encoded[..]).unwrap(),
+            Amf0Value::String(long_string)
+        );
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let values = vec![
+            Amf0Value::String("connect".to_string()),
+            Amf0Value::Number(1.0),
+            Amf0Value::Object(vec![
+                ("app".to_string(), Amf0Value::String("live".to_string())),
+                ("fpad".to_string(), Amf0Value::Boolean(false)),
+            ]),
+            Amf0Value::Null,
+            Amf0Value::Undefined,
+            Amf0Value::EcmaArray(vec![("duration".to_string(), Amf0Value::Number(0.0))]),
+            Amf0Value::StrictArray(vec![Amf0Value::Number(2.0), Amf0Value::Null]),
+            Amf0Value::Date(1_792_349_581_113.0),
+        ];
+        let mut buf = BytesMut::new();
+        for value in &values {
+            value.encode(&mut buf);
+        }
+        assert_eq!(Amf0Value::decode_all(&buf).unwrap(), values);
+        assert_eq!(
+            values[2].property("app").and_then(Amf0Value::as_str),
+            Some("live")
+        );
+        assert_eq!(
+            values[5].property("duration"),
+            Some(&Amf0Value::Number(0.0))
+        );
+        assert_eq!(values[3].property("app"), None);
+    }
+
+    #[test]
+    fn decodes_ecma_arrays_whatever_their_count() {
+        // encoders don't all fill the count in, the object end marker is what ends the array
+        let encoded = [
+            MARKER_ECMA_ARRAY,
+            0,
+            0,
+            0,
+            0,
+            0,
+            1,
+            b'a',
+            MARKER_BOOLEAN,
+            1,
+            0,
+            0,
+            MARKER_OBJECT_END,
+        ];
+        assert_eq!(
+            Amf0Value::decode_all(&encoded).unwrap(),
+            vec![Amf0Value::EcmaArray(vec![(
+                "a".to_string(),
+                Amf0Value::Boolean(true)
+            )])]
+        );
+    }
+
+    #[test]
+    fn refuses_truncated_and_unsupported_values() {
+        assert!(Amf0Value::decode(&mut &[MARKER_NUMBER, 0, 0][..]).is_err());
+        assert!(Amf0Value::decode(&mut &[MARKER_STRING, 0, 3, b'a'][..]).is_err());
+        assert!(Amf0Value::decode(&mut &[MARKER_OBJECT, 0, 1, b'a'][..]).is_err());
+        assert!(Amf0Value::decode(&mut &[][..]).is_err());
+        // AMF0 references and XML documents aren't used by RTMP publishers
+        assert!(Amf0Value::decode(&mut &[0x07, 0, 0][..]).is_err());
+    }
+}
@@ -0,0 +1,1039 @@
+use crate::rtmp::amf::Amf0Value;
+use crate::rtmp::RtmpMedia;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::debug;
+use rand::RngCore;
+use shared::error::{Error, Result};
+use std::collections::{HashMap, VecDeque};
+
+const RTMP_VERSION: u8 = 3;
+const HANDSHAKE_SIZE: usize = 1536;
+const DEFAULT_CHUNK_SIZE: usize = 128;
+/// chunk size of the messages sent once connected
+const LOCAL_CHUNK_SIZE: usize = 4096;
+const WINDOW_ACK_SIZE: u32 = 2_500_000;
+/// id of the only message stream created, which the publisher publishes on
+const PUBLISH_STREAM_ID: u32 = 1;
+const EXTENDED_TIMESTAMP: u32 = 0xff_ffff;
+
+const CHUNK_STREAM_ID_PROTOCOL_CONTROL: u32 = 2;
+const CHUNK_STREAM_ID_COMMAND: u32 = 3;
+const CHUNK_STREAM_ID_STREAM: u32 = 5;
+
+const MESSAGE_TYPE_SET_CHUNK_SIZE: u8 = 1;
+const MESSAGE_TYPE_ABORT: u8 = 2;
+const MESSAGE_TYPE_ACKNOWLEDGEMENT: u8 = 3;
+const MESSAGE_TYPE_USER_CONTROL: u8 = 4;
+const MESSAGE_TYPE_WINDOW_ACK_SIZE: u8 = 5;
+const MESSAGE_TYPE_SET_PEER_BANDWIDTH: u8 = 6;
+const MESSAGE_TYPE_AUDIO: u8 = 8;
+const MESSAGE_TYPE_VIDEO: u8 = 9;
+const MESSAGE_TYPE_DATA_AMF3: u8 = 15;
+const MESSAGE_TYPE_COMMAND_AMF3: u8 = 17;
+const MESSAGE_TYPE_DATA_AMF0: u8 = 18;
+const MESSAGE_TYPE_COMMAND_AMF0: u8 = 20;
+
+const USER_CONTROL_STREAM_BEGIN: u16 = 0;
+const USER_CONTROL_PING_REQUEST: u16 = 6;
+const USER_CONTROL_PING_RESPONSE: u16 = 7;
+/// dynamic limit type of the set peer bandwidth message
+const PEER_BANDWIDTH_LIMIT_DYNAMIC: u8 = 2;
+
+const SOUND_FORMAT_EX_HEADER: u8 = 9;
+const SOUND_FORMAT_AAC: u8 = 10;
+const AUDIO_PACKET_TYPE_CODED_FRAMES: u8 = 1;
+const FOURCC_OPUS: &[u8] = b"Opus";
+
+const VIDEO_FRAME_TYPE_KEY: u8 = 1;
+const VIDEO_FRAME_TYPE_COMMAND: u8 = 5;
+const VIDEO_CODEC_ID_AVC: u8 = 7;
+const VIDEO_PACKET_TYPE_SEQUENCE_START: u8 = 0;
+const VIDEO_PACKET_TYPE_CODED_FRAMES: u8 = 1;
+const VIDEO_PACKET_TYPE_CODED_FRAMES_X: u8 = 3;
+const FOURCC_AVC: &[u8] = b"avc1";
+const H264_NALU_TYPE_SPS: u8 = 7;
+const ANNEXB_START_CODE: &[u8] = &[0x00, 0x00, 0x00, 0x01];
+
+/// RtmpEvent is what a publisher asks for, or sends, over an RTMP connection
+#[derive(Debug, Clone, PartialEq)]
+pub enum RtmpEvent {
+    /// the publisher wants to publish the stream key in the app it connected to, which has to be
+    /// accepted or rejected
+    Publish { app: String, stream_key: String },
+    /// media of an accepted publish
+    Media(RtmpMedia),
+    /// the publisher stopped publishing
+    Unpublish,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum HandshakeState {
+    /// waiting for C0 and C1
+    Uninitialized,
+    /// S0, S1 and S2 sent, waiting for C2
+    AckSent,
+    Done,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PublishState {
+    Idle,
+    Pending,
+    Publishing,
+}
+
+/// ChunkStream keeps the header fields of the last chunk of a chunk stream, which the following
+/// chunks may omit, and the message they are assembling
+#[derive(Default)]
+struct ChunkStream {
+    timestamp: u32,
+    /// timestamp or timestamp delta of the last chunk header that had one
+    timestamp_field: u32,
+    has_extended_timestamp: bool,
+    message_length: usize,
+    message_type_id: u8,
+    message_stream_id: u32,
+    payload: BytesMut,
+}
+
+struct RtmpMessage {
+    type_id: u8,
+    timestamp: u32,
+    payload: Bytes,
+}
+
+enum ChunkRead {
+    /// the input doesn't hold a whole chunk yet
+    Incomplete,
+    /// a chunk was read, its message being incomplete
+    Partial,
+    Message(RtmpMessage),
+}
+
+/// AvcConfig is the H.264 configuration of the AVC sequence header
+struct AvcConfig {
+    nalu_length_size: usize,
+    parameter_sets: Vec<Bytes>,
+}
+
+/// RtmpConnection is the server side of the RTMP connection of a publisher, which decodes the
+/// H.264 video and the enhanced RTMP Opus audio it publishes. It doesn't do any I/O, the bytes
+/// read from the connection are given to handle_input, and the bytes to write are polled from
+/// poll_output
+pub struct RtmpConnection {
+    handshake_state: HandshakeState,
+    publish_state: PublishState,
+    input: BytesMut,
+    outputs: VecDeque<BytesMut>,
+    peer_chunk_size: usize,
+    local_chunk_size: usize,
+    chunk_streams: HashMap<u32, ChunkStream>,
+    /// window acknowledgement size set by the publisher
+    peer_window_ack_size: Option<u32>,
+    received_bytes: u64,
+    acknowledged_bytes: u64,
+    app: Option<String>,
+    avc_config: Option<AvcConfig>,
+}
+
+impl Default for RtmpConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RtmpConnection {
+    pub fn new() -> Self {
+        Self {
+            handshake_state: HandshakeState::Uninitialized,
+            publish_state: PublishState::Idle,
+            input: BytesMut::new(),
+            outputs: VecDeque::new(),
+            peer_chunk_size: DEFAULT_CHUNK_SIZE,
+            local_chunk_size: DEFAULT_CHUNK_SIZE,
+            chunk_streams: HashMap::new(),
+            peer_window_ack_size: None,
+            received_bytes: 0,
+            acknowledged_bytes: 0,
+            app: None,
+            avc_config: None,
+        }
+    }
+
+    /// handle_input reads the bytes received from the publisher, and returns the events of the
+    /// messages they complete. An error is fatal to the connection, the publisher being told
+    /// about it when it publishes
+    pub fn handle_input(&mut self, data: &[u8]) -> Result<Vec<RtmpEvent>> {
+        self.input.extend_from_slice(data);
+        self.received_bytes += data.len() as u64;
+
+        let mut events = vec![];
+        if let Err(err) = self.read_input(&mut events) {
+            if self.publish_state != PublishState::Idle {
+                self.publish_state = PublishState::Idle;
+                self.send_on_status("error", "NetStream.Publish.Denied", &err.to_string());
+            }
+            return Err(err);
+        }
+        self.acknowledge();
+        Ok(events)
+    }
+
+    /// poll_output returns the next bytes to write to the publisher
+    pub fn poll_output(&mut self) -> Option<BytesMut> {
+        self.outputs.pop_front()
+    }
+
+    /// accept_publish starts the publish, its media being returned from now on
+    pub fn accept_publish(&mut self) {
+        self.publish_state = PublishState::Publishing;
+        let mut payload = BytesMut::new();
+        payload.put_u16(USER_CONTROL_STREAM_BEGIN);
+        payload.put_u32(PUBLISH_STREAM_ID);
+        self.send_message(
+            CHUNK_STREAM_ID_PROTOCOL_CONTROL,
+            MESSAGE_TYPE_USER_CONTROL,
+            0,
+            &payload,
+        );
+        self.send_on_status("status", "NetStream.Publish.Start", "publishing");
+    }
+
+    /// reject_publish tells the publisher why it can't publish
+    pub fn reject_publish(&mut self, description: &str) {
+        self.publish_state = PublishState::Idle;
+        self.send_on_status("error", "NetStream.Publish.Denied", description);
+    }
+
+    fn read_input(&mut self, events: &mut Vec<RtmpEvent>) -> Result<()> {
+        loop {
+            match self.handshake_state {
+                HandshakeState::Uninitialized => {
+                    if self.input.len() < 1 + HANDSHAKE_SIZE {
+                        return Ok(());
+                    }
+                    if self.input[0] != RTMP_VERSION {
+                        return Err(Error::Other(format!(
+                            "unsupported rtmp version {}",
+                            self.input[0]
+                        )));
+                    }
+                    let c1 = self.input.split_to(1 + HANDSHAKE_SIZE).split_off(1);
+                    let mut s0s1s2 = BytesMut::with_capacity(1 + 2 * HANDSHAKE_SIZE);
+                    s0s1s2.put_u8(RTMP_VERSION);
+                    // time and zero fields of S1, followed by its random bytes
+                    s0s1s2.put_bytes(0, 8);
+                    let mut random = vec![0u8; HANDSHAKE_SIZE - 8];
+                    rand::thread_rng().fill_bytes(&mut random);
+                    s0s1s2.put_slice(&random);
+                    // S2 echoes C1
+                    s0s1s2.put_slice(&c1);
+                    self.outputs.push_back(s0s1s2);
+                    self.handshake_state = HandshakeState::AckSent;
+                }
+                HandshakeState::AckSent => {
+                    if self.input.len() < HANDSHAKE_SIZE {
+                        return Ok(());
+                    }
+                    self.input.advance(HANDSHAKE_SIZE);
+                    self.handshake_state = HandshakeState::Done;
+                }
+                HandshakeState::Done => match self.read_chunk()? {
+                    ChunkRead::Incomplete => return Ok(()),
+                    ChunkRead::Partial => {}
+                    ChunkRead::Message(message) => {
+                        if let Some(event) = self.handle_message(message)? {
+                            events.push(event);
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// read_chunk reads the next chunk of the input, leaving the input untouched while the
+    /// chunk is incomplete
+    fn read_chunk(&mut self) -> Result<ChunkRead> {
+        let buf = &self.input[..];
+        if buf.is_empty() {
+            return Ok(ChunkRead::Incomplete);
+        }
+        let fmt = buf[0] >> 6;
+        let (chunk_stream_id, mut offset) = match buf[0] & 0x3f {
+            0 if buf.len() >= 2 => (64 + buf[1] as u32, 2),
+            1 if buf.len() >= 3 => (64 + buf[1] as u32 + buf[2] as u32 * 256, 3),
+            0 | 1 => return Ok(ChunkRead::Incomplete),
+            chunk_stream_id => (chunk_stream_id as u32, 1),
+        };
+        let header_len = [11, 7, 3, 0][fmt as usize];
+        if buf.len() < offset + header_len {
+            return Ok(ChunkRead::Incomplete);
+        }
+        let header = &buf[offset..offset + header_len];
+        offset += header_len;
+
+        let new_chunk_stream = ChunkStream::default();
+        let chunk_stream = match self.chunk_streams.get(&chunk_stream_id) {
+            Some(chunk_stream) => chunk_stream,
+            None if fmt == 0 => &new_chunk_stream,
+            None => {
+                return Err(Error::Other(format!(
+                    "chunk of format {} starts chunk stream {}",
+                    fmt, chunk_stream_id
+                )))
+            }
+        };
+        let message_length = if fmt <= 1 {
+            read_u24(&header[3..6]) as usize
+        } else {
+            chunk_stream.message_length
+        };
+        let timestamp_field = (fmt <= 2).then(|| read_u24(&header[0..3]));
+        let has_extended_timestamp = timestamp_field
+            .map_or(chunk_stream.has_extended_timestamp, |timestamp_field| {
+                timestamp_field == EXTENDED_TIMESTAMP
+            });
+        let timestamp_field = if has_extended_timestamp {
+            if buf.len() < offset + 4 {
+                return Ok(ChunkRead::Incomplete);
+            }
+            offset += 4;
+            read_u32(&buf[offset - 4..offset])
+        } else {
+            timestamp_field.unwrap_or(chunk_stream.timestamp_field)
+        };
+        let is_new_message = fmt <= 2 || chunk_stream.payload.is_empty();
+        let assembled_len = if is_new_message {
+            0
+        } else {
+            chunk_stream.payload.len()
+        };
+        let chunk_len = message_length
+            .saturating_sub(assembled_len)
+            .min(self.peer_chunk_size);
+        if buf.len() < offset + chunk_len {
+            return Ok(ChunkRead::Incomplete);
+        }
+        let message_type_id = header.get(6).copied();
+        let message_stream_id = header
+            .get(7..11)
+            .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]));
+
+        let mut chunk = self.input.split_to(offset + chunk_len);
+        chunk.advance(offset);
+        let chunk_stream = self.chunk_streams.entry(chunk_stream_id).or_default();
+        if is_new_message {
+            chunk_stream.payload.clear();
+            chunk_stream.timestamp = if fmt == 0 {
+                timestamp_field
+            } else {
+                // a new message without timestamp field has the delta of the previous one
+                chunk_stream.timestamp.wrapping_add(timestamp_field)
+            };
+        }
+        chunk_stream.timestamp_field = timestamp_field;
+        chunk_stream.has_extended_timestamp = has_extended_timestamp;
+        chunk_stream.message_length = message_length;
+        if let Some(message_type_id) = message_type_id {
+            chunk_stream.message_type_id = message_type_id;
+        }
+        if let Some(message_stream_id) = message_stream_id {
+            chunk_stream.message_stream_id = message_stream_id;
+        }
+        chunk_stream.payload.unsplit(chunk);
+        if chunk_stream.payload.len() < message_length {
+            return Ok(ChunkRead::Partial);
+        }
+
+        Ok(ChunkRead::Message(RtmpMessage {
+            type_id: chunk_stream.message_type_id,
+            timestamp: chunk_stream.timestamp,
+            payload: chunk_stream.payload.split().freeze(),
+        }))
+    }
+
+    fn handle_message(&mut self, message: RtmpMessage) -> Result<Option<RtmpEvent>> {
+        let payload = message.payload;
+        match message.type_id {
+            MESSAGE_TYPE_SET_CHUNK_SIZE => {
+                let chunk_size = read_u32(check_len(&payload, 4)?) & 0x7fff_ffff;
+                if chunk_size == 0 {
+                    return Err(Error::Other("chunk size 0 is invalid".to_string()));
+                }
+                self.peer_chunk_size = chunk_size as usize;
+            }
+            MESSAGE_TYPE_ABORT => {
+                let chunk_stream_id = read_u32(check_len(&payload, 4)?);
+                if let Some(chunk_stream) = self.chunk_streams.get_mut(&chunk_stream_id) {
+                    chunk_stream.payload.clear();
+                }
+            }
+            MESSAGE_TYPE_WINDOW_ACK_SIZE => {
+                self.peer_window_ack_size = Some(read_u32(check_len(&payload, 4)?));
+            }
+            MESSAGE_TYPE_USER_CONTROL => {
+                let event_type = u16::from_be_bytes([check_len(&payload, 2)?[0], payload[1]]);
+                if event_type == USER_CONTROL_PING_REQUEST {
+                    let mut response = BytesMut::new();
+                    response.put_u16(USER_CONTROL_PING_RESPONSE);
+                    response.put_slice(&payload[2..]);
+                    self.send_message(
+                        CHUNK_STREAM_ID_PROTOCOL_CONTROL,
+                        MESSAGE_TYPE_USER_CONTROL,
+                        0,
+                        &response,
+                    );
+                }
+            }
+            MESSAGE_TYPE_ACKNOWLEDGEMENT
+            | MESSAGE_TYPE_SET_PEER_BANDWIDTH
+            | MESSAGE_TYPE_DATA_AMF0
+            | MESSAGE_TYPE_DATA_AMF3 => {}
+            MESSAGE_TYPE_COMMAND_AMF0 => {
+                return self.handle_command(Amf0Value::decode_all(&payload)?);
+            }
+            MESSAGE_TYPE_COMMAND_AMF3 => {
+                // AMF3 commands are AMF0 values behind a format selector byte
+                return self.handle_command(Amf0Value::decode_all(&check_len(&payload, 1)?[1..])?);
+            }
+            MESSAGE_TYPE_AUDIO if self.publish_state == PublishState::Publishing => {
+                return Ok(self
+                    .handle_audio(message.timestamp, payload)?
+                    .map(RtmpEvent::Media));
+            }
+            MESSAGE_TYPE_VIDEO if self.publish_state == PublishState::Publishing => {
+                return Ok(self
+                    .handle_video(message.timestamp, payload)?
+                    .map(RtmpEvent::Media));
+            }
+            MESSAGE_TYPE_AUDIO | MESSAGE_TYPE_VIDEO => {}
+            type_id => debug!("ignore rtmp message of type {}", type_id),
+        }
+        Ok(None)
+    }
+
+    fn handle_command(&mut self, values: Vec<Amf0Value>) -> Result<Option<RtmpEvent>> {
+        let name = values
+            .first()
+            .and_then(Amf0Value::as_str)
+            .unwrap_or_default();
+        let transaction_id = values.get(1).and_then(Amf0Value::as_number).unwrap_or(0.0);
+        debug!("rtmp command {} of transaction {}", name, transaction_id);
+        match name {
+            "connect" => {
+                let app = values
+                    .get(2)
+                    .and_then(|command_object| command_object.property("app"))
+                    .and_then(Amf0Value::as_str)
+                    .ok_or(Error::Other("connect without app".to_string()))?;
+                self.app = Some(app.trim_matches('/').to_string());
+                self.send_connect_result(transaction_id);
+            }
+            "releaseStream" | "FCPublish" => {
+                self.send_command(
+                    CHUNK_STREAM_ID_COMMAND,
+                    0,
+                    &[
+                        Amf0Value::String("_result".to_string()),
+                        Amf0Value::Number(transaction_id),
+                        Amf0Value::Null,
+                    ],
+                );
+            }
+            "createStream" => {
+                self.send_command(
+                    CHUNK_STREAM_ID_COMMAND,
+                    0,
+                    &[
+                        Amf0Value::String("_result".to_string()),
+                        Amf0Value::Number(transaction_id),
+                        Amf0Value::Null,
+                        Amf0Value::Number(PUBLISH_STREAM_ID as f64),
+                    ],
+                );
+            }
+            "publish" => {
+                let app = self
+                    .app
+                    .clone()
+                    .ok_or(Error::Other("publish before connect".to_string()))?;
+                if self.publish_state != PublishState::Idle {
+                    return Err(Error::Other("publish while publishing".to_string()));
+                }
+                // the publishing name is the third argument, after the null command object
+                let stream_key = values
+                    .get(3)
+                    .and_then(Amf0Value::as_str)
+                    .ok_or(Error::Other("publish without publishing name".to_string()))?;
+                // query parameters of the name are meant for authentication, which isn't done
+                let stream_key = stream_key.split('?').next().unwrap_or_default();
+                self.publish_state = PublishState::Pending;
+                return Ok(Some(RtmpEvent::Publish {
+                    app,
+                    stream_key: stream_key.to_string(),
+                }));
+            }
+            "FCUnpublish" | "deleteStream" | "closeStream"
+                if self.publish_state != PublishState::Idle =>
+            {
+                self.publish_state = PublishState::Idle;
+                return Ok(Some(RtmpEvent::Unpublish));
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// handle_audio returns the Opus packet of enhanced RTMP audio, other formats being refused
+    fn handle_audio(&mut self, timestamp: u32, data: Bytes) -> Result<Option<RtmpMedia>> {
+        let Some(&flags) = data.first() else {
+            return Ok(None);
+        };
+        match flags >> 4 {
+            SOUND_FORMAT_EX_HEADER => {
+                let fourcc = &check_len(&data, 5)?[1..5];
+                if fourcc != FOURCC_OPUS {
+                    return Err(Error::Other(format!(
+                        "audio codec {} is not supported, publish Opus",
+                        String::from_utf8_lossy(fourcc)
+                    )));
+                }
+                // the sequence start only holds the Opus identification header
+                if flags & 0x0f != AUDIO_PACKET_TYPE_CODED_FRAMES {
+                    return Ok(None);
+                }
+                Ok(Some(RtmpMedia::Audio {
+                    timestamp,
+                    data: data.slice(5..),
+                }))
+            }
+            SOUND_FORMAT_AAC => Err(Error::Other(
+                "AAC audio is not supported, publish Opus with enhanced RTMP".to_string(),
+            )),
+            sound_format => Err(Error::Other(format!(
+                "audio sound format {} is not supported, publish Opus with enhanced RTMP",
+                sound_format
+            ))),
+        }
+    }
+
+    /// handle_video returns the H.264 access units of legacy or enhanced RTMP video, in Annex B
+    /// format, other codecs being refused
+    fn handle_video(&mut self, timestamp: u32, data: Bytes) -> Result<Option<RtmpMedia>> {
+        let Some(&flags) = data.first() else {
+            return Ok(None);
+        };
+        let frame_type = (flags >> 4) & 0x07;
+        if frame_type == VIDEO_FRAME_TYPE_COMMAND {
+            return Ok(None);
+        }
+        let (packet_type, composition_time, body) = if flags & 0x80 != 0 {
+            let fourcc = &check_len(&data, 5)?[1..5];
+            if fourcc != FOURCC_AVC {
+                return Err(Error::Other(format!(
+                    "video codec {} is not supported, publish H.264",
+                    String::from_utf8_lossy(fourcc)
+                )));
+            }
+            match flags & 0x0f {
+                VIDEO_PACKET_TYPE_CODED_FRAMES => (
+                    VIDEO_PACKET_TYPE_CODED_FRAMES,
+                    read_i24(&check_len(&data, 8)?[5..8]),
+                    data.slice(8..),
+                ),
+                VIDEO_PACKET_TYPE_CODED_FRAMES_X => {
+                    (VIDEO_PACKET_TYPE_CODED_FRAMES, 0, data.slice(5..))
+                }
+                packet_type => (packet_type, 0, data.slice(5..)),
+            }
+        } else {
+            if flags & 0x0f != VIDEO_CODEC_ID_AVC {
+                return Err(Error::Other(format!(
+                    "video codec id {} is not supported, publish H.264",
+                    flags & 0x0f
+                )));
+            }
+            let header = check_len(&data, 5)?;
+            (header[1], read_i24(&header[2..5]), data.slice(5..))
+        };
+
+        match packet_type {
+            VIDEO_PACKET_TYPE_SEQUENCE_START => {
+                self.avc_config = Some(parse_avc_config(&body)?);
+                Ok(None)
+            }
+            VIDEO_PACKET_TYPE_CODED_FRAMES => {
+                let Some(avc_config) = &self.avc_config else {
+                    debug!("drop rtmp video frame received before its sequence header");
+                    return Ok(None);
+                };
+                let is_keyframe = frame_type == VIDEO_FRAME_TYPE_KEY;
+                let mut access_unit = BytesMut::with_capacity(body.len() + 64);
+                let mut nalus = &body[..];
+                let mut has_sps = false;
+                while !nalus.is_empty() {
+                    let len_size = avc_config.nalu_length_size;
+                    if nalus.len() < len_size {
+                        return Err(Error::Other("truncated h264 nalu length".to_string()));
+                    }
+                    let len = nalus[..len_size]
+                        .iter()
+                        .fold(0usize, |len, &b| (len << 8) | b as usize);
+                    nalus = &nalus[len_size..];
+                    if nalus.len() < len {
+                        return Err(Error::Other("truncated h264 nalu".to_string()));
+                    }
+                    if len > 0 {
+                        has_sps |= nalus[0] & 0x1f == H264_NALU_TYPE_SPS;
+                        access_unit.put_slice(ANNEXB_START_CODE);
+                        access_unit.put_slice(&nalus[..len]);
+                    }
+                    nalus = &nalus[len..];
+                }
+                if access_unit.is_empty() {
+                    return Ok(None);
+                }
+                // the parameter sets are sent out of band in RTMP, and in band in RTP
+                if is_keyframe && !has_sps {
+                    let mut parameter_sets = BytesMut::new();
+                    for parameter_set in &avc_config.parameter_sets {
+                        parameter_sets.put_slice(ANNEXB_START_CODE);
+                        parameter_sets.put_slice(parameter_set);
+                    }
+                    parameter_sets.unsplit(access_unit);
+                    access_unit = parameter_sets;
+                }
+                Ok(Some(RtmpMedia::Video {
+                    timestamp: timestamp.wrapping_add_signed(composition_time),
+                    is_keyframe,
+                    data: access_unit.freeze(),
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn send_connect_result(&mut self, transaction_id: f64) {
+        self.send_message(
+            CHUNK_STREAM_ID_PROTOCOL_CONTROL,
+            MESSAGE_TYPE_WINDOW_ACK_SIZE,
+            0,
+            &WINDOW_ACK_SIZE.to_be_bytes(),
+        );
+        let mut peer_bandwidth = BytesMut::new();
+        peer_bandwidth.put_u32(WINDOW_ACK_SIZE);
+        peer_bandwidth.put_u8(PEER_BANDWIDTH_LIMIT_DYNAMIC);
+        self.send_message(
+            CHUNK_STREAM_ID_PROTOCOL_CONTROL,
+            MESSAGE_TYPE_SET_PEER_BANDWIDTH,
+            0,
+            &peer_bandwidth,
+        );
+        self.send_message(
+            CHUNK_STREAM_ID_PROTOCOL_CONTROL,
+            MESSAGE_TYPE_SET_CHUNK_SIZE,
+            0,
+            &(LOCAL_CHUNK_SIZE as u32).to_be_bytes(),
+        );
+        self.local_chunk_size = LOCAL_CHUNK_SIZE;
+        self.send_command(
+            CHUNK_STREAM_ID_COMMAND,
+            0,
+            &[
+                Amf0Value::String("_result".to_string()),
+                Amf0Value::Number(transaction_id),
+                Amf0Value::Object(vec![
+                    (
+                        "fmsVer".to_string(),
+                        Amf0Value::String("FMS/3,0,1,123".to_string()),
+                    ),
+                    ("capabilities".to_string(), Amf0Value::Number(31.0)),
+                ]),
+                Amf0Value::Object(vec![
+                    ("level".to_string(), Amf0Value::String("status".to_string())),
+                    (
+                        "code".to_string(),
+                        Amf0Value::String("NetConnection.Connect.Success".to_string()),
+                    ),
+                    (
+                        "description".to_string(),
+                        Amf0Value::String("Connection succeeded.".to_string()),
+                    ),
+                    ("objectEncoding".to_string(), Amf0Value::Number(0.0)),
+                ]),
+            ],
+        );
+    }
+
+    fn send_on_status(&mut self, level: &str, code: &str, description: &str) {
+        self.send_command(
+            CHUNK_STREAM_ID_STREAM,
+            PUBLISH_STREAM_ID,
+            &[
+                Amf0Value::String("onStatus".to_string()),
+                Amf0Value::Number(0.0),
+                Amf0Value::Null,
+                Amf0Value::Object(vec![
+                    ("level".to_string(), Amf0Value::String(level.to_string())),
+                    ("code".to_string(), Amf0Value::String(code.to_string())),
+                    (
+                        "description".to_string(),
+                        Amf0Value::String(description.to_string()),
+                    ),
+                ]),
+            ],
+        );
+    }
+
+    fn send_command(&mut self, chunk_stream_id: u32, message_stream_id: u32, values: &[Amf0Value]) {
+        let mut payload = BytesMut::new();
+        for value in values {
+            value.encode(&mut payload);
+        }
+        self.send_message(
+            chunk_stream_id,
+            MESSAGE_TYPE_COMMAND_AMF0,
+            message_stream_id,
+            &payload,
+        );
+    }
+
+    /// send_message splits a message into chunks, the first one with a full header, and the
+    /// others with none. The chunk stream ids of the messages sent fit in the first byte
+    fn send_message(
+        &mut self,
+        chunk_stream_id: u32,
+        type_id: u8,
+        message_stream_id: u32,
+        payload: &[u8],
+    ) {
+        let mut output =
+            BytesMut::with_capacity(12 + payload.len() + payload.len() / self.local_chunk_size);
+        output.put_u8(chunk_stream_id as u8);
+        // timestamp
+        output.put_bytes(0, 3);
+        output.put_uint(payload.len() as u64, 3);
+        output.put_u8(type_id);
+        output.put_u32_le(message_stream_id);
+        for (i, chunk) in payload.chunks(self.local_chunk_size).enumerate() {
+            if i > 0 {
+                output.put_u8(0xc0 | chunk_stream_id as u8);
+            }
+            output.put_slice(chunk);
+        }
+        self.outputs.push_back(output);
+    }
+
+    /// acknowledge tells the publisher how many bytes were received, each time its window
+    /// acknowledgement size is reached
+    fn acknowledge(&mut self) {
+        let Some(window_ack_size) = self.peer_window_ack_size else {
+            return;
+        };
+        if self.received_bytes - self.acknowledged_bytes < window_ack_size as u64 {
+            return;
+        }
+        self.acknowledged_bytes = self.received_bytes;
+        self.send_message(
+            CHUNK_STREAM_ID_PROTOCOL_CONTROL,
+            MESSAGE_TYPE_ACKNOWLEDGEMENT,
+            0,
+            &(self.received_bytes as u32).to_be_bytes(),
+        );
+    }
+}
+
+/// parse_avc_config returns the nalu length size and the parameter sets of an
+/// AVCDecoderConfigurationRecord
+fn parse_avc_config(data: &[u8]) -> Result<AvcConfig> {
+    let header = check_len(data, 6)?;
+    let nalu_length_size = (header[4] & 0x03) as usize + 1;
+    let mut parameter_sets = vec![];
+    let mut rest = &data[5..];
+    // the sequence parameter sets are counted on 5 bits, the picture ones on 8 bits
+    for count_mask in [0x1f, 0xff] {
+        let count = check_len(rest, 1)?[0] & count_mask;
+        rest = &rest[1..];
+        for _ in 0..count {
+            let len = u16::from_be_bytes([check_len(rest, 2)?[0], rest[1]]) as usize;
+            let parameter_set = &check_len(rest, 2 + len)?[2..2 + len];
+            parameter_sets.push(Bytes::copy_from_slice(parameter_set));
+            rest = &rest[2 + len..];
+        }
+    }
+    Ok(AvcConfig {
+        nalu_length_size,
+        parameter_sets,
+    })
+}
+
+fn check_len(data: &[u8], len: usize) -> Result<&[u8]> {
+    if data.len() < len {
+        return Err(Error::Other(format!(
+            "rtmp message of {} bytes is shorter than {} bytes",
+            data.len(),
+            len
+        )));
+    }
+    Ok(data)
+}
+
+fn read_u24(data: &[u8]) -> u32 {
+    u32::from_be_bytes([0, data[0], data[1], data[2]])
+}
+
+fn read_i24(data: &[u8]) -> i32 {
+    (u32::from_be_bytes([data[0], data[1], data[2], 0]) as i32) >> 8
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(connection: &mut RtmpConnection) -> Vec<u8> {
+        let mut c0c1 = vec![RTMP_VERSION];
+        c0c1.extend((0..HANDSHAKE_SIZE).map(|i| i as u8));
+        assert!(connection.handle_input(&c0c1).unwrap().is_empty());
+        let s0s1s2 = connection.poll_output().unwrap().to_vec();
+        assert!(connection
+            .handle_input(&[0; HANDSHAKE_SIZE])
+            .unwrap()
+            .is_empty());
+        s0s1s2
+    }
+
+    /// message splits a message sent by a publisher into chunks of chunk_size, the first one
+    /// with a full header and the others without any
+    fn message(
+        chunk_stream_id: u8,
+        type_id: u8,
+        timestamp: u32,
+        payload: &[u8],
+        chunk_size: usize,
+    ) -> Vec<u8> {
+        let mut data = vec![chunk_stream_id];
+        data.extend_from_slice(&timestamp.min(EXTENDED_TIMESTAMP).to_be_bytes()[1..]);
+        data.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        data.push(type_id);
+        data.extend_from_slice(&PUBLISH_STREAM_ID.to_le_bytes());
+        for (i, chunk) in payload.chunks(chunk_size).enumerate() {
+            if i > 0 {
+                data.push(0xc0 | chunk_stream_id);
+            }
+            if timestamp >= EXTENDED_TIMESTAMP {
+                data.extend_from_slice(&timestamp.to_be_bytes());
+            }
+            data.extend_from_slice(chunk);
+        }
+        data
+    }
+
+    fn command(values: &[Amf0Value]) -> Vec<u8> {
+        let mut payload = BytesMut::new();
+        for value in values {
+            value.encode(&mut payload);
+        }
+        message(
+            CHUNK_STREAM_ID_COMMAND as u8,
+            MESSAGE_TYPE_COMMAND_AMF0,
+            0,
+            &payload,
+            DEFAULT_CHUNK_SIZE,
+        )
+    }
+
+    fn outputs(connection: &mut RtmpConnection) -> Vec<u8> {
+        std::iter::from_fn(|| connection.poll_output())
+            .flat_map(|output| output.to_vec())
+            .collect()
+    }
+
+    fn opus(payload: &[u8]) -> Vec<u8> {
+        [
+            &[SOUND_FORMAT_EX_HEADER << 4 | AUDIO_PACKET_TYPE_CODED_FRAMES][..],
+            FOURCC_OPUS,
+            payload,
+        ]
+        .concat()
+    }
+
+    fn audio_timestamps(events: Vec<RtmpEvent>) -> Vec<u32> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                RtmpEvent::Media(RtmpMedia::Audio { timestamp, .. }) => timestamp,
+                event => panic!("unexpected event {:?}", event),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn answers_the_handshake() {
+        let mut connection = RtmpConnection::new();
+        let mut c0c1 = vec![RTMP_VERSION];
+        c0c1.extend((0..HANDSHAKE_SIZE).map(|i| i as u8));
+
+        // nothing is sent until C1 is whole
+        assert!(connection.handle_input(&c0c1[..100]).unwrap().is_empty());
+        assert!(connection.poll_output().is_none());
+        assert!(connection.handle_input(&c0c1[100..]).unwrap().is_empty());
+        let s0s1s2 = connection.poll_output().unwrap();
+        assert_eq!(s0s1s2.len(), 1 + 2 * HANDSHAKE_SIZE);
+        assert_eq!(s0s1s2[0], RTMP_VERSION);
+        assert_eq!(&s0s1s2[1 + HANDSHAKE_SIZE..], &c0c1[1..]);
+
+        // C2 ends the handshake, the chunks following it in the same input being read
+        let mut c2 = vec![0; HANDSHAKE_SIZE];
+        c2.extend(message(
+            CHUNK_STREAM_ID_PROTOCOL_CONTROL as u8,
+            MESSAGE_TYPE_SET_CHUNK_SIZE,
+            0,
+            &4096u32.to_be_bytes(),
+            DEFAULT_CHUNK_SIZE,
+        ));
+        assert!(connection.handle_input(&c2).unwrap().is_empty());
+        assert_eq!(connection.handshake_state, HandshakeState::Done);
+        assert_eq!(connection.peer_chunk_size, 4096);
+    }
+
+    #[test]
+    fn refuses_unsupported_versions() {
+        let mut connection = RtmpConnection::new();
+        let mut c0c1 = vec![6];
+        c0c1.extend([0; HANDSHAKE_SIZE]);
+        assert!(connection.handle_input(&c0c1).is_err());
+    }
+
+    #[test]
+    fn reassembles_messages_split_into_chunks() {
+        let mut connection = RtmpConnection::new();
+        handshake(&mut connection);
+
+        // a connect command longer than a chunk, received a byte at a time
+        let tc_url = format!("rtmp://127.0.0.1/live/{}", "a".repeat(200));
+        let connect = command(&[
+            Amf0Value::String("connect".to_string()),
+            Amf0Value::Number(1.0),
+            Amf0Value::Object(vec![
+                ("app".to_string(), Amf0Value::String("live/".to_string())),
+                ("tcUrl".to_string(), Amf0Value::String(tc_url)),
+            ]),
+        ]);
+        assert!(connect.len() > 2 * DEFAULT_CHUNK_SIZE);
+        for byte in &connect {
+            assert!(connection.handle_input(&[*byte]).unwrap().is_empty());
+        }
+        assert_eq!(connection.app.as_deref(), Some("live"));
+        let outputs = outputs(&mut connection);
+        let result = outputs
+            .windows(b"NetConnection.Connect.Success".len())
+            .any(|window| window == b"NetConnection.Connect.Success");
+        assert!(result);
+
+        let publish = command(&[
+            Amf0Value::String("publish".to_string()),
+            Amf0Value::Number(5.0),
+            Amf0Value::Null,
+            Amf0Value::String("2?token=secret".to_string()),
+            Amf0Value::String("live".to_string()),
+        ]);
+        assert_eq!(
+            connection.handle_input(&publish).unwrap(),
+            vec![RtmpEvent::Publish {
+                app: "live".to_string(),
+                stream_key: "2".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn reads_chunk_headers_omitting_the_fields_of_the_previous_chunk() {
+        let mut connection = RtmpConnection::new();
+        handshake(&mut connection);
+        connection.publish_state = PublishState::Publishing;
+        let frame = opus(&[0xfc; 3]);
+        let len = (frame.len() as u32).to_be_bytes();
+
+        let mut data = message(
+            CHUNK_STREAM_ID_STREAM as u8,
+            MESSAGE_TYPE_AUDIO,
+            1000,
+            &frame,
+            DEFAULT_CHUNK_SIZE,
+        );
+        // timestamp delta, message length and type
+        data.extend([0x40 | CHUNK_STREAM_ID_STREAM as u8, 0, 0, 20]);
+        data.extend_from_slice(&len[1..]);
+        data.push(MESSAGE_TYPE_AUDIO);
+        data.extend_from_slice(&frame);
+        // timestamp delta only
+        data.extend([0x80 | CHUNK_STREAM_ID_STREAM as u8, 0, 0, 40]);
+        data.extend_from_slice(&frame);
+        // nothing, the previous delta being applied again
+        data.push(0xc0 | CHUNK_STREAM_ID_STREAM as u8);
+        data.extend_from_slice(&frame);
+
+        assert_eq!(
+            audio_timestamps(connection.handle_input(&data).unwrap()),
+            vec![1000, 1020, 1060, 1100]
+        );
+    }
+
+    #[test]
+    fn reads_extended_timestamps_of_every_chunk() {
+        let mut connection = RtmpConnection::new();
+        handshake(&mut connection);
+        connection.publish_state = PublishState::Publishing;
+
+        let frame = opus(&[0xfc; 300]);
+        let data = message(
+            CHUNK_STREAM_ID_STREAM as u8,
+            MESSAGE_TYPE_AUDIO,
+            0x0100_0000,
+            &frame,
+            DEFAULT_CHUNK_SIZE,
+        );
+        let events = connection.handle_input(&data).unwrap();
+        assert_eq!(
+            events,
+            vec![RtmpEvent::Media(RtmpMedia::Audio {
+                timestamp: 0x0100_0000,
+                data: Bytes::copy_from_slice(&frame[5..]),
+            })]
+        );
+    }
+
+    #[test]
+    fn refuses_chunk_streams_started_without_a_full_header() {
+        let mut connection = RtmpConnection::new();
+        handshake(&mut connection);
+        let data = [0x80 | CHUNK_STREAM_ID_STREAM as u8, 0, 0, 20];
+        assert!(connection.handle_input(&data).is_err());
+    }
+
+    #[test]
+    fn answers_pings() {
+        let mut connection = RtmpConnection::new();
+        handshake(&mut connection);
+        let mut ping = USER_CONTROL_PING_REQUEST.to_be_bytes().to_vec();
+        ping.extend_from_slice(&1234u32.to_be_bytes());
+        connection
+            .handle_input(&message(
+                CHUNK_STREAM_ID_PROTOCOL_CONTROL as u8,
+                MESSAGE_TYPE_USER_CONTROL,
+                0,
+                &ping,
+                DEFAULT_CHUNK_SIZE,
+            ))
+            .unwrap();
+
+        let pong = connection.poll_output().unwrap();
+        assert_eq!(pong[7], MESSAGE_TYPE_USER_CONTROL);
+        assert_eq!(&pong[12..14], &USER_CONTROL_PING_RESPONSE.to_be_bytes());
+        assert_eq!(&pong[14..], &1234u32.to_be_bytes());
+    }
+}
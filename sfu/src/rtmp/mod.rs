@@ -0,0 +1,201 @@
+pub(crate) mod amf;
+pub(crate) mod connection;
+
+use crate::description::config::{MIME_TYPE_H264, MIME_TYPE_OPUS};
+use crate::description::rtp_codec::{RTCRtpCodecParameters, RTCRtpParameters, RTPCodecType};
+use crate::description::rtp_transceiver::{MediaStreamId, RTCRtpSender, RTCRtpTransceiver, SSRC};
+use crate::description::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+use crate::injector::find_codec;
+use crate::session::config::SessionConfig;
+use crate::types::{EndpointId, Mid};
+use bytes::Bytes;
+use log::warn;
+use rtp::codecs::{h264::H264Payloader, opus::OpusPayloader};
+use rtp::packetizer::Payloader;
+use shared::error::{Error, Result};
+
+/// mtu of the payloads of bridged packets, leaving room for SRTP and header extensions
+const RTP_PAYLOAD_MTU: usize = 1200;
+const AUDIO_MID: &str = "0";
+const VIDEO_MID: &str = "1";
+
+/// RtmpMedia is a frame published over RTMP, timestamped in milliseconds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RtmpMedia {
+    /// H.264 access unit in Annex B format, starting with the parameter sets on keyframes
+    Video {
+        timestamp: u32,
+        is_keyframe: bool,
+        data: Bytes,
+    },
+    /// Opus packet
+    Audio { timestamp: u32, data: Bytes },
+}
+
+/// RtmpIngest packetizes the frames of an RTMP publisher into RTP packets, published into its
+/// session as a virtual endpoint
+pub(crate) struct RtmpIngest {
+    endpoint_id: EndpointId,
+    audio: RtmpTrack,
+    video: RtmpTrack,
+    packets: Vec<(Mid, RTPCodecType, rtp::packet::Packet)>,
+}
+
+impl RtmpIngest {
+    /// new takes the Opus codec and the first H.264 codec in non interleaved mode the session
+    /// negotiates
+    pub(crate) fn new(endpoint_id: EndpointId, session_config: &SessionConfig) -> Result<Self> {
+        let video_codec = session_config
+            .get_codecs_by_kind(RTPCodecType::Video)
+            .into_iter()
+            .find(|codec| {
+                codec
+                    .capability
+                    .mime_type
+                    .eq_ignore_ascii_case(MIME_TYPE_H264)
+                    && codec
+                        .capability
+                        .sdp_fmtp_line
+                        .contains("packetization-mode=1")
+            })
+            .ok_or(Error::Other(format!(
+                "{} is not negotiated in the session",
+                MIME_TYPE_H264
+            )))?;
+
+        Ok(Self {
+            endpoint_id,
+            audio: RtmpTrack::new(
+                AUDIO_MID.to_string(),
+                RTPCodecType::Audio,
+                find_codec(session_config, RTPCodecType::Audio, MIME_TYPE_OPUS)?,
+                Box::new(OpusPayloader),
+            ),
+            video: RtmpTrack::new(
+                VIDEO_MID.to_string(),
+                RTPCodecType::Video,
+                video_codec,
+                Box::<H264Payloader>::default(),
+            ),
+            packets: vec![],
+        })
+    }
+
+    pub(crate) fn endpoint_id(&self) -> EndpointId {
+        self.endpoint_id
+    }
+
+    /// transceivers returns the transceivers the virtual endpoint publishes its tracks on, as
+    /// received by the server
+    pub(crate) fn transceivers(&self) -> Vec<RTCRtpTransceiver> {
+        [&self.audio, &self.video]
+            .into_iter()
+            .map(|track| RTCRtpTransceiver {
+                mid: track.mid.clone(),
+                sender: Some(RTCRtpSender {
+                    cname: format!("{}", self.endpoint_id),
+                    msid: MediaStreamId {
+                        stream_id: format!("{}", self.endpoint_id),
+                        track_id: format!("{}-{}", self.endpoint_id, track.mid),
+                    },
+                    ssrcs: vec![track.ssrc],
+                    ssrc_groups: vec![],
+                }),
+                direction: RTCRtpTransceiverDirection::Recvonly,
+                current_direction: RTCRtpTransceiverDirection::Recvonly,
+                rtp_params: RTCRtpParameters {
+                    header_extensions: vec![],
+                    codecs: vec![track.codec.clone()],
+                },
+                kind: track.kind,
+                rids: vec![],
+            })
+            .collect()
+    }
+
+    /// push packetizes a frame, its packets being returned by poll_packets
+    pub(crate) fn push(&mut self, media: RtmpMedia) {
+        let (track, timestamp, data) = match media {
+            RtmpMedia::Video {
+                timestamp, data, ..
+            } => (&mut self.video, timestamp, data),
+            RtmpMedia::Audio { timestamp, data } => (&mut self.audio, timestamp, data),
+        };
+        match track.packetize(timestamp, &data) {
+            Ok(packets) => self.packets.extend(
+                packets
+                    .into_iter()
+                    .map(|packet| (track.mid.clone(), track.kind, packet)),
+            ),
+            Err(err) => warn!(
+                "can't packetize mid {} of endpoint {}: {}",
+                track.mid, self.endpoint_id, err
+            ),
+        }
+    }
+
+    /// poll_packets returns the packets of the frames pushed, with the mid and kind of their
+    /// track
+    pub(crate) fn poll_packets(&mut self) -> Vec<(Mid, RTPCodecType, rtp::packet::Packet)> {
+        std::mem::take(&mut self.packets)
+    }
+}
+
+/// RtmpTrack sends the frames of an RTMP track on an SSRC of its own, with a random initial
+/// sequence number and timestamp
+struct RtmpTrack {
+    mid: Mid,
+    kind: RTPCodecType,
+    codec: RTCRtpCodecParameters,
+    ssrc: SSRC,
+    payloader: Box<dyn Payloader>,
+    sequence_number: u16,
+    initial_timestamp: u32,
+}
+
+impl RtmpTrack {
+    fn new(
+        mid: Mid,
+        kind: RTPCodecType,
+        codec: RTCRtpCodecParameters,
+        payloader: Box<dyn Payloader>,
+    ) -> Self {
+        Self {
+            mid,
+            kind,
+            codec,
+            ssrc: rand::random::<u32>(),
+            payloader,
+            sequence_number: rand::random::<u16>(),
+            initial_timestamp: rand::random::<u32>(),
+        }
+    }
+
+    /// packetize splits a frame into packets, the marker bit being set on the last packet of
+    /// video frames
+    fn packetize(&mut self, timestamp: u32, data: &Bytes) -> Result<Vec<rtp::packet::Packet>> {
+        let clock_rate_khz = self.codec.capability.clock_rate / 1000;
+        let timestamp = self
+            .initial_timestamp
+            .wrapping_add(timestamp.wrapping_mul(clock_rate_khz));
+        let payloads = self.payloader.payload(RTP_PAYLOAD_MTU, data)?;
+        let payload_count = payloads.len();
+        let mut packets = Vec::with_capacity(payload_count);
+        for (i, payload) in payloads.into_iter().enumerate() {
+            packets.push(rtp::packet::Packet {
+                header: rtp::header::Header {
+                    version: 2,
+                    marker: self.kind == RTPCodecType::Video && i + 1 == payload_count,
+                    payload_type: self.codec.payload_type,
+                    sequence_number: self.sequence_number,
+                    timestamp,
+                    ssrc: self.ssrc,
+                    ..Default::default()
+                },
+                payload,
+            });
+            self.sequence_number = self.sequence_number.wrapping_add(1);
+        }
+        Ok(packets)
+    }
+}
@@ -0,0 +1,184 @@
+use crate::cascade::WorkerPlacement;
+use crate::chat::ChatLimits;
+use crate::description::codec_policy::CodecPolicy;
+use crate::description::config::MediaConfig;
+use crate::server::certificate::RTCCertificate;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// ServerConfig provides customized parameters for SFU server
+pub struct ServerConfig {
+    pub(crate) certificates: Vec<RTCCertificate>,
+    pub(crate) dtls_handshake_config: Arc<dtls::config::HandshakeConfig>,
+    pub(crate) sctp_endpoint_config: Arc<sctp::EndpointConfig>,
+    pub(crate) sctp_server_config: Arc<sctp::ServerConfig>,
+    pub(crate) media_config: MediaConfig,
+    pub(crate) codec_policy: CodecPolicy,
+    pub(crate) idle_timeout: Duration,
+    pub(crate) keyframe_request_interval: Duration,
+    pub(crate) last_n: Option<usize>,
+    pub(crate) recording_dir: Option<PathBuf>,
+    pub(crate) capture_dir: Option<PathBuf>,
+    pub(crate) capture_max_bytes: u64,
+    pub(crate) capture_max_duration: Duration,
+    pub(crate) media_dir: Option<PathBuf>,
+    pub(crate) egress_dir: Option<PathBuf>,
+    pub(crate) egress_allowed_hosts: Vec<IpAddr>,
+    pub(crate) hls_dir: Option<PathBuf>,
+    pub(crate) worker_placement: WorkerPlacement,
+    pub(crate) chat_limits: ChatLimits,
+}
+
+impl ServerConfig {
+    /// create new server config
+    pub fn new(certificates: Vec<RTCCertificate>) -> Self {
+        Self {
+            certificates,
+            media_config: MediaConfig::default(),
+            codec_policy: CodecPolicy::default(),
+            sctp_endpoint_config: Arc::new(sctp::EndpointConfig::default()),
+            sctp_server_config: Arc::new(sctp::ServerConfig::default()),
+            dtls_handshake_config: Arc::new(dtls::config::HandshakeConfig::default()),
+            idle_timeout: Duration::from_secs(30),
+            keyframe_request_interval: Duration::from_millis(500),
+            last_n: None,
+            recording_dir: None,
+            capture_dir: None,
+            capture_max_bytes: 100 * 1024 * 1024,
+            capture_max_duration: Duration::from_secs(600),
+            media_dir: None,
+            egress_dir: None,
+            egress_allowed_hosts: vec![],
+            hls_dir: None,
+            worker_placement: WorkerPlacement::default(),
+            chat_limits: ChatLimits::default(),
+        }
+    }
+
+    /// build with provided MediaConfig
+    pub fn with_media_config(mut self, media_config: MediaConfig) -> Self {
+        self.media_config = media_config;
+        self
+    }
+
+    /// build with the codec policy of the sessions, unless one is set for the session
+    pub fn with_codec_policy(mut self, codec_policy: CodecPolicy) -> Self {
+        self.codec_policy = codec_policy;
+        self
+    }
+
+    /// build with provided sctp::ServerConfig
+    pub fn with_sctp_server_config(mut self, sctp_server_config: Arc<sctp::ServerConfig>) -> Self {
+        self.sctp_server_config = sctp_server_config;
+        self
+    }
+
+    /// build with provided sctp::EndpointConfig
+    pub fn with_sctp_endpoint_config(
+        mut self,
+        sctp_endpoint_config: Arc<sctp::EndpointConfig>,
+    ) -> Self {
+        self.sctp_endpoint_config = sctp_endpoint_config;
+        self
+    }
+
+    /// build with provided dtls::config::HandshakeConfig
+    pub fn with_dtls_handshake_config(
+        mut self,
+        dtls_handshake_config: Arc<dtls::config::HandshakeConfig>,
+    ) -> Self {
+        self.dtls_handshake_config = dtls_handshake_config;
+        self
+    }
+
+    /// build with idle timeout
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// build with the minimum interval between two keyframe requests sent for a publisher's
+    /// stream, the requests of its subscribers being aggregated meanwhile
+    pub fn with_keyframe_request_interval(mut self, keyframe_request_interval: Duration) -> Self {
+        self.keyframe_request_interval = keyframe_request_interval;
+        self
+    }
+
+    /// build with the number of most recent dominant speakers whose video is forwarded to each
+    /// endpoint of a session, next to the endpoints it pins, video being unlimited by default
+    pub fn with_last_n(mut self, last_n: usize) -> Self {
+        self.last_n = Some(last_n);
+        self
+    }
+
+    /// build with the directory sessions are recorded into, recording being disabled without it
+    pub fn with_recording_dir(mut self, recording_dir: PathBuf) -> Self {
+        self.recording_dir = Some(recording_dir);
+        self
+    }
+
+    /// build with the directory packet captures are written into, capturing being disabled
+    /// without it
+    pub fn with_capture_dir(mut self, capture_dir: PathBuf) -> Self {
+        self.capture_dir = Some(capture_dir);
+        self
+    }
+
+    /// build with the size in bytes and the duration a packet capture stops at, a capture
+    /// request asking for lower limits only
+    pub fn with_capture_limits(mut self, max_bytes: u64, max_duration: Duration) -> Self {
+        self.capture_max_bytes = max_bytes;
+        self.capture_max_duration = max_duration;
+        self
+    }
+
+    /// build with the directory media files are injected into sessions from, injecting being
+    /// disabled without it
+    pub fn with_media_dir(mut self, media_dir: PathBuf) -> Self {
+        self.media_dir = Some(media_dir);
+        self
+    }
+
+    /// build with the directory the SDP files of plain RTP egresses are written into, egress
+    /// being disabled without it
+    pub fn with_egress_dir(mut self, egress_dir: PathBuf) -> Self {
+        self.egress_dir = Some(egress_dir);
+        self
+    }
+
+    /// build with the hosts plain RTP egresses may forward to, any other host being refused so
+    /// that the REST API can't be used to send media to arbitrary addresses
+    pub fn with_egress_allowed_hosts(mut self, egress_allowed_hosts: Vec<IpAddr>) -> Self {
+        self.egress_allowed_hosts = egress_allowed_hosts;
+        self
+    }
+
+    /// build with the directory the HLS playlists and segments of sessions are written into,
+    /// for the web server to serve them, HLS being disabled without it
+    pub fn with_hls_dir(mut self, hls_dir: PathBuf) -> Self {
+        self.hls_dir = Some(hls_dir);
+        self
+    }
+
+    /// build with the placement of the endpoints of sessions on the workers, a session spanning
+    /// several of them relaying its tracks between them, each session being hosted by a single
+    /// worker by default
+    pub fn with_worker_placement(mut self, worker_placement: WorkerPlacement) -> Self {
+        self.worker_placement = worker_placement;
+        self
+    }
+
+    /// build with the size in bytes of the largest chat message an endpoint may send over its
+    /// data channel, and the rate in messages per second it may send them at, bursts of up to
+    /// burst messages being let through. A rate of 0 leaves the messages unlimited
+    pub fn with_chat_limits(mut self, max_message_size: usize, rate: u32, burst: u32) -> Self {
+        self.chat_limits = ChatLimits {
+            max_message_size,
+            rate,
+            burst,
+        };
+        self
+    }
+}
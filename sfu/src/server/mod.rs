@@ -0,0 +1,3 @@
+pub(crate) mod certificate;
+pub(crate) mod config;
+pub(crate) mod states;
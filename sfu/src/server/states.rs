@@ -0,0 +1,1469 @@
+use crate::capture::{CaptureRequest, CaptureSummary, PacketCapture};
+use crate::cascade::{RelayEvent, RelayMessage, RelayPeer, SessionCascade};
+use crate::description::{codec_policy::CodecPolicy, RTCSessionDescription};
+use crate::egress::{EgressRequest, EgressSummary, PlainRtpEgress};
+use crate::endpoint::{
+    candidate::{Candidate, ConnectionCredentials},
+    transport::Transport,
+    Endpoint,
+};
+use crate::hls::{HlsPackager, HlsRequest, HlsSummary};
+use crate::ingest::{PlainIngest, PlainIngestDescription, PlainIngestRequest};
+use crate::injector::{InjectRequest, MediaInjector};
+use crate::messages::{ServerEvent, SessionEvent};
+use crate::recording::{RecordingManifest, SessionRecorder};
+use crate::rtmp::{RtmpIngest, RtmpMedia};
+use crate::server::config::ServerConfig;
+use crate::session::{config::SessionConfig, subscription::SubscriptionRequest, Session};
+use crate::simulcast::LayerRequest;
+use crate::speaker::PinRequest;
+use crate::stats::EndpointStats;
+use crate::types::{EndpointId, FourTuple, SessionId, UserName};
+use log::{debug, error, info, warn};
+use retty::transport::TransportContext;
+use shared::error::{Error, Result};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// ServerStates maintains SFU internal states, such sessions, endpoints, etc.
+pub struct ServerStates {
+    server_config: Arc<ServerConfig>,
+    local_addr: SocketAddr,
+    candidate_addrs: Vec<SocketAddr>,
+    tcp_candidate_addrs: Vec<SocketAddr>,
+    ice_ufrag_prefix: String,
+    sessions: HashMap<SessionId, Session>,
+    /// messages to the other workers hosting the sessions removed
+    relay_messages: Vec<(RelayPeer, RelayMessage)>,
+    /// lifecycle events of the sessions created and closed by the worker
+    events: Vec<ServerEvent>,
+    /// codec policies set for sessions yet to start, applied when they do
+    codec_policies: HashMap<SessionId, CodecPolicy>,
+
+    //TODO: add idle timeout cleanup logic to remove idle endpoint and candidates
+    candidates: HashMap<UserName, Rc<Candidate>>,
+    endpoints: HashMap<FourTuple, (SessionId, EndpointId)>,
+}
+
+impl ServerStates {
+    /// create new server states
+    pub fn new(server_config: Arc<ServerConfig>, local_addr: SocketAddr) -> Result<Self> {
+        let _ = server_config
+            .certificates
+            .first()
+            .ok_or(Error::ErrInvalidCertificate)?
+            .get_fingerprints()
+            .first()
+            .ok_or(Error::ErrInvalidCertificate)?;
+
+        Ok(Self {
+            server_config,
+            local_addr,
+            candidate_addrs: vec![local_addr],
+            tcp_candidate_addrs: vec![],
+            ice_ufrag_prefix: String::new(),
+            sessions: HashMap::new(),
+            relay_messages: vec![],
+            events: vec![],
+            codec_policies: HashMap::new(),
+
+            candidates: HashMap::new(),
+            endpoints: HashMap::new(),
+        })
+    }
+
+    /// build with the addresses announced as ICE candidates in every answer,
+    /// in order of preference. Defaults to the local address only.
+    pub fn with_candidate_addrs(mut self, candidate_addrs: Vec<SocketAddr>) -> Self {
+        if !candidate_addrs.is_empty() {
+            self.candidate_addrs = candidate_addrs;
+        }
+        self
+    }
+
+    /// build with the addresses announced as passive ICE-TCP candidates (RFC 6544)
+    pub fn with_tcp_candidate_addrs(mut self, tcp_candidate_addrs: Vec<SocketAddr>) -> Self {
+        self.tcp_candidate_addrs = tcp_candidate_addrs;
+        self
+    }
+
+    /// build with a prefix put in front of every local ICE username fragment, so that
+    /// connectivity checks arriving on a shared port can be routed to this ServerStates
+    pub fn with_ice_ufrag_prefix(mut self, ice_ufrag_prefix: String) -> Self {
+        self.ice_ufrag_prefix = ice_ufrag_prefix;
+        self
+    }
+
+    /// accept offer and return answer, the endpoint joining with the offer receiving every track
+    /// published in the session when auto_subscribe is set
+    pub fn accept_offer(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        four_tuple: Option<FourTuple>,
+        mut offer: RTCSessionDescription,
+        auto_subscribe: bool,
+    ) -> Result<RTCSessionDescription> {
+        let parsed = offer.unmarshal()?;
+        let remote_conn_cred = ConnectionCredentials::from_sdp(&parsed)?;
+        offer.parsed = Some(parsed);
+
+        let fingerprints = self
+            .server_config
+            .certificates
+            .first()
+            .unwrap()
+            .get_fingerprints();
+        let ice_ufrag_prefix = self.ice_ufrag_prefix.clone();
+
+        let session = self.create_or_get_mut_session(session_id);
+        let has_endpoint = session.has_endpoint(&endpoint_id);
+
+        let local_conn_cred = if has_endpoint {
+            // a connected endpoint renegotiates over its data channel
+            let four_tuple = four_tuple.ok_or(Error::Other(format!(
+                "endpoint id {} is already connected, renegotiate over its data channel",
+                endpoint_id
+            )))?;
+            session.rollback_offer(endpoint_id);
+            session.set_remote_description(endpoint_id, &offer)?;
+
+            let endpoint = session
+                .get_endpoint(&endpoint_id)
+                .ok_or(Error::Other(format!(
+                    "can't find endpoint id {}",
+                    endpoint_id
+                )))?;
+            let transports = endpoint.get_transports();
+            let transport = transports.get(&four_tuple).ok_or(Error::Other(format!(
+                "can't find transport for endpoint id {} with {:?}",
+                endpoint_id, four_tuple
+            )))?;
+            transport
+                .candidate()
+                .ok_or(Error::Other(format!(
+                    "plain transport of endpoint id {} can't be negotiated",
+                    endpoint_id
+                )))?
+                .local_connection_credentials()
+                .clone()
+        } else {
+            ConnectionCredentials::new(
+                fingerprints,
+                remote_conn_cred.dtls_params.role,
+                &ice_ufrag_prefix,
+            )
+        };
+
+        let answer = session.create_answer(endpoint_id, &offer, &local_conn_cred.ice_params)?;
+        if has_endpoint {
+            session.set_local_description(endpoint_id, &answer)?;
+        } else {
+            let mut candidate = Candidate::new(
+                session_id,
+                endpoint_id,
+                remote_conn_cred,
+                local_conn_cred,
+                offer,
+                answer.clone(),
+                Instant::now() + self.server_config.idle_timeout,
+            );
+            candidate.set_auto_subscribed(auto_subscribe);
+            self.add_candidate(Rc::new(candidate));
+        }
+
+        Ok(answer)
+    }
+
+    pub(crate) fn accept_answer(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        _four_tuple: FourTuple,
+        mut answer: RTCSessionDescription,
+    ) -> Result<()> {
+        let parsed = answer.unmarshal()?;
+        answer.parsed = Some(parsed);
+
+        let session = self.create_or_get_mut_session(session_id);
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+        // the answer to an offer given up on glare
+        if !endpoint.is_offer_pending() {
+            return Err(Error::Other(format!(
+                "endpoint id {} answered without any offer pending",
+                endpoint_id
+            )));
+        }
+        endpoint.set_offer_pending(false);
+        session.set_remote_description(endpoint_id, &answer)?;
+
+        Ok(())
+    }
+
+    /// set the codec policy of a session before its endpoints join, instead of the server one
+    pub fn set_codec_policy(
+        &mut self,
+        session_id: SessionId,
+        codec_policy: CodecPolicy,
+    ) -> Result<()> {
+        debug!("{} uses codec policy {:?}", session_id, codec_policy);
+        match self.get_mut_session(&session_id) {
+            Some(session) => session.set_codec_policy(codec_policy),
+            None => {
+                // the session isn't started for a policy alone, it would never be closed
+                self.codec_policies.insert(session_id, codec_policy);
+                Ok(())
+            }
+        }
+    }
+
+    /// start recording the tracks published in a session, into a directory of its own in the
+    /// recording directory
+    pub fn start_recording(&mut self, session_id: SessionId) -> Result<()> {
+        let recording_dir = self
+            .server_config
+            .recording_dir
+            .clone()
+            .ok_or(Error::Other(
+                "recording directory is not configured".to_string(),
+            ))?;
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        if session.is_recording() {
+            return Err(Error::Other(format!(
+                "session id {} is already recording",
+                session_id
+            )));
+        }
+
+        let recorder = SessionRecorder::new(session_id, &recording_dir, Instant::now())?;
+        info!(
+            "{} starts recording in {:?}",
+            session_id,
+            recorder.get_directory()
+        );
+        session.start_recording(recorder);
+
+        Ok(())
+    }
+
+    /// stop recording a session, and return the manifest written next to its files
+    pub fn stop_recording(&mut self, session_id: SessionId) -> Result<RecordingManifest> {
+        let recorder = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?
+            .take_recorder()
+            .ok_or(Error::Other(format!(
+                "session id {} is not recording",
+                session_id
+            )))?;
+
+        info!("{} stops recording", session_id);
+        recorder.stop()
+    }
+
+    /// start capturing the decrypted RTP and RTCP of a session, or of one of its endpoints, into
+    /// a pcapng file in the capture directory
+    pub fn start_capture(&mut self, session_id: SessionId, request: CaptureRequest) -> Result<()> {
+        let capture_dir = self.server_config.capture_dir.clone().ok_or(Error::Other(
+            "capture directory is not configured".to_string(),
+        ))?;
+        let max_bytes = self.server_config.capture_max_bytes;
+        let max_duration = self.server_config.capture_max_duration;
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        if session.is_capturing() {
+            return Err(Error::Other(format!(
+                "session id {} is already capturing",
+                session_id
+            )));
+        }
+        if let Some(endpoint_id) = request.endpoint_id {
+            if !session.has_endpoint(&endpoint_id) {
+                return Err(Error::Other(format!(
+                    "can't find endpoint id {}",
+                    endpoint_id
+                )));
+            }
+        }
+
+        let capture = PacketCapture::new(
+            session_id,
+            &capture_dir,
+            request,
+            max_bytes,
+            max_duration,
+            Instant::now(),
+        )?;
+        info!("{} starts capture in {:?}", session_id, capture.get_file());
+        session.start_capture(capture);
+
+        Ok(())
+    }
+
+    /// stop capturing a session, and return the summary of its capture
+    pub fn stop_capture(&mut self, session_id: SessionId) -> Result<CaptureSummary> {
+        let capture = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?
+            .take_capture()
+            .ok_or(Error::Other(format!(
+                "session id {} is not capturing",
+                session_id
+            )))?;
+
+        info!("{} stops capture", session_id);
+        Ok(capture.stop())
+    }
+
+    /// start forwarding the decrypted media of a session, or of one of its endpoints, as plain
+    /// RTP, described by an SDP file in the egress directory
+    pub fn start_egress(
+        &mut self,
+        session_id: SessionId,
+        request: EgressRequest,
+    ) -> Result<EgressSummary> {
+        let egress_dir = self.server_config.egress_dir.clone().ok_or(Error::Other(
+            "egress directory is not configured".to_string(),
+        ))?;
+        let host = request.host.to_canonical();
+        if !self
+            .server_config
+            .egress_allowed_hosts
+            .iter()
+            .any(|allowed_host| allowed_host.to_canonical() == host)
+        {
+            return Err(Error::Other(format!(
+                "egress host {} is not allowed",
+                request.host
+            )));
+        }
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        if session.is_egressing() {
+            return Err(Error::Other(format!(
+                "session id {} already has an egress",
+                session_id
+            )));
+        }
+        if let Some(endpoint_id) = request.endpoint_id {
+            if !session.has_endpoint(&endpoint_id) {
+                return Err(Error::Other(format!(
+                    "can't find endpoint id {}",
+                    endpoint_id
+                )));
+            }
+        }
+
+        let egress = PlainRtpEgress::new(session_id, &egress_dir, request, Instant::now())?;
+        info!(
+            "{} starts egress described by {:?}",
+            session_id,
+            egress.get_sdp_file()
+        );
+        session.start_egress(egress)?;
+
+        session
+            .get_egress()
+            .map(|egress| egress.summary())
+            .ok_or(Error::Other(format!(
+                "session id {} has no egress",
+                session_id
+            )))
+    }
+
+    /// stop forwarding a session as plain RTP, and return the summary of its egress
+    pub fn stop_egress(&mut self, session_id: SessionId) -> Result<EgressSummary> {
+        let egress = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?
+            .take_egress()
+            .ok_or(Error::Other(format!(
+                "session id {} has no egress",
+                session_id
+            )))?;
+
+        info!("{} stops egress", session_id);
+        Ok(egress.stop())
+    }
+
+    /// start streaming a session over HLS, from the tracks of one of its endpoints or of its
+    /// dominant speaker, into the HLS directory
+    pub fn start_hls(&mut self, session_id: SessionId, request: HlsRequest) -> Result<HlsSummary> {
+        let hls_dir = self
+            .server_config
+            .hls_dir
+            .clone()
+            .ok_or(Error::Other("hls directory is not configured".to_string()))?;
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        if session.is_streaming_hls() {
+            return Err(Error::Other(format!(
+                "session id {} is already streamed over hls",
+                session_id
+            )));
+        }
+        if let Some(endpoint_id) = request.endpoint_id {
+            if !session.has_endpoint(&endpoint_id) {
+                return Err(Error::Other(format!(
+                    "can't find endpoint id {}",
+                    endpoint_id
+                )));
+            }
+        }
+
+        let hls = HlsPackager::new(session_id, &hls_dir, request, Instant::now())?;
+        info!(
+            "{} starts hls served at {}",
+            session_id,
+            hls.get_playlist_path()
+        );
+        let summary = hls.summary();
+        session.start_hls(hls);
+        Ok(summary)
+    }
+
+    /// stop streaming a session over HLS, and return the summary of its stream
+    pub fn stop_hls(&mut self, session_id: SessionId) -> Result<HlsSummary> {
+        let hls = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?
+            .take_hls()
+            .ok_or(Error::Other(format!(
+                "session id {} is not streamed over hls",
+                session_id
+            )))?;
+
+        info!("{} stops hls", session_id);
+        Ok(hls.stop())
+    }
+
+    /// capture_packet hands a decrypted RTP or RTCP packet to the capture of the session of the
+    /// transport, if any
+    pub(crate) fn capture_packet(
+        &mut self,
+        now: Instant,
+        transport_context: &TransportContext,
+        is_outbound: bool,
+        payload: &[u8],
+    ) {
+        let Some((session_id, endpoint_id)) = self.find_endpoint(&transport_context.into()) else {
+            return;
+        };
+        if let Some(capture) = self
+            .get_mut_session(&session_id)
+            .and_then(|session| session.get_mut_capture())
+        {
+            capture.on_packet(
+                now,
+                endpoint_id,
+                transport_context.local_addr,
+                transport_context.peer_addr,
+                is_outbound,
+                payload,
+            );
+        }
+    }
+
+    /// start injecting local media files into a session, as a virtual endpoint publishing their
+    /// tracks
+    pub fn start_injection(&mut self, session_id: SessionId, request: InjectRequest) -> Result<()> {
+        let media_dir = self.server_config.media_dir.clone().ok_or(Error::Other(
+            "media directory is not configured".to_string(),
+        ))?;
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        if session.has_endpoint(&request.endpoint_id) {
+            return Err(Error::Other(format!(
+                "endpoint id {} already exists in session id {}",
+                request.endpoint_id, session_id
+            )));
+        }
+
+        let endpoint_id = request.endpoint_id;
+        let injector = MediaInjector::new(
+            request,
+            &media_dir,
+            session.session_config(),
+            Instant::now(),
+        )?;
+        info!(
+            "{} starts injecting media as endpoint {}",
+            session_id, endpoint_id
+        );
+        session.start_injection(injector);
+
+        Ok(())
+    }
+
+    /// stop injecting media into a session, removing its virtual endpoint
+    pub fn stop_injection(&mut self, session_id: SessionId, endpoint_id: EndpointId) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        info!(
+            "{} stops injecting media as endpoint {}",
+            session_id, endpoint_id
+        );
+        session.stop_injection(&endpoint_id)
+    }
+
+    /// start publishing the tracks of a plain RTP source into a session, and return where and
+    /// how the source has to send them
+    pub fn start_plain_ingest(
+        &mut self,
+        session_id: SessionId,
+        request: PlainIngestRequest,
+    ) -> Result<PlainIngestDescription> {
+        let local_addr = self
+            .candidate_addrs
+            .first()
+            .copied()
+            .unwrap_or(self.local_addr);
+        let session = self.create_or_get_mut_session(session_id);
+        if session.has_endpoint(&request.endpoint_id) {
+            return Err(Error::Other(format!(
+                "endpoint id {} already exists in session id {}",
+                request.endpoint_id, session_id
+            )));
+        }
+
+        let endpoint_id = request.endpoint_id;
+        let plain_ingest = PlainIngest::new(request, session.session_config())?;
+        let description = plain_ingest.description(session_id, local_addr);
+        let remote_addr = plain_ingest.remote_addr();
+        info!(
+            "{} starts plain ingest as endpoint {}",
+            session_id, endpoint_id
+        );
+        session.start_plain_ingest(plain_ingest);
+        if let Some(peer_addr) = remote_addr {
+            let four_tuple = FourTuple {
+                local_addr: self.local_addr,
+                peer_addr,
+            };
+            self.add_plain_transport(session_id, endpoint_id, four_tuple)?;
+        }
+
+        Ok(description)
+    }
+
+    /// stop publishing the tracks of a plain RTP source, removing its endpoint
+    pub fn stop_plain_ingest(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        info!(
+            "{} stops plain ingest as endpoint {}",
+            session_id, endpoint_id
+        );
+        for four_tuple in session.stop_plain_ingest(&endpoint_id)? {
+            self.remove_endpoint(&four_tuple);
+        }
+
+        Ok(())
+    }
+
+    /// start publishing the media of an RTMP publisher into a session, as a virtual endpoint
+    pub fn start_rtmp_ingest(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    ) -> Result<()> {
+        let session = self.create_or_get_mut_session(session_id);
+        if session.has_endpoint(&endpoint_id) {
+            return Err(Error::Other(format!(
+                "endpoint id {} already exists in session id {}",
+                endpoint_id, session_id
+            )));
+        }
+
+        let rtmp_ingest = RtmpIngest::new(endpoint_id, session.session_config())?;
+        info!(
+            "{} starts rtmp ingest as endpoint {}",
+            session_id, endpoint_id
+        );
+        session.start_rtmp_ingest(rtmp_ingest);
+
+        Ok(())
+    }
+
+    /// stop publishing the media of an RTMP publisher, removing its virtual endpoint
+    pub fn stop_rtmp_ingest(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        info!(
+            "{} stops rtmp ingest as endpoint {}",
+            session_id, endpoint_id
+        );
+        session.stop_rtmp_ingest(&endpoint_id)
+    }
+
+    /// packetize a frame of an RTMP publisher, its packets being forwarded at the next timeout
+    pub fn push_rtmp_media(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        media: RtmpMedia,
+    ) -> Result<()> {
+        self.get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?
+            .push_rtmp_media(&endpoint_id, media)
+    }
+
+    /// apply a message of another worker or node hosting endpoints of the same session. The home
+    /// worker of a session hosts it as soon as another worker does, for its recording, egress and
+    /// HLS to get every track
+    pub fn handle_relay_message(&mut self, message: RelayMessage) -> Result<()> {
+        let RelayMessage {
+            session_id,
+            origin,
+            event,
+        } = message;
+        if !self.sessions.contains_key(&session_id) {
+            let is_home = self.server_config.worker_placement.session_port(session_id)
+                == Some(self.local_addr.port());
+            if !is_home || !matches!(event, RelayEvent::Join) {
+                // the session was removed meanwhile, or this worker doesn't host it yet
+                return Ok(());
+            }
+        }
+
+        let is_leave = matches!(event, RelayEvent::Leave);
+        let session = self.create_or_get_mut_session(session_id);
+        session.handle_relay_event(origin, event)?;
+        if is_leave && session.is_idle() && session.get_endpoints().is_empty() {
+            self.close_session(session_id);
+        }
+
+        Ok(())
+    }
+
+    /// relay a session with another node over the link, the session being hosted by this worker
+    /// at least as long as the link is up
+    pub fn add_node_link(&mut self, session_id: SessionId, link_id: u64) {
+        let local_port = self.local_addr.port();
+        let session = self.create_or_get_mut_session(session_id);
+        if session.get_mut_cascade().is_none() {
+            session.start_cascade(SessionCascade::new(
+                session_id,
+                local_port,
+                vec![local_port],
+            ));
+        }
+        info!(
+            "{} is relayed with another node over link {}",
+            session_id, link_id
+        );
+        session.add_node_link(link_id);
+    }
+
+    /// stop relaying the sessions over a link which went down
+    pub fn remove_node_link(&mut self, link_id: u64) {
+        let mut session_ids: Vec<SessionId> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| {
+                session
+                    .get_cascade()
+                    .is_some_and(|cascade| cascade.has_link(link_id))
+            })
+            .map(|(&session_id, _)| session_id)
+            .collect();
+        session_ids.sort_unstable();
+        for session_id in session_ids {
+            if let Err(err) = self.handle_relay_message(RelayMessage {
+                session_id,
+                origin: RelayPeer::Node(link_id),
+                event: RelayEvent::Leave,
+            }) {
+                warn!("{} can't leave link {}: {}", session_id, link_id, err);
+            }
+        }
+    }
+
+    /// stop relaying the session with other nodes, and return the links which were left
+    pub fn stop_node_links(&mut self, session_id: SessionId) -> Result<Vec<u64>> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        let links = session.leave_node_links();
+        if session.is_idle() && session.get_endpoints().is_empty() {
+            self.close_session(session_id);
+        }
+        info!("{} stops relaying over links {:?}", session_id, links);
+        Ok(links)
+    }
+
+    /// poll the messages to send to the other workers and nodes hosting endpoints of the
+    /// sessions, with the peer to send them to
+    pub fn poll_relay_messages(&mut self) -> Vec<(RelayPeer, RelayMessage)> {
+        let mut relay_messages = std::mem::take(&mut self.relay_messages);
+        for session in self.sessions.values_mut() {
+            if let Some(cascade) = session.get_mut_cascade() {
+                relay_messages.extend(cascade.take_messages());
+            }
+        }
+        relay_messages
+    }
+
+    /// poll the events of the sessions, endpoints and tracks hosted by the worker,
+    /// in the order they happened within a session
+    pub fn poll_events(&mut self) -> Vec<ServerEvent> {
+        // sessions started and closed since the last poll come first, the events of the closed
+        // ones being drained when closing them
+        let mut events = std::mem::take(&mut self.events);
+        for session in self.sessions.values_mut() {
+            events.extend(session.take_events());
+        }
+        events
+    }
+
+    /// request a simulcast layer for a track forwarded to the endpoint
+    pub fn request_layer(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        request: LayerRequest,
+    ) -> Result<()> {
+        let endpoint = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+
+        let is_simulcast = endpoint
+            .get_transceivers()
+            .get(&request.track)
+            .is_some_and(|transceiver| transceiver.is_simulcast());
+        if !is_simulcast {
+            return Err(Error::Other(format!(
+                "{} is not a simulcast track of endpoint id {}",
+                request.track, endpoint_id
+            )));
+        }
+
+        debug!(
+            "{}/{} requests layer {:?} of {}",
+            session_id, endpoint_id, request.preference, request.track
+        );
+        endpoint
+            .get_mut_simulcast_forwarders()
+            .entry(request.track)
+            .or_default()
+            .set_preference(request.preference);
+
+        Ok(())
+    }
+
+    /// pin the endpoints whose video is always forwarded to the endpoint, replacing the ones
+    /// pinned before
+    pub fn pin_endpoints(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        request: PinRequest,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        debug!(
+            "{}/{} pins endpoints {:?}",
+            session_id, endpoint_id, request.endpoint_ids
+        );
+        session.set_pinned_endpoints(&endpoint_id, request.endpoint_ids.into_iter().collect())
+    }
+
+    /// subscribe the endpoint to a track of another endpoint, which is offered the change
+    /// from the timeout loop
+    pub fn subscribe(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        request: SubscriptionRequest,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        debug!(
+            "{}/{} subscribes to {} of {}",
+            session_id, endpoint_id, request.mid, request.endpoint_id
+        );
+        session.subscribe(endpoint_id, request.endpoint_id, &request.mid)
+    }
+
+    /// unsubscribe the endpoint from a track of another endpoint, which is offered the change
+    /// from the timeout loop
+    pub fn unsubscribe(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        request: SubscriptionRequest,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        debug!(
+            "{}/{} unsubscribes from {} of {}",
+            session_id, endpoint_id, request.mid, request.endpoint_id
+        );
+        session.unsubscribe(endpoint_id, request.endpoint_id, &request.mid)
+    }
+
+    /// get the stats of the media sent to the endpoint
+    pub fn get_endpoint_stats(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    ) -> Result<EndpointStats> {
+        let endpoint = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+
+        let bandwidth_estimate = endpoint.bandwidth_estimate();
+        let mut stats = EndpointStats {
+            session_id,
+            endpoint_id,
+            estimated_bitrate: bandwidth_estimate.map(|estimate| estimate.bitrate),
+            bandwidth_estimation: bandwidth_estimate.map(|estimate| estimate.source),
+            video_paused: endpoint.is_video_paused(),
+            forwarded_layers: endpoint
+                .get_simulcast_forwarders()
+                .iter()
+                .filter_map(|(mid, forwarder)| {
+                    Some((mid.clone(), forwarder.current_rid()?.to_string()))
+                })
+                .collect(),
+            ..Default::default()
+        };
+        endpoint.get_mut_interceptor().collect_stats(&mut stats);
+
+        Ok(stats)
+    }
+
+    /// get the last active speaker change of the session, as seen by one of its endpoints
+    pub fn get_active_speaker(
+        &self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    ) -> Result<Option<SessionEvent>> {
+        let session = self.get_session(&session_id).ok_or(Error::Other(format!(
+            "can't find session id {}",
+            session_id
+        )))?;
+        if !session.has_endpoint(&endpoint_id) {
+            return Err(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )));
+        }
+
+        Ok(session
+            .get_dominant_speaker()
+            .dominant()
+            .map(|endpoint_id| SessionEvent::ActiveSpeakerChanged { endpoint_id }))
+    }
+
+    /// get the ids of the sessions hosted by the worker
+    pub fn get_session_ids(&self) -> Vec<SessionId> {
+        self.sessions.keys().copied().collect()
+    }
+
+    pub(crate) fn server_config(&self) -> &Arc<ServerConfig> {
+        &self.server_config
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub(crate) fn create_or_get_mut_session(&mut self, session_id: SessionId) -> &mut Session {
+        if let Entry::Vacant(e) = self.sessions.entry(session_id) {
+            let mut session_config = SessionConfig::new(
+                Arc::clone(&self.server_config),
+                self.local_addr,
+                self.candidate_addrs.clone(),
+                self.tcp_candidate_addrs.clone(),
+            );
+            if let Some(codec_policy) = self.codec_policies.remove(&session_id) {
+                session_config.codec_policy = codec_policy;
+            }
+            let mut session = Session::new(session_config, session_id);
+            let local_port = self.local_addr.port();
+            let ports = self
+                .server_config
+                .worker_placement
+                .session_ports(session_id);
+            // as in is_home_worker, the ports of the session starting with its home worker
+            if ports.first().map_or(true, |&port| port == local_port) {
+                self.events.push(ServerEvent::SessionStarted { session_id });
+            }
+            if ports.len() > 1 && ports.contains(&local_port) {
+                debug!("{} spans the workers {:?}", session_id, ports);
+                session.start_cascade(SessionCascade::new(session_id, local_port, ports));
+            }
+            e.insert(session);
+        }
+
+        self.sessions.get_mut(&session_id).unwrap()
+    }
+
+    pub(crate) fn get_mut_sessions(&mut self) -> &mut HashMap<SessionId, Session> {
+        &mut self.sessions
+    }
+
+    pub(crate) fn get_sessions(&self) -> &HashMap<SessionId, Session> {
+        &self.sessions
+    }
+
+    pub(crate) fn get_session(&self, session_id: &SessionId) -> Option<&Session> {
+        self.sessions.get(session_id)
+    }
+
+    pub(crate) fn get_mut_session(&mut self, session_id: &SessionId) -> Option<&mut Session> {
+        self.sessions.get_mut(session_id)
+    }
+
+    pub(crate) fn remove_session(&mut self, session_id: &SessionId) -> Option<Session> {
+        self.sessions.remove(session_id)
+    }
+
+    /// close_session removes an idle session, completing its recording, and lets the other
+    /// workers hosting it know
+    fn close_session(&mut self, session_id: SessionId) {
+        let Some(mut session) = self.remove_session(&session_id) else {
+            return;
+        };
+        if let Some(recorder) = session.take_recorder() {
+            match recorder.stop() {
+                Ok(manifest) => info!(
+                    "{} stops recording in {:?} once empty",
+                    session_id, manifest.directory
+                ),
+                Err(err) => error!("{} can't stop recording: {}", session_id, err),
+            }
+        }
+        if let Some(mut cascade) = session.take_cascade() {
+            cascade.leave();
+            self.relay_messages.extend(cascade.take_messages());
+        }
+        self.events.extend(session.take_events());
+        if self.is_home_worker(session_id) {
+            self.events.push(ServerEvent::SessionEnded { session_id });
+        }
+    }
+
+    /// is_home_worker tells whether the session is reported by this worker, the other workers
+    /// hosting the session reporting only their own endpoints
+    fn is_home_worker(&self, session_id: SessionId) -> bool {
+        self.server_config
+            .worker_placement
+            .session_port(session_id)
+            .map_or(true, |port| port == self.local_addr.port())
+    }
+
+    pub(crate) fn add_candidate(&mut self, candidate: Rc<Candidate>) -> Option<Rc<Candidate>> {
+        let username = candidate.username();
+        self.candidates.insert(username, candidate)
+    }
+
+    pub(crate) fn remove_candidate(&mut self, username: &UserName) -> Option<Rc<Candidate>> {
+        self.candidates.remove(username)
+    }
+
+    pub(crate) fn find_candidate(&self, username: &UserName) -> Option<&Rc<Candidate>> {
+        self.candidates.get(username)
+    }
+
+    pub(crate) fn get_candidates(&self) -> &HashMap<UserName, Rc<Candidate>> {
+        &self.candidates
+    }
+
+    pub(crate) fn get_endpoints(&self) -> &HashMap<FourTuple, (SessionId, EndpointId)> {
+        &self.endpoints
+    }
+
+    pub(crate) fn add_endpoint(
+        &mut self,
+        four_tuple: FourTuple,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    ) {
+        self.endpoints.insert(four_tuple, (session_id, endpoint_id));
+        info!(
+            "{}/{} is connected via {:?}",
+            session_id, endpoint_id, four_tuple
+        )
+    }
+
+    pub(crate) fn remove_endpoint(&mut self, four_tuple: &FourTuple) {
+        self.endpoints.remove(four_tuple);
+    }
+
+    pub(crate) fn find_endpoint(&self, four_tuple: &FourTuple) -> Option<(SessionId, EndpointId)> {
+        self.endpoints.get(four_tuple).cloned()
+    }
+
+    pub(crate) fn get_mut_endpoint(&mut self, four_tuple: &FourTuple) -> Result<&mut Endpoint> {
+        let (session_id, endpoint_id) = self.find_endpoint(four_tuple).ok_or(Error::Other(
+            format!("can't find endpoint with four_tuple {:?}", four_tuple),
+        ))?;
+
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {:?}",
+                session_id
+            )))?;
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {:?}",
+                endpoint_id
+            )))?;
+
+        Ok(endpoint)
+    }
+
+    pub(crate) fn get_mut_transport(&mut self, four_tuple: &FourTuple) -> Result<&mut Transport> {
+        let (session_id, endpoint_id) = self.find_endpoint(four_tuple).ok_or(Error::Other(
+            format!("can't find endpoint with four_tuple {:?}", four_tuple),
+        ))?;
+
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {:?}",
+                session_id
+            )))?;
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {:?}",
+                endpoint_id
+            )))?;
+        let transports = endpoint.get_mut_transports();
+        let transport = transports.get_mut(four_tuple).ok_or(Error::Other(format!(
+            "can't find transport with four_tuple {:?} for endpoint id {}",
+            four_tuple, endpoint_id,
+        )))?;
+
+        Ok(transport)
+    }
+
+    /// latch_plain_transport adds the transport of the plain RTP source declaring the SSRC of
+    /// a packet received from an unknown address, if any
+    pub(crate) fn latch_plain_transport(
+        &mut self,
+        four_tuple: FourTuple,
+        is_rtcp: bool,
+        message: &[u8],
+    ) -> Result<()> {
+        let ssrc_offset = if is_rtcp { 4 } else { 8 };
+        let Some(ssrc) = message
+            .get(ssrc_offset..ssrc_offset + 4)
+            .map(|ssrc| u32::from_be_bytes([ssrc[0], ssrc[1], ssrc[2], ssrc[3]]))
+        else {
+            return Ok(());
+        };
+        let Some((session_id, endpoint_id)) =
+            self.sessions.iter().find_map(|(&session_id, session)| {
+                session
+                    .find_plain_ingest(ssrc)
+                    .map(|endpoint_id| (session_id, endpoint_id))
+            })
+        else {
+            return Ok(());
+        };
+
+        info!(
+            "{}/{} plain ingest sends from {}",
+            session_id, endpoint_id, four_tuple.peer_addr
+        );
+        self.add_plain_transport(session_id, endpoint_id, four_tuple)
+    }
+
+    /// add_plain_transport adds the transport of a plain RTP source
+    fn add_plain_transport(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        four_tuple: FourTuple,
+    ) -> Result<()> {
+        let server_config = Arc::clone(&self.server_config);
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        let plain_ingest = session
+            .get_mut_plain_ingest(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "endpoint id {} is not a plain ingest",
+                endpoint_id
+            )))?;
+        plain_ingest.set_remote_addr(four_tuple.peer_addr);
+        let transport = Transport::new_plain(
+            four_tuple,
+            plain_ingest.srtp_contexts()?,
+            server_config.dtls_handshake_config.clone(),
+            server_config.sctp_endpoint_config.clone(),
+            server_config.sctp_server_config.clone(),
+        );
+        session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?
+            .add_transport(transport);
+        self.add_endpoint(four_tuple, session_id, endpoint_id);
+
+        Ok(())
+    }
+
+    pub(crate) fn remove_transport(&mut self, four_tuple: FourTuple) {
+        debug!("remove idle transport {:?}", four_tuple);
+
+        let Some((session_id, endpoint_id)) = self.find_endpoint(&four_tuple) else {
+            return;
+        };
+        let Some(session) = self.get_mut_session(&session_id) else {
+            return;
+        };
+        let Some(endpoint) = session.get_mut_endpoint(&endpoint_id) else {
+            return;
+        };
+
+        let transport = endpoint.remove_transport(&four_tuple);
+        if endpoint.get_transports().is_empty() {
+            if session.get_plain_ingest(&endpoint_id).is_some() {
+                // an idle plain RTP source stops publishing, like a WebRTC one leaving
+                let _ = session.stop_plain_ingest(&endpoint_id);
+            } else {
+                session.remove_endpoint(&endpoint_id);
+            }
+            if session.is_idle() {
+                self.close_session(session_id);
+            }
+            self.remove_endpoint(&four_tuple);
+        }
+        if let Some(candidate) = transport
+            .as_ref()
+            .and_then(|transport| transport.candidate())
+        {
+            self.remove_candidate(&candidate.username());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::description::sdp_type::RTCSdpType;
+    use crate::handler::gateway::GatewayHandler;
+    use crate::server::certificate::RTCCertificate;
+
+    fn server_config() -> ServerConfig {
+        let key_pair = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+        ServerConfig::new(vec![RTCCertificate::from_key_pair(key_pair).unwrap()])
+    }
+
+    fn server_states() -> ServerStates {
+        server_states_with(server_config())
+    }
+
+    fn server_states_with(server_config: ServerConfig) -> ServerStates {
+        ServerStates::new(Arc::new(server_config), "127.0.0.1:3478".parse().unwrap()).unwrap()
+    }
+
+    fn vp8_only() -> CodecPolicy {
+        CodecPolicy {
+            video_codecs: vec!["VP8".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keeps_the_codec_policy_of_a_session_until_it_starts() {
+        let mut server_states = server_states();
+        server_states.set_codec_policy(1, vp8_only()).unwrap();
+        assert!(server_states.get_session(&1).is_none());
+        assert!(server_states.poll_events().is_empty());
+
+        let session = server_states.create_or_get_mut_session(1);
+        assert_eq!(session.session_config().codec_policy, vp8_only());
+        assert!(server_states.codec_policies.is_empty());
+        assert_eq!(
+            server_states
+                .create_or_get_mut_session(2)
+                .session_config()
+                .codec_policy,
+            CodecPolicy::default()
+        );
+    }
+
+    #[test]
+    fn sets_the_codec_policy_of_a_started_session() {
+        let mut server_states = server_states();
+        server_states.create_or_get_mut_session(1);
+        server_states.set_codec_policy(1, vp8_only()).unwrap();
+        assert_eq!(
+            server_states
+                .get_session(&1)
+                .unwrap()
+                .session_config()
+                .codec_policy,
+            vp8_only()
+        );
+        assert!(server_states.codec_policies.is_empty());
+    }
+
+    fn egress_request(host: &str) -> EgressRequest {
+        EgressRequest {
+            endpoint_id: None,
+            host: host.parse().unwrap(),
+            port: 5004,
+            audio: true,
+            video: true,
+            sender_reports: false,
+        }
+    }
+
+    #[test]
+    fn forwards_egresses_to_the_allowed_hosts_only() {
+        let egress_dir =
+            std::env::temp_dir().join(format!("beep-sfu-egress-{}", std::process::id()));
+        let mut server_states = server_states_with(
+            server_config()
+                .with_egress_dir(egress_dir.clone())
+                .with_egress_allowed_hosts(vec!["127.0.0.1".parse().unwrap()]),
+        );
+        server_states.create_or_get_mut_session(1);
+
+        assert!(server_states
+            .start_egress(1, egress_request("10.0.0.1"))
+            .is_err());
+        assert!(server_states
+            .start_egress(1, egress_request("::ffff:127.0.0.1"))
+            .is_ok());
+        server_states.stop_egress(1).unwrap();
+        std::fs::remove_dir_all(egress_dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_egresses_without_allowed_hosts() {
+        let egress_dir =
+            std::env::temp_dir().join(format!("beep-sfu-no-egress-{}", std::process::id()));
+        let mut server_states = server_states_with(server_config().with_egress_dir(egress_dir));
+        server_states.create_or_get_mut_session(1);
+
+        assert!(server_states
+            .start_egress(1, egress_request("127.0.0.1"))
+            .is_err());
+    }
+
+    const FINGERPRINT: &str = "sha-256 \
+        AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89";
+
+    /// Offer of the data channel endpoints join with
+    fn offer(session_version: u64) -> RTCSessionDescription {
+        RTCSessionDescription::offer(format!(
+            "v=0\r\n\
+             o=- 1 {} IN IP4 127.0.0.1\r\n\
+             s=-\r\n\
+             t=0 0\r\n\
+             a=group:BUNDLE 0\r\n\
+             a=fingerprint:{}\r\n\
+             m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+             c=IN IP4 0.0.0.0\r\n\
+             a=ice-ufrag:abcd\r\n\
+             a=ice-pwd:abcdefghijklmnopqrstuvwx\r\n\
+             a=setup:actpass\r\n\
+             a=mid:0\r\n\
+             a=sctp-port:5000\r\n",
+            session_version, FINGERPRINT
+        ))
+        .unwrap()
+    }
+
+    fn transport_context() -> TransportContext {
+        TransportContext {
+            local_addr: "127.0.0.1:3478".parse().unwrap(),
+            peer_addr: "127.0.0.1:50000".parse().unwrap(),
+            ecn: None,
+        }
+    }
+
+    /// Endpoint 1 of session 1 connected with the offer it joined with, as after its ICE
+    /// connectivity checks
+    fn connected_server_states() -> ServerStates {
+        let mut server_states = server_states();
+        server_states
+            .accept_offer(1, 1, None, offer(1), false)
+            .unwrap();
+        let candidate = Rc::clone(server_states.get_candidates().values().next().unwrap());
+        let transport_context = transport_context();
+        server_states
+            .get_mut_session(&1)
+            .unwrap()
+            .add_endpoint(&candidate, &transport_context)
+            .unwrap();
+        server_states.add_endpoint((&transport_context).into(), 1, 1);
+        server_states
+    }
+
+    fn endpoint(server_states: &ServerStates) -> &Endpoint {
+        server_states
+            .get_session(&1)
+            .unwrap()
+            .get_endpoint(&1)
+            .unwrap()
+    }
+
+    fn offer_over_data_channel(server_states: &mut ServerStates) -> bool {
+        GatewayHandler::create_offer_message_event(
+            server_states,
+            Instant::now(),
+            transport_context(),
+            0,
+            0,
+        )
+        .unwrap()
+        .is_some()
+    }
+
+    #[test]
+    fn rejects_an_answer_without_a_pending_offer() {
+        let mut server_states = connected_server_states();
+        let answer = RTCSessionDescription::answer(offer(2).sdp).unwrap();
+        assert!(server_states
+            .accept_answer(1, 1, (&transport_context()).into(), answer)
+            .is_err());
+    }
+
+    #[test]
+    fn defers_offers_while_one_is_pending() {
+        let mut server_states = connected_server_states();
+        assert!(offer_over_data_channel(&mut server_states));
+        assert!(endpoint(&server_states).is_offer_pending());
+
+        // the offer crossing the pending one waits for its answer
+        assert!(!offer_over_data_channel(&mut server_states));
+        assert!(endpoint(&server_states).is_renegotiation_needed());
+    }
+
+    #[test]
+    fn rolls_back_the_pending_offer_when_offered() {
+        let mut server_states = connected_server_states();
+        assert!(offer_over_data_channel(&mut server_states));
+
+        // glare: the endpoint offers before answering the offer of the server
+        let answer = server_states
+            .accept_offer(1, 1, Some((&transport_context()).into()), offer(2), false)
+            .unwrap();
+        assert_eq!(answer.sdp_type, RTCSdpType::Answer);
+        assert!(!endpoint(&server_states).is_offer_pending());
+
+        // and the answer to the offer given up on arrives late
+        let late_answer = RTCSessionDescription::answer(offer(3).sdp).unwrap();
+        assert!(server_states
+            .accept_answer(1, 1, (&transport_context()).into(), late_answer)
+            .is_err());
+    }
+}
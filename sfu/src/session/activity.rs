@@ -0,0 +1,90 @@
+use crate::description::rtp_codec::RTPCodecType;
+use crate::messages::ConnectionQuality;
+use crate::types::{EndpointId, Mid};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// a published track sending no media, or only digital silence, for this long is muted
+const MUTE_TIMEOUT: Duration = Duration::from_secs(2);
+/// audio level in -dBov of digital silence, which is what muted microphones send
+const SILENCE_LEVEL: u8 = 127;
+
+#[derive(Debug)]
+struct TrackActivity {
+    kind: RTPCodecType,
+    last_active: Instant,
+    is_muted: bool,
+}
+
+/// MediaActivity follows the media published by the endpoints connected to the worker, to tell
+/// when their tracks are muted and unmuted, and when the quality of their connection changes
+#[derive(Default, Debug)]
+pub(crate) struct MediaActivity {
+    tracks: HashMap<EndpointId, HashMap<Mid, TrackActivity>>,
+    qualities: HashMap<EndpointId, ConnectionQuality>,
+}
+
+impl MediaActivity {
+    /// on_rtp records a packet of a published track, and returns whether it unmutes the track.
+    /// Tracks are unmuted until they stop sending media
+    pub(crate) fn on_rtp(
+        &mut self,
+        endpoint_id: EndpointId,
+        mid: &str,
+        kind: RTPCodecType,
+        audio_level: Option<u8>,
+        now: Instant,
+    ) -> bool {
+        let is_active = audio_level != Some(SILENCE_LEVEL);
+        let tracks = self.tracks.entry(endpoint_id).or_default();
+        let Some(track) = tracks.get_mut(mid) else {
+            tracks.insert(
+                mid.to_string(),
+                TrackActivity {
+                    kind,
+                    last_active: now,
+                    is_muted: false,
+                },
+            );
+            return false;
+        };
+        if !is_active {
+            return false;
+        }
+        track.last_active = now;
+        std::mem::replace(&mut track.is_muted, false)
+    }
+
+    /// poll_muted returns the tracks muted since the last poll
+    pub(crate) fn poll_muted(&mut self, now: Instant) -> Vec<(EndpointId, Mid, RTPCodecType)> {
+        let mut muted = vec![];
+        for (&endpoint_id, tracks) in self.tracks.iter_mut() {
+            for (mid, track) in tracks.iter_mut() {
+                if !track.is_muted && now.duration_since(track.last_active) >= MUTE_TIMEOUT {
+                    track.is_muted = true;
+                    muted.push((endpoint_id, mid.clone(), track.kind));
+                }
+            }
+        }
+        muted
+    }
+
+    /// update_quality records the connection quality of the endpoint, and returns whether it
+    /// changed. Connections are good until rated otherwise
+    pub(crate) fn update_quality(
+        &mut self,
+        endpoint_id: EndpointId,
+        quality: ConnectionQuality,
+    ) -> bool {
+        let previous = self
+            .qualities
+            .insert(endpoint_id, quality)
+            .unwrap_or(ConnectionQuality::Good);
+        previous != quality
+    }
+
+    pub(crate) fn remove_endpoint(&mut self, endpoint_id: &EndpointId) {
+        self.tracks.remove(endpoint_id);
+        self.qualities.remove(endpoint_id);
+    }
+}
@@ -0,0 +1,38 @@
+use crate::description::codec_policy::CodecPolicy;
+use crate::description::rtp_codec::{RTCRtpCodecParameters, RTPCodecType};
+use crate::server::config::ServerConfig;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+pub(crate) struct SessionConfig {
+    pub(crate) server_config: Arc<ServerConfig>,
+    pub(crate) local_addr: SocketAddr,
+    pub(crate) candidate_addrs: Vec<SocketAddr>,
+    pub(crate) tcp_candidate_addrs: Vec<SocketAddr>,
+    pub(crate) codec_policy: CodecPolicy,
+}
+
+impl SessionConfig {
+    pub(crate) fn new(
+        server_config: Arc<ServerConfig>,
+        local_addr: SocketAddr,
+        candidate_addrs: Vec<SocketAddr>,
+        tcp_candidate_addrs: Vec<SocketAddr>,
+    ) -> Self {
+        let codec_policy = server_config.codec_policy.clone();
+        Self {
+            server_config,
+            local_addr,
+            candidate_addrs,
+            tcp_candidate_addrs,
+            codec_policy,
+        }
+    }
+
+    /// get_codecs_by_kind returns the codecs offered and answered to the endpoints of the
+    /// session, as allowed by its codec policy
+    pub(crate) fn get_codecs_by_kind(&self, typ: RTPCodecType) -> Vec<RTCRtpCodecParameters> {
+        self.codec_policy
+            .apply(typ, self.server_config.media_config.get_codecs_by_kind(typ))
+    }
+}
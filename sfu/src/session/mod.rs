@@ -0,0 +1,2019 @@
+use bytes::BytesMut;
+use log::{info, warn};
+use retty::transport::TransportContext;
+use sdp::description::session::Origin;
+use sdp::util::ConnectionRole;
+use sdp::SessionDescription;
+use shared::error::{Error, Result};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Instant;
+
+pub(crate) mod activity;
+pub(crate) mod config;
+pub(crate) mod subscription;
+
+use crate::capture::PacketCapture;
+use crate::cascade::{RelayEvent, RelayPeer, SessionCascade};
+use crate::chat::{ChatEvent, ChatRequest, ChatRoom};
+use crate::description::codec_policy::CodecPolicy;
+use crate::description::{
+    codecs_from_media_description, get_cname, get_mid_value, get_msid, get_peer_direction,
+    get_rids, get_ssrc_groups, get_ssrcs, populate_sdp, rtp_extensions_from_media_description,
+    update_sdp_origin, MediaSection, RTCSessionDescription, MEDIA_SECTION_APPLICATION,
+};
+use crate::description::{
+    rtp_codec::{RTCRtpParameters, RTPCodecType},
+    rtp_transceiver::{
+        MediaStreamId, RTCRtpSender, RTCRtpTransceiver, SsrcGroup, SSRC, SSRC_GROUP_FID,
+    },
+    rtp_transceiver_direction::RTCRtpTransceiverDirection,
+    sdp_type::RTCSdpType,
+};
+use crate::egress::PlainRtpEgress;
+use crate::endpoint::{
+    candidate::{Candidate, DTLSRole, RTCIceParameters, DEFAULT_DTLS_ROLE_OFFER},
+    transport::Transport,
+    Endpoint, IncomingStream,
+};
+use crate::hls::HlsPackager;
+use crate::ingest::PlainIngest;
+use crate::injector::MediaInjector;
+use crate::messages::{ConnectionQuality, ServerEvent};
+use crate::recording::SessionRecorder;
+use crate::rtmp::{RtmpIngest, RtmpMedia};
+use crate::session::activity::MediaActivity;
+use crate::session::config::SessionConfig;
+use crate::speaker::DominantSpeaker;
+use crate::types::{EndpointId, FourTuple, Mid, SessionId};
+
+pub(crate) struct Session {
+    session_config: SessionConfig,
+    session_id: SessionId,
+    endpoints: HashMap<EndpointId, Endpoint>,
+    /// endpoint ids in joining order, the last N policy falls back to it when too few endpoints
+    /// have spoken yet
+    joined_endpoint_ids: Vec<EndpointId>,
+    dominant_speaker: DominantSpeaker,
+    /// publishers whose video is forwarded to each subscriber under the last N policy, updated
+    /// when the dominant speaker, the endpoints or their pins change
+    forwarded_videos: HashMap<EndpointId, HashSet<EndpointId>>,
+    recorder: Option<SessionRecorder>,
+    capture: Option<PacketCapture>,
+    egress: Option<PlainRtpEgress>,
+    hls: Option<HlsPackager>,
+    /// relay with the other workers hosting endpoints of the session, when it spans several
+    cascade: Option<SessionCascade>,
+    /// media injectors by the endpoint id of their virtual endpoint
+    injectors: HashMap<EndpointId, MediaInjector>,
+    /// plain RTP sources by their endpoint id
+    plain_ingests: HashMap<EndpointId, PlainIngest>,
+    /// RTMP publishers by their endpoint id
+    rtmp_ingests: HashMap<EndpointId, RtmpIngest>,
+    /// endpoints to send an offer to from the timeout loop, their renegotiation being triggered
+    /// by the server rather than by a message they sent
+    pending_offers: HashSet<EndpointId>,
+    /// mute state and connection quality of the endpoints connected to this worker
+    activity: MediaActivity,
+    /// chat rate limits of the endpoints connected to this worker
+    chat: ChatRoom,
+    /// events of the endpoints of the session, polled by the server states
+    events: Vec<ServerEvent>,
+}
+
+impl Session {
+    pub(crate) fn new(session_config: SessionConfig, session_id: SessionId) -> Self {
+        Self {
+            session_config,
+            session_id,
+            endpoints: HashMap::new(),
+            joined_endpoint_ids: vec![],
+            dominant_speaker: DominantSpeaker::default(),
+            forwarded_videos: HashMap::new(),
+            recorder: None,
+            capture: None,
+            egress: None,
+            hls: None,
+            cascade: None,
+            injectors: HashMap::new(),
+            plain_ingests: HashMap::new(),
+            rtmp_ingests: HashMap::new(),
+            pending_offers: HashSet::new(),
+            activity: MediaActivity::default(),
+            chat: ChatRoom::default(),
+            events: vec![],
+        }
+    }
+
+    pub(crate) fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    pub(crate) fn session_config(&self) -> &SessionConfig {
+        &self.session_config
+    }
+
+    pub(crate) fn add_endpoint(
+        &mut self,
+        candidate: &Rc<Candidate>,
+        transport_context: &TransportContext,
+    ) -> Result<bool> {
+        let dtls_handshake_config = self
+            .session_config
+            .server_config
+            .dtls_handshake_config
+            .clone();
+        let sctp_endpoint_config = self
+            .session_config
+            .server_config
+            .sctp_endpoint_config
+            .clone();
+        let sctp_server_config = self.session_config.server_config.sctp_server_config.clone();
+        let endpoint_id = candidate.endpoint_id();
+        let endpoint = self.get_mut_endpoint(&endpoint_id);
+        let four_tuple = transport_context.into();
+        if let Some(endpoint) = endpoint {
+            if endpoint.has_transport(&four_tuple) {
+                Ok(true)
+            } else {
+                let transport = Transport::new(
+                    four_tuple,
+                    Rc::clone(candidate),
+                    dtls_handshake_config,
+                    sctp_endpoint_config,
+                    sctp_server_config,
+                );
+                endpoint.add_transport(transport);
+                Ok(true)
+            }
+        } else {
+            let registry = self.session_config.server_config.media_config.registry();
+            let interceptor = registry.build(""); //TODO: use named registry id
+            let mut endpoint = Endpoint::new(endpoint_id, interceptor);
+            endpoint.set_auto_subscribed(candidate.is_auto_subscribed());
+            let transport = Transport::new(
+                four_tuple,
+                Rc::clone(candidate),
+                dtls_handshake_config,
+                sctp_endpoint_config,
+                sctp_server_config,
+            );
+            endpoint.add_transport(transport);
+            endpoint.set_local_description(candidate.local_description().clone());
+            endpoint.set_remote_description(candidate.remote_description().clone());
+            self.endpoints.insert(endpoint_id, endpoint);
+            self.joined_endpoint_ids.push(endpoint_id);
+            self.update_forwarded_videos();
+            self.events.push(ServerEvent::EndpointJoined {
+                session_id: self.session_id,
+                endpoint_id,
+            });
+            Ok(false)
+        }
+    }
+
+    /// set_codec_policy replaces the codec policy of the session, which can't change once
+    /// endpoints negotiated their codecs
+    pub(crate) fn set_codec_policy(&mut self, codec_policy: CodecPolicy) -> Result<()> {
+        if !self.endpoints.is_empty() {
+            return Err(Error::Other(format!(
+                "session id {} already has endpoints",
+                self.session_id
+            )));
+        }
+        self.session_config.codec_policy = codec_policy;
+        Ok(())
+    }
+
+    /// bind_local_stream lets the interceptor of the endpoint know about a stream sent to it
+    pub(crate) fn bind_local_stream(&mut self, endpoint_id: &EndpointId, ssrc: SSRC) {
+        if let Some(endpoint) = self.endpoints.get_mut(endpoint_id) {
+            endpoint.bind_local_stream(ssrc, &self.session_config);
+        }
+    }
+
+    pub(crate) fn get_endpoint(&self, endpoint_id: &EndpointId) -> Option<&Endpoint> {
+        self.endpoints.get(endpoint_id)
+    }
+
+    pub(crate) fn get_mut_endpoint(&mut self, endpoint_id: &EndpointId) -> Option<&mut Endpoint> {
+        self.endpoints.get_mut(endpoint_id)
+    }
+
+    pub(crate) fn remove_endpoint(&mut self, endpoint_id: &EndpointId) -> Option<Endpoint> {
+        if !self.is_relayed(endpoint_id) {
+            if let Some(endpoint) = self.endpoints.get(endpoint_id) {
+                for transceiver in published_transceivers(endpoint) {
+                    self.events.push(ServerEvent::TrackUnpublished {
+                        session_id: self.session_id,
+                        endpoint_id: *endpoint_id,
+                        mid: transceiver.mid,
+                        kind: transceiver.kind.to_string(),
+                    });
+                }
+                self.events.push(ServerEvent::EndpointLeft {
+                    session_id: self.session_id,
+                    endpoint_id: *endpoint_id,
+                });
+            }
+        }
+        self.dominant_speaker.remove_endpoint(endpoint_id);
+        self.activity.remove_endpoint(endpoint_id);
+        self.chat.remove_endpoint(endpoint_id);
+        self.joined_endpoint_ids.retain(|id| id != endpoint_id);
+        self.forwarded_videos.remove(endpoint_id);
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.remove_endpoint(endpoint_id);
+        }
+        if let Some(hls) = self.hls.as_mut() {
+            hls.remove_endpoint(endpoint_id);
+        }
+        if let Some(cascade) = self.cascade.as_mut() {
+            cascade.remove_endpoint(endpoint_id);
+        }
+        self.plain_ingests.remove(endpoint_id);
+        self.rtmp_ingests.remove(endpoint_id);
+        let endpoint = self.endpoints.remove(endpoint_id);
+        self.update_forwarded_videos();
+        endpoint
+    }
+
+    /// take_events returns the events of the endpoints since the last call
+    pub(crate) fn take_events(&mut self) -> Vec<ServerEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// on_published_rtp follows the media of a track published by an endpoint connected to this
+    /// worker, reporting the track unmuted when its media resumes
+    pub(crate) fn on_published_rtp(
+        &mut self,
+        endpoint_id: EndpointId,
+        mid: &str,
+        kind: RTPCodecType,
+        audio_level: Option<u8>,
+        now: Instant,
+    ) {
+        if self.is_relayed(&endpoint_id) {
+            return;
+        }
+        if self
+            .activity
+            .on_rtp(endpoint_id, mid, kind, audio_level, now)
+        {
+            self.events.push(ServerEvent::TrackUnmuted {
+                session_id: self.session_id,
+                endpoint_id,
+                mid: mid.to_string(),
+                kind: kind.to_string(),
+            });
+        }
+    }
+
+    /// poll_activity reports the tracks whose media stopped as muted, and the endpoints whose
+    /// connection quality changed
+    pub(crate) fn poll_activity(&mut self, now: Instant) {
+        for (endpoint_id, mid, kind) in self.activity.poll_muted(now) {
+            self.events.push(ServerEvent::TrackMuted {
+                session_id: self.session_id,
+                endpoint_id,
+                mid,
+                kind: kind.to_string(),
+            });
+        }
+
+        for (&endpoint_id, endpoint) in self.endpoints.iter() {
+            if endpoint.get_transports().is_empty() {
+                continue;
+            }
+            let quality = if endpoint.is_video_paused() {
+                ConnectionQuality::Poor
+            } else {
+                ConnectionQuality::Good
+            };
+            if self.activity.update_quality(endpoint_id, quality) {
+                self.events.push(ServerEvent::ConnectionQualityChanged {
+                    session_id: self.session_id,
+                    endpoint_id,
+                    quality,
+                });
+            }
+        }
+    }
+
+    /// report_active_speaker reports a new dominant speaker, from the home worker only as every
+    /// worker hosting the session detects it from the same audio levels
+    pub(crate) fn report_active_speaker(&mut self, endpoint_id: EndpointId) {
+        if self
+            .cascade
+            .as_ref()
+            .map_or(true, |cascade| cascade.is_home())
+        {
+            self.events.push(ServerEvent::ActiveSpeakerChanged {
+                session_id: self.session_id,
+                endpoint_id,
+            });
+        }
+    }
+
+    /// handle_chat checks the chat message of an endpoint connected to this worker against the
+    /// chat limits, and relays it to the other workers and nodes hosting the session. It returns
+    /// the message to send to the endpoints of this worker, or the reason it was rejected to
+    /// send back to the endpoint
+    pub(crate) fn handle_chat(
+        &mut self,
+        endpoint_id: EndpointId,
+        request: ChatRequest,
+        size: usize,
+        now: Instant,
+    ) -> ChatEvent {
+        let chat_limits = self.session_config.server_config.chat_limits;
+        if let Err(reason) = self.chat.admit(endpoint_id, size, &chat_limits, now) {
+            return ChatEvent::ChatError { reason };
+        }
+        if let ChatRequest::DirectMessage { to, .. } = &request {
+            // the endpoints of other workers are only known once they publish
+            if !self.endpoints.contains_key(to) && self.cascade.is_none() {
+                return ChatEvent::ChatError {
+                    reason: format!("can't find endpoint id {}", to),
+                };
+            }
+        }
+
+        let event = request.into_event(endpoint_id);
+        if let Some(cascade) = self.cascade.as_mut() {
+            cascade.relay_chat(None, &event);
+        }
+        event
+    }
+
+    pub(crate) fn has_endpoint(&self, endpoint_id: &EndpointId) -> bool {
+        self.endpoints.contains_key(endpoint_id)
+    }
+
+    /// has_connected_endpoints tells whether any endpoint still has a transport, virtual
+    /// endpoints having none
+    pub(crate) fn has_connected_endpoints(&self) -> bool {
+        self.endpoints
+            .values()
+            .any(|endpoint| !endpoint.get_transports().is_empty())
+    }
+
+    pub(crate) fn get_endpoints(&self) -> &HashMap<EndpointId, Endpoint> {
+        &self.endpoints
+    }
+
+    pub(crate) fn get_mut_endpoints(&mut self) -> &mut HashMap<EndpointId, Endpoint> {
+        &mut self.endpoints
+    }
+
+    pub(crate) fn get_dominant_speaker(&self) -> &DominantSpeaker {
+        &self.dominant_speaker
+    }
+
+    /// on_audio_level records the audio level of a packet published by the endpoint, and returns
+    /// the new dominant speaker when it changes
+    pub(crate) fn on_audio_level(
+        &mut self,
+        endpoint_id: EndpointId,
+        level: u8,
+        now: Instant,
+    ) -> Option<EndpointId> {
+        let dominant_speaker = self
+            .dominant_speaker
+            .on_audio_level(endpoint_id, level, now)?;
+        self.update_forwarded_videos();
+        Some(dominant_speaker)
+    }
+
+    /// set_pinned_endpoints replaces the endpoints whose video is always forwarded to the
+    /// endpoint
+    pub(crate) fn set_pinned_endpoints(
+        &mut self,
+        endpoint_id: &EndpointId,
+        pinned_endpoints: HashSet<EndpointId>,
+    ) -> Result<()> {
+        self.get_mut_endpoint(endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?
+            .set_pinned_endpoints(pinned_endpoints);
+        self.update_forwarded_videos();
+        Ok(())
+    }
+
+    pub(crate) fn get_mut_recorder(&mut self) -> Option<&mut SessionRecorder> {
+        self.recorder.as_mut()
+    }
+
+    pub(crate) fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// start_recording records the tracks published in the session from now on
+    pub(crate) fn start_recording(&mut self, recorder: SessionRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// take_recorder stops recording the session, the recorder being left to complete its files
+    pub(crate) fn take_recorder(&mut self) -> Option<SessionRecorder> {
+        self.recorder.take()
+    }
+
+    pub(crate) fn get_mut_capture(&mut self) -> Option<&mut PacketCapture> {
+        self.capture.as_mut()
+    }
+
+    pub(crate) fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// start_capture captures the packets of the session from now on
+    pub(crate) fn start_capture(&mut self, capture: PacketCapture) {
+        self.capture = Some(capture);
+    }
+
+    /// take_capture stops capturing the session, the capture being left to complete its file
+    pub(crate) fn take_capture(&mut self) -> Option<PacketCapture> {
+        self.capture.take()
+    }
+
+    pub(crate) fn get_egress(&self) -> Option<&PlainRtpEgress> {
+        self.egress.as_ref()
+    }
+
+    pub(crate) fn is_egressing(&self) -> bool {
+        self.egress.is_some()
+    }
+
+    /// start_egress forwards the tracks published in the session to the egress from now on,
+    /// the ones already published taking its first ports in joining order
+    pub(crate) fn start_egress(&mut self, mut egress: PlainRtpEgress) -> Result<()> {
+        for endpoint_id in self.joined_endpoint_ids.iter() {
+            let Some(endpoint) = self.endpoints.get(endpoint_id) else {
+                continue;
+            };
+            let mut transceivers: Vec<&RTCRtpTransceiver> =
+                endpoint.get_transceivers().values().collect();
+            transceivers.sort_by(|a, b| (a.mid.len(), &a.mid).cmp(&(b.mid.len(), &b.mid)));
+            for transceiver in transceivers {
+                egress.add_track(*endpoint_id, transceiver)?;
+            }
+        }
+        self.egress = Some(egress);
+        Ok(())
+    }
+
+    /// take_egress stops forwarding the session to its egress
+    pub(crate) fn take_egress(&mut self) -> Option<PlainRtpEgress> {
+        self.egress.take()
+    }
+
+    /// egress_rtp returns the plain RTP packet to send to the egress for a packet published by
+    /// an endpoint, if any, and whether a keyframe has to be requested for it
+    pub(crate) fn egress_rtp(
+        &mut self,
+        now: Instant,
+        endpoint_id: EndpointId,
+        incoming_stream: &IncomingStream,
+        rtp_packet: &rtp::packet::Packet,
+    ) -> (Option<(SocketAddr, BytesMut)>, bool) {
+        let (Some(egress), Some(endpoint)) =
+            (self.egress.as_mut(), self.endpoints.get(&endpoint_id))
+        else {
+            return (None, false);
+        };
+        let Some(transceiver) = endpoint.get_transceivers().get(&incoming_stream.mid) else {
+            return (None, false);
+        };
+        egress.on_rtp(
+            now,
+            endpoint_id,
+            transceiver,
+            endpoint.get_simulcast_tracks().get(&incoming_stream.mid),
+            incoming_stream,
+            rtp_packet,
+        )
+    }
+
+    pub(crate) fn poll_egress_timeout(&self) -> Option<Instant> {
+        self.egress
+            .as_ref()
+            .and_then(|egress| egress.poll_timeout())
+    }
+
+    /// poll_egress_sender_reports returns the RTCP sender reports of the egress due by now
+    pub(crate) fn poll_egress_sender_reports(
+        &mut self,
+        now: Instant,
+    ) -> Vec<(SocketAddr, BytesMut)> {
+        self.egress
+            .as_mut()
+            .map_or(vec![], |egress| egress.poll_sender_reports(now))
+    }
+
+    pub(crate) fn get_hls(&self) -> Option<&HlsPackager> {
+        self.hls.as_ref()
+    }
+
+    pub(crate) fn is_streaming_hls(&self) -> bool {
+        self.hls.is_some()
+    }
+
+    /// start_hls packages the tracks of the streamed endpoint over HLS from now on
+    pub(crate) fn start_hls(&mut self, hls: HlsPackager) {
+        self.hls = Some(hls);
+    }
+
+    /// take_hls stops packaging the session over HLS, the packager being left to end its
+    /// playlist
+    pub(crate) fn take_hls(&mut self) -> Option<HlsPackager> {
+        self.hls.take()
+    }
+
+    /// hls_rtp hands a packet published by an endpoint to the HLS packager, and returns whether
+    /// a keyframe has to be requested for it
+    pub(crate) fn hls_rtp(
+        &mut self,
+        now: Instant,
+        endpoint_id: EndpointId,
+        incoming_stream: &IncomingStream,
+        rtp_packet: &rtp::packet::Packet,
+    ) -> bool {
+        let dominant_speaker = self.dominant_speaker.dominant();
+        let (Some(hls), Some(endpoint)) = (self.hls.as_mut(), self.endpoints.get(&endpoint_id))
+        else {
+            return false;
+        };
+        let Some(transceiver) = endpoint.get_transceivers().get(&incoming_stream.mid) else {
+            return false;
+        };
+        match hls.on_rtp(
+            now,
+            endpoint_id,
+            dominant_speaker,
+            transceiver,
+            endpoint.get_simulcast_tracks().get(&incoming_stream.mid),
+            incoming_stream,
+            rtp_packet,
+        ) {
+            Ok(is_keyframe_needed) => is_keyframe_needed,
+            Err(err) => {
+                warn!(
+                    "{}: can't package mid {} of endpoint {} over hls: {}",
+                    self.session_id, incoming_stream.mid, endpoint_id, err
+                );
+                false
+            }
+        }
+    }
+
+    pub(crate) fn get_cascade(&self) -> Option<&SessionCascade> {
+        self.cascade.as_ref()
+    }
+
+    pub(crate) fn get_mut_cascade(&mut self) -> Option<&mut SessionCascade> {
+        self.cascade.as_mut()
+    }
+
+    /// start_cascade relays the tracks of the session with the other workers it spans
+    pub(crate) fn start_cascade(&mut self, cascade: SessionCascade) {
+        self.cascade = Some(cascade);
+    }
+
+    /// take_cascade stops relaying the tracks of the session, the cascade being left to tell the
+    /// other workers
+    pub(crate) fn take_cascade(&mut self) -> Option<SessionCascade> {
+        self.cascade.take()
+    }
+
+    /// is_relayed tells whether the endpoint is connected to another worker, which relays its
+    /// tracks
+    pub(crate) fn is_relayed(&self, endpoint_id: &EndpointId) -> bool {
+        self.cascade
+            .as_ref()
+            .is_some_and(|cascade| cascade.is_relayed(endpoint_id))
+    }
+
+    /// is_idle tells whether the session can be removed, once no endpoint is connected to this
+    /// worker. The home worker keeps the session while other workers host it, for its recording,
+    /// egress and HLS to get every track
+    pub(crate) fn is_idle(&self) -> bool {
+        !self.has_connected_endpoints()
+            && self
+                .cascade
+                .as_ref()
+                .map_or(true, |cascade| !cascade.is_home() || !cascade.has_peers())
+    }
+
+    /// handle_relay_event applies a message of another worker or node hosting the session
+    pub(crate) fn handle_relay_event(
+        &mut self,
+        origin: RelayPeer,
+        event: RelayEvent,
+    ) -> Result<()> {
+        let session_id = self.session_id;
+        let cascade = self.cascade.as_mut().ok_or(Error::Other(format!(
+            "session id {} doesn't span several workers",
+            session_id
+        )))?;
+        match event {
+            RelayEvent::Join => {
+                if cascade.add_peer(origin) {
+                    info!("{}: {} hosts the session too", session_id, origin);
+                    let publications: Vec<(EndpointId, Vec<RTCRtpTransceiver>)> = self
+                        .endpoints
+                        .values()
+                        .map(|endpoint| (endpoint.endpoint_id(), published_transceivers(endpoint)))
+                        .collect();
+                    for (endpoint_id, transceivers) in publications {
+                        cascade.publish_to(origin, endpoint_id, transceivers);
+                    }
+                }
+            }
+            RelayEvent::Leave => {
+                info!("{}: {} no longer hosts the session", session_id, origin);
+                for endpoint_id in cascade.remove_peer(origin) {
+                    self.unpublish_relayed_endpoint(&endpoint_id);
+                }
+            }
+            RelayEvent::Publish {
+                endpoint_id,
+                transceivers,
+            } => self.publish_relayed_endpoint(origin, endpoint_id, transceivers)?,
+            RelayEvent::Unpublish { endpoint_id } => {
+                if cascade.relayed_origin(&endpoint_id) == Some(origin) {
+                    self.unpublish_relayed_endpoint(&endpoint_id);
+                }
+            }
+            RelayEvent::Rtp {
+                endpoint_id,
+                incoming_stream,
+                audio_level,
+                packet,
+            } => {
+                if cascade.relayed_origin(&endpoint_id) != Some(origin) {
+                    return Ok(());
+                }
+                if let Some(endpoint) = self.endpoints.get_mut(&endpoint_id) {
+                    endpoint.bind_incoming_stream(packet.header.ssrc, &incoming_stream);
+                }
+                cascade.relay_rtp(endpoint_id, &incoming_stream, audio_level, &packet);
+                cascade.push_relayed_packet(endpoint_id, incoming_stream, audio_level, packet);
+            }
+            RelayEvent::Rtcp {
+                endpoint_id,
+                payload,
+            } => {
+                if cascade.relayed_origin(&endpoint_id) == Some(origin) {
+                    cascade.relay_rtcp(endpoint_id, payload.clone());
+                    cascade.push_relayed_rtcp(endpoint_id, payload);
+                }
+            }
+            RelayEvent::KeyframeRequest { endpoint_id, ssrcs } => {
+                // requests for an endpoint relayed by another peer are passed on to it
+                if cascade.request_keyframes(endpoint_id, ssrcs.clone()) {
+                    return Ok(());
+                }
+                // sent from the timeout loop, aggregated with the requests of this worker
+                if let Some(endpoint) = self.endpoints.get_mut(&endpoint_id) {
+                    for ssrc in ssrcs {
+                        endpoint.get_mut_keyframe_requests().defer(ssrc);
+                    }
+                }
+            }
+            RelayEvent::Chat { event } => {
+                // sent from the timeout loop to the endpoints connected to this worker
+                if let Some(endpoint_id) = event.sender() {
+                    cascade.relay_chat(Some(origin), &event);
+                    cascade.push_relayed_chat(endpoint_id, event);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// publish_to_cascade relays the tracks published by an endpoint to the other workers and
+    /// nodes hosting the session
+    fn publish_to_cascade(&mut self, endpoint_id: EndpointId) {
+        let (Some(cascade), Some(endpoint)) =
+            (self.cascade.as_mut(), self.endpoints.get(&endpoint_id))
+        else {
+            return;
+        };
+        cascade.publish(endpoint_id, published_transceivers(endpoint));
+    }
+
+    /// publish_relayed_endpoint adds the tracks published by an endpoint of another worker or
+    /// node as a virtual endpoint, or the tracks it publishes on top of the ones relayed before
+    fn publish_relayed_endpoint(
+        &mut self,
+        origin: RelayPeer,
+        endpoint_id: EndpointId,
+        transceivers: Vec<RTCRtpTransceiver>,
+    ) -> Result<()> {
+        let transceivers: Vec<RTCRtpTransceiver> = transceivers
+            .into_iter()
+            .map(|mut transceiver| {
+                transceiver.direction = RTCRtpTransceiverDirection::Recvonly;
+                transceiver.current_direction = RTCRtpTransceiverDirection::Recvonly;
+                transceiver
+            })
+            .collect();
+
+        if !self.has_endpoint(&endpoint_id) {
+            info!(
+                "{}: {} relays endpoint {}",
+                self.session_id, origin, endpoint_id
+            );
+            if let Some(cascade) = self.cascade.as_mut() {
+                cascade.add_relayed_endpoint(endpoint_id, origin);
+            }
+            self.add_virtual_endpoint(endpoint_id, transceivers);
+            return Ok(());
+        }
+        if self
+            .cascade
+            .as_ref()
+            .and_then(|cascade| cascade.relayed_origin(&endpoint_id))
+            != Some(origin)
+        {
+            return Err(Error::Other(format!(
+                "endpoint id {} already exists in session id {}",
+                endpoint_id, self.session_id
+            )));
+        }
+
+        let new_transceivers: Vec<RTCRtpTransceiver> = transceivers
+            .into_iter()
+            .filter(|transceiver| {
+                self.endpoints.get(&endpoint_id).is_some_and(|endpoint| {
+                    !endpoint.get_transceivers().contains_key(&transceiver.mid)
+                })
+            })
+            .collect();
+        self.offer_virtual_transceivers(endpoint_id, &new_transceivers);
+        if let Some(endpoint) = self.endpoints.get_mut(&endpoint_id) {
+            for transceiver in new_transceivers {
+                endpoint.get_mut_mids().push(transceiver.mid.clone());
+                endpoint
+                    .get_mut_transceivers()
+                    .insert(transceiver.mid.clone(), transceiver);
+            }
+        }
+        self.publish_to_cascade(endpoint_id);
+
+        Ok(())
+    }
+
+    /// add_node_link relays the session with the node at the other end of the link
+    pub(crate) fn add_node_link(&mut self, link_id: u64) {
+        if let Some(cascade) = self.cascade.as_mut() {
+            cascade.add_link(link_id);
+        }
+    }
+
+    /// leave_node_links stops relaying the session with other nodes, and returns the links
+    /// which were left
+    pub(crate) fn leave_node_links(&mut self) -> Vec<u64> {
+        let Some(cascade) = self.cascade.as_mut() else {
+            return vec![];
+        };
+        let links = cascade.links();
+        let mut endpoint_ids = vec![];
+        for &link_id in links.iter() {
+            endpoint_ids.extend(cascade.leave_link(link_id));
+        }
+        for endpoint_id in endpoint_ids {
+            self.unpublish_relayed_endpoint(&endpoint_id);
+        }
+        links
+    }
+
+    /// unpublish_relayed_endpoint removes the virtual endpoint of an endpoint of another worker
+    fn unpublish_relayed_endpoint(&mut self, endpoint_id: &EndpointId) {
+        let Some(transceivers) = self
+            .endpoints
+            .get(endpoint_id)
+            .filter(|_| self.is_relayed(endpoint_id))
+            .map(|endpoint| endpoint.get_transceivers().values().cloned().collect())
+        else {
+            return;
+        };
+        info!(
+            "{}: endpoint {} is no longer relayed",
+            self.session_id, endpoint_id
+        );
+        self.remove_virtual_endpoint(endpoint_id, transceivers);
+    }
+
+    /// start_injection adds the virtual endpoint of the injector, whose tracks are forwarded to
+    /// the auto subscribed endpoints like the ones of any publisher
+    pub(crate) fn start_injection(&mut self, injector: MediaInjector) {
+        let endpoint_id = injector.endpoint_id();
+        self.add_virtual_endpoint(endpoint_id, injector.transceivers());
+        self.injectors.insert(endpoint_id, injector);
+    }
+
+    /// stop_injection removes the virtual endpoint of an injector, its tracks being made
+    /// inactive for the endpoints they were forwarded to
+    pub(crate) fn stop_injection(&mut self, endpoint_id: &EndpointId) -> Result<()> {
+        let injector = self
+            .injectors
+            .remove(endpoint_id)
+            .ok_or(Error::Other(format!(
+                "endpoint id {} is not injected",
+                endpoint_id
+            )))?;
+        self.remove_virtual_endpoint(endpoint_id, injector.transceivers());
+
+        Ok(())
+    }
+
+    pub(crate) fn get_plain_ingest(&self, endpoint_id: &EndpointId) -> Option<&PlainIngest> {
+        self.plain_ingests.get(endpoint_id)
+    }
+
+    pub(crate) fn get_mut_plain_ingest(
+        &mut self,
+        endpoint_id: &EndpointId,
+    ) -> Option<&mut PlainIngest> {
+        self.plain_ingests.get_mut(endpoint_id)
+    }
+
+    /// find_plain_ingest returns the endpoint id of the plain RTP source declaring the SSRC,
+    /// among the ones whose address isn't known yet
+    pub(crate) fn find_plain_ingest(&self, ssrc: SSRC) -> Option<EndpointId> {
+        self.plain_ingests
+            .values()
+            .find(|plain_ingest| {
+                plain_ingest.remote_addr().is_none() && plain_ingest.has_ssrc(ssrc)
+            })
+            .map(|plain_ingest| plain_ingest.endpoint_id())
+    }
+
+    /// start_plain_ingest adds the endpoint of a plain RTP source, whose tracks are forwarded
+    /// like the ones of any publisher, and whose transport is added once its address is known
+    pub(crate) fn start_plain_ingest(&mut self, plain_ingest: PlainIngest) {
+        let endpoint_id = plain_ingest.endpoint_id();
+        self.add_virtual_endpoint(endpoint_id, plain_ingest.transceivers());
+        self.plain_ingests.insert(endpoint_id, plain_ingest);
+    }
+
+    /// stop_plain_ingest removes the endpoint of a plain RTP source, along with its transport,
+    /// and returns the four tuple of the latter
+    pub(crate) fn stop_plain_ingest(&mut self, endpoint_id: &EndpointId) -> Result<Vec<FourTuple>> {
+        let plain_ingest = self
+            .plain_ingests
+            .remove(endpoint_id)
+            .ok_or(Error::Other(format!(
+                "endpoint id {} is not a plain ingest",
+                endpoint_id
+            )))?;
+        let four_tuples = self.endpoints.get(endpoint_id).map_or(vec![], |endpoint| {
+            endpoint.get_transports().keys().copied().collect()
+        });
+        self.remove_virtual_endpoint(endpoint_id, plain_ingest.transceivers());
+
+        Ok(four_tuples)
+    }
+
+    /// start_rtmp_ingest adds the virtual endpoint of an RTMP publisher, whose tracks are
+    /// forwarded like the ones of any publisher
+    pub(crate) fn start_rtmp_ingest(&mut self, rtmp_ingest: RtmpIngest) {
+        let endpoint_id = rtmp_ingest.endpoint_id();
+        self.add_virtual_endpoint(endpoint_id, rtmp_ingest.transceivers());
+        self.rtmp_ingests.insert(endpoint_id, rtmp_ingest);
+    }
+
+    /// stop_rtmp_ingest removes the virtual endpoint of an RTMP publisher
+    pub(crate) fn stop_rtmp_ingest(&mut self, endpoint_id: &EndpointId) -> Result<()> {
+        let rtmp_ingest = self
+            .rtmp_ingests
+            .remove(endpoint_id)
+            .ok_or(Error::Other(format!(
+                "endpoint id {} is not an rtmp ingest",
+                endpoint_id
+            )))?;
+        self.remove_virtual_endpoint(endpoint_id, rtmp_ingest.transceivers());
+
+        Ok(())
+    }
+
+    /// push_rtmp_media packetizes a frame of an RTMP publisher, its packets being returned by
+    /// poll_injected_packets
+    pub(crate) fn push_rtmp_media(
+        &mut self,
+        endpoint_id: &EndpointId,
+        media: RtmpMedia,
+    ) -> Result<()> {
+        self.rtmp_ingests
+            .get_mut(endpoint_id)
+            .ok_or(Error::Other(format!(
+                "endpoint id {} is not an rtmp ingest",
+                endpoint_id
+            )))?
+            .push(media);
+
+        Ok(())
+    }
+
+    /// add_virtual_endpoint adds an endpoint publishing the transceivers without negotiating
+    /// them, and offers its tracks to the auto subscribed endpoints
+    fn add_virtual_endpoint(
+        &mut self,
+        endpoint_id: EndpointId,
+        transceivers: Vec<RTCRtpTransceiver>,
+    ) {
+        self.offer_virtual_transceivers(endpoint_id, &transceivers);
+
+        let registry = self.session_config.server_config.media_config.registry();
+        let mut endpoint = Endpoint::new(endpoint_id, registry.build(""));
+        endpoint.set_auto_subscribed(false);
+        for transceiver in transceivers {
+            endpoint.get_mut_mids().push(transceiver.mid.clone());
+            endpoint
+                .get_mut_transceivers()
+                .insert(transceiver.mid.clone(), transceiver);
+        }
+        if !self.is_relayed(&endpoint_id) {
+            self.events.push(ServerEvent::EndpointJoined {
+                session_id: self.session_id,
+                endpoint_id,
+            });
+            for transceiver in published_transceivers(&endpoint) {
+                self.events.push(ServerEvent::TrackPublished {
+                    session_id: self.session_id,
+                    endpoint_id,
+                    mid: transceiver.mid,
+                    kind: transceiver.kind.to_string(),
+                });
+            }
+        }
+        self.endpoints.insert(endpoint_id, endpoint);
+        self.joined_endpoint_ids.push(endpoint_id);
+        self.update_forwarded_videos();
+        self.publish_to_cascade(endpoint_id);
+    }
+
+    /// offer_virtual_transceivers adds the transceivers of a virtual endpoint to the auto
+    /// subscribed endpoints, which are offered them from the timeout loop
+    fn offer_virtual_transceivers(
+        &mut self,
+        endpoint_id: EndpointId,
+        transceivers: &[RTCRtpTransceiver],
+    ) {
+        for (&other_endpoint_id, other_endpoint) in self.endpoints.iter_mut() {
+            // endpoints still connecting get the tracks once their data channel opens
+            if !other_endpoint.is_auto_subscribed() || !other_endpoint.is_datachannel_ready() {
+                continue;
+            }
+            for transceiver in transceivers {
+                let other_mid_value = format!("{}-{}", endpoint_id, transceiver.mid);
+                if other_endpoint.is_unsubscribed(&other_mid_value) {
+                    continue;
+                }
+                let (other_mids, other_transceivers) =
+                    other_endpoint.get_mut_mids_and_transceivers();
+                let mut other_transceiver = transceiver.clone();
+                other_transceiver.mid = other_mid_value.clone();
+                other_transceiver.direction = RTCRtpTransceiverDirection::Sendonly;
+                other_transceiver.current_direction = RTCRtpTransceiverDirection::Unspecified;
+                other_transceiver.align_header_extension_ids(other_transceivers.values());
+                if !other_transceivers.contains_key(&other_mid_value) {
+                    other_mids.push(other_mid_value.clone());
+                }
+                other_transceivers.insert(other_mid_value, other_transceiver);
+                other_endpoint.set_renegotiation_needed(true);
+                self.pending_offers.insert(other_endpoint_id);
+            }
+        }
+    }
+
+    /// remove_virtual_endpoint removes an endpoint added by add_virtual_endpoint, its tracks
+    /// being made inactive for the endpoints they were forwarded to
+    fn remove_virtual_endpoint(
+        &mut self,
+        endpoint_id: &EndpointId,
+        transceivers: Vec<RTCRtpTransceiver>,
+    ) {
+        for transceiver in transceivers {
+            let other_mid_value = format!("{}-{}", endpoint_id, transceiver.mid);
+            for (&other_endpoint_id, other_endpoint) in self.endpoints.iter_mut() {
+                let Some(other_transceiver) = other_endpoint
+                    .get_mut_transceivers()
+                    .get_mut(&other_mid_value)
+                else {
+                    continue;
+                };
+                if other_transceiver.direction != RTCRtpTransceiverDirection::Inactive {
+                    other_transceiver.direction = RTCRtpTransceiverDirection::Inactive;
+                    other_endpoint.set_renegotiation_needed(true);
+                    self.pending_offers.insert(other_endpoint_id);
+                }
+            }
+        }
+        self.remove_endpoint(endpoint_id);
+    }
+
+    /// poll_injection_timeout returns when the next injected frame is due
+    pub(crate) fn poll_injection_timeout(&self) -> Option<Instant> {
+        self.injectors
+            .values()
+            .filter_map(|injector| injector.poll_timeout())
+            .min()
+    }
+
+    /// poll_injected_packets returns the injected packets due by now and the packets of the
+    /// frames RTMP publishers sent, with the endpoint id, mid and kind they are published with,
+    /// and stops the injectors whose files ended
+    pub(crate) fn poll_injected_packets(
+        &mut self,
+        now: Instant,
+    ) -> Vec<(EndpointId, Mid, RTPCodecType, rtp::packet::Packet)> {
+        let mut packets = vec![];
+        let mut finished_endpoint_ids = vec![];
+        for (&endpoint_id, injector) in self.injectors.iter_mut() {
+            packets.extend(
+                injector
+                    .poll_packets(now)
+                    .into_iter()
+                    .map(|(mid, kind, packet)| (endpoint_id, mid, kind, packet)),
+            );
+            if injector.is_finished() {
+                finished_endpoint_ids.push(endpoint_id);
+            }
+        }
+        for (&endpoint_id, rtmp_ingest) in self.rtmp_ingests.iter_mut() {
+            packets.extend(
+                rtmp_ingest
+                    .poll_packets()
+                    .into_iter()
+                    .map(|(mid, kind, packet)| (endpoint_id, mid, kind, packet)),
+            );
+        }
+        for endpoint_id in finished_endpoint_ids {
+            info!(
+                "{}: injection as endpoint {} reached the end of its files",
+                self.session_id, endpoint_id
+            );
+            let _ = self.stop_injection(&endpoint_id);
+        }
+        packets
+    }
+
+    /// take_pending_offers returns the endpoints to send an offer to
+    pub(crate) fn take_pending_offers(&mut self) -> Vec<EndpointId> {
+        self.pending_offers.drain().collect()
+    }
+
+    /// subscribe forwards the track published by the publisher with the mid to the subscriber,
+    /// which is offered it from the timeout loop
+    pub(crate) fn subscribe(
+        &mut self,
+        subscriber_id: EndpointId,
+        publisher_id: EndpointId,
+        mid: &Mid,
+    ) -> Result<()> {
+        if subscriber_id == publisher_id {
+            return Err(Error::Other(format!(
+                "endpoint id {} can't subscribe to its own track {}",
+                subscriber_id, mid
+            )));
+        }
+        let publisher = self
+            .get_endpoint(&publisher_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                publisher_id
+            )))?;
+        let mut transceiver = publisher
+            .get_transceivers()
+            .get(mid)
+            .filter(|transceiver| {
+                transceiver.direction == RTCRtpTransceiverDirection::Recvonly
+                    && !publisher.is_stopped(mid)
+            })
+            .ok_or(Error::Other(format!(
+                "{} is not a track published by endpoint id {}",
+                mid, publisher_id
+            )))?
+            .clone();
+
+        let subscriber = self
+            .get_mut_endpoint(&subscriber_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                subscriber_id
+            )))?;
+        let forwarded_mid = format!("{}-{}", publisher_id, mid);
+        subscriber.set_auto_subscribed(false);
+        subscriber
+            .get_mut_unsubscribed_mids()
+            .remove(&forwarded_mid);
+
+        let (mids, transceivers) = subscriber.get_mut_mids_and_transceivers();
+        if let Some(forwarded_transceiver) = transceivers.get_mut(&forwarded_mid) {
+            if forwarded_transceiver.direction.has_send() {
+                return Ok(());
+            }
+            forwarded_transceiver.direction = RTCRtpTransceiverDirection::Sendonly;
+        } else {
+            transceiver.mid = forwarded_mid.clone();
+            transceiver.direction = RTCRtpTransceiverDirection::Sendonly;
+            transceiver.current_direction = RTCRtpTransceiverDirection::Unspecified;
+            transceiver.align_header_extension_ids(transceivers.values());
+            mids.push(forwarded_mid.clone());
+            transceivers.insert(forwarded_mid, transceiver);
+        }
+        subscriber.set_renegotiation_needed(true);
+        self.pending_offers.insert(subscriber_id);
+
+        Ok(())
+    }
+
+    /// unsubscribe stops forwarding the track published by the publisher with the mid to the
+    /// subscriber, which is offered the change from the timeout loop
+    pub(crate) fn unsubscribe(
+        &mut self,
+        subscriber_id: EndpointId,
+        publisher_id: EndpointId,
+        mid: &Mid,
+    ) -> Result<()> {
+        let subscriber = self
+            .get_mut_endpoint(&subscriber_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                subscriber_id
+            )))?;
+        let forwarded_mid = format!("{}-{}", publisher_id, mid);
+        subscriber.set_auto_subscribed(false);
+        subscriber
+            .get_mut_unsubscribed_mids()
+            .insert(forwarded_mid.clone());
+
+        // the transceiver is kept inactive, since m-lines can't be removed from the SDP
+        let Some(forwarded_transceiver) = subscriber.get_mut_transceivers().get_mut(&forwarded_mid)
+        else {
+            return Ok(());
+        };
+        if forwarded_transceiver.direction == RTCRtpTransceiverDirection::Inactive {
+            return Ok(());
+        }
+        forwarded_transceiver.direction = RTCRtpTransceiverDirection::Inactive;
+        subscriber.set_renegotiation_needed(true);
+        self.pending_offers.insert(subscriber_id);
+
+        Ok(())
+    }
+
+    /// is_video_forwarded tells whether the publisher's video is to be forwarded to the
+    /// subscriber, which only receives the video of the last N dominant speakers and of the
+    /// endpoints it pins, unless its bandwidth pauses any video
+    pub(crate) fn is_video_forwarded(
+        &self,
+        publisher_id: &EndpointId,
+        subscriber_id: &EndpointId,
+    ) -> bool {
+        let Some(subscriber) = self.endpoints.get(subscriber_id) else {
+            return false;
+        };
+        if subscriber.is_video_paused() {
+            return false;
+        }
+        if self.session_config.server_config.last_n.is_none() {
+            return true;
+        }
+        self.forwarded_videos
+            .get(subscriber_id)
+            .is_some_and(|publisher_ids| publisher_ids.contains(publisher_id))
+    }
+
+    /// update_forwarded_videos computes again the publishers whose video is forwarded to every
+    /// subscriber, the last N dominant speakers completed by the first endpoints to join, plus
+    /// the endpoints it pins
+    fn update_forwarded_videos(&mut self) {
+        let Some(last_n) = self.session_config.server_config.last_n else {
+            return;
+        };
+
+        let recent_speakers = self.dominant_speaker.recent_speakers();
+        let speakers: Vec<EndpointId> = recent_speakers
+            .iter()
+            .chain(
+                self.joined_endpoint_ids
+                    .iter()
+                    .filter(|endpoint_id| !recent_speakers.contains(endpoint_id)),
+            )
+            .copied()
+            .collect();
+        self.forwarded_videos = self
+            .endpoints
+            .iter()
+            .map(|(&subscriber_id, subscriber)| {
+                let publisher_ids = speakers
+                    .iter()
+                    .filter(|&&endpoint_id| endpoint_id != subscriber_id)
+                    .take(last_n)
+                    .chain(subscriber.get_pinned_endpoints())
+                    .copied()
+                    .collect();
+                (subscriber_id, publisher_ids)
+            })
+            .collect();
+    }
+
+    /// resolve_forwarded_stream maps the SSRC of a stream forwarded to the endpoint to the
+    /// publisher's endpoint id and the SSRC it is received on, the one of the target layer for
+    /// simulcast tracks
+    pub(crate) fn resolve_forwarded_stream(
+        &self,
+        endpoint_id: &EndpointId,
+        ssrc: SSRC,
+    ) -> Option<(EndpointId, SSRC)> {
+        let endpoint = self.get_endpoint(endpoint_id)?;
+        let transceiver = endpoint.get_transceivers().values().find(|transceiver| {
+            transceiver.direction.has_send()
+                && transceiver
+                    .sender
+                    .as_ref()
+                    .is_some_and(|sender| sender.ssrcs.contains(&ssrc))
+        })?;
+
+        let (publisher_id, publisher_mid) = transceiver.mid.split_once('-')?;
+        let publisher_id = publisher_id.parse::<EndpointId>().ok()?;
+        let rid = if transceiver.is_simulcast() {
+            let forwarder = endpoint.get_simulcast_forwarders().get(&transceiver.mid)?;
+            Some(forwarder.target_rid().or(forwarder.current_rid())?)
+        } else {
+            None
+        };
+
+        let publisher_ssrc = self
+            .get_endpoint(&publisher_id)?
+            .incoming_ssrc(publisher_mid, rid)?;
+        Some((publisher_id, publisher_ssrc))
+    }
+
+    /// resolve_simulcast_nack maps the sequence numbers missed by the endpoint on the simulcast
+    /// track it receives with the SSRC back to the SSRC and sequence numbers of the layer
+    /// published, the packets forwarded before the last layer switch being left out. Returns
+    /// None when the SSRC isn't a simulcast track forwarded to the endpoint, or no layer is.
+    pub(crate) fn resolve_simulcast_nack(
+        &self,
+        endpoint_id: &EndpointId,
+        ssrc: SSRC,
+        sequence_numbers: &[u16],
+    ) -> Option<(SSRC, Vec<u16>)> {
+        let endpoint = self.get_endpoint(endpoint_id)?;
+        let transceiver = endpoint.get_transceivers().values().find(|transceiver| {
+            transceiver.is_simulcast()
+                && transceiver
+                    .sender
+                    .as_ref()
+                    .is_some_and(|sender| sender.ssrcs.contains(&ssrc))
+        })?;
+
+        let (publisher_id, publisher_mid) = transceiver.mid.split_once('-')?;
+        let publisher_id = publisher_id.parse::<EndpointId>().ok()?;
+        let forwarder = endpoint.get_simulcast_forwarders().get(&transceiver.mid)?;
+        let publisher_ssrc = self
+            .get_endpoint(&publisher_id)?
+            .incoming_ssrc(publisher_mid, Some(forwarder.current_rid()?))?;
+        let published_sequence_numbers = sequence_numbers
+            .iter()
+            .filter_map(|&sequence_number| forwarder.published_sequence_number(sequence_number))
+            .collect();
+        Some((publisher_ssrc, published_sequence_numbers))
+    }
+
+    pub(crate) fn set_remote_description(
+        &mut self,
+        endpoint_id: EndpointId,
+        remote_description: &RTCSessionDescription,
+    ) -> Result<()> {
+        if !self.has_endpoint(&endpoint_id) {
+            return Err(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )));
+        }
+        let parsed = remote_description
+            .parsed
+            .as_ref()
+            .ok_or(Error::Other("Unparsed remote description".to_string()))?;
+
+        let we_offer = remote_description.sdp_type == RTCSdpType::Answer;
+
+        for media in &parsed.media_descriptions {
+            if media.media_name.media == MEDIA_SECTION_APPLICATION {
+                continue;
+            }
+
+            let kind = RTPCodecType::from(media.media_name.media.as_str());
+            let direction = get_peer_direction(media);
+            if kind == RTPCodecType::Unspecified
+                || direction == RTCRtpTransceiverDirection::Unspecified
+            {
+                continue;
+            }
+
+            let mid_value = match get_mid_value(media) {
+                Some(mid) => {
+                    if mid.is_empty() {
+                        return Err(Error::Other(
+                            "ErrPeerConnRemoteDescriptionWithoutMidValue".to_string(),
+                        ));
+                    } else {
+                        mid
+                    }
+                }
+                None => continue,
+            };
+
+            if !we_offer {
+                // This is an offer from the remote.
+                let has_mid_value = self
+                    .endpoints
+                    .get(&endpoint_id)
+                    .unwrap()
+                    .get_transceivers()
+                    .contains_key(mid_value);
+
+                if !has_mid_value {
+                    let cname = get_cname(media);
+                    let msid = get_msid(media);
+                    let ssrc_groups = get_ssrc_groups(media)?;
+                    let ssrcs = get_ssrcs(media)?;
+                    let codecs = codecs_from_media_description(media)?;
+                    let header_extensions = rtp_extensions_from_media_description(media)?;
+                    let rtp_params = RTCRtpParameters {
+                        header_extensions,
+                        codecs,
+                    };
+
+                    let local_direction = if direction == RTCRtpTransceiverDirection::Recvonly {
+                        RTCRtpTransceiverDirection::Sendonly
+                    } else {
+                        RTCRtpTransceiverDirection::Recvonly
+                    };
+
+                    let mut rids: Vec<String> = get_rids(media).into_keys().collect();
+                    rids.sort();
+
+                    let sender = if !rids.is_empty() {
+                        // simulcast layers are forwarded as a single stream with its own SSRC,
+                        // and browsers don't announce any SSRC for rid based simulcast
+                        let ssrcs = vec![rand::random::<u32>(), rand::random::<u32>()];
+                        Some(RTCRtpSender {
+                            cname: cname.unwrap_or_else(|| format!("{}", endpoint_id)),
+                            msid: msid.unwrap_or_else(|| MediaStreamId {
+                                stream_id: format!("{}", endpoint_id),
+                                track_id: format!("{}-{}", endpoint_id, mid_value),
+                            }),
+                            // with its retransmissions sent by the server on a second SSRC
+                            ssrc_groups: vec![SsrcGroup {
+                                name: SSRC_GROUP_FID.to_string(),
+                                ssrcs: ssrcs.clone(),
+                            }],
+                            ssrcs,
+                        })
+                    } else if let (Some(cname), Some(msid)) = (cname, msid) {
+                        Some(RTCRtpSender {
+                            cname,
+                            msid,
+                            ssrcs,
+                            ssrc_groups,
+                        })
+                    } else {
+                        None
+                    };
+
+                    let transceiver = RTCRtpTransceiver {
+                        mid: mid_value.to_string(),
+                        sender: sender.clone(),
+                        direction: local_direction,
+                        current_direction: RTCRtpTransceiverDirection::Unspecified,
+                        rtp_params: rtp_params.clone(),
+                        kind,
+                        rids: rids.clone(),
+                    };
+
+                    {
+                        let endpoint =
+                            self.get_mut_endpoint(&endpoint_id)
+                                .ok_or(Error::Other(format!(
+                                    "can't find endpoint id {}",
+                                    endpoint_id
+                                )))?;
+                        endpoint.get_mut_mids().push(mid_value.to_string());
+                        endpoint
+                            .get_mut_transceivers()
+                            .insert(mid_value.to_string(), transceiver.clone());
+                    }
+                    if local_direction == RTCRtpTransceiverDirection::Recvonly {
+                        self.events.push(ServerEvent::TrackPublished {
+                            session_id: self.session_id,
+                            endpoint_id,
+                            mid: mid_value.to_string(),
+                            kind: kind.to_string(),
+                        });
+                    }
+
+                    // add it to other endpoints' transceivers as send only
+                    self.forward_to_other_endpoints(endpoint_id, &transceiver, direction);
+                } else if let Some(transceiver) = self
+                    .endpoints
+                    .get(&endpoint_id)
+                    .and_then(|endpoint| endpoint.get_transceivers().get(mid_value))
+                    .filter(|transceiver| {
+                        transceiver.direction == RTCRtpTransceiverDirection::Recvonly
+                    })
+                    .cloned()
+                {
+                    // a track published before, which the endpoint may stop or resume sending
+                    // without removing it from its SDP
+                    self.update_publication(endpoint_id, transceiver, direction);
+                }
+            } else {
+                // This is an answer from the remote.
+                let endpoint = self
+                    .get_mut_endpoint(&endpoint_id)
+                    .ok_or(Error::Other(format!(
+                        "can't find endpoint id {}",
+                        endpoint_id
+                    )))?;
+                if let Some(transceiver) = endpoint.get_mut_transceivers().get_mut(mid_value) {
+                    //let previous_direction = transceiver.current_direction();
+
+                    // 4.5.9.2.9
+                    // Let direction be an RTCRtpTransceiverDirection value representing the direction
+                    // from the media description, but with the send and receive directions reversed to
+                    // represent this peer's point of view. If the media description is rejected,
+                    // set direction to "inactive".
+                    let reversed_direction = direction.reverse();
+
+                    // 4.5.9.2.13.2
+                    // Set transceiver.[[CurrentDirection]] and transceiver.[[Direction]]s to direction.
+                    transceiver.set_current_direction(reversed_direction);
+                }
+            }
+        }
+        if !we_offer {
+            // the media sections keep the order of the remote offer, the transceivers it lacks,
+            // such as the ones of an offer it crossed, coming after them in the next offer
+            let offered_mids: Vec<&String> = parsed
+                .media_descriptions
+                .iter()
+                .filter_map(get_mid_value)
+                .collect();
+            let endpoint = self
+                .get_mut_endpoint(&endpoint_id)
+                .ok_or(Error::Other(format!(
+                    "can't find endpoint id {}",
+                    endpoint_id
+                )))?;
+            endpoint.get_mut_mids().sort_by_key(|mid| {
+                offered_mids
+                    .iter()
+                    .position(|offered_mid| *offered_mid == mid)
+                    .unwrap_or(offered_mids.len())
+            });
+        }
+        self.publish_to_cascade(endpoint_id);
+
+        Ok(())
+    }
+
+    /// rollback_offer gives up the offer sent to the endpoint when its own offer crosses it, the
+    /// server being the polite peer of the negotiation. The endpoint is offered again once the
+    /// server answered it
+    pub(crate) fn rollback_offer(&mut self, endpoint_id: EndpointId) {
+        let session_id = self.session_id;
+        if let Some(endpoint) = self.get_mut_endpoint(&endpoint_id) {
+            if endpoint.is_offer_pending() {
+                info!(
+                    "{}: offer of endpoint {} crossed the one sent to it, which is given up",
+                    session_id, endpoint_id
+                );
+                endpoint.set_offer_pending(false);
+                endpoint.set_renegotiation_needed(true);
+            }
+        }
+    }
+
+    /// forward_to_other_endpoints adds a track published by the endpoint to the transceivers of
+    /// the other endpoints, or updates the direction of the ones they have, unless they
+    /// unsubscribed from it. The track is sent to them while the endpoint sends it, whether it
+    /// also receives on the same transceiver or not, and is inactive otherwise
+    fn forward_to_other_endpoints(
+        &mut self,
+        endpoint_id: EndpointId,
+        transceiver: &RTCRtpTransceiver,
+        direction: RTCRtpTransceiverDirection,
+    ) {
+        let direction = match direction {
+            RTCRtpTransceiverDirection::Sendonly | RTCRtpTransceiverDirection::Sendrecv => {
+                RTCRtpTransceiverDirection::Sendonly
+            }
+            _ => RTCRtpTransceiverDirection::Inactive,
+        };
+        let other_mid_value = format!("{}-{}", endpoint_id, transceiver.mid);
+        for (&other_endpoint_id, other_endpoint) in self.endpoints.iter_mut() {
+            if other_endpoint_id == endpoint_id {
+                continue;
+            }
+            let is_unsubscribed = other_endpoint.is_unsubscribed(&other_mid_value);
+            let is_auto_subscribed = other_endpoint.is_auto_subscribed();
+            let (other_mids, other_transceivers) = other_endpoint.get_mut_mids_and_transceivers();
+            if let Some(other_transceiver) = other_transceivers.get_mut(&other_mid_value) {
+                if other_transceiver.direction != direction && !is_unsubscribed {
+                    other_transceiver.direction = direction;
+                    other_endpoint.set_renegotiation_needed(true);
+                }
+            } else if direction == RTCRtpTransceiverDirection::Sendonly && is_auto_subscribed {
+                let mut other_transceiver = transceiver.clone();
+                other_transceiver.mid = other_mid_value.clone();
+                other_transceiver.direction = direction;
+                other_transceiver.current_direction = RTCRtpTransceiverDirection::Unspecified;
+                other_transceiver.align_header_extension_ids(other_transceivers.values());
+
+                other_mids.push(other_mid_value.clone());
+                other_transceivers.insert(other_mid_value.clone(), other_transceiver);
+                other_endpoint.set_renegotiation_needed(true);
+            }
+        }
+    }
+
+    /// update_publication stops forwarding a track the endpoint no longer sends, its transceiver
+    /// becoming inactive for the other endpoints, and forwards it again once it resumes
+    fn update_publication(
+        &mut self,
+        endpoint_id: EndpointId,
+        transceiver: RTCRtpTransceiver,
+        direction: RTCRtpTransceiverDirection,
+    ) {
+        let is_stopped = !matches!(
+            direction,
+            RTCRtpTransceiverDirection::Sendonly | RTCRtpTransceiverDirection::Sendrecv
+        );
+        let Some(endpoint) = self.endpoints.get_mut(&endpoint_id) else {
+            return;
+        };
+        if !endpoint.set_stopped(&transceiver.mid, is_stopped) {
+            return;
+        }
+
+        let (session_id, mid, kind) = (
+            self.session_id,
+            transceiver.mid.clone(),
+            transceiver.kind.to_string(),
+        );
+        if is_stopped {
+            info!(
+                "{}: endpoint {} stops publishing mid {}",
+                session_id, endpoint_id, mid
+            );
+            self.events.push(ServerEvent::TrackUnpublished {
+                session_id,
+                endpoint_id,
+                mid,
+                kind,
+            });
+            self.forward_to_other_endpoints(
+                endpoint_id,
+                &transceiver,
+                RTCRtpTransceiverDirection::Inactive,
+            );
+        } else {
+            info!(
+                "{}: endpoint {} resumes publishing mid {}",
+                session_id, endpoint_id, mid
+            );
+            self.events.push(ServerEvent::TrackPublished {
+                session_id,
+                endpoint_id,
+                mid,
+                kind,
+            });
+            self.forward_to_other_endpoints(endpoint_id, &transceiver, direction);
+        }
+    }
+
+    pub(crate) fn set_local_description(
+        &mut self,
+        endpoint_id: EndpointId,
+        local_description: &RTCSessionDescription,
+    ) -> Result<()> {
+        let parsed = local_description
+            .parsed
+            .as_ref()
+            .ok_or(Error::Other("Unparsed local description".to_string()))?;
+        let endpoint = self
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+
+        let transceivers = endpoint.get_mut_transceivers();
+        let we_answer = local_description.sdp_type == RTCSdpType::Answer;
+        if we_answer {
+            for media in &parsed.media_descriptions {
+                if media.media_name.media == MEDIA_SECTION_APPLICATION {
+                    continue;
+                }
+
+                let kind = RTPCodecType::from(media.media_name.media.as_str());
+                let direction = get_peer_direction(media);
+                if kind == RTPCodecType::Unspecified
+                    || direction == RTCRtpTransceiverDirection::Unspecified
+                {
+                    continue;
+                }
+
+                let mid_value = match get_mid_value(media) {
+                    Some(mid) => {
+                        if mid.is_empty() {
+                            return Err(Error::Other(
+                                "ErrPeerConnRemoteDescriptionWithoutMidValue".to_string(),
+                            ));
+                        } else {
+                            mid
+                        }
+                    }
+                    _ => continue,
+                };
+
+                if let Some(transceiver) = transceivers.get_mut(mid_value) {
+                    //let previous_direction = transceiver.current_direction();
+                    // 4.9.1.7.3 applying a local answer or pranswer
+                    // Set transceiver.[[CurrentDirection]] and transceiver.[[FiredDirection]] to direction.
+                    transceiver.set_current_direction(direction);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn create_offer(
+        &self,
+        endpoint_id: EndpointId,
+        remote_description: &RTCSessionDescription,
+        local_ice_params: &RTCIceParameters,
+    ) -> Result<RTCSessionDescription> {
+        let use_identity = false; //TODO: self.config.idp_login_url.is_some();
+
+        let mut d = self.generate_matched_sdp(
+            endpoint_id,
+            remote_description,
+            local_ice_params,
+            use_identity,
+            true, /*includeUnmatched */
+            DEFAULT_DTLS_ROLE_OFFER.to_connection_role(),
+        )?;
+
+        let mut sdp_origin = Origin::default();
+        update_sdp_origin(&mut sdp_origin, &mut d);
+
+        let sdp = d.marshal();
+
+        let offer = RTCSessionDescription {
+            sdp_type: RTCSdpType::Offer,
+            sdp,
+            parsed: Some(d),
+        };
+
+        Ok(offer)
+    }
+
+    pub(crate) fn create_answer(
+        &self,
+        endpoint: EndpointId,
+        remote_description: &RTCSessionDescription,
+        local_ice_params: &RTCIceParameters,
+    ) -> Result<RTCSessionDescription> {
+        let use_identity = false; //TODO: self.config.idp_login_url.is_some();
+        let mut d = self.generate_matched_sdp(
+            endpoint,
+            remote_description,
+            local_ice_params,
+            use_identity,
+            false, /*includeUnmatched */
+            DTLSRole::Server.to_connection_role(),
+        )?;
+
+        let mut sdp_origin = Origin::default();
+        update_sdp_origin(&mut sdp_origin, &mut d);
+
+        let sdp = d.marshal();
+
+        let answer = RTCSessionDescription {
+            sdp_type: RTCSdpType::Answer,
+            sdp,
+            parsed: Some(d),
+        };
+
+        Ok(answer)
+    }
+
+    /// generate_matched_sdp generates a SDP and takes the remote state into account
+    /// this is used everytime we have a remote_description
+    pub(crate) fn generate_matched_sdp(
+        &self,
+        endpoint_id: EndpointId,
+        remote_description: &RTCSessionDescription,
+        local_ice_params: &RTCIceParameters,
+        use_identity: bool,
+        include_unmatched: bool,
+        connection_role: ConnectionRole,
+    ) -> Result<SessionDescription> {
+        let d = SessionDescription::new_jsep_session_description(use_identity);
+        let (empty_mids, empty_transceivers) = (vec![], HashMap::new());
+
+        let media_sections = {
+            let (mids, transceivers) = if let Some(endpoint) = self.get_endpoint(&endpoint_id) {
+                (endpoint.get_mids(), endpoint.get_transceivers())
+            } else {
+                (&empty_mids, &empty_transceivers)
+            };
+
+            let mut media_sections = vec![];
+            let mut already_have_application_media_section = false;
+            let mut matched: HashSet<Mid> = HashSet::new();
+            if let Some(parsed) = remote_description.parsed.as_ref() {
+                for media in &parsed.media_descriptions {
+                    if let Some(mid_value) = get_mid_value(media) {
+                        if mid_value.is_empty() {
+                            return Err(Error::Other(
+                                "ErrPeerConnRemoteDescriptionWithoutMidValue".to_string(),
+                            ));
+                        }
+
+                        if media.media_name.media == MEDIA_SECTION_APPLICATION {
+                            media_sections.push(MediaSection {
+                                mid: mid_value.to_owned(),
+                                data: true,
+                                ..Default::default()
+                            });
+                            already_have_application_media_section = true;
+                            continue;
+                        }
+
+                        let kind = RTPCodecType::from(media.media_name.media.as_str());
+                        let direction = get_peer_direction(media);
+                        if kind == RTPCodecType::Unspecified
+                            || direction == RTCRtpTransceiverDirection::Unspecified
+                        {
+                            continue;
+                        }
+
+                        if transceivers.contains_key(mid_value) {
+                            media_sections.push(MediaSection {
+                                mid: mid_value.to_owned(),
+                                rid_map: get_rids(media),
+                                offered_direction: (!include_unmatched).then_some(direction),
+                                ..Default::default()
+                            });
+                            matched.insert(mid_value.to_string());
+                        } else {
+                            return Err(Error::Other("ErrPeerConnTransceiverMidNil".to_string()));
+                        }
+                    }
+                }
+            }
+
+            // If we are offering also include unmatched local transceivers
+            if include_unmatched {
+                for mid in mids.iter() {
+                    if !matched.contains::<Mid>(mid) {
+                        media_sections.push(MediaSection {
+                            mid: mid.clone(),
+                            ..Default::default()
+                        });
+                    }
+                }
+
+                if !already_have_application_media_section {
+                    media_sections.push(MediaSection {
+                        mid: format!("{}", media_sections.len()),
+                        data: true,
+                        ..Default::default()
+                    });
+                }
+            }
+
+            media_sections
+        };
+
+        let dtls_fingerprints =
+            if let Some(cert) = self.session_config.server_config.certificates.first() {
+                cert.get_fingerprints()
+            } else {
+                return Err(Error::Other("ErrNonCertificate".to_string()));
+            };
+
+        let transceivers = if let Some(endpoint) = self.get_endpoint(&endpoint_id) {
+            endpoint.get_transceivers()
+        } else {
+            &empty_transceivers
+        };
+
+        populate_sdp(
+            d,
+            &dtls_fingerprints,
+            &self.session_config,
+            local_ice_params,
+            connection_role,
+            &media_sections,
+            transceivers,
+            true,
+        )
+    }
+}
+
+/// published_transceivers returns the transceivers of the tracks the endpoint publishes, in
+/// the order they were negotiated
+fn published_transceivers(endpoint: &Endpoint) -> Vec<RTCRtpTransceiver> {
+    endpoint
+        .get_mids()
+        .iter()
+        .filter_map(|mid| endpoint.get_transceivers().get(mid))
+        .filter(|transceiver| transceiver.direction == RTCRtpTransceiverDirection::Recvonly)
+        .filter(|transceiver| !endpoint.is_stopped(&transceiver.mid))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::config::ServerConfig;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn session(last_n: Option<usize>, endpoint_ids: &[EndpointId]) -> Session {
+        let mut server_config = ServerConfig::new(vec![]);
+        if let Some(last_n) = last_n {
+            server_config = server_config.with_last_n(last_n);
+        }
+        let server_config = Arc::new(server_config);
+        let local_addr = "127.0.0.1:3478".parse().unwrap();
+        let mut session = Session::new(
+            SessionConfig::new(server_config, local_addr, vec![], vec![]),
+            1,
+        );
+        for &endpoint_id in endpoint_ids {
+            session.add_virtual_endpoint(endpoint_id, vec![]);
+        }
+        session
+    }
+
+    fn forwarded_videos(session: &Session, subscriber_id: EndpointId) -> Vec<EndpointId> {
+        let mut publisher_ids: Vec<EndpointId> = session
+            .get_endpoints()
+            .keys()
+            .filter(|&&publisher_id| publisher_id != subscriber_id)
+            .filter(|publisher_id| session.is_video_forwarded(publisher_id, &subscriber_id))
+            .copied()
+            .collect();
+        publisher_ids.sort();
+        publisher_ids
+    }
+
+    fn speak(session: &mut Session, endpoint_id: EndpointId, now: Instant) -> Instant {
+        let mut now = now;
+        for _ in 0..100 {
+            now += Duration::from_millis(20);
+            if session.on_audio_level(endpoint_id, 10, now) == Some(endpoint_id) {
+                return now;
+            }
+        }
+        panic!(
+            "endpoint {} didn't become the dominant speaker",
+            endpoint_id
+        );
+    }
+
+    #[test]
+    fn forwards_the_first_endpoints_to_join_until_someone_speaks() {
+        let session = session(Some(1), &[1, 2, 3]);
+        assert_eq!(forwarded_videos(&session, 1), vec![2]);
+        assert_eq!(forwarded_videos(&session, 2), vec![1]);
+        assert_eq!(forwarded_videos(&session, 3), vec![1]);
+    }
+
+    #[test]
+    fn forwards_the_last_dominant_speakers() {
+        let mut session = session(Some(1), &[1, 2, 3]);
+        let now = speak(&mut session, 3, Instant::now());
+        assert_eq!(forwarded_videos(&session, 1), vec![3]);
+        assert_eq!(forwarded_videos(&session, 2), vec![3]);
+        assert_eq!(forwarded_videos(&session, 3), vec![1]);
+
+        speak(&mut session, 2, now + Duration::from_secs(2));
+        assert_eq!(forwarded_videos(&session, 1), vec![2]);
+        assert_eq!(forwarded_videos(&session, 3), vec![2]);
+        assert_eq!(forwarded_videos(&session, 2), vec![3]);
+    }
+
+    #[test]
+    fn forwards_pinned_endpoints() {
+        let mut session = session(Some(1), &[1, 2, 3]);
+        session
+            .set_pinned_endpoints(&1, [3].into_iter().collect())
+            .unwrap();
+        assert_eq!(forwarded_videos(&session, 1), vec![2, 3]);
+        assert_eq!(forwarded_videos(&session, 2), vec![1]);
+        assert!(session.set_pinned_endpoints(&4, HashSet::new()).is_err());
+    }
+
+    #[test]
+    fn updates_forwarded_videos_when_endpoints_leave() {
+        let mut session = session(Some(1), &[1, 2, 3]);
+        speak(&mut session, 2, Instant::now());
+        session.remove_endpoint(&2);
+        assert_eq!(forwarded_videos(&session, 1), vec![3]);
+        assert_eq!(forwarded_videos(&session, 3), vec![1]);
+
+        session.add_virtual_endpoint(4, vec![]);
+        assert_eq!(forwarded_videos(&session, 4), vec![1]);
+    }
+
+    #[test]
+    fn forwards_every_video_without_last_n() {
+        let session = session(None, &[1, 2, 3]);
+        assert_eq!(forwarded_videos(&session, 1), vec![2, 3]);
+    }
+
+    #[test]
+    fn offers_subscription_changes_once() {
+        let mut session = session(None, &[2]);
+        session.add_virtual_endpoint(
+            1,
+            vec![RTCRtpTransceiver {
+                mid: "0".to_string(),
+                sender: None,
+                direction: RTCRtpTransceiverDirection::Recvonly,
+                current_direction: RTCRtpTransceiverDirection::Recvonly,
+                rtp_params: RTCRtpParameters::default(),
+                kind: RTPCodecType::Video,
+                rids: vec![],
+            }],
+        );
+        session.take_pending_offers();
+        let forwarded_direction = |session: &Session| {
+            session
+                .get_endpoint(&2)
+                .and_then(|endpoint| endpoint.get_transceivers().get("1-0"))
+                .map(|transceiver| transceiver.direction)
+        };
+
+        session.subscribe(2, 1, &"0".to_string()).unwrap();
+        assert_eq!(
+            forwarded_direction(&session),
+            Some(RTCRtpTransceiverDirection::Sendonly)
+        );
+        assert_eq!(session.take_pending_offers(), vec![2]);
+        session.subscribe(2, 1, &"0".to_string()).unwrap();
+        assert!(session.take_pending_offers().is_empty());
+
+        session.unsubscribe(2, 1, &"0".to_string()).unwrap();
+        assert_eq!(
+            forwarded_direction(&session),
+            Some(RTCRtpTransceiverDirection::Inactive)
+        );
+        assert_eq!(session.take_pending_offers(), vec![2]);
+        assert!(session
+            .get_endpoint(&2)
+            .unwrap()
+            .is_unsubscribed(&"1-0".to_string()));
+
+        assert!(session.subscribe(2, 1, &"1".to_string()).is_err());
+        assert!(session.subscribe(1, 1, &"0".to_string()).is_err());
+    }
+
+    #[test]
+    fn rejects_direct_messages_to_unknown_endpoints() {
+        let mut session = session(None, &[1, 2]);
+        let now = Instant::now();
+        let direct_message = |to| ChatRequest::DirectMessage {
+            to,
+            text: "hi".to_string(),
+        };
+
+        match session.handle_chat(1, direct_message(2), 10, now) {
+            ChatEvent::DirectMessage { from, to, .. } => assert_eq!((from, to), (1, 2)),
+            event => panic!("expected a direct message, got {:?}", event),
+        }
+        assert_eq!(
+            session.handle_chat(1, direct_message(3), 10, now),
+            ChatEvent::ChatError {
+                reason: "can't find endpoint id 3".to_string()
+            }
+        );
+    }
+}
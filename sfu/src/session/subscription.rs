@@ -0,0 +1,11 @@
+use crate::types::{EndpointId, Mid};
+use serde::Deserialize;
+
+/// SubscriptionRequest identifies a track published by another endpoint of the session, by its
+/// mid in the publisher's SDP. Once an endpoint subscribes or unsubscribes explicitly, it only
+/// receives the tracks it subscribes to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SubscriptionRequest {
+    pub endpoint_id: EndpointId,
+    pub mid: Mid,
+}
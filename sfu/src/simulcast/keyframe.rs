@@ -0,0 +1,196 @@
+use crate::description::config::{MIME_TYPE_AV1, MIME_TYPE_H264, MIME_TYPE_VP8, MIME_TYPE_VP9};
+
+const H264_NALU_TYPE_MASK: u8 = 0x1F;
+const H264_NALU_IDR: u8 = 5;
+const H264_NALU_SPS: u8 = 7;
+const H264_NALU_STAP_A: u8 = 24;
+const H264_NALU_FU_A: u8 = 28;
+const H264_FU_START_BIT: u8 = 0x80;
+
+/// is_keyframe checks whether the RTP payload starts a keyframe, which is where a receiver
+/// can start decoding a stream from
+pub(crate) fn is_keyframe(mime_type: &str, payload: &[u8]) -> bool {
+    if mime_type.eq_ignore_ascii_case(MIME_TYPE_VP8) {
+        vp8_keyframe_offset(payload).is_some()
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_VP9) {
+        is_vp9_keyframe(payload)
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_H264) {
+        is_h264_keyframe(payload)
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_AV1) {
+        is_av1_keyframe(payload)
+    } else {
+        false
+    }
+}
+
+/// keyframe_size returns the width and height of a keyframe when the codec carries them
+/// in the frame header
+pub(crate) fn keyframe_size(mime_type: &str, payload: &[u8]) -> Option<(u32, u32)> {
+    if !mime_type.eq_ignore_ascii_case(MIME_TYPE_VP8) {
+        return None;
+    }
+
+    // RFC 6386 section 9.1: 3 bytes frame tag, 3 bytes start code, then 14 bits width and height
+    let frame = &payload[vp8_keyframe_offset(payload)?..];
+    if frame.len() < 10 || frame[3..6] != [0x9D, 0x01, 0x2A] {
+        return None;
+    }
+    let width = u16::from_le_bytes([frame[6], frame[7]]) & 0x3FFF;
+    let height = u16::from_le_bytes([frame[8], frame[9]]) & 0x3FFF;
+    Some((width as u32, height as u32))
+}
+
+/// vp8_keyframe_offset returns the offset of the VP8 keyframe header after the payload
+/// descriptor (RFC 7741 section 4.2), if this packet starts a keyframe
+fn vp8_keyframe_offset(payload: &[u8]) -> Option<usize> {
+    let descriptor = *payload.first()?;
+    let is_start_of_partition = descriptor & 0x10 != 0;
+    let partition_index = descriptor & 0x07;
+    if !is_start_of_partition || partition_index != 0 {
+        return None;
+    }
+
+    let mut offset = 1;
+    if descriptor & 0x80 != 0 {
+        let extension = *payload.get(offset)?;
+        offset += 1;
+        if extension & 0x80 != 0 {
+            let picture_id = *payload.get(offset)?;
+            offset += if picture_id & 0x80 != 0 { 2 } else { 1 };
+        }
+        if extension & 0x40 != 0 {
+            offset += 1;
+        }
+        if extension & 0x30 != 0 {
+            offset += 1;
+        }
+    }
+
+    // the P bit of the frame tag is 0 for keyframes
+    let frame_tag = *payload.get(offset)?;
+    (frame_tag & 0x01 == 0).then_some(offset)
+}
+
+/// is_vp9_keyframe checks the P (inter-picture predicted) and B (beginning of frame) bits
+/// of the VP9 payload descriptor
+fn is_vp9_keyframe(payload: &[u8]) -> bool {
+    match payload.first() {
+        Some(descriptor) => descriptor & 0x40 == 0 && descriptor & 0x08 != 0,
+        None => false,
+    }
+}
+
+/// is_h264_keyframe looks for an IDR slice or a SPS in single NAL unit, STAP-A and FU-A
+/// packets (RFC 6184 section 5)
+fn is_h264_keyframe(payload: &[u8]) -> bool {
+    let Some(&header) = payload.first() else {
+        return false;
+    };
+
+    match header & H264_NALU_TYPE_MASK {
+        H264_NALU_IDR | H264_NALU_SPS => true,
+        H264_NALU_STAP_A => {
+            let mut offset = 1;
+            while offset + 2 < payload.len() {
+                let size = u16::from_be_bytes([payload[offset], payload[offset + 1]]) as usize;
+                let nalu_type = payload[offset + 2] & H264_NALU_TYPE_MASK;
+                if nalu_type == H264_NALU_IDR || nalu_type == H264_NALU_SPS {
+                    return true;
+                }
+                offset += 2 + size;
+            }
+            false
+        }
+        H264_NALU_FU_A => match payload.get(1) {
+            Some(fu_header) => {
+                fu_header & H264_FU_START_BIT != 0
+                    && fu_header & H264_NALU_TYPE_MASK == H264_NALU_IDR
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// is_av1_keyframe checks the N bit of the AV1 aggregation header, set on the first packet
+/// of a coded video sequence
+fn is_av1_keyframe(payload: &[u8]) -> bool {
+    match payload.first() {
+        Some(aggregation_header) => aggregation_header & 0x08 != 0,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_vp8_keyframes() {
+        // S bit, partition 0, then a keyframe tag and start code for 640x360
+        let keyframe = [
+            0x10, 0x00, 0x00, 0x00, 0x9D, 0x01, 0x2A, 0x80, 0x02, 0x68, 0x01,
+        ];
+        assert!(is_keyframe(MIME_TYPE_VP8, &keyframe));
+        assert_eq!(keyframe_size(MIME_TYPE_VP8, &keyframe), Some((640, 360)));
+
+        // with the X bit, a 15 bits picture id, TL0PICIDX and TID/KEYIDX
+        let extended = [
+            0x90, 0xF0, 0x80, 0x01, 0x02, 0x03, 0x00, 0x00, 0x00, 0x9D, 0x01, 0x2A, 0x40, 0x01,
+            0xF0, 0x00,
+        ];
+        assert!(is_keyframe(MIME_TYPE_VP8, &extended));
+        assert_eq!(keyframe_size(MIME_TYPE_VP8, &extended), Some((320, 240)));
+
+        // inter frame, continuation packet and a later partition
+        assert!(!is_keyframe(MIME_TYPE_VP8, &[0x10, 0x01, 0x00, 0x00]));
+        assert!(!is_keyframe(MIME_TYPE_VP8, &keyframe[..0]));
+        assert!(!is_keyframe(MIME_TYPE_VP8, &[0x00, 0x00, 0x00, 0x00]));
+        assert!(!is_keyframe(MIME_TYPE_VP8, &[0x11, 0x00, 0x00, 0x00]));
+        // truncated extension
+        assert!(!is_keyframe(MIME_TYPE_VP8, &[0x90, 0x80]));
+    }
+
+    #[test]
+    fn detects_vp9_keyframes() {
+        assert!(is_keyframe(MIME_TYPE_VP9, &[0x08]));
+        // P bit set, or not the beginning of a frame
+        assert!(!is_keyframe(MIME_TYPE_VP9, &[0x48]));
+        assert!(!is_keyframe(MIME_TYPE_VP9, &[0x00]));
+        assert!(!is_keyframe(MIME_TYPE_VP9, &[]));
+        assert_eq!(keyframe_size(MIME_TYPE_VP9, &[0x08]), None);
+    }
+
+    #[test]
+    fn detects_h264_keyframes() {
+        assert!(is_keyframe(MIME_TYPE_H264, &[0x65, 0x88]));
+        assert!(is_keyframe(MIME_TYPE_H264, &[0x67, 0x42]));
+        assert!(!is_keyframe(MIME_TYPE_H264, &[0x41, 0x9A]));
+
+        // STAP-A with an SEI then a SPS, and one with a single non-IDR slice
+        let stap_a = [0x78, 0x00, 0x02, 0x06, 0x05, 0x00, 0x02, 0x67, 0x42];
+        assert!(is_keyframe(MIME_TYPE_H264, &stap_a));
+        assert!(!is_keyframe(
+            MIME_TYPE_H264,
+            &[0x78, 0x00, 0x02, 0x41, 0x9A]
+        ));
+
+        // FU-A start and middle fragments of an IDR slice, and the start of a non-IDR slice
+        assert!(is_keyframe(MIME_TYPE_H264, &[0x7C, 0x85, 0x88]));
+        assert!(!is_keyframe(MIME_TYPE_H264, &[0x7C, 0x05, 0x88]));
+        assert!(!is_keyframe(MIME_TYPE_H264, &[0x7C, 0x81, 0x9A]));
+        assert!(!is_keyframe(MIME_TYPE_H264, &[0x7C]));
+    }
+
+    #[test]
+    fn detects_av1_keyframes() {
+        assert!(is_keyframe(MIME_TYPE_AV1, &[0x18]));
+        assert!(!is_keyframe(MIME_TYPE_AV1, &[0x10]));
+    }
+
+    #[test]
+    fn ignores_case_and_other_codecs() {
+        assert!(is_keyframe(&MIME_TYPE_VP9.to_lowercase(), &[0x08]));
+        assert!(!is_keyframe("audio/opus", &[0x08]));
+    }
+}
@@ -0,0 +1,386 @@
+pub(crate) mod keyframe;
+
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// a layer without any packet for this long is considered stopped by the publisher
+const LAYER_INACTIVE_TIMEOUT: Duration = Duration::from_secs(1);
+/// window over which the bitrate of a layer is measured
+const BITRATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// LayerPreference is what a subscriber asked to receive for a simulcast track. Both fields are
+/// upper bounds, the estimated bandwidth of the subscriber may still select a lower layer.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LayerPreference {
+    /// rid of the highest layer to forward
+    #[serde(default)]
+    pub rid: Option<String>,
+    /// maximum height in pixels of the forwarded layer
+    #[serde(default)]
+    pub max_height: Option<u32>,
+}
+
+/// LayerRequest asks for a layer of the track forwarded to the endpoint under `track`, which is
+/// the mid of the forwarded media section, i.e. `{publisher endpoint id}-{publisher mid}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LayerRequest {
+    pub track: String,
+    #[serde(flatten)]
+    pub preference: LayerPreference,
+}
+
+/// SimulcastLayer keeps what is observed on one rid of a publisher's track
+#[derive(Debug)]
+pub(crate) struct SimulcastLayer {
+    rid: String,
+    bitrate: u64,
+    window_start: Instant,
+    window_bytes: usize,
+    size: Option<(u32, u32)>,
+    last_packet: Instant,
+}
+
+impl SimulcastLayer {
+    fn new(rid: String, now: Instant) -> Self {
+        Self {
+            rid,
+            bitrate: 0,
+            window_start: now,
+            window_bytes: 0,
+            size: None,
+            last_packet: now,
+        }
+    }
+
+    fn is_active(&self, now: Instant) -> bool {
+        now.duration_since(self.last_packet) < LAYER_INACTIVE_TIMEOUT
+    }
+
+    fn height(&self) -> Option<u32> {
+        self.size.map(|(_, height)| height)
+    }
+}
+
+/// SimulcastTrack gathers the layers of a publisher's track sent with rid based simulcast
+#[derive(Default, Debug)]
+pub(crate) struct SimulcastTrack {
+    layers: Vec<SimulcastLayer>,
+}
+
+impl SimulcastTrack {
+    pub(crate) fn on_packet(
+        &mut self,
+        rid: &str,
+        now: Instant,
+        size: usize,
+        keyframe_size: Option<(u32, u32)>,
+    ) {
+        let layer = match self.layers.iter().position(|layer| layer.rid == rid) {
+            Some(index) => &mut self.layers[index],
+            None => {
+                self.layers.push(SimulcastLayer::new(rid.to_string(), now));
+                self.layers.last_mut().unwrap()
+            }
+        };
+
+        let elapsed = now.duration_since(layer.window_start);
+        if elapsed >= BITRATE_WINDOW {
+            layer.bitrate = (layer.window_bytes as f64 * 8.0 / elapsed.as_secs_f64()) as u64;
+            layer.window_start = now;
+            layer.window_bytes = 0;
+        }
+        layer.window_bytes += size;
+        layer.last_packet = now;
+        if keyframe_size.is_some() {
+            layer.size = keyframe_size;
+        }
+    }
+
+    /// select returns the rid of the highest active layer within the preference of the subscriber
+    /// and its estimated bandwidth, or the lowest active layer when none fits.
+    pub(crate) fn select(
+        &self,
+        preference: &LayerPreference,
+        estimated_bitrate: Option<u64>,
+        now: Instant,
+    ) -> Option<&str> {
+        let mut active: Vec<&SimulcastLayer> = self
+            .layers
+            .iter()
+            .filter(|layer| layer.is_active(now))
+            .collect();
+        active.sort_by_key(|layer| (layer.height(), layer.bitrate));
+
+        let highest = match &preference.rid {
+            Some(rid) => active
+                .iter()
+                .position(|layer| &layer.rid == rid)
+                .unwrap_or(active.len().saturating_sub(1)),
+            None => active.len().saturating_sub(1),
+        };
+
+        active
+            .iter()
+            .take(highest + 1)
+            .rev()
+            .find(|layer| {
+                let fits_height = match (preference.max_height, layer.height()) {
+                    (Some(max_height), Some(height)) => height <= max_height,
+                    _ => true,
+                };
+                let fits_bitrate =
+                    estimated_bitrate.map_or(true, |bitrate| layer.bitrate <= bitrate);
+                fits_height && fits_bitrate
+            })
+            .or(active.first())
+            .map(|layer| layer.rid.as_str())
+    }
+}
+
+/// SimulcastForwarder forwards one layer of a simulcast track to a subscriber as a single
+/// stream, rewriting SSRC, sequence numbers and timestamps so that switching layers on a
+/// keyframe looks like a continuous stream to the receiver.
+#[derive(Default, Debug)]
+pub(crate) struct SimulcastForwarder {
+    preference: LayerPreference,
+    current_rid: Option<String>,
+    target_rid: Option<String>,
+    sequence_number_offset: u16,
+    timestamp_offset: u32,
+    /// forwarded sequence number of the first packet of the current layer
+    first_forwarded: Option<u16>,
+    last_forwarded: Option<(u16, u32, Instant)>,
+}
+
+impl SimulcastForwarder {
+    pub(crate) fn preference(&self) -> &LayerPreference {
+        &self.preference
+    }
+
+    pub(crate) fn set_preference(&mut self, preference: LayerPreference) {
+        self.preference = preference;
+    }
+
+    pub(crate) fn current_rid(&self) -> Option<&str> {
+        self.current_rid.as_deref()
+    }
+
+    pub(crate) fn target_rid(&self) -> Option<&str> {
+        self.target_rid.as_deref()
+    }
+
+    /// set_target_rid returns whether the target layer changed
+    pub(crate) fn set_target_rid(&mut self, target_rid: Option<&str>) -> bool {
+        if self.target_rid.as_deref() == target_rid {
+            return false;
+        }
+        self.target_rid = target_rid.map(|rid| rid.to_string());
+        true
+    }
+
+    /// is_switch_pending tells whether a keyframe of the target layer is awaited
+    pub(crate) fn is_switch_pending(&self) -> bool {
+        self.target_rid.is_some() && self.target_rid != self.current_rid
+    }
+
+    /// forward returns the packet to send to the subscriber, if the packet belongs to the
+    /// forwarded layer. Switching to the target layer only happens on one of its keyframes, and
+    /// without target layer the track is paused until one is set again.
+    pub(crate) fn forward(
+        &mut self,
+        rid: &str,
+        packet: &rtp::packet::Packet,
+        is_keyframe: bool,
+        ssrc: u32,
+        clock_rate: u32,
+        now: Instant,
+    ) -> Option<rtp::packet::Packet> {
+        if self.target_rid.is_none() {
+            self.current_rid = None;
+            return None;
+        }
+
+        if self.current_rid.as_deref() != Some(rid) {
+            if !is_keyframe || self.target_rid.as_deref() != Some(rid) {
+                return None;
+            }
+
+            if let Some((sequence_number, timestamp, forwarded_at)) = self.last_forwarded {
+                let elapsed_ticks =
+                    (now.duration_since(forwarded_at).as_secs_f64() * clock_rate as f64) as u32;
+                self.sequence_number_offset = sequence_number
+                    .wrapping_add(1)
+                    .wrapping_sub(packet.header.sequence_number);
+                self.timestamp_offset = timestamp
+                    .wrapping_add(elapsed_ticks.max(1))
+                    .wrapping_sub(packet.header.timestamp);
+            }
+            self.current_rid = Some(rid.to_string());
+            self.first_forwarded = Some(
+                packet
+                    .header
+                    .sequence_number
+                    .wrapping_add(self.sequence_number_offset),
+            );
+        }
+
+        let mut packet = packet.clone();
+        packet.header.ssrc = ssrc;
+        packet.header.sequence_number = packet
+            .header
+            .sequence_number
+            .wrapping_add(self.sequence_number_offset);
+        packet.header.timestamp = packet.header.timestamp.wrapping_add(self.timestamp_offset);
+
+        let is_newer = match self.last_forwarded {
+            Some((sequence_number, _, _)) => {
+                (packet.header.sequence_number.wrapping_sub(sequence_number) as i16) > 0
+            }
+            None => true,
+        };
+        if is_newer {
+            self.last_forwarded =
+                Some((packet.header.sequence_number, packet.header.timestamp, now));
+        } else if self.first_forwarded.is_some_and(|first_forwarded| {
+            (packet.header.sequence_number.wrapping_sub(first_forwarded) as i16) < 0
+        }) {
+            // a late packet of the layer from before the switch, its sequence number was
+            // taken by the previous layer
+            return None;
+        }
+
+        Some(packet)
+    }
+
+    /// published_sequence_number maps the sequence number of a forwarded packet back to the one
+    /// of the current layer, if the packet was forwarded since the last switch
+    pub(crate) fn published_sequence_number(&self, sequence_number: u16) -> Option<u16> {
+        let first_forwarded = self.first_forwarded?;
+        let (last_forwarded, _, _) = self.last_forwarded?;
+        let forwarded_count = last_forwarded.wrapping_sub(first_forwarded);
+        if sequence_number.wrapping_sub(first_forwarded) > forwarded_count {
+            return None;
+        }
+        Some(sequence_number.wrapping_sub(self.sequence_number_offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SSRC: u32 = 1234;
+    const CLOCK_RATE: u32 = 90000;
+
+    fn packet(sequence_number: u16, timestamp: u32) -> rtp::packet::Packet {
+        let mut packet = rtp::packet::Packet::default();
+        packet.header.sequence_number = sequence_number;
+        packet.header.timestamp = timestamp;
+        packet
+    }
+
+    /// forwarder switched from layer "q", at sequence numbers 100..110, to layer "h", at
+    /// sequence numbers 5000.., with 5000 forwarded as 110
+    fn switched_forwarder(now: Instant) -> SimulcastForwarder {
+        let mut forwarder = SimulcastForwarder::default();
+        forwarder.set_target_rid(Some("q"));
+        for sequence_number in 100..110 {
+            let is_keyframe = sequence_number == 100;
+            forwarder
+                .forward(
+                    "q",
+                    &packet(sequence_number, 0),
+                    is_keyframe,
+                    SSRC,
+                    CLOCK_RATE,
+                    now,
+                )
+                .unwrap();
+        }
+        forwarder.set_target_rid(Some("h"));
+        assert!(forwarder.is_switch_pending());
+        assert!(forwarder
+            .forward("h", &packet(4999, 0), false, SSRC, CLOCK_RATE, now)
+            .is_none());
+        let forwarded = forwarder
+            .forward("h", &packet(5000, 0), true, SSRC, CLOCK_RATE, now)
+            .unwrap();
+        assert_eq!(forwarded.header.sequence_number, 110);
+        assert_eq!(forwarded.header.ssrc, SSRC);
+        forwarder
+    }
+
+    #[test]
+    fn forwards_a_continuous_stream_across_switches() {
+        let now = Instant::now();
+        let mut forwarder = switched_forwarder(now);
+        assert_eq!(forwarder.current_rid(), Some("h"));
+        assert!(!forwarder.is_switch_pending());
+        // the previous layer isn't forwarded anymore
+        assert!(forwarder
+            .forward("q", &packet(110, 0), false, SSRC, CLOCK_RATE, now)
+            .is_none());
+        let forwarded = forwarder
+            .forward("h", &packet(5001, 0), false, SSRC, CLOCK_RATE, now)
+            .unwrap();
+        assert_eq!(forwarded.header.sequence_number, 111);
+    }
+
+    #[test]
+    fn maps_sequence_numbers_back_to_the_current_layer() {
+        let now = Instant::now();
+        let mut forwarder = switched_forwarder(now);
+        forwarder
+            .forward("h", &packet(5001, 0), false, SSRC, CLOCK_RATE, now)
+            .unwrap();
+
+        assert_eq!(forwarder.published_sequence_number(110), Some(5000));
+        assert_eq!(forwarder.published_sequence_number(111), Some(5001));
+        // forwarded from the previous layer, or not forwarded yet
+        assert_eq!(forwarder.published_sequence_number(109), None);
+        assert_eq!(forwarder.published_sequence_number(112), None);
+    }
+
+    #[test]
+    fn forwards_retransmissions_of_the_current_layer() {
+        let now = Instant::now();
+        let mut forwarder = switched_forwarder(now);
+        for sequence_number in 5001..5004 {
+            forwarder
+                .forward(
+                    "h",
+                    &packet(sequence_number, 0),
+                    false,
+                    SSRC,
+                    CLOCK_RATE,
+                    now,
+                )
+                .unwrap();
+        }
+
+        let retransmitted = forwarder
+            .forward("h", &packet(5001, 0), false, SSRC, CLOCK_RATE, now)
+            .unwrap();
+        assert_eq!(retransmitted.header.sequence_number, 111);
+        // a late packet from before the switch would reuse a sequence number of layer "q"
+        assert!(forwarder
+            .forward("h", &packet(4998, 0), false, SSRC, CLOCK_RATE, now)
+            .is_none());
+        // the retransmission didn't move the stream back
+        let forwarded = forwarder
+            .forward("h", &packet(5004, 0), false, SSRC, CLOCK_RATE, now)
+            .unwrap();
+        assert_eq!(forwarded.header.sequence_number, 114);
+    }
+
+    #[test]
+    fn pauses_without_target_layer() {
+        let now = Instant::now();
+        let mut forwarder = switched_forwarder(now);
+        forwarder.set_target_rid(None);
+        assert!(forwarder
+            .forward("h", &packet(5001, 0), true, SSRC, CLOCK_RATE, now)
+            .is_none());
+        assert_eq!(forwarder.current_rid(), None);
+    }
+}
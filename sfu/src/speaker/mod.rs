@@ -0,0 +1,295 @@
+use crate::types::EndpointId;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// audio levels are -dBov from 0 (loudest) to 127 (silence), quieter levels are background noise
+const SPEECH_LEVEL_THRESHOLD: u8 = 70;
+/// speech is measured over this window
+const SPEECH_WINDOW: Duration = Duration::from_millis(1000);
+/// the dominant speaker isn't evaluated more often than this
+const EVALUATION_INTERVAL: Duration = Duration::from_millis(300);
+/// the dominant speaker is kept for at least this long, unless it leaves
+const MIN_DOMINANT_DURATION: Duration = Duration::from_millis(1500);
+/// another speaker takes over once its speech score exceeds the dominant speaker's one by this
+/// ratio, to avoid flapping between speakers talking together
+const SWITCH_RATIO: f64 = 1.5;
+/// speech score below which a speaker can't become dominant, a few short words
+const MIN_SPEECH_SCORE: u64 = 100;
+
+/// parse_audio_level reads the level in -dBov of the `ssrc-audio-level` header extension,
+/// RFC 6464, ignoring the voice activity flag which not every sender sets
+pub(crate) fn parse_audio_level(payload: &[u8]) -> Option<u8> {
+    payload.first().map(|byte| byte & 0x7F)
+}
+
+/// PinRequest replaces the endpoints whose video is always forwarded to the endpoint, whatever
+/// the last N policy of the session
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PinRequest {
+    pub endpoint_ids: Vec<EndpointId>,
+}
+
+/// SpeakerActivity keeps the audio levels of an endpoint over the speech window
+#[derive(Default, Debug)]
+struct SpeakerActivity {
+    levels: VecDeque<(Instant, u8)>,
+}
+
+impl SpeakerActivity {
+    fn on_audio_level(&mut self, now: Instant, level: u8) {
+        self.levels.push_back((now, level));
+        self.expire(now);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self
+            .levels
+            .front()
+            .is_some_and(|&(received_at, _)| now.duration_since(received_at) > SPEECH_WINDOW)
+        {
+            self.levels.pop_front();
+        }
+    }
+
+    /// score sums how loud the speech packets of the window are, so that both speaking longer
+    /// and louder count
+    fn score(&self) -> u64 {
+        self.levels
+            .iter()
+            .filter(|&&(_, level)| level <= SPEECH_LEVEL_THRESHOLD)
+            .map(|&(_, level)| (SPEECH_LEVEL_THRESHOLD - level) as u64 + 1)
+            .sum()
+    }
+}
+
+/// DominantSpeaker detects who is speaking in a session from the audio levels of its endpoints,
+/// with hysteresis so that short interjections and background noise don't take over.
+#[derive(Default, Debug)]
+pub(crate) struct DominantSpeaker {
+    speakers: HashMap<EndpointId, SpeakerActivity>,
+    dominant: Option<EndpointId>,
+    /// endpoints which have been the dominant speaker, the most recent first
+    recent_speakers: Vec<EndpointId>,
+    last_change: Option<Instant>,
+    last_evaluation: Option<Instant>,
+}
+
+impl DominantSpeaker {
+    pub(crate) fn dominant(&self) -> Option<EndpointId> {
+        self.dominant
+    }
+
+    pub(crate) fn recent_speakers(&self) -> &[EndpointId] {
+        &self.recent_speakers
+    }
+
+    /// on_audio_level records the audio level of a packet received from the endpoint, and returns
+    /// the new dominant speaker when it changes
+    pub(crate) fn on_audio_level(
+        &mut self,
+        endpoint_id: EndpointId,
+        level: u8,
+        now: Instant,
+    ) -> Option<EndpointId> {
+        self.speakers
+            .entry(endpoint_id)
+            .or_default()
+            .on_audio_level(now, level);
+
+        if self.last_evaluation.is_some_and(|last_evaluation| {
+            now.duration_since(last_evaluation) < EVALUATION_INTERVAL
+        }) {
+            return None;
+        }
+        self.last_evaluation = Some(now);
+        self.evaluate(now)
+    }
+
+    /// remove_endpoint forgets a leaving endpoint, which stops being the dominant speaker
+    pub(crate) fn remove_endpoint(&mut self, endpoint_id: &EndpointId) {
+        self.speakers.remove(endpoint_id);
+        self.recent_speakers
+            .retain(|speaker| speaker != endpoint_id);
+        if self.dominant == Some(*endpoint_id) {
+            self.dominant = None;
+            self.last_change = None;
+        }
+    }
+
+    fn evaluate(&mut self, now: Instant) -> Option<EndpointId> {
+        let mut loudest: Option<(EndpointId, u64)> = None;
+        let mut dominant_score = 0;
+        for (&endpoint_id, speaker) in self.speakers.iter_mut() {
+            speaker.expire(now);
+            let score = speaker.score();
+            if Some(endpoint_id) == self.dominant {
+                dominant_score = score;
+            } else if loudest.map_or(true, |(_, loudest_score)| score > loudest_score) {
+                loudest = Some((endpoint_id, score));
+            }
+        }
+
+        let (challenger, challenger_score) = loudest?;
+        if challenger_score < MIN_SPEECH_SCORE
+            || (challenger_score as f64) < dominant_score as f64 * SWITCH_RATIO
+        {
+            return None;
+        }
+        if self.dominant.is_some()
+            && self
+                .last_change
+                .is_some_and(|last_change| now.duration_since(last_change) < MIN_DOMINANT_DURATION)
+        {
+            return None;
+        }
+
+        self.dominant = Some(challenger);
+        self.last_change = Some(now);
+        self.recent_speakers
+            .retain(|&speaker| speaker != challenger);
+        self.recent_speakers.insert(0, challenger);
+        Some(challenger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKET_INTERVAL: Duration = Duration::from_millis(20);
+
+    /// talk sends the audio levels of the endpoints every 20 ms for the duration, and returns
+    /// when the dominant speaker changed to whom
+    fn talk(
+        dominant_speaker: &mut DominantSpeaker,
+        levels: &[(EndpointId, u8)],
+        start: Instant,
+        duration: Duration,
+    ) -> Vec<(Duration, EndpointId)> {
+        let mut changes = vec![];
+        let mut elapsed = Duration::ZERO;
+        while elapsed < duration {
+            for &(endpoint_id, level) in levels {
+                if let Some(dominant) =
+                    dominant_speaker.on_audio_level(endpoint_id, level, start + elapsed)
+                {
+                    changes.push((elapsed, dominant));
+                }
+            }
+            elapsed += PACKET_INTERVAL;
+        }
+        changes
+    }
+
+    #[test]
+    fn parses_the_audio_level_without_the_voice_activity_flag() {
+        assert_eq!(parse_audio_level(&[0x80 | 30]), Some(30));
+        assert_eq!(parse_audio_level(&[127]), Some(127));
+        assert_eq!(parse_audio_level(&[]), None);
+    }
+
+    #[test]
+    fn ignores_background_noise() {
+        let mut dominant_speaker = DominantSpeaker::default();
+        let changes = talk(
+            &mut dominant_speaker,
+            &[(1, SPEECH_LEVEL_THRESHOLD + 1), (2, 127)],
+            Instant::now(),
+            Duration::from_secs(5),
+        );
+        assert!(changes.is_empty());
+        assert_eq!(dominant_speaker.dominant(), None);
+    }
+
+    #[test]
+    fn evaluates_the_speakers_at_the_evaluation_interval() {
+        let mut dominant_speaker = DominantSpeaker::default();
+        let changes = talk(
+            &mut dominant_speaker,
+            &[(1, 10)],
+            Instant::now(),
+            Duration::from_secs(1),
+        );
+        // the first packet is evaluated right away, with too little speech to take over
+        assert_eq!(changes, vec![(EVALUATION_INTERVAL, 1)]);
+        assert_eq!(dominant_speaker.recent_speakers(), &[1]);
+    }
+
+    #[test]
+    fn switches_to_a_louder_speaker_after_the_min_dominant_duration() {
+        let mut dominant_speaker = DominantSpeaker::default();
+        let start = Instant::now();
+        let changes = talk(
+            &mut dominant_speaker,
+            &[(1, 10), (2, 127)],
+            start,
+            Duration::from_secs(1),
+        );
+        let (became_dominant, _) = changes[0];
+        assert_eq!(changes, vec![(became_dominant, 1)]);
+
+        // endpoint 2 starts speaking right after endpoint 1 took over, and goes on alone
+        let changes = talk(
+            &mut dominant_speaker,
+            &[(1, 127), (2, 10)],
+            start + became_dominant + PACKET_INTERVAL,
+            Duration::from_secs(3),
+        );
+        assert_eq!(changes.len(), 1);
+        let (switched, dominant) = changes[0];
+        assert_eq!(dominant, 2);
+        assert!(PACKET_INTERVAL + switched >= MIN_DOMINANT_DURATION);
+        assert!(PACKET_INTERVAL + switched < MIN_DOMINANT_DURATION + EVALUATION_INTERVAL);
+        assert_eq!(dominant_speaker.recent_speakers(), &[2, 1]);
+    }
+
+    #[test]
+    fn keeps_the_dominant_speaker_against_a_speaker_within_the_switch_ratio() {
+        let mut dominant_speaker = DominantSpeaker::default();
+        let start = Instant::now();
+        let changes = talk(
+            &mut dominant_speaker,
+            &[(1, 10)],
+            start,
+            Duration::from_secs(1),
+        );
+        assert_eq!(changes.last().map(|&(_, dominant)| dominant), Some(1));
+
+        // endpoint 2 speaks louder, 71 against 61 a packet, but not by the switch ratio of 1.5
+        let changes = talk(
+            &mut dominant_speaker,
+            &[(1, 10), (2, 0)],
+            start + Duration::from_secs(1),
+            Duration::from_secs(10),
+        );
+        assert!(changes.is_empty());
+        assert_eq!(dominant_speaker.dominant(), Some(1));
+    }
+
+    #[test]
+    fn forgets_a_leaving_dominant_speaker() {
+        let mut dominant_speaker = DominantSpeaker::default();
+        let start = Instant::now();
+        talk(
+            &mut dominant_speaker,
+            &[(1, 10)],
+            start,
+            Duration::from_secs(1),
+        );
+        assert_eq!(dominant_speaker.dominant(), Some(1));
+
+        dominant_speaker.remove_endpoint(&1);
+        assert_eq!(dominant_speaker.dominant(), None);
+        assert!(dominant_speaker.recent_speakers().is_empty());
+        // another speaker takes over without waiting for the min dominant duration
+        let changes = talk(
+            &mut dominant_speaker,
+            &[(2, 10)],
+            start + Duration::from_secs(1),
+            Duration::from_secs(1),
+        );
+        assert_eq!(changes.first().map(|&(_, dominant)| dominant), Some(2));
+        assert!(changes[0].0 < MIN_DOMINANT_DURATION);
+    }
+}
@@ -0,0 +1,23 @@
+use crate::interceptor::BandwidthEstimateSource;
+use crate::types::{EndpointId, Mid, SessionId};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// EndpointStats is a snapshot of what the server knows about the media sent to an endpoint
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct EndpointStats {
+    pub session_id: SessionId,
+    pub endpoint_id: EndpointId,
+    /// bandwidth estimated towards the endpoint, in bits per second
+    pub estimated_bitrate: Option<u64>,
+    /// feedback the bandwidth estimate comes from
+    pub bandwidth_estimation: Option<BandwidthEstimateSource>,
+    /// whether video is paused because of a too low estimated bandwidth
+    pub video_paused: bool,
+    /// lost packets retransmitted from the packets kept by the server
+    pub nacks_served_locally: u64,
+    /// lost packets no longer kept by the server, whose NACKs were forwarded to the publishers
+    pub nacks_forwarded: u64,
+    /// rid of the layer currently forwarded for each simulcast track
+    pub forwarded_layers: BTreeMap<Mid, String>,
+}
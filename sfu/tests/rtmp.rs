@@ -0,0 +1,281 @@
+use bytes::Bytes;
+use sfu::{RtmpConnection, RtmpEvent, RtmpMedia};
+
+const HANDSHAKE_SIZE: usize = 1536;
+const CHUNK_SIZE: usize = 128;
+const TAG_TYPE_AUDIO: u8 = 8;
+const TAG_TYPE_VIDEO: u8 = 9;
+const TAG_TYPE_SCRIPT_DATA: u8 = 18;
+const MESSAGE_TYPE_COMMAND_AMF0: u8 = 20;
+
+const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xbf, 0xe5, 0x84];
+const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+
+/// flv_file is a canned FLV file, as ffmpeg writes it with -c:v libx264 -c:a libopus: its
+/// metadata, the AVC sequence header, a keyframe longer than a chunk, Opus frames and an inter
+/// frame with a composition time offset
+fn flv_file() -> Vec<u8> {
+    let idr = [&[0x65][..], &[0x88; 299]].concat();
+    let non_idr = [0x41, 0x9a, 0x02, 0x04];
+    let mut avc_config = vec![0x01, SPS[1], SPS[2], SPS[3], 0xff, 0xe1];
+    avc_config.extend_from_slice(&(SPS.len() as u16).to_be_bytes());
+    avc_config.extend_from_slice(SPS);
+    avc_config.push(0x01);
+    avc_config.extend_from_slice(&(PPS.len() as u16).to_be_bytes());
+    avc_config.extend_from_slice(PPS);
+
+    let tags: Vec<(u8, u32, Vec<u8>)> = vec![
+        (
+            TAG_TYPE_SCRIPT_DATA,
+            0,
+            [
+                &[0x02, 0x00, 0x0a][..],
+                b"onMetaData",
+                &[0x08, 0, 0, 0, 0, 0, 0, 0x09],
+            ]
+            .concat(),
+        ),
+        (
+            TAG_TYPE_VIDEO,
+            0,
+            [&[0x17, 0x00, 0, 0, 0][..], &avc_config].concat(),
+        ),
+        (
+            TAG_TYPE_AUDIO,
+            0,
+            [&[0x90][..], b"Opus", b"OpusHead"].concat(),
+        ),
+        (
+            TAG_TYPE_VIDEO,
+            0,
+            [
+                &[0x17, 0x01, 0, 0, 0][..],
+                &(idr.len() as u32).to_be_bytes(),
+                &idr,
+            ]
+            .concat(),
+        ),
+        (
+            TAG_TYPE_AUDIO,
+            0,
+            [&[0x91][..], b"Opus", &[0xfc, 1]].concat(),
+        ),
+        (
+            TAG_TYPE_AUDIO,
+            20,
+            [&[0x91][..], b"Opus", &[0xfc, 2]].concat(),
+        ),
+        (
+            TAG_TYPE_VIDEO,
+            33,
+            [
+                &[0x27, 0x01, 0, 0, 66][..],
+                &(non_idr.len() as u32).to_be_bytes(),
+                &non_idr,
+            ]
+            .concat(),
+        ),
+    ];
+
+    let mut flv = b"FLV".to_vec();
+    flv.extend([0x01, 0x05, 0, 0, 0, 9]);
+    flv.extend(0u32.to_be_bytes());
+    for (tag_type, timestamp, data) in tags {
+        flv.push(tag_type);
+        flv.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+        flv.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+        flv.push((timestamp >> 24) as u8);
+        flv.extend([0, 0, 0]);
+        flv.extend_from_slice(&data);
+        flv.extend(((11 + data.len()) as u32).to_be_bytes());
+    }
+    flv
+}
+
+/// flv_tags returns the type, timestamp and data of the tags of an FLV file
+fn flv_tags(flv: &[u8]) -> Vec<(u8, u32, &[u8])> {
+    assert_eq!(&flv[..3], b"FLV");
+    let mut tags = vec![];
+    let mut rest = &flv[9 + 4..];
+    while !rest.is_empty() {
+        let len = u32::from_be_bytes([0, rest[1], rest[2], rest[3]]) as usize;
+        let timestamp = u32::from_be_bytes([rest[7], rest[4], rest[5], rest[6]]);
+        tags.push((rest[0], timestamp, &rest[11..11 + len]));
+        rest = &rest[11 + len + 4..];
+    }
+    tags
+}
+
+/// message splits a message of the publisher into chunks, the first one with a full header and
+/// the others without any
+fn message(chunk_stream_id: u8, type_id: u8, timestamp: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = vec![chunk_stream_id];
+    data.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+    data.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    data.push(type_id);
+    data.extend_from_slice(&1u32.to_le_bytes());
+    for (i, chunk) in payload.chunks(CHUNK_SIZE).enumerate() {
+        if i > 0 {
+            data.push(0xc0 | chunk_stream_id);
+        }
+        data.extend_from_slice(chunk);
+    }
+    data
+}
+
+/// command encodes an AMF0 command of strings, numbers and nulls, the connect one taking an
+/// object with the app instead of its third string
+fn command(name: &str, transaction_id: f64, arguments: &[Option<&str>]) -> Vec<u8> {
+    let mut payload = vec![0x02];
+    payload.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    payload.extend_from_slice(name.as_bytes());
+    payload.push(0x00);
+    payload.extend_from_slice(&transaction_id.to_be_bytes());
+    for argument in arguments {
+        match argument {
+            Some(app) if name == "connect" => {
+                payload.extend([0x03, 0x00, 0x03]);
+                payload.extend_from_slice(b"app");
+                payload.push(0x02);
+                payload.extend_from_slice(&(app.len() as u16).to_be_bytes());
+                payload.extend_from_slice(app.as_bytes());
+                payload.extend([0x00, 0x00, 0x09]);
+            }
+            Some(argument) => {
+                payload.push(0x02);
+                payload.extend_from_slice(&(argument.len() as u16).to_be_bytes());
+                payload.extend_from_slice(argument.as_bytes());
+            }
+            None => payload.push(0x05),
+        }
+    }
+    message(3, MESSAGE_TYPE_COMMAND_AMF0, 0, &payload)
+}
+
+fn outputs(connection: &mut RtmpConnection) -> Vec<u8> {
+    std::iter::from_fn(|| connection.poll_output())
+        .flat_map(|output| output.to_vec())
+        .collect()
+}
+
+fn contains(data: &[u8], text: &str) -> bool {
+    data.windows(text.len())
+        .any(|window| window == text.as_bytes())
+}
+
+#[test]
+fn publishes_a_flv_file() {
+    let mut connection = RtmpConnection::new();
+    let mut c0c1 = vec![3];
+    c0c1.extend([0x5a; HANDSHAKE_SIZE]);
+    assert!(connection.handle_input(&c0c1).unwrap().is_empty());
+    assert_eq!(outputs(&mut connection).len(), 1 + 2 * HANDSHAKE_SIZE);
+    assert!(connection
+        .handle_input(&[0; HANDSHAKE_SIZE])
+        .unwrap()
+        .is_empty());
+
+    assert!(connection
+        .handle_input(&command("connect", 1.0, &[Some("live")]))
+        .unwrap()
+        .is_empty());
+    assert!(contains(
+        &outputs(&mut connection),
+        "NetConnection.Connect.Success"
+    ));
+    assert!(connection
+        .handle_input(&command("createStream", 2.0, &[None]))
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        connection
+            .handle_input(&command("publish", 3.0, &[None, Some("7"), Some("live")]))
+            .unwrap(),
+        vec![RtmpEvent::Publish {
+            app: "live".to_string(),
+            stream_key: "7".to_string(),
+        }]
+    );
+    connection.accept_publish();
+    assert!(contains(
+        &outputs(&mut connection),
+        "NetStream.Publish.Start"
+    ));
+
+    let flv = flv_file();
+    let mut events = vec![];
+    for (tag_type, timestamp, data) in flv_tags(&flv) {
+        let chunk_stream_id = if tag_type == TAG_TYPE_VIDEO { 6 } else { 4 };
+        let input = message(chunk_stream_id, tag_type, timestamp, data);
+        // the publisher writes as it reads the file, a few bytes at a time
+        for input in input.chunks(100) {
+            events.extend(connection.handle_input(input).unwrap());
+        }
+    }
+
+    let idr = [&[0x65][..], &[0x88; 299]].concat();
+    let keyframe = [
+        &[0, 0, 0, 1][..],
+        SPS,
+        &[0, 0, 0, 1],
+        PPS,
+        &[0, 0, 0, 1],
+        &idr,
+    ]
+    .concat();
+    assert_eq!(
+        events,
+        vec![
+            RtmpEvent::Media(RtmpMedia::Video {
+                timestamp: 0,
+                is_keyframe: true,
+                data: Bytes::from(keyframe),
+            }),
+            RtmpEvent::Media(RtmpMedia::Audio {
+                timestamp: 0,
+                data: Bytes::from_static(&[0xfc, 1]),
+            }),
+            RtmpEvent::Media(RtmpMedia::Audio {
+                timestamp: 20,
+                data: Bytes::from_static(&[0xfc, 2]),
+            }),
+            RtmpEvent::Media(RtmpMedia::Video {
+                timestamp: 33 + 66,
+                is_keyframe: false,
+                data: Bytes::from_static(&[0, 0, 0, 1, 0x41, 0x9a, 0x02, 0x04]),
+            }),
+        ]
+    );
+
+    assert_eq!(
+        connection
+            .handle_input(&command("deleteStream", 4.0, &[None]))
+            .unwrap(),
+        vec![RtmpEvent::Unpublish]
+    );
+}
+
+#[test]
+fn refuses_aac_audio() {
+    let mut connection = RtmpConnection::new();
+    let mut c0c1 = vec![3];
+    c0c1.extend([0; HANDSHAKE_SIZE]);
+    connection.handle_input(&c0c1).unwrap();
+    connection.handle_input(&[0; HANDSHAKE_SIZE]).unwrap();
+    connection
+        .handle_input(&command("connect", 1.0, &[Some("live")]))
+        .unwrap();
+    connection
+        .handle_input(&command("publish", 2.0, &[None, Some("7")]))
+        .unwrap();
+    connection.accept_publish();
+    outputs(&mut connection);
+
+    // the sequence header of AAC LC, 44.1 kHz, stereo
+    let aac = message(4, TAG_TYPE_AUDIO, 0, &[0xaf, 0x00, 0x12, 0x10]);
+    assert!(connection.handle_input(&aac).is_err());
+    assert!(contains(
+        &outputs(&mut connection),
+        "NetStream.Publish.Denied"
+    ));
+}
@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{SessionDirectory, SessionOwner};
+
+/// Directory of a single node, sessions being owned by its workers only.
+#[derive(Default)]
+pub struct MemoryDirectory {
+    // session_id -> owner and expiry of its lease
+    owners: Mutex<HashMap<u64, (SessionOwner, Instant)>>,
+}
+
+impl MemoryDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionDirectory for MemoryDirectory {
+    fn claim(
+        &self,
+        session_id: u64,
+        owner: &SessionOwner,
+        lease: Duration,
+    ) -> std::io::Result<SessionOwner> {
+        let now = Instant::now();
+        let mut owners = self.owners.lock().unwrap();
+        match owners.get(&session_id) {
+            Some((current, expiry)) if *expiry > now => Ok(current.clone()),
+            _ => {
+                owners.insert(session_id, (owner.clone(), now + lease));
+                Ok(owner.clone())
+            }
+        }
+    }
+
+    fn lookup(&self, session_id: u64) -> std::io::Result<Option<SessionOwner>> {
+        let now = Instant::now();
+        Ok(self
+            .owners
+            .lock()
+            .unwrap()
+            .get(&session_id)
+            .filter(|(_, expiry)| *expiry > now)
+            .map(|(owner, _)| owner.clone()))
+    }
+
+    fn renew(
+        &self,
+        session_id: u64,
+        owner: &SessionOwner,
+        lease: Duration,
+    ) -> std::io::Result<bool> {
+        let now = Instant::now();
+        let mut owners = self.owners.lock().unwrap();
+        match owners.get(&session_id) {
+            Some((current, expiry)) if current != owner && *expiry > now => Ok(false),
+            // a lease renewed late is taken again, unless another owner claimed the session
+            _ => {
+                owners.insert(session_id, (owner.clone(), now + lease));
+                Ok(true)
+            }
+        }
+    }
+
+    fn release(&self, session_id: u64, owner: &SessionOwner) -> std::io::Result<()> {
+        let mut owners = self.owners.lock().unwrap();
+        if owners
+            .get(&session_id)
+            .is_some_and(|(current, _)| current == owner)
+        {
+            owners.remove(&session_id);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io::Error,
+    str::FromStr,
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use sfu::WorkerPlacement;
+use tracing::{debug, info, warn};
+
+use crate::transport::handlers::{SignalingMessage, SignalingProtocolMessage};
+
+pub use self::memory::MemoryDirectory;
+pub use self::redis::RedisDirectory;
+
+mod memory;
+mod redis;
+
+/// How long a node proxying a request waits for the owner of the session to answer it
+const PROXY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Node and media worker owning a session, the node being named by the base URL of its
+/// signalling server.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SessionOwner {
+    pub node: String,
+    pub port: u16,
+}
+
+/// Ownership of the sessions shared by the nodes of a cluster. An owner holds a session for the
+/// lease it claimed or last renewed it with, the session being free to claim again once the
+/// lease expires.
+pub trait SessionDirectory: Send + Sync {
+    /// Claim `session_id` for `owner` unless another owner holds it, and return the owner
+    /// holding it afterwards.
+    fn claim(
+        &self,
+        session_id: u64,
+        owner: &SessionOwner,
+        lease: Duration,
+    ) -> std::io::Result<SessionOwner>;
+
+    fn lookup(&self, session_id: u64) -> std::io::Result<Option<SessionOwner>>;
+
+    /// Extend the lease of `owner`, returning false when another owner holds the session.
+    fn renew(
+        &self,
+        session_id: u64,
+        owner: &SessionOwner,
+        lease: Duration,
+    ) -> std::io::Result<bool>;
+
+    /// Give the session up, if `owner` still holds it.
+    fn release(&self, session_id: u64, owner: &SessionOwner) -> std::io::Result<()>;
+}
+
+/// Where the directory lives, `memory` for a single node or a `redis://` URL shared by the
+/// nodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DirectoryBackend {
+    Memory,
+    Redis(String),
+}
+
+impl FromStr for DirectoryBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "memory" {
+            Ok(DirectoryBackend::Memory)
+        } else if s.starts_with("redis://") || s.starts_with("rediss://") {
+            Ok(DirectoryBackend::Redis(s.to_string()))
+        } else {
+            Err(format!(
+                "invalid session directory {}, expected memory or a redis:// URL",
+                s
+            ))
+        }
+    }
+}
+
+impl DirectoryBackend {
+    pub fn open(&self) -> std::io::Result<Arc<dyn SessionDirectory>> {
+        Ok(match self {
+            DirectoryBackend::Memory => Arc::new(MemoryDirectory::new()),
+            DirectoryBackend::Redis(url) => Arc::new(RedisDirectory::open(url)?),
+        })
+    }
+}
+
+/// How a request for a session owned by another node reaches it.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Forwarding {
+    /// Answer with a temporary redirect to the owner
+    #[default]
+    Redirect,
+    /// Send the request to the owner and its response back
+    Proxy,
+}
+
+/// Where a request for a session is handled.
+#[derive(Debug)]
+pub enum Route {
+    Local,
+    Remote(SessionOwner),
+}
+
+/// Routes the requests for a session to the node owning it, claiming the sessions first
+/// requested on this node and renewing them while a worker hosts them.
+pub struct SessionRouter {
+    directory: Arc<dyn SessionDirectory>,
+    node: String,
+    worker_placement: WorkerPlacement,
+    lease: Duration,
+    forwarding: Forwarding,
+    /// shared by the requests proxied to the other nodes, to reuse their connections
+    proxy_client: reqwest::Client,
+    // session_id -> owner and time of the claim, for the sessions held by this node
+    claimed: Mutex<HashMap<u64, (SessionOwner, Instant)>>,
+}
+
+impl SessionRouter {
+    pub fn new(
+        directory: Arc<dyn SessionDirectory>,
+        node: String,
+        worker_placement: WorkerPlacement,
+        lease: Duration,
+        forwarding: Forwarding,
+    ) -> std::io::Result<Self> {
+        let proxy_client = reqwest::Client::builder()
+            .timeout(PROXY_TIMEOUT)
+            .build()
+            .map_err(Error::other)?;
+        Ok(SessionRouter {
+            directory,
+            node,
+            worker_placement,
+            lease,
+            forwarding,
+            proxy_client,
+            claimed: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn forwarding(&self) -> Forwarding {
+        self.forwarding
+    }
+
+    pub fn proxy_client(&self) -> &reqwest::Client {
+        &self.proxy_client
+    }
+
+    fn local_owner(&self, session_id: u64) -> SessionOwner {
+        SessionOwner {
+            node: self.node.clone(),
+            port: self
+                .worker_placement
+                .session_port(session_id)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Claim the session for this node unless another node owns it.
+    pub fn claim(&self, session_id: u64) -> std::io::Result<Route> {
+        let local_owner = self.local_owner(session_id);
+        let owner = self.directory.claim(session_id, &local_owner, self.lease)?;
+        if owner.node != self.node {
+            return Ok(Route::Remote(owner));
+        }
+        if let Entry::Vacant(entry) = self.claimed.lock().unwrap().entry(session_id) {
+            info!(
+                "session {} is owned by this node on port {}",
+                session_id, owner.port
+            );
+            entry.insert((owner, Instant::now()));
+        }
+        Ok(Route::Local)
+    }
+
+    /// Find the node owning the session, without claiming it.
+    pub fn lookup(&self, session_id: u64) -> std::io::Result<Route> {
+        Ok(match self.directory.lookup(session_id)? {
+            Some(owner) if owner.node != self.node => Route::Remote(owner),
+            _ => Route::Local,
+        })
+    }
+
+    /// Renew the leases of the sessions hosted by the workers, every third of a lease, and
+    /// release the ones no worker hosts anymore.
+    pub fn spawn_renewal(
+        self: &Arc<Self>,
+        signaling_txs: HashMap<u16, Sender<SignalingMessage>>,
+    ) -> std::io::Result<()> {
+        let router = Arc::clone(self);
+        std::thread::Builder::new()
+            .name("session-directory".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(router.lease / 3);
+                let hosted = hosted_sessions(&signaling_txs);
+                router.renew(&hosted);
+            })?;
+        Ok(())
+    }
+
+    fn renew(&self, hosted: &[u64]) {
+        let claimed: Vec<(u64, SessionOwner, Instant)> = self
+            .claimed
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&session_id, (owner, claimed_at))| (session_id, owner.clone(), *claimed_at))
+            .collect();
+
+        for (session_id, owner, claimed_at) in claimed {
+            if hosted.contains(&session_id) {
+                match self.directory.renew(session_id, &owner, self.lease) {
+                    Ok(true) => continue,
+                    Ok(false) => warn!("session {} was claimed by another node", session_id),
+                    Err(e) => {
+                        warn!("Failed to renew session {}: {}", session_id, e);
+                        continue;
+                    }
+                }
+            } else if claimed_at.elapsed() < self.lease {
+                // the worker may not have created the session yet
+                continue;
+            } else {
+                debug!("session {} is no longer hosted by this node", session_id);
+                if let Err(e) = self.directory.release(session_id, &owner) {
+                    warn!("Failed to release session {}: {}", session_id, e);
+                }
+            }
+            self.claimed.lock().unwrap().remove(&session_id);
+        }
+    }
+}
+
+/// Ask every worker for the sessions it hosts.
+fn hosted_sessions(signaling_txs: &HashMap<u16, Sender<SignalingMessage>>) -> Vec<u64> {
+    let mut hosted = vec![];
+    for tx in signaling_txs.values() {
+        let (response_tx, response_rx) = mpsc::channel();
+        if tx
+            .send(SignalingMessage {
+                request: SignalingProtocolMessage::GetSessions,
+                response_tx,
+            })
+            .is_err()
+        {
+            continue;
+        }
+        if let Ok(SignalingProtocolMessage::Sessions { session_ids }) = response_rx.recv() {
+            hosted.extend(session_ids);
+        }
+    }
+    hosted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEASE: Duration = Duration::from_millis(200);
+
+    fn owner(node: &str) -> SessionOwner {
+        SessionOwner {
+            node: node.to_string(),
+            port: 3478,
+        }
+    }
+
+    /// Run the lifecycle of a lease against a directory, a session id of its own keeping runs
+    /// against a shared server apart.
+    fn check_lease_lifecycle(directory: &dyn SessionDirectory) {
+        let session_id = rand::random::<u64>();
+        let (a, b) = (owner("http://node-a"), owner("http://node-b"));
+
+        assert_eq!(directory.lookup(session_id).unwrap(), None);
+        assert_eq!(directory.claim(session_id, &a, LEASE).unwrap(), a);
+        // another owner gets the one holding the session
+        assert_eq!(directory.claim(session_id, &b, LEASE).unwrap(), a);
+        assert_eq!(directory.lookup(session_id).unwrap(), Some(a.clone()));
+        assert!(!directory.renew(session_id, &b, LEASE).unwrap());
+        assert!(directory.renew(session_id, &a, LEASE).unwrap());
+
+        // a release by another owner leaves the session alone
+        directory.release(session_id, &b).unwrap();
+        assert_eq!(directory.lookup(session_id).unwrap(), Some(a.clone()));
+
+        // an expired lease is free to claim, and renewing it late doesn't take it back
+        std::thread::sleep(LEASE + LEASE / 2);
+        assert_eq!(directory.lookup(session_id).unwrap(), None);
+        assert_eq!(directory.claim(session_id, &b, LEASE).unwrap(), b);
+        assert!(!directory.renew(session_id, &a, LEASE).unwrap());
+
+        // a lease renewed late is taken again when nobody claimed the session meanwhile
+        std::thread::sleep(LEASE + LEASE / 2);
+        assert!(directory.renew(session_id, &b, LEASE).unwrap());
+        assert_eq!(directory.lookup(session_id).unwrap(), Some(b.clone()));
+
+        directory.release(session_id, &b).unwrap();
+        assert_eq!(directory.lookup(session_id).unwrap(), None);
+        assert_eq!(directory.claim(session_id, &a, LEASE).unwrap(), a);
+        directory.release(session_id, &a).unwrap();
+    }
+
+    #[test]
+    fn test_memory_directory() {
+        check_lease_lifecycle(&MemoryDirectory::new());
+    }
+
+    #[test]
+    #[ignore = "needs a local redis-server"]
+    fn test_redis_directory() {
+        let url =
+            std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        check_lease_lifecycle(&RedisDirectory::open(&url).unwrap());
+    }
+
+    #[test]
+    fn test_router_routes_to_owner() {
+        let directory: Arc<dyn SessionDirectory> = Arc::new(MemoryDirectory::new());
+        let router = |node: &str| {
+            SessionRouter::new(
+                Arc::clone(&directory),
+                node.to_string(),
+                WorkerPlacement::new(vec![3478], 1),
+                LEASE,
+                Forwarding::Proxy,
+            )
+            .unwrap()
+        };
+        let (router_a, router_b) = (router("http://node-a"), router("http://node-b"));
+
+        assert!(matches!(router_a.claim(7).unwrap(), Route::Local));
+        assert!(matches!(router_a.lookup(7).unwrap(), Route::Local));
+        match router_b.claim(7).unwrap() {
+            Route::Remote(remote) => assert_eq!(remote, owner("http://node-a")),
+            route => panic!("expected the session on node a, got {:?}", route),
+        }
+        assert!(matches!(router_b.lookup(7).unwrap(), Route::Remote(_)));
+        // a session nobody claimed is looked up locally
+        assert!(matches!(router_b.lookup(8).unwrap(), Route::Local));
+    }
+}
@@ -0,0 +1,149 @@
+use std::{io::Error, sync::Mutex, time::Duration};
+
+use ::redis::{Client, Connection, RedisError, Script};
+
+use super::{SessionDirectory, SessionOwner};
+
+const KEY_PREFIX: &str = "beep-sfu:session:";
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Extends the lease of the owner, or takes the session again once the lease expired
+const RENEW_SCRIPT: &str = r#"
+local current = redis.call('GET', KEYS[1])
+if current == ARGV[1] or not current then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+    return 1
+end
+return 0
+"#;
+
+const RELEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// Directory shared by the nodes through a Redis server, each session being a key holding its
+/// owner as JSON and expiring with its lease.
+pub struct RedisDirectory {
+    client: Client,
+    // reopened on the next request once it failed
+    connection: Mutex<Option<Connection>>,
+    renew_script: Script,
+    release_script: Script,
+}
+
+impl RedisDirectory {
+    pub fn open(url: &str) -> std::io::Result<Self> {
+        let client = Client::open(url).map_err(to_io_error)?;
+        let connection = client
+            .get_connection_with_timeout(CONNECTION_TIMEOUT)
+            .map_err(to_io_error)?;
+        Ok(RedisDirectory {
+            client,
+            connection: Mutex::new(Some(connection)),
+            renew_script: Script::new(RENEW_SCRIPT),
+            release_script: Script::new(RELEASE_SCRIPT),
+        })
+    }
+
+    fn with_connection<T>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, RedisError>,
+    ) -> std::io::Result<T> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_none() {
+            *connection = Some(
+                self.client
+                    .get_connection_with_timeout(CONNECTION_TIMEOUT)
+                    .map_err(to_io_error)?,
+            );
+        }
+        let result = f(connection.as_mut().unwrap());
+        if let Err(err) = &result {
+            if err.is_io_error() || err.is_connection_dropped() {
+                *connection = None;
+            }
+        }
+        result.map_err(to_io_error)
+    }
+}
+
+impl SessionDirectory for RedisDirectory {
+    fn claim(
+        &self,
+        session_id: u64,
+        owner: &SessionOwner,
+        lease: Duration,
+    ) -> std::io::Result<SessionOwner> {
+        let key = session_key(session_id);
+        let value = serde_json::to_string(owner)?;
+        let current: Option<String> = self.with_connection(|connection| {
+            let claimed: Option<String> = ::redis::cmd("SET")
+                .arg(&key)
+                .arg(&value)
+                .arg("NX")
+                .arg("PX")
+                .arg(lease.as_millis() as u64)
+                .query(connection)?;
+            if claimed.is_some() {
+                return Ok(Some(value.clone()));
+            }
+            ::redis::cmd("GET").arg(&key).query(connection)
+        })?;
+        match current {
+            Some(current) => Ok(serde_json::from_str(&current)?),
+            // the lease of the other owner expired in between
+            None => self.claim(session_id, owner, lease),
+        }
+    }
+
+    fn lookup(&self, session_id: u64) -> std::io::Result<Option<SessionOwner>> {
+        let current: Option<String> = self.with_connection(|connection| {
+            ::redis::cmd("GET")
+                .arg(session_key(session_id))
+                .query(connection)
+        })?;
+        match current {
+            Some(current) => Ok(Some(serde_json::from_str(&current)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn renew(
+        &self,
+        session_id: u64,
+        owner: &SessionOwner,
+        lease: Duration,
+    ) -> std::io::Result<bool> {
+        let value = serde_json::to_string(owner)?;
+        let renewed: i64 = self.with_connection(|connection| {
+            self.renew_script
+                .key(session_key(session_id))
+                .arg(&value)
+                .arg(lease.as_millis() as u64)
+                .invoke(connection)
+        })?;
+        Ok(renewed == 1)
+    }
+
+    fn release(&self, session_id: u64, owner: &SessionOwner) -> std::io::Result<()> {
+        let value = serde_json::to_string(owner)?;
+        let _: i64 = self.with_connection(|connection| {
+            self.release_script
+                .key(session_key(session_id))
+                .arg(&value)
+                .invoke(connection)
+        })?;
+        Ok(())
+    }
+}
+
+fn session_key(session_id: u64) -> String {
+    format!("{}{}", KEY_PREFIX, session_id)
+}
+
+fn to_io_error(err: RedisError) -> Error {
+    Error::other(err)
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use crossbeam_channel::{Receiver, Sender};
+use sfu::ServerEvent;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{debug, error};
+
+/// Frames buffered for a subscriber reading slower than events happen, after which it is
+/// dropped and expected to reconnect from its last event id.
+const SUBSCRIBER_BUFFER: usize = 256;
+
+/// A subscription to the events of a session, as Server-Sent Events frames.
+pub struct Subscription {
+    /// Frames of the backlog following the last event id the subscriber received, or a `reset`
+    /// event when some of the events following it are no longer kept
+    pub backlog: Vec<Bytes>,
+    pub rx: mpsc::Receiver<Bytes>,
+}
+
+#[derive(Default)]
+struct SessionStream {
+    /// Most recent frames, with their event id
+    backlog: VecDeque<(u64, Bytes)>,
+    /// Id of the last event dropped from the backlog
+    dropped_event_id: u64,
+    subscribers: Vec<mpsc::Sender<Bytes>>,
+    is_ended: bool,
+}
+
+#[derive(Default)]
+struct Streams {
+    next_event_id: u64,
+    sessions: HashMap<u64, SessionStream>,
+}
+
+/// Streams the events of the sessions hosted by the media workers to their subscribers, keeping
+/// the most recent ones of every session for subscribers resuming after a disconnection.
+///
+/// Event ids are shared by every session, so that an id names the same event whichever session
+/// it is resumed from.
+pub struct EventStreams {
+    backlog_len: usize,
+    streams: Mutex<Streams>,
+}
+
+impl EventStreams {
+    pub fn new(backlog_len: usize) -> Self {
+        EventStreams {
+            backlog_len,
+            streams: Mutex::new(Streams {
+                next_event_id: 1,
+                sessions: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Subscribe to the events of the session, from the one following `last_event_id` when
+    /// it is still in the backlog. A session may be subscribed to before it starts.
+    ///
+    /// A subscriber whose last event id is older than the backlog, or unknown to this node as
+    /// when it restarted, missed events and gets a `reset` event instead, telling it to fetch
+    /// the state of the session again.
+    pub fn subscribe(&self, session_id: u64, last_event_id: Option<u64>) -> Subscription {
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_BUFFER);
+        let mut streams = self.streams.lock().unwrap();
+        // sessions ended or never started whose subscribers went away
+        streams.sessions.retain(|_, stream| {
+            stream
+                .subscribers
+                .retain(|subscriber| !subscriber.is_closed());
+            !stream.subscribers.is_empty() || (!stream.is_ended && !stream.backlog.is_empty())
+        });
+
+        let latest_event_id = streams.next_event_id - 1;
+        let stream = streams.sessions.entry(session_id).or_default();
+        let backlog = match last_event_id {
+            Some(last_event_id)
+                if last_event_id < stream.dropped_event_id || last_event_id > latest_event_id =>
+            {
+                debug!(
+                    "Resetting subscriber of session {} from event {}",
+                    session_id, last_event_id
+                );
+                vec![reset_frame(session_id, latest_event_id)]
+            }
+            Some(last_event_id) => stream
+                .backlog
+                .iter()
+                .filter(|(event_id, _)| *event_id > last_event_id)
+                .map(|(_, frame)| frame.clone())
+                .collect(),
+            None => vec![],
+        };
+        stream.subscribers.push(tx);
+        Subscription { backlog, rx }
+    }
+
+    /// Send the event to the subscribers of its session, and keep it in the backlog.
+    pub fn publish(&self, event: &ServerEvent) {
+        let data = match serde_json::to_value(event) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to serialize event {:?}: {}", event, e);
+                return;
+            }
+        };
+        let event_type = data["type"].as_str().unwrap_or_default();
+
+        let mut streams = self.streams.lock().unwrap();
+        let event_id = streams.next_event_id;
+        streams.next_event_id += 1;
+        let frame = Bytes::from(format!(
+            "id: {}\nevent: {}\ndata: {}\n\n",
+            event_id, event_type, data
+        ));
+
+        let session_id = event.session_id();
+        let stream = streams.sessions.entry(session_id).or_default();
+        stream.is_ended = matches!(event, ServerEvent::SessionEnded { .. });
+        stream.backlog.push_back((event_id, frame.clone()));
+        while stream.backlog.len() > self.backlog_len {
+            if let Some((dropped_event_id, _)) = stream.backlog.pop_front() {
+                stream.dropped_event_id = dropped_event_id;
+            }
+        }
+        stream
+            .subscribers
+            .retain(|subscriber| match subscriber.try_send(frame.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    debug!("Dropping slow subscriber of session {}", session_id);
+                    false
+                }
+                Err(TrySendError::Closed(_)) => false,
+            });
+        if stream.is_ended && stream.subscribers.is_empty() {
+            streams.sessions.remove(&session_id);
+        }
+    }
+}
+
+/// Frame of a `reset` event, with the id of the latest event for a subscriber reconnecting
+/// after it to resume from there.
+fn reset_frame(session_id: u64, latest_event_id: u64) -> Bytes {
+    Bytes::from(format!(
+        "id: {}\nevent: reset\ndata: {}\n\n",
+        latest_event_id,
+        serde_json::json!({ "type": "reset", "session_id": session_id })
+    ))
+}
+
+/// Hand the events sent by the media workers to the event streams, and their lifecycle events
+/// to the webhooks when some are configured.
+pub fn spawn_fanout(
+    events_rx: Receiver<ServerEvent>,
+    event_streams: Arc<EventStreams>,
+    webhooks_tx: Option<Sender<ServerEvent>>,
+) -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name("events".to_string())
+        .spawn(move || {
+            for event in events_rx.iter() {
+                event_streams.publish(&event);
+                if let Some(webhooks_tx) = &webhooks_tx {
+                    if event.is_lifecycle() {
+                        // the workers aren't held back by a dispatcher behind on its deliveries
+                        if let Err(crossbeam_channel::TrySendError::Full(event)) =
+                            webhooks_tx.try_send(event)
+                        {
+                            error!(
+                                "Dropping webhook event {:?}, the dispatcher is behind",
+                                event
+                            );
+                        }
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joined(session_id: u64, endpoint_id: u64) -> ServerEvent {
+        ServerEvent::EndpointJoined {
+            session_id,
+            endpoint_id,
+        }
+    }
+
+    fn frames(frames: &[Bytes]) -> Vec<String> {
+        frames
+            .iter()
+            .map(|frame| String::from_utf8(frame.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_resume_from_last_event_id() {
+        let event_streams = EventStreams::new(4);
+        event_streams.publish(&joined(1, 1));
+        event_streams.publish(&joined(2, 1));
+        event_streams.publish(&joined(1, 2));
+
+        let Subscription { backlog, mut rx } = event_streams.subscribe(1, Some(1));
+        assert_eq!(
+            frames(&backlog),
+            [
+                "id: 3\nevent: endpoint_joined\ndata: {\"endpoint_id\":2,\"session_id\":1,\"type\":\"endpoint_joined\"}\n\n"
+            ]
+        );
+        event_streams.publish(&joined(1, 3));
+        assert!(String::from_utf8(rx.try_recv().unwrap().to_vec())
+            .unwrap()
+            .starts_with("id: 4\n"));
+
+        // a subscriber without a last event id only gets the events from then on
+        assert!(event_streams.subscribe(1, None).backlog.is_empty());
+        assert!(event_streams.subscribe(1, Some(4)).backlog.is_empty());
+    }
+
+    #[test]
+    fn test_reset_when_older_than_backlog() {
+        let event_streams = EventStreams::new(2);
+        for endpoint_id in 1..=4 {
+            event_streams.publish(&joined(1, endpoint_id));
+        }
+
+        // events 1 and 2 are no longer kept, resuming after event 2 misses none
+        assert_eq!(event_streams.subscribe(1, Some(2)).backlog.len(), 2);
+        assert_eq!(
+            frames(&event_streams.subscribe(1, Some(1)).backlog),
+            ["id: 4\nevent: reset\ndata: {\"session_id\":1,\"type\":\"reset\"}\n\n"]
+        );
+    }
+
+    #[test]
+    fn test_reset_when_unknown_event_id() {
+        // as when the node restarted, its event ids starting over
+        let event_streams = EventStreams::new(2);
+        event_streams.publish(&joined(1, 1));
+        assert_eq!(
+            frames(&event_streams.subscribe(1, Some(42)).backlog),
+            ["id: 1\nevent: reset\ndata: {\"session_id\":1,\"type\":\"reset\"}\n\n"]
+        );
+    }
+}
@@ -1,3 +1,6 @@
+// the shipping of the logs to Loki is only used by the prod logger, disabled for now
+#![allow(dead_code)]
+
 use std::{
     fs::File,
     io::{BufRead, BufReader},
@@ -7,7 +10,7 @@ use chrono::DateTime;
 use reqwest::Client;
 use serde_json::json;
 use tracing_log::LogTracer;
-use tracing_subscriber::{fmt};
+use tracing_subscriber::fmt;
 
 use crate::logging::log_type::Log;
 
@@ -16,25 +19,25 @@ static LOG_FILE: &str = "./var/beep-sfu/";
 mod log_type;
 
 pub fn init_logger(
-    env: &str,
+    _env: &str,
 ) -> Result<tracing_appender::non_blocking::WorkerGuard, Box<dyn std::error::Error>> {
     // if env == "prod" {
     //     let file_appender = tracing_appender::rolling::hourly(LOG_FILE, "beep-sfu.log");
-    // 
+    //
     //     let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
-    // 
+    //
     //     let subscriber = fmt()
     //         .json()
     //         .with_thread_names(true)
     //         .with_writer(non_blocking)
     //         .finish();
-    // 
+    //
     //     //trace with json
-    // 
+    //
     //     tracing::subscriber::set_global_default(subscriber)?;
-    // 
+    //
     //     // tracing::subscriber::set_global_default(subscriber)?;
-    // 
+    //
     //     actix_rt::spawn(async {
     //         loop {
     //             tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
@@ -49,23 +52,23 @@ pub fn init_logger(
     //                 };
     //         }
     //     });
-    // 
+    //
     //     Ok(guard)
     // } else {
-        LogTracer::init().expect("Failed to set logger");
+    LogTracer::init().expect("Failed to set logger");
 
-        // let filter = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info"))?;
+    // let filter = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info"))?;
 
-        let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stdout());
+    let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stdout());
 
-        let subscriber = fmt()
-            .with_max_level(tracing::Level::DEBUG)
-            .with_thread_names(true)
-            .with_writer(non_blocking)
-            .finish();
+    let subscriber = fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_thread_names(true)
+        .with_writer(non_blocking)
+        .finish();
 
-        tracing::subscriber::set_global_default(subscriber)?;
-        Ok(guard)
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(guard)
     // }
 }
 
@@ -138,7 +141,7 @@ pub async fn send_logs_to_loki(loki_endpoint: String) -> Result<(), reqwest::Err
             logs_parsed.push(PushLog {
                 timestamp: log_parsed.timestamp,
                 log: line.to_string(),
-                log_source: log_source,
+                log_source,
             });
         });
     });
@@ -177,7 +180,7 @@ async fn send_single_log(
 
     // println!("Payload: {:?}", payload_str);
 
-    let response = client
+    let _response = client
         .post(loki_endpoint)
         .body(payload_str)
         .header("Content-Type", "application/json")
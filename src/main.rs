@@ -1,49 +1,66 @@
+use std::net::SocketAddr;
 /**
  * @authors Mathias Durat <mathias.durat@etu.umontpellier.fr>, Tristan-Mihai Radulescu <tristan-mihai.radulescu@etu.umontpellier.fr>
  * @forked_from https://github.com/webrtc-rs/sfu (Rusty Rain <y@ngr.tc>)
  */
 use std::{
     collections::HashMap,
-    net::{IpAddr, UdpSocket},
+    net::{IpAddr, TcpListener, ToSocketAddrs, UdpSocket},
     str::FromStr,
-    sync::{mpsc, Arc},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
 };
-use std::net::SocketAddr;
 
-use actix_web::rt::signal;
-use clap::{command, Parser};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use clap::Parser;
 use dtls::extension::extension_use_srtp::SrtpProtectionProfile;
 use log::info;
-use sfu::RTCCertificate;
-use signalling::web_server;
+use sfu::{RTCCertificate, WorkerPlacement};
+use signalling::web_server::{self, WebServerConfig};
 use tracing::span;
 use wg::WaitGroup;
 
-use crate::transport::sync_run;
+use crate::directory::{DirectoryBackend, Forwarding, SessionRouter};
+use crate::events::EventStreams;
+use crate::middleware::verify_jwt::TokenVerifier;
+use crate::relay::{CredentialIssuer, PeerNetwork, RelayConfig, TurnUser};
+use crate::transport::candidates::{self, AnnouncedAddress};
+use crate::transport::cascade::CascadeNodes;
+use crate::transport::rtmp::RtmpWorkers;
+use crate::transport::tcp::{self, SharedPortWorker};
+use crate::transport::{sync_run, WorkerConfig, MEDIA_INPUT_QUEUE_LEN};
+use crate::webhook::WebhookConfig;
 
+mod directory;
+mod events;
 mod logging;
 mod middleware;
+mod relay;
 mod signalling;
 mod transport;
+mod webhook;
 
 #[derive(Default, Debug, Clone, Copy, clap::ValueEnum)]
 enum Level {
-    ERROR,
-    WARN,
+    Error,
+    Warn,
     #[default]
-    INFO,
-    DEBUG,
-    TRACE,
+    Info,
+    Debug,
+    Trace,
 }
 
 impl From<Level> for tracing::Level {
     fn from(level: Level) -> Self {
         match level {
-            Level::ERROR => tracing::Level::ERROR,
-            Level::WARN => tracing::Level::WARN,
-            Level::INFO => tracing::Level::INFO,
-            Level::DEBUG => tracing::Level::DEBUG,
-            Level::TRACE => tracing::Level::TRACE,
+            Level::Error => tracing::Level::ERROR,
+            Level::Warn => tracing::Level::WARN,
+            Level::Info => tracing::Level::INFO,
+            Level::Debug => tracing::Level::DEBUG,
+            Level::Trace => tracing::Level::TRACE,
         }
     }
 }
@@ -60,19 +77,197 @@ struct Cli {
     host: String,
     #[arg(long, default_value_t = format!("127.0.0.1"))]
     ip_endpoint: String,
+    /// Address announced as ICE candidate, as IP or IP:PORT where PORT is the external port of
+    /// --media-port-min (NodePort-style remapping). Repeatable, replaces --ip-endpoint when set.
+    #[arg(long = "announced-address", value_delimiter = ',')]
+    announced_addresses: Vec<AnnouncedAddress>,
+    /// STUN server (HOST:PORT) used to discover the public address of every media port
+    #[arg(long)]
+    stun_server: Option<String>,
     #[arg(short, long, default_value_t = 8080)]
     signal_port: u16,
+    /// Secret the HS256 bearer tokens of the REST API are verified with (the routes requiring
+    /// a token refuse every request when unset)
+    #[arg(long)]
+    jwt_secret: Option<String>,
     #[arg(long, default_value_t = 3478)]
     media_port_min: u16,
     #[arg(long, default_value_t = 3479)]
     media_port_max: u16,
+    /// First port of the passive ICE-TCP listeners, one per media worker (disabled when unset)
+    #[arg(long)]
+    tcp_media_port_min: Option<u16>,
+    /// Port shared by all media workers accepting ICE-TCP, either plain or wrapped in TLS
+    #[arg(long, requires_all = ["tls_cert", "tls_key"])]
+    tls_port: Option<u16>,
+    /// Connections served at once by the ICE-TCP and shared ports, further ones being refused
+    #[arg(long, default_value_t = 1024)]
+    tcp_max_connections: usize,
+    /// PEM certificate chain served on --tls-port
+    #[arg(long)]
+    tls_cert: Option<String>,
+    /// PEM private key of --tls-cert
+    #[arg(long)]
+    tls_key: Option<String>,
+    /// Port of the embedded TURN server, on both UDP and TCP (disabled when unset)
+    #[arg(long, requires = "turn_relay_address")]
+    turn_port: Option<u16>,
+    /// Public IP the TURN relays are reached at by peers, announced to clients as their relayed
+    /// address
+    #[arg(long)]
+    turn_relay_address: Option<IpAddr>,
+    /// Network the TURN server may relay to although it is internal (loopback, private,
+    /// link-local...), as ADDRESS/PREFIX_LEN. Repeatable
+    #[arg(long = "turn-allowed-peer", value_delimiter = ',')]
+    turn_allowed_peers: Vec<PeerNetwork>,
+    #[arg(long, default_value_t = format!("beep"))]
+    turn_realm: String,
+    /// Static long-term TURN credential, as USERNAME:PASSWORD. Repeatable
+    #[arg(long = "turn-user", value_delimiter = ',')]
+    turn_users: Vec<TurnUser>,
+    /// Secret shared with other TURN REST API issuers, random when unset
+    #[arg(long)]
+    turn_secret: Option<String>,
+    /// Lifetime in seconds of the credentials handed out by /turn/credentials
+    #[arg(long, default_value_t = 86400)]
+    turn_credential_ttl: u64,
+    #[arg(long, default_value_t = 49152)]
+    turn_relay_port_min: u16,
+    #[arg(long, default_value_t = 65535)]
+    turn_relay_port_max: u16,
+    /// Allocations a TURN user may hold at once, further ones being refused
+    #[arg(long, default_value_t = 10)]
+    turn_max_allocations_per_user: usize,
+    /// Number of packets kept per forwarded stream to answer NACKs, rounded up to a power of two
+    #[arg(long, default_value_t = 1024)]
+    nack_buffer_size: u16,
+    /// Minimum interval in milliseconds between two keyframe requests sent for a publisher's stream
+    #[arg(long, default_value_t = 500)]
+    keyframe_request_interval: u64,
+    /// Number of most recent dominant speakers whose video each endpoint receives, next to the
+    /// endpoints it pins (unlimited when unset)
+    #[arg(long)]
+    last_n: Option<usize>,
+    /// Audio codecs allowed, by name or mime type, the preferred first (all when unset)
+    #[arg(long, value_delimiter = ',')]
+    audio_codecs: Vec<String>,
+    /// Video codecs allowed, by name or mime type, the preferred first (all when unset)
+    #[arg(long, value_delimiter = ',')]
+    video_codecs: Vec<String>,
+    /// H.264 profile-level-ids allowed (all when unset)
+    #[arg(long, value_delimiter = ',')]
+    h264_profile_level_ids: Vec<String>,
+    /// Disable RTX retransmissions
+    #[arg(long, default_value_t = false)]
+    no_rtx: bool,
+    /// Disable RED redundant encoding
+    #[arg(long, default_value_t = false)]
+    no_red: bool,
+    /// Disable FEC
+    #[arg(long, default_value_t = false)]
+    no_fec: bool,
+    /// Directory sessions are recorded into, through the REST API (recording disabled when unset)
+    #[arg(long)]
+    recording_dir: Option<std::path::PathBuf>,
+    /// Directory packet captures are written into, through the REST API (capture disabled when
+    /// unset)
+    #[arg(long)]
+    capture_dir: Option<std::path::PathBuf>,
+    /// Size in megabytes a packet capture stops at
+    #[arg(long, default_value_t = 100)]
+    capture_max_size: u64,
+    /// Duration in seconds a packet capture stops after
+    #[arg(long, default_value_t = 600)]
+    capture_max_duration: u64,
+    /// Directory media files are injected into sessions from, through the REST API (injection
+    /// disabled when unset)
+    #[arg(long)]
+    media_dir: Option<std::path::PathBuf>,
+    /// Directory the SDP files of plain RTP egresses are written into, through the REST API
+    /// (egress disabled when unset)
+    #[arg(long)]
+    egress_dir: Option<std::path::PathBuf>,
+    /// Host plain RTP egresses may forward media to, any other one being refused. Repeatable
+    #[arg(long = "egress-allowed-host", value_delimiter = ',')]
+    egress_allowed_hosts: Vec<IpAddr>,
+    /// Directory the HLS playlists and segments of sessions are written into, and served from
+    /// at /hls/SESSION_ID/index.m3u8 (HLS disabled when unset)
+    #[arg(long)]
+    hls_dir: Option<std::path::PathBuf>,
+    /// Port of the RTMP listener, publishing H.264 and Opus into sessions at
+    /// rtmp://HOST:PORT/SESSION_ID with the endpoint id as stream key (disabled when unset)
+    #[arg(long)]
+    rtmp_port: Option<u16>,
+    /// Media workers a session may span, its endpoints being spread over them and their tracks
+    /// relayed between them (a session stays on a single worker by default)
+    #[arg(long, default_value_t = 1)]
+    session_workers: usize,
+    /// Port other nodes open links to, relaying the tracks of the sessions hosted by several
+    /// nodes (disabled when unset)
+    #[arg(long, requires = "cascade_secret")]
+    cascade_port: Option<u16>,
+    /// Secret shared by the nodes, signing the links between them (cascading disabled when
+    /// unset)
+    #[arg(long)]
+    cascade_secret: Option<String>,
+    /// Directory of the sessions owned by the nodes of a cluster, `memory` for a single node or
+    /// a redis:// URL shared by the nodes
+    #[arg(long, default_value = "memory")]
+    session_directory: DirectoryBackend,
+    /// Base URL other nodes reach the signalling server of this node at, naming it in the
+    /// session directory (http://HOST:SIGNAL_PORT when unset)
+    #[arg(long)]
+    node_address: Option<String>,
+    /// Lifetime in seconds of the ownership of a session, renewed while a worker hosts it
+    #[arg(long, default_value_t = 30)]
+    session_lease: u64,
+    /// How the requests for a session owned by another node reach it
+    #[arg(long, default_value_t = Forwarding::Redirect)]
+    #[clap(value_enum)]
+    session_forwarding: Forwarding,
+    /// URL notified of the lifecycle of sessions, endpoints and tracks, with a signed JSON POST.
+    /// Repeatable (notifications disabled when unset)
+    #[arg(
+        long = "webhook-url",
+        value_delimiter = ',',
+        requires = "webhook_secret"
+    )]
+    webhook_urls: Vec<String>,
+    /// Secret the webhook notifications are signed with, as HMAC-SHA256
+    #[arg(long)]
+    webhook_secret: Option<String>,
+    /// Directory the webhook notifications not acknowledged yet are kept in, and retried from
+    /// after a restart
+    #[arg(long, default_value = "./var/beep-sfu/webhooks")]
+    webhook_queue_dir: std::path::PathBuf,
+    /// Attempts after which a webhook notification is given up, retries backing off
+    /// exponentially up to 5 minutes apart
+    #[arg(long, default_value_t = 10)]
+    webhook_max_attempts: u32,
+    /// Webhook notifications kept waiting for a retry, on disk and in memory, the oldest being
+    /// given up past it
+    #[arg(long, default_value_t = 10000)]
+    webhook_max_pending: usize,
+    /// Most recent events of every session kept for the clients resuming their event stream at
+    /// /sessions/SESSION_ID/events
+    #[arg(long, default_value_t = 256)]
+    event_backlog: usize,
+    /// Size in bytes of the largest chat message an endpoint may send over its data channel
+    #[arg(long, default_value_t = 4096)]
+    chat_max_message_size: usize,
+    /// Chat messages per second an endpoint may send over its data channel (unlimited when 0)
+    #[arg(long, default_value_t = 5)]
+    chat_rate: u32,
+    /// Chat messages an endpoint may send at once, above --chat-rate
+    #[arg(long, default_value_t = 10)]
+    chat_burst: u32,
 
     #[arg(short, long, default_value_t = format!("prod"))]
     env: String,
 
     #[arg(short, long)]
     debug: bool,
-    #[arg(short, long, default_value_t = Level::INFO)]
+    #[arg(short, long, default_value_t = Level::Info)]
     #[clap(value_enum)]
     level: Level,
 }
@@ -81,22 +276,53 @@ struct Cli {
 async fn main() -> std::io::Result<()> {
     let cli = Cli::parse();
 
-    let guard = logging::init_logger(&cli.env).unwrap(); //better error handling needed
+    let _guard = logging::init_logger(&cli.env).unwrap(); //better error handling needed
     let root = span!(tracing::Level::INFO, "main");
 
     let _enter = root.enter();
     tracing::info!("Starting Beep SFU Server");
 
-    let host_addr= IpAddr::from_str(&*cli.host).map_err(|e| {
+    let host_addr = IpAddr::from_str(&cli.host).map_err(|e| {
         tracing::error!("Failed to parse host address: {:?}", e);
-        std::io::Error::new(std::io::ErrorKind::Other, "Failed to parse host address")
+        std::io::Error::other("Failed to parse host address")
     })?;
 
-    let ip_endpoint = IpAddr::from_str(&*cli.ip_endpoint).map_err(|e| {
+    let ip_endpoint = IpAddr::from_str(&cli.ip_endpoint).map_err(|e| {
         tracing::error!("Failed to parse host address: {:?}", e);
-        std::io::Error::new(std::io::ErrorKind::Other, "Failed to parse host address")
+        std::io::Error::other("Failed to parse host address")
     })?;
 
+    let announced_addresses = if cli.announced_addresses.is_empty() {
+        vec![AnnouncedAddress {
+            ip: ip_endpoint,
+            port_base: None,
+        }]
+    } else {
+        cli.announced_addresses.clone()
+    };
+    for announced in &announced_addresses {
+        announced
+            .check_port_range(cli.media_port_min, cli.media_port_max)
+            .map_err(|e| {
+                tracing::error!("{}", e);
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+            })?;
+    }
+
+    let stun_server = match &cli.stun_server {
+        Some(stun_server) => Some(
+            stun_server
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.find(|addr| addr.is_ipv4() == host_addr.is_ipv4()))
+                .ok_or_else(|| {
+                    tracing::error!("Failed to resolve stun server {}", stun_server);
+                    std::io::Error::other("Failed to resolve stun server")
+                })?,
+        ),
+        None => None,
+    };
+
     let media_ports: Vec<u16> = (cli.media_port_min..=cli.media_port_max).collect();
 
     let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(1);
@@ -106,7 +332,7 @@ async fn main() -> std::io::Result<()> {
 
     let key_pair = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256).map_err(|e| {
         tracing::error!("Failed to generate key pair: {:?}", e);
-        std::io::Error::new(std::io::ErrorKind::Other, "Failed to generate key pair")
+        std::io::Error::other("Failed to generate key pair")
     })?;
 
     let certificates =
@@ -126,36 +352,240 @@ async fn main() -> std::io::Result<()> {
             .build(false, None)
             .map_err(|e| {
                 tracing::error!("Failed to build dtls handshake config: {:?}", e);
-                std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Failed to build dtls handshake config",
-                )
+                std::io::Error::other("Failed to build dtls handshake config")
             })?,
     );
 
     let sctp_endpoint_config = Arc::new(sctp::EndpointConfig::default());
     let sctp_server_config = Arc::new(sctp::ServerConfig::default());
-    let server_config = Arc::new(
-        sfu::ServerConfig::new(certificates)
-            .with_dtls_handshake_config(dtls_handshake_config)
-            .with_sctp_endpoint_config(sctp_endpoint_config)
-            .with_sctp_server_config(sctp_server_config),
-    );
+    let mut server_config = sfu::ServerConfig::new(certificates)
+        .with_media_config(sfu::MediaConfig::with_nack_buffer_size(
+            cli.nack_buffer_size,
+        ))
+        .with_keyframe_request_interval(Duration::from_millis(cli.keyframe_request_interval))
+        .with_capture_limits(
+            cli.capture_max_size * 1024 * 1024,
+            Duration::from_secs(cli.capture_max_duration),
+        )
+        .with_chat_limits(cli.chat_max_message_size, cli.chat_rate, cli.chat_burst)
+        .with_codec_policy(
+            sfu::CodecPolicy::default()
+                .with_audio_codecs(cli.audio_codecs)
+                .with_video_codecs(cli.video_codecs)
+                .with_h264_profile_level_ids(cli.h264_profile_level_ids)
+                .with_rtx(!cli.no_rtx)
+                .with_red(!cli.no_red)
+                .with_fec(!cli.no_fec),
+        )
+        .with_dtls_handshake_config(dtls_handshake_config)
+        .with_sctp_endpoint_config(sctp_endpoint_config)
+        .with_sctp_server_config(sctp_server_config);
+    if let Some(last_n) = cli.last_n {
+        server_config = server_config.with_last_n(last_n);
+    }
+    if let Some(recording_dir) = cli.recording_dir {
+        server_config = server_config.with_recording_dir(recording_dir);
+    }
+    if let Some(capture_dir) = cli.capture_dir {
+        server_config = server_config.with_capture_dir(capture_dir);
+    }
+    if let Some(media_dir) = cli.media_dir {
+        server_config = server_config.with_media_dir(media_dir);
+    }
+    if let Some(egress_dir) = cli.egress_dir {
+        if cli.egress_allowed_hosts.is_empty() {
+            tracing::warn!("No --egress-allowed-host, every egress is refused");
+        }
+        server_config = server_config
+            .with_egress_dir(egress_dir)
+            .with_egress_allowed_hosts(cli.egress_allowed_hosts);
+    }
+    if let Some(hls_dir) = cli.hls_dir.clone() {
+        server_config = server_config.with_hls_dir(hls_dir);
+    }
+    let worker_placement = WorkerPlacement::new(media_ports.clone(), cli.session_workers);
+    if worker_placement.is_cascaded() {
+        info!("Sessions span up to {} media workers", cli.session_workers);
+    }
+    server_config = server_config.with_worker_placement(worker_placement.clone());
+    let server_config = Arc::new(server_config);
 
     let wait_group = WaitGroup::new();
 
+    let tls_listener = match cli.tls_port {
+        Some(tls_port) => Some(
+            TcpListener::bind(format!("{host_addr}:{tls_port}")).map_err(|e| {
+                tracing::error!("Failed to bind tls listener: {:?}", e);
+                std::io::Error::other("Failed to bind tls listener")
+            })?,
+        ),
+        None => None,
+    };
+    let mut shared_port_workers = HashMap::new();
+
+    let rtmp_listener = match cli.rtmp_port {
+        Some(rtmp_port) => Some(
+            TcpListener::bind(format!("{host_addr}:{rtmp_port}")).map_err(|e| {
+                tracing::error!("Failed to bind rtmp listener: {:?}", e);
+                std::io::Error::other("Failed to bind rtmp listener")
+            })?,
+        ),
+        None => None,
+    };
+    let mut rtmp_media_txs = HashMap::new();
+
+    let cascade_listener = match cli.cascade_port {
+        Some(cascade_port) => Some(
+            TcpListener::bind(format!("{host_addr}:{cascade_port}")).map_err(|e| {
+                tracing::error!("Failed to bind cascade listener: {:?}", e);
+                std::io::Error::other("Failed to bind cascade listener")
+            })?,
+        ),
+        None => None,
+    };
+
+    let webhooks_tx = match &cli.webhook_secret {
+        Some(webhook_secret) if !cli.webhook_urls.is_empty() => Some(
+            webhook::spawn_dispatcher(WebhookConfig {
+                urls: cli.webhook_urls.clone(),
+                secret: webhook_secret.clone(),
+                queue_dir: cli.webhook_queue_dir.clone(),
+                max_attempts: cli.webhook_max_attempts.max(1),
+                max_pending: cli.webhook_max_pending.max(1),
+            })
+            .map_err(|e| {
+                tracing::error!("Failed to start webhook dispatcher: {:?}", e);
+                e
+            })?,
+        ),
+        _ => None,
+    };
+    let event_streams = Arc::new(EventStreams::new(cli.event_backlog));
+    let (events_tx, events_rx) = crossbeam_channel::unbounded();
+    events::spawn_fanout(events_rx, event_streams.clone(), webhooks_tx)?;
+
+    // the inputs of every worker exist before any starts, sessions spanning several workers
+    // relaying their tracks through them
+    let mut media_channels: HashMap<u16, _> = media_ports
+        .iter()
+        .map(|&port| (port, crossbeam_channel::bounded(MEDIA_INPUT_QUEUE_LEN)))
+        .collect();
+    let relay_txs: HashMap<u16, crossbeam_channel::Sender<transport::MediaInput>> = media_channels
+        .iter()
+        .map(|(&port, (media_tx, _))| (port, media_tx.clone()))
+        .collect();
+
+    let mut sockets = HashMap::new();
+    for &port in &media_ports {
+        let socket = UdpSocket::bind(format!("{host_addr}:{port}")).map_err(|e| {
+            tracing::error!("Failed to bind udp socket: {:?}", e);
+            std::io::Error::other("Failed to bind udp socket")
+        })?;
+        sockets.insert(port, socket);
+    }
+    // every port is mapped on its own, and discovered at the same time as the others so that an
+    // unreachable STUN server delays the startup by a single timeout
+    let mapped_addrs: HashMap<u16, SocketAddr> = match stun_server {
+        Some(stun_server) => std::thread::scope(|scope| {
+            let discoveries: Vec<_> = sockets
+                .iter()
+                .map(|(&port, socket)| {
+                    (
+                        port,
+                        scope.spawn(move || {
+                            candidates::discover_mapped_address(
+                                socket,
+                                stun_server,
+                                Duration::from_secs(1),
+                            )
+                        }),
+                    )
+                })
+                .collect();
+            discoveries
+                .into_iter()
+                .filter_map(|(port, discovery)| match discovery.join() {
+                    Ok(Ok(mapped)) => Some((port, mapped)),
+                    Ok(Err(e)) => {
+                        tracing::warn!(
+                            "Failed to discover public address of port {}: {:?}",
+                            port,
+                            e
+                        );
+                        None
+                    }
+                    Err(_) => None,
+                })
+                .collect()
+        }),
+        None => HashMap::new(),
+    };
+
+    let tcp_connection_limit = tcp::ConnectionLimit::new(cli.tcp_max_connections);
+    let tcp_stopping = Arc::new(AtomicBool::new(false));
     info!("Starting media server with {} workers", media_ports.len());
     for port in media_ports {
         let worker = wait_group.add(1);
         let stop_rx = stop_rx.clone();
         let (signaling_tx, signaling_rx) = mpsc::channel();
+        let (media_tx, media_rx) = media_channels
+            .remove(&port)
+            .expect("media channel of every port");
 
-        let socket = UdpSocket::bind(format!("{host_addr}:{port}")).map_err(|e| {
-            tracing::error!("Failed to bind udp socket: {:?}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, "Failed to bind udp socket")
-        })?;
+        let socket = sockets.remove(&port).expect("socket of every port");
         let socket_endpoint = SocketAddr::new(ip_endpoint, port);
 
+        let mut candidate_addrs =
+            candidates::candidate_addrs(&announced_addresses, port, cli.media_port_min);
+        if let Some(&mapped) = mapped_addrs.get(&port) {
+            if !candidate_addrs.contains(&mapped) {
+                candidate_addrs.push(mapped);
+            }
+        }
+        info!("Announcing {:?} for media port {}", candidate_addrs, port);
+
+        let tcp_port = cli
+            .tcp_media_port_min
+            .map(|tcp_media_port_min| tcp_media_port_min + (port - cli.media_port_min));
+        let tcp_listener = match tcp_port {
+            Some(tcp_port) => Some(
+                TcpListener::bind(format!("{host_addr}:{tcp_port}")).map_err(|e| {
+                    tracing::error!("Failed to bind tcp listener: {:?}", e);
+                    std::io::Error::other("Failed to bind tcp listener")
+                })?,
+            ),
+            None => None,
+        };
+        let mut tcp_candidate_addrs = vec![];
+        for tcp_port in tcp_port.iter().chain(cli.tls_port.iter()) {
+            for announced in &announced_addresses {
+                let addr = SocketAddr::new(announced.ip, *tcp_port);
+                if !tcp_candidate_addrs.contains(&addr) {
+                    tcp_candidate_addrs.push(addr);
+                }
+            }
+        }
+        shared_port_workers.insert(
+            port,
+            SharedPortWorker {
+                local_addr: socket_endpoint,
+                media_tx: media_tx.clone(),
+            },
+        );
+
+        rtmp_media_txs.insert(port, media_tx.clone());
+
+        let worker_config = WorkerConfig {
+            server_ip: socket_endpoint,
+            candidate_addrs,
+            tcp_candidate_addrs,
+            tcp_listener,
+            tcp_connection_limit: tcp_connection_limit.clone(),
+            ice_ufrag_prefix: tcp::ice_ufrag_prefix(port),
+            relay_txs: relay_txs.clone(),
+            events_tx: events_tx.clone(),
+        };
+
         media_port_thread_map.insert(port, signaling_tx);
         let server_config = server_config.clone();
 
@@ -163,7 +593,15 @@ async fn main() -> std::io::Result<()> {
             //write sfu handler here
             let _span = span!(tracing::Level::INFO, "worker", port = port).entered();
 
-            match sync_run(stop_rx, socket, signaling_rx, server_config, socket_endpoint) {
+            match sync_run(
+                stop_rx,
+                socket,
+                signaling_rx,
+                media_tx,
+                media_rx,
+                server_config,
+                worker_config,
+            ) {
                 Ok(_) => (),
                 Err(e) => {
                     tracing::error!("Failed to run sfu: {:?}", e);
@@ -174,18 +612,130 @@ async fn main() -> std::io::Result<()> {
         });
     }
 
+    if let Some(tls_listener) = tls_listener {
+        let tls_config = match (&cli.tls_cert, &cli.tls_key) {
+            (Some(cert), Some(key)) => {
+                Some(Arc::new(tcp::load_tls_config(cert, key).map_err(|e| {
+                    tracing::error!("Failed to load tls certificate: {:?}", e);
+                    e
+                })?))
+            }
+            _ => None,
+        };
+        tcp::spawn_shared_listener(
+            tls_listener,
+            tls_config,
+            shared_port_workers,
+            tcp_connection_limit,
+            tcp_stopping.clone(),
+        )?;
+    }
+
+    if let Some(rtmp_listener) = rtmp_listener {
+        transport::rtmp::spawn_listener(
+            rtmp_listener,
+            RtmpWorkers {
+                signaling_txs: media_port_thread_map.clone(),
+                media_txs: rtmp_media_txs,
+                worker_placement: worker_placement.clone(),
+            },
+        )?;
+    }
+
+    let cascade_nodes = cli.cascade_secret.clone().map(|cascade_secret| {
+        CascadeNodes::new(cascade_secret, relay_txs.clone(), worker_placement.clone())
+    });
+    if let (Some(cascade_listener), Some(cascade_nodes)) = (cascade_listener, &cascade_nodes) {
+        transport::cascade::spawn_listener(cascade_listener, cascade_nodes.clone())?;
+    }
+
+    let (turn_server, credential_issuer) = match (cli.turn_port, cli.turn_relay_address) {
+        (Some(turn_port), Some(turn_relay_address)) => {
+            let shared_secret = cli
+                .turn_secret
+                .clone()
+                .unwrap_or_else(|| BASE64_STANDARD.encode(rand::random::<[u8; 32]>()));
+            let turn_server = relay::start(RelayConfig {
+                listen_addr: SocketAddr::new(host_addr, turn_port),
+                relay_ip: turn_relay_address,
+                relay_port_min: cli.turn_relay_port_min,
+                relay_port_max: cli.turn_relay_port_max,
+                realm: cli.turn_realm.clone(),
+                users: cli.turn_users.clone(),
+                shared_secret: shared_secret.clone(),
+                allowed_peers: cli.turn_allowed_peers.clone(),
+                max_allocations_per_user: cli.turn_max_allocations_per_user,
+                connection_limit: tcp::ConnectionLimit::new(cli.tcp_max_connections),
+            })?;
+
+            let mut uris = vec![];
+            for announced in &announced_addresses {
+                let addr = SocketAddr::new(announced.ip, turn_port);
+                uris.push(format!("turn:{}?transport=udp", addr));
+                uris.push(format!("turn:{}?transport=tcp", addr));
+            }
+            let credential_issuer = CredentialIssuer::new(
+                shared_secret,
+                Duration::from_secs(cli.turn_credential_ttl),
+                uris,
+            );
+            (Some(turn_server), Some(credential_issuer))
+        }
+        _ => (None, None),
+    };
+
     let signal_port = cli.signal_port;
 
+    let session_directory = cli.session_directory.open().map_err(|e| {
+        tracing::error!("Failed to open session directory: {:?}", e);
+        e
+    })?;
+    let node_address = cli
+        .node_address
+        .clone()
+        .unwrap_or_else(|| format!("http://{}:{}", host_addr, signal_port));
+    info!(
+        "Sessions are owned as {} in the session directory",
+        node_address
+    );
+    let session_router = Arc::new(SessionRouter::new(
+        session_directory,
+        node_address,
+        worker_placement.clone(),
+        Duration::from_secs(cli.session_lease),
+        cli.session_forwarding,
+    )?);
+    session_router.spawn_renewal(media_port_thread_map.clone())?;
+
+    let token_verifier = cli.jwt_secret.as_deref().map(TokenVerifier::new);
+    if token_verifier.is_none() {
+        tracing::warn!("No --jwt-secret, the routes requiring a bearer token are refused");
+    }
+
     web_server::start(
         &host_addr.to_string(),
         &signal_port.to_string(),
-        media_port_thread_map.clone(),
+        WebServerConfig {
+            token_verifier,
+            media_port_thread_map: media_port_thread_map.clone(),
+            worker_placement,
+            credential_issuer,
+            hls_dir: cli.hls_dir,
+            cascade_nodes,
+            session_router,
+            event_streams,
+        },
     )
     .await?;
 
+    if let Some(turn_server) = turn_server {
+        turn_server.close();
+    }
+
     info!("Press Ctrl-C to stop");
+    // the signalling server returns once stopped by Ctrl-C, the media workers stop with it
     std::thread::spawn(move || {
-        let _ = signal::ctrl_c();
+        tcp_stopping.store(true, Ordering::Relaxed);
         stop_tx.send(()).unwrap();
     });
 
@@ -1 +1 @@
-pub mod verify_jwt;
\ No newline at end of file
+pub mod verify_jwt;
@@ -1,8 +1,261 @@
-use actix_web::dev::ServiceRequest;
-
-pub fn verify_token(req: ServiceRequest) -> bool {
-    // Add your JWT verification logic here
-    // For example, extract JWT from headers and make a request to external API for verification
-    // return true if JWT is valid, else false
-    true
-}
\ No newline at end of file
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{http::header, web, web::Data, HttpRequest};
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
+use ring::hmac;
+use serde::Deserialize;
+use tracing::debug;
+
+/// Seconds the expiry and not-before times of a token are allowed to be off by, for clock skew
+const CLOCK_LEEWAY: u64 = 60;
+
+/// Claims checked in the tokens of the REST API.
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    pub sub: Option<String>,
+    pub exp: u64,
+    pub nbf: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct JoseHeader {
+    alg: String,
+}
+
+/// Verifies the HS256 JSON Web Tokens of the REST API against a shared secret.
+#[derive(Clone)]
+pub struct TokenVerifier {
+    key: hmac::Key,
+}
+
+impl TokenVerifier {
+    pub fn new(secret: &str) -> Self {
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+        }
+    }
+
+    /// Checks the signature and the validity period of a token, `now` being in seconds since
+    /// the Unix epoch.
+    pub fn verify(&self, token: &str, now: u64) -> Result<Claims, String> {
+        let Some((signing_input, signature)) = token.rsplit_once('.') else {
+            return Err("token is not a JWS compact serialization".to_string());
+        };
+        let Some((header, payload)) = signing_input
+            .split_once('.')
+            .filter(|(_, payload)| !payload.contains('.'))
+        else {
+            return Err("token is not a JWS compact serialization".to_string());
+        };
+
+        let header: JoseHeader = decode_json(header)?;
+        // the algorithm is fixed rather than taken from the token, which rejects "none"
+        if header.alg != "HS256" {
+            return Err(format!("unsupported algorithm {}", header.alg));
+        }
+        let signature = BASE64_URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|e| format!("invalid signature encoding: {}", e))?;
+        hmac::verify(&self.key, signing_input.as_bytes(), &signature)
+            .map_err(|_| "invalid signature".to_string())?;
+
+        let claims: Claims = decode_json(payload)?;
+        if claims.exp + CLOCK_LEEWAY <= now {
+            return Err("token has expired".to_string());
+        }
+        if claims.nbf.is_some_and(|nbf| nbf > now + CLOCK_LEEWAY) {
+            return Err("token is not valid yet".to_string());
+        }
+        Ok(claims)
+    }
+}
+
+fn decode_json<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T, String> {
+    let json = BASE64_URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|e| format!("invalid base64url: {}", e))?;
+    serde_json::from_slice(&json).map_err(|e| format!("invalid JSON: {}", e))
+}
+
+/// verify_token checks the bearer token of a REST API request. Requests are refused when the
+/// server has no --jwt-secret to check them with.
+pub fn verify_token(req: &HttpRequest) -> bool {
+    let Some(verifier) = req
+        .app_data::<Data<Option<TokenVerifier>>>()
+        .and_then(|verifier| verifier.as_ref().as_ref())
+    else {
+        return false;
+    };
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string)
+        // browsers can't set headers on an EventSource, which gives the token as a query
+        // parameter instead (RFC 6750)
+        .or_else(|| access_token(req));
+    let Some(token) = token else {
+        return false;
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    match verifier.verify(&token, now) {
+        Ok(claims) => {
+            debug!(
+                "Accepting token of {:?} for {} {}",
+                claims.sub,
+                req.method(),
+                req.path()
+            );
+            true
+        }
+        Err(e) => {
+            debug!("Refusing token of {} {}: {}", req.method(), req.path(), e);
+            false
+        }
+    }
+}
+
+fn access_token(req: &HttpRequest) -> Option<String> {
+    web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()?
+        .into_inner()
+        .remove("access_token")
+}
+
+/// The path and query of the request, fit to log with the token of an `access_token` parameter
+/// left out.
+pub fn redacted_uri(req: &HttpRequest) -> String {
+    if req.query_string().is_empty() {
+        return req.path().to_string();
+    }
+    let query: String = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(
+            url::form_urlencoded::parse(req.query_string().as_bytes()).map(|(name, value)| {
+                if name == "access_token" {
+                    (name, "REDACTED".into())
+                } else {
+                    (name, value)
+                }
+            }),
+        )
+        .finish();
+    format!("{}?{}", req.path(), query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_800_000_000;
+
+    fn sign(secret: &str, header: &str, payload: &str) -> String {
+        let signing_input = format!(
+            "{}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(header),
+            BASE64_URL_SAFE_NO_PAD.encode(payload)
+        );
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let signature = hmac::sign(&key, signing_input.as_bytes());
+        format!(
+            "{}.{}",
+            signing_input,
+            BASE64_URL_SAFE_NO_PAD.encode(signature.as_ref())
+        )
+    }
+
+    fn hs256(payload: &str) -> String {
+        sign("secret", r#"{"alg":"HS256","typ":"JWT"}"#, payload)
+    }
+
+    #[test]
+    fn accepts_valid_token() {
+        let verifier = TokenVerifier::new("secret");
+        let token = hs256(&format!(r#"{{"sub":"admin","exp":{}}}"#, NOW + 300));
+        let claims = verifier.verify(&token, NOW).unwrap();
+        assert_eq!(claims.sub.as_deref(), Some("admin"));
+    }
+
+    #[test]
+    fn rejects_other_secret() {
+        let verifier = TokenVerifier::new("other");
+        let token = hs256(&format!(r#"{{"exp":{}}}"#, NOW + 300));
+        assert!(verifier.verify(&token, NOW).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_payload() {
+        let verifier = TokenVerifier::new("secret");
+        let token = hs256(&format!(r#"{{"sub":"user","exp":{}}}"#, NOW + 300));
+        let forged_payload =
+            BASE64_URL_SAFE_NO_PAD.encode(format!(r#"{{"sub":"admin","exp":{}}}"#, NOW + 300));
+        let parts: Vec<&str> = token.split('.').collect();
+        let forged = format!("{}.{}.{}", parts[0], forged_payload, parts[2]);
+        assert!(verifier.verify(&forged, NOW).is_err());
+    }
+
+    #[test]
+    fn rejects_expired_and_early_tokens() {
+        let verifier = TokenVerifier::new("secret");
+        let expired = hs256(&format!(r#"{{"exp":{}}}"#, NOW - CLOCK_LEEWAY - 1));
+        assert!(verifier.verify(&expired, NOW).is_err());
+        // within the leeway
+        let skewed = hs256(&format!(r#"{{"exp":{}}}"#, NOW - 1));
+        assert!(verifier.verify(&skewed, NOW).is_ok());
+        let early = hs256(&format!(
+            r#"{{"exp":{},"nbf":{}}}"#,
+            NOW + 600,
+            NOW + CLOCK_LEEWAY + 1
+        ));
+        assert!(verifier.verify(&early, NOW).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_expiry() {
+        let verifier = TokenVerifier::new("secret");
+        assert!(verifier.verify(&hs256(r#"{"sub":"admin"}"#), NOW).is_err());
+    }
+
+    #[test]
+    fn rejects_unsigned_and_malformed_tokens() {
+        let verifier = TokenVerifier::new("secret");
+        let payload = BASE64_URL_SAFE_NO_PAD.encode(format!(r#"{{"exp":{}}}"#, NOW + 300));
+        let none = format!(
+            "{}.{}.",
+            BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#),
+            payload
+        );
+        assert!(verifier.verify(&none, NOW).is_err());
+        let hs512 = sign(
+            "secret",
+            r#"{"alg":"HS512"}"#,
+            &format!(r#"{{"exp":{}}}"#, NOW + 300),
+        );
+        assert!(verifier.verify(&hs512, NOW).is_err());
+        assert!(verifier.verify("", NOW).is_err());
+        assert!(verifier.verify("a.b", NOW).is_err());
+        assert!(verifier.verify("a.b.c.d", NOW).is_err());
+        assert!(verifier.verify("not-base64!.e30.", NOW).is_err());
+    }
+
+    #[test]
+    fn redacts_access_token_from_uri() {
+        let req = actix_web::test::TestRequest::with_uri(
+            "/sessions/1/events?access_token=eyJhbGciOiJIUzI1NiJ9.e30.c2ln&lang=fr",
+        )
+        .to_http_request();
+        assert_eq!(
+            redacted_uri(&req),
+            "/sessions/1/events?access_token=REDACTED&lang=fr"
+        );
+        let req = actix_web::test::TestRequest::with_uri("/sessions/1/events").to_http_request();
+        assert_eq!(redacted_uri(&req), "/sessions/1/events");
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    io::{Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use ring::hmac;
+use tracing::{debug, info};
+
+use crate::transport::tcp::ConnectionLimit;
+
+use self::server::{Client, Server};
+
+pub mod server;
+pub mod tcp;
+
+/// Interval the listeners check whether the server is stopping at
+pub(crate) const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A static long-term credential, given as `USERNAME:PASSWORD` on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnUser {
+    pub username: String,
+    pub password: String,
+}
+
+impl FromStr for TurnUser {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((username, password)) if !username.is_empty() => Ok(TurnUser {
+                username: username.to_string(),
+                password: password.to_string(),
+            }),
+            _ => Err(format!(
+                "invalid turn user {}, expected USERNAME:PASSWORD",
+                s
+            )),
+        }
+    }
+}
+
+/// Time-limited credentials handed out to clients, in the format of the TURN REST API
+/// (draft-uberti-behave-turn-rest).
+#[derive(Debug, serde::Serialize)]
+pub struct TurnCredentials {
+    pub username: String,
+    pub password: String,
+    pub ttl: u64,
+    pub uris: Vec<String>,
+}
+
+/// Issues credentials accepted by the TURN server with the same shared secret.
+#[derive(Clone)]
+pub struct CredentialIssuer {
+    shared_secret: String,
+    ttl: Duration,
+    uris: Vec<String>,
+}
+
+impl CredentialIssuer {
+    pub fn new(shared_secret: String, ttl: Duration, uris: Vec<String>) -> Self {
+        CredentialIssuer {
+            shared_secret,
+            ttl,
+            uris,
+        }
+    }
+
+    /// Credentials valid for the configured ttl, the username being `EXPIRY:USER`.
+    pub fn issue(&self, user: &str) -> std::io::Result<TurnCredentials> {
+        let expiry = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(Error::other)?
+            + self.ttl;
+        let username = format!("{}:{}", expiry.as_secs(), user);
+        let password = rest_api_password(&self.shared_secret, &username);
+
+        Ok(TurnCredentials {
+            username,
+            password,
+            ttl: self.ttl.as_secs(),
+            uris: self.uris.clone(),
+        })
+    }
+}
+
+fn rest_api_password(shared_secret: &str, username: &str) -> String {
+    let key = hmac::Key::new(
+        hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+        shared_secret.as_bytes(),
+    );
+    BASE64_STANDARD.encode(hmac::sign(&key, username.as_bytes()))
+}
+
+/// An IP network, given as `ADDRESS/PREFIX_LEN` or a single `ADDRESS` on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl PeerNetwork {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for PeerNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr = IpAddr::from_str(addr)
+            .map_err(|e| format!("invalid peer network {}: {}", s, e))?
+            .to_canonical();
+        let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .ok_or_else(|| format!("invalid prefix length in peer network {}", s))?,
+            None => max_prefix_len,
+        };
+        Ok(PeerNetwork { addr, prefix_len })
+    }
+}
+
+/// Whether a relay towards `ip` is refused: the loopback, private, link-local and other
+/// non-routable addresses would let clients reach the internal network of the server, unless
+/// they are in one of the `allowed` networks.
+pub fn is_denied_peer(ip: IpAddr, allowed: &[PeerNetwork]) -> bool {
+    let ip = ip.to_canonical();
+    if allowed.iter().any(|network| network.contains(ip)) {
+        return false;
+    }
+    match ip {
+        IpAddr::V4(ip) => is_internal_ipv4(ip),
+        IpAddr::V6(ip) => is_internal_ipv6(ip),
+    }
+}
+
+fn is_internal_ipv4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        // "this network" 0.0.0.0/8 and the carrier-grade NAT 100.64.0.0/10
+        || first == 0
+        || (first == 100 && second & 0xc0 == 64)
+}
+
+fn is_internal_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local fc00::/7 and link-local fe80::/10
+        || first & 0xfe00 == 0xfc00
+        || first & 0xffc0 == 0xfe80
+}
+
+pub struct RelayConfig {
+    pub listen_addr: SocketAddr,
+    pub relay_ip: IpAddr,
+    pub relay_port_min: u16,
+    pub relay_port_max: u16,
+    pub realm: String,
+    pub users: Vec<TurnUser>,
+    pub shared_secret: String,
+    pub allowed_peers: Vec<PeerNetwork>,
+    /// allocations held at once by a user, the USER of the `EXPIRY:USER` REST API usernames
+    pub max_allocations_per_user: usize,
+    pub connection_limit: ConnectionLimit,
+}
+
+/// A running TURN server, stopped with [`RelayServer::close`].
+pub struct RelayServer {
+    stopping: Arc<AtomicBool>,
+}
+
+impl RelayServer {
+    /// Stop accepting messages, the allocations being released within a second.
+    pub fn close(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Start the TURN server on `listen_addr`, both UDP and TCP. Allocations are relayed from
+/// `relay_port_min..=relay_port_max` on the IP of `listen_addr`, and announced on `relay_ip`.
+pub fn start(config: RelayConfig) -> std::io::Result<RelayServer> {
+    let udp_socket = Arc::new(UdpSocket::bind(config.listen_addr)?);
+    udp_socket.set_read_timeout(Some(STOP_CHECK_INTERVAL))?;
+    let tcp_listener = TcpListener::bind(config.listen_addr)?;
+    info!(
+        "turn listening {} (udp, tcp), relaying from {} on ports {}-{}...",
+        config.listen_addr, config.relay_ip, config.relay_port_min, config.relay_port_max
+    );
+
+    let stopping = Arc::new(AtomicBool::new(false));
+    let connection_limit = config.connection_limit.clone();
+    let server = Arc::new(Server::new(config, stopping.clone()));
+
+    std::thread::spawn({
+        let server = Arc::clone(&server);
+        let stopping = stopping.clone();
+        move || {
+            let mut buf = vec![0; server::MAX_MESSAGE_LEN];
+            while !stopping.load(Ordering::Relaxed) {
+                match udp_socket.recv_from(&mut buf) {
+                    Ok((n, peer_addr)) => {
+                        let client = Client::udp(peer_addr, Arc::clone(&udp_socket));
+                        server.handle(&client, &buf[..n]);
+                    }
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                    Err(e) => debug!("turn udp receive failed: {:?}", e),
+                }
+            }
+        }
+    });
+
+    tcp::spawn_listener(tcp_listener, server, connection_limit, stopping.clone())?;
+
+    Ok(RelayServer { stopping })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn denies_internal_peers() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "172.31.255.255",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "ff02::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(
+                is_denied_peer(ip.parse().unwrap(), &[]),
+                "{} is allowed",
+                ip
+            );
+        }
+        for ip in ["203.0.113.7", "172.32.0.1", "100.128.0.1", "2001:db8::1"] {
+            assert!(
+                !is_denied_peer(ip.parse().unwrap(), &[]),
+                "{} is denied",
+                ip
+            );
+        }
+    }
+
+    #[test]
+    fn allows_configured_networks() {
+        let allowed: Vec<PeerNetwork> = ["10.0.0.0/8", "::1"]
+            .iter()
+            .map(|network| network.parse().unwrap())
+            .collect();
+        assert!(!is_denied_peer("10.20.30.40".parse().unwrap(), &allowed));
+        assert!(!is_denied_peer(
+            "::ffff:10.0.0.1".parse().unwrap(),
+            &allowed
+        ));
+        assert!(!is_denied_peer("::1".parse().unwrap(), &allowed));
+        assert!(is_denied_peer("192.168.0.1".parse().unwrap(), &allowed));
+        assert!(is_denied_peer("127.0.0.1".parse().unwrap(), &allowed));
+    }
+
+    #[test]
+    fn parses_peer_networks() {
+        assert_eq!(
+            "192.168.0.0/16".parse::<PeerNetwork>(),
+            Ok(PeerNetwork {
+                addr: "192.168.0.0".parse().unwrap(),
+                prefix_len: 16
+            })
+        );
+        assert_eq!(
+            "0.0.0.0/0"
+                .parse::<PeerNetwork>()
+                .map(|n| n.contains("8.8.8.8".parse().unwrap())),
+            Ok(true)
+        );
+        assert!("10.0.0.0/33".parse::<PeerNetwork>().is_err());
+        assert!("fe80::/129".parse::<PeerNetwork>().is_err());
+        assert!("not-an-ip/8".parse::<PeerNetwork>().is_err());
+    }
+
+    #[test]
+    fn parses_turn_users() {
+        assert_eq!(
+            "alice:secret:with:colons".parse::<TurnUser>(),
+            Ok(TurnUser {
+                username: "alice".to_string(),
+                password: "secret:with:colons".to_string()
+            })
+        );
+        assert!(":password".parse::<TurnUser>().is_err());
+        assert!("alice".parse::<TurnUser>().is_err());
+    }
+}
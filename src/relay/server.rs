@@ -0,0 +1,1191 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel::{Sender, TrySendError};
+use ring::hmac;
+use stun::{
+    attributes::{
+        RawAttribute, ATTR_CHANNEL_NUMBER, ATTR_DATA, ATTR_LIFETIME, ATTR_NONCE, ATTR_REALM,
+        ATTR_REQUESTED_ADDRESS_FAMILY, ATTR_REQUESTED_TRANSPORT, ATTR_USERNAME,
+        ATTR_XOR_PEER_ADDRESS, ATTR_XOR_RELAYED_ADDRESS,
+    },
+    error_code::{
+        ErrorCode, ErrorCodeAttribute, CODE_ADDR_FAMILY_NOT_SUPPORTED, CODE_ALLOC_MISMATCH,
+        CODE_ALLOC_QUOTA_REACHED, CODE_BAD_REQUEST, CODE_FORBIDDEN, CODE_INSUFFICIENT_CAPACITY,
+        CODE_PEER_ADDR_FAMILY_MISMATCH, CODE_STALE_NONCE, CODE_UNAUTHORIZED,
+        CODE_UNSUPPORTED_TRANS_PROTO, CODE_WRONG_CREDENTIALS,
+    },
+    fingerprint::FINGERPRINT,
+    integrity::MessageIntegrity,
+    message::{
+        Message, MessageClass, MessageType, Setter, TransactionId, CLASS_ERROR_RESPONSE,
+        CLASS_INDICATION, CLASS_REQUEST, CLASS_SUCCESS_RESPONSE, METHOD_ALLOCATE, METHOD_BINDING,
+        METHOD_CHANNEL_BIND, METHOD_CREATE_PERMISSION, METHOD_DATA, METHOD_REFRESH, METHOD_SEND,
+    },
+    textattrs::TextAttribute,
+    xoraddr::XorMappedAddress,
+};
+use tracing::{debug, info, warn};
+
+use super::{is_denied_peer, rest_api_password, PeerNetwork, RelayConfig, STOP_CHECK_INTERVAL};
+
+/// Largest message read from a client or a peer, the largest UDP datagram
+pub const MAX_MESSAGE_LEN: usize = 65535;
+/// ChannelData messages start with a 4 bytes header, its length field excludes it
+pub const CHANNEL_DATA_HEADER_LEN: usize = 4;
+/// Lifetime of an allocation when the client requests none, and the longest one granted
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(600);
+const MAX_LIFETIME: Duration = Duration::from_secs(3600);
+/// Lifetimes of a permission and of a channel binding (RFC 8656 sections 9 and 12)
+const PERMISSION_LIFETIME: Duration = Duration::from_secs(300);
+const CHANNEL_LIFETIME: Duration = Duration::from_secs(600);
+/// Seconds a nonce is accepted for, clients retrying with a fresh one after a 438
+const NONCE_LIFETIME: u64 = 3600;
+/// Channel numbers a client may bind (RFC 8656 section 12)
+const CHANNEL_NUMBERS: RangeInclusive<u16> = 0x4000..=0x4fff;
+/// REQUESTED-TRANSPORT of UDP, the only one relayed
+const PROTOCOL_UDP: u8 = 17;
+/// REQUESTED-ADDRESS-FAMILY values (RFC 8656 section 18.8)
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+/// Transport a client reached the server over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+}
+
+#[derive(Clone)]
+enum Sink {
+    Udp(Arc<UdpSocket>),
+    Tcp(Sender<Vec<u8>>),
+}
+
+/// A client of the server, with the way back to it. Allocations are keyed by its transport
+/// and address, the server side of the 5-tuple being fixed.
+#[derive(Clone)]
+pub struct Client {
+    protocol: Protocol,
+    addr: SocketAddr,
+    sink: Sink,
+}
+
+impl Client {
+    pub fn udp(addr: SocketAddr, socket: Arc<UdpSocket>) -> Self {
+        Client {
+            protocol: Protocol::Udp,
+            addr,
+            sink: Sink::Udp(socket),
+        }
+    }
+
+    pub fn tcp(addr: SocketAddr, tx: Sender<Vec<u8>>) -> Self {
+        Client {
+            protocol: Protocol::Tcp,
+            addr,
+            sink: Sink::Tcp(tx),
+        }
+    }
+
+    fn key(&self) -> (Protocol, SocketAddr) {
+        (self.protocol, self.addr)
+    }
+
+    /// Send a message to the client, dropping it when its connection can't keep up.
+    fn send(&self, mut message: Vec<u8>) {
+        match &self.sink {
+            Sink::Udp(socket) => {
+                if let Err(e) = socket.send_to(&message, self.addr) {
+                    debug!("failed to send turn message to {}: {:?}", self.addr, e);
+                }
+            }
+            Sink::Tcp(tx) => {
+                // ChannelData is padded to 4 bytes over TCP, STUN messages always are
+                message.resize(message.len().next_multiple_of(4), 0);
+                if let Err(TrySendError::Full(_)) = tx.try_send(message) {
+                    debug!("turn tcp connection of {} is congested", self.addr);
+                }
+            }
+        }
+    }
+}
+
+/// Outcome of a request, the attributes of the success response or the error code.
+type Outcome = Result<Vec<Box<dyn Setter>>, (ErrorCode, &'static str)>;
+
+/// Credentials a request was authenticated with.
+struct Credentials {
+    username: String,
+    integrity: MessageIntegrity,
+}
+
+struct ChannelBinding {
+    peer: SocketAddr,
+    expires_at: Instant,
+}
+
+struct AllocationState {
+    expires_at: Instant,
+    permissions: HashMap<IpAddr, Instant>,
+    channels: HashMap<u16, ChannelBinding>,
+}
+
+impl AllocationState {
+    fn has_permission(&self, peer: SocketAddr, now: Instant) -> bool {
+        self.permissions
+            .get(&peer.ip().to_canonical())
+            .is_some_and(|expires_at| *expires_at > now)
+    }
+
+    fn channel_of(&self, peer: SocketAddr, now: Instant) -> Option<u16> {
+        self.channels
+            .iter()
+            .find(|(_, binding)| binding.peer == peer && binding.expires_at > now)
+            .map(|(number, _)| *number)
+    }
+}
+
+/// A relayed transport address of a client, with its permissions and channels.
+struct Allocation {
+    client: Client,
+    username: String,
+    relay_socket: UdpSocket,
+    state: Mutex<AllocationState>,
+}
+
+impl Allocation {
+    fn expire(&self) {
+        self.state.lock().unwrap().expires_at = Instant::now();
+    }
+
+    /// Relay a datagram from a peer to the client, as ChannelData when a channel is bound to
+    /// the peer or in a Data indication otherwise. Dropped without a permission for the peer.
+    fn relay_to_client(&self, peer: SocketAddr, data: &[u8]) {
+        let now = Instant::now();
+        let channel = {
+            let state = self.state.lock().unwrap();
+            if !state.has_permission(peer, now) {
+                debug!("dropping datagram from {} without permission", peer);
+                return;
+            }
+            state.channel_of(peer, now)
+        };
+
+        let message = match channel {
+            Some(number) => {
+                let mut message = Vec::with_capacity(CHANNEL_DATA_HEADER_LEN + data.len());
+                message.extend_from_slice(&number.to_be_bytes());
+                message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                message.extend_from_slice(data);
+                message
+            }
+            None => {
+                let mut indication = Message::new();
+                let built = indication.build(&[
+                    Box::new(TransactionId::new()),
+                    Box::new(MessageType::new(METHOD_DATA, CLASS_INDICATION)),
+                    Box::new(XorAddress(ATTR_XOR_PEER_ADDRESS, peer)),
+                    raw_attribute(ATTR_DATA, data.to_vec()),
+                ]);
+                if let Err(e) = built {
+                    warn!("failed to build turn data indication: {}", e);
+                    return;
+                }
+                indication.raw
+            }
+        };
+        self.client.send(message);
+    }
+}
+
+/// A TURN server (RFC 8656) relaying UDP, for the clients reaching it over UDP or TCP, with
+/// long-term credentials.
+pub struct Server {
+    relay_ip: IpAddr,
+    relay_bind_ip: IpAddr,
+    relay_ports: RangeInclusive<u16>,
+    realm: String,
+    users: HashMap<String, String>,
+    shared_secret: String,
+    allowed_peers: Vec<PeerNetwork>,
+    max_allocations_per_user: usize,
+    nonce_key: hmac::Key,
+    allocations: Mutex<HashMap<(Protocol, SocketAddr), Arc<Allocation>>>,
+    stopping: Arc<AtomicBool>,
+}
+
+impl Server {
+    pub fn new(config: RelayConfig, stopping: Arc<AtomicBool>) -> Self {
+        Server {
+            relay_ip: config.relay_ip,
+            relay_bind_ip: config.listen_addr.ip(),
+            relay_ports: config.relay_port_min..=config.relay_port_max,
+            realm: config.realm,
+            users: config
+                .users
+                .into_iter()
+                .map(|user| (user.username, user.password))
+                .collect(),
+            shared_secret: config.shared_secret,
+            allowed_peers: config.allowed_peers,
+            max_allocations_per_user: config.max_allocations_per_user,
+            nonce_key: hmac::Key::new(hmac::HMAC_SHA256, &rand::random::<[u8; 32]>()),
+            allocations: Mutex::new(HashMap::new()),
+            stopping,
+        }
+    }
+
+    /// Handle a STUN or ChannelData message from a client.
+    pub fn handle(self: &Arc<Self>, client: &Client, data: &[u8]) {
+        // The two first bits are 0b00 for STUN, 0b01 for ChannelData
+        match data.first().map(|b| b >> 6) {
+            Some(0) => self.handle_stun(client, data),
+            Some(1) => self.handle_channel_data(client, data),
+            _ => debug!("ignoring non-turn message from {}", client.addr),
+        }
+    }
+
+    /// Whether the client holds an allocation, which keeps its TCP connection open.
+    pub fn has_allocation(&self, client: &Client) -> bool {
+        self.allocations.lock().unwrap().contains_key(&client.key())
+    }
+
+    /// Delete the allocation of a client, e.g. when its TCP connection is closed.
+    pub fn release(&self, client: &Client) {
+        if let Some(allocation) = self.allocations.lock().unwrap().remove(&client.key()) {
+            allocation.expire();
+        }
+    }
+
+    fn handle_stun(self: &Arc<Self>, client: &Client, data: &[u8]) {
+        let mut message = Message {
+            raw: data.to_vec(),
+            ..Default::default()
+        };
+        if let Err(e) = message.decode() {
+            debug!("invalid stun message from {}: {}", client.addr, e);
+            return;
+        }
+
+        match (message.typ.class, message.typ.method) {
+            (CLASS_INDICATION, METHOD_SEND) => self.handle_send(client, &message),
+            (CLASS_REQUEST, METHOD_BINDING) => {
+                let mapped = XorAddress(stun::attributes::ATTR_XORMAPPED_ADDRESS, client.addr);
+                self.respond(client, &message, Ok(vec![Box::new(mapped)]), None);
+            }
+            (CLASS_REQUEST, method) => {
+                let credentials = match self.authenticate(&mut message) {
+                    Ok(credentials) => credentials,
+                    Err(error) => {
+                        let attributes: Vec<Box<dyn Setter>> = vec![
+                            Box::new(TextAttribute::new(ATTR_REALM, self.realm.clone())),
+                            Box::new(TextAttribute::new(ATTR_NONCE, self.nonce(unix_now()))),
+                        ];
+                        self.respond_error(client, &message, error, attributes);
+                        return;
+                    }
+                };
+                let outcome = match method {
+                    METHOD_ALLOCATE => self.allocate(client, &message, &credentials),
+                    METHOD_REFRESH => self.refresh(client, &message, &credentials),
+                    METHOD_CREATE_PERMISSION => {
+                        self.create_permission(client, &message, &credentials)
+                    }
+                    METHOD_CHANNEL_BIND => self.channel_bind(client, &message, &credentials),
+                    _ => Err((CODE_BAD_REQUEST, "Unsupported method")),
+                };
+                self.respond(client, &message, outcome, Some(&credentials.integrity));
+            }
+            _ => debug!("ignoring stun {} from {}", message.typ, client.addr),
+        }
+    }
+
+    /// Check the long-term credentials of a request (RFC 8489 section 9.2).
+    fn authenticate(
+        &self,
+        request: &mut Message,
+    ) -> Result<Credentials, (ErrorCode, &'static str)> {
+        if !request.contains(stun::attributes::ATTR_MESSAGE_INTEGRITY) {
+            return Err((CODE_UNAUTHORIZED, "Unauthorized"));
+        }
+        let (Ok(username), Ok(realm), Ok(nonce)) = (
+            TextAttribute::get_from_as(request, ATTR_USERNAME),
+            TextAttribute::get_from_as(request, ATTR_REALM),
+            TextAttribute::get_from_as(request, ATTR_NONCE),
+        ) else {
+            return Err((CODE_BAD_REQUEST, "Missing credentials"));
+        };
+        let now = unix_now();
+        if !self.is_valid_nonce(&nonce.text, now) {
+            return Err((CODE_STALE_NONCE, "Stale Nonce"));
+        }
+        if realm.text != self.realm {
+            return Err((CODE_UNAUTHORIZED, "Unauthorized"));
+        }
+        let Some(password) = self.password(&username.text, now) else {
+            debug!("unknown or expired turn user {}", username.text);
+            return Err((CODE_UNAUTHORIZED, "Unauthorized"));
+        };
+
+        let integrity =
+            MessageIntegrity::new_long_term_integrity(username.text.clone(), realm.text, password);
+        integrity
+            .check(request)
+            .map_err(|_| (CODE_UNAUTHORIZED, "Unauthorized"))?;
+        Ok(Credentials {
+            username: username.text,
+            integrity,
+        })
+    }
+
+    /// Static users first, then the time-limited `EXPIRY:USER` ones of the TURN REST API.
+    fn password(&self, username: &str, now: u64) -> Option<String> {
+        if let Some(password) = self.users.get(username) {
+            return Some(password.clone());
+        }
+        let expiry = username.split(':').next()?.parse::<u64>().ok()?;
+        (expiry >= now).then(|| rest_api_password(&self.shared_secret, username))
+    }
+
+    /// A nonce is its issue time signed with a key of this run, which needs no state.
+    fn nonce(&self, issued_at: u64) -> String {
+        let tag = hmac::sign(&self.nonce_key, &issued_at.to_be_bytes());
+        format!("{:016x}{}", issued_at, hex::encode(tag.as_ref()))
+    }
+
+    fn is_valid_nonce(&self, nonce: &str, now: u64) -> bool {
+        let Some((issued_at, tag)) = nonce.get(..16).zip(nonce.get(16..)) else {
+            return false;
+        };
+        let (Ok(issued_at), Ok(tag)) = (u64::from_str_radix(issued_at, 16), hex::decode(tag))
+        else {
+            return false;
+        };
+        issued_at + NONCE_LIFETIME >= now
+            && hmac::verify(&self.nonce_key, &issued_at.to_be_bytes(), &tag).is_ok()
+    }
+
+    fn allocate(
+        self: &Arc<Self>,
+        client: &Client,
+        request: &Message,
+        credentials: &Credentials,
+    ) -> Outcome {
+        if self.has_allocation(client) {
+            return Err((CODE_ALLOC_MISMATCH, "Allocation Mismatch"));
+        }
+        match request.get(ATTR_REQUESTED_TRANSPORT) {
+            Ok(transport) if transport.first() == Some(&PROTOCOL_UDP) => (),
+            Ok(_) => {
+                return Err((
+                    CODE_UNSUPPORTED_TRANS_PROTO,
+                    "Unsupported Transport Protocol",
+                ))
+            }
+            Err(_) => return Err((CODE_BAD_REQUEST, "Missing REQUESTED-TRANSPORT")),
+        }
+        if let Ok(family) = request.get(ATTR_REQUESTED_ADDRESS_FAMILY) {
+            let relay_family = if self.relay_ip.is_ipv4() {
+                FAMILY_IPV4
+            } else {
+                FAMILY_IPV6
+            };
+            if family.first() != Some(&relay_family) {
+                return Err((
+                    CODE_ADDR_FAMILY_NOT_SUPPORTED,
+                    "Address Family not Supported",
+                ));
+            }
+        }
+
+        let Some(relay_socket) = self.bind_relay_socket() else {
+            warn!("no turn relay port left for {}", client.addr);
+            return Err((CODE_INSUFFICIENT_CAPACITY, "Insufficient Capacity"));
+        };
+        let relay_port = relay_socket
+            .local_addr()
+            .map_err(|_| (CODE_INSUFFICIENT_CAPACITY, "Insufficient Capacity"))?
+            .port();
+        relay_socket
+            .set_read_timeout(Some(STOP_CHECK_INTERVAL))
+            .map_err(|_| (CODE_INSUFFICIENT_CAPACITY, "Insufficient Capacity"))?;
+        let lifetime = requested_lifetime(request).unwrap_or(DEFAULT_LIFETIME);
+        let allocation = Arc::new(Allocation {
+            client: client.clone(),
+            username: credentials.username.clone(),
+            relay_socket,
+            state: Mutex::new(AllocationState {
+                expires_at: Instant::now() + lifetime,
+                permissions: HashMap::new(),
+                channels: HashMap::new(),
+            }),
+        });
+        {
+            let mut allocations = self.allocations.lock().unwrap();
+            let user = quota_user(&credentials.username);
+            let held = allocations
+                .values()
+                .filter(|allocation| quota_user(&allocation.username) == user)
+                .count();
+            if held >= self.max_allocations_per_user {
+                warn!("turn user {} reached its allocation quota", user);
+                return Err((CODE_ALLOC_QUOTA_REACHED, "Allocation Quota Reached"));
+            }
+            allocations.insert(client.key(), Arc::clone(&allocation));
+        }
+        info!(
+            "turn allocation of {} ({:?}) for {} relaying on port {}",
+            client.addr, client.protocol, credentials.username, relay_port
+        );
+
+        let server = Arc::clone(self);
+        std::thread::spawn(move || server.relay_from_peers(allocation));
+
+        Ok(vec![
+            Box::new(XorAddress(
+                ATTR_XOR_RELAYED_ADDRESS,
+                SocketAddr::new(self.relay_ip, relay_port),
+            )),
+            lifetime_attribute(lifetime),
+            Box::new(XorAddress(
+                stun::attributes::ATTR_XORMAPPED_ADDRESS,
+                client.addr,
+            )),
+        ])
+    }
+
+    /// Bind a relay socket on a free port of the range, starting from a random one.
+    fn bind_relay_socket(&self) -> Option<UdpSocket> {
+        let (min, max) = (*self.relay_ports.start(), *self.relay_ports.end());
+        let ports = (max as u32).checked_sub(min as u32)? + 1;
+        let start = rand::random::<u32>() % ports;
+        (0..ports)
+            .map(|i| min + ((start + i) % ports) as u16)
+            .find_map(|port| UdpSocket::bind(SocketAddr::new(self.relay_bind_ip, port)).ok())
+    }
+
+    fn refresh(&self, client: &Client, request: &Message, credentials: &Credentials) -> Outcome {
+        let allocation = self.allocation_of(client, credentials)?;
+        let lifetime = requested_lifetime(request).unwrap_or(DEFAULT_LIFETIME);
+        if lifetime.is_zero() {
+            self.release(client);
+            info!("turn allocation of {} released", client.addr);
+        } else {
+            allocation.state.lock().unwrap().expires_at = Instant::now() + lifetime;
+        }
+        Ok(vec![lifetime_attribute(lifetime)])
+    }
+
+    fn create_permission(
+        &self,
+        client: &Client,
+        request: &Message,
+        credentials: &Credentials,
+    ) -> Outcome {
+        let allocation = self.allocation_of(client, credentials)?;
+        let peers = self.peer_addresses(request)?;
+        let expires_at = Instant::now() + PERMISSION_LIFETIME;
+        let mut state = allocation.state.lock().unwrap();
+        for peer in peers {
+            state.permissions.insert(peer.ip(), expires_at);
+        }
+        Ok(vec![])
+    }
+
+    fn channel_bind(
+        &self,
+        client: &Client,
+        request: &Message,
+        credentials: &Credentials,
+    ) -> Outcome {
+        let allocation = self.allocation_of(client, credentials)?;
+        let number = request
+            .get(ATTR_CHANNEL_NUMBER)
+            .ok()
+            .and_then(|value| value.get(..2).map(|b| u16::from_be_bytes([b[0], b[1]])))
+            .filter(|number| CHANNEL_NUMBERS.contains(number))
+            .ok_or((CODE_BAD_REQUEST, "Invalid CHANNEL-NUMBER"))?;
+        let peer = match self.peer_addresses(request)?.as_slice() {
+            [peer] => *peer,
+            _ => return Err((CODE_BAD_REQUEST, "Expected one XOR-PEER-ADDRESS")),
+        };
+
+        let now = Instant::now();
+        let mut state = allocation.state.lock().unwrap();
+        // a channel is bound to a single peer and the other way round, until it expires
+        let conflict = state.channels.iter().any(|(bound, binding)| {
+            binding.expires_at > now && ((*bound == number) != (binding.peer == peer))
+        });
+        if conflict {
+            return Err((CODE_BAD_REQUEST, "Channel or peer already bound"));
+        }
+        state.channels.insert(
+            number,
+            ChannelBinding {
+                peer,
+                expires_at: now + CHANNEL_LIFETIME,
+            },
+        );
+        state
+            .permissions
+            .insert(peer.ip(), now + PERMISSION_LIFETIME);
+        Ok(vec![])
+    }
+
+    /// The XOR-PEER-ADDRESS attributes of a request, refused when one of them is denied.
+    fn peer_addresses(
+        &self,
+        request: &Message,
+    ) -> Result<Vec<SocketAddr>, (ErrorCode, &'static str)> {
+        let mut peers = vec![];
+        for attribute in &request.attributes.0 {
+            if attribute.typ != ATTR_XOR_PEER_ADDRESS {
+                continue;
+            }
+            let peer = decode_xor_address(request, &attribute.value)
+                .ok_or((CODE_BAD_REQUEST, "Invalid XOR-PEER-ADDRESS"))?;
+            if peer.is_ipv4() != self.relay_ip.is_ipv4() {
+                return Err((
+                    CODE_PEER_ADDR_FAMILY_MISMATCH,
+                    "Peer Address Family Mismatch",
+                ));
+            }
+            if is_denied_peer(peer.ip(), &self.allowed_peers) {
+                warn!("refusing turn permission towards {}", peer);
+                return Err((CODE_FORBIDDEN, "Forbidden"));
+            }
+            peers.push(SocketAddr::new(peer.ip().to_canonical(), peer.port()));
+        }
+        if peers.is_empty() {
+            return Err((CODE_BAD_REQUEST, "Missing XOR-PEER-ADDRESS"));
+        }
+        Ok(peers)
+    }
+
+    /// The allocation of the client, which must have been made with the same credentials.
+    fn allocation_of(
+        &self,
+        client: &Client,
+        credentials: &Credentials,
+    ) -> Result<Arc<Allocation>, (ErrorCode, &'static str)> {
+        let allocation = self
+            .allocations
+            .lock()
+            .unwrap()
+            .get(&client.key())
+            .cloned()
+            .ok_or((CODE_ALLOC_MISMATCH, "Allocation Mismatch"))?;
+        if allocation.username != credentials.username {
+            return Err((CODE_WRONG_CREDENTIALS, "Wrong Credentials"));
+        }
+        Ok(allocation)
+    }
+
+    /// Send indications are not authenticated, relayed only to the peers with a permission.
+    fn handle_send(&self, client: &Client, indication: &Message) {
+        let Some(allocation) = self.allocations.lock().unwrap().get(&client.key()).cloned() else {
+            return;
+        };
+        let (Ok(peer), Ok(data)) = (
+            indication.get(ATTR_XOR_PEER_ADDRESS),
+            indication.get(ATTR_DATA),
+        ) else {
+            return;
+        };
+        let Some(peer) = decode_xor_address(indication, &peer) else {
+            return;
+        };
+        let peer = SocketAddr::new(peer.ip().to_canonical(), peer.port());
+        if !allocation
+            .state
+            .lock()
+            .unwrap()
+            .has_permission(peer, Instant::now())
+        {
+            debug!(
+                "dropping send indication towards {} without permission",
+                peer
+            );
+            return;
+        }
+        if let Err(e) = allocation.relay_socket.send_to(&data, peer) {
+            debug!("failed to relay to {}: {:?}", peer, e);
+        }
+    }
+
+    fn handle_channel_data(&self, client: &Client, data: &[u8]) {
+        if data.len() < CHANNEL_DATA_HEADER_LEN {
+            return;
+        }
+        let number = u16::from_be_bytes([data[0], data[1]]);
+        let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        let Some(payload) = data.get(CHANNEL_DATA_HEADER_LEN..CHANNEL_DATA_HEADER_LEN + len) else {
+            return;
+        };
+        let Some(allocation) = self.allocations.lock().unwrap().get(&client.key()).cloned() else {
+            return;
+        };
+        let peer = {
+            let state = allocation.state.lock().unwrap();
+            match state.channels.get(&number) {
+                Some(binding) if binding.expires_at > Instant::now() => binding.peer,
+                _ => return,
+            }
+        };
+        if let Err(e) = allocation.relay_socket.send_to(payload, peer) {
+            debug!("failed to relay to {}: {:?}", peer, e);
+        }
+    }
+
+    /// Relay the datagrams of the peers to the client until the allocation expires.
+    fn relay_from_peers(&self, allocation: Arc<Allocation>) {
+        let mut buf = vec![0; MAX_MESSAGE_LEN];
+        while !self.stopping.load(Ordering::Relaxed)
+            && allocation.state.lock().unwrap().expires_at > Instant::now()
+        {
+            match allocation.relay_socket.recv_from(&mut buf) {
+                Ok((n, peer)) => allocation.relay_to_client(peer, &buf[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => {
+                    debug!("turn relay receive failed: {:?}", e);
+                    break;
+                }
+            }
+        }
+
+        let key = allocation.client.key();
+        let mut allocations = self.allocations.lock().unwrap();
+        // a new allocation may have been made by the same client since this one was released
+        if allocations
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, &allocation))
+        {
+            allocations.remove(&key);
+            info!("turn allocation of {} expired", allocation.client.addr);
+        }
+    }
+
+    fn respond(
+        &self,
+        client: &Client,
+        request: &Message,
+        outcome: Outcome,
+        integrity: Option<&MessageIntegrity>,
+    ) {
+        let (class, mut attributes) = match outcome {
+            Ok(attributes) => (CLASS_SUCCESS_RESPONSE, attributes),
+            Err((code, reason)) => (
+                CLASS_ERROR_RESPONSE,
+                vec![Box::new(ErrorCodeAttribute {
+                    code,
+                    reason: reason.as_bytes().to_vec(),
+                }) as Box<dyn Setter>],
+            ),
+        };
+        if let Some(integrity) = integrity {
+            attributes.push(Box::new(integrity.clone()));
+        }
+        self.send_response(client, request, class, attributes);
+    }
+
+    fn respond_error(
+        &self,
+        client: &Client,
+        request: &Message,
+        (code, reason): (ErrorCode, &'static str),
+        mut attributes: Vec<Box<dyn Setter>>,
+    ) {
+        attributes.insert(
+            0,
+            Box::new(ErrorCodeAttribute {
+                code,
+                reason: reason.as_bytes().to_vec(),
+            }),
+        );
+        self.send_response(client, request, CLASS_ERROR_RESPONSE, attributes);
+    }
+
+    fn send_response(
+        &self,
+        client: &Client,
+        request: &Message,
+        class: MessageClass,
+        attributes: Vec<Box<dyn Setter>>,
+    ) {
+        let mut setters: Vec<Box<dyn Setter>> = vec![
+            Box::new(request.transaction_id),
+            Box::new(MessageType::new(request.typ.method, class)),
+        ];
+        setters.extend(attributes);
+        setters.push(Box::new(FINGERPRINT));
+
+        let mut response = Message::new();
+        match response.build(&setters) {
+            Ok(()) => client.send(response.raw),
+            Err(e) => warn!("failed to build turn response: {}", e),
+        }
+    }
+}
+
+/// An address set as an XOR-MAPPED-ADDRESS-like attribute of another type.
+struct XorAddress(stun::attributes::AttrType, SocketAddr);
+
+impl Setter for XorAddress {
+    fn add_to(&self, m: &mut Message) -> shared::error::Result<()> {
+        XorMappedAddress {
+            ip: self.1.ip(),
+            port: self.1.port(),
+        }
+        .add_to_as(m, self.0)
+    }
+}
+
+/// Decode the value of an XOR-PEER-ADDRESS of `message`, which is xored with its transaction id.
+fn decode_xor_address(message: &Message, value: &[u8]) -> Option<SocketAddr> {
+    let mut single = Message::new();
+    single.transaction_id = message.transaction_id;
+    single.add(ATTR_XOR_PEER_ADDRESS, value);
+    let mut addr = XorMappedAddress::default();
+    addr.get_from_as(&single, ATTR_XOR_PEER_ADDRESS).ok()?;
+    Some(SocketAddr::new(addr.ip, addr.port))
+}
+
+fn requested_lifetime(request: &Message) -> Option<Duration> {
+    let value = request.get(ATTR_LIFETIME).ok()?;
+    let seconds = u32::from_be_bytes(value.get(..4)?.try_into().ok()?);
+    Some(Duration::from_secs(seconds as u64).min(MAX_LIFETIME))
+}
+
+fn lifetime_attribute(lifetime: Duration) -> Box<dyn Setter> {
+    raw_attribute(
+        ATTR_LIFETIME,
+        (lifetime.as_secs() as u32).to_be_bytes().to_vec(),
+    )
+}
+
+fn raw_attribute(typ: stun::attributes::AttrType, value: Vec<u8>) -> Box<dyn Setter> {
+    Box::new(RawAttribute {
+        typ,
+        length: 0,
+        value,
+    })
+}
+
+/// User an allocation counts against, the USER of a TURN REST API `EXPIRY:USER` username.
+fn quota_user(username: &str) -> &str {
+    username.split_once(':').map_or(username, |(_, user)| user)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use stun::attributes::ATTR_ERROR_CODE;
+
+    use super::*;
+    use crate::{
+        relay::{CredentialIssuer, TurnUser},
+        transport::tcp::ConnectionLimit,
+    };
+
+    const REALM: &str = "beep";
+
+    struct Harness {
+        server: Arc<Server>,
+        server_socket: Arc<UdpSocket>,
+        client_socket: UdpSocket,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            Harness::with_quota(10)
+        }
+
+        fn with_quota(max_allocations_per_user: usize) -> Self {
+            let server = Arc::new(Server::new(
+                RelayConfig {
+                    listen_addr: "127.0.0.1:0".parse().unwrap(),
+                    relay_ip: "127.0.0.1".parse().unwrap(),
+                    // an ephemeral port
+                    relay_port_min: 0,
+                    relay_port_max: 0,
+                    realm: REALM.to_string(),
+                    users: vec!["alice:secret".parse::<TurnUser>().unwrap()],
+                    shared_secret: "shared".to_string(),
+                    allowed_peers: vec!["127.0.0.0/8".parse().unwrap()],
+                    max_allocations_per_user,
+                    connection_limit: ConnectionLimit::new(1),
+                },
+                Arc::new(AtomicBool::new(false)),
+            ));
+            Harness::with_server(server)
+        }
+
+        /// Another client of the same server.
+        fn with_server(server: Arc<Server>) -> Self {
+            let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            client_socket
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            Harness {
+                server,
+                server_socket: Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap()),
+                client_socket,
+            }
+        }
+
+        fn client(&self) -> Client {
+            Client::udp(
+                self.client_socket.local_addr().unwrap(),
+                Arc::clone(&self.server_socket),
+            )
+        }
+
+        fn send(&self, data: &[u8]) {
+            self.server.handle(&self.client(), data);
+        }
+
+        fn receive(&self) -> Vec<u8> {
+            let mut buf = vec![0; MAX_MESSAGE_LEN];
+            let n = self.client_socket.recv(&mut buf).unwrap();
+            buf.truncate(n);
+            buf
+        }
+
+        fn request(
+            &self,
+            method: stun::message::Method,
+            attributes: Vec<Box<dyn Setter>>,
+        ) -> Message {
+            let mut setters: Vec<Box<dyn Setter>> = vec![
+                Box::new(TransactionId::new()),
+                Box::new(MessageType::new(method, CLASS_REQUEST)),
+            ];
+            setters.extend(attributes);
+            let mut request = Message::new();
+            request.build(&setters).unwrap();
+            self.send(&request.raw);
+            decode(&self.receive())
+        }
+
+        fn authenticated(
+            &self,
+            method: stun::message::Method,
+            password: &str,
+            nonce: &str,
+            mut attributes: Vec<Box<dyn Setter>>,
+        ) -> Message {
+            attributes.extend([
+                Box::new(TextAttribute::new(ATTR_USERNAME, "alice".to_string())) as Box<dyn Setter>,
+                Box::new(TextAttribute::new(ATTR_REALM, REALM.to_string())),
+                Box::new(TextAttribute::new(ATTR_NONCE, nonce.to_string())),
+                Box::new(MessageIntegrity::new_long_term_integrity(
+                    "alice".to_string(),
+                    REALM.to_string(),
+                    password.to_string(),
+                )),
+            ]);
+            self.request(method, attributes)
+        }
+
+        fn nonce(&self) -> String {
+            let challenge = self.request(METHOD_ALLOCATE, vec![requested_udp()]);
+            assert_eq!(error_code(&challenge), Some(401));
+            TextAttribute::get_from_as(&challenge, ATTR_NONCE)
+                .unwrap()
+                .text
+        }
+
+        /// Allocate a relay, returning its address and the nonce to sign further requests with.
+        fn allocate(&self) -> (SocketAddr, String) {
+            let nonce = self.nonce();
+            let response =
+                self.authenticated(METHOD_ALLOCATE, "secret", &nonce, vec![requested_udp()]);
+            assert_eq!(response.typ.class, CLASS_SUCCESS_RESPONSE);
+            let mut relayed = XorMappedAddress::default();
+            relayed
+                .get_from_as(&response, ATTR_XOR_RELAYED_ADDRESS)
+                .unwrap();
+            (SocketAddr::new(relayed.ip, relayed.port), nonce)
+        }
+    }
+
+    fn decode(data: &[u8]) -> Message {
+        let mut message = Message {
+            raw: data.to_vec(),
+            ..Default::default()
+        };
+        message.decode().unwrap();
+        message
+    }
+
+    fn error_code(message: &Message) -> Option<u16> {
+        message.contains(ATTR_ERROR_CODE).then(|| {
+            let mut error = ErrorCodeAttribute::default();
+            stun::message::Getter::get_from(&mut error, message).unwrap();
+            error.code.0
+        })
+    }
+
+    fn requested_udp() -> Box<dyn Setter> {
+        raw_attribute(ATTR_REQUESTED_TRANSPORT, vec![PROTOCOL_UDP, 0, 0, 0])
+    }
+
+    fn peer_address(peer: SocketAddr) -> Box<dyn Setter> {
+        Box::new(XorAddress(ATTR_XOR_PEER_ADDRESS, peer))
+    }
+
+    fn peer_socket() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        socket
+    }
+
+    #[test]
+    fn answers_binding_requests_without_credentials() {
+        let harness = Harness::new();
+        let response = harness.request(METHOD_BINDING, vec![]);
+        assert_eq!(response.typ.class, CLASS_SUCCESS_RESPONSE);
+        let mut mapped = XorMappedAddress::default();
+        stun::message::Getter::get_from(&mut mapped, &response).unwrap();
+        assert_eq!(
+            SocketAddr::new(mapped.ip, mapped.port),
+            harness.client_socket.local_addr().unwrap()
+        );
+    }
+
+    #[test]
+    fn challenges_and_checks_credentials() {
+        let harness = Harness::new();
+        let nonce = harness.nonce();
+
+        let response =
+            harness.authenticated(METHOD_ALLOCATE, "wrong", &nonce, vec![requested_udp()]);
+        assert_eq!(error_code(&response), Some(401));
+
+        let stale = harness.server.nonce(unix_now() - NONCE_LIFETIME - 1);
+        let response =
+            harness.authenticated(METHOD_ALLOCATE, "secret", &stale, vec![requested_udp()]);
+        assert_eq!(error_code(&response), Some(438));
+
+        let response = harness.authenticated(
+            METHOD_ALLOCATE,
+            "secret",
+            "0000000000000000",
+            vec![requested_udp()],
+        );
+        assert_eq!(error_code(&response), Some(438));
+
+        let response = harness.authenticated(METHOD_ALLOCATE, "secret", &nonce, vec![]);
+        assert_eq!(error_code(&response), Some(400));
+
+        let response = harness.authenticated(
+            METHOD_ALLOCATE,
+            "secret",
+            &nonce,
+            vec![raw_attribute(ATTR_REQUESTED_TRANSPORT, vec![6, 0, 0, 0])],
+        );
+        assert_eq!(error_code(&response), Some(442));
+        assert!(!harness.server.has_allocation(&harness.client()));
+    }
+
+    #[test]
+    fn accepts_rest_api_credentials_until_they_expire() {
+        let harness = Harness::new();
+        let issuer = CredentialIssuer::new("shared".to_string(), Duration::from_secs(60), vec![]);
+        let credentials = issuer.issue("bob").unwrap();
+        let now = unix_now();
+        assert_eq!(
+            harness.server.password(&credentials.username, now),
+            Some(credentials.password)
+        );
+        assert_eq!(
+            harness.server.password(&credentials.username, now + 120),
+            None
+        );
+        assert_eq!(harness.server.password("bob", now), None);
+    }
+
+    #[test]
+    fn relays_between_client_and_peer() {
+        let harness = Harness::new();
+        let (relayed, nonce) = harness.allocate();
+        let peer = peer_socket();
+        let peer_addr = peer.local_addr().unwrap();
+
+        // nothing is relayed without a permission
+        let mut send = Message::new();
+        send.build(&[
+            Box::new(TransactionId::new()),
+            Box::new(MessageType::new(METHOD_SEND, CLASS_INDICATION)),
+            peer_address(peer_addr),
+            raw_attribute(ATTR_DATA, b"hello".to_vec()),
+        ])
+        .unwrap();
+        harness.send(&send.raw);
+        let response = harness.authenticated(
+            METHOD_CREATE_PERMISSION,
+            "secret",
+            &nonce,
+            vec![peer_address(peer_addr)],
+        );
+        assert_eq!(response.typ.class, CLASS_SUCCESS_RESPONSE);
+        harness.send(&send.raw);
+        let mut buf = [0; 1500];
+        let (n, from) = peer.recv_from(&mut buf).unwrap();
+        assert_eq!((&buf[..n], from), (&b"hello"[..], relayed));
+
+        peer.send_to(b"world", relayed).unwrap();
+        let data = decode(&harness.receive());
+        assert_eq!(data.typ, MessageType::new(METHOD_DATA, CLASS_INDICATION));
+        assert_eq!(data.get(ATTR_DATA).unwrap(), b"world");
+        assert_eq!(
+            decode_xor_address(&data, &data.get(ATTR_XOR_PEER_ADDRESS).unwrap()),
+            Some(peer_addr)
+        );
+
+        let response = harness.authenticated(
+            METHOD_CHANNEL_BIND,
+            "secret",
+            &nonce,
+            vec![
+                raw_attribute(ATTR_CHANNEL_NUMBER, vec![0x40, 0x01, 0, 0]),
+                peer_address(peer_addr),
+            ],
+        );
+        assert_eq!(response.typ.class, CLASS_SUCCESS_RESPONSE);
+        harness.send(&[0x40, 0x01, 0x00, 0x03, 1, 2, 3]);
+        let (n, _) = peer.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], &[1, 2, 3]);
+        // the permission covers the IPv4-mapped address of the peer
+        let mapped_peer = SocketAddr::new(
+            IpAddr::V6(Ipv4Addr::LOCALHOST.to_ipv6_mapped()),
+            peer_addr.port(),
+        );
+        let mut send = Message::new();
+        send.build(&[
+            Box::new(TransactionId::new()),
+            Box::new(MessageType::new(METHOD_SEND, CLASS_INDICATION)),
+            peer_address(mapped_peer),
+            raw_attribute(ATTR_DATA, b"mapped".to_vec()),
+        ])
+        .unwrap();
+        harness.send(&send.raw);
+        let (n, _) = peer.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"mapped");
+        peer.send_to(&[4, 5], relayed).unwrap();
+        assert_eq!(harness.receive(), vec![0x40, 0x01, 0x00, 0x02, 4, 5]);
+
+        // the channel can't be bound to another peer
+        let response = harness.authenticated(
+            METHOD_CHANNEL_BIND,
+            "secret",
+            &nonce,
+            vec![
+                raw_attribute(ATTR_CHANNEL_NUMBER, vec![0x40, 0x01, 0, 0]),
+                peer_address("127.0.0.2:9".parse().unwrap()),
+            ],
+        );
+        assert_eq!(error_code(&response), Some(400));
+    }
+
+    #[test]
+    fn refuses_denied_peers() {
+        let harness = Harness::new();
+        let (_, nonce) = harness.allocate();
+        for peer in ["10.0.0.1:3478", "169.254.169.254:80", "192.168.1.1:53"] {
+            let response = harness.authenticated(
+                METHOD_CREATE_PERMISSION,
+                "secret",
+                &nonce,
+                vec![peer_address(peer.parse().unwrap())],
+            );
+            assert_eq!(error_code(&response), Some(403), "{} is allowed", peer);
+            let response = harness.authenticated(
+                METHOD_CHANNEL_BIND,
+                "secret",
+                &nonce,
+                vec![
+                    raw_attribute(ATTR_CHANNEL_NUMBER, vec![0x40, 0x00, 0, 0]),
+                    peer_address(peer.parse().unwrap()),
+                ],
+            );
+            assert_eq!(error_code(&response), Some(403), "{} is allowed", peer);
+        }
+    }
+
+    #[test]
+    fn refreshes_and_releases_allocations() {
+        let harness = Harness::new();
+        let (_, nonce) = harness.allocate();
+
+        let response =
+            harness.authenticated(METHOD_ALLOCATE, "secret", &nonce, vec![requested_udp()]);
+        assert_eq!(error_code(&response), Some(437));
+
+        let response = harness.authenticated(
+            METHOD_REFRESH,
+            "secret",
+            &nonce,
+            vec![raw_attribute(ATTR_LIFETIME, 7200u32.to_be_bytes().to_vec())],
+        );
+        assert_eq!(response.typ.class, CLASS_SUCCESS_RESPONSE);
+        assert_eq!(
+            response.get(ATTR_LIFETIME).unwrap(),
+            (MAX_LIFETIME.as_secs() as u32).to_be_bytes()
+        );
+
+        let response = harness.authenticated(
+            METHOD_REFRESH,
+            "secret",
+            &nonce,
+            vec![raw_attribute(ATTR_LIFETIME, vec![0, 0, 0, 0])],
+        );
+        assert_eq!(response.typ.class, CLASS_SUCCESS_RESPONSE);
+        assert!(!harness.server.has_allocation(&harness.client()));
+
+        let response = harness.authenticated(METHOD_REFRESH, "secret", &nonce, vec![]);
+        assert_eq!(error_code(&response), Some(437));
+    }
+
+    #[test]
+    fn limits_the_allocations_of_a_user() {
+        let harness = Harness::with_quota(1);
+        harness.allocate();
+
+        let other = Harness::with_server(Arc::clone(&harness.server));
+        let nonce = other.nonce();
+        let response =
+            other.authenticated(METHOD_ALLOCATE, "secret", &nonce, vec![requested_udp()]);
+        assert_eq!(error_code(&response), Some(486));
+        assert!(!other.server.has_allocation(&other.client()));
+
+        harness.server.release(&harness.client());
+        other.allocate();
+    }
+
+    #[test]
+    fn counts_rest_api_allocations_against_their_user() {
+        assert_eq!(quota_user("1718000000:bob"), "bob");
+        assert_eq!(quota_user("alice"), "alice");
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    io::{Error, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use tracing::{debug, error, info, warn};
+
+use crate::transport::tcp::ConnectionLimit;
+
+use super::{
+    server::{Client, Server, CHANNEL_DATA_HEADER_LEN},
+    STOP_CHECK_INTERVAL,
+};
+
+/// STUN messages start with a 20 bytes header, its length field excludes it
+const STUN_HEADER_LEN: usize = 20;
+/// Interval the listener polls for new connections at, to notice the server stopping
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+/// Messages waiting to be written to a connection, further ones being dropped
+const OUTGOING_QUEUE_LEN: usize = 256;
+/// Time a connection may stay silent without holding an allocation
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Split the next STUN or ChannelData message off the byte stream of a client, ChannelData
+/// being padded to 4 bytes over TCP (RFC 8656 section 12.5). Returns `None` until the whole
+/// message is buffered.
+pub fn split_message(buf: &mut BytesMut) -> std::io::Result<Option<BytesMut>> {
+    if buf.len() < CHANNEL_DATA_HEADER_LEN {
+        return Ok(None);
+    }
+    let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+    // The two first bits are 0b00 for STUN, 0b01 for ChannelData
+    let (message_len, padded_len) = match buf[0] >> 6 {
+        0 => (STUN_HEADER_LEN + len, STUN_HEADER_LEN + len),
+        1 => (
+            CHANNEL_DATA_HEADER_LEN + len,
+            CHANNEL_DATA_HEADER_LEN + len.next_multiple_of(4),
+        ),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "neither STUN nor ChannelData",
+            ))
+        }
+    };
+    if buf.len() < padded_len {
+        return Ok(None);
+    }
+    let mut message = buf.split_to(padded_len);
+    message.truncate(message_len);
+    Ok(Some(message))
+}
+
+/// Accept TURN clients over TCP (RFC 8656 section 3.1), each connection being served by its own
+/// thread and its allocation released when it closes.
+pub fn spawn_listener(
+    listener: TcpListener,
+    server: Arc<Server>,
+    connection_limit: ConnectionLimit,
+    stopping: Arc<AtomicBool>,
+) -> std::io::Result<()> {
+    listener.set_nonblocking(true)?;
+    info!("turn tcp listening {}...", listener.local_addr()?);
+
+    std::thread::spawn(move || {
+        while !stopping.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, peer_addr)) => {
+                    let Some(permit) = connection_limit.try_acquire() else {
+                        warn!(
+                            "refusing turn tcp connection from {}, too many connections",
+                            peer_addr
+                        );
+                        continue;
+                    };
+                    let server = Arc::clone(&server);
+                    let stopping = Arc::clone(&stopping);
+                    std::thread::spawn(move || {
+                        let _permit = permit;
+                        if let Err(e) = serve(stream, peer_addr, &server, &stopping) {
+                            debug!("turn tcp connection from {} ended: {}", peer_addr, e);
+                        }
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_INTERVAL),
+                Err(e) => {
+                    error!("turn tcp accept failed: {:?}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn serve(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    server: &Arc<Server>,
+    stopping: &AtomicBool,
+) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(STOP_CHECK_INTERVAL))?;
+
+    let (outgoing_tx, outgoing_rx) = crossbeam_channel::bounded::<Vec<u8>>(OUTGOING_QUEUE_LEN);
+    let mut writer = stream.try_clone()?;
+    std::thread::spawn(move || {
+        for message in outgoing_rx {
+            if writer.write_all(&message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let client = Client::tcp(peer_addr, outgoing_tx);
+    let result = read_messages(&stream, &client, server, stopping);
+    server.release(&client);
+    // unblocks the writer, which may be stuck on a client not reading anymore
+    let _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+/// Hand the messages of a client to the server until it closes the connection, or stays
+/// silent for too long without an allocation.
+fn read_messages(
+    mut stream: &TcpStream,
+    client: &Client,
+    server: &Arc<Server>,
+    stopping: &AtomicBool,
+) -> std::io::Result<()> {
+    let mut buf = BytesMut::new();
+    let mut chunk = vec![0; 4096];
+    let mut last_read = Instant::now();
+    while !stopping.load(Ordering::Relaxed) {
+        while let Some(message) = split_message(&mut buf)? {
+            server.handle(client, &message);
+        }
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(n) => {
+                buf.extend_from_slice(&chunk[..n]);
+                last_read = Instant::now();
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if last_read.elapsed() >= IDLE_TIMEOUT && !server.has_allocation(client) {
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        "idle connection without allocation",
+                    ));
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_stun_messages() {
+        // a binding request with a 4 bytes attribute, followed by the start of another one
+        let mut stun = vec![0x00, 0x01, 0x00, 0x04, 0x21, 0x12, 0xa4, 0x42];
+        stun.extend_from_slice(&[7; 12]);
+        stun.extend_from_slice(&[0x80, 0x22, 0x00, 0x00]);
+        let mut buf = BytesMut::from(&stun[..]);
+        buf.extend_from_slice(&stun[..10]);
+
+        let message = split_message(&mut buf).unwrap().unwrap();
+        assert_eq!(&message[..], &stun[..]);
+        assert!(split_message(&mut buf).unwrap().is_none());
+        assert_eq!(buf.len(), 10);
+
+        buf.extend_from_slice(&stun[10..]);
+        assert_eq!(&split_message(&mut buf).unwrap().unwrap()[..], &stun[..]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn strips_channel_data_padding() {
+        // 5 bytes of data on channel 0x4000, padded to 8
+        let mut buf = BytesMut::from(&[0x40, 0x00, 0x00, 0x05, 1, 2, 3, 4, 5, 0, 0, 0][..]);
+        buf.extend_from_slice(&[0x40, 0x01, 0x00, 0x04, 9, 9, 9, 9]);
+
+        let message = split_message(&mut buf).unwrap().unwrap();
+        assert_eq!(&message[..], &[0x40, 0x00, 0x00, 0x05, 1, 2, 3, 4, 5]);
+        let message = split_message(&mut buf).unwrap().unwrap();
+        assert_eq!(&message[..], &[0x40, 0x01, 0x00, 0x04, 9, 9, 9, 9]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn waits_for_padding() {
+        let mut buf = BytesMut::from(&[0x40, 0x00, 0x00, 0x01, 1][..]);
+        assert!(split_message(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(&[0, 0, 0]);
+        assert_eq!(
+            &split_message(&mut buf).unwrap().unwrap()[..],
+            &[0x40, 0x00, 0x00, 0x01, 1]
+        );
+    }
+
+    #[test]
+    fn waits_for_header() {
+        let mut buf = BytesMut::from(&[0x00, 0x01, 0x00][..]);
+        assert!(split_message(&mut buf).unwrap().is_none());
+        assert_eq!(buf.len(), 3);
+    }
+
+    #[test]
+    fn rejects_other_protocols() {
+        // an RTP packet
+        let mut buf = BytesMut::from(&[0x80, 0x60, 0x00, 0x01, 0x00][..]);
+        assert!(split_message(&mut buf).is_err());
+    }
+}
@@ -1,16 +1,28 @@
 use std::{
     collections::HashMap,
+    convert::Infallible,
+    path::PathBuf,
     sync::mpsc::{self, Sender},
+    time::{Duration, Instant},
 };
 
 use actix_web::{
-    get, post,
+    get,
+    http::{header, StatusCode},
+    post,
     web::{self, Data},
-    HttpResponse, Responder,
+    HttpRequest, HttpResponse, Responder,
 };
 use bytes::Bytes;
+use futures_util::{stream, StreamExt};
+use sfu::WorkerPlacement;
 use tracing::{error, info};
 
+use crate::directory::{Forwarding, Route, SessionOwner, SessionRouter};
+use crate::events::{EventStreams, Subscription};
+use crate::middleware::verify_jwt::{redacted_uri, verify_token};
+use crate::relay::CredentialIssuer;
+use crate::transport::cascade::{self, CascadeNodes};
 use crate::transport::handlers::{SignalingMessage, SignalingProtocolMessage};
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -25,18 +37,97 @@ pub async fn health() -> impl Responder {
     HttpResponse::Ok().body("OK")
 }
 
+/// redirect_to_owner answers a request for a session owned by another node with a temporary
+/// redirect to that node
+fn redirect_to_owner(req: &HttpRequest, owner: &SessionOwner) -> HttpResponse {
+    let url = format!("{}{}", owner.node.trim_end_matches('/'), req.uri());
+    info!("Redirecting {} to {}", redacted_uri(req), owner.node);
+    HttpResponse::TemporaryRedirect()
+        .insert_header((header::LOCATION, url))
+        .finish()
+}
+
+/// forward_to_owner hands a request for a session owned by another node over to that node,
+/// redirecting the client to it or proxying the request with the client shared by the router
+async fn forward_to_owner(
+    req: &HttpRequest,
+    body: Bytes,
+    owner: &SessionOwner,
+    session_router: &SessionRouter,
+) -> HttpResponse {
+    if session_router.forwarding() == Forwarding::Redirect {
+        return redirect_to_owner(req, owner);
+    }
+    let url = format!("{}{}", owner.node.trim_end_matches('/'), req.uri());
+    info!("Forwarding {} to {}", redacted_uri(req), owner.node);
+
+    let mut request = session_router.proxy_client().post(&url).body(body);
+    for name in [header::CONTENT_TYPE, header::AUTHORIZATION] {
+        if let Some(value) = req.headers().get(&name) {
+            request = request.header(name.as_str(), value.as_bytes());
+        }
+    }
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            error!(
+                "Error proxying {} to {}: {}",
+                redacted_uri(req),
+                owner.node,
+                e
+            );
+            return HttpResponse::BadGateway().body("Session owner is unreachable");
+        }
+    };
+    let status =
+        StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("text/plain")
+        .to_string();
+    match response.bytes().await {
+        Ok(body) => HttpResponse::build(status)
+            .content_type(content_type)
+            .body(body),
+        Err(e) => {
+            error!(
+                "Error proxying {} to {}: {}",
+                redacted_uri(req),
+                owner.node,
+                e
+            );
+            HttpResponse::BadGateway().body("Session owner is unreachable")
+        }
+    }
+}
+
+/// Options of an endpoint joining a session with its offer
+#[derive(Debug, serde::Deserialize)]
+pub struct OfferQuery {
+    /// Whether the endpoint receives every track published in the session, until it subscribes
+    /// or unsubscribes explicitly
+    #[serde(default = "default_auto_subscribe")]
+    auto_subscribe: bool,
+}
+
+fn default_auto_subscribe() -> bool {
+    true
+}
+
 #[post("/offer/{session}/{endpoint}")]
 pub async fn handle_offer(
-    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    path: web::Path<(u64, u64)>,
+    query: web::Query<OfferQuery>,
     offer_sdp: web::Json<RTCSessionDescriptionSerializable>,
     media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+    session_router: Data<SessionRouter>,
 ) -> impl Responder {
-    let path = path.into_inner();
-    let session_id = u64::from_str_radix(&path.0, 10).unwrap();
-    let endpoint_id = u64::from_str_radix(&path.1, 10).unwrap();
-    let sorted_ports: Vec<u16> = media_port_thread_map.keys().map(|x| *x).collect();
-    let port = sorted_ports[(session_id as usize) % sorted_ports.len()];
-    let (response_tx, response_rx) = mpsc::channel();
+    let (session_id, endpoint_id) = path.into_inner();
+    let port = worker_placement.endpoint_port(session_id, endpoint_id);
     let payload_to_string = serde_json::to_string(&offer_sdp).map_err(|e| {
         error!("Error serializing offer: {}", e);
         HttpResponse::InternalServerError().body("Error serializing offer")
@@ -49,84 +140,803 @@ pub async fn handle_offer(
         }
         Err(r) => return r,
     };
-    match media_port_thread_map.get(&port) {
-        Some(tx) => {
-            tx.send(SignalingMessage {
-                request: SignalingProtocolMessage::Offer {
-                    session_id,
-                    endpoint_id,
-                    offer_sdp: offer_sdp,
-                },
-                response_tx,
-            })
-            .unwrap();
+
+    let router = session_router.clone().into_inner();
+    match web::block(move || router.claim(session_id)).await {
+        Ok(Ok(Route::Local)) => (),
+        Ok(Ok(Route::Remote(owner))) => {
+            return forward_to_owner(&req, offer_sdp, &owner, &session_router).await
+        }
+        Ok(Err(e)) => {
+            error!("Error claiming session {}: {}", session_id, e);
+            return HttpResponse::ServiceUnavailable().body("Session directory is unavailable");
         }
-        None => {
-            return HttpResponse::InternalServerError().body("No media port available");
+        Err(_) => {
+            return HttpResponse::InternalServerError().body("Session directory is unavailable")
         }
+    }
+
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::Offer {
+            session_id,
+            endpoint_id,
+            offer_sdp,
+            auto_subscribe: query.auto_subscribe,
+        },
+    )
+    .await
+}
+
+/// dispatch_to_worker sends a request to the media worker listening on the port, and answers
+/// with its response, waited for on a blocking thread rather than on the async runtime
+async fn dispatch_to_worker(
+    media_port_thread_map: &HashMap<u16, Sender<SignalingMessage>>,
+    port: Option<u16>,
+    request: SignalingProtocolMessage,
+) -> HttpResponse {
+    let Some(tx) = port.and_then(|port| media_port_thread_map.get(&port)) else {
+        return HttpResponse::InternalServerError().body("No media port available");
+    };
+    let (response_tx, response_rx) = mpsc::channel();
+    if tx
+        .send(SignalingMessage {
+            request,
+            response_tx,
+        })
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().body("Media worker is stopped");
+    }
+    let response = match web::block(move || response_rx.recv()).await {
+        Ok(Ok(response)) => response,
+        _ => return HttpResponse::InternalServerError().body("Media worker is stopped"),
     };
 
-    let response = response_rx.recv().expect("receive answer offer");
-    let to_send = match response {
+    match response {
+        SignalingProtocolMessage::Ok { .. } => HttpResponse::Ok().finish(),
         SignalingProtocolMessage::Answer {
-            session_id: _,
-            endpoint_id: _,
-            answer_sdp,
+            answer_sdp: body, ..
+        }
+        | SignalingProtocolMessage::Recording { manifest: body, .. }
+        | SignalingProtocolMessage::Capture { summary: body, .. }
+        | SignalingProtocolMessage::Egress { summary: body, .. }
+        | SignalingProtocolMessage::Hls { summary: body, .. }
+        | SignalingProtocolMessage::Cascade { summary: body, .. }
+        | SignalingProtocolMessage::PlainIngest {
+            description: body, ..
+        }
+        | SignalingProtocolMessage::Stats { stats: body, .. }
+        | SignalingProtocolMessage::ActiveSpeaker {
+            event: Some(body), ..
         } => HttpResponse::Ok()
             .content_type("application/json")
-            .body(answer_sdp),
+            .body(body),
+        // nobody spoke in the session yet
+        SignalingProtocolMessage::ActiveSpeaker { event: None, .. } => {
+            HttpResponse::NoContent().finish()
+        }
         SignalingProtocolMessage::Err {
             session_id,
             endpoint_id,
             reason,
         } => {
-            let reason_str = std::str::from_utf8(&reason).unwrap();
+            let reason_str = String::from_utf8_lossy(&reason);
             error!(
                 "Error for session {} endpoint {}: {}",
                 session_id, endpoint_id, reason_str
             );
-            HttpResponse::InternalServerError().body(format!(
-                "Error for session {} endpoint {}: {}",
-                session_id, endpoint_id, reason_str
-            ))
+            HttpResponse::BadRequest().body(reason_str.to_string())
         }
-        SignalingProtocolMessage::Leave {
+        _ => HttpResponse::InternalServerError().body("Unexpected media worker response"),
+    }
+}
+
+#[post("/layer/{session}/{endpoint}")]
+pub async fn request_layer(
+    req: HttpRequest,
+    path: web::Path<(u64, u64)>,
+    layer_request: web::Bytes,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let (session_id, endpoint_id) = path.into_inner();
+    let port = worker_placement.endpoint_port(session_id, endpoint_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::Layer {
             session_id,
             endpoint_id,
-        } => {
-            error!("Session {} endpoint {} left", session_id, endpoint_id);
-            return HttpResponse::InternalServerError().body("Session left");
-        }
-        SignalingProtocolMessage::Offer {
+            layer_request,
+        },
+    )
+    .await
+}
+
+#[post("/pin/{session}/{endpoint}")]
+pub async fn pin_endpoints(
+    req: HttpRequest,
+    path: web::Path<(u64, u64)>,
+    pin_request: web::Bytes,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let (session_id, endpoint_id) = path.into_inner();
+    let port = worker_placement.endpoint_port(session_id, endpoint_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::Pin {
             session_id,
             endpoint_id,
-            offer_sdp,
-        } => {
-            let offer_sdp_str = std::str::from_utf8(&offer_sdp).unwrap();
-            error!(
-                "Received offer for session {} endpoint {} while expecting answer with sdp {}",
-                session_id, endpoint_id, offer_sdp_str
-            );
-            return HttpResponse::InternalServerError()
-                .body("Received offer for session endpoint while expecting answer");
-        }
+            pin_request,
+        },
+    )
+    .await
+}
 
-        SignalingProtocolMessage::Ok {
+#[post("/subscribe/{session}/{endpoint}")]
+pub async fn subscribe(
+    req: HttpRequest,
+    path: web::Path<(u64, u64)>,
+    subscription_request: web::Bytes,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let (session_id, endpoint_id) = path.into_inner();
+    let port = worker_placement.endpoint_port(session_id, endpoint_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::Subscribe {
             session_id,
             endpoint_id,
-        } => {
-            error!(
-                "Received Ok for session {} endpoint {} while expecting answer",
-                session_id, endpoint_id
-            );
-            return HttpResponse::InternalServerError()
-                .body("Received Ok for session endpoint while expecting answer");
+            subscription_request,
+        },
+    )
+    .await
+}
+
+#[post("/unsubscribe/{session}/{endpoint}")]
+pub async fn unsubscribe(
+    req: HttpRequest,
+    path: web::Path<(u64, u64)>,
+    subscription_request: web::Bytes,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let (session_id, endpoint_id) = path.into_inner();
+    let port = worker_placement.endpoint_port(session_id, endpoint_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::Unsubscribe {
+            session_id,
+            endpoint_id,
+            subscription_request,
+        },
+    )
+    .await
+}
+
+#[post("/codecs/{session}")]
+pub async fn set_codec_policy(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    codec_policy: web::Bytes,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::CodecPolicy {
+            session_id,
+            codec_policy,
+        },
+    )
+    .await
+}
+
+#[post("/recording/{session}/start")]
+pub async fn start_recording(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StartRecording { session_id },
+    )
+    .await
+}
+
+#[post("/recording/{session}/stop")]
+pub async fn stop_recording(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StopRecording { session_id },
+    )
+    .await
+}
+
+#[post("/capture/{session}/start")]
+pub async fn start_capture(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    capture_request: web::Bytes,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StartCapture {
+            session_id,
+            capture_request,
+        },
+    )
+    .await
+}
+
+#[post("/capture/{session}/stop")]
+pub async fn stop_capture(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StopCapture { session_id },
+    )
+    .await
+}
+
+#[post("/inject/{session}/start")]
+pub async fn start_injection(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    inject_request: web::Bytes,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StartInjection {
+            session_id,
+            inject_request,
+        },
+    )
+    .await
+}
+
+#[post("/inject/{session}/{endpoint}/stop")]
+pub async fn stop_injection(
+    req: HttpRequest,
+    path: web::Path<(u64, u64)>,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let (session_id, endpoint_id) = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StopInjection {
+            session_id,
+            endpoint_id,
+        },
+    )
+    .await
+}
+
+#[post("/egress/{session}/start")]
+pub async fn start_egress(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    egress_request: web::Bytes,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StartEgress {
+            session_id,
+            egress_request,
+        },
+    )
+    .await
+}
+
+#[post("/egress/{session}/stop")]
+pub async fn stop_egress(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StopEgress { session_id },
+    )
+    .await
+}
+
+#[post("/hls/{session}/start")]
+pub async fn start_hls(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    hls_request: web::Bytes,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StartHls {
+            session_id,
+            hls_request,
+        },
+    )
+    .await
+}
+
+#[post("/hls/{session}/stop")]
+pub async fn stop_hls(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StopHls { session_id },
+    )
+    .await
+}
+
+/// how long a request for a playlist update, or for a partial segment yet to be packaged, is
+/// held before being answered
+const HLS_BLOCKING_TIMEOUT: Duration = Duration::from_secs(3);
+const HLS_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Low latency HLS delivery directives of a playlist request
+#[derive(Debug, serde::Deserialize)]
+pub struct HlsQuery {
+    #[serde(rename = "_HLS_msn")]
+    msn: Option<u64>,
+    #[serde(rename = "_HLS_part")]
+    part: Option<usize>,
+}
+
+#[get("/hls/{session}/{file}")]
+pub async fn hls_file(
+    path: web::Path<(u64, String)>,
+    query: web::Query<HlsQuery>,
+    hls_dir: Data<Option<PathBuf>>,
+) -> impl Responder {
+    let Some(hls_dir) = hls_dir.get_ref() else {
+        return HttpResponse::NotFound().body("HLS is not configured");
+    };
+    let (session_id, file_name) = path.into_inner();
+    // temporary files start with a dot
+    if file_name.starts_with('.')
+        || !file_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+    {
+        return HttpResponse::NotFound().finish();
+    }
+    let (content_type, cache_control) = match file_name.rsplit_once('.') {
+        Some((_, "m3u8")) => ("application/vnd.apple.mpegurl", "no-cache"),
+        Some((_, "mp4")) => ("video/mp4", "public, max-age=60"),
+        Some((_, "m4s")) => ("video/iso.segment", "public, max-age=60"),
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    let file = hls_dir.join(session_id.to_string()).join(&file_name);
+
+    // partial segments are requested as soon as they are hinted, and playlists as soon as
+    // they list the requested segment or partial segment
+    let deadline = Instant::now() + HLS_BLOCKING_TIMEOUT;
+    loop {
+        if let Ok(data) = std::fs::read(&file) {
+            let is_ready = match (content_type, query.msn) {
+                ("application/vnd.apple.mpegurl", Some(msn)) => {
+                    is_hls_playlist_ready(&String::from_utf8_lossy(&data), msn, query.part)
+                }
+                _ => true,
+            };
+            if is_ready || Instant::now() >= deadline {
+                return HttpResponse::Ok()
+                    .content_type(content_type)
+                    .insert_header(("Cache-Control", cache_control))
+                    .body(data);
+            }
+        } else if Instant::now() >= deadline {
+            return HttpResponse::NotFound().finish();
         }
+        actix_web::rt::time::sleep(HLS_POLL_INTERVAL).await;
+    }
+}
+
+/// is_hls_playlist_ready tells whether a playlist lists the segment, or the partial segment of
+/// the segment, a viewer asks for, or a later one, or is ended
+fn is_hls_playlist_ready(playlist: &str, msn: u64, part: Option<usize>) -> bool {
+    if playlist.contains("#EXT-X-ENDLIST") {
+        return true;
+    }
+    playlist
+        .lines()
+        // the preload hint names a partial segment yet to be packaged
+        .filter(|line| !line.starts_with("#EXT-X-PRELOAD-HINT"))
+        .filter_map(|line| {
+            let uri = line.split("segment").nth(1)?;
+            let uri = uri.split(".m4s").next()?;
+            let (sequence_number, part_index) = match uri.split_once('.') {
+                Some((sequence_number, part_index)) => (
+                    sequence_number.parse::<u64>().ok()?,
+                    part_index.parse::<usize>().ok(),
+                ),
+                None => (uri.parse::<u64>().ok()?, None),
+            };
+            Some((sequence_number, part_index))
+        })
+        .any(|(sequence_number, part_index)| {
+            sequence_number > msn
+                || (sequence_number == msn
+                    && match (part, part_index) {
+                        // a complete segment has every partial segment
+                        (_, None) => true,
+                        (Some(part), Some(part_index)) => part_index >= part,
+                        (None, Some(_)) => false,
+                    })
+        })
+}
+
+#[post("/ingest/{session}/start")]
+pub async fn start_plain_ingest(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    ingest_request: web::Bytes,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StartPlainIngest {
+            session_id,
+            ingest_request,
+        },
+    )
+    .await
+}
+
+#[post("/ingest/{session}/{endpoint}/stop")]
+pub async fn stop_plain_ingest(
+    req: HttpRequest,
+    path: web::Path<(u64, u64)>,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let (session_id, endpoint_id) = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StopPlainIngest {
+            session_id,
+            endpoint_id,
+        },
+    )
+    .await
+}
+
+/// Address of the cascade listener of another node hosting the session
+#[derive(Debug, serde::Deserialize)]
+pub struct CascadeRequest {
+    address: String,
+}
+
+#[post("/cascade/{session}/start")]
+pub async fn start_cascade(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    cascade_request: web::Json<CascadeRequest>,
+    cascade_nodes: Data<Option<CascadeNodes>>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let Some(cascade_nodes) = cascade_nodes.get_ref().clone() else {
+        return HttpResponse::NotFound().body("Cascading is not configured");
     };
-    to_send
+    let session_id = path.into_inner();
+    let address = cascade_request.into_inner().address;
+
+    let link = {
+        let address = address.clone();
+        web::block(move || cascade::connect(&cascade_nodes, &address, session_id)).await
+    };
+    match link {
+        Ok(Ok(link_id)) => HttpResponse::Ok().json(serde_json::json!({
+            "session_id": session_id,
+            "link_id": link_id,
+            "address": address,
+        })),
+        Ok(Err(e)) => {
+            error!("Error linking session {} to {}: {}", session_id, address, e);
+            HttpResponse::BadGateway().body(e.to_string())
+        }
+        Err(_) => HttpResponse::InternalServerError().body("Unexpected start cascade response"),
+    }
+}
+
+#[post("/cascade/{session}/stop")]
+pub async fn stop_cascade(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+    let port = worker_placement.session_port(session_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::StopCascade { session_id },
+    )
+    .await
+}
+
+#[get("/stats/{session}/{endpoint}")]
+pub async fn endpoint_stats(
+    req: HttpRequest,
+    path: web::Path<(u64, u64)>,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let (session_id, endpoint_id) = path.into_inner();
+    let port = worker_placement.endpoint_port(session_id, endpoint_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::GetStats {
+            session_id,
+            endpoint_id,
+        },
+    )
+    .await
+}
+
+#[get("/active_speaker/{session}/{endpoint}")]
+pub async fn active_speaker(
+    req: HttpRequest,
+    path: web::Path<(u64, u64)>,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let (session_id, endpoint_id) = path.into_inner();
+    let port = worker_placement.endpoint_port(session_id, endpoint_id);
+    dispatch_to_worker(
+        &media_port_thread_map,
+        port,
+        SignalingProtocolMessage::GetActiveSpeaker {
+            session_id,
+            endpoint_id,
+        },
+    )
+    .await
 }
 
 #[post("/leave/{session}/{endpoint}")]
-pub async fn leave() -> impl Responder {
-    HttpResponse::Ok() //idk what to do here
+pub async fn leave(
+    req: HttpRequest,
+    path: web::Path<(u64, u64)>,
+    body: web::Bytes,
+    media_port_thread_map: Data<HashMap<u16, Sender<SignalingMessage>>>,
+    worker_placement: Data<WorkerPlacement>,
+    session_router: Data<SessionRouter>,
+) -> impl Responder {
+    let (session_id, endpoint_id) = path.into_inner();
+    let router = session_router.clone().into_inner();
+    match web::block(move || router.lookup(session_id)).await {
+        Ok(Ok(Route::Remote(owner))) => forward_to_owner(&req, body, &owner, &session_router).await,
+        Ok(Ok(Route::Local)) => {
+            dispatch_to_worker(
+                &media_port_thread_map,
+                worker_placement.endpoint_port(session_id, endpoint_id),
+                SignalingProtocolMessage::Leave {
+                    session_id,
+                    endpoint_id,
+                },
+            )
+            .await
+        }
+        Ok(Err(e)) => {
+            error!("Error looking session {} up: {}", session_id, e);
+            HttpResponse::ServiceUnavailable().body("Session directory is unavailable")
+        }
+        Err(_) => HttpResponse::InternalServerError().body("Session directory is unavailable"),
+    }
+}
+
+/// Delay in milliseconds before a client reconnects to an event stream
+const EVENT_STREAM_RETRY: &str = "retry: 3000\n\n";
+/// Interval comments are sent at on an idle event stream, for proxies not to close it
+const EVENT_STREAM_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[get("/sessions/{session}/events")]
+pub async fn session_events(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    event_streams: Data<EventStreams>,
+    session_router: Data<SessionRouter>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let session_id = path.into_inner();
+
+    let router = session_router.clone().into_inner();
+    match web::block(move || router.lookup(session_id)).await {
+        // a stream can't be proxied, the client is redirected to the owner whatever the forwarding
+        Ok(Ok(Route::Remote(owner))) => return redirect_to_owner(&req, &owner),
+        Ok(Ok(Route::Local)) => (),
+        Ok(Err(e)) => {
+            error!("Error looking session {} up: {}", session_id, e);
+            return HttpResponse::ServiceUnavailable().body("Session directory is unavailable");
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().body("Session directory is unavailable")
+        }
+    }
+
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    let Subscription { backlog, rx } = event_streams.subscribe(session_id, last_event_id);
+    info!(
+        "Streaming events of session {} from event {:?}",
+        session_id, last_event_id
+    );
+
+    let backlog = stream::iter(
+        std::iter::once(Bytes::from_static(EVENT_STREAM_RETRY.as_bytes())).chain(backlog),
+    );
+    let live = stream::unfold(rx, |mut rx| async move {
+        match actix_web::rt::time::timeout(EVENT_STREAM_KEEP_ALIVE, rx.recv()).await {
+            Ok(Some(frame)) => Some((frame, rx)),
+            // the subscriber was too slow and dropped
+            Ok(None) => None,
+            Err(_) => Some((Bytes::from_static(b": keep-alive\n\n"), rx)),
+        }
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(backlog.chain(live).map(Ok::<_, Infallible>))
+}
+
+#[get("/turn/credentials/{endpoint}")]
+pub async fn turn_credentials(
+    req: HttpRequest,
+    path: web::Path<String>,
+    credential_issuer: Data<Option<CredentialIssuer>>,
+) -> impl Responder {
+    if !verify_token(&req) {
+        return HttpResponse::Unauthorized().body("Missing or invalid token");
+    }
+    let Some(credential_issuer) = credential_issuer.as_ref() else {
+        return HttpResponse::NotFound().body("TURN server is disabled");
+    };
+
+    match credential_issuer.issue(&path.into_inner()) {
+        Ok(credentials) => HttpResponse::Ok().json(credentials),
+        Err(e) => {
+            error!("Error issuing turn credentials: {}", e);
+            HttpResponse::InternalServerError().body("Error issuing turn credentials")
+        }
+    }
 }
@@ -1,23 +1,61 @@
-use std::{collections::HashMap, sync::mpsc::Sender};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{mpsc::Sender, Arc},
+};
 
 use actix_cors::Cors;
-use actix_web::{web::Data, App, HttpServer};
+use actix_web::{
+    error::ErrorBadRequest,
+    web::{Data, PathConfig},
+    App, HttpServer,
+};
+use sfu::WorkerPlacement;
 use tracing::info;
 
 use crate::{
-    signalling::signaling_controller::{handle_offer, health, leave},
-    transport::handlers::SignalingMessage,
+    directory::SessionRouter,
+    events::EventStreams,
+    middleware::verify_jwt::TokenVerifier,
+    relay::CredentialIssuer,
+    signalling::signaling_controller::{
+        active_speaker, endpoint_stats, handle_offer, health, hls_file, leave, pin_endpoints,
+        request_layer, session_events, set_codec_policy, start_capture, start_cascade,
+        start_egress, start_hls, start_injection, start_plain_ingest, start_recording,
+        stop_capture, stop_cascade, stop_egress, stop_hls, stop_injection, stop_plain_ingest,
+        stop_recording, subscribe, turn_credentials, unsubscribe,
+    },
+    transport::{cascade::CascadeNodes, handlers::SignalingMessage},
 };
 
-pub async fn start(
-    addr: &str,
-    port: &str,
-    media_port_thread_map: HashMap<u16, Sender<SignalingMessage>>,
-) -> std::io::Result<()> {
+/// State shared with the handlers of the signalling server.
+pub struct WebServerConfig {
+    pub token_verifier: Option<TokenVerifier>,
+    /// port -> signalling input of the media worker bound to it
+    pub media_port_thread_map: HashMap<u16, Sender<SignalingMessage>>,
+    pub worker_placement: WorkerPlacement,
+    pub credential_issuer: Option<CredentialIssuer>,
+    pub hls_dir: Option<PathBuf>,
+    pub cascade_nodes: Option<CascadeNodes>,
+    pub session_router: Arc<SessionRouter>,
+    pub event_streams: Arc<EventStreams>,
+}
+
+pub async fn start(addr: &str, port: &str, config: WebServerConfig) -> std::io::Result<()> {
     let addr = format!("{}:{}", addr, port);
+    let WebServerConfig {
+        token_verifier,
+        media_port_thread_map,
+        worker_placement,
+        credential_issuer,
+        hls_dir,
+        cascade_nodes,
+        session_router,
+        event_streams,
+    } = config;
 
     info!("Running in prod mode");
-    return HttpServer::new(move || {
+    HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
             .allow_any_method()
@@ -26,12 +64,45 @@ pub async fn start(
 
         App::new()
             .wrap(cors)
+            .app_data(Data::new(token_verifier.clone()))
             .app_data(Data::new(media_port_thread_map.clone()))
+            .app_data(Data::new(worker_placement.clone()))
+            .app_data(Data::new(credential_issuer.clone()))
+            .app_data(Data::new(hls_dir.clone()))
+            .app_data(Data::new(cascade_nodes.clone()))
+            .app_data(Data::from(session_router.clone()))
+            .app_data(Data::from(event_streams.clone()))
+            // session and endpoint ids which aren't numbers are a bad request, not a missing route
+            .app_data(PathConfig::default().error_handler(|err, _| ErrorBadRequest(err)))
             .service(handle_offer)
             .service(health)
             .service(leave)
+            .service(request_layer)
+            .service(pin_endpoints)
+            .service(subscribe)
+            .service(unsubscribe)
+            .service(set_codec_policy)
+            .service(start_recording)
+            .service(stop_recording)
+            .service(start_capture)
+            .service(stop_capture)
+            .service(start_injection)
+            .service(stop_injection)
+            .service(start_egress)
+            .service(stop_egress)
+            .service(start_hls)
+            .service(stop_hls)
+            .service(hls_file)
+            .service(start_plain_ingest)
+            .service(stop_plain_ingest)
+            .service(start_cascade)
+            .service(stop_cascade)
+            .service(endpoint_stats)
+            .service(active_speaker)
+            .service(session_events)
+            .service(turn_credentials)
     })
     .bind(addr)?
     .run()
-    .await;
+    .await
 }
@@ -0,0 +1,232 @@
+use std::{
+    io::{Error, ErrorKind},
+    net::{IpAddr, SocketAddr, UdpSocket},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use stun::{
+    message::{Getter, Message, TransactionId, BINDING_REQUEST, BINDING_SUCCESS},
+    xoraddr::XorMappedAddress,
+};
+use tracing::{info, warn};
+
+const STUN_DISCOVERY_ATTEMPTS: u32 = 3;
+
+/// An address announced to clients as an ICE candidate.
+///
+/// Behind a 1:1 NAT the media ports are usually reachable under the same numbers, but some
+/// setups (e.g. Kubernetes NodePort) expose them under another range. In that case `port_base`
+/// is the external port of `--media-port-min`, and the other workers follow consecutively.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnnouncedAddress {
+    pub ip: IpAddr,
+    pub port_base: Option<u16>,
+}
+
+impl AnnouncedAddress {
+    /// Check that the external ports of all the workers fit below 65536, which `socket_addr`
+    /// relies on.
+    pub fn check_port_range(&self, media_port_min: u16, media_port_max: u16) -> Result<(), String> {
+        let Some(port_base) = self.port_base else {
+            return Ok(());
+        };
+        let workers = media_port_max.saturating_sub(media_port_min);
+        match port_base.checked_add(workers) {
+            Some(_) => Ok(()),
+            None => Err(format!(
+                "announced address {}:{} leaves no room for the {} media ports",
+                self.ip,
+                port_base,
+                workers as u32 + 1
+            )),
+        }
+    }
+
+    /// Resolve the announced address for the worker bound on `port`.
+    pub fn socket_addr(&self, port: u16, media_port_min: u16) -> SocketAddr {
+        let port = match self.port_base {
+            Some(port_base) => port_base + (port - media_port_min),
+            None => port,
+        };
+        SocketAddr::new(self.ip, port)
+    }
+}
+
+impl FromStr for AnnouncedAddress {
+    type Err = String;
+
+    /// Accepts `IP`, `IPv4:PORT` or `[IPv6]:PORT`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = IpAddr::from_str(s) {
+            return Ok(AnnouncedAddress {
+                ip,
+                port_base: None,
+            });
+        }
+        SocketAddr::from_str(s)
+            .map(|addr| AnnouncedAddress {
+                ip: addr.ip(),
+                port_base: Some(addr.port()),
+            })
+            .map_err(|e| format!("invalid announced address {}: {}", s, e))
+    }
+}
+
+/// Build the list of candidate addresses for the worker bound on `port`, in order of preference.
+pub fn candidate_addrs(
+    announced_addresses: &[AnnouncedAddress],
+    port: u16,
+    media_port_min: u16,
+) -> Vec<SocketAddr> {
+    let mut addrs: Vec<SocketAddr> = vec![];
+    for announced in announced_addresses {
+        let addr = announced.socket_addr(port, media_port_min);
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    addrs
+}
+
+/// Ask `stun_server` which public address `socket` is mapped to (RFC 5389 Binding request).
+///
+/// Must be called before the socket is handed to its worker, since the response is read
+/// straight from it.
+pub fn discover_mapped_address(
+    socket: &UdpSocket,
+    stun_server: SocketAddr,
+    timeout: Duration,
+) -> std::io::Result<SocketAddr> {
+    let mut buf = vec![0; 1500];
+    for attempt in 1..=STUN_DISCOVERY_ATTEMPTS {
+        let mut request = Message::new();
+        let transaction_id = TransactionId::new();
+        request
+            .build(&[Box::new(transaction_id), Box::new(BINDING_REQUEST)])
+            .map_err(|e| Error::other(format!("failed to build stun request: {}", e)))?;
+        socket.send_to(&request.raw, stun_server)?;
+
+        let deadline = Instant::now() + timeout;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            socket.set_read_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+            let (n, peer_addr) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+                Err(e) => return Err(e),
+            };
+            if peer_addr != stun_server {
+                continue;
+            }
+
+            let mut response = Message {
+                raw: buf[..n].to_vec(),
+                ..Default::default()
+            };
+            if response.decode().is_err()
+                || response.transaction_id != transaction_id
+                || response.typ != BINDING_SUCCESS
+            {
+                continue;
+            }
+
+            let mut mapped = XorMappedAddress::default();
+            mapped.get_from(&response).map_err(|e| {
+                Error::other(format!("stun response without XOR-MAPPED-ADDRESS: {}", e))
+            })?;
+            let mapped = SocketAddr::new(mapped.ip, mapped.port);
+            info!(
+                "{} is mapped to {} according to {}",
+                socket.local_addr()?,
+                mapped,
+                stun_server
+            );
+            return Ok(mapped);
+        }
+        warn!(
+            "no stun response from {} (attempt {}/{})",
+            stun_server, attempt, STUN_DISCOVERY_ATTEMPTS
+        );
+    }
+
+    Err(Error::new(
+        ErrorKind::TimedOut,
+        format!("no stun response from {}", stun_server),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_announced_address() {
+        assert_eq!(
+            "203.0.113.7".parse(),
+            Ok(AnnouncedAddress {
+                ip: "203.0.113.7".parse().unwrap(),
+                port_base: None,
+            })
+        );
+        assert_eq!(
+            "203.0.113.7:30000".parse(),
+            Ok(AnnouncedAddress {
+                ip: "203.0.113.7".parse().unwrap(),
+                port_base: Some(30000),
+            })
+        );
+        assert_eq!(
+            "[2001:db8::7]:30000".parse(),
+            Ok(AnnouncedAddress {
+                ip: "2001:db8::7".parse().unwrap(),
+                port_base: Some(30000),
+            })
+        );
+        assert!("2001:db8::7:30000".parse::<AnnouncedAddress>().is_err());
+        assert!("203.0.113.7:70000".parse::<AnnouncedAddress>().is_err());
+        assert!("example.com:3478".parse::<AnnouncedAddress>().is_err());
+    }
+
+    #[test]
+    fn test_socket_addr() {
+        let same: AnnouncedAddress = "203.0.113.7".parse().unwrap();
+        assert_eq!(
+            same.socket_addr(3479, 3478),
+            "203.0.113.7:3479".parse().unwrap()
+        );
+        let shifted: AnnouncedAddress = "203.0.113.7:30000".parse().unwrap();
+        assert_eq!(
+            shifted.socket_addr(3478, 3478),
+            "203.0.113.7:30000".parse().unwrap()
+        );
+        assert_eq!(
+            shifted.socket_addr(3481, 3478),
+            "203.0.113.7:30003".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_check_port_range() {
+        let same: AnnouncedAddress = "203.0.113.7".parse().unwrap();
+        assert!(same.check_port_range(3478, 65535).is_ok());
+        let shifted: AnnouncedAddress = "203.0.113.7:65534".parse().unwrap();
+        assert!(shifted.check_port_range(3478, 3479).is_ok());
+        assert!(shifted.check_port_range(3478, 3480).is_err());
+    }
+
+    #[test]
+    fn test_candidate_addrs_deduplicated() {
+        let announced: Vec<AnnouncedAddress> =
+            ["203.0.113.7", "203.0.113.7:3478", "[2001:db8::7]:40000"]
+                .iter()
+                .map(|s| s.parse().unwrap())
+                .collect();
+        assert_eq!(
+            candidate_addrs(&announced, 3479, 3478),
+            vec![
+                "203.0.113.7:3479".parse().unwrap(),
+                "[2001:db8::7]:40001".parse().unwrap(),
+            ]
+        );
+    }
+}
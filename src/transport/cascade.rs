@@ -0,0 +1,600 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use crossbeam_channel::{Sender, TrySendError};
+use ring::hmac;
+use sfu::{RelayMessage, WorkerPlacement};
+use tracing::{debug, info, warn};
+
+use super::{send_media, MediaInput};
+
+/// Links are framed by their length on 32 bits, relayed RTP packets being larger than RFC 4571
+/// frames once prefixed by their stream
+const FRAME_HEADER_LEN: usize = 4;
+const MAX_FRAME_LEN: usize = 1024 * 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const NONCE_LEN: usize = 16;
+/// Length of the HMAC-SHA256 ending every frame once the link is up
+const MAC_LEN: usize = 32;
+/// Messages waiting to be written to a link, after which the worker drops the next ones
+const OUTGOING_QUEUE_LEN: usize = 1024;
+/// Directions of the frames of a link, part of their MAC so that a frame can't be reflected
+const DIRECTION_FROM_OPENER: u8 = 0;
+const DIRECTION_FROM_ACCEPTOR: u8 = 1;
+
+/// First frame of a link, naming the session it relays, with a fresh nonce of the node opening
+/// it.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Hello {
+    session_id: u64,
+    nonce: String,
+}
+
+/// Answer of the accepting node, with a fresh nonce of its own and its proof of the secret
+/// shared by the nodes.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Challenge {
+    nonce: String,
+    signature: String,
+}
+
+/// Proof of the secret by the node opening the link, after which the accepting node acknowledges
+/// the link with an empty frame, or rejects it with the reason.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Response {
+    signature: String,
+}
+
+/// Nonces of both nodes, which the proofs of the secret and the key of the link are bound to, so
+/// that neither a handshake nor the frames of a link can be replayed.
+struct Nonces {
+    session_id: u64,
+    opener: [u8; NONCE_LEN],
+    acceptor: [u8; NONCE_LEN],
+}
+
+impl Nonces {
+    fn message(&self, label: &str) -> Vec<u8> {
+        [
+            label.as_bytes(),
+            &self.session_id.to_be_bytes(),
+            &self.opener,
+            &self.acceptor,
+        ]
+        .concat()
+    }
+
+    fn signature(&self, secret: &str, label: &str) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        hex::encode(hmac::sign(&key, &self.message(label)))
+    }
+
+    fn verify(&self, secret: &str, label: &str, signature: &str) -> std::io::Result<()> {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let signature = hex::decode(signature).unwrap_or_default();
+        hmac::verify(&key, &self.message(label), &signature)
+            .map_err(|_| Error::new(ErrorKind::PermissionDenied, "invalid signature"))
+    }
+
+    fn link_key(&self, secret: &str) -> hmac::Key {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let link_secret = hmac::sign(&key, &self.message("link"));
+        hmac::Key::new(hmac::HMAC_SHA256, link_secret.as_ref())
+    }
+}
+
+fn new_nonce() -> [u8; NONCE_LEN] {
+    rand::random()
+}
+
+fn decode_nonce(nonce: &str) -> std::io::Result<[u8; NONCE_LEN]> {
+    hex::decode(nonce)
+        .ok()
+        .and_then(|nonce| nonce.try_into().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid nonce"))
+}
+
+/// MAC of the frames a link carries in one direction. Each frame ends with the HMAC of its
+/// direction, its sequence number and its payload, so that a frame can't be forged, replayed
+/// nor reordered.
+struct FrameAuth {
+    key: hmac::Key,
+    direction: u8,
+    sequence_number: u64,
+}
+
+impl FrameAuth {
+    fn new(key: hmac::Key, direction: u8) -> Self {
+        FrameAuth {
+            key,
+            direction,
+            sequence_number: 0,
+        }
+    }
+
+    fn mac_input(&self, payload: &[u8]) -> Vec<u8> {
+        let mut input = Vec::with_capacity(9 + payload.len());
+        input.push(self.direction);
+        input.extend_from_slice(&self.sequence_number.to_be_bytes());
+        input.extend_from_slice(payload);
+        input
+    }
+
+    fn seal(&mut self, payload: &[u8]) -> Vec<u8> {
+        let tag = hmac::sign(&self.key, &self.mac_input(payload));
+        self.sequence_number += 1;
+        let mut frame = Vec::with_capacity(payload.len() + MAC_LEN);
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(tag.as_ref());
+        frame
+    }
+
+    fn open(&mut self, mut frame: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        let payload_len = frame
+            .len()
+            .checked_sub(MAC_LEN)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "frame shorter than its mac"))?;
+        let tag = frame.split_off(payload_len);
+        hmac::verify(&self.key, &self.mac_input(&frame), &tag)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid frame mac"))?;
+        self.sequence_number += 1;
+        Ok(frame)
+    }
+}
+
+/// Workers reachable from the links to other nodes, keyed by their media port.
+///
+/// As for [`super::rtmp::RtmpWorkers`], `worker_placement` must be the placement given to the
+/// web server: a link always lands on the home worker of its session, which relays its tracks
+/// to the other workers hosting the session.
+#[derive(Clone)]
+pub struct CascadeNodes {
+    pub secret: String,
+    pub media_txs: HashMap<u16, Sender<MediaInput>>,
+    pub worker_placement: WorkerPlacement,
+    pub next_link_id: Arc<AtomicU64>,
+}
+
+impl CascadeNodes {
+    pub fn new(
+        secret: String,
+        media_txs: HashMap<u16, Sender<MediaInput>>,
+        worker_placement: WorkerPlacement,
+    ) -> Self {
+        CascadeNodes {
+            secret,
+            media_txs,
+            worker_placement,
+            next_link_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    fn media_tx(&self, session_id: u64) -> Option<&Sender<MediaInput>> {
+        self.worker_placement
+            .session_port(session_id)
+            .and_then(|port| self.media_txs.get(&port))
+    }
+}
+
+/// Accept the links other nodes open to relay the tracks of a session they host too.
+pub fn spawn_listener(listener: TcpListener, nodes: CascadeNodes) -> std::io::Result<()> {
+    info!("cascade listening {}...", listener.local_addr()?);
+    let nodes = Arc::new(nodes);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("cascade accept failed: {:?}", e);
+                    continue;
+                }
+            };
+            let nodes = Arc::clone(&nodes);
+            std::thread::spawn(move || {
+                let peer_addr = match stream.peer_addr() {
+                    Ok(peer_addr) => peer_addr,
+                    Err(_) => return,
+                };
+                if let Err(e) = accept(stream, peer_addr, &nodes) {
+                    warn!("cascade link from {} rejected: {}", peer_addr, e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn accept(
+    mut stream: TcpStream,
+    peer_addr: SocketAddr,
+    nodes: &CascadeNodes,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let hello: Hello = serde_json::from_slice(&read_frame(&mut stream)?)?;
+    let nonces = Nonces {
+        session_id: hello.session_id,
+        opener: decode_nonce(&hello.nonce)?,
+        acceptor: new_nonce(),
+    };
+    let challenge = Challenge {
+        nonce: hex::encode(nonces.acceptor),
+        signature: nonces.signature(&nodes.secret, "acceptor"),
+    };
+    write_frame(&mut stream, &serde_json::to_vec(&challenge)?)?;
+    let response: Response = serde_json::from_slice(&read_frame(&mut stream)?)?;
+    if let Err(e) = nonces.verify(&nodes.secret, "opener", &response.signature) {
+        write_frame(&mut stream, e.to_string().as_bytes())?;
+        return Err(e);
+    }
+    let Some(media_tx) = nodes.media_tx(hello.session_id) else {
+        write_frame(&mut stream, b"no media worker available")?;
+        return Err(Error::other("no media worker available"));
+    };
+    // an empty acknowledgement accepts the link
+    write_frame(&mut stream, &[])?;
+
+    let link_id = nodes.next_link_id.fetch_add(1, Ordering::Relaxed);
+    info!(
+        "cascade link {} from {} relays session {}",
+        link_id, peer_addr, hello.session_id
+    );
+    let key = nonces.link_key(&nodes.secret);
+    start_link(
+        stream,
+        hello.session_id,
+        link_id,
+        media_tx.clone(),
+        FrameAuth::new(key.clone(), DIRECTION_FROM_ACCEPTOR),
+        FrameAuth::new(key, DIRECTION_FROM_OPENER),
+    )
+}
+
+/// Open a link to the node listening on `address`, relaying the tracks of `session_id` between
+/// both nodes. Returns the id of the link.
+pub fn connect(nodes: &CascadeNodes, address: &str, session_id: u64) -> std::io::Result<u64> {
+    let media_tx = nodes
+        .media_tx(session_id)
+        .ok_or_else(|| Error::other("no media worker available"))?;
+    let addr = address.to_socket_addrs()?.next().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("cannot resolve {}", address),
+        )
+    })?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let opener_nonce = new_nonce();
+    let hello = Hello {
+        session_id,
+        nonce: hex::encode(opener_nonce),
+    };
+    write_frame(&mut stream, &serde_json::to_vec(&hello)?)?;
+    let challenge: Challenge = serde_json::from_slice(&read_frame(&mut stream)?)?;
+    let nonces = Nonces {
+        session_id,
+        opener: opener_nonce,
+        acceptor: decode_nonce(&challenge.nonce)?,
+    };
+    // the other node proves it knows the secret before any track is relayed to it
+    nonces.verify(&nodes.secret, "acceptor", &challenge.signature)?;
+    let response = Response {
+        signature: nonces.signature(&nodes.secret, "opener"),
+    };
+    write_frame(&mut stream, &serde_json::to_vec(&response)?)?;
+    let ack = read_frame(&mut stream)?;
+    if !ack.is_empty() {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            String::from_utf8_lossy(&ack).to_string(),
+        ));
+    }
+
+    let link_id = nodes.next_link_id.fetch_add(1, Ordering::Relaxed);
+    info!(
+        "cascade link {} to {} relays session {}",
+        link_id, addr, session_id
+    );
+    let key = nonces.link_key(&nodes.secret);
+    start_link(
+        stream,
+        session_id,
+        link_id,
+        media_tx.clone(),
+        FrameAuth::new(key.clone(), DIRECTION_FROM_OPENER),
+        FrameAuth::new(key, DIRECTION_FROM_ACCEPTOR),
+    )?;
+    Ok(link_id)
+}
+
+/// Send a message to the link to another node, which is dropped when the link can't keep up.
+/// Returns false once the link is gone.
+pub fn send_message(tx: &Sender<Bytes>, message: Bytes) -> bool {
+    match tx.try_send(message) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            debug!("cascade link is congested, dropping a message");
+            true
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    }
+}
+
+/// Hand the link over to the worker, before reading its messages so that the worker knows the
+/// link when they arrive, then read and write it on threads of its own.
+fn start_link(
+    stream: TcpStream,
+    session_id: u64,
+    link_id: u64,
+    media_tx: Sender<MediaInput>,
+    mut sealing: FrameAuth,
+    mut opening: FrameAuth,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(None)?;
+    stream.set_nodelay(true)?;
+    let mut reader = stream.try_clone()?;
+    let mut writer = stream;
+
+    let (tx, rx) = crossbeam_channel::bounded::<Bytes>(OUTGOING_QUEUE_LEN);
+    media_tx
+        .send(MediaInput::NodeLinked {
+            session_id,
+            link_id,
+            tx,
+        })
+        .map_err(|_| Error::other("media worker is stopped"))?;
+
+    std::thread::spawn(move || {
+        for frame in rx.iter() {
+            if let Err(e) = write_frame(&mut writer, &sealing.seal(&frame)) {
+                debug!("cascade link {} write failed: {}", link_id, e);
+                break;
+            }
+        }
+        // the worker left the link, or the other node closed it
+        let _ = writer.shutdown(Shutdown::Both);
+    });
+
+    std::thread::spawn(move || {
+        loop {
+            let frame = match read_frame(&mut reader).and_then(|frame| opening.open(frame)) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    debug!("cascade link {} closed: {}", link_id, e);
+                    break;
+                }
+            };
+            match RelayMessage::unmarshal(link_id, Bytes::from(frame)) {
+                Ok(message) => {
+                    if !send_media(&media_tx, MediaInput::Relay(message)) {
+                        break;
+                    }
+                }
+                Err(err) => warn!("cascade link {} sent an invalid message: {}", link_id, err),
+            }
+        }
+        let _ = reader.shutdown(Shutdown::Both);
+        let _ = media_tx.send(MediaInput::NodeUnlinked { link_id });
+    });
+
+    Ok(())
+}
+
+fn read_frame(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    stream.read_exact(&mut header)?;
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("frame of {} bytes is too large", len),
+        ));
+    }
+    let mut frame = vec![0u8; len];
+    stream.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+fn write_frame(stream: &mut TcpStream, frame: &[u8]) -> std::io::Result<()> {
+    let mut buf = Vec::with_capacity(FRAME_HEADER_LEN + frame.len());
+    buf.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+    buf.extend_from_slice(frame);
+    stream.write_all(&buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::marshal::Marshal;
+
+    use super::*;
+    use crate::transport::MEDIA_INPUT_QUEUE_LEN;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Node whose single worker, on `port`, hosts every session
+    fn node(secret: &str, port: u16) -> (CascadeNodes, crossbeam_channel::Receiver<MediaInput>) {
+        let (media_tx, media_rx) = crossbeam_channel::bounded(MEDIA_INPUT_QUEUE_LEN);
+        let nodes = CascadeNodes::new(
+            secret.to_string(),
+            HashMap::from([(port, media_tx)]),
+            WorkerPlacement::new(vec![port], 1),
+        );
+        (nodes, media_rx)
+    }
+
+    fn listen(nodes: CascadeNodes) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn_listener(listener, nodes).unwrap();
+        addr
+    }
+
+    fn linked(media_rx: &crossbeam_channel::Receiver<MediaInput>) -> (u64, Sender<Bytes>) {
+        match media_rx.recv_timeout(TIMEOUT).unwrap() {
+            MediaInput::NodeLinked {
+                session_id: 1,
+                link_id,
+                tx,
+            } => (link_id, tx),
+            _ => panic!("expected the link of session 1"),
+        }
+    }
+
+    /// Relayed RTP packet of the audio track of endpoint 2 in session 1
+    fn relayed_rtp(sequence_number: u16) -> Bytes {
+        let packet = rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: 111,
+                sequence_number,
+                timestamp: 960,
+                ssrc: 1234,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(&[0xfc, 0xff, 0xfe]),
+        };
+        let mut message = vec![4];
+        message.extend_from_slice(&1u64.to_be_bytes());
+        message.extend_from_slice(&2u64.to_be_bytes());
+        // mid, no rid, not a repair stream, no audio level
+        message.extend_from_slice(&[1, b'0', 0, 0, 0xff]);
+        message.extend_from_slice(&packet.marshal().unwrap());
+        Bytes::from(message)
+    }
+
+    #[test]
+    fn test_frame_auth() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"link key");
+        let mut sealing = FrameAuth::new(key.clone(), DIRECTION_FROM_OPENER);
+        let mut opening = FrameAuth::new(key.clone(), DIRECTION_FROM_OPENER);
+
+        let first = sealing.seal(b"first");
+        assert_eq!(first.len(), 5 + MAC_LEN);
+        let second = sealing.seal(b"second");
+        assert_eq!(opening.open(first.clone()).unwrap(), b"first");
+        // a frame replayed, or received out of order, doesn't match its sequence number
+        assert!(opening.open(first.clone()).is_err());
+        assert_eq!(opening.open(second).unwrap(), b"second");
+
+        let mut tampered = sealing.seal(b"third");
+        tampered[0] ^= 1;
+        assert!(opening.open(tampered).is_err());
+        assert!(opening.open(vec![0; MAC_LEN - 1]).is_err());
+
+        // a frame reflected to the node which sealed it isn't accepted either
+        let mut reflected = FrameAuth::new(key, DIRECTION_FROM_ACCEPTOR);
+        assert!(reflected.open(first).is_err());
+    }
+
+    #[test]
+    fn test_relay_rtp_between_nodes() {
+        let (opener, opener_rx) = node("s3cret", 5000);
+        let (acceptor, acceptor_rx) = node("s3cret", 6000);
+        let addr = listen(acceptor);
+
+        let link_id = connect(&opener, &addr.to_string(), 1).unwrap();
+        let (opener_link_id, opener_tx) = linked(&opener_rx);
+        assert_eq!(opener_link_id, link_id);
+        let (acceptor_link_id, acceptor_tx) = linked(&acceptor_rx);
+
+        for (tx, rx, link_id) in [
+            (&opener_tx, &acceptor_rx, acceptor_link_id),
+            (&acceptor_tx, &opener_rx, opener_link_id),
+        ] {
+            for sequence_number in [7, 8] {
+                assert!(send_message(tx, relayed_rtp(sequence_number)));
+                match rx.recv_timeout(TIMEOUT).unwrap() {
+                    MediaInput::Relay(message) => {
+                        assert_eq!(message.marshal().unwrap(), relayed_rtp(sequence_number));
+                        assert!(!message.is_leave());
+                    }
+                    _ => panic!("expected the relayed packet on link {}", link_id),
+                }
+            }
+        }
+
+        // the worker leaving the link closes it on both nodes
+        drop(opener_tx);
+        for rx in [&acceptor_rx, &opener_rx] {
+            assert!(matches!(
+                rx.recv_timeout(TIMEOUT).unwrap(),
+                MediaInput::NodeUnlinked { .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_reject_wrong_secret() {
+        let (opener, opener_rx) = node("wrong", 5000);
+        let (acceptor, acceptor_rx) = node("s3cret", 6000);
+        let addr = listen(acceptor);
+
+        let err = connect(&opener, &addr.to_string(), 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(opener_rx.try_recv().is_err());
+        assert!(acceptor_rx
+            .recv_timeout(Duration::from_millis(200))
+            .is_err());
+    }
+
+    #[test]
+    fn test_reject_forged_frame() {
+        let (acceptor, acceptor_rx) = node("s3cret", 6000);
+        let addr = listen(acceptor);
+
+        // handshake as a node knowing the secret would, then write a frame without its mac
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let opener_nonce = new_nonce();
+        let hello = Hello {
+            session_id: 1,
+            nonce: hex::encode(opener_nonce),
+        };
+        write_frame(&mut stream, &serde_json::to_vec(&hello).unwrap()).unwrap();
+        let challenge: Challenge =
+            serde_json::from_slice(&read_frame(&mut stream).unwrap()).unwrap();
+        let nonces = Nonces {
+            session_id: 1,
+            opener: opener_nonce,
+            acceptor: decode_nonce(&challenge.nonce).unwrap(),
+        };
+        nonces
+            .verify("s3cret", "acceptor", &challenge.signature)
+            .unwrap();
+        let response = Response {
+            signature: nonces.signature("s3cret", "opener"),
+        };
+        write_frame(&mut stream, &serde_json::to_vec(&response).unwrap()).unwrap();
+        assert!(read_frame(&mut stream).unwrap().is_empty());
+        let (link_id, _tx) = linked(&acceptor_rx);
+
+        write_frame(&mut stream, &relayed_rtp(7)).unwrap();
+        match acceptor_rx.recv_timeout(TIMEOUT).unwrap() {
+            MediaInput::NodeUnlinked {
+                link_id: unlinked_id,
+            } => assert_eq!(unlinked_id, link_id),
+            _ => panic!("expected the link to be closed"),
+        }
+    }
+
+    #[test]
+    fn test_send_message_drops_when_congested() {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        assert!(send_message(&tx, Bytes::from_static(b"a")));
+        assert!(send_message(&tx, Bytes::from_static(b"b")));
+        assert_eq!(rx.try_recv().unwrap().as_ref(), b"a");
+        assert!(rx.try_recv().is_err());
+        drop(rx);
+        assert!(!send_message(&tx, Bytes::from_static(b"c")));
+    }
+}
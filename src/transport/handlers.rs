@@ -1,12 +1,10 @@
-use std::{
-    cell::RefCell,
-    io::{Error, ErrorKind},
-    rc::Rc,
-    sync::mpsc::Sender,
-};
+use std::{cell::RefCell, io::Error, rc::Rc, sync::mpsc::Sender};
 
 use bytes::Bytes;
-use sfu::{RTCSessionDescription, ServerStates};
+use sfu::{
+    CaptureRequest, CodecPolicy, EgressRequest, HlsRequest, InjectRequest, LayerRequest,
+    PinRequest, PlainIngestRequest, RTCSessionDescription, ServerStates, SubscriptionRequest,
+};
 use tracing::info;
 
 pub enum SignalingProtocolMessage {
@@ -23,6 +21,7 @@ pub enum SignalingProtocolMessage {
         session_id: u64,
         endpoint_id: u64,
         offer_sdp: Bytes,
+        auto_subscribe: bool,
     },
     Answer {
         session_id: u64,
@@ -33,6 +32,131 @@ pub enum SignalingProtocolMessage {
         session_id: u64,
         endpoint_id: u64,
     },
+    Layer {
+        session_id: u64,
+        endpoint_id: u64,
+        layer_request: Bytes,
+    },
+    Pin {
+        session_id: u64,
+        endpoint_id: u64,
+        pin_request: Bytes,
+    },
+    Subscribe {
+        session_id: u64,
+        endpoint_id: u64,
+        subscription_request: Bytes,
+    },
+    Unsubscribe {
+        session_id: u64,
+        endpoint_id: u64,
+        subscription_request: Bytes,
+    },
+    CodecPolicy {
+        session_id: u64,
+        codec_policy: Bytes,
+    },
+    StartRecording {
+        session_id: u64,
+    },
+    StopRecording {
+        session_id: u64,
+    },
+    Recording {
+        session_id: u64,
+        manifest: Bytes,
+    },
+    StartCapture {
+        session_id: u64,
+        capture_request: Bytes,
+    },
+    StopCapture {
+        session_id: u64,
+    },
+    Capture {
+        session_id: u64,
+        summary: Bytes,
+    },
+    StartInjection {
+        session_id: u64,
+        inject_request: Bytes,
+    },
+    StopInjection {
+        session_id: u64,
+        endpoint_id: u64,
+    },
+    StartEgress {
+        session_id: u64,
+        egress_request: Bytes,
+    },
+    StopEgress {
+        session_id: u64,
+    },
+    Egress {
+        session_id: u64,
+        summary: Bytes,
+    },
+    StartHls {
+        session_id: u64,
+        hls_request: Bytes,
+    },
+    StopHls {
+        session_id: u64,
+    },
+    Hls {
+        session_id: u64,
+        summary: Bytes,
+    },
+    StopCascade {
+        session_id: u64,
+    },
+    Cascade {
+        session_id: u64,
+        summary: Bytes,
+    },
+    StartPlainIngest {
+        session_id: u64,
+        ingest_request: Bytes,
+    },
+    StopPlainIngest {
+        session_id: u64,
+        endpoint_id: u64,
+    },
+    PlainIngest {
+        session_id: u64,
+        endpoint_id: u64,
+        description: Bytes,
+    },
+    StartRtmpIngest {
+        session_id: u64,
+        endpoint_id: u64,
+    },
+    StopRtmpIngest {
+        session_id: u64,
+        endpoint_id: u64,
+    },
+    GetStats {
+        session_id: u64,
+        endpoint_id: u64,
+    },
+    Stats {
+        session_id: u64,
+        endpoint_id: u64,
+        stats: Bytes,
+    },
+    GetActiveSpeaker {
+        session_id: u64,
+        endpoint_id: u64,
+    },
+    ActiveSpeaker {
+        session_id: u64,
+        endpoint_id: u64,
+        event: Option<Bytes>,
+    },
+    GetSessions,
+    Sessions {
+        session_ids: Vec<u64>,
+    },
 }
 
 pub struct SignalingMessage {
@@ -49,11 +173,13 @@ pub fn handle_signaling_message(
             session_id,
             endpoint_id,
             offer_sdp,
+            auto_subscribe,
         } => handle_offer_message(
             server_states,
             session_id,
             endpoint_id,
             offer_sdp,
+            auto_subscribe,
             signaling_msg.response_tx,
         ),
         SignalingProtocolMessage::Leave {
@@ -65,6 +191,191 @@ pub fn handle_signaling_message(
             endpoint_id,
             signaling_msg.response_tx,
         ),
+        SignalingProtocolMessage::Layer {
+            session_id,
+            endpoint_id,
+            layer_request,
+        } => handle_layer_message(
+            server_states,
+            session_id,
+            endpoint_id,
+            layer_request,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::Pin {
+            session_id,
+            endpoint_id,
+            pin_request,
+        } => handle_pin_message(
+            server_states,
+            session_id,
+            endpoint_id,
+            pin_request,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::Subscribe {
+            session_id,
+            endpoint_id,
+            subscription_request,
+        } => handle_subscription_message(
+            server_states,
+            session_id,
+            endpoint_id,
+            subscription_request,
+            true,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::Unsubscribe {
+            session_id,
+            endpoint_id,
+            subscription_request,
+        } => handle_subscription_message(
+            server_states,
+            session_id,
+            endpoint_id,
+            subscription_request,
+            false,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::CodecPolicy {
+            session_id,
+            codec_policy,
+        } => handle_codec_policy_message(
+            server_states,
+            session_id,
+            codec_policy,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::StartRecording { session_id } => {
+            handle_start_recording_message(server_states, session_id, signaling_msg.response_tx)
+        }
+        SignalingProtocolMessage::StopRecording { session_id } => {
+            handle_stop_recording_message(server_states, session_id, signaling_msg.response_tx)
+        }
+        SignalingProtocolMessage::StartCapture {
+            session_id,
+            capture_request,
+        } => handle_start_capture_message(
+            server_states,
+            session_id,
+            capture_request,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::StopCapture { session_id } => {
+            handle_stop_capture_message(server_states, session_id, signaling_msg.response_tx)
+        }
+        SignalingProtocolMessage::StartInjection {
+            session_id,
+            inject_request,
+        } => handle_start_injection_message(
+            server_states,
+            session_id,
+            inject_request,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::StopInjection {
+            session_id,
+            endpoint_id,
+        } => handle_stop_injection_message(
+            server_states,
+            session_id,
+            endpoint_id,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::StartEgress {
+            session_id,
+            egress_request,
+        } => handle_start_egress_message(
+            server_states,
+            session_id,
+            egress_request,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::StopEgress { session_id } => {
+            handle_stop_egress_message(server_states, session_id, signaling_msg.response_tx)
+        }
+        SignalingProtocolMessage::StartHls {
+            session_id,
+            hls_request,
+        } => handle_start_hls_message(
+            server_states,
+            session_id,
+            hls_request,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::StopHls { session_id } => {
+            handle_stop_hls_message(server_states, session_id, signaling_msg.response_tx)
+        }
+        SignalingProtocolMessage::StopCascade { session_id } => {
+            handle_stop_cascade_message(server_states, session_id, signaling_msg.response_tx)
+        }
+        SignalingProtocolMessage::StartPlainIngest {
+            session_id,
+            ingest_request,
+        } => handle_start_plain_ingest_message(
+            server_states,
+            session_id,
+            ingest_request,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::StopPlainIngest {
+            session_id,
+            endpoint_id,
+        } => handle_stop_plain_ingest_message(
+            server_states,
+            session_id,
+            endpoint_id,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::StartRtmpIngest {
+            session_id,
+            endpoint_id,
+        } => handle_start_rtmp_ingest_message(
+            server_states,
+            session_id,
+            endpoint_id,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::StopRtmpIngest {
+            session_id,
+            endpoint_id,
+        } => handle_stop_rtmp_ingest_message(
+            server_states,
+            session_id,
+            endpoint_id,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::GetStats {
+            session_id,
+            endpoint_id,
+        } => handle_get_stats_message(
+            server_states,
+            session_id,
+            endpoint_id,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::GetActiveSpeaker {
+            session_id,
+            endpoint_id,
+        } => handle_get_active_speaker_message(
+            server_states,
+            session_id,
+            endpoint_id,
+            signaling_msg.response_tx,
+        ),
+        SignalingProtocolMessage::GetSessions => {
+            handle_get_sessions_message(server_states, signaling_msg.response_tx)
+        }
+        SignalingProtocolMessage::Sessions { session_ids: _ } => Ok(signaling_msg
+            .response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id: 0,
+                endpoint_id: 0,
+                reason: Bytes::from("Invalid Request"),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
         SignalingProtocolMessage::Ok {
             session_id,
             endpoint_id,
@@ -78,6 +389,21 @@ pub fn handle_signaling_message(
             session_id,
             endpoint_id,
             answer_sdp: _,
+        }
+        | SignalingProtocolMessage::PlainIngest {
+            session_id,
+            endpoint_id,
+            description: _,
+        }
+        | SignalingProtocolMessage::Stats {
+            session_id,
+            endpoint_id,
+            stats: _,
+        }
+        | SignalingProtocolMessage::ActiveSpeaker {
+            session_id,
+            endpoint_id,
+            event: _,
         } => Ok(signaling_msg
             .response_tx
             .send(SignalingProtocolMessage::Err {
@@ -86,10 +412,36 @@ pub fn handle_signaling_message(
                 reason: Bytes::from("Invalid Request"),
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        SignalingProtocolMessage::Recording {
+            session_id,
+            manifest: _,
+        }
+        | SignalingProtocolMessage::Capture {
+            session_id,
+            summary: _,
+        }
+        | SignalingProtocolMessage::Egress {
+            session_id,
+            summary: _,
+        }
+        | SignalingProtocolMessage::Hls {
+            session_id,
+            summary: _,
+        }
+        | SignalingProtocolMessage::Cascade {
+            session_id,
+            summary: _,
+        } => Ok(signaling_msg
+            .response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id: 0,
+                reason: Bytes::from("Invalid Request"),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
             })?),
     }
 }
@@ -99,17 +451,13 @@ fn handle_offer_message(
     session_id: u64,
     endpoint_id: u64,
     offer: Bytes,
+    auto_subscribe: bool,
     response_tx: Sender<SignalingProtocolMessage>,
 ) -> std::io::Result<()> {
     let try_handle = || -> std::io::Result<Bytes> {
         let offer_str = match String::from_utf8(offer.to_vec()) {
             Ok(offer_str) => offer_str,
-            Err(err) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("failed to parse offer: {}", err),
-                ))
-            }
+            Err(err) => return Err(Error::other(format!("failed to parse offer: {}", err))),
         };
         info!(
             "handle_offer_message: {}/{}/{}",
@@ -118,14 +466,15 @@ fn handle_offer_message(
         let mut server_states = server_states.borrow_mut();
 
         let offer_sdp = serde_json::from_str::<RTCSessionDescription>(&offer_str)?;
-        let answer = match server_states.accept_offer(session_id, endpoint_id, None, offer_sdp) {
+        let answer = match server_states.accept_offer(
+            session_id,
+            endpoint_id,
+            None,
+            offer_sdp,
+            auto_subscribe,
+        ) {
             Ok(answer) => answer,
-            Err(err) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("failed to accept offer: {}", err),
-                ))
-            }
+            Err(err) => return Err(Error::other(format!("failed to accept offer: {}", err))),
         };
         let answer_str = serde_json::to_string(&answer)?;
         info!("generate answer sdp: {}", answer_str);
@@ -140,10 +489,47 @@ fn handle_offer_message(
                 answer_sdp,
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_layer_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    endpoint_id: u64,
+    layer_request: Bytes,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<()> {
+        let layer_request = serde_json::from_slice::<LayerRequest>(&layer_request)?;
+        info!(
+            "handle_layer_message: {}/{}/{:?}",
+            session_id, endpoint_id, layer_request,
+        );
+        let mut server_states = server_states.borrow_mut();
+        server_states
+            .request_layer(session_id, endpoint_id, layer_request)
+            .map_err(|err| Error::other(format!("failed to request layer: {}", err)))
+    };
+
+    match try_handle() {
+        Ok(_) => Ok(response_tx
+            .send(SignalingProtocolMessage::Ok {
+                session_id,
+                endpoint_id,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
             })?),
         Err(err) => Ok(response_tx
             .send(SignalingProtocolMessage::Err {
@@ -152,14 +538,795 @@ fn handle_offer_message(
                 reason: Bytes::from(err.to_string()),
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
     }
 }
 
+fn handle_pin_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    endpoint_id: u64,
+    pin_request: Bytes,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<()> {
+        let pin_request = serde_json::from_slice::<PinRequest>(&pin_request)?;
+        info!(
+            "handle_pin_message: {}/{}/{:?}",
+            session_id, endpoint_id, pin_request,
+        );
+        let mut server_states = server_states.borrow_mut();
+        server_states
+            .pin_endpoints(session_id, endpoint_id, pin_request)
+            .map_err(|err| Error::other(format!("failed to pin endpoints: {}", err)))
+    };
+
+    match try_handle() {
+        Ok(_) => Ok(response_tx
+            .send(SignalingProtocolMessage::Ok {
+                session_id,
+                endpoint_id,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_subscription_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    endpoint_id: u64,
+    subscription_request: Bytes,
+    is_subscribe: bool,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<()> {
+        let subscription_request =
+            serde_json::from_slice::<SubscriptionRequest>(&subscription_request)?;
+        info!(
+            "handle_subscription_message: {}/{}/{:?}/{}",
+            session_id, endpoint_id, subscription_request, is_subscribe,
+        );
+        let mut server_states = server_states.borrow_mut();
+        let result = if is_subscribe {
+            server_states.subscribe(session_id, endpoint_id, subscription_request)
+        } else {
+            server_states.unsubscribe(session_id, endpoint_id, subscription_request)
+        };
+        result.map_err(|err| Error::other(format!("failed to change subscription: {}", err)))
+    };
+
+    match try_handle() {
+        Ok(_) => Ok(response_tx
+            .send(SignalingProtocolMessage::Ok {
+                session_id,
+                endpoint_id,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_codec_policy_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    codec_policy: Bytes,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    // the codec policy is set for the whole session, not by one of its endpoints
+    let endpoint_id = 0;
+    let try_handle = || -> std::io::Result<()> {
+        let codec_policy = serde_json::from_slice::<CodecPolicy>(&codec_policy)?;
+        info!(
+            "handle_codec_policy_message: {}/{:?}",
+            session_id, codec_policy,
+        );
+        let mut server_states = server_states.borrow_mut();
+        server_states
+            .set_codec_policy(session_id, codec_policy)
+            .map_err(|err| Error::other(format!("failed to set codec policy: {}", err)))
+    };
+
+    match try_handle() {
+        Ok(_) => Ok(response_tx
+            .send(SignalingProtocolMessage::Ok {
+                session_id,
+                endpoint_id,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_start_recording_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    // the whole session is recorded, not one of its endpoints
+    let endpoint_id = 0;
+    let try_handle = || -> std::io::Result<()> {
+        info!("handle_start_recording_message: {}", session_id);
+        let mut server_states = server_states.borrow_mut();
+        server_states
+            .start_recording(session_id)
+            .map_err(|err| Error::other(format!("failed to start recording: {}", err)))
+    };
+
+    match try_handle() {
+        Ok(_) => Ok(response_tx
+            .send(SignalingProtocolMessage::Ok {
+                session_id,
+                endpoint_id,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_stop_recording_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<Bytes> {
+        info!("handle_stop_recording_message: {}", session_id);
+        let mut server_states = server_states.borrow_mut();
+        let manifest = server_states
+            .stop_recording(session_id)
+            .map_err(|err| Error::other(format!("failed to stop recording: {}", err)))?;
+        Ok(Bytes::from(serde_json::to_vec(&manifest)?))
+    };
+
+    match try_handle() {
+        Ok(manifest) => Ok(response_tx
+            .send(SignalingProtocolMessage::Recording {
+                session_id,
+                manifest,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id: 0,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_start_capture_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    capture_request: Bytes,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    // the endpoint to capture, if any, is part of the request
+    let endpoint_id = 0;
+    let try_handle = || -> std::io::Result<()> {
+        // an empty request captures the whole session within the server limits
+        let capture_request = if capture_request.is_empty() {
+            CaptureRequest::default()
+        } else {
+            serde_json::from_slice::<CaptureRequest>(&capture_request)?
+        };
+        info!(
+            "handle_start_capture_message: {}/{:?}",
+            session_id, capture_request,
+        );
+        let mut server_states = server_states.borrow_mut();
+        server_states
+            .start_capture(session_id, capture_request)
+            .map_err(|err| Error::other(format!("failed to start capture: {}", err)))
+    };
+
+    match try_handle() {
+        Ok(_) => Ok(response_tx
+            .send(SignalingProtocolMessage::Ok {
+                session_id,
+                endpoint_id,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_stop_capture_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<Bytes> {
+        info!("handle_stop_capture_message: {}", session_id);
+        let mut server_states = server_states.borrow_mut();
+        let summary = server_states
+            .stop_capture(session_id)
+            .map_err(|err| Error::other(format!("failed to stop capture: {}", err)))?;
+        Ok(Bytes::from(serde_json::to_vec(&summary)?))
+    };
+
+    match try_handle() {
+        Ok(summary) => Ok(response_tx
+            .send(SignalingProtocolMessage::Capture {
+                session_id,
+                summary,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id: 0,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_start_injection_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    inject_request: Bytes,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let mut endpoint_id = 0;
+    let mut try_handle = || -> std::io::Result<()> {
+        let inject_request = serde_json::from_slice::<InjectRequest>(&inject_request)?;
+        info!(
+            "handle_start_injection_message: {}/{:?}",
+            session_id, inject_request,
+        );
+        endpoint_id = inject_request.endpoint_id;
+        let mut server_states = server_states.borrow_mut();
+        server_states
+            .start_injection(session_id, inject_request)
+            .map_err(|err| Error::other(format!("failed to start injection: {}", err)))
+    };
+
+    match try_handle() {
+        Ok(_) => Ok(response_tx
+            .send(SignalingProtocolMessage::Ok {
+                session_id,
+                endpoint_id,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_stop_injection_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    endpoint_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<()> {
+        info!(
+            "handle_stop_injection_message: {}/{}",
+            session_id, endpoint_id
+        );
+        let mut server_states = server_states.borrow_mut();
+        server_states
+            .stop_injection(session_id, endpoint_id)
+            .map_err(|err| Error::other(format!("failed to stop injection: {}", err)))
+    };
+
+    match try_handle() {
+        Ok(_) => Ok(response_tx
+            .send(SignalingProtocolMessage::Ok {
+                session_id,
+                endpoint_id,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_start_egress_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    egress_request: Bytes,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<Bytes> {
+        let egress_request = serde_json::from_slice::<EgressRequest>(&egress_request)?;
+        info!(
+            "handle_start_egress_message: {}/{:?}",
+            session_id, egress_request,
+        );
+        let mut server_states = server_states.borrow_mut();
+        let summary = server_states
+            .start_egress(session_id, egress_request)
+            .map_err(|err| Error::other(format!("failed to start egress: {}", err)))?;
+        Ok(Bytes::from(serde_json::to_vec(&summary)?))
+    };
+
+    match try_handle() {
+        Ok(summary) => Ok(response_tx
+            .send(SignalingProtocolMessage::Egress {
+                session_id,
+                summary,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id: 0,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_stop_egress_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<Bytes> {
+        info!("handle_stop_egress_message: {}", session_id);
+        let mut server_states = server_states.borrow_mut();
+        let summary = server_states
+            .stop_egress(session_id)
+            .map_err(|err| Error::other(format!("failed to stop egress: {}", err)))?;
+        Ok(Bytes::from(serde_json::to_vec(&summary)?))
+    };
+
+    match try_handle() {
+        Ok(summary) => Ok(response_tx
+            .send(SignalingProtocolMessage::Egress {
+                session_id,
+                summary,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id: 0,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_start_hls_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    hls_request: Bytes,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<Bytes> {
+        let hls_request = serde_json::from_slice::<HlsRequest>(&hls_request)?;
+        info!("handle_start_hls_message: {}/{:?}", session_id, hls_request);
+        let mut server_states = server_states.borrow_mut();
+        let summary = server_states
+            .start_hls(session_id, hls_request)
+            .map_err(|err| Error::other(format!("failed to start hls: {}", err)))?;
+        Ok(Bytes::from(serde_json::to_vec(&summary)?))
+    };
+
+    match try_handle() {
+        Ok(summary) => Ok(response_tx
+            .send(SignalingProtocolMessage::Hls {
+                session_id,
+                summary,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id: 0,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_stop_hls_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<Bytes> {
+        info!("handle_stop_hls_message: {}", session_id);
+        let mut server_states = server_states.borrow_mut();
+        let summary = server_states
+            .stop_hls(session_id)
+            .map_err(|err| Error::other(format!("failed to stop hls: {}", err)))?;
+        Ok(Bytes::from(serde_json::to_vec(&summary)?))
+    };
+
+    match try_handle() {
+        Ok(summary) => Ok(response_tx
+            .send(SignalingProtocolMessage::Hls {
+                session_id,
+                summary,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id: 0,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_stop_cascade_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<Bytes> {
+        info!("handle_stop_cascade_message: {}", session_id);
+        let mut server_states = server_states.borrow_mut();
+        let links = server_states
+            .stop_node_links(session_id)
+            .map_err(|err| Error::other(format!("failed to stop cascading: {}", err)))?;
+        Ok(Bytes::from(serde_json::to_vec(&serde_json::json!({
+            "session_id": session_id,
+            "links": links,
+        }))?))
+    };
+
+    match try_handle() {
+        Ok(summary) => Ok(response_tx
+            .send(SignalingProtocolMessage::Cascade {
+                session_id,
+                summary,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id: 0,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_start_plain_ingest_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    ingest_request: Bytes,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let mut endpoint_id = 0;
+    let mut try_handle = || -> std::io::Result<Bytes> {
+        let ingest_request = serde_json::from_slice::<PlainIngestRequest>(&ingest_request)?;
+        info!(
+            "handle_start_plain_ingest_message: {}/{:?}",
+            session_id, ingest_request,
+        );
+        endpoint_id = ingest_request.endpoint_id;
+        let mut server_states = server_states.borrow_mut();
+        let description = server_states
+            .start_plain_ingest(session_id, ingest_request)
+            .map_err(|err| Error::other(format!("failed to start plain ingest: {}", err)))?;
+        Ok(Bytes::from(serde_json::to_vec(&description)?))
+    };
+
+    match try_handle() {
+        Ok(description) => Ok(response_tx
+            .send(SignalingProtocolMessage::PlainIngest {
+                session_id,
+                endpoint_id,
+                description,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_stop_plain_ingest_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    endpoint_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<()> {
+        info!(
+            "handle_stop_plain_ingest_message: {}/{}",
+            session_id, endpoint_id
+        );
+        let mut server_states = server_states.borrow_mut();
+        server_states
+            .stop_plain_ingest(session_id, endpoint_id)
+            .map_err(|err| Error::other(format!("failed to stop plain ingest: {}", err)))
+    };
+
+    match try_handle() {
+        Ok(_) => Ok(response_tx
+            .send(SignalingProtocolMessage::Ok {
+                session_id,
+                endpoint_id,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_start_rtmp_ingest_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    endpoint_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<()> {
+        info!(
+            "handle_start_rtmp_ingest_message: {}/{}",
+            session_id, endpoint_id
+        );
+        let mut server_states = server_states.borrow_mut();
+        server_states
+            .start_rtmp_ingest(session_id, endpoint_id)
+            .map_err(|err| Error::other(format!("failed to start rtmp ingest: {}", err)))
+    };
+
+    match try_handle() {
+        Ok(_) => Ok(response_tx
+            .send(SignalingProtocolMessage::Ok {
+                session_id,
+                endpoint_id,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_stop_rtmp_ingest_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    endpoint_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<()> {
+        info!(
+            "handle_stop_rtmp_ingest_message: {}/{}",
+            session_id, endpoint_id
+        );
+        let mut server_states = server_states.borrow_mut();
+        server_states
+            .stop_rtmp_ingest(session_id, endpoint_id)
+            .map_err(|err| Error::other(format!("failed to stop rtmp ingest: {}", err)))
+    };
+
+    match try_handle() {
+        Ok(_) => Ok(response_tx
+            .send(SignalingProtocolMessage::Ok {
+                session_id,
+                endpoint_id,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_get_stats_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    endpoint_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<Bytes> {
+        let mut server_states = server_states.borrow_mut();
+        let stats = server_states
+            .get_endpoint_stats(session_id, endpoint_id)
+            .map_err(|err| Error::other(format!("failed to get stats: {}", err)))?;
+        Ok(Bytes::from(serde_json::to_vec(&stats)?))
+    };
+
+    match try_handle() {
+        Ok(stats) => Ok(response_tx
+            .send(SignalingProtocolMessage::Stats {
+                session_id,
+                endpoint_id,
+                stats,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_get_active_speaker_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    endpoint_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let try_handle = || -> std::io::Result<Option<Bytes>> {
+        let server_states = server_states.borrow();
+        let event = server_states
+            .get_active_speaker(session_id, endpoint_id)
+            .map_err(|err| Error::other(format!("failed to get active speaker: {}", err)))?;
+        Ok(match event {
+            Some(event) => Some(Bytes::from(serde_json::to_vec(&event)?)),
+            None => None,
+        })
+    };
+
+    match try_handle() {
+        Ok(event) => Ok(response_tx
+            .send(SignalingProtocolMessage::ActiveSpeaker {
+                session_id,
+                endpoint_id,
+                event,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_get_sessions_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> std::io::Result<()> {
+    let session_ids = server_states.borrow().get_session_ids();
+    response_tx
+        .send(SignalingProtocolMessage::Sessions { session_ids })
+        .map_err(|_| Error::other("failed to send back signaling message response".to_string()))
+}
+
 fn handle_leave_message(
     _server_states: &Rc<RefCell<ServerStates>>,
     session_id: u64,
@@ -178,10 +1345,7 @@ fn handle_leave_message(
                 endpoint_id,
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
         Err(err) => Ok(response_tx
             .send(SignalingProtocolMessage::Err {
@@ -190,10 +1354,7 @@ fn handle_leave_message(
                 reason: Bytes::from(err.to_string()),
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
     }
 }
@@ -1,45 +1,127 @@
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
+use crossbeam_channel::{RecvTimeoutError, Sender, TrySendError};
 use log::error;
 use retty::channel::{InboundPipeline, Pipeline};
 use retty::transport::{TaggedBytesMut, TransportContext};
 use sfu::{
     DataChannelHandler, DemuxerHandler, DtlsHandler, ExceptionHandler, GatewayHandler,
-    InterceptorHandler, SctpHandler, ServerConfig, ServerStates, SrtpHandler, StunHandler,
+    InterceptorHandler, RelayMessage, RelayPeer, RtmpMedia, SctpHandler, ServerConfig, ServerEvent,
+    ServerStates, SrtpHandler, StunHandler,
 };
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io::ErrorKind;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Receiver;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use std::vec;
-use tracing::info;
+use tracing::{debug, info, warn};
 
 use crate::transport::handlers::handle_signaling_message;
 
 use self::handlers::SignalingMessage;
 
+pub mod candidates;
+pub mod cascade;
 pub mod handlers;
+pub mod rtmp;
+pub mod tcp;
 
-/// This is the "main run loop" that handles all clients, reads and writes UdpSocket traffic,
-/// and forwards media data between clients.
+/// Inputs queued for a media worker, beyond which the packets reaching it are dropped.
+pub const MEDIA_INPUT_QUEUE_LEN: usize = 4096;
+
+/// Everything that reaches a media worker from its sockets and the RTMP listener.
+pub enum MediaInput {
+    Packet(TaggedBytesMut),
+    /// A TCP (or TLS) connection is ready, frames for `peer_addr` must be sent to `tx`
+    TcpConnected {
+        peer_addr: SocketAddr,
+        tx: Sender<BytesMut>,
+    },
+    TcpDisconnected {
+        peer_addr: SocketAddr,
+    },
+    /// A frame of the RTMP publisher bridged into `session_id` as `endpoint_id`
+    RtmpMedia {
+        session_id: u64,
+        endpoint_id: u64,
+        media: RtmpMedia,
+    },
+    /// Tracks of a session relayed by another worker hosting some of its endpoints
+    Relay(RelayMessage),
+    /// A link to another node hosting `session_id` is ready, messages for `link_id` must be
+    /// sent to `tx`
+    NodeLinked {
+        session_id: u64,
+        link_id: u64,
+        tx: Sender<Bytes>,
+    },
+    NodeUnlinked {
+        link_id: u64,
+    },
+}
+
+/// Addressing of a single media worker.
+pub struct WorkerConfig {
+    pub server_ip: SocketAddr,
+    pub candidate_addrs: Vec<SocketAddr>,
+    pub tcp_candidate_addrs: Vec<SocketAddr>,
+    pub tcp_listener: Option<TcpListener>,
+    /// shared by the ICE-TCP listeners of all workers
+    pub tcp_connection_limit: tcp::ConnectionLimit,
+    pub ice_ufrag_prefix: String,
+    /// port -> input of the other media workers, for the sessions spanning several of them
+    pub relay_txs: HashMap<u16, Sender<MediaInput>>,
+    /// events of the sessions hosted by the worker, streamed to clients and notified to webhooks
+    pub events_tx: Sender<ServerEvent>,
+}
+
+/// This is the "main run loop" that handles all clients, reads and writes UdpSocket and
+/// ICE-TCP traffic, and forwards media data between clients.
 pub fn sync_run(
     stop_rx: crossbeam_channel::Receiver<()>,
     socket: UdpSocket,
     rx: Receiver<SignalingMessage>,
+    media_tx: Sender<MediaInput>,
+    media_rx: crossbeam_channel::Receiver<MediaInput>,
     server_config: Arc<ServerConfig>,
-    server_ip: SocketAddr,
+    worker_config: WorkerConfig,
 ) -> std::io::Result<()> {
-    let server_states_config = ServerStates::new(server_config, server_ip).unwrap();
+    let server_ip = worker_config.server_ip;
+    let server_states_config = ServerStates::new(server_config, server_ip)
+        .unwrap()
+        .with_candidate_addrs(worker_config.candidate_addrs)
+        .with_tcp_candidate_addrs(worker_config.tcp_candidate_addrs)
+        .with_ice_ufrag_prefix(worker_config.ice_ufrag_prefix);
 
     let server_states = Rc::new(RefCell::new(server_states_config));
 
     info!("listening {}...", socket.local_addr()?);
 
-    let pipeline = build_pipeline(server_ip, server_states.clone());
+    let stopping = Arc::new(AtomicBool::new(false));
+    spawn_udp_reader(
+        socket.try_clone()?,
+        server_ip,
+        media_tx.clone(),
+        stopping.clone(),
+    )?;
+    if let Some(tcp_listener) = worker_config.tcp_listener {
+        tcp::spawn_listener(
+            tcp_listener,
+            server_ip,
+            media_tx,
+            worker_config.tcp_connection_limit.clone(),
+            stopping.clone(),
+        )?;
+    }
+    // peer_addr -> outgoing frames of its ICE-TCP connection
+    let mut tcp_connections: HashMap<SocketAddr, Sender<BytesMut>> = HashMap::new();
+    // link_id -> outgoing messages of the link to another node
+    let mut node_links: HashMap<u64, Sender<Bytes>> = HashMap::new();
 
-    let mut buf = vec![0; 2000];
+    let pipeline = build_pipeline(server_ip, server_states.clone());
 
     pipeline.transport_active();
     loop {
@@ -52,7 +134,9 @@ pub fn sync_run(
             }
         };
 
-        write_socket_output(&socket, &pipeline)?;
+        write_socket_output(&socket, &mut tcp_connections, &pipeline)?;
+        write_relay_output(&server_states, &worker_config.relay_txs, &mut node_links);
+        write_event_output(&server_states, &worker_config.events_tx);
 
         // Spawn new incoming signal message from the signaling server thread.
         if let Ok(signal_message) = rx.try_recv() {
@@ -74,55 +158,185 @@ pub fn sync_run(
             continue;
         }
 
-        socket
-            .set_read_timeout(Some(delay_from_now))
-            .expect("setting socket read timeout");
-
-        if let Some(input) = read_socket_input(&socket, &mut buf, server_ip) {
-            pipeline.read(input);
+        match media_rx.recv_timeout(delay_from_now) {
+            Ok(MediaInput::Packet(input)) => pipeline.read(input),
+            Ok(MediaInput::TcpConnected { peer_addr, tx }) => {
+                tcp_connections.insert(peer_addr, tx);
+            }
+            Ok(MediaInput::TcpDisconnected { peer_addr }) => {
+                tcp_connections.remove(&peer_addr);
+            }
+            Ok(MediaInput::RtmpMedia {
+                session_id,
+                endpoint_id,
+                media,
+            }) => {
+                if let Err(err) =
+                    server_states
+                        .borrow_mut()
+                        .push_rtmp_media(session_id, endpoint_id, media)
+                {
+                    debug!("drop rtmp media: {}", err);
+                }
+            }
+            Ok(MediaInput::Relay(message)) => {
+                if let Err(err) = server_states.borrow_mut().handle_relay_message(message) {
+                    debug!("drop relay message: {}", err);
+                }
+            }
+            Ok(MediaInput::NodeLinked {
+                session_id,
+                link_id,
+                tx,
+            }) => {
+                node_links.insert(link_id, tx);
+                server_states
+                    .borrow_mut()
+                    .add_node_link(session_id, link_id);
+            }
+            Ok(MediaInput::NodeUnlinked { link_id }) => {
+                node_links.remove(&link_id);
+                server_states.borrow_mut().remove_node_link(link_id);
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
         }
 
         // Drive time forward in all clients.
         pipeline.handle_timeout(Instant::now());
     }
     pipeline.transport_inactive();
+    stopping.store(true, Ordering::Relaxed);
 
-    info!(
-        "media server on {} is gracefully down",
-        server_ip
-    );
+    info!("media server on {} is gracefully down", server_ip);
     Ok(())
 }
 
 fn write_socket_output(
     socket: &UdpSocket,
+    tcp_connections: &mut HashMap<SocketAddr, Sender<BytesMut>>,
     pipeline: &Rc<Pipeline<TaggedBytesMut, TaggedBytesMut>>,
 ) -> std::io::Result<()> {
     while let Some(transmit) = pipeline.poll_transmit() {
-        socket.send_to(&transmit.message, transmit.transport.peer_addr)?;
+        let peer_addr = transmit.transport.peer_addr;
+        if let Some(tx) = tcp_connections.get(&peer_addr) {
+            if !tcp::send_frame(tx, transmit.message) {
+                tcp_connections.remove(&peer_addr);
+            }
+        } else {
+            socket.send_to(&transmit.message, peer_addr)?;
+        }
     }
 
     Ok(())
 }
 
-fn read_socket_input(socket: &UdpSocket, buf: &mut [u8], server_ip: SocketAddr) -> Option<TaggedBytesMut> {
-    match socket.recv_from(buf) {
-        Ok((n, peer_addr)) => {
-            return Some(TaggedBytesMut {
-                now: Instant::now(),
-                transport: TransportContext {
-                    local_addr: server_ip,
-                    peer_addr,
-                    ecn: None,
-                },
-                message: BytesMut::from(&buf[..n]),
-            });
+/// Send the tracks of the sessions spanning several workers, or several nodes, to the other
+/// workers and nodes hosting them.
+fn write_relay_output(
+    server_states: &Rc<RefCell<ServerStates>>,
+    relay_txs: &HashMap<u16, Sender<MediaInput>>,
+    node_links: &mut HashMap<u64, Sender<Bytes>>,
+) {
+    for (peer, message) in server_states.borrow_mut().poll_relay_messages() {
+        match peer {
+            RelayPeer::Worker(port) => match relay_txs.get(&port) {
+                // a blocking send could deadlock two workers relaying to each other
+                Some(tx) => {
+                    send_media(tx, MediaInput::Relay(message));
+                }
+                None => warn!("no media worker on port {} to relay to", port),
+            },
+            RelayPeer::Node(link_id) => {
+                let Some(tx) = node_links.get(&link_id) else {
+                    continue;
+                };
+                match message.marshal() {
+                    Ok(frame) => {
+                        if !cascade::send_message(tx, frame) {
+                            node_links.remove(&link_id);
+                            continue;
+                        }
+                    }
+                    Err(err) => warn!("drop relay message to link {}: {}", link_id, err),
+                }
+                // the link is closed once its last message is written
+                if message.is_leave() {
+                    node_links.remove(&link_id);
+                }
+            }
+        }
+    }
+}
+
+/// Hand the events of the sessions over to the signalling server, which streams them to clients
+/// and notifies the webhooks of them.
+fn write_event_output(server_states: &Rc<RefCell<ServerStates>>, events_tx: &Sender<ServerEvent>) {
+    for event in server_states.borrow_mut().poll_events() {
+        let _ = events_tx.send(event);
+    }
+}
+
+/// Queue a packet for a media worker, which is dropped when the worker can't keep up. Returns
+/// false once the worker is gone.
+pub fn send_media(tx: &Sender<MediaInput>, input: MediaInput) -> bool {
+    match tx.try_send(input) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            debug!("media worker is congested, dropping a packet");
+            true
         }
+        Err(TrySendError::Disconnected(_)) => false,
+    }
+}
+
+/// Read the UdpSocket on its own thread, so that the run loop can wait on UDP and TCP at once.
+fn spawn_udp_reader(
+    socket: UdpSocket,
+    server_ip: SocketAddr,
+    media_tx: Sender<MediaInput>,
+    stopping: Arc<AtomicBool>,
+) -> std::io::Result<()> {
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+    std::thread::spawn(move || {
+        let mut buf = vec![0; 2000];
+        while !stopping.load(Ordering::Relaxed) {
+            if let Some(input) = read_socket_input(&socket, &mut buf, server_ip) {
+                if !send_media(&media_tx, MediaInput::Packet(input)) {
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn read_socket_input(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    server_ip: SocketAddr,
+) -> Option<TaggedBytesMut> {
+    match socket.recv_from(buf) {
+        Ok((n, peer_addr)) => Some(TaggedBytesMut {
+            now: Instant::now(),
+            transport: TransportContext {
+                local_addr: server_ip,
+                peer_addr,
+                ecn: None,
+            },
+            message: BytesMut::from(&buf[..n]),
+        }),
 
         Err(e) => match e.kind() {
             // Expected error for set_read_timeout(). One for windows, one for the rest.
             ErrorKind::WouldBlock | ErrorKind::TimedOut => None,
-            _ => panic!("UdpSocket read failed: {e:?}"),
+            // e.g. ICMP port unreachable reported on the next read, must not stop the worker
+            _ => {
+                warn!("UdpSocket read failed: {e:?}");
+                None
+            }
         },
     }
 }
@@ -161,3 +375,28 @@ fn build_pipeline(
 
     pipeline.finalize()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disconnected(port: u16) -> MediaInput {
+        MediaInput::TcpDisconnected {
+            peer_addr: SocketAddr::from(([127, 0, 0, 1], port)),
+        }
+    }
+
+    #[test]
+    fn test_send_media_drops_when_congested() {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        assert!(send_media(&tx, disconnected(1)));
+        assert!(send_media(&tx, disconnected(2)));
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(MediaInput::TcpDisconnected { peer_addr }) if peer_addr.port() == 1
+        ));
+        assert!(rx.try_recv().is_err());
+        drop(rx);
+        assert!(!send_media(&tx, disconnected(3)));
+    }
+}
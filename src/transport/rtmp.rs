@@ -0,0 +1,223 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{mpsc, Arc},
+    time::Duration,
+};
+
+use crossbeam_channel::Sender;
+use sfu::{RtmpConnection, RtmpEvent, WorkerPlacement};
+use tracing::{debug, info, warn};
+
+use super::handlers::{SignalingMessage, SignalingProtocolMessage};
+use super::MediaInput;
+
+/// How long a publisher may stay silent before its connection is closed
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Workers reachable from the RTMP listener, keyed by their media port.
+///
+/// `worker_placement` must be the placement given to the web server, so that sessions are
+/// routed to the same worker from both, publishers going to the home worker of their session.
+pub struct RtmpWorkers {
+    pub signaling_txs: HashMap<u16, mpsc::Sender<SignalingMessage>>,
+    pub media_txs: HashMap<u16, Sender<MediaInput>>,
+    pub worker_placement: WorkerPlacement,
+}
+
+impl RtmpWorkers {
+    fn port(&self, session_id: u64) -> u16 {
+        self.worker_placement
+            .session_port(session_id)
+            .unwrap_or_default()
+    }
+}
+
+/// A publish accepted into a session, as the endpoint named by its stream key.
+#[derive(Clone, Copy)]
+struct Publish {
+    session_id: u64,
+    endpoint_id: u64,
+    port: u16,
+}
+
+/// Accept RTMP publishers, each publishing `rtmp://HOST:PORT/SESSION_ID` with the endpoint id
+/// as stream key.
+pub fn spawn_listener(listener: TcpListener, workers: RtmpWorkers) -> std::io::Result<()> {
+    info!("rtmp listening {}...", listener.local_addr()?);
+    let workers = Arc::new(workers);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("rtmp accept failed: {:?}", e);
+                    continue;
+                }
+            };
+            let workers = Arc::clone(&workers);
+            std::thread::spawn(move || {
+                let peer_addr = match stream.peer_addr() {
+                    Ok(peer_addr) => peer_addr,
+                    Err(_) => return,
+                };
+                if let Err(e) = serve(stream, peer_addr, &workers) {
+                    debug!("rtmp connection from {} ended: {}", peer_addr, e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn serve(
+    mut stream: TcpStream,
+    peer_addr: SocketAddr,
+    workers: &RtmpWorkers,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    let mut connection = RtmpConnection::new();
+    let mut publish: Option<Publish> = None;
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+
+    let result = loop {
+        let n = match stream.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(e) => break Err(e),
+        };
+        let events = match connection.handle_input(&buf[..n]) {
+            Ok(events) => events,
+            Err(err) => {
+                warn!("rtmp connection from {} failed: {}", peer_addr, err);
+                // the publisher is told why before the connection is closed
+                let _ = flush(&mut stream, &mut connection);
+                break Err(Error::new(ErrorKind::InvalidData, err.to_string()));
+            }
+        };
+        for event in events {
+            match event {
+                RtmpEvent::Publish { app, stream_key } => {
+                    match start_publish(workers, &app, &stream_key) {
+                        Ok(started) => {
+                            info!(
+                                "rtmp publisher {} publishes into session {} as endpoint {}",
+                                peer_addr, started.session_id, started.endpoint_id
+                            );
+                            connection.accept_publish();
+                            publish = Some(started);
+                        }
+                        Err(reason) => {
+                            warn!("rtmp publisher {} rejected: {}", peer_addr, reason);
+                            connection.reject_publish(&reason);
+                        }
+                    }
+                }
+                RtmpEvent::Media(media) => {
+                    let Some(publish) = publish else {
+                        continue;
+                    };
+                    if let Some(media_tx) = workers.media_txs.get(&publish.port) {
+                        let _ = media_tx.send(MediaInput::RtmpMedia {
+                            session_id: publish.session_id,
+                            endpoint_id: publish.endpoint_id,
+                            media,
+                        });
+                    }
+                }
+                RtmpEvent::Unpublish => {
+                    if let Some(publish) = publish.take() {
+                        stop_publish(workers, publish);
+                    }
+                }
+            }
+        }
+        if let Err(e) = flush(&mut stream, &mut connection) {
+            break Err(e);
+        }
+    };
+
+    if let Some(publish) = publish {
+        stop_publish(workers, publish);
+    }
+    result
+}
+
+fn flush(stream: &mut TcpStream, connection: &mut RtmpConnection) -> std::io::Result<()> {
+    while let Some(output) = connection.poll_output() {
+        stream.write_all(&output)?;
+    }
+    stream.flush()
+}
+
+/// The app is the session id, and the stream key the endpoint id.
+fn start_publish(workers: &RtmpWorkers, app: &str, stream_key: &str) -> Result<Publish, String> {
+    let session_id = app
+        .parse::<u64>()
+        .map_err(|_| format!("app {} is not a session id", app))?;
+    let endpoint_id = stream_key
+        .parse::<u64>()
+        .map_err(|_| format!("stream key {} is not an endpoint id", stream_key))?;
+    let port = workers.port(session_id);
+    request(
+        workers,
+        port,
+        SignalingProtocolMessage::StartRtmpIngest {
+            session_id,
+            endpoint_id,
+        },
+    )?;
+
+    Ok(Publish {
+        session_id,
+        endpoint_id,
+        port,
+    })
+}
+
+fn stop_publish(workers: &RtmpWorkers, publish: Publish) {
+    info!(
+        "rtmp publisher stops publishing into session {} as endpoint {}",
+        publish.session_id, publish.endpoint_id
+    );
+    if let Err(reason) = request(
+        workers,
+        publish.port,
+        SignalingProtocolMessage::StopRtmpIngest {
+            session_id: publish.session_id,
+            endpoint_id: publish.endpoint_id,
+        },
+    ) {
+        warn!("failed to stop rtmp ingest: {}", reason);
+    }
+}
+
+fn request(
+    workers: &RtmpWorkers,
+    port: u16,
+    request: SignalingProtocolMessage,
+) -> Result<(), String> {
+    let tx = workers
+        .signaling_txs
+        .get(&port)
+        .ok_or_else(|| format!("no media worker on port {}", port))?;
+    let (response_tx, response_rx) = mpsc::channel();
+    tx.send(SignalingMessage {
+        request,
+        response_tx,
+    })
+    .map_err(|_| "media worker is down".to_string())?;
+
+    match response_rx.recv() {
+        Ok(SignalingProtocolMessage::Ok { .. }) => Ok(()),
+        Ok(SignalingProtocolMessage::Err { reason, .. }) => {
+            Err(String::from_utf8_lossy(&reason).to_string())
+        }
+        _ => Err("unexpected response from media worker".to_string()),
+    }
+}
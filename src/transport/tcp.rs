@@ -0,0 +1,579 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf, BytesMut};
+use crossbeam_channel::{Receiver, Sender, TryRecvError, TrySendError};
+use retty::transport::{TaggedBytesMut, TransportContext};
+use stun::{attributes::ATTR_USERNAME, message::Message, textattrs::TextAttribute};
+use tracing::{debug, error, info, warn};
+
+use super::{send_media, MediaInput};
+
+/// RFC 4571 frames are prefixed by their length on 16 bits
+const FRAME_HEADER_LEN: usize = 2;
+/// TLS records always start with a handshake content type on a new connection
+const TLS_HANDSHAKE_CONTENT_TYPE: u8 = 0x16;
+/// How long a connection waits for incoming data before flushing outgoing frames
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+/// How long a connection may take to send its first connectivity check, TLS handshake included
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the listeners wait between two accepts when no connection is pending
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+/// Frames waiting to be written to a connection, after which the worker drops the next ones
+const OUTGOING_QUEUE_LEN: usize = 256;
+/// Length in hex digits of the worker port put in front of the local ICE ufrag
+pub const ICE_UFRAG_PORT_PREFIX_LEN: usize = 4;
+
+/// Local ICE ufrag prefix identifying the worker bound on `port`, see [`spawn_shared_listener`].
+pub fn ice_ufrag_prefix(port: u16) -> String {
+    format!("{:0width$x}", port, width = ICE_UFRAG_PORT_PREFIX_LEN)
+}
+
+/// Bounds the connections served at once by all the ICE-TCP listeners, or by the TURN one, each
+/// connection having its own thread.
+#[derive(Clone)]
+pub struct ConnectionLimit {
+    connections: Arc<AtomicUsize>,
+    max_connections: usize,
+}
+
+impl ConnectionLimit {
+    pub fn new(max_connections: usize) -> Self {
+        ConnectionLimit {
+            connections: Arc::new(AtomicUsize::new(0)),
+            max_connections,
+        }
+    }
+
+    /// Returns `None` when the limit is reached, the connection being counted until the permit
+    /// is dropped otherwise.
+    pub(crate) fn try_acquire(&self) -> Option<ConnectionPermit> {
+        self.connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |connections| {
+                (connections < self.max_connections).then_some(connections + 1)
+            })
+            .ok()
+            .map(|_| ConnectionPermit {
+                connections: Arc::clone(&self.connections),
+            })
+    }
+}
+
+pub(crate) struct ConnectionPermit {
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Send a frame to the connection of an ICE-TCP peer, which is dropped when the connection
+/// can't keep up. Returns false once the connection is gone.
+pub fn send_frame(tx: &Sender<BytesMut>, frame: BytesMut) -> bool {
+    match tx.try_send(frame) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            debug!("ice-tcp connection is congested, dropping a frame");
+            true
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    }
+}
+
+/// Load a rustls server config from PEM encoded certificate chain and private key files.
+pub fn load_tls_config(cert_path: &str, key_path: &str) -> std::io::Result<rustls::ServerConfig> {
+    let parse = |path: &str| -> std::io::Result<Vec<pem::Pem>> {
+        let data = fs::read(path)?;
+        pem::parse_many(data).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse {}: {}", path, e),
+            )
+        })
+    };
+
+    let certs: Vec<rustls::Certificate> = parse(cert_path)?
+        .into_iter()
+        .filter(|p| p.tag() == "CERTIFICATE")
+        .map(|p| rustls::Certificate(p.into_contents()))
+        .collect();
+    let key = parse(key_path)?
+        .into_iter()
+        .find(|p| p.tag().ends_with("PRIVATE KEY"))
+        .map(|p| rustls::PrivateKey(p.into_contents()))
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("no private key found in {}", key_path),
+            )
+        })?;
+
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid tls certificate: {}", e),
+            )
+        })
+}
+
+/// RFC 4571 framing over any byte stream. Reads never block for longer than the underlying
+/// socket read timeout, so one thread can serve both directions.
+struct FramedStream<S: Read + Write> {
+    stream: S,
+    buf: BytesMut,
+}
+
+impl<S: Read + Write> FramedStream<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream,
+            buf: BytesMut::with_capacity(4096),
+        }
+    }
+
+    /// Returns `Ok(None)` when no complete frame arrived in a single read, which blocks until
+    /// the read timeout at most, so that callers can enforce their own deadlines.
+    fn read_frame(&mut self) -> std::io::Result<Option<BytesMut>> {
+        if let Some(frame) = self.split_frame() {
+            return Ok(Some(frame));
+        }
+
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => Err(Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+            Ok(n) => {
+                self.buf.extend_from_slice(&chunk[..n]);
+                Ok(self.split_frame())
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn split_frame(&mut self) -> Option<BytesMut> {
+        if self.buf.len() < FRAME_HEADER_LEN {
+            return None;
+        }
+        let len = u16::from_be_bytes([self.buf[0], self.buf[1]]) as usize;
+        if self.buf.len() < FRAME_HEADER_LEN + len {
+            return None;
+        }
+        self.buf.advance(FRAME_HEADER_LEN);
+        Some(self.buf.split_to(len))
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        if frame.len() > u16::MAX as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("frame of {} bytes is too large for RFC 4571", frame.len()),
+            ));
+        }
+        let mut out = Vec::with_capacity(FRAME_HEADER_LEN + frame.len());
+        out.extend_from_slice(&(frame.len() as u16).to_be_bytes());
+        out.extend_from_slice(frame);
+        self.stream.write_all(&out)?;
+        self.stream.flush()
+    }
+}
+
+/// Accept passive ICE-TCP connections for a single worker on its own port.
+pub fn spawn_listener(
+    listener: TcpListener,
+    local_addr: SocketAddr,
+    media_tx: Sender<MediaInput>,
+    connection_limit: ConnectionLimit,
+    stopping: Arc<AtomicBool>,
+) -> std::io::Result<()> {
+    listener.set_nonblocking(true)?;
+    info!("ice-tcp listening {}...", listener.local_addr()?);
+
+    std::thread::spawn(move || {
+        while !stopping.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, peer_addr)) => {
+                    let Some(permit) = connection_limit.try_acquire() else {
+                        warn!(
+                            "refusing ice-tcp connection from {}, too many connections",
+                            peer_addr
+                        );
+                        continue;
+                    };
+                    let media_tx = media_tx.clone();
+                    std::thread::spawn(move || {
+                        let _permit = permit;
+                        if let Err(e) = serve_plain(stream, peer_addr, local_addr, media_tx) {
+                            debug!("ice-tcp connection from {} ended: {}", peer_addr, e);
+                        }
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_INTERVAL),
+                Err(e) => {
+                    error!("ice-tcp accept failed: {:?}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// A worker reachable through the shared port, keyed by its media port.
+#[derive(Clone)]
+pub struct SharedPortWorker {
+    pub local_addr: SocketAddr,
+    pub media_tx: Sender<MediaInput>,
+}
+
+/// Accept ICE-TCP connections for all workers on one port (typically 443).
+///
+/// Connections starting with a TLS handshake are decrypted first when `tls_config` is set.
+/// The worker is found from the port encoded in the local ICE ufrag of the first STUN binding
+/// request, see [`ice_ufrag_prefix`].
+pub fn spawn_shared_listener(
+    listener: TcpListener,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    workers: HashMap<u16, SharedPortWorker>,
+    connection_limit: ConnectionLimit,
+    stopping: Arc<AtomicBool>,
+) -> std::io::Result<()> {
+    listener.set_nonblocking(true)?;
+    info!(
+        "shared ice-tcp listening {} (tls {})...",
+        listener.local_addr()?,
+        if tls_config.is_some() { "on" } else { "off" }
+    );
+    let workers = Arc::new(workers);
+
+    std::thread::spawn(move || {
+        while !stopping.load(Ordering::Relaxed) {
+            let (stream, peer_addr) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    std::thread::sleep(ACCEPT_INTERVAL);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("shared ice-tcp accept failed: {:?}", e);
+                    continue;
+                }
+            };
+            let Some(permit) = connection_limit.try_acquire() else {
+                warn!(
+                    "refusing shared ice-tcp connection from {}, too many connections",
+                    peer_addr
+                );
+                continue;
+            };
+            let tls_config = tls_config.clone();
+            let workers = Arc::clone(&workers);
+            std::thread::spawn(move || {
+                let _permit = permit;
+                if let Err(e) = serve_shared(stream, peer_addr, tls_config, &workers) {
+                    debug!("shared ice-tcp connection from {} ended: {}", peer_addr, e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn serve_shared(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    workers: &HashMap<u16, SharedPortWorker>,
+) -> std::io::Result<()> {
+    // accepted sockets inherit the non-blocking mode of the listener on some platforms
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(FIRST_FRAME_TIMEOUT))?;
+    let deadline = Instant::now() + FIRST_FRAME_TIMEOUT;
+    let mut first_byte = [0u8; 1];
+    if stream.peek(&mut first_byte)? == 0 {
+        return Ok(());
+    }
+
+    match tls_config {
+        Some(tls_config) if first_byte[0] == TLS_HANDSHAKE_CONTENT_TYPE => {
+            let connection = rustls::ServerConnection::new(tls_config).map_err(Error::other)?;
+            let raw = stream.try_clone()?;
+            let mut framed = FramedStream::new(rustls::StreamOwned::new(connection, stream));
+            let (worker, first_frame) = route_first_frame(&mut framed, &raw, deadline, workers)?;
+            serve(
+                framed,
+                &raw,
+                peer_addr,
+                worker.local_addr,
+                first_frame,
+                worker.media_tx.clone(),
+            )
+        }
+        _ => {
+            let raw = stream.try_clone()?;
+            let mut framed = FramedStream::new(stream);
+            let (worker, first_frame) = route_first_frame(&mut framed, &raw, deadline, workers)?;
+            serve(
+                framed,
+                &raw,
+                peer_addr,
+                worker.local_addr,
+                first_frame,
+                worker.media_tx.clone(),
+            )
+        }
+    }
+}
+
+fn route_first_frame<'a, S: Read + Write>(
+    framed: &mut FramedStream<S>,
+    raw: &TcpStream,
+    deadline: Instant,
+    workers: &'a HashMap<u16, SharedPortWorker>,
+) -> std::io::Result<(&'a SharedPortWorker, BytesMut)> {
+    let frame = read_first_frame(framed, raw, deadline)?;
+
+    let mut message = Message {
+        raw: frame.to_vec(),
+        ..Default::default()
+    };
+    message
+        .decode()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("not a stun message: {}", e)))?;
+    let username = TextAttribute::get_from_as(&message, ATTR_USERNAME)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("no stun username: {}", e)))?;
+
+    let port = username
+        .text
+        .get(..ICE_UFRAG_PORT_PREFIX_LEN)
+        .and_then(|prefix| u16::from_str_radix(prefix, 16).ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "unroutable ice ufrag"))?;
+    let worker = workers.get(&port).ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("no media worker for port {}", port),
+        )
+    })?;
+
+    Ok((worker, frame))
+}
+
+/// Wait for the first frame of a connection until the deadline, however slowly its bytes come.
+fn read_first_frame<S: Read + Write>(
+    framed: &mut FramedStream<S>,
+    raw: &TcpStream,
+    deadline: Instant,
+) -> std::io::Result<BytesMut> {
+    loop {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| Error::new(ErrorKind::TimedOut, "no connectivity check"))?;
+        raw.set_read_timeout(Some(remaining))?;
+        if let Some(frame) = framed.read_frame()? {
+            return Ok(frame);
+        }
+    }
+}
+
+fn serve_plain(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    local_addr: SocketAddr,
+    media_tx: Sender<MediaInput>,
+) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let raw = stream.try_clone()?;
+    let mut framed = FramedStream::new(stream);
+    let first_frame = read_first_frame(&mut framed, &raw, Instant::now() + FIRST_FRAME_TIMEOUT)?;
+    serve(framed, &raw, peer_addr, local_addr, first_frame, media_tx)
+}
+
+/// Relay frames between one connection and its worker until either side goes away.
+fn serve<S: Read + Write>(
+    mut framed: FramedStream<S>,
+    raw: &TcpStream,
+    peer_addr: SocketAddr,
+    local_addr: SocketAddr,
+    first_frame: BytesMut,
+    media_tx: Sender<MediaInput>,
+) -> std::io::Result<()> {
+    raw.set_read_timeout(Some(POLL_INTERVAL))?;
+    raw.set_nodelay(true)?;
+
+    let (tx, rx): (Sender<BytesMut>, Receiver<BytesMut>) =
+        crossbeam_channel::bounded(OUTGOING_QUEUE_LEN);
+    let send = |message: MediaInput| {
+        media_tx
+            .send(message)
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "media worker is gone"))
+    };
+    let send_packet = |message: MediaInput| {
+        if send_media(&media_tx, message) {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::BrokenPipe, "media worker is gone"))
+        }
+    };
+    let tagged = |message: BytesMut| {
+        MediaInput::Packet(TaggedBytesMut {
+            now: Instant::now(),
+            transport: TransportContext {
+                local_addr,
+                peer_addr,
+                ecn: None,
+            },
+            message,
+        })
+    };
+
+    send(MediaInput::TcpConnected { peer_addr, tx })?;
+    debug!("ice-tcp connection from {} to {}", peer_addr, local_addr);
+
+    let result = (|| -> std::io::Result<()> {
+        send_packet(tagged(first_frame))?;
+        loop {
+            loop {
+                match rx.try_recv() {
+                    Ok(frame) => framed.write_frame(&frame)?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            if let Some(frame) = framed.read_frame()? {
+                send_packet(tagged(frame))?;
+            }
+        }
+    })();
+
+    let _ = send(MediaInput::TcpDisconnected { peer_addr });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Stream handing out the given chunks one read at a time, then timing out or closing
+    struct ChunkedStream {
+        chunks: VecDeque<Vec<u8>>,
+        is_closed: bool,
+        written: Vec<u8>,
+    }
+
+    impl ChunkedStream {
+        fn new(chunks: &[&[u8]], is_closed: bool) -> Self {
+            ChunkedStream {
+                chunks: chunks.iter().map(|chunk| chunk.to_vec()).collect(),
+                is_closed,
+                written: vec![],
+            }
+        }
+    }
+
+    impl Read for ChunkedStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.chunks.pop_front() {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(&chunk);
+                    Ok(chunk.len())
+                }
+                None if self.is_closed => Ok(0),
+                None => Err(Error::new(ErrorKind::WouldBlock, "no data")),
+            }
+        }
+    }
+
+    impl Write for ChunkedStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_read_frame_split_across_reads() {
+        let mut framed = FramedStream::new(ChunkedStream::new(&[&[0], &[3, b'a'], b"bc"], false));
+        assert_eq!(framed.read_frame().unwrap(), None);
+        assert_eq!(framed.read_frame().unwrap(), None);
+        assert_eq!(framed.read_frame().unwrap().unwrap().as_ref(), b"abc");
+        assert_eq!(framed.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_frames_of_a_single_read() {
+        let mut framed = FramedStream::new(ChunkedStream::new(
+            &[&[0, 1, b'a', 0, 0, 0, 2, b'b', b'c', 0]],
+            false,
+        ));
+        assert_eq!(framed.read_frame().unwrap().unwrap().as_ref(), b"a");
+        assert_eq!(framed.read_frame().unwrap().unwrap().as_ref(), b"");
+        assert_eq!(framed.read_frame().unwrap().unwrap().as_ref(), b"bc");
+        // the header of the next frame is kept until its end arrives
+        assert_eq!(framed.read_frame().unwrap(), None);
+        assert_eq!(framed.buf.as_ref(), &[0]);
+    }
+
+    #[test]
+    fn test_read_frame_closed() {
+        let mut framed = FramedStream::new(ChunkedStream::new(&[&[0, 2, b'a']], true));
+        assert_eq!(framed.read_frame().unwrap(), None);
+        let err = framed.read_frame().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_write_frame() {
+        let mut framed = FramedStream::new(ChunkedStream::new(&[], false));
+        framed.write_frame(b"abc").unwrap();
+        framed.write_frame(b"").unwrap();
+        assert_eq!(framed.stream.written, vec![0, 3, b'a', b'b', b'c', 0, 0]);
+
+        let err = framed.write_frame(&vec![0; 65536]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(framed.stream.written.len(), 7);
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let limit = ConnectionLimit::new(2);
+        let first = limit.try_acquire().unwrap();
+        let _second = limit.try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+        drop(first);
+        assert!(limit.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_send_frame_drops_when_congested() {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        assert!(send_frame(&tx, BytesMut::from(&b"a"[..])));
+        assert!(send_frame(&tx, BytesMut::from(&b"b"[..])));
+        assert_eq!(rx.try_recv().unwrap().as_ref(), b"a");
+        assert!(rx.try_recv().is_err());
+        drop(rx);
+        assert!(!send_frame(&tx, BytesMut::from(&b"c"[..])));
+    }
+}
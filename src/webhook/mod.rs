@@ -0,0 +1,557 @@
+use std::{
+    io::Error,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use futures_util::future::join_all;
+use ring::hmac;
+use sfu::ServerEvent;
+use tracing::{debug, error, info, warn};
+
+use self::queue::{Delivery, DeliveryQueue};
+
+mod queue;
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// How long the dispatcher waits for events when no delivery is due
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Events waiting for the dispatcher, the newer ones being dropped while it is behind
+const EVENTS_CHANNEL_LEN: usize = 1024;
+
+/// Endpoints of the backend notified of the lifecycle of sessions, endpoints and tracks.
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+    /// Signs every notification, for the backend to check where it comes from
+    pub secret: String,
+    /// Directory the deliveries not acknowledged yet are kept in, across restarts
+    pub queue_dir: PathBuf,
+    /// Attempts after which a delivery is given up
+    pub max_attempts: u32,
+    /// Deliveries kept waiting, in memory and on disk, the oldest being given up past it
+    pub max_pending: usize,
+}
+
+/// Body of a notification, the event being flattened next to its id and creation time.
+#[derive(serde::Serialize)]
+struct Notification<'a> {
+    id: &'a str,
+    /// Unix time in milliseconds the event was received by the dispatcher
+    timestamp: u64,
+    #[serde(flatten)]
+    event: &'a ServerEvent,
+}
+
+/// Start the thread delivering the events sent to the returned channel to every webhook,
+/// resuming the deliveries left in the queue by a previous run.
+pub fn spawn_dispatcher(config: WebhookConfig) -> std::io::Result<Sender<ServerEvent>> {
+    let mut dispatcher = Dispatcher::new(config)?;
+    if !dispatcher.pending.is_empty() {
+        info!("Resuming {} webhook deliveries", dispatcher.pending.len());
+    }
+
+    info!("Notifying webhooks {:?}", dispatcher.config.urls);
+    let (events_tx, events_rx) = crossbeam_channel::bounded(EVENTS_CHANNEL_LEN);
+    std::thread::Builder::new()
+        .name("webhooks".to_string())
+        .spawn(move || dispatcher.run(events_rx))?;
+
+    Ok(events_tx)
+}
+
+struct Dispatcher {
+    config: WebhookConfig,
+    queue: DeliveryQueue,
+    pending: Vec<Delivery>,
+    /// orders the deliveries of the events created within the same millisecond
+    sequence: u64,
+    runtime: tokio::runtime::Runtime,
+    client: reqwest::Client,
+}
+
+impl Dispatcher {
+    fn new(config: WebhookConfig) -> std::io::Result<Self> {
+        let queue = DeliveryQueue::open(config.queue_dir.clone())?;
+        let mut pending = queue.load()?;
+        pending.retain(|delivery| {
+            let configured = config.urls.contains(&delivery.url);
+            if !configured {
+                warn!(
+                    "Dropping webhook delivery {} to {}, which is no longer configured",
+                    delivery.event_id, delivery.url
+                );
+                let _ = queue.remove(delivery);
+            }
+            configured
+        });
+        if pending.len() > config.max_pending {
+            let dropped = pending.len() - config.max_pending;
+            warn!(
+                "Dropping the {} oldest webhook deliveries, over the {} kept",
+                dropped, config.max_pending
+            );
+            for delivery in pending.drain(..dropped) {
+                let _ = queue.remove(&delivery);
+            }
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let client = {
+            let _guard = runtime.enter();
+            reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()
+                .map_err(Error::other)?
+        };
+
+        Ok(Dispatcher {
+            config,
+            queue,
+            pending,
+            sequence: 0,
+            runtime,
+            client,
+        })
+    }
+
+    fn run(&mut self, events_rx: Receiver<ServerEvent>) {
+        loop {
+            let now = unix_millis();
+            let timeout = self
+                .pending
+                .iter()
+                .map(|delivery| delivery.next_attempt)
+                .min()
+                .map_or(IDLE_TIMEOUT, |next_attempt| {
+                    Duration::from_millis(next_attempt.saturating_sub(now))
+                });
+            match events_rx.recv_timeout(timeout) {
+                Ok(event) => {
+                    self.enqueue(&event);
+                    for event in events_rx.try_iter() {
+                        self.enqueue(&event);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                // the deliveries left are resumed on the next run
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            self.deliver_due();
+        }
+    }
+
+    /// Queue a delivery of the event to every webhook, stored before its first attempt.
+    fn enqueue(&mut self, event: &ServerEvent) {
+        let created = unix_millis();
+        let id = hex::encode(rand::random::<[u8; 16]>());
+        let notification = match serde_json::to_value(Notification {
+            id: &id,
+            timestamp: created,
+            event,
+        }) {
+            Ok(notification) => notification,
+            Err(e) => {
+                error!("Failed to serialize event {:?}: {}", event, e);
+                return;
+            }
+        };
+        let event_type = notification["type"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let body = notification.to_string();
+        self.sequence += 1;
+
+        self.make_room(self.config.urls.len());
+        for (index, url) in self.config.urls.iter().enumerate() {
+            let delivery = Delivery {
+                url: url.clone(),
+                event_type: event_type.clone(),
+                event_id: id.clone(),
+                body: body.clone(),
+                attempts: 0,
+                next_attempt: created,
+                file_name: format!(
+                    "{:013}-{:010}-{}-{}.json",
+                    created, self.sequence, id, index
+                ),
+            };
+            if let Err(e) = self.queue.store(&delivery) {
+                warn!("Failed to store webhook delivery {}: {}", id, e);
+            }
+            self.pending.push(delivery);
+        }
+    }
+
+    /// Give the oldest deliveries up until there is room for `count` more.
+    fn make_room(&mut self, count: usize) {
+        while self.pending.len() + count > self.config.max_pending {
+            let Some(oldest) = self
+                .pending
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.file_name.cmp(&b.file_name))
+                .map(|(index, _)| index)
+            else {
+                return;
+            };
+            let delivery = self.pending.swap_remove(oldest);
+            warn!(
+                "Dropping webhook delivery {} {} to {}, {} deliveries are already waiting",
+                delivery.event_type, delivery.event_id, delivery.url, self.config.max_pending
+            );
+            if let Err(e) = self.queue.remove(&delivery) {
+                warn!(
+                    "Failed to remove webhook delivery {}: {}",
+                    delivery.event_id, e
+                );
+            }
+        }
+    }
+
+    fn deliver_due(&mut self) {
+        let now = unix_millis();
+        let (due, waiting): (Vec<Delivery>, Vec<Delivery>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|delivery| delivery.next_attempt <= now);
+        self.pending = waiting;
+
+        // a webhook slow to answer doesn't hold the others back, while the deliveries to each
+        // webhook are attempted in the order of their events
+        let mut by_url: Vec<Vec<Delivery>> = vec![];
+        for delivery in due {
+            match by_url.iter_mut().find(|batch| batch[0].url == delivery.url) {
+                Some(batch) => batch.push(delivery),
+                None => by_url.push(vec![delivery]),
+            }
+        }
+        let attempts = self
+            .runtime
+            .block_on(join_all(by_url.into_iter().map(|batch| async {
+                let mut attempts = vec![];
+                for delivery in batch {
+                    let result = self.post(&delivery).await;
+                    attempts.push((delivery, result));
+                }
+                attempts
+            })));
+
+        for (mut delivery, result) in attempts.into_iter().flatten() {
+            match result {
+                Ok(()) => {
+                    debug!(
+                        "Delivered {} {} to {}",
+                        delivery.event_type, delivery.event_id, delivery.url
+                    );
+                    if let Err(e) = self.queue.remove(&delivery) {
+                        warn!(
+                            "Failed to remove webhook delivery {}: {}",
+                            delivery.event_id, e
+                        );
+                    }
+                    continue;
+                }
+                Err(reason) => {
+                    delivery.attempts += 1;
+                    if delivery.attempts >= self.config.max_attempts {
+                        error!(
+                            "Giving up delivering {} {} to {} after {} attempts: {}",
+                            delivery.event_type,
+                            delivery.event_id,
+                            delivery.url,
+                            delivery.attempts,
+                            reason
+                        );
+                        if let Err(e) = self.queue.remove(&delivery) {
+                            warn!(
+                                "Failed to remove webhook delivery {}: {}",
+                                delivery.event_id, e
+                            );
+                        }
+                        continue;
+                    }
+                    let backoff = backoff(delivery.attempts);
+                    warn!(
+                        "Failed to deliver {} {} to {}, retrying in {:?}: {}",
+                        delivery.event_type, delivery.event_id, delivery.url, backoff, reason
+                    );
+                    delivery.next_attempt = unix_millis() + backoff.as_millis() as u64;
+                }
+            }
+            if let Err(e) = self.queue.store(&delivery) {
+                warn!(
+                    "Failed to store webhook delivery {}: {}",
+                    delivery.event_id, e
+                );
+            }
+            self.pending.push(delivery);
+        }
+    }
+
+    /// POST the notification, signed with the time of the attempt so that the backend can reject
+    /// replayed ones.
+    async fn post(&self, delivery: &Delivery) -> Result<(), String> {
+        let timestamp = unix_millis() / 1000;
+        let request = self
+            .client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Beep-Event", &delivery.event_type)
+            .header("X-Beep-Delivery", &delivery.event_id)
+            .header("X-Beep-Timestamp", timestamp.to_string())
+            .header(
+                "X-Beep-Signature",
+                format!(
+                    "sha256={}",
+                    signature(&self.config.secret, timestamp, &delivery.body)
+                ),
+            )
+            .body(delivery.body.clone());
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("responded {}", response.status()))
+        }
+    }
+}
+
+/// HMAC-SHA256 of `TIMESTAMP.BODY`, hex encoded.
+fn signature(secret: &str, timestamp: u64, body: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hex::encode(hmac::sign(
+        &key,
+        format!("{}.{}", timestamp, body).as_bytes(),
+    ))
+}
+
+/// Delay before the attempt following `attempts` failed ones, doubling up to [`MAX_BACKOFF`].
+fn backoff(attempts: u32) -> Duration {
+    INITIAL_BACKOFF
+        .checked_mul(1 << attempts.saturating_sub(1).min(16))
+        .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF))
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        path::Path,
+        sync::mpsc,
+        time::Instant,
+    };
+
+    use super::*;
+
+    const SECRET: &str = "webhook-secret";
+
+    fn config(name: &str, urls: Vec<String>) -> WebhookConfig {
+        let queue_dir =
+            std::env::temp_dir().join(format!("beep-sfu-webhooks-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&queue_dir);
+        WebhookConfig {
+            urls,
+            secret: SECRET.to_string(),
+            queue_dir,
+            max_attempts: 3,
+            max_pending: 100,
+        }
+    }
+
+    fn queued_files(dir: &Path) -> Vec<String> {
+        let mut file_names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        file_names.sort();
+        file_names
+    }
+
+    /// Webhook answering every request with `status`, handing their headers and body over.
+    fn listen(status: u16) -> (String, mpsc::Receiver<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        let (requests_tx, requests_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut headers = vec![];
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                    headers.push(line.trim_end().to_lowercase());
+                }
+                let content_length = headers
+                    .iter()
+                    .find_map(|header| header.strip_prefix("content-length: "))
+                    .map_or(0, |len| len.parse().unwrap());
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let _ = requests_tx.send((headers, String::from_utf8(body).unwrap()));
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+            }
+        });
+        (url, requests_rx)
+    }
+
+    fn header<'a>(headers: &'a [String], name: &str) -> &'a str {
+        headers
+            .iter()
+            .find_map(|header| header.strip_prefix(&format!("{}: ", name)))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature(SECRET, 1_718_000_000, r#"{"id":"1"}"#),
+            "883525fc5e2dc2dd81c5097924b67afe89614ed56cf0440eab6d8bbde062253e"
+        );
+        assert_ne!(
+            signature(SECRET, 1_718_000_001, r#"{"id":"1"}"#),
+            signature(SECRET, 1_718_000_000, r#"{"id":"1"}"#)
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), INITIAL_BACKOFF);
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+        assert_eq!(backoff(5), Duration::from_secs(16));
+        assert_eq!(backoff(9), Duration::from_secs(256));
+        assert_eq!(backoff(10), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_deliver_to_webhook() {
+        let (url, requests_rx) = listen(200);
+        let config = config("deliver", vec![url]);
+        let queue_dir = config.queue_dir.clone();
+        let events_tx = spawn_dispatcher(config).unwrap();
+        events_tx
+            .send(ServerEvent::EndpointJoined {
+                session_id: 1,
+                endpoint_id: 2,
+            })
+            .unwrap();
+
+        let (headers, body) = requests_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let notification: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(notification["type"], "endpoint_joined");
+        assert_eq!(notification["session_id"], 1);
+        assert_eq!(notification["endpoint_id"], 2);
+        assert_eq!(header(&headers, "x-beep-event"), "endpoint_joined");
+        assert_eq!(
+            header(&headers, "x-beep-delivery"),
+            notification["id"].as_str().unwrap()
+        );
+        let timestamp = header(&headers, "x-beep-timestamp").parse().unwrap();
+        assert_eq!(
+            header(&headers, "x-beep-signature"),
+            format!("sha256={}", signature(SECRET, timestamp, &body))
+        );
+
+        // the acknowledged delivery leaves the queue
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !queued_files(&queue_dir).is_empty() {
+            assert!(Instant::now() < deadline, "delivery left in the queue");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = std::fs::remove_dir_all(&queue_dir);
+    }
+
+    #[test]
+    fn test_deliver_while_another_webhook_stalls() {
+        // accepts the connection but never answers, until the delivery times out
+        let stalled = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stalled_url = format!("http://{}/events", stalled.local_addr().unwrap());
+        let (url, requests_rx) = listen(200);
+        let config = config("stalled", vec![stalled_url, url]);
+        let queue_dir = config.queue_dir.clone();
+        let events_tx = spawn_dispatcher(config).unwrap();
+        events_tx
+            .send(ServerEvent::SessionStarted { session_id: 1 })
+            .unwrap();
+
+        assert!(requests_rx
+            .recv_timeout(DELIVERY_TIMEOUT / 2)
+            .is_ok_and(|(headers, _)| header(&headers, "x-beep-event") == "session_started"));
+        drop(stalled);
+        let _ = std::fs::remove_dir_all(&queue_dir);
+    }
+
+    #[test]
+    fn test_retry_rejected_delivery() {
+        let (url, requests_rx) = listen(500);
+        let config = config("retry", vec![url]);
+        let queue_dir = config.queue_dir.clone();
+        let mut dispatcher = Dispatcher::new(config).unwrap();
+        dispatcher.enqueue(&ServerEvent::SessionEnded { session_id: 1 });
+        dispatcher.deliver_due();
+
+        assert!(requests_rx.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(dispatcher.pending.len(), 1);
+        assert_eq!(dispatcher.pending[0].attempts, 1);
+        assert!(dispatcher.pending[0].next_attempt > unix_millis());
+        // the attempts survive a restart
+        let resumed = Dispatcher::new(dispatcher.config.clone()).unwrap();
+        assert_eq!(resumed.pending.len(), 1);
+        assert_eq!(resumed.pending[0].attempts, 1);
+        let _ = std::fs::remove_dir_all(&queue_dir);
+    }
+
+    #[test]
+    fn test_cap_pending_deliveries() {
+        let mut config = config("cap", vec!["http://127.0.0.1:9/events".to_string()]);
+        config.max_pending = 3;
+        let queue_dir = config.queue_dir.clone();
+        let mut dispatcher = Dispatcher::new(config.clone()).unwrap();
+        for session_id in 1..=5 {
+            dispatcher.enqueue(&ServerEvent::SessionStarted { session_id });
+        }
+
+        // the oldest are given up, in memory and on disk
+        let session_ids: Vec<u64> = dispatcher
+            .pending
+            .iter()
+            .map(|delivery| {
+                serde_json::from_str::<serde_json::Value>(&delivery.body).unwrap()["session_id"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(session_ids, [3, 4, 5]);
+        assert_eq!(queued_files(&queue_dir).len(), 3);
+
+        // and so are the ones left by a run keeping more
+        config.max_pending = 2;
+        let resumed = Dispatcher::new(config).unwrap();
+        assert_eq!(resumed.pending.len(), 2);
+        assert_eq!(queued_files(&queue_dir).len(), 2);
+        let _ = std::fs::remove_dir_all(&queue_dir);
+    }
+}
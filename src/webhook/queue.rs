@@ -0,0 +1,90 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+
+/// A notification to deliver to one webhook, kept on disk until it is acknowledged or given up.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Delivery {
+    pub url: String,
+    pub event_type: String,
+    pub event_id: String,
+    /// JSON body, signed as is on every attempt
+    pub body: String,
+    pub attempts: u32,
+    /// Unix time in milliseconds of the next attempt
+    pub next_attempt: u64,
+    #[serde(skip)]
+    pub file_name: String,
+}
+
+/// Deliveries waiting for an attempt, one JSON file each in a directory surviving restarts.
+pub struct DeliveryQueue {
+    dir: PathBuf,
+}
+
+impl DeliveryQueue {
+    pub fn open(dir: PathBuf) -> std::io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(DeliveryQueue { dir })
+    }
+
+    /// Read back the deliveries left by a previous run, oldest first.
+    pub fn load(&self) -> std::io::Result<Vec<Delivery>> {
+        let mut file_names = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let file_name = entry?.file_name().to_string_lossy().to_string();
+            // temporary files of writes interrupted by a crash
+            if file_name.starts_with('.') {
+                let _ = fs::remove_file(self.dir.join(&file_name));
+                continue;
+            }
+            if file_name.ends_with(".json") {
+                file_names.push(file_name);
+            }
+        }
+        file_names.sort();
+
+        let mut deliveries = vec![];
+        for file_name in file_names {
+            match read_delivery(&self.dir.join(&file_name)) {
+                Ok(mut delivery) => {
+                    delivery.file_name = file_name;
+                    deliveries.push(delivery);
+                }
+                Err(e) => {
+                    warn!("Dropping unreadable webhook delivery {}: {}", file_name, e);
+                    let _ = fs::remove_file(self.dir.join(&file_name));
+                }
+            }
+        }
+        Ok(deliveries)
+    }
+
+    /// Write the delivery to its file, replacing it atomically once written and synced, so that
+    /// a crash leaves either the previous or the new delivery on disk.
+    pub fn store(&self, delivery: &Delivery) -> std::io::Result<()> {
+        let tmp_path = self.dir.join(format!(".{}", delivery.file_name));
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(delivery)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(&delivery.file_name))?;
+        // the rename is durable once the directory entry is
+        fs::File::open(&self.dir)?.sync_all()
+    }
+
+    pub fn remove(&self, delivery: &Delivery) -> std::io::Result<()> {
+        match fs::remove_file(self.dir.join(&delivery.file_name)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn read_delivery(path: &Path) -> std::io::Result<Delivery> {
+    let data = fs::read(path)?;
+    serde_json::from_slice(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}